@@ -0,0 +1,605 @@
+//! # The append-only file (AOF), a log of the writes that the dataset is rebuilt from.
+//!
+//! With `appendonly yes`, every write that the server propagates to its replicas is
+//! appended to the AOF too, in the very same form (see [`replication::effect`]), so
+//! replaying the file on startup runs the writes again with the same effects. The AOF
+//! then takes the place of the RDB file on startup, like in Redis, even if it does not
+//! exist yet, in which case the server starts with an empty dataset.
+//!
+//! Appending only hands the writes to the OS, and when they reach the disk depends
+//! on `appendfsync`, see [`Fsync`], which `CONFIG SET` can change at runtime.
+//!
+//! A server that stopped in the middle of an append leaves the last command cut short,
+//! which the replay skips with a warning, cutting it off the file before appending more.
+//! Anything else that can't be parsed fails the startup, like an invalid RDB file does.
+//!
+//! As the AOF only ever grows, `BGREWRITEAOF` [rewrites](rewrite) it in the background
+//! as the fewest commands that rebuild the dataset of a [`Snapshot`], the one that
+//! `BGSAVE` would save. The writes made meanwhile are still appended to the old file,
+//! but also kept aside to be appended to the new one, which then takes the place of the
+//! old one at once, see [`Aof::replace`].
+//!
+//! With `aof-use-rdb-preamble yes`, the default, the snapshot is rather written as an RDB
+//! file that the commands appended afterwards follow, which is both faster to write and
+//! to load. The AOF is then loaded by reading the RDB file first and replaying the rest.
+//! Otherwise, only strings can be given a TTL by commands, so the TTLs that other values
+//! got from an RDB file don't survive a rewrite.
+//!
+//! [`replication::effect`]: crate::replication::effect
+//! [`Snapshot`]: crate::database::Snapshot
+
+use crate::command::Request;
+use crate::database::{Data, Key, StreamId, Value};
+use crate::rdb;
+use crate::resp::{self, Protocol, Token};
+use std::fmt::{self, Display, Formatter, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task;
+
+/// How often the writes are flushed to the disk with [`Fsync::EverySec`].
+pub const FSYNC_PERIOD: Duration = Duration::from_secs(1);
+
+/// How many elements a command of a [`rewrite`] adds at most, like in Redis.
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// Possible errors that can arise while rewriting the AOF.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("ERR Background append only file rewriting already in progress")]
+    RewriteInProgress,
+}
+
+/// When the writes appended to the AOF are flushed to the disk (`appendfsync`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fsync {
+    /// After every write, before it is replied to, which loses none of them in a crash.
+    Always,
+    /// Once per [`FSYNC_PERIOD`], by a background task, which loses a second of writes at most.
+    #[default]
+    EverySec,
+    /// Whenever the OS sees fit, which is the fastest but may lose the most.
+    No,
+}
+
+impl FromStr for Fsync {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.to_ascii_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            _ => Err(format!(
+                "Unknown appendfsync policy {string:?}, expected always, everysec or no"
+            )),
+        }
+    }
+}
+
+impl Display for Fsync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The append-only file, see the [module docs](self).
+#[derive(Debug)]
+pub struct Aof {
+    path: PathBuf,
+    enabled: bool,
+    fsync: RwLock<Fsync>,
+    state: Mutex<State>,
+    rewrite: Mutex<Rewrite>,
+    /// Wakes up the task that runs the rewrites.
+    rewrite_requested: Notify,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The file to append to, once the one on disk has been replayed.
+    file: Option<File>,
+    /// Whether anything was appended since the last flush to the disk.
+    unsynced: bool,
+    /// Whether the last append or flush failed.
+    last_write_failed: bool,
+    /// The writes appended since the rewrite in progress took its snapshot, if any.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+/// The state of the rewrites, like that of the background saves of [`Persistence`].
+///
+/// [`Persistence`]: crate::persistence::Persistence
+#[derive(Debug, Default)]
+struct Rewrite {
+    /// Whether a rewrite has been requested and has not finished yet.
+    in_progress: bool,
+    /// Whether to run another rewrite once the one in progress finishes.
+    scheduled: bool,
+    /// Whether the last rewrite failed.
+    last_failed: bool,
+}
+
+/// The writes parsed from an AOF, see [`parse`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Replay {
+    pub requests: Vec<Request>,
+    /// How many bytes of the file the `requests` span, short of a command cut short, if any.
+    pub len: usize,
+}
+
+impl Aof {
+    pub fn new(path: PathBuf, enabled: bool, fsync: Fsync) -> Self {
+        Self {
+            path,
+            enabled,
+            fsync: RwLock::new(fsync),
+            state: Mutex::default(),
+            rewrite: Mutex::default(),
+            rewrite_requested: Notify::new(),
+        }
+    }
+
+    /// Whether the AOF is on at all (`appendonly`).
+    pub const fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn fsync(&self) -> Fsync {
+        *self.fsync.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Change the `appendfsync` policy, which applies from the next write on.
+    pub fn set_fsync(&self, fsync: Fsync) {
+        *self.fsync.write().unwrap_or_else(PoisonError::into_inner) = fsync;
+    }
+
+    /// Read the AOF, or [`None`] if there is no such file yet.
+    pub async fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Start appending to the AOF, creating it if needed, after cutting it to the `len`
+    /// of what was replayed from it, see the [module docs](self).
+    pub fn open(&self, len: usize) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let len = len as u64;
+        if file.metadata()?.len() > len {
+            tracing::warn!(path = ?self.path, len, "Cutting off a truncated command from the AOF");
+            file.set_len(len)?;
+        }
+        self.state().file = Some(file);
+        Ok(())
+    }
+
+    /// Append a write, given as its `request`, flushing it to the disk right away with
+    /// [`Fsync::Always`]. Does nothing until the AOF is [open](Self::open).
+    pub fn append(&self, request: &[Vec<u8>]) {
+        let mut state = self.state();
+        let Some(file) = &mut state.file else {
+            return;
+        };
+        let command = encode(request);
+        let mut written = file.write_all(&command);
+        let always = self.fsync() == Fsync::Always;
+        if always && written.is_ok() {
+            written = file.sync_data();
+        }
+        if let Some(buffer) = &mut state.rewrite_buffer {
+            buffer.extend_from_slice(&command);
+        }
+        if let Err(err) = &written {
+            tracing::error!(path = ?self.path, "Could not append to the AOF: {err}");
+        }
+        state.unsynced = !always;
+        state.last_write_failed = written.is_err();
+    }
+
+    /// Flush what was appended since the last time to the disk, with [`Fsync::EverySec`].
+    ///
+    /// The flush runs on a thread of its own, so that appending can go on meanwhile.
+    pub async fn sync_pending(&self) {
+        if self.fsync() != Fsync::EverySec {
+            return;
+        }
+        let file = {
+            let mut state = self.state();
+            match &state.file {
+                Some(file) if state.unsynced => {
+                    let file = file.try_clone();
+                    state.unsynced = false;
+                    file
+                }
+                _ => return,
+            }
+        };
+        let synced = match file {
+            Ok(file) => task::spawn_blocking(move || file.sync_data())
+                .await
+                .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err))),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = synced {
+            tracing::error!(path = ?self.path, "Could not flush the AOF to the disk: {err}");
+            let mut state = self.state();
+            state.unsynced = true;
+            state.last_write_failed = true;
+        }
+    }
+
+    /// Request a rewrite, or if one is in progress already, fail unless `schedule` is set,
+    /// in which case another one will run right after it.
+    pub fn request_rewrite(&self, schedule: bool) -> Result<(), Error> {
+        let mut rewrite = self.rewrite();
+        if !rewrite.in_progress {
+            rewrite.in_progress = true;
+            self.rewrite_requested.notify_one();
+        } else if schedule {
+            rewrite.scheduled = true;
+        } else {
+            return Err(Error::RewriteInProgress);
+        }
+        Ok(())
+    }
+
+    /// Wait until a rewrite is requested, then run it and report whether
+    /// it succeeded with [`Self::finish_rewrite`].
+    pub async fn rewrite_requested(&self) {
+        self.rewrite_requested.notified().await;
+    }
+
+    /// Start keeping aside the writes appended from now on, for the rewrite that
+    /// takes its snapshot at the same time, see [`Self::replace`].
+    pub fn begin_rewrite(&self) {
+        let mut state = self.state();
+        if state.file.is_some() {
+            state.rewrite_buffer = Some(vec![]);
+        }
+    }
+
+    /// Replace the AOF with the `contents` of a rewrite, followed by the writes kept aside
+    /// since it began, atomically, and go on appending to the new file.
+    pub async fn replace(&self, contents: Vec<u8>) -> io::Result<()> {
+        let temp = self
+            .path
+            .with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+        let mut replaced = tokio::fs::write(&temp, contents).await;
+        if replaced.is_ok() {
+            replaced = self.swap(&temp);
+        }
+        if replaced.is_err() {
+            self.state().rewrite_buffer = None;
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+        replaced
+    }
+
+    /// Append the writes kept aside to the rewritten `temp` file and rename it over the AOF,
+    /// all without letting any other write in.
+    fn swap(&self, temp: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        let buffer = state.rewrite_buffer.take().unwrap_or_default();
+        let mut file = OpenOptions::new().append(true).open(temp)?;
+        file.write_all(&buffer)?;
+        file.sync_data()?;
+        std::fs::rename(temp, &self.path)?;
+        if state.file.is_some() {
+            state.file = Some(file);
+            state.unsynced = false;
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of the rewrite in progress, and start the scheduled one, if any.
+    pub fn finish_rewrite(&self, succeeded: bool) {
+        let mut rewrite = self.rewrite();
+        rewrite.last_failed = !succeeded;
+        if rewrite.scheduled {
+            rewrite.scheduled = false;
+            self.rewrite_requested.notify_one();
+        } else {
+            rewrite.in_progress = false;
+        }
+    }
+
+    /// The AOF part of the `persistence` section of `INFO`.
+    pub fn info(&self) -> String {
+        let state = self.state();
+        let rewrite = self.rewrite();
+        let mut info = String::new();
+        let _ = write!(info, "aof_enabled:{}\r\n", u8::from(self.enabled));
+        let _ = write!(
+            info,
+            "aof_rewrite_in_progress:{}\r\n",
+            u8::from(rewrite.in_progress)
+        );
+        let status = if rewrite.last_failed { "err" } else { "ok" };
+        let _ = write!(info, "aof_last_bgrewrite_status:{status}\r\n");
+        let status = if state.last_write_failed { "err" } else { "ok" };
+        let _ = write!(info, "aof_last_write_status:{status}\r\n");
+        info
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn rewrite(&self) -> MutexGuard<'_, Rewrite> {
+        self.rewrite.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Encode a write, given as its `request`, the way it is appended to the AOF.
+fn encode(request: &[Vec<u8>]) -> Vec<u8> {
+    let command = Token::Array {
+        tokens: request
+            .iter()
+            .map(|arg| Token::BulkBytes { data: arg.clone() })
+            .collect(),
+    };
+    command.to_bytes(Protocol::Resp2)
+}
+
+/// Serialize the libraries of `functions` and the `entries` of a dataset as the
+/// commands that rebuild them, see the [module docs](self).
+///
+/// Streams are rebuilt with all their metadata: `XSETID` restores their last ID and
+/// counters, and forced `XCLAIM`s the pending entries of their consumer groups.
+pub fn rewrite<'a>(
+    functions: &[String],
+    entries: impl Iterator<Item = (&'a Key, &'a Value)>,
+) -> Vec<u8> {
+    let mut out = vec![];
+    let mut emit = |args: Vec<Vec<u8>>| out.extend_from_slice(&encode(&args));
+    for code in functions {
+        emit(args(&[&"FUNCTION", &"LOAD", code]));
+    }
+    for (key, value) in entries {
+        match &value.data {
+            Data::String(string) => {
+                let mut command = args(&[&"SET", key]);
+                command.push(string.clone());
+                if let Some(deadline) = value.expires_at() {
+                    command.extend(args(&[&"PXAT", &rdb::unix_millis(deadline)]));
+                }
+                emit(command);
+            }
+            Data::Set(set) => {
+                let members: Vec<_> = set.iter().collect();
+                for chunk in members.chunks(ITEMS_PER_COMMAND) {
+                    let mut command = args(&[&"SADD", key]);
+                    command.extend(chunk.iter().map(|member| member.as_bytes().to_vec()));
+                    emit(command);
+                }
+            }
+            Data::SortedSet(zset) => {
+                let members: Vec<_> = zset.iter().collect();
+                for chunk in members.chunks(ITEMS_PER_COMMAND) {
+                    let mut command = args(&[&"ZADD", key]);
+                    for (member, score) in chunk {
+                        command.extend(args(&[&score.0, member]));
+                    }
+                    emit(command);
+                }
+            }
+            Data::Stream(stream) => {
+                for (id, fields) in stream.after(StreamId::MIN) {
+                    let mut command = args(&[&"XADD", key, id]);
+                    for (field, value) in fields {
+                        command.extend(args(&[field, value]));
+                    }
+                    emit(command);
+                }
+                if stream.len() == 0 {
+                    // An entry trimmed right away creates the stream, `XSETID` then sets its ID.
+                    let id = stream.last_id().max(StreamId { ms: 0, seq: 1 });
+                    emit(args(&[&"XADD", key, &"MAXLEN", &0, &id, &"x", &"y"]));
+                }
+                emit(args(&[
+                    &"XSETID",
+                    key,
+                    &stream.last_id(),
+                    &"ENTRIESADDED",
+                    &stream.entries_added(),
+                    &"MAXDELETEDID",
+                    &stream.max_deleted_id(),
+                ]));
+                for (name, group) in stream.groups() {
+                    emit(args(&[
+                        &"XGROUP",
+                        &"CREATE",
+                        key,
+                        name,
+                        &group.last_delivered,
+                    ]));
+                    for consumer in group.consumers.keys() {
+                        emit(args(&[&"XGROUP", &"CREATECONSUMER", key, name, consumer]));
+                    }
+                    for (id, pending) in &group.pending {
+                        emit(args(&[
+                            &"XCLAIM",
+                            key,
+                            name,
+                            &pending.consumer,
+                            &0,
+                            id,
+                            &"TIME",
+                            &pending.delivered_at,
+                            &"RETRYCOUNT",
+                            &pending.deliveries,
+                            &"FORCE",
+                            &"JUSTID",
+                        ]));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The arguments of a command of a [`rewrite`], all spelled out as text.
+fn args(args: &[&dyn Display]) -> Vec<Vec<u8>> {
+    args.iter()
+        .map(|arg| arg.to_string().into_bytes())
+        .collect()
+}
+
+/// Parse the writes in the `contents` of an AOF, skipping a last command cut short.
+pub fn parse(contents: &[u8]) -> Result<Replay, String> {
+    let mut replay = Replay::default();
+    while replay.len < contents.len() {
+        let rest = &contents[replay.len..];
+        let (request, len) = match resp::decode_bulk_array(rest) {
+            Some(decoded) => decoded,
+            None => match Token::decode(rest) {
+                Ok(None) => break,
+                Ok(Some(_)) | Err(_) => {
+                    return Err(format!("Invalid command at byte {} of the AOF", replay.len))
+                }
+            },
+        };
+        replay.requests.push(request);
+        replay.len += len;
+    }
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, rewrite, Aof, Fsync};
+    use crate::database::{Database, ReadFrom, Value};
+    use std::time::Duration;
+    use std::{env, fs};
+
+    #[test]
+    fn fsync_policies() {
+        for name in ["always", "everysec", "no"] {
+            assert_eq!(name.parse::<Fsync>().unwrap().to_string(), name);
+        }
+        assert_eq!("EverySec".parse(), Ok(Fsync::EverySec));
+        assert!("sometimes".parse::<Fsync>().is_err());
+    }
+
+    #[tokio::test]
+    async fn append_and_replay() {
+        let path = env::temp_dir().join("redis-starter-rust-append.aof");
+        let _ = fs::remove_file(&path);
+        let aof = Aof::new(path.clone(), true, Fsync::EverySec);
+        assert_eq!(aof.read().await.unwrap(), None);
+        // Nothing is appended until the AOF is open.
+        aof.append(&[b"SET".to_vec(), b"early".to_vec(), b"1".to_vec()]);
+        aof.open(0).unwrap();
+        aof.append(&[b"SET".to_vec(), b"key".to_vec(), vec![0xFF, b'\n']]);
+        aof.sync_pending().await;
+        aof.set_fsync(Fsync::Always);
+        aof.append(&[b"DEL".to_vec(), b"key".to_vec()]);
+        assert!(aof.info().contains("aof_last_write_status:ok\r\n"));
+
+        let contents = aof.read().await.unwrap().unwrap();
+        let replay = parse(&contents).unwrap();
+        assert_eq!(replay.len, contents.len());
+        assert_eq!(
+            replay.requests,
+            [
+                vec![b"SET".to_vec(), b"key".to_vec(), vec![0xFF, b'\n']],
+                vec![b"DEL".to_vec(), b"key".to_vec()],
+            ]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rewrites() {
+        let mut db = Database::new();
+        db.set("plain".into(), Value::without_ttl("1".into()));
+        db.set(
+            "volatile".into(),
+            Value::with_ttl("2".into(), Duration::from_secs(60)),
+        );
+        let members = (0..100).map(|i| i.to_string()).collect();
+        let _ = db.sadd("set".into(), members).unwrap();
+        db.xgroup_create(
+            "stream".into(),
+            "g".into(),
+            ReadFrom::After(Default::default()),
+            true,
+        )
+        .unwrap();
+        let snapshot = db.snapshot();
+        let functions = ["#!lua name=lib\n".to_string()];
+        let contents = rewrite(&functions, snapshot.iter());
+
+        let replay = parse(&contents).unwrap();
+        let words = |request: &Vec<Vec<u8>>| -> Vec<String> {
+            request
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        };
+        let commands: Vec<Vec<String>> = replay.requests.iter().map(words).collect();
+        assert_eq!(commands[0], ["FUNCTION", "LOAD", "#!lua name=lib\n"]);
+        assert!(commands.contains(&vec!["SET".into(), "plain".into(), "1".into()]));
+        let volatile = commands
+            .iter()
+            .find(|command| command[1] == "volatile")
+            .unwrap();
+        assert_eq!(volatile[3], "PXAT");
+        // Every command adds 64 members at most.
+        let sadds: Vec<_> = commands
+            .iter()
+            .filter(|command| command[0] == "SADD")
+            .collect();
+        assert_eq!(
+            sadds
+                .iter()
+                .map(|command| command.len() - 2)
+                .collect::<Vec<_>>(),
+            [64, 36]
+        );
+        let stream: Vec<String> = commands
+            .iter()
+            .filter(|command| command.contains(&"stream".to_string()))
+            .map(|command| command.join(" "))
+            .collect();
+        assert_eq!(
+            stream,
+            [
+                "XADD stream MAXLEN 0 0-1 x y",
+                "XSETID stream 0-0 ENTRIESADDED 0 MAXDELETEDID 0-0",
+                "XGROUP CREATE stream g 0-0",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_and_invalid() {
+        let contents = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nDEL\r\n$3\r\nke";
+        let replay = parse(contents).unwrap();
+        assert_eq!(replay.requests, [vec![b"PING".to_vec()]]);
+        assert_eq!(replay.len, 14);
+        assert!(parse(b"*1\r\n$4\r\nPING\r\n+OK\r\n").is_err());
+        assert!(parse(b"*1\r\n$x\r\n").is_err());
+    }
+}
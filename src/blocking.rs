@@ -0,0 +1,111 @@
+//! # The per-key registry of clients blocked by commands like `BLPOP` and `BZPOPMIN`.
+//!
+//! A blocked client [registers](Waiters::register) for the keys it waits on
+//! *before* checking them for the first time, and every command that adds data
+//! to a key [wakes](Waiters::wake) everyone registered for it. Each [`Waiter`]
+//! owns a [`Notify`], whose stored permit makes sure that a wake-up landing
+//! between a check and the following wait is never lost. Woken clients race
+//! for the data under the database lock, and the losers simply wait again.
+
+use crate::database::Key;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+
+/// The registry of blocked clients, keyed by the keys they wait on.
+#[derive(Debug, Default)]
+pub struct Waiters {
+    blocked: Mutex<HashMap<Key, Vec<Arc<Notify>>>>,
+}
+
+impl Waiters {
+    /// Register interest in `keys`, until the returned [`Waiter`] is dropped.
+    pub fn register(&self, keys: &[Key]) -> Waiter<'_> {
+        let notify = Arc::new(Notify::new());
+        let mut blocked = self.blocked();
+        for key in keys {
+            blocked
+                .entry(key.clone())
+                .or_default()
+                .push(Arc::clone(&notify));
+        }
+        Waiter {
+            waiters: self,
+            keys: keys.to_vec(),
+            notify,
+        }
+    }
+
+    /// Wake up every client waiting on `key`, in the order in which they blocked.
+    pub fn wake(&self, key: &str) {
+        if let Some(waiters) = self.blocked().get(key) {
+            waiters.iter().for_each(|notify| notify.notify_one());
+        }
+    }
+
+    fn blocked(&self) -> MutexGuard<'_, HashMap<Key, Vec<Arc<Notify>>>> {
+        self.blocked.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A client blocked on some keys, see [`Waiters::register`].
+#[derive(Debug)]
+pub struct Waiter<'a> {
+    waiters: &'a Waiters,
+    keys: Vec<Key>,
+    notify: Arc<Notify>,
+}
+
+impl Waiter<'_> {
+    /// Wait until one of the keys is [woken](Waiters::wake), or return
+    /// immediately if that already happened since the last wait.
+    pub async fn wait(&self) {
+        self.notify.notified().await;
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let mut blocked = self.waiters.blocked();
+        for key in &self.keys {
+            if let Some(waiters) = blocked.get_mut(key) {
+                waiters.retain(|notify| !Arc::ptr_eq(notify, &self.notify));
+                if waiters.is_empty() {
+                    let _ = blocked.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Waiters;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn wake_before_wait_is_not_lost() {
+        let waiters = Waiters::default();
+        let waiter = waiters.register(&["a".to_string(), "b".to_string()]);
+        waiters.wake("c");
+        assert!(timeout(Duration::from_millis(10), waiter.wait())
+            .await
+            .is_err());
+        waiters.wake("b");
+        assert!(timeout(Duration::from_millis(10), waiter.wait())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn dropped_waiters_are_unregistered() {
+        let waiters = Waiters::default();
+        let first = waiters.register(&["a".to_string()]);
+        let second = waiters.register(&["a".to_string()]);
+        drop(first);
+        assert_eq!(waiters.blocked().get("a").map(Vec::len), Some(1));
+        drop(second);
+        assert!(waiters.blocked().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! # Fault injection for chaos testing, configured at runtime through `DEBUG CHAOS`.
+//!
+//! Only available with `enable-debug-chaos yes`, so that production servers can never
+//! be slowed down or broken on purpose. Faults are configured per command name:
+//!
+//! ```text
+//! DEBUG CHAOS SET get delay-ms 50 jitter 20 error-rate 0.01
+//! DEBUG CHAOS LIST
+//! DEBUG CHAOS CLEAR [get]
+//! ```
+//!
+//! Every affected command is then delayed by `delay-ms`, give or take up to
+//! `jitter` milliseconds, and fails with an error reply with a probability of
+//! `error-rate`, instead of being executed.
+
+use crate::random::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The faults to inject into a single command.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fault {
+    /// How long to delay the command for.
+    pub delay: Duration,
+    /// How much the `delay` may randomly vary, in both directions.
+    pub jitter: Duration,
+    /// The probability of the command failing, between `0` and `1`.
+    pub error_rate: f64,
+}
+
+// The error rate is validated to be a probability, so it is never `NaN`.
+impl Eq for Fault {}
+
+impl Fault {
+    /// Build a [`Fault`] out of its `DEBUG CHAOS SET` options, like `["delay-ms", "50"]`.
+    pub fn from_options(options: &[String]) -> Option<Self> {
+        let mut fault = Self::default();
+        for option in options.chunks(2) {
+            let [name, value] = option else {
+                return None;
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "delay-ms" => fault.delay = Duration::from_millis(value.parse().ok()?),
+                "jitter" => fault.jitter = Duration::from_millis(value.parse().ok()?),
+                "error-rate" => {
+                    fault.error_rate = value
+                        .parse()
+                        .ok()
+                        .filter(|rate| (0.0..=1.0).contains(rate))?;
+                }
+                _ => return None,
+            }
+        }
+        Some(fault)
+    }
+}
+
+/// What should happen to a command before (or instead of) executing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Injection {
+    pub delay: Duration,
+    pub fail: bool,
+}
+
+/// The registry of configured [`Fault`]s, keyed by lowercase command name.
+#[derive(Debug)]
+pub struct Chaos {
+    faults: Mutex<HashMap<String, Fault>>,
+    rng: Mutex<Rng>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            faults: Mutex::default(),
+            rng: Mutex::new(Rng::new()),
+        }
+    }
+}
+
+impl Chaos {
+    /// Inject `fault` into every future call of `command`, replacing any previous fault.
+    pub fn set(&self, command: &str, fault: Fault) {
+        let _ = self.faults().insert(command.to_ascii_lowercase(), fault);
+    }
+
+    /// Stop injecting faults into `command`, or into all commands.
+    ///
+    /// Returns the number of commands that had a fault configured.
+    pub fn clear(&self, command: Option<&str>) -> usize {
+        let mut faults = self.faults();
+        match command {
+            Some(command) => usize::from(faults.remove(&command.to_ascii_lowercase()).is_some()),
+            None => faults.drain().count(),
+        }
+    }
+
+    /// All the configured faults, ordered by command name.
+    pub fn list(&self) -> Vec<(String, Fault)> {
+        let mut faults: Vec<_> = self
+            .faults()
+            .iter()
+            .map(|(command, fault)| (command.clone(), *fault))
+            .collect();
+        faults.sort_by(|(a, _), (b, _)| a.cmp(b));
+        faults
+    }
+
+    /// Roll the dice for a call of `command`, if it has a fault configured.
+    pub fn roll(&self, command: &str) -> Option<Injection> {
+        let fault = *self.faults().get(&command.to_ascii_lowercase())?;
+        let mut rng = self
+            .rng
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let jitter = fault.jitter.as_millis() as u64;
+        let offset = rng.next_u64() % (2 * jitter + 1);
+        let delay = (fault.delay + Duration::from_millis(offset))
+            .saturating_sub(Duration::from_millis(jitter));
+        let chance = (rng.next_u64() >> 11) as f64 / (1_u64 << 53) as f64;
+        Some(Injection {
+            delay,
+            fail: chance < fault.error_rate,
+        })
+    }
+
+    fn faults(&self) -> std::sync::MutexGuard<'_, HashMap<String, Fault>> {
+        self.faults
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chaos, Fault};
+    use std::time::Duration;
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn parse_options() {
+        let fault = Fault::from_options(&options(&[
+            "delay-ms",
+            "50",
+            "jitter",
+            "20",
+            "error-rate",
+            "0.01",
+        ]));
+        assert_eq!(
+            fault,
+            Some(Fault {
+                delay: Duration::from_millis(50),
+                jitter: Duration::from_millis(20),
+                error_rate: 0.01,
+            })
+        );
+        assert_eq!(Fault::from_options(&options(&["error-rate", "2"])), None);
+        assert_eq!(Fault::from_options(&options(&["delay-ms"])), None);
+        assert_eq!(Fault::from_options(&options(&["bogus", "1"])), None);
+    }
+
+    #[test]
+    fn rolls_within_bounds() {
+        let chaos = Chaos::default();
+        assert_eq!(chaos.roll("get"), None);
+
+        chaos.set(
+            "GET",
+            Fault {
+                delay: Duration::from_millis(50),
+                jitter: Duration::from_millis(20),
+                error_rate: 1.0,
+            },
+        );
+        for _ in 0..100 {
+            let injection = chaos.roll("get").unwrap();
+            assert!(injection.fail);
+            assert!((30..=70).contains(&injection.delay.as_millis()));
+        }
+
+        chaos.set("set", Fault::default());
+        assert_eq!(chaos.list().len(), 2);
+        assert!(!chaos.roll("set").unwrap().fail);
+        assert_eq!(chaos.clear(Some("get")), 1);
+        assert_eq!(chaos.clear(None), 1);
+        assert!(chaos.list().is_empty());
+    }
+}
@@ -0,0 +1,421 @@
+//! # Startup integrity self-test (`--check`).
+//!
+//! Instead of serving clients, `--check` runs a fixed list of checks and exits,
+//! so that operators can validate a deployment before putting it in rotation:
+//!
+//! | Check             | What it verifies                                                   |
+//! |-------------------|--------------------------------------------------------------------|
+//! | `rdb-file`        | The configured RDB file, if any, decodes, and has no duplicate keys |
+//! | `expiry-metadata` | The TTLs of the loaded keys agree with what `EXT.WHENEXPIRES` tells |
+//! | `type-registry`   | Every data type has a distinct `TYPE` name and a known RDB support  |
+//! | `rdb-round-trip`  | A sample of every data type survives being written and read back    |
+//!
+//! Every check is reported on its own line, and the process exits with `1` if any failed.
+//!
+//! To look into a single RDB file instead, like one that a failing test left behind,
+//! `check-rdb <file>` walks it in full, verifies its checksum, and prints a [summary]
+//! of its contents, exiting with `1` if it could not be decoded.
+//!
+//! [summary]: RdbSummary
+
+use crate::config::Config;
+use crate::database::{Data, Database, ExpiryReport, IdSpec, Key, Score, Value};
+use crate::database::{ReadFrom, ReadGroupFrom, StreamId, XAddOptions, ZAddOptions};
+use crate::rdb;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use std::{fs, io, mem};
+
+/// The outcome of a single check: a summary of what was found, or what went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Result<String, String>,
+}
+
+impl Check {
+    pub const fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Ok(summary) => write!(f, "ok   {}: {summary}", self.name),
+            Err(problem) => write!(f, "FAIL {}: {problem}", self.name),
+        }
+    }
+}
+
+/// Run all the checks against the persistence files of `config`.
+pub fn run(config: &Config) -> Vec<Check> {
+    let mut db = Database::new();
+    let path = config.dir.join(&config.dbfilename);
+    let loaded = match fs::read(&path) {
+        Ok(bytes) => load(&bytes, &mut db),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(format!(
+            "{} does not exist, nothing to load",
+            path.display()
+        )),
+        Err(err) => Err(format!("could not read {}: {err}", path.display())),
+    };
+    vec![
+        Check {
+            name: "rdb-file",
+            outcome: loaded,
+        },
+        Check {
+            name: "expiry-metadata",
+            outcome: expiry_metadata(&db),
+        },
+        Check {
+            name: "type-registry",
+            outcome: type_registry(),
+        },
+        Check {
+            name: "rdb-round-trip",
+            outcome: round_trip(&sample()),
+        },
+    ]
+}
+
+/// Decode an RDB file into `db`, like loading it at startup would.
+fn load(bytes: &[u8], db: &mut Database) -> Result<String, String> {
+    let file = rdb::read_file(bytes).map_err(|err| err.to_string())?;
+    let mut keys = HashSet::new();
+    if let Some(entry) = file.entries.iter().find(|entry| !keys.insert(&entry.key)) {
+        return Err(format!("duplicate key {:?}", entry.key));
+    }
+    let now = SystemTime::now();
+    let expired = file
+        .entries
+        .iter()
+        .filter(|entry| entry.is_expired_at(now))
+        .count();
+    let total = file.entries.len();
+    let _ = db.import(
+        file.entries
+            .into_iter()
+            .map(rdb::Entry::into_pair)
+            .collect(),
+    );
+    Ok(format!(
+        "RDB version {}, {total} key(s), {expired} already expired",
+        file.version
+    ))
+}
+
+/// Whether the checksum at the end of an RDB file was verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Verified,
+    /// The checksum is zero, which means that the writer did not compute one.
+    Disabled,
+    /// The file is of a version from before there were checksums.
+    Absent,
+}
+
+/// What `check-rdb` found in an RDB file that decoded in full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RdbSummary {
+    pub version: u32,
+    pub checksum: Checksum,
+    pub aux: Vec<(String, String)>,
+    /// How many libraries of functions there are.
+    pub libraries: usize,
+    /// How many keys there are of each type, by `TYPE` name.
+    pub types: BTreeMap<&'static str, usize>,
+    /// How many keys have a TTL, including those that already expired.
+    pub volatile: usize,
+    pub expired: usize,
+}
+
+impl Display for RdbSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let checksum = match self.checksum {
+            Checksum::Verified => "verified",
+            Checksum::Disabled => "disabled",
+            Checksum::Absent => "absent",
+        };
+        writeln!(f, "RDB version {}, checksum {checksum}", self.version)?;
+        for (field, value) in &self.aux {
+            writeln!(f, "aux {field}={value:?}")?;
+        }
+        writeln!(f, "{} library(ies) of functions", self.libraries)?;
+        for (name, count) in &self.types {
+            writeln!(f, "{name}: {count} key(s)")?;
+        }
+        let keys: usize = self.types.values().sum();
+        write!(
+            f,
+            "{keys} key(s), {} with a TTL, {} already expired",
+            self.volatile, self.expired
+        )
+    }
+}
+
+/// Read and summarize the RDB file at `path`, see `check-rdb`.
+pub fn check_rdb(path: &Path) -> Result<RdbSummary, String> {
+    let bytes =
+        fs::read(path).map_err(|err| format!("could not read {}: {err}", path.display()))?;
+    summarize(&bytes, SystemTime::now())
+}
+
+/// Decode the RDB file in `bytes` and summarize it, with TTLs that ran out by `now` counting as expired.
+fn summarize(bytes: &[u8], now: SystemTime) -> Result<RdbSummary, String> {
+    let file = rdb::read_file(bytes).map_err(|err| err.to_string())?;
+    let mut keys = HashSet::new();
+    if let Some(entry) = file.entries.iter().find(|entry| !keys.insert(&entry.key)) {
+        return Err(format!("duplicate key {:?}", entry.key));
+    }
+    // Having been decoded, the file ends with its checksum, if its version has any.
+    let checksum = match bytes.len().checked_sub(8).map(|start| &bytes[start..]) {
+        _ if file.version < 5 => Checksum::Absent,
+        Some([0, 0, 0, 0, 0, 0, 0, 0]) => Checksum::Disabled,
+        _ => Checksum::Verified,
+    };
+    let mut types = BTreeMap::new();
+    for entry in &file.entries {
+        *types.entry(entry.data.type_name()).or_insert(0) += 1;
+    }
+    Ok(RdbSummary {
+        version: file.version,
+        checksum,
+        libraries: file.functions.len(),
+        volatile: file
+            .entries
+            .iter()
+            .filter(|entry| entry.expires_at.is_some())
+            .count(),
+        expired: file
+            .entries
+            .iter()
+            .filter(|entry| entry.is_expired_at(now))
+            .count(),
+        types,
+        aux: file.aux,
+    })
+}
+
+/// Check that the TTL of every live key is reported back consistently.
+fn expiry_metadata(db: &Database) -> Result<String, String> {
+    let mut volatile = 0;
+    for (key, value) in db.iter() {
+        let consistent = match (value.expires_at(), db.when_expires(key)) {
+            (None, ExpiryReport::Persistent) => true,
+            (Some(deadline), ExpiryReport::Scheduled { deadline_ms, .. }) => {
+                volatile += 1;
+                rdb::unix_millis(deadline) == deadline_ms
+            }
+            _ => false,
+        };
+        if !consistent {
+            return Err(format!("inconsistent expiry of key {key:?}"));
+        }
+    }
+    Ok(format!("{volatile} key(s) with a TTL"))
+}
+
+/// The `TYPE` names of all the data types.
+const TYPE_NAMES: [&str; 4] = ["string", "set", "zset", "stream"];
+
+/// Whether RDB files can hold `data`, which is spelled out per type so that
+/// adding a type without deciding on its persistence does not compile.
+const fn persisted(data: &Data) -> bool {
+    match data {
+        Data::String(_) | Data::Set(_) | Data::SortedSet(_) | Data::Stream(_) => true,
+    }
+}
+
+/// Check that the sample covers every type, and that their `TYPE` names are distinct.
+fn type_registry() -> Result<String, String> {
+    let sample = sample();
+    let types: HashSet<_> = sample
+        .iter()
+        .map(|(_, value)| mem::discriminant(&value.data))
+        .collect();
+    let names: HashSet<_> = sample
+        .iter()
+        .map(|(_, value)| value.data.type_name())
+        .collect();
+    if names.len() != types.len() {
+        return Err("several types share a TYPE name".to_string());
+    }
+    if let Some(missing) = TYPE_NAMES.iter().find(|name| !names.contains(*name)) {
+        return Err(format!("no sample of type {missing:?}"));
+    }
+    let unpersisted: Vec<_> = sample
+        .iter()
+        .filter(|(_, value)| !persisted(&value.data))
+        .map(|(_, value)| value.data.type_name())
+        .collect();
+    match unpersisted.as_slice() {
+        [] => Ok(format!("{} type(s), all persisted", names.len())),
+        _ => Ok(format!(
+            "{} type(s), not persisted: {}",
+            names.len(),
+            unpersisted.join(", ")
+        )),
+    }
+}
+
+/// A few keys of every type, some of them with a TTL.
+fn sample() -> Vec<(Key, Value)> {
+    let mut db = Database::new();
+    let ttl = Some(Duration::from_secs(3600));
+    db.set("string".into(), Value::new("plain".to_string(), None));
+    db.set(
+        "string:binary".into(),
+        Value::new(Data::String(vec![0, 0xFF, b'\r', b'\n']), ttl),
+    );
+    let members = vec![(Score(1.5), "a".into()), (Score(-0.25), "b".into())];
+    let fields = vec![("field".into(), "value".into())];
+    let filled = db
+        .pfadd("string:hll".into(), &["a".into(), "b".into()])
+        .and_then(|_| db.sadd("set".into(), vec!["a".into(), "b".into(), "1".into()]))
+        .and_then(|_| db.zadd("zset".into(), ZAddOptions::default(), members))
+        .and_then(|_| {
+            let options = XAddOptions::default();
+            db.xadd("stream".into(), IdSpec::Auto, fields, options)
+        })
+        .and_then(|_| {
+            db.xgroup_create(
+                "stream".into(),
+                "group".into(),
+                ReadFrom::After(StreamId::MIN),
+                false,
+            )
+        })
+        .and_then(|_| {
+            let streams = [("stream".to_string(), ReadGroupFrom::New)];
+            db.xreadgroup("group", "consumer", &streams, None, false)
+        });
+    let _ = filled.expect("the sample keys do not clash");
+    db.iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Check that the persisted keys of `sample` survive being written and read back.
+fn round_trip(sample: &[(Key, Value)]) -> Result<String, String> {
+    let mut out = vec![];
+    rdb::write_file(
+        &mut out,
+        &[],
+        &[],
+        sample.iter().map(|(key, value)| (key, value)),
+        true,
+    );
+    let file = rdb::read_file(&out).map_err(|err| err.to_string())?;
+    let persisted: Vec<_> = sample
+        .iter()
+        .filter(|(_, value)| persisted(&value.data))
+        .collect();
+    if file.entries.len() != persisted.len() {
+        return Err(format!(
+            "wrote {} key(s), read back {}",
+            persisted.len(),
+            file.entries.len()
+        ));
+    }
+    for (key, value) in persisted {
+        let Some(entry) = file.entries.iter().find(|entry| &entry.key == key) else {
+            return Err(format!("key {key:?} got lost"));
+        };
+        let deadline = |deadline: Option<SystemTime>| deadline.map(rdb::unix_millis);
+        if entry.data != value.data || deadline(entry.expires_at) != deadline(value.expires_at()) {
+            return Err(format!("key {key:?} changed"));
+        }
+    }
+    Ok(format!(
+        "{} key(s), {} bytes",
+        file.entries.len(),
+        out.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expiry_metadata, load, round_trip, sample, summarize, type_registry, Checksum};
+    use crate::database::{Database, Value};
+    use crate::rdb;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn checks_pass_on_a_sound_build() {
+        assert_eq!(type_registry(), Ok("4 type(s), all persisted".to_string()));
+        assert!(round_trip(&sample()).is_ok());
+
+        let mut out = vec![];
+        let sample = sample();
+        rdb::write_file(
+            &mut out,
+            &[],
+            &[],
+            sample.iter().map(|(key, value)| (key, value)),
+            false,
+        );
+        let mut db = Database::new();
+        assert_eq!(
+            load(&out, &mut db),
+            Ok("RDB version 11, 6 key(s), 0 already expired".to_string())
+        );
+        assert_eq!(expiry_metadata(&db), Ok("1 key(s) with a TTL".to_string()));
+    }
+
+    #[test]
+    fn broken_files_fail() {
+        let mut db = Database::new();
+        assert_eq!(
+            load(b"REDIS0011\xFE", &mut db),
+            Err("Unexpected end of RDB data".to_string())
+        );
+
+        let (key, value) = ("a".to_string(), Value::new("1".to_string(), None));
+        let mut out = vec![];
+        rdb::write_file(&mut out, &[], &[], [(&key, &value), (&key, &value)], false);
+        assert_eq!(load(&out, &mut db), Err("duplicate key \"a\"".to_string()));
+        assert_eq!(
+            summarize(&out, SystemTime::now()),
+            Err("duplicate key \"a\"".to_string())
+        );
+    }
+
+    #[test]
+    fn rdb_summary() {
+        let mut out = vec![];
+        let sample = sample();
+        let functions = ["#!lua name=lib\n".to_string()];
+        rdb::write_file(
+            &mut out,
+            &[("redis-ver", "7.2.0")],
+            &functions,
+            sample.iter().map(|(key, value)| (key, value)),
+            true,
+        );
+        let summary = summarize(&out, SystemTime::now()).unwrap();
+        assert_eq!(summary.checksum, Checksum::Verified);
+        assert_eq!(
+            summary.to_string(),
+            "RDB version 11, checksum verified\n\
+             aux redis-ver=\"7.2.0\"\n\
+             1 library(ies) of functions\n\
+             set: 1 key(s)\n\
+             stream: 1 key(s)\n\
+             string: 3 key(s)\n\
+             zset: 1 key(s)\n\
+             6 key(s), 1 with a TTL, 0 already expired"
+        );
+        let later = SystemTime::now() + Duration::from_secs(7200);
+        assert_eq!(summarize(&out, later).unwrap().expired, 1);
+
+        let length = out.len();
+        out[length - 8..].fill(0);
+        assert_eq!(summarize(&out, later).unwrap().checksum, Checksum::Disabled);
+        out[length - 9] = 0;
+        assert!(summarize(&out, later).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! # A minimal Redis client, for when the server has to talk to other servers.
+
+use crate::resp::Token;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// A connection to a Redis server, sending commands and decoding their replies.
+#[derive(Debug)]
+pub struct Client {
+    stream: TcpStream,
+    /// Bytes that have been read, but not decoded yet.
+    buffer: Vec<u8>,
+}
+
+impl Client {
+    /// Connect to the Redis server at `addr`.
+    pub async fn connect(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Self {
+            stream: TcpStream::connect(addr).await?,
+            buffer: Vec::with_capacity(4096),
+        })
+    }
+
+    /// The address of the server, and the local one of the connection to it.
+    pub fn addrs(&self) -> std::io::Result<(SocketAddr, SocketAddr)> {
+        Ok((self.stream.peer_addr()?, self.stream.local_addr()?))
+    }
+
+    /// Send a command, given as its arguments, and wait for the reply.
+    ///
+    /// Error replies are returned as [`Token::SimpleError`], not as [`Err`].
+    pub async fn call(&mut self, args: &[&str]) -> anyhow::Result<Token> {
+        self.send(args).await?;
+        self.read_reply().await
+    }
+
+    /// Send a command, given as its arguments, without waiting for a reply.
+    pub async fn send(&mut self, args: &[&str]) -> anyhow::Result<()> {
+        let command = Token::Array {
+            tokens: args
+                .iter()
+                .map(|arg| Token::from(arg.to_string()))
+                .collect(),
+        };
+        self.stream
+            .write_all(command.to_string().as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Read and decode the next reply sent by the server.
+    pub async fn read_reply(&mut self) -> anyhow::Result<Token> {
+        Ok(self.read_counted().await?.0)
+    }
+
+    /// Read and decode the next [`Token`] sent by the server, along with how many bytes it took.
+    pub async fn read_counted(&mut self) -> anyhow::Result<(Token, usize)> {
+        loop {
+            if let Some((token, consumed)) = Token::decode(&self.buffer)? {
+                let _ = self.buffer.drain(..consumed);
+                return Ok((token, consumed));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Read an RDB file sent by the server during a full resynchronization.
+    ///
+    /// It is sent like a bulk string, `$<len>\r\n<bytes>`, but without the trailing CRLF.
+    pub async fn read_rdb(&mut self) -> anyhow::Result<Vec<u8>> {
+        let header_len = loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                break end + 2;
+            }
+            self.fill().await?;
+        };
+        let len = std::str::from_utf8(&self.buffer[..header_len - 2])
+            .ok()
+            .and_then(|header| header.strip_prefix('$'))
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or_else(|| anyhow::anyhow!("Expected the length of an RDB file"))?;
+        while self.buffer.len() < header_len + len {
+            self.fill().await?;
+        }
+        let rdb = self.buffer[header_len..header_len + len].to_vec();
+        let _ = self.buffer.drain(..header_len + len);
+        Ok(rdb)
+    }
+
+    /// Read more bytes from the server into the buffer.
+    async fn fill(&mut self) -> anyhow::Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            anyhow::bail!("Connection closed by the server");
+        }
+        Ok(())
+    }
+}
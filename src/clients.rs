@@ -0,0 +1,461 @@
+//! # The registry of connected clients, as listed by `CLIENT LIST`.
+//!
+//! Every connection keeps its own [`Info`] up to date as it runs commands, behind a lock
+//! of its own, so that the connections don't contend with each other on every command.
+//! The registry only gets locked to add, remove, list or kill the connections.
+
+use crate::resp::Protocol;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// The reply to `CLIENT SETNAME` with a name that is not [valid](valid_name).
+pub const INVALID_NAME: &str =
+    "ERR Client names cannot contain spaces, newlines or special characters.";
+
+/// Whether `name` may name a connection: it has to fit in a line of `CLIENT LIST`,
+/// so only printable ASCII characters other than the space are allowed.
+pub fn valid_name(name: &str) -> bool {
+    name.bytes().all(|byte| (b'!'..=b'~').contains(&byte))
+}
+
+/// The reply to `CLIENT KILL addr` when no client is connected from `addr`.
+pub const NO_SUCH_CLIENT: &str = "ERR No such client";
+
+/// A connection as shared between itself and the [`Clients`]: its [`Info`],
+/// and the means to tell it to disconnect.
+#[derive(Debug, Clone)]
+pub struct Handle(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    info: Mutex<Info>,
+    killed: watch::Sender<bool>,
+}
+
+impl Handle {
+    pub fn new(info: Info) -> Self {
+        Self(Arc::new(Shared {
+            info: Mutex::new(info),
+            killed: watch::channel(false).0,
+        }))
+    }
+
+    /// Lock the [`Info`] of the connection.
+    pub fn info(&self) -> MutexGuard<'_, Info> {
+        self.0.info.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Tell the connection to disconnect, as soon as it is done with the current command.
+    pub fn kill(&self) {
+        let _ = self.0.killed.send_replace(true);
+    }
+
+    /// Get notified once the connection is [killed](Handle::kill).
+    pub fn killed(&self) -> watch::Receiver<bool> {
+        self.0.killed.subscribe()
+    }
+}
+
+/// The connected clients, by ID.
+#[derive(Debug)]
+pub struct Clients {
+    clients: RwLock<BTreeMap<u64, Handle>>,
+    /// Which commands are held back, and until when, see [`Clients::pause`].
+    paused: watch::Sender<Option<Paused>>,
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Self {
+            clients: RwLock::default(),
+            paused: watch::channel(None).0,
+        }
+    }
+}
+
+impl Clients {
+    /// List the connection behind `handle` for as long as the returned guard lives.
+    pub fn register(&self, handle: &Handle) -> Registered<'_> {
+        let id = handle.info().id;
+        let _ = self
+            .clients
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, handle.clone());
+        Registered { clients: self, id }
+    }
+
+    /// Take a snapshot of the [`Info`] of the connected clients that pass the `filter`,
+    /// if any, in the order they connected.
+    pub fn list(&self, filter: Option<&Filter>) -> Vec<Info> {
+        self.clients
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(|handle| handle.info().clone())
+            .filter(|info| filter.map_or(true, |filter| filter.matches(info)))
+            .collect()
+    }
+
+    /// Kill the connections that pass all the `filters`, except for the one `spared`,
+    /// returning how many were killed.
+    pub fn kill(&self, filters: &[Filter], spared: Option<u64>) -> usize {
+        self.clients
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(id, _)| spared != Some(**id))
+            .filter(|(_, handle)| {
+                let info = handle.info();
+                filters.iter().all(|filter| filter.matches(&info))
+            })
+            .map(|(_, handle)| handle.kill())
+            .count()
+    }
+
+    /// Hold back the commands of the clients for `timeout`, either all of them or only
+    /// those that may write (`CLIENT PAUSE`).
+    ///
+    /// A pause that is already going on only ever gets longer or stricter.
+    pub fn pause(&self, pause: Pause, timeout: Duration) {
+        let until = tokio::time::Instant::now() + timeout;
+        self.paused.send_modify(|paused| {
+            *paused = Some(match paused.filter(|paused| !paused.is_over()) {
+                Some(paused) => Paused {
+                    pause: paused.pause.max(pause),
+                    until: paused.until.max(until),
+                },
+                None => Paused { pause, until },
+            });
+        });
+    }
+
+    /// End the pause, if any, letting the held back commands run (`CLIENT UNPAUSE`).
+    pub fn unpause(&self) {
+        let _ = self.paused.send_replace(None);
+    }
+
+    /// Whether commands are being held back, some or all of them.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+            .borrow()
+            .map_or(false, |paused| !paused.is_over())
+    }
+
+    /// Wait until a command may run: right away unless there is a pause, and only after
+    /// it if it holds back all commands, or if the command is a `write`.
+    pub async fn unpaused(&self, write: bool) {
+        if !self.is_paused() {
+            return;
+        }
+        let mut paused = self.paused.subscribe();
+        loop {
+            let current = *paused.borrow_and_update();
+            match current {
+                Some(current) if !current.is_over() && (write || current.pause == Pause::All) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(current.until) => {}
+                        _ = paused.changed() => {}
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Which commands `CLIENT PAUSE` holds back, ordered from the least strict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pause {
+    /// Those that may write to the dataset, or otherwise change what replicas get.
+    Write,
+    All,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Paused {
+    pause: Pause,
+    until: tokio::time::Instant,
+}
+
+impl Paused {
+    fn is_over(&self) -> bool {
+        self.until <= tokio::time::Instant::now()
+    }
+}
+
+/// Keeps a connection listed in the [`Clients`] until dropped, see [`Clients::register`].
+#[derive(Debug)]
+pub struct Registered<'a> {
+    clients: &'a Clients,
+    id: u64,
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        let _ = self
+            .clients
+            .clients
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+/// Which clients `CLIENT LIST` lists, or `CLIENT KILL` kills.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Those of the kind (`TYPE`).
+    Kind(Kind),
+    /// Those with one of the IDs (`ID`).
+    Ids(Vec<u64>),
+    /// Those connected from the address, as `ip:port` (`ADDR`).
+    Addr(String),
+    /// Those connected to the local address, as `ip:port` (`LADDR`).
+    LocalAddr(String),
+}
+
+impl Filter {
+    pub fn matches(&self, info: &Info) -> bool {
+        let is = |addr: Option<SocketAddr>, wanted: &str| {
+            addr.map_or(false, |addr| addr.to_string() == wanted)
+        };
+        match self {
+            Self::Kind(kind) => info.kind() == *kind,
+            Self::Ids(ids) => ids.contains(&info.id),
+            Self::Addr(addr) => is(info.addr, addr),
+            Self::LocalAddr(laddr) => is(info.laddr, laddr),
+        }
+    }
+}
+
+/// What is on the other end of a connection, as `CLIENT LIST TYPE` filters it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Kind {
+    #[default]
+    Normal,
+    Master,
+    Replica,
+    /// A normal client that is subscribed to some channels or patterns.
+    PubSub,
+}
+
+impl Kind {
+    /// Parse the type of `CLIENT LIST TYPE`, in any case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Some(Self::Normal),
+            "master" => Some(Self::Master),
+            "replica" | "slave" => Some(Self::Replica),
+            "pubsub" => Some(Self::PubSub),
+            _ => None,
+        }
+    }
+}
+
+/// What `CLIENT LIST` and `CLIENT INFO` tell about a connection.
+#[derive(Debug, Clone)]
+pub struct Info {
+    pub id: u64,
+    /// The address of the client, and the local one that it connected to.
+    pub addr: Option<SocketAddr>,
+    pub laddr: Option<SocketAddr>,
+    /// The name of the connection, empty unless one was set.
+    pub name: String,
+    pub kind: Kind,
+    pub connected: Instant,
+    /// When the client last sent a command, which is the `last_command`.
+    pub last_interaction: Instant,
+    /// The full name of the command, like `client|list`, see [`crate::command::full_name`].
+    pub last_command: String,
+    pub channels: usize,
+    pub patterns: usize,
+    pub shard_channels: usize,
+    /// How many commands are queued in the open transaction, if any.
+    pub multi: Option<usize>,
+    pub watched: usize,
+    pub protocol: Protocol,
+    /// Whether the client is exempt from client eviction (`CLIENT NO-EVICT`).
+    pub no_evict: bool,
+    /// Whether the reads of the client leave the access metadata be (`CLIENT NO-TOUCH`).
+    pub no_touch: bool,
+}
+
+impl Info {
+    pub fn new(id: u64, kind: Kind) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            addr: None,
+            laddr: None,
+            name: String::new(),
+            kind,
+            connected: now,
+            last_interaction: now,
+            last_command: "NULL".to_string(),
+            channels: 0,
+            patterns: 0,
+            shard_channels: 0,
+            multi: None,
+            watched: 0,
+            protocol: Protocol::default(),
+            no_evict: false,
+            no_touch: false,
+        }
+    }
+
+    /// The kind of the connection, taking its subscriptions into account.
+    pub const fn kind(&self) -> Kind {
+        match self.kind {
+            Kind::Normal if self.channels + self.patterns + self.shard_channels > 0 => Kind::PubSub,
+            kind => kind,
+        }
+    }
+
+    /// The flags of the connection, as one letter each, or `N` for none.
+    fn flags(&self) -> String {
+        let mut flags = String::new();
+        match self.kind() {
+            Kind::Normal => {}
+            Kind::Master => flags.push('M'),
+            Kind::Replica => flags.push('S'),
+            Kind::PubSub => flags.push('P'),
+        }
+        if self.multi.is_some() {
+            flags.push('x');
+        }
+        if self.no_evict {
+            flags.push('e');
+        }
+        if self.no_touch {
+            flags.push('T');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        flags
+    }
+}
+
+/// Render the connection as one line of `CLIENT LIST`, without the trailing newline.
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = |addr: Option<SocketAddr>| addr.map(|addr| addr.to_string()).unwrap_or_default();
+        let multi = self.multi.map_or(-1, |queued| queued as i64);
+        write!(
+            f,
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 \
+             sub={} psub={} ssub={} multi={multi} watch={} cmd={} user=default resp={}",
+            self.id,
+            addr(self.addr),
+            addr(self.laddr),
+            self.name,
+            self.connected.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            self.flags(),
+            self.channels,
+            self.patterns,
+            self.shard_channels,
+            self.watched,
+            self.last_command,
+            self.protocol.version(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{valid_name, Clients, Filter, Handle, Info, Kind, Pause};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn registry() {
+        let clients = Clients::default();
+        let (first, second) = (
+            Handle::new(Info::new(1, Kind::Normal)),
+            Handle::new(Info::new(2, Kind::Replica)),
+        );
+        let _second = clients.register(&second);
+        let registered = clients.register(&first);
+        first.info().channels = 1;
+        let ids = |filter: Option<Filter>| {
+            let list = clients.list(filter.as_ref());
+            list.iter().map(|info| info.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(None), [1, 2]);
+        assert_eq!(ids(Some(Filter::Kind(Kind::PubSub))), [1]);
+        assert_eq!(ids(Some(Filter::Kind(Kind::Normal))), []);
+        assert_eq!(ids(Some(Filter::Ids(vec![2, 3]))), [2]);
+        drop(registered);
+        assert_eq!(ids(None), [2]);
+    }
+
+    #[test]
+    fn kill() {
+        let clients = Clients::default();
+        let handles: Vec<_> = (1..=3)
+            .map(|id| Handle::new(Info::new(id, Kind::Normal)))
+            .collect();
+        let _registered: Vec<_> = handles
+            .iter()
+            .map(|handle| clients.register(handle))
+            .collect();
+        handles[1].info().addr = Some("127.0.0.1:50000".parse().unwrap());
+        let killed = handles.iter().map(Handle::killed).collect::<Vec<_>>();
+        let addr = Filter::Addr("127.0.0.1:50000".to_string());
+        assert_eq!(clients.kill(&[addr.clone(), Filter::Ids(vec![1])], None), 0);
+        assert_eq!(clients.kill(&[addr], None), 1);
+        assert!(!*killed[0].borrow() && *killed[1].borrow() && !*killed[2].borrow());
+        assert_eq!(clients.kill(&[Filter::Kind(Kind::Normal)], Some(3)), 2);
+        assert!(*killed[0].borrow() && !*killed[2].borrow());
+    }
+
+    #[test]
+    fn line() {
+        let mut info = Info::new(7, Kind::Normal);
+        info.addr = Some("127.0.0.1:50000".parse().unwrap());
+        info.multi = Some(2);
+        info.no_touch = true;
+        info.last_command = "client|info".to_string();
+        assert_eq!(
+            info.to_string(),
+            "id=7 addr=127.0.0.1:50000 laddr= name= age=0 idle=0 flags=xT db=0 \
+             sub=0 psub=0 ssub=0 multi=2 watch=0 cmd=client|info user=default resp=2"
+        );
+        assert_eq!(Kind::from_name("SLAVE"), Some(Kind::Replica));
+    }
+
+    #[tokio::test]
+    async fn pause() {
+        let clients = Clients::default();
+        let start = Instant::now();
+        clients.pause(Pause::Write, Duration::from_millis(100));
+        clients.unpaused(false).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        clients.unpaused(true).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(!clients.is_paused());
+
+        // The pause only ever gets longer or stricter, until it is lifted.
+        clients.pause(Pause::All, Duration::from_secs(10));
+        clients.pause(Pause::Write, Duration::from_millis(1));
+        let read = tokio::time::timeout(Duration::from_millis(50), clients.unpaused(false));
+        assert!(read.await.is_err());
+        clients.unpause();
+        clients.unpaused(true).await;
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn names() {
+        assert!(valid_name("worker-1"));
+        assert!(valid_name(""));
+        assert!(!valid_name("worker 1"));
+        assert!(!valid_name("worker\n1"));
+        assert!(!valid_name("wörker"));
+    }
+}
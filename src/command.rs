@@ -1,8 +1,26 @@
 //! # Redis commands, their interpretation and handling.
 
-use crate::database::Value;
+use crate::chaos::Fault;
+use crate::clients::{Filter, Kind, Pause};
+use crate::database::{Aggregate, BitOperation, BitRange, BitUnit, IdSpec, ReadFrom};
+use crate::database::{AutoClaimOptions, PendingRange, XAddOptions, XClaimOptions};
+use crate::database::{BitFieldOp, BitFieldOverflow, BitFieldType};
+use crate::database::{Coordinates, DistanceUnit, GeoOrigin, GeoSearch, GeoShape};
+use crate::database::{ReadGroupFrom, StreamBound, StreamId, Trim, TrimStrategy};
+use crate::database::{ScanOptions, Score, SetOperation, Value, MAX_BIT_OFFSET};
+use crate::database::{ZAddOptions, ZRange, DEFAULT_SAMPLES};
+use crate::replication::ReplicaOf;
 use crate::resp::Token;
-use std::time::Duration;
+use crate::scripting::functions::RestorePolicy;
+use crate::slowlog;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod table;
+
+pub use table::{full_name, keys, lookup, KeysError, Spec, COMMANDS};
+
+/// A request as it was sent: the name of the command and its arguments, as bytes.
+pub type Request = Vec<Vec<u8>>;
 
 /// Possible errors that can arise during [`Token`] to [`Command`] translation.
 #[derive(Debug, Clone, thiserror::Error)]
@@ -15,26 +33,287 @@ pub enum ParseError {
     MissingArgument,
     #[error("Wrong command argument")]
     WrongArgument,
+    /// Keys, names and options are text, only values may be arbitrary bytes.
+    #[error("Command argument is not valid UTF-8")]
+    NotText,
 }
 
 /// Known commands that the server can respond to.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
-    /// The server should reply with [`PONG_RESPONSE`].
-    Ping,
+    /// The server should reply with [`PONG_RESPONSE`], or with `message` if given.
+    Ping { message: Option<String> },
+    /// Close the connection, once the reply is sent.
+    Quit,
+    /// Bring the connection back to the state it was in right after connecting,
+    /// leaving subscriber mode along the way.
+    Reset,
+    /// Start a transaction, queueing the following commands until [`Command::Exec`].
+    Multi,
+    /// Run the commands queued since [`Command::Multi`], all at once.
+    Exec,
+    /// Drop the commands queued since [`Command::Multi`].
+    Discard,
+    /// Make the next [`Command::Exec`] fail if any of the `keys` change meanwhile.
+    Watch { keys: Vec<String> },
+    /// Stop watching all the keys watched with [`Command::Watch`].
+    Unwatch,
+    /// Run the Lua `script` with the key names `keys` and the other arguments `args`.
+    Eval {
+        script: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+    /// Like [`Command::Eval`], but with a script from the script cache, named by its SHA-1 digest.
+    EvalSha {
+        sha1: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+    /// Add `script` to the script cache without running it (`SCRIPT LOAD`).
+    ScriptLoad { script: String },
+    /// Check which of the scripts named by the SHA-1 digests `sha1s` are cached (`SCRIPT EXISTS`).
+    ScriptExists { sha1s: Vec<String> },
+    /// Empty the script cache (`SCRIPT FLUSH`).
+    ScriptFlush,
+    /// Load the library of functions with the source `code` (`FUNCTION LOAD`).
+    ///
+    /// Fails if a library with the same name is loaded already, unless `replace` is set.
+    FunctionLoad { code: String, replace: bool },
+    /// List the libraries whose names match `pattern`, or all of them (`FUNCTION LIST`).
+    FunctionList {
+        pattern: Option<String>,
+        with_code: bool,
+    },
+    /// Serialize all the libraries into a payload for [`Command::FunctionRestore`] (`FUNCTION DUMP`).
+    FunctionDump,
+    /// Load the libraries serialized in `payload` by [`Command::FunctionDump`] (`FUNCTION RESTORE`).
+    FunctionRestore {
+        payload: String,
+        policy: RestorePolicy,
+    },
+    /// Remove all the libraries (`FUNCTION FLUSH`).
+    FunctionFlush,
+    /// Run the function `function` with the key names `keys` and the other arguments `args`.
+    ///
+    /// With `read_only` (`FCALL_RO`), only functions flagged `no-writes` can be run.
+    FCall {
+        function: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        read_only: bool,
+    },
+    /// Subscribe the client to the given `channels`.
+    Subscribe { channels: Vec<String> },
+    /// Unsubscribe the client from the given `channels`, or from all of them if none are given.
+    Unsubscribe { channels: Vec<String> },
+    /// Subscribe the client to the channels matching the glob-style `patterns`.
+    PSubscribe { patterns: Vec<String> },
+    /// Unsubscribe the client from the given `patterns`, or from all of them if none are given.
+    PUnsubscribe { patterns: Vec<String> },
+    /// Subscribe the client to the given shard `channels`.
+    SSubscribe { channels: Vec<String> },
+    /// Unsubscribe the client from the given shard `channels`, or from all of them if none are given.
+    SUnsubscribe { channels: Vec<String> },
+    /// Send `message` to every client subscribed to the shard channel `channel`.
+    SPublish { channel: String, message: String },
+    /// List the channels with subscribers, only those matching `pattern` if given (`PUBSUB CHANNELS`).
+    PubSubChannels { pattern: Option<String> },
+    /// Count the subscribers of each of the `channels` (`PUBSUB NUMSUB`).
+    PubSubNumSub { channels: Vec<String> },
+    /// Count the patterns subscribed to (`PUBSUB NUMPAT`).
+    PubSubNumPat,
+    /// Send `message` to every client subscribed to `channel`, or to a pattern matching it.
+    Publish { channel: String, message: String },
     /// The server should repeat the `message`.
     Echo { message: String },
+    /// Switch to a different protocol version, replying with a summary of the connection.
+    ///
+    /// Without a `version`, the current protocol is kept.
+    Hello { version: Option<i64> },
+    /// Shut the server down, saving the dataset first if `save` (`SHUTDOWN [NOSAVE|SAVE]`).
+    Shutdown { save: bool },
+    /// Save the dataset to the RDB file, replying once it is written.
+    Save,
+    /// Save the dataset to the RDB file in the background, or once the background save
+    /// in progress finishes if `schedule` is set (`BGSAVE [SCHEDULE]`).
+    BgSave { schedule: bool },
+    /// Rewrite the AOF in the background, as the fewest commands that rebuild the dataset.
+    BgRewriteAof,
+    /// Get the UNIX time of the last successful save, in seconds.
+    LastSave,
+    /// Returns information and statistics about the server,
+    /// either a single `section` of it or everything.
+    Info { section: Option<String> },
+    /// Tell the master about a replica during the handshake: the `listening_port`
+    /// of the replica, and the `capabilities` that it supports (`REPLCONF`).
+    ReplConf {
+        listening_port: Option<u16>,
+        capabilities: Vec<String>,
+    },
+    /// Ask a replica how much of the propagated commands it processed (`REPLCONF GETACK *`).
+    ReplConfGetAck,
+    /// Tell the master that the replica processed the propagated commands up to `offset`
+    /// (`REPLCONF ACK`), which gets no reply.
+    ReplConfAck { offset: u64 },
+    /// Ask the master to synchronize a replica that has its history `replid` up to `offset`,
+    /// or nothing at all if `replid` is `?`.
+    PSync { replid: String, offset: i64 },
+    /// Tell whether this server is a master or a replica, along with the state of its replication.
+    Role,
+    /// List the connected clients, or those that pass the `filter`, see [`crate::clients`].
+    ClientList { filter: Option<Filter> },
+    /// Describe the connection of the client itself, like a line of [`Command::ClientList`].
+    ClientInfo,
+    /// Name the connection of the client, or clear its name if `name` is empty.
+    ClientSetName { name: String },
+    /// Get the name of the connection of the client, if it has one.
+    ClientGetName,
+    /// Get the ID of the connection of the client.
+    ClientId,
+    /// Disconnect the clients that pass all the `filters`, except for the client itself
+    /// if `skip_me`, replying with how many were.
+    ClientKill { filters: Vec<Filter>, skip_me: bool },
+    /// Hold back the commands of all clients, or only those that may write,
+    /// for `timeout` or until [`Command::ClientUnpause`], see [`crate::clients::Clients::pause`].
+    ClientPause { timeout: Duration, pause: Pause },
+    /// Let the commands held back by [`Command::ClientPause`] run.
+    ClientUnpause,
+    /// Get the newest `count` entries of the [slow log](crate::slowlog), or all of them.
+    SlowlogGet { count: Option<usize> },
+    /// Count the entries of the slow log.
+    SlowlogLen,
+    /// Empty the slow log.
+    SlowlogReset,
+    /// Describe the subcommands of `SLOWLOG`.
+    SlowlogHelp,
+    /// Exempt the client from client eviction, or not.
+    ClientNoEvict { enabled: bool },
+    /// Make the reads of the client leave the access metadata of values be, or not,
+    /// see [`Command::touches_keys`].
+    ClientNoTouch { enabled: bool },
+    /// Disconnect the client connected from `addr`, the old way of `CLIENT KILL`,
+    /// which replies with an error unless there is one.
+    ClientKillAddr { addr: String },
+    /// Start replicating the `master` instead of the current one, if any,
+    /// or stop replicating and become a master with `REPLICAOF NO ONE`.
+    ReplicaOf { master: Option<ReplicaOf> },
+    /// Wait until at least `replicas` replicas acknowledged all the writes so far, or until
+    /// `timeout` runs out, replying with how many did. A zero `timeout` waits indefinitely.
+    Wait { replicas: usize, timeout: Duration },
     /// Set key to hold the string value.
     ///
     /// If key already holds a value, it is overwritten, regardless of its type.
     /// Any previous TTL associated with the key is discarded on successful operation.
+    /// The TTL is given relative to now (`EX`, `PX`) or as a Unix time (`EXAT`, `PXAT`).
     Set { key: String, value: Value },
+    /// Tell when `key` expires, or when and how it was removed if it did recently (`EXT.WHENEXPIRES`).
+    ///
+    /// This is an extension command, not present in Redis, meant for debugging expiry.
+    WhenExpires { key: String },
+    /// Returns the number of seconds since the value at `key` was last accessed (`OBJECT IDLETIME`).
+    ObjectIdleTime { key: String },
+    /// Returns the logarithmic access frequency counter of the value at `key` (`OBJECT FREQ`).
+    ObjectFreq { key: String },
+    /// Estimate how many bytes the `key` and its value take (`MEMORY USAGE`),
+    /// looking at `samples` elements of a collection, or all of them if [`None`].
+    MemoryUsage { key: String, samples: Option<usize> },
+    /// Report how much memory the dataset takes (`MEMORY STATS`).
+    MemoryStats,
+    /// Bulk-load string values along with their access metadata (`EXT.IMPORT`),
+    /// given as `key value idle-seconds lfu-counter` quadruples.
+    ///
+    /// This is an extension command, not present in Redis. It is meant for warming up
+    /// a cache from an exported snapshot, without making all of the imported keys look
+    /// freshly accessed, which would distort the eviction order.
+    Import { entries: Vec<(String, Value)> },
     /// Get the value of key.
     ///
     /// If the key does not exist the special value `nil` is returned.
     /// An error is returned if the value stored at `key` is not a string,
     /// because `GET` only handles string values.
     Get { key: String },
+    /// Returns the string representation of the type of the value stored at `key`.
+    Type { key: String },
+    /// Returns the remaining time to live of a key that has a timeout, in milliseconds.
+    PTtl { key: String },
+    /// List all the keys matching the glob-style `pattern`.
+    Keys { pattern: String },
+    /// Remove the `keys`, replying with how many of them existed (`DEL` and `UNLINK`).
+    Del { keys: Vec<String> },
+    /// Incrementally iterate over the keys in the database, starting at `cursor`.
+    Scan { cursor: u64, options: ScanOptions },
+    /// Get the string value that `key` held at the Unix time `at` (in seconds),
+    /// according to the RDB snapshots on disk (`SNAPSHOT GET`).
+    ///
+    /// This is an experimental extension command, not present in Redis.
+    SnapshotGet { at: u64, key: String },
+    /// Set `key` to `new` if, and only if, it currently holds `expected` (`EXT.CAS`).
+    ///
+    /// This is an extension command, not present in Redis. The comparison and the
+    /// swap happen atomically, which makes it suitable for lock renewal and optimistic
+    /// updates without resorting to scripting. When `ttl` (`PX <ms>`) is given, it replaces
+    /// the TTL of the key; otherwise the current TTL is kept. Replies with `1` if the
+    /// value was swapped and `0` otherwise.
+    CompareAndSet {
+        key: String,
+        expected: Vec<u8>,
+        new: Vec<u8>,
+        ttl: Option<Duration>,
+    },
+    /// Set or clear the bit at `offset` of the string stored at `key`, growing the
+    /// string as needed, and reply with the previous value of the bit.
+    SetBit {
+        key: String,
+        offset: usize,
+        bit: bool,
+    },
+    /// Get the bit at `offset` of the string stored at `key`.
+    GetBit { key: String, offset: usize },
+    /// Store the result of the bitwise `operation` over the strings stored at `keys` in
+    /// `destination`, replying with its length.
+    BitOp {
+        operation: BitOperation,
+        destination: String,
+        keys: Vec<String>,
+    },
+    /// Count the set bits of the string stored at `key`, or just of its `range`.
+    BitCount {
+        key: String,
+        range: Option<BitRange>,
+    },
+    /// Find the first bit set to `bit` in the string stored at `key`, or just in its `range`.
+    BitPos {
+        key: String,
+        bit: bool,
+        range: Option<BitRange>,
+    },
+    /// Add `elements` to the HyperLogLog stored at `key`, creating it if needed.
+    PfAdd { key: String, elements: Vec<String> },
+    /// Estimate the number of distinct elements added to the HyperLogLogs stored at `keys`.
+    PfCount { keys: Vec<String> },
+    /// Merge the HyperLogLogs stored at `sources` into the one stored at `destination`.
+    PfMerge {
+        destination: String,
+        sources: Vec<String>,
+    },
+    /// Run the `operations` of `BITFIELD` or `BITFIELD_RO` over the string stored at `key`.
+    BitField {
+        key: String,
+        operations: Vec<BitFieldOp>,
+    },
+    /// Reserve a block of `count` sequential IDs from the counter at `key` (`EXT.SEQUENCE`).
+    ///
+    /// This is an extension command, not present in Redis. It does what an `INCRBY`
+    /// followed by a subtraction does, in one step, replying with the first and the last
+    /// ID of the block, so that clients can hand out IDs locally without a round trip each.
+    /// Missing counters start at zero, so the first block starts at `1`.
+    Sequence { key: String, count: u32 },
+    /// Compress the replies to this connection that encode to at least `min_len` bytes,
+    /// or stop compressing them without one (`EXT.COMPRESS <min-bytes>|OFF`).
+    ///
+    /// This is an extension command, not present in Redis, see [`crate::compress`].
+    Compress { min_len: Option<usize> },
     /// The `CONFIG GET` command is used to read the configuration of a Redis server.
     ///
     /// The symmetric command used to alter the configuration at run time is
@@ -42,106 +321,3599 @@ pub enum Command {
     /// patterns. Any configuration parameter matching any of the patterns are
     /// reported as a list of key-value pairs.
     ConfigGet { key: String },
+    /// Set the configuration `parameters` to the given values at run time, all at once.
+    ConfigSet { parameters: Vec<(String, String)> },
+    /// Describe the commands `names` as in the [command table](table), or all of them
+    /// if there are no `names`, as `COMMAND` without a subcommand does.
+    CommandInfo { names: Vec<String> },
+    /// Count the commands in the [command table](table).
+    CommandCount,
+    /// Document the commands `names`, or all of them if there are no `names`.
+    CommandDocs { names: Vec<String> },
+    /// Pick the keys out of the command line `args`, see [`keys`].
+    CommandGetKeys { args: Vec<String> },
+    /// Add the specified members to the set stored at `key`.
+    ///
+    /// Specified members that are already a member of this set are ignored.
+    /// If `key` does not exist, a new set is created before adding the specified members.
+    SAdd { key: String, members: Vec<String> },
+    /// Remove the specified members from the set stored at `key`.
+    ///
+    /// Specified members that are not a member of this set are ignored.
+    /// The key is deleted once the set becomes empty.
+    SRem { key: String, members: Vec<String> },
+    /// Move `member` from the set at `source` to the set at `destination`, atomically.
+    SMove {
+        source: String,
+        destination: String,
+        member: String,
+    },
+    /// Returns all the members of the set value stored at `key`.
+    SMembers { key: String },
+    /// Returns if `member` is a member of the set stored at `key`.
+    SIsMember { key: String, member: String },
+    /// Returns whether each member is a member of the set stored at `key`.
+    SMIsMember { key: String, members: Vec<String> },
+    /// Returns the set cardinality (number of elements) of the set stored at `key`.
+    SCard { key: String },
+    /// Returns the cardinality of the intersection of the sets stored at `keys`.
+    ///
+    /// With a non-zero `limit`, the computation stops as soon as that cardinality is reached.
+    SInterCard { keys: Vec<String>, limit: usize },
+    /// Removes and returns one or more random members from the set value stored at `key`.
+    ///
+    /// Without `count`, the reply is a single member (or `nil`), otherwise it is an array.
+    SPop { key: String, count: Option<usize> },
+    /// Returns one or more random members from the set value stored at `key`.
+    ///
+    /// Without `count`, the reply is a single member (or `nil`). A positive `count`
+    /// returns up to that many distinct members, while a negative `count` returns
+    /// exactly `|count|` members, possibly repeating some of them.
+    SRandMember { key: String, count: Option<i64> },
+    /// Returns the members of the set resulting from `operation` over all the given sets
+    /// (`SINTER`, `SUNION` or `SDIFF`). Keys that do not exist are considered to be empty sets.
+    SetOperation {
+        operation: SetOperation,
+        keys: Vec<String>,
+    },
+    /// Like [`Command::SetOperation`], but the resulting set is stored in `destination`
+    /// (`SINTERSTORE`, `SUNIONSTORE` or `SDIFFSTORE`). If `destination` already exists,
+    /// it is overwritten.
+    SetOperationStore {
+        operation: SetOperation,
+        destination: String,
+        keys: Vec<String>,
+    },
+    /// Adds all the specified members with the specified scores to the sorted set stored at `key`.
+    ///
+    /// The `options` control whether new members are added, existing ones updated, or both,
+    /// and what the reply counts. With [`ZAddOptions::increment`], there is exactly one
+    /// member, and the reply is its new score.
+    ZAdd {
+        key: String,
+        options: ZAddOptions,
+        members: Vec<(Score, String)>,
+    },
+    /// Add `locations` to the sorted set stored at `key`, scored by their geohashes.
+    ///
+    /// The `options` are the ones of `ZADD`, except for `GT`, `LT` and `INCR`.
+    GeoAdd {
+        key: String,
+        options: ZAddOptions,
+        locations: Vec<(Coordinates, String)>,
+    },
+    /// Get the coordinates of the `members` of the sorted set stored at `key`.
+    GeoPos { key: String, members: Vec<String> },
+    /// Get the distance between the members `from` and `to` of the sorted set stored at `key`.
+    GeoDist {
+        key: String,
+        from: String,
+        to: String,
+        unit: DistanceUnit,
+    },
+    /// Find the members of the sorted set stored at `key` within the area of the `search`,
+    /// replying with their distances, geohashes and coordinates as well if asked to.
+    GeoSearch {
+        key: String,
+        search: GeoSearch,
+        with_dist: bool,
+        with_hash: bool,
+        with_coord: bool,
+    },
+    /// Store the members of the sorted set stored at `source` within the area of the `search`
+    /// at `destination`, scored by their distances with `STOREDIST`.
+    GeoSearchStore {
+        destination: String,
+        source: String,
+        search: GeoSearch,
+        store_dist: bool,
+    },
+    /// Increments the score of `member` in the sorted set stored at `key` by `increment`.
+    ///
+    /// If `member` does not exist in the sorted set, it is added with `increment` as its score.
+    /// Replies with the new score of `member`.
+    ZIncrBy {
+        key: String,
+        increment: Score,
+        member: String,
+    },
+    /// Returns the score of `member` in the sorted set at `key`.
+    ZScore { key: String, member: String },
+    /// Returns the sorted set cardinality (number of elements) of the sorted set stored at `key`.
+    ZCard { key: String },
+    /// Returns the rank of `member` in the sorted set stored at `key`,
+    /// with the scores ordered from low to high, or from high to low if `reverse` (`ZREVRANK`).
+    ///
+    /// With `with_score`, the reply also includes the score of the member.
+    ZRank {
+        key: String,
+        member: String,
+        reverse: bool,
+        with_score: bool,
+    },
+    /// Removes and returns up to `count` (or one) members with the lowest scores
+    /// in the sorted set stored at `key` (`ZPOPMIN`), or the highest ones if `max` (`ZPOPMAX`).
+    ZPop {
+        key: String,
+        max: bool,
+        count: Option<usize>,
+    },
+    /// Returns random members of the sorted set stored at `key` (`ZRANDMEMBER`).
+    ///
+    /// Without a `count`, a single member is returned, or `nil` if the key does not exist.
+    /// Otherwise, `count` is interpreted like for [`Command::SRandMember`]. With `with_scores`,
+    /// each member is followed by its score in the reply.
+    ZRandMember {
+        key: String,
+        count: Option<i64>,
+        with_scores: bool,
+    },
+    /// The blocking variant of [`Command::ZPop`] (`BZPOPMIN` and `BZPOPMAX`), popping a single
+    /// member from the first non-empty sorted set out of `keys`.
+    ///
+    /// If all of them are empty, the client blocks until another client adds members to one
+    /// of the `keys`, or until `timeout` runs out. A zero `timeout` blocks indefinitely.
+    BZPop {
+        keys: Vec<String>,
+        max: bool,
+        timeout: Duration,
+    },
+    /// Returns the members of the sorted set stored at `key` that fall into `range`,
+    /// from the lowest to the highest score, or the other way around if `reverse`.
+    ///
+    /// With `limit`, only `count` members are returned after skipping `offset` ones.
+    /// With `with_scores`, each member is followed by its score in the reply.
+    ZRange {
+        key: String,
+        range: ZRange,
+        reverse: bool,
+        limit: Option<(i64, i64)>,
+        with_scores: bool,
+    },
+    /// Removes the members of the sorted set stored at `key` that fall into `range`
+    /// (`ZREMRANGEBYRANK` or `ZREMRANGEBYSCORE`).
+    ZRemRange { key: String, range: ZRange },
+    /// Computes `operation` over the sorted sets at `keys` and stores the result in `destination`
+    /// (`ZINTERSTORE`, `ZUNIONSTORE` or `ZDIFFSTORE`). If `destination` already exists,
+    /// it is overwritten.
+    ///
+    /// The scores from each input are multiplied by its weight from `weights` (`1` by default),
+    /// and the scores of a member present in several inputs are combined with `aggregate`.
+    ZSetOperationStore {
+        operation: SetOperation,
+        destination: String,
+        keys: Vec<String>,
+        weights: Vec<Score>,
+        aggregate: Aggregate,
+    },
+    /// Appends an entry with the given `fields` to the stream stored at `key`, under the ID
+    /// described by `id`, and trims the stream if asked to. Replies with the ID of the entry,
+    /// or with nil if the stream does not exist and [`XAddOptions::no_make_stream`] is set.
+    XAdd {
+        key: String,
+        options: XAddOptions,
+        id: IdSpec,
+        fields: Vec<(String, String)>,
+    },
+    /// Creates the consumer `group` of the stream stored at `key`, starting after the entry `from`,
+    /// and creates the stream if it does not exist and `make_stream` is set (`XGROUP CREATE`).
+    XGroupCreate {
+        key: String,
+        group: String,
+        from: ReadFrom,
+        make_stream: bool,
+    },
+    /// Sets the last delivered entry of the consumer `group` (`XGROUP SETID`).
+    XGroupSetId {
+        key: String,
+        group: String,
+        from: ReadFrom,
+    },
+    /// Destroys the consumer `group` of the stream stored at `key` (`XGROUP DESTROY`).
+    XGroupDestroy { key: String, group: String },
+    /// Creates the `consumer` in the consumer `group` (`XGROUP CREATECONSUMER`).
+    XGroupCreateConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+    /// Deletes the `consumer` from the consumer `group`, along with its pending
+    /// entries, replying with their number (`XGROUP DELCONSUMER`).
+    XGroupDelConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+    /// Like [`Command::XRead`], but reads as the `consumer` of the consumer `group`,
+    /// either the entries never delivered to the group (`>`), or the ones pending
+    /// for the consumer. New entries become pending, unless `no_ack` is set.
+    XReadGroup {
+        group: String,
+        consumer: String,
+        streams: Vec<(String, ReadGroupFrom)>,
+        count: Option<usize>,
+        block: Option<Duration>,
+        no_ack: bool,
+    },
+    /// Acknowledges the entries with the given `ids` in the consumer `group` of the
+    /// stream stored at `key`, replying with the number of entries that were pending.
+    XAck {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    /// Summarizes the pending entries of the consumer `group` of the stream stored at `key`,
+    /// or lists those within the `range`, along with their owners and delivery counts.
+    XPending {
+        key: String,
+        group: String,
+        range: Option<PendingRange>,
+    },
+    /// Hands the pending entries with the given `ids` of the consumer `group` of the stream
+    /// stored at `key` that have been idle for at least `min_idle` milliseconds over to
+    /// the `consumer`, replying with the claimed entries.
+    XClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        options: XClaimOptions,
+    },
+    /// Like [`Command::XClaim`], but scans the pending entries from `start` for the ones to
+    /// claim, replying with the ID to continue from, the claimed entries and the deleted ones.
+    XAutoClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        start: StreamBound,
+        options: AutoClaimOptions,
+    },
+    /// Describes the stream stored at `key` (`XINFO STREAM`).
+    XInfoStream { key: String },
+    /// Describes every consumer group of the stream stored at `key` (`XINFO GROUPS`).
+    XInfoGroups { key: String },
+    /// Describes every consumer of the consumer `group` of the stream stored at `key`
+    /// (`XINFO CONSUMERS`).
+    XInfoConsumers { key: String, group: String },
+    /// Gets the number of entries in the stream stored at `key`.
+    XLen { key: String },
+    /// Removes the entries with the given `ids` from the stream stored at `key`,
+    /// replying with the number of entries removed.
+    XDel { key: String, ids: Vec<StreamId> },
+    /// Trims the stream stored at `key`, replying with the number of entries evicted.
+    XTrim { key: String, trim: Trim },
+    /// Sets the ID of the last entry ever added to the stream stored at `key`, and optionally
+    /// how many entries were ever added and the highest ID of the removed ones.
+    XSetId {
+        key: String,
+        last_id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    },
+    /// Gets up to `count` entries of the stream stored at `key` with IDs between `start` and
+    /// `end` (`XRANGE`), or between `end` and `start` in descending order if `reverse` (`XREVRANGE`).
+    XRange {
+        key: String,
+        start: StreamBound,
+        end: StreamBound,
+        count: Option<usize>,
+        reverse: bool,
+    },
+    /// Gets up to `count` entries from each of the `streams` with IDs greater than the given one,
+    /// replying with each stream that has any such entries, along with those entries.
+    /// With `block`, waits for new entries for up to that long if there are none yet,
+    /// where a zero `block` waits indefinitely.
+    XRead {
+        streams: Vec<(String, ReadFrom)>,
+        count: Option<usize>,
+        block: Option<Duration>,
+    },
+    /// Stall the whole server for `duration`, keeping every other client waiting (`DEBUG SLEEP`).
+    DebugSleep { duration: Duration },
+    /// Describe how the value at `key` is stored (`DEBUG OBJECT`).
+    DebugObject { key: String },
+    /// Turn the background removal of expired keys on or off (`DEBUG SET-ACTIVE-EXPIRE`).
+    DebugSetActiveExpire { enabled: bool },
+    /// Log an estimate of what the memory is taken by (`DEBUG JMAP`).
+    DebugJmap,
+    /// Inject `fault` into every future call of `command` (`DEBUG CHAOS SET`).
+    ChaosSet { command: String, fault: Fault },
+    /// Stop injecting faults into `command`, or into all commands (`DEBUG CHAOS CLEAR`).
+    ChaosClear { command: Option<String> },
+    /// List the configured faults (`DEBUG CHAOS LIST`).
+    ChaosList,
+}
+
+impl TryFrom<Request> for Command {
+    type Error = ParseError;
+
+    /// Build a [`Command`] out of a request, given as the command name followed by its arguments.
+    fn try_from(args: Request) -> Result<Self, Self::Error> {
+        let command = args.first().ok_or(ParseError::MissingCommand)?;
+        let command = String::from_utf8_lossy(command).to_ascii_lowercase();
+        Self::parse(command, Arguments::new(args))
+    }
 }
 
 impl TryFrom<Token> for Command {
     type Error = ParseError;
 
-    fn try_from(tokens: Token) -> Result<Self, Self::Error> {
-        use ParseError::{MissingArgument, MissingCommand, UnknownCommand, WrongArgument};
-        use Token::{Array, BulkString, SimpleString};
-        match tokens {
-            SimpleString { data } | BulkString { data } => match data.as_str() {
-                "ping" => Ok(Self::Ping),
-                _ => Err(UnknownCommand(data)),
-            },
-            Array { tokens } => {
-                let command = tokens
-                    .first()
-                    .ok_or(MissingCommand)?
-                    .extract()
-                    .unwrap_or_default()
-                    .to_ascii_lowercase();
-                let arg_1 = tokens.get(1).ok_or(MissingArgument).map(Token::extract);
-                let arg_2 = tokens.get(2).ok_or(MissingArgument).map(Token::extract);
-                let arg_3 = tokens.get(4).and_then(Token::extract);
-                match (command.as_str(), arg_1, arg_2, arg_3) {
-                    ("ping", _, _, _) => Ok(Self::Ping),
-                    ("echo", msg, _, _) => Ok(Self::Echo {
-                        message: msg?.ok_or(WrongArgument)?.to_string(),
+    fn try_from(request: Token) -> Result<Self, Self::Error> {
+        Self::try_from(arguments(request)?)
+    }
+}
+
+/// Turn a request decoded by the general RESP parser into the command name and its arguments.
+///
+/// Requests are arrays of strings, except for bare strings sent by hand, like `PING`.
+pub fn arguments(request: Token) -> Result<Request, ParseError> {
+    let bytes = |token: Token| match token {
+        Token::BulkBytes { data } => Some(data),
+        token => token.extract().map(|data| data.as_bytes().to_vec()),
+    };
+    match request {
+        Token::Array { tokens } if tokens.is_empty() => Err(ParseError::MissingCommand),
+        Token::Array { tokens } => tokens
+            .into_iter()
+            .map(bytes)
+            .collect::<Option<_>>()
+            .ok_or(ParseError::WrongArgument),
+        Token::SimpleString { .. } | Token::BulkString { .. } | Token::BulkBytes { .. } => {
+            Ok(vec![bytes(request).ok_or(ParseError::MissingCommand)?])
+        }
+        _ => Err(ParseError::MissingCommand),
+    }
+}
+
+impl Command {
+    /// Whether the command may change the dataset: the keyspace, or the libraries of functions.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Self::BitField { operations, .. } => operations
+                .iter()
+                .any(|operation| !matches!(operation, BitFieldOp::Get { .. })),
+            _ => matches!(
+                self,
+                Self::Set { .. }
+                    | Self::Del { .. }
+                    | Self::Import { .. }
+                    | Self::CompareAndSet { .. }
+                    | Self::SetBit { .. }
+                    | Self::BitOp { .. }
+                    | Self::PfAdd { .. }
+                    | Self::PfMerge { .. }
+                    | Self::Sequence { .. }
+                    | Self::SAdd { .. }
+                    | Self::SRem { .. }
+                    | Self::SMove { .. }
+                    | Self::SPop { .. }
+                    | Self::SetOperationStore { .. }
+                    | Self::ZAdd { .. }
+                    | Self::GeoAdd { .. }
+                    | Self::GeoSearchStore { .. }
+                    | Self::ZIncrBy { .. }
+                    | Self::ZPop { .. }
+                    | Self::BZPop { .. }
+                    | Self::ZRemRange { .. }
+                    | Self::ZSetOperationStore { .. }
+                    | Self::XAdd { .. }
+                    | Self::XGroupCreate { .. }
+                    | Self::XGroupSetId { .. }
+                    | Self::XGroupDestroy { .. }
+                    | Self::XGroupCreateConsumer { .. }
+                    | Self::XGroupDelConsumer { .. }
+                    | Self::XReadGroup { .. }
+                    | Self::XAck { .. }
+                    | Self::XClaim { .. }
+                    | Self::XAutoClaim { .. }
+                    | Self::XDel { .. }
+                    | Self::XTrim { .. }
+                    | Self::XSetId { .. }
+                    | Self::FunctionLoad { .. }
+                    | Self::FunctionRestore { .. }
+                    | Self::FunctionFlush
+            ),
+        }
+    }
+
+    /// Whether `CLIENT PAUSE WRITE` holds the command back: it may write,
+    /// or may run writes that can't be told apart beforehand, like scripts.
+    pub fn pauses_on_write(&self) -> bool {
+        self.is_write()
+            || matches!(
+                self,
+                Self::Eval { .. }
+                    | Self::EvalSha { .. }
+                    | Self::FCall {
+                        read_only: false,
+                        ..
+                    }
+                    | Self::Publish { .. }
+                    | Self::SPublish { .. }
+            )
+    }
+
+    /// Whether reading the keys of the command counts as an access to them, which
+    /// updates their idle time and LFU counter, unless the client asked not to
+    /// (`CLIENT NO-TOUCH`). Introspecting the keys doesn't, and neither do scripts,
+    /// whose commands access the keys themselves.
+    pub fn touches_keys(&self) -> bool {
+        !self.is_write()
+            && !matches!(
+                self,
+                Self::Type { .. }
+                    | Self::PTtl { .. }
+                    | Self::ObjectIdleTime { .. }
+                    | Self::ObjectFreq { .. }
+                    | Self::MemoryUsage { .. }
+                    | Self::DebugObject { .. }
+                    | Self::WhenExpires { .. }
+                    | Self::Watch { .. }
+                    | Self::Eval { .. }
+                    | Self::EvalSha { .. }
+                    | Self::FCall { .. }
+            )
+    }
+
+    /// Whether the command may block, waiting for other clients to add data.
+    pub const fn may_block(&self) -> bool {
+        matches!(
+            self,
+            Self::BZPop { .. }
+                | Self::XRead { block: Some(_), .. }
+                | Self::XReadGroup { block: Some(_), .. }
+        )
+    }
+
+    /// Build a [`Command`] named `command` out of its [`Arguments`].
+    fn parse(command: String, mut args: Arguments) -> Result<Self, ParseError> {
+        use ParseError::{MissingArgument, UnknownCommand};
+        match command.as_str() {
+            "ping" => Ok(Self::Ping {
+                message: args.optional_parsed()?,
+            }),
+            "quit" => Ok(Self::Quit),
+            "reset" => Ok(Self::Reset),
+            "multi" => Ok(Self::Multi),
+            "exec" => Ok(Self::Exec),
+            "discard" => Ok(Self::Discard),
+            "watch" => Ok(Self::Watch { keys: args.rest()? }),
+            "unwatch" => Ok(Self::Unwatch),
+            "eval" => {
+                let script = args.next()?;
+                let (keys, args) = parse_script_args(&mut args)?;
+                Ok(Self::Eval { script, keys, args })
+            }
+            "evalsha" => {
+                let sha1 = args.next()?;
+                let (keys, args) = parse_script_args(&mut args)?;
+                Ok(Self::EvalSha { sha1, keys, args })
+            }
+            "script" => match args.next()?.to_ascii_lowercase().as_str() {
+                "load" => Ok(Self::ScriptLoad {
+                    script: args.next()?,
+                }),
+                "exists" => Ok(Self::ScriptExists {
+                    sha1s: args.rest()?,
+                }),
+                "flush" => {
+                    parse_flush_mode(args.optional_parsed()?)?;
+                    Ok(Self::ScriptFlush)
+                }
+                _ => Err(UnknownCommand(command)),
+            },
+            "function" => match args.next()?.to_ascii_lowercase().as_str() {
+                "load" => {
+                    let first = args.next()?;
+                    match args.optional_parsed::<String>()? {
+                        None => Ok(Self::FunctionLoad {
+                            code: first,
+                            replace: false,
+                        }),
+                        Some(code) if first.eq_ignore_ascii_case("replace") => {
+                            Ok(Self::FunctionLoad {
+                                code,
+                                replace: true,
+                            })
+                        }
+                        Some(_) => Err(ParseError::WrongArgument),
+                    }
+                }
+                "list" => {
+                    let (mut pattern, mut with_code) = (None, false);
+                    let mut options = args.remaining()?.into_iter();
+                    while let Some(option) = options.next() {
+                        match option.to_ascii_lowercase().as_str() {
+                            "libraryname" if pattern.is_none() => {
+                                pattern = Some(options.next().ok_or(MissingArgument)?);
+                            }
+                            "withcode" if !with_code => with_code = true,
+                            _ => return Err(ParseError::WrongArgument),
+                        }
+                    }
+                    Ok(Self::FunctionList { pattern, with_code })
+                }
+                "dump" => Ok(Self::FunctionDump),
+                "restore" => Ok(Self::FunctionRestore {
+                    payload: args.next()?,
+                    policy: match args.optional_parsed::<String>()? {
+                        None => RestorePolicy::default(),
+                        Some(policy) => match policy.to_ascii_lowercase().as_str() {
+                            "append" => RestorePolicy::Append,
+                            "replace" => RestorePolicy::Replace,
+                            "flush" => RestorePolicy::Flush,
+                            _ => return Err(ParseError::WrongArgument),
+                        },
+                    },
+                }),
+                "flush" => {
+                    parse_flush_mode(args.optional_parsed()?)?;
+                    Ok(Self::FunctionFlush)
+                }
+                _ => Err(UnknownCommand(command)),
+            },
+            "fcall" | "fcall_ro" => {
+                let function = args.next()?;
+                let (keys, args) = parse_script_args(&mut args)?;
+                Ok(Self::FCall {
+                    function,
+                    keys,
+                    args,
+                    read_only: command == "fcall_ro",
+                })
+            }
+            "subscribe" => Ok(Self::Subscribe {
+                channels: args.rest()?,
+            }),
+            "unsubscribe" => Ok(Self::Unsubscribe {
+                channels: args.remaining()?,
+            }),
+            "psubscribe" => Ok(Self::PSubscribe {
+                patterns: args.rest()?,
+            }),
+            "punsubscribe" => Ok(Self::PUnsubscribe {
+                patterns: args.remaining()?,
+            }),
+            "ssubscribe" => Ok(Self::SSubscribe {
+                channels: args.rest()?,
+            }),
+            "sunsubscribe" => Ok(Self::SUnsubscribe {
+                channels: args.remaining()?,
+            }),
+            "spublish" => Ok(Self::SPublish {
+                channel: args.next()?,
+                message: args.next()?,
+            }),
+            "pubsub" => match args.next()?.to_ascii_lowercase().as_str() {
+                "channels" => Ok(Self::PubSubChannels {
+                    pattern: args.optional_parsed()?,
+                }),
+                "numsub" => Ok(Self::PubSubNumSub {
+                    channels: args.remaining()?,
+                }),
+                "numpat" => Ok(Self::PubSubNumPat),
+                _ => Err(UnknownCommand(command)),
+            },
+            "publish" => Ok(Self::Publish {
+                channel: args.next()?,
+                message: args.next()?,
+            }),
+            "echo" => Ok(Self::Echo {
+                message: args.next()?,
+            }),
+            "hello" => Ok(Self::Hello {
+                version: args.optional_parsed()?,
+            }),
+            "shutdown" => match args.optional_parsed::<String>()? {
+                None => Ok(Self::Shutdown { save: false }),
+                Some(modifier) => match modifier.to_ascii_lowercase().as_str() {
+                    "nosave" => Ok(Self::Shutdown { save: false }),
+                    "save" => Ok(Self::Shutdown { save: true }),
+                    _ => Err(ParseError::WrongArgument),
+                },
+            },
+            "save" => Ok(Self::Save),
+            "lastsave" => Ok(Self::LastSave),
+            "bgsave" => match args.optional_parsed::<String>()? {
+                None => Ok(Self::BgSave { schedule: false }),
+                Some(modifier) if modifier.eq_ignore_ascii_case("schedule") => {
+                    Ok(Self::BgSave { schedule: true })
+                }
+                Some(_) => Err(ParseError::WrongArgument),
+            },
+            "bgrewriteaof" => Ok(Self::BgRewriteAof),
+            "info" => Ok(Self::Info {
+                section: args.optional_parsed()?,
+            }),
+            "replconf" => {
+                let options = args.rest()?;
+                match options.as_slice() {
+                    [option, _] if option.eq_ignore_ascii_case("getack") => {
+                        return Ok(Self::ReplConfGetAck)
+                    }
+                    [option, offset] if option.eq_ignore_ascii_case("ack") => {
+                        return Ok(Self::ReplConfAck {
+                            offset: parsed(offset)?,
+                        })
+                    }
+                    _ => {}
+                }
+                if options.len() % 2 != 0 {
+                    return Err(ParseError::WrongArgument);
+                }
+                let (mut listening_port, mut capabilities) = (None, vec![]);
+                for pair in options.chunks(2) {
+                    match pair[0].to_ascii_lowercase().as_str() {
+                        "listening-port" => listening_port = Some(parsed(&pair[1])?),
+                        "capa" => capabilities.push(pair[1].clone()),
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::ReplConf {
+                    listening_port,
+                    capabilities,
+                })
+            }
+            "psync" => Ok(Self::PSync {
+                replid: args.next()?,
+                offset: parsed(&args.next()?)?,
+            }),
+            "role" => Ok(Self::Role),
+            "slowlog" => match args.next()?.to_ascii_lowercase().as_str() {
+                "get" => Ok(Self::SlowlogGet {
+                    count: match args.optional_parsed::<i64>()? {
+                        None => Some(slowlog::DEFAULT_COUNT),
+                        Some(-1) => None,
+                        Some(count) => {
+                            Some(usize::try_from(count).map_err(|_| ParseError::WrongArgument)?)
+                        }
+                    },
+                }),
+                "len" => Ok(Self::SlowlogLen),
+                "reset" => Ok(Self::SlowlogReset),
+                "help" => Ok(Self::SlowlogHelp),
+                _ => Err(UnknownCommand(command)),
+            },
+            "client" => match args.next()?.to_ascii_lowercase().as_str() {
+                "list" => Ok(Self::ClientList {
+                    filter: parse_client_filter(&args.remaining()?)?,
+                }),
+                "info" => Ok(Self::ClientInfo),
+                "setname" => Ok(Self::ClientSetName { name: args.next()? }),
+                "getname" => Ok(Self::ClientGetName),
+                "id" => Ok(Self::ClientId),
+                "pause" => Ok(Self::ClientPause {
+                    timeout: Duration::from_millis(args.next_parsed()?),
+                    pause: match args
+                        .optional_parsed::<String>()?
+                        .map(|s| s.to_ascii_lowercase())
+                    {
+                        None => Pause::All,
+                        Some(pause) if pause == "all" => Pause::All,
+                        Some(pause) if pause == "write" => Pause::Write,
+                        Some(_) => return Err(ParseError::WrongArgument),
+                    },
+                }),
+                "unpause" => Ok(Self::ClientUnpause),
+                "no-evict" => Ok(Self::ClientNoEvict {
+                    enabled: parse_on_off(&args.next()?)?,
+                }),
+                "no-touch" => Ok(Self::ClientNoTouch {
+                    enabled: parse_on_off(&args.next()?)?,
+                }),
+                "kill" => match args.rest()?.as_slice() {
+                    [addr] => Ok(Self::ClientKillAddr { addr: addr.clone() }),
+                    arguments => parse_client_kill(arguments),
+                },
+                _ => Err(UnknownCommand(command)),
+            },
+            "replicaof" | "slaveof" => {
+                let (host, port) = (args.next()?, args.next()?);
+                if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+                    return Ok(Self::ReplicaOf { master: None });
+                }
+                Ok(Self::ReplicaOf {
+                    master: Some(ReplicaOf {
+                        host,
+                        port: parsed(&port)?,
+                    }),
+                })
+            }
+            "wait" => Ok(Self::Wait {
+                replicas: args.next_parsed()?,
+                timeout: Duration::from_millis(args.next_parsed()?),
+            }),
+            "get" => Ok(Self::Get { key: args.next()? }),
+            "set" => {
+                let key = args.next()?;
+                let value = args.next_bytes()?;
+                let (mut ttl, mut idle, mut frequency) = (None, None, None);
+                let mut options = args.remaining()?.into_iter();
+                while let Some(option) = options.next() {
+                    let argument = options.next().ok_or(MissingArgument)?;
+                    match option.to_ascii_lowercase().as_str() {
+                        "px" => ttl = Some(Duration::from_millis(parsed(&argument)?)),
+                        "ex" => ttl = Some(Duration::from_secs(parsed(&argument)?)),
+                        "pxat" => ttl = Some(until(Duration::from_millis(parsed(&argument)?))),
+                        "exat" => ttl = Some(until(Duration::from_secs(parsed(&argument)?))),
+                        "idletime" => idle = Some(Duration::from_secs(parsed(&argument)?)),
+                        "freq" => frequency = Some(parsed(&argument)?),
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                // Like with `RESTORE`, a key is either managed by LRU or by LFU, not both.
+                if idle.is_some() && frequency.is_some() {
+                    return Err(ParseError::WrongArgument);
+                }
+                Ok(Self::Set {
+                    key,
+                    value: Value::new(value, ttl).with_access(idle, frequency),
+                })
+            }
+            "object" => match args.next()?.to_ascii_lowercase().as_str() {
+                "idletime" => Ok(Self::ObjectIdleTime { key: args.next()? }),
+                "freq" => Ok(Self::ObjectFreq { key: args.next()? }),
+                _ => Err(UnknownCommand(command)),
+            },
+            "memory" => match args.next()?.to_ascii_lowercase().as_str() {
+                "usage" => {
+                    let key = args.next()?;
+                    let samples = match args.remaining()?.as_slice() {
+                        [] => Some(DEFAULT_SAMPLES),
+                        [option, samples] if option.eq_ignore_ascii_case("samples") => {
+                            // Like in Redis, `SAMPLES 0` looks at all the elements.
+                            Some(parsed::<usize>(samples)?).filter(|&samples| samples > 0)
+                        }
+                        _ => return Err(ParseError::WrongArgument),
+                    };
+                    Ok(Self::MemoryUsage { key, samples })
+                }
+                "stats" => Ok(Self::MemoryStats),
+                _ => Err(UnknownCommand(command)),
+            },
+            "ext.import" => {
+                let arguments = args.remaining_bytes();
+                if arguments.is_empty() {
+                    return Err(ParseError::MissingArgument);
+                }
+                if arguments.len() % 4 != 0 {
+                    return Err(ParseError::WrongArgument);
+                }
+                let entries = arguments
+                    .chunks(4)
+                    .map(|entry| {
+                        let value = Value::new(entry[1].clone(), None).with_access(
+                            Some(Duration::from_secs(parsed(&text(entry[2].clone())?)?)),
+                            Some(parsed(&text(entry[3].clone())?)?),
+                        );
+                        Ok((text(entry[0].clone())?, value))
+                    })
+                    .collect::<Result<_, ParseError>>()?;
+                Ok(Self::Import { entries })
+            }
+            "type" => Ok(Self::Type { key: args.next()? }),
+            "pttl" => Ok(Self::PTtl { key: args.next()? }),
+            "keys" => Ok(Self::Keys {
+                pattern: args.next()?,
+            }),
+            "del" | "unlink" => Ok(Self::Del { keys: args.rest()? }),
+            "scan" => {
+                let cursor = args.next_parsed()?;
+                let mut options = ScanOptions::default();
+                while let Some(option) = args.optional_parsed::<String>()? {
+                    match option.to_ascii_lowercase().as_str() {
+                        "match" => options.pattern = Some(args.next()?),
+                        "count" => options.count = Some(args.next_parsed()?),
+                        "type" => options.kind = Some(args.next()?),
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::Scan { cursor, options })
+            }
+            "snapshot" => match args.next()?.to_ascii_lowercase().as_str() {
+                "get" => Ok(Self::SnapshotGet {
+                    at: args.next_parsed()?,
+                    key: args.next()?,
+                }),
+                _ => Err(UnknownCommand(command)),
+            },
+            "ext.cas" => {
+                let key = args.next()?;
+                let expected = args.next_bytes()?;
+                let new = args.next_bytes()?;
+                let ttl = match args.remaining()?.as_slice() {
+                    [] => None,
+                    [px, ms] if px.eq_ignore_ascii_case("px") => Some(Duration::from_millis(
+                        ms.parse().map_err(|_| ParseError::WrongArgument)?,
+                    )),
+                    _ => return Err(ParseError::WrongArgument),
+                };
+                Ok(Self::CompareAndSet {
+                    key,
+                    expected,
+                    new,
+                    ttl,
+                })
+            }
+            "setbit" => Ok(Self::SetBit {
+                key: args.next()?,
+                offset: parse_bit_offset(&args.next()?)?,
+                bit: parse_bit(&args.next()?)?,
+            }),
+            "getbit" => Ok(Self::GetBit {
+                key: args.next()?,
+                offset: parse_bit_offset(&args.next()?)?,
+            }),
+            "bitop" => {
+                let operation = match args.next()?.to_ascii_lowercase().as_str() {
+                    "and" => BitOperation::And,
+                    "or" => BitOperation::Or,
+                    "xor" => BitOperation::Xor,
+                    "not" => BitOperation::Not,
+                    _ => return Err(ParseError::WrongArgument),
+                };
+                let destination = args.next()?;
+                let keys = args.rest()?;
+                // Inverting only makes sense for a single string.
+                if operation == BitOperation::Not && keys.len() > 1 {
+                    return Err(ParseError::WrongArgument);
+                }
+                Ok(Self::BitOp {
+                    operation,
+                    destination,
+                    keys,
+                })
+            }
+            "bitcount" => {
+                let key = args.next()?;
+                let range = match args.remaining()?.as_slice() {
+                    [] => None,
+                    [start, end, unit @ ..] => Some(parse_bit_range(start, Some(end), unit)?),
+                    [_] => return Err(ParseError::WrongArgument),
+                };
+                Ok(Self::BitCount { key, range })
+            }
+            "bitpos" => {
+                let key = args.next()?;
+                let bit = parse_bit(&args.next()?)?;
+                let range = match args.remaining()?.as_slice() {
+                    [] => None,
+                    [start] => Some(parse_bit_range(start, None, &[])?),
+                    [start, end, unit @ ..] => Some(parse_bit_range(start, Some(end), unit)?),
+                };
+                Ok(Self::BitPos { key, bit, range })
+            }
+            "pfadd" => Ok(Self::PfAdd {
+                key: args.next()?,
+                elements: args.remaining()?,
+            }),
+            "pfcount" => Ok(Self::PfCount { keys: args.rest()? }),
+            "pfmerge" => Ok(Self::PfMerge {
+                destination: args.next()?,
+                sources: args.remaining()?,
+            }),
+            "bitfield" => {
+                let key = args.next()?;
+                let operations = parse_bitfield(&args.remaining()?, false)?;
+                Ok(Self::BitField { key, operations })
+            }
+            "bitfield_ro" => {
+                let key = args.next()?;
+                let operations = parse_bitfield(&args.remaining()?, true)?;
+                Ok(Self::BitField { key, operations })
+            }
+            "ext.compress" => match args.next()? {
+                off if off.eq_ignore_ascii_case("off") => Ok(Self::Compress { min_len: None }),
+                min_len => Ok(Self::Compress {
+                    min_len: Some(parsed(&min_len)?),
+                }),
+            },
+            "ext.whenexpires" => Ok(Self::WhenExpires { key: args.next()? }),
+            "ext.sequence" => {
+                let key = args.next()?;
+                let count = match args.next_parsed()? {
+                    0 => return Err(ParseError::WrongArgument),
+                    count => count,
+                };
+                Ok(Self::Sequence { key, count })
+            }
+            "config" => match args.next()?.to_ascii_lowercase().as_str() {
+                "get" => Ok(Self::ConfigGet {
+                    key: args.next().map_err(|_| MissingArgument)?,
+                }),
+                "set" => {
+                    let arguments = args.rest()?;
+                    if arguments.len() % 2 != 0 {
+                        return Err(ParseError::WrongArgument);
+                    }
+                    let parameters = arguments
+                        .chunks(2)
+                        .map(|pair| (pair[0].to_ascii_lowercase(), pair[1].clone()))
+                        .collect();
+                    Ok(Self::ConfigSet { parameters })
+                }
+                _ => Err(UnknownCommand(command)),
+            },
+            "command" => match args
+                .optional_parsed::<String>()?
+                .map(|s| s.to_ascii_lowercase())
+            {
+                None => Ok(Self::CommandInfo { names: vec![] }),
+                Some(subcommand) => match subcommand.as_str() {
+                    "info" => Ok(Self::CommandInfo {
+                        names: args.remaining()?,
                     }),
-                    ("get", key, _, _) => Ok(Self::Get {
-                        key: key?.ok_or(WrongArgument)?.to_string(),
+                    "count" => Ok(Self::CommandCount),
+                    "docs" => Ok(Self::CommandDocs {
+                        names: args.remaining()?,
                     }),
-                    ("set", key, val, ttl) => {
-                        let ttl = ttl.map(|ttl| {
-                            let ms = ttl.parse::<u64>().ok();
-                            ms.map(Duration::from_millis)
-                        });
-                        Ok(Self::Set {
-                            key: key?.ok_or(WrongArgument)?.to_string(),
-                            value: Value::new(
-                                val?.ok_or(WrongArgument)?.to_string(),
-                                ttl.flatten(),
-                            ),
-                        })
+                    "getkeys" => Ok(Self::CommandGetKeys { args: args.rest()? }),
+                    _ => Err(UnknownCommand(command)),
+                },
+            },
+            "zpopmin" | "zpopmax" => Ok(Self::ZPop {
+                key: args.next()?,
+                max: command == "zpopmax",
+                count: args.optional_parsed()?,
+            }),
+            "zrandmember" => {
+                let key = args.next()?;
+                let count = random_count(args.optional_parsed()?)?;
+                let with_scores = match args.optional_parsed::<String>()? {
+                    None => false,
+                    Some(option)
+                        if count.is_some() && option.eq_ignore_ascii_case("withscores") =>
+                    {
+                        true
+                    }
+                    Some(_) => return Err(ParseError::WrongArgument),
+                };
+                Ok(Self::ZRandMember {
+                    key,
+                    count,
+                    with_scores,
+                })
+            }
+            "bzpopmin" | "bzpopmax" => {
+                let mut keys = args.rest()?;
+                let timeout = parsed(&keys.pop().ok_or(MissingArgument)?)?;
+                let timeout =
+                    Duration::try_from_secs_f64(timeout).map_err(|_| ParseError::WrongArgument)?;
+                if keys.is_empty() {
+                    return Err(ParseError::MissingArgument);
+                }
+                Ok(Self::BZPop {
+                    keys,
+                    max: command == "bzpopmax",
+                    timeout,
+                })
+            }
+            "zrange" => {
+                let key = args.next()?;
+                let start = args.next()?;
+                let stop = args.next()?;
+                let (mut by_score, mut by_lex, mut reverse) = (false, false, false);
+                let (mut limit, mut with_scores) = (None, false);
+                let mut options = args.remaining()?.into_iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_lowercase().as_str() {
+                        "byscore" => by_score = true,
+                        "bylex" => by_lex = true,
+                        "rev" => reverse = true,
+                        "withscores" => with_scores = true,
+                        "limit" => {
+                            let mut next = || options.next().and_then(|arg| arg.parse().ok());
+                            limit = Some((
+                                next().ok_or(ParseError::WrongArgument)?,
+                                next().ok_or(ParseError::WrongArgument)?,
+                            ));
+                        }
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                // With `BYSCORE` and `BYLEX`, `REV` expects the maximum to come first.
+                let (min, max) = if reverse && (by_score || by_lex) {
+                    (stop, start)
+                } else {
+                    (start, stop)
+                };
+                let range = match (by_score, by_lex) {
+                    (false, false) if limit.is_none() => ZRange::Index {
+                        start: parsed(&min)?,
+                        stop: parsed(&max)?,
+                    },
+                    (true, false) => ZRange::Score {
+                        min: parsed(&min)?,
+                        max: parsed(&max)?,
+                    },
+                    (false, true) if !with_scores => ZRange::Lex {
+                        min: parsed(&min)?,
+                        max: parsed(&max)?,
+                    },
+                    _ => return Err(ParseError::WrongArgument),
+                };
+                Ok(Self::ZRange {
+                    key,
+                    range,
+                    reverse,
+                    limit,
+                    with_scores,
+                })
+            }
+            "zremrangebyrank" => Ok(Self::ZRemRange {
+                key: args.next()?,
+                range: ZRange::Index {
+                    start: args.next_parsed()?,
+                    stop: args.next_parsed()?,
+                },
+            }),
+            "zremrangebyscore" => Ok(Self::ZRemRange {
+                key: args.next()?,
+                range: ZRange::Score {
+                    min: parsed(&args.next()?)?,
+                    max: parsed(&args.next()?)?,
+                },
+            }),
+            "zinterstore" | "zunionstore" | "zdiffstore" => {
+                let destination = args.next()?;
+                let numkeys = args.next_parsed::<usize>()?;
+                if numkeys == 0 {
+                    return Err(ParseError::WrongArgument);
+                }
+                let keys = (0..numkeys)
+                    .map(|_| args.next())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let (mut weights, mut aggregate) = (vec![], Aggregate::default());
+                let mut options = args.remaining()?.into_iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_lowercase().as_str() {
+                        // Differences take neither weights nor an aggregate function.
+                        _ if command == "zdiffstore" => return Err(ParseError::WrongArgument),
+                        "weights" => {
+                            weights = (0..numkeys)
+                                .map(|_| parsed(&options.next().ok_or(MissingArgument)?))
+                                .collect::<Result<_, _>>()?;
+                        }
+                        "aggregate" => {
+                            let function = options.next().ok_or(MissingArgument)?;
+                            aggregate = match function.to_ascii_lowercase().as_str() {
+                                "sum" => Aggregate::Sum,
+                                "min" => Aggregate::Min,
+                                "max" => Aggregate::Max,
+                                _ => return Err(ParseError::WrongArgument),
+                            };
+                        }
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::ZSetOperationStore {
+                    operation: set_operation(&command),
+                    destination,
+                    keys,
+                    weights,
+                    aggregate,
+                })
+            }
+            "xadd" => {
+                let key = args.next()?;
+                let arguments = args.rest()?;
+                let mut options = XAddOptions::default();
+                let mut rest = arguments.iter();
+                let id = loop {
+                    let argument = rest.next().ok_or(ParseError::MissingArgument)?;
+                    let option = argument.to_ascii_lowercase();
+                    match option.as_str() {
+                        "nomkstream" => {
+                            options.no_make_stream = true;
+                            continue;
+                        }
+                        "maxlen" | "minid" => options.trim = Some(parse_trim(&option, &mut rest)?),
+                        _ => break parsed::<IdSpec>(argument)?,
+                    }
+                };
+                let rest = rest.as_slice();
+                if rest.is_empty() || rest.len() % 2 != 0 {
+                    return Err(ParseError::WrongArgument);
+                }
+                let fields = rest
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                Ok(Self::XAdd {
+                    key,
+                    options,
+                    id,
+                    fields,
+                })
+            }
+            "xgroup" => match args.next()?.to_ascii_lowercase().as_str() {
+                "create" => {
+                    let (key, group, from) = (args.next()?, args.next()?, args.next_parsed()?);
+                    let make_stream = match args.remaining()?.as_slice() {
+                        [] => false,
+                        [option] if option.eq_ignore_ascii_case("mkstream") => true,
+                        _ => return Err(ParseError::WrongArgument),
+                    };
+                    Ok(Self::XGroupCreate {
+                        key,
+                        group,
+                        from,
+                        make_stream,
+                    })
+                }
+                "setid" => Ok(Self::XGroupSetId {
+                    key: args.next()?,
+                    group: args.next()?,
+                    from: args.next_parsed()?,
+                }),
+                "destroy" => Ok(Self::XGroupDestroy {
+                    key: args.next()?,
+                    group: args.next()?,
+                }),
+                "createconsumer" => Ok(Self::XGroupCreateConsumer {
+                    key: args.next()?,
+                    group: args.next()?,
+                    consumer: args.next()?,
+                }),
+                "delconsumer" => Ok(Self::XGroupDelConsumer {
+                    key: args.next()?,
+                    group: args.next()?,
+                    consumer: args.next()?,
+                }),
+                _ => Err(UnknownCommand(command)),
+            },
+            "xreadgroup" => {
+                if !args.next()?.eq_ignore_ascii_case("group") {
+                    return Err(ParseError::WrongArgument);
+                }
+                let (group, consumer) = (args.next()?, args.next()?);
+                let (mut count, mut block, mut no_ack) = (None, None, false);
+                loop {
+                    match args.next()?.to_ascii_lowercase().as_str() {
+                        "count" => count = Some(args.next_parsed()?),
+                        "block" => block = Some(Duration::from_millis(args.next_parsed()?)),
+                        "noack" => no_ack = true,
+                        "streams" => break,
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::XReadGroup {
+                    group,
+                    consumer,
+                    streams: parse_streams(&args.rest()?)?,
+                    count,
+                    block,
+                    no_ack,
+                })
+            }
+            "xack" => Ok(Self::XAck {
+                key: args.next()?,
+                group: args.next()?,
+                ids: args
+                    .rest()?
+                    .iter()
+                    .map(|id| parsed(id))
+                    .collect::<Result<_, _>>()?,
+            }),
+            "xpending" => {
+                let (key, group) = (args.next()?, args.next()?);
+                let arguments = args.remaining()?;
+                let range = match arguments.as_slice() {
+                    [] => None,
+                    _ => Some(parse_pending_range(&arguments)?),
+                };
+                Ok(Self::XPending { key, group, range })
+            }
+            "xclaim" => {
+                let (key, group, consumer) = (args.next()?, args.next()?, args.next()?);
+                let min_idle = args.next_parsed()?;
+                let arguments = args.rest()?;
+                // The IDs go on until the first argument that is not one.
+                let ids: Vec<StreamId> = arguments.iter().map_while(|id| id.parse().ok()).collect();
+                if ids.is_empty() {
+                    return Err(ParseError::WrongArgument);
+                }
+                let mut rest = arguments[ids.len()..].iter();
+                let mut options = XClaimOptions::default();
+                while let Some(option) = rest.next() {
+                    let mut value = || rest.next().ok_or(ParseError::MissingArgument);
+                    match option.to_ascii_lowercase().as_str() {
+                        "idle" => options.idle = Some(parsed(value()?)?),
+                        "time" => options.time = Some(parsed(value()?)?),
+                        "retrycount" => options.retry_count = Some(parsed(value()?)?),
+                        "lastid" => options.last_id = Some(parsed(value()?)?),
+                        "force" => options.force = true,
+                        "justid" => options.just_id = true,
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::XClaim {
+                    key,
+                    group,
+                    consumer,
+                    min_idle,
+                    ids,
+                    options,
+                })
+            }
+            "xautoclaim" => {
+                let (key, group, consumer) = (args.next()?, args.next()?, args.next()?);
+                let (min_idle, start) = (args.next_parsed()?, args.next_parsed()?);
+                let arguments = args.remaining()?;
+                let mut rest = arguments.iter();
+                let mut options = AutoClaimOptions::default();
+                while let Some(option) = rest.next() {
+                    match option.to_ascii_lowercase().as_str() {
+                        "count" => {
+                            let count = rest.next().ok_or(ParseError::MissingArgument)?;
+                            options.count = parsed(count)?;
+                            if options.count == 0 {
+                                return Err(ParseError::WrongArgument);
+                            }
+                        }
+                        "justid" => options.just_id = true,
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::XAutoClaim {
+                    key,
+                    group,
+                    consumer,
+                    min_idle,
+                    start,
+                    options,
+                })
+            }
+            "xinfo" => match args.next()?.to_ascii_lowercase().as_str() {
+                "stream" => Ok(Self::XInfoStream { key: args.next()? }),
+                "groups" => Ok(Self::XInfoGroups { key: args.next()? }),
+                "consumers" => Ok(Self::XInfoConsumers {
+                    key: args.next()?,
+                    group: args.next()?,
+                }),
+                _ => Err(UnknownCommand(command)),
+            },
+            "xlen" => Ok(Self::XLen { key: args.next()? }),
+            "xdel" => Ok(Self::XDel {
+                key: args.next()?,
+                ids: args
+                    .rest()?
+                    .iter()
+                    .map(|id| parsed(id))
+                    .collect::<Result<_, _>>()?,
+            }),
+            "xtrim" => {
+                let key = args.next()?;
+                let arguments = args.rest()?;
+                let mut rest = arguments.iter();
+                let strategy = rest
+                    .next()
+                    .ok_or(ParseError::MissingArgument)?
+                    .to_ascii_lowercase();
+                if !matches!(strategy.as_str(), "maxlen" | "minid") {
+                    return Err(ParseError::WrongArgument);
+                }
+                let trim = parse_trim(&strategy, &mut rest)?;
+                Ok(Self::XTrim { key, trim })
+            }
+            "xsetid" => {
+                let (key, last_id) = (args.next()?, args.next_parsed()?);
+                let (mut entries_added, mut max_deleted_id) = (None, None);
+                let arguments = args.remaining()?;
+                let mut rest = arguments.iter();
+                while let Some(option) = rest.next() {
+                    let value = rest.next().ok_or(ParseError::MissingArgument)?;
+                    match option.to_ascii_lowercase().as_str() {
+                        "entriesadded" => entries_added = Some(parsed(value)?),
+                        "maxdeletedid" => max_deleted_id = Some(parsed(value)?),
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                Ok(Self::XSetId {
+                    key,
+                    last_id,
+                    entries_added,
+                    max_deleted_id,
+                })
+            }
+            "xrange" | "xrevrange" => {
+                let key = args.next()?;
+                let (first, second) = (args.next()?, args.next()?);
+                let reverse = command == "xrevrange";
+                // `XREVRANGE` expects the end of the interval to come first.
+                let (start, end) = if reverse {
+                    (second, first)
+                } else {
+                    (first, second)
+                };
+                let count = match args.optional_parsed::<String>()? {
+                    Some(option) if option.eq_ignore_ascii_case("count") => {
+                        Some(args.next_parsed()?)
                     }
-                    ("config", Ok(Some("get")), key, _) => Ok(Self::ConfigGet {
-                        key: key?.ok_or(MissingArgument)?.to_string(),
+                    Some(_) => return Err(ParseError::WrongArgument),
+                    None => None,
+                };
+                Ok(Self::XRange {
+                    key,
+                    start: parsed(&start)?,
+                    end: parsed(&end)?,
+                    count,
+                    reverse,
+                })
+            }
+            "xread" => {
+                let (mut count, mut block) = (None, None);
+                loop {
+                    match args.next()?.to_ascii_lowercase().as_str() {
+                        "count" => count = Some(args.next_parsed()?),
+                        "block" => block = Some(Duration::from_millis(args.next_parsed()?)),
+                        "streams" => break,
+                        _ => return Err(ParseError::WrongArgument),
+                    }
+                }
+                let streams = parse_streams(&args.rest()?)?;
+                Ok(Self::XRead {
+                    streams,
+                    count,
+                    block,
+                })
+            }
+            "debug" => match args.next()?.to_ascii_lowercase().as_str() {
+                "sleep" => Ok(Self::DebugSleep {
+                    duration: Duration::try_from_secs_f64(args.next_parsed()?)
+                        .map_err(|_| ParseError::WrongArgument)?,
+                }),
+                "object" => Ok(Self::DebugObject { key: args.next()? }),
+                "set-active-expire" => Ok(Self::DebugSetActiveExpire {
+                    enabled: args.next_parsed::<i64>()? != 0,
+                }),
+                "jmap" => Ok(Self::DebugJmap),
+                "chaos" => match args.next()?.to_ascii_lowercase().as_str() {
+                    "set" => Ok(Self::ChaosSet {
+                        command: args.next()?,
+                        fault: Fault::from_options(&args.remaining()?)
+                            .ok_or(ParseError::WrongArgument)?,
                     }),
+                    "clear" => Ok(Self::ChaosClear {
+                        command: args.optional_parsed()?,
+                    }),
+                    "list" => Ok(Self::ChaosList),
                     _ => Err(UnknownCommand(command)),
+                },
+                _ => Err(UnknownCommand(command)),
+            },
+            "sadd" => Ok(Self::SAdd {
+                key: args.next()?,
+                members: args.rest()?,
+            }),
+            "srem" => Ok(Self::SRem {
+                key: args.next()?,
+                members: args.rest()?,
+            }),
+            "smove" => Ok(Self::SMove {
+                source: args.next()?,
+                destination: args.next()?,
+                member: args.next()?,
+            }),
+            "smembers" => Ok(Self::SMembers { key: args.next()? }),
+            "sismember" => Ok(Self::SIsMember {
+                key: args.next()?,
+                member: args.next()?,
+            }),
+            "smismember" => Ok(Self::SMIsMember {
+                key: args.next()?,
+                members: args.rest()?,
+            }),
+            "scard" => Ok(Self::SCard { key: args.next()? }),
+            "sintercard" => {
+                let numkeys = args.next_parsed::<usize>()?;
+                if numkeys == 0 {
+                    return Err(ParseError::WrongArgument);
+                }
+                let keys = (0..numkeys)
+                    .map(|_| args.next())
+                    .collect::<Result<_, _>>()?;
+                let limit = match args.remaining()?.as_slice() {
+                    [] => 0,
+                    [option, limit] if option.eq_ignore_ascii_case("limit") => {
+                        limit.parse().map_err(|_| ParseError::WrongArgument)?
+                    }
+                    _ => return Err(ParseError::WrongArgument),
+                };
+                Ok(Self::SInterCard { keys, limit })
+            }
+            "spop" => Ok(Self::SPop {
+                key: args.next()?,
+                count: args.optional_parsed()?,
+            }),
+            "srandmember" => Ok(Self::SRandMember {
+                key: args.next()?,
+                count: random_count(args.optional_parsed()?)?,
+            }),
+            "sinter" | "sunion" | "sdiff" => Ok(Self::SetOperation {
+                operation: set_operation(&command),
+                keys: args.rest()?,
+            }),
+            "sinterstore" | "sunionstore" | "sdiffstore" => Ok(Self::SetOperationStore {
+                operation: set_operation(&command),
+                destination: args.next()?,
+                keys: args.rest()?,
+            }),
+            "zadd" => {
+                let key = args.next()?;
+                let arguments = args.rest()?;
+                let mut options = ZAddOptions::default();
+                let mut rest = arguments.as_slice();
+                while let Some((flag, tail)) = rest.split_first() {
+                    match flag.to_ascii_lowercase().as_str() {
+                        "nx" => options.only_new = true,
+                        "xx" => options.only_existing = true,
+                        "gt" => options.greater = true,
+                        "lt" => options.less = true,
+                        "ch" => options.changed = true,
+                        "incr" => options.increment = true,
+                        _ => break,
+                    }
+                    rest = tail;
+                }
+                if !options.are_compatible() || rest.is_empty() || rest.len() % 2 != 0 {
+                    return Err(ParseError::WrongArgument);
+                }
+                let members = rest
+                    .chunks(2)
+                    .map(|pair| {
+                        pair[0]
+                            .parse::<Score>()
+                            .map(|score| (score, pair[1].clone()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| ParseError::WrongArgument)?;
+                if options.increment && members.len() != 1 {
+                    return Err(ParseError::WrongArgument);
                 }
+                Ok(Self::ZAdd {
+                    key,
+                    options,
+                    members,
+                })
             }
+            "geoadd" => {
+                let key = args.next()?;
+                let arguments = args.rest()?;
+                let mut options = ZAddOptions::default();
+                let mut rest = arguments.as_slice();
+                while let Some((flag, tail)) = rest.split_first() {
+                    match flag.to_ascii_lowercase().as_str() {
+                        "nx" => options.only_new = true,
+                        "xx" => options.only_existing = true,
+                        "ch" => options.changed = true,
+                        _ => break,
+                    }
+                    rest = tail;
+                }
+                if options.only_new && options.only_existing
+                    || rest.is_empty()
+                    || rest.len() % 3 != 0
+                {
+                    return Err(ParseError::WrongArgument);
+                }
+                let locations = rest
+                    .chunks(3)
+                    .map(|triple| {
+                        let coordinates = Coordinates {
+                            longitude: parse_degrees(&triple[0])?,
+                            latitude: parse_degrees(&triple[1])?,
+                        };
+                        Ok((coordinates, triple[2].clone()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Self::GeoAdd {
+                    key,
+                    options,
+                    locations,
+                })
+            }
+            "geopos" => Ok(Self::GeoPos {
+                key: args.next()?,
+                members: args.remaining()?,
+            }),
+            "geodist" => Ok(Self::GeoDist {
+                key: args.next()?,
+                from: args.next()?,
+                to: args.next()?,
+                unit: match args.optional_parsed::<String>()? {
+                    Some(unit) => parse_distance_unit(&unit)?,
+                    None => DistanceUnit::Meters,
+                },
+            }),
+            "geosearch" => {
+                let key = args.next()?;
+                let (search, flags) =
+                    parse_geosearch(&args.rest()?, &["withdist", "withhash", "withcoord"])?;
+                Ok(Self::GeoSearch {
+                    key,
+                    search,
+                    with_dist: flags[0],
+                    with_hash: flags[1],
+                    with_coord: flags[2],
+                })
+            }
+            "geosearchstore" => {
+                let destination = args.next()?;
+                let source = args.next()?;
+                let (search, flags) = parse_geosearch(&args.rest()?, &["storedist"])?;
+                Ok(Self::GeoSearchStore {
+                    destination,
+                    source,
+                    search,
+                    store_dist: flags[0],
+                })
+            }
+            "zincrby" => Ok(Self::ZIncrBy {
+                key: args.next()?,
+                increment: args.next_parsed()?,
+                member: args.next()?,
+            }),
+            "zscore" => Ok(Self::ZScore {
+                key: args.next()?,
+                member: args.next()?,
+            }),
+            "zcard" => Ok(Self::ZCard { key: args.next()? }),
+            "zrank" | "zrevrank" => {
+                let key = args.next()?;
+                let member = args.next()?;
+                let with_score = match args.remaining()?.as_slice() {
+                    [] => false,
+                    [option] if option.eq_ignore_ascii_case("withscore") => true,
+                    _ => return Err(ParseError::WrongArgument),
+                };
+                Ok(Self::ZRank {
+                    key,
+                    member,
+                    reverse: command == "zrevrank",
+                    with_score,
+                })
+            }
+            _ => Err(UnknownCommand(command)),
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::Command;
-    use crate::{database::Value, resp::Token};
+/// Parse a single argument into a `T`.
+/// Parse the `numkeys` argument of `EVAL` and `EVALSHA`, followed by the key names and
+/// the other arguments of the script.
+fn parse_script_args(args: &mut Arguments) -> Result<(Vec<String>, Vec<String>), ParseError> {
+    let numkeys = args.next_parsed::<usize>()?;
+    let mut keys = args.remaining()?;
+    if numkeys > keys.len() {
+        return Err(ParseError::WrongArgument);
+    }
+    let args = keys.split_off(numkeys);
+    Ok((keys, args))
+}
 
-    #[test]
-    fn parse_ping() {
-        let tokens = Token::try_from("+ping\r\n").unwrap();
-        let command = Command::try_from(tokens).unwrap();
-        assert_eq!(command, Command::Ping);
+/// Check the optional `ASYNC` or `SYNC` of `SCRIPT FLUSH` and `FUNCTION FLUSH`.
+///
+/// Flushing is instant either way, so both are the same here.
+fn parse_flush_mode(mode: Option<String>) -> Result<(), ParseError> {
+    match mode.map(|mode| mode.to_ascii_lowercase()).as_deref() {
+        None | Some("async" | "sync") => Ok(()),
+        Some(_) => Err(ParseError::WrongArgument),
     }
+}
 
-    #[test]
-    fn parse_echo() {
-        let tokens = Token::try_from("*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n").unwrap();
-        let command = Command::try_from(tokens).unwrap();
-        assert_eq!(
-            command,
-            Command::Echo {
-                message: String::from("hey")
-            }
-        );
+fn parsed<T: std::str::FromStr>(arg: &str) -> Result<T, ParseError> {
+    arg.parse().map_err(|_| ParseError::WrongArgument)
+}
+
+/// Check the `count` of `SRANDMEMBER` or `ZRANDMEMBER`, which like in Redis goes from
+/// `-i64::MAX` to `i64::MAX`, as there is no positive count of as many elements as `i64::MIN`.
+fn random_count(count: Option<i64>) -> Result<Option<i64>, ParseError> {
+    match count {
+        Some(i64::MIN) => Err(ParseError::WrongArgument),
+        count => Ok(count),
     }
+}
 
-    #[test]
-    fn parse_get() {
-        let tokens = Token::try_from("*2\r\n$4\r\nGET\r\n$3\r\nfoo\r\n").unwrap();
-        let command = Command::try_from(tokens).unwrap();
-        assert_eq!(
-            command,
-            Command::Get {
-                key: "foo".to_string(),
+/// An argument as text, which it has to be unless it is a value.
+fn text(arg: Vec<u8>) -> Result<String, ParseError> {
+    String::from_utf8(arg).map_err(|_| ParseError::NotText)
+}
+
+/// How long until the Unix time `deadline`, or zero if it passed already.
+fn until(deadline: Duration) -> Duration {
+    (UNIX_EPOCH + deadline)
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
+
+/// Tell which [`SetOperation`] a `SINTER`/`SUNION`/`SDIFF`-like command performs.
+fn set_operation(command: &str) -> SetOperation {
+    match command {
+        "sinter" | "sinterstore" | "zinterstore" => SetOperation::Intersection,
+        "sunion" | "sunionstore" | "zunionstore" => SetOperation::Union,
+        _ => SetOperation::Difference,
+    }
+}
+
+/// Parse the `STREAMS` arguments of `XREAD` and `XREADGROUP`: a number of keys,
+/// followed by the same number of IDs to read each of the streams from.
+fn parse_streams<T: std::str::FromStr>(
+    arguments: &[String],
+) -> Result<Vec<(String, T)>, ParseError> {
+    if arguments.len() % 2 != 0 {
+        return Err(ParseError::WrongArgument);
+    }
+    let (keys, ids) = arguments.split_at(arguments.len() / 2);
+    keys.iter()
+        .zip(ids)
+        .map(|(key, id)| Ok((key.clone(), parsed(id)?)))
+        .collect()
+}
+
+/// Parse the trimming options of `XADD` and `XTRIM` that follow `MAXLEN` or `MINID`
+/// (the `strategy`): `[= | ~] threshold [LIMIT count]`.
+fn parse_trim(strategy: &str, rest: &mut std::slice::Iter<'_, String>) -> Result<Trim, ParseError> {
+    let mut threshold = rest.next().ok_or(ParseError::MissingArgument)?;
+    let approximate = threshold == "~";
+    if approximate || threshold == "=" {
+        threshold = rest.next().ok_or(ParseError::MissingArgument)?;
+    }
+    let strategy = match strategy {
+        "maxlen" => TrimStrategy::MaxLen(parsed(threshold)?),
+        _ => TrimStrategy::MinId(parsed(threshold)?),
+    };
+    let mut limit = 0;
+    if rest
+        .as_slice()
+        .first()
+        .is_some_and(|arg| arg.eq_ignore_ascii_case("limit"))
+    {
+        // Only approximate trimming can be limited.
+        if !approximate {
+            return Err(ParseError::WrongArgument);
+        }
+        let _ = rest.next();
+        limit = parsed(rest.next().ok_or(ParseError::MissingArgument)?)?;
+    }
+    Ok(Trim {
+        strategy,
+        approximate,
+        limit,
+    })
+}
+
+/// Parse the value of a single bit, which is either `0` or `1`.
+fn parse_bit(bit: &str) -> Result<bool, ParseError> {
+    match bit {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse a longitude or a latitude, which is then validated by the database.
+fn parse_degrees(degrees: &str) -> Result<f64, ParseError> {
+    match parsed::<f64>(degrees)? {
+        degrees if degrees.is_finite() => Ok(degrees),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the options of `GEOSEARCH` and `GEOSEARCHSTORE`, along with which of the `flags`
+/// specific to either of them are present.
+fn parse_geosearch(
+    arguments: &[String],
+    flags: &[&str],
+) -> Result<(GeoSearch, Vec<bool>), ParseError> {
+    let (mut origin, mut shape) = (None, None);
+    let (mut unit, mut sort, mut count, mut any) = (DistanceUnit::Meters, None, None, false);
+    let mut present = vec![false; flags.len()];
+    let mut rest = arguments;
+    while let Some((option, tail)) = rest.split_first() {
+        let option = option.to_ascii_lowercase();
+        rest = match (option.as_str(), tail) {
+            ("frommember", [member, tail @ ..]) if origin.is_none() => {
+                origin = Some(GeoOrigin::Member(member.clone()));
+                tail
             }
-        );
+            ("fromlonlat", [longitude, latitude, tail @ ..]) if origin.is_none() => {
+                origin = Some(GeoOrigin::Coordinates(Coordinates {
+                    longitude: parse_degrees(longitude)?,
+                    latitude: parse_degrees(latitude)?,
+                }));
+                tail
+            }
+            ("byradius", [radius, radius_unit, tail @ ..]) if shape.is_none() => {
+                shape = Some(GeoShape::Radius(parse_size(radius)?));
+                unit = parse_distance_unit(radius_unit)?;
+                tail
+            }
+            ("bybox", [width, height, box_unit, tail @ ..]) if shape.is_none() => {
+                shape = Some(GeoShape::Box {
+                    width: parse_size(width)?,
+                    height: parse_size(height)?,
+                });
+                unit = parse_distance_unit(box_unit)?;
+                tail
+            }
+            ("asc", tail) => {
+                sort = Some(false);
+                tail
+            }
+            ("desc", tail) => {
+                sort = Some(true);
+                tail
+            }
+            ("count", [limit, tail @ ..]) => {
+                count = match parsed(limit)? {
+                    0 => return Err(ParseError::WrongArgument),
+                    limit => Some(limit),
+                };
+                match tail.split_first() {
+                    Some((flag, tail)) if flag.eq_ignore_ascii_case("any") => {
+                        any = true;
+                        tail
+                    }
+                    _ => tail,
+                }
+            }
+            (option, tail) => {
+                let index = flags.iter().position(|flag| *flag == option);
+                present[index.ok_or(ParseError::WrongArgument)?] = true;
+                tail
+            }
+        };
     }
+    let (Some(origin), Some(shape)) = (origin, shape) else {
+        return Err(ParseError::WrongArgument);
+    };
+    let search = GeoSearch {
+        origin,
+        shape,
+        unit,
+        sort,
+        count,
+        any,
+    };
+    Ok((search, present))
+}
 
-    #[test]
-    fn parse_set() {
-        let tokens = Token::try_from("*3\r\n$4\r\nSET\r\n$3\r\nfoo\r\n+bar\r\n").unwrap();
-        let command = Command::try_from(tokens).unwrap();
-        assert_eq!(
-            command,
-            Command::Set {
-                key: "foo".to_string(),
-                value: Value::without_ttl("bar".to_string())
+/// Parse a radius, a width or a height, which must not be negative.
+fn parse_size(size: &str) -> Result<f64, ParseError> {
+    match parsed::<f64>(size)? {
+        size if size.is_finite() && size >= 0.0 => Ok(size),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the unit of a distance: `m`, `km`, `mi` or `ft`.
+fn parse_distance_unit(unit: &str) -> Result<DistanceUnit, ParseError> {
+    match unit.to_ascii_lowercase().as_str() {
+        "m" => Ok(DistanceUnit::Meters),
+        "km" => Ok(DistanceUnit::Kilometers),
+        "mi" => Ok(DistanceUnit::Miles),
+        "ft" => Ok(DistanceUnit::Feet),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the offset of a bit in a string, which is limited by the size of strings.
+fn parse_bit_offset(offset: &str) -> Result<usize, ParseError> {
+    match parsed(offset)? {
+        offset if offset <= MAX_BIT_OFFSET => Ok(offset),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the range of `BITCOUNT` and `BITPOS`: `start [end [BYTE|BIT]]`.
+fn parse_bit_range(
+    start: &str,
+    end: Option<&String>,
+    unit: &[String],
+) -> Result<BitRange, ParseError> {
+    let unit = match unit {
+        [] => BitUnit::Byte,
+        [unit] if unit.eq_ignore_ascii_case("byte") => BitUnit::Byte,
+        [unit] if unit.eq_ignore_ascii_case("bit") => BitUnit::Bit,
+        _ => return Err(ParseError::WrongArgument),
+    };
+    Ok(BitRange {
+        start: parsed(start)?,
+        end: end.map(|end| parsed(end)).transpose()?,
+        unit,
+    })
+}
+
+/// Parse the operations of `BITFIELD`, of which `BITFIELD_RO` (`read_only`) only allows `GET`.
+fn parse_bitfield(arguments: &[String], read_only: bool) -> Result<Vec<BitFieldOp>, ParseError> {
+    let mut operations = vec![];
+    let mut arguments = arguments.iter();
+    while let Some(operation) = arguments.next() {
+        let mut next = || arguments.next().ok_or(ParseError::WrongArgument);
+        let operation = match operation.to_ascii_lowercase().as_str() {
+            "get" => {
+                let kind = parse_bitfield_type(next()?)?;
+                let offset = parse_bitfield_offset(next()?, kind)?;
+                BitFieldOp::Get { kind, offset }
             }
-        );
+            _ if read_only => return Err(ParseError::WrongArgument),
+            "set" => {
+                let kind = parse_bitfield_type(next()?)?;
+                let offset = parse_bitfield_offset(next()?, kind)?;
+                let value = parsed(next()?)?;
+                BitFieldOp::Set {
+                    kind,
+                    offset,
+                    value,
+                }
+            }
+            "incrby" => {
+                let kind = parse_bitfield_type(next()?)?;
+                let offset = parse_bitfield_offset(next()?, kind)?;
+                let increment = parsed(next()?)?;
+                BitFieldOp::IncrBy {
+                    kind,
+                    offset,
+                    increment,
+                }
+            }
+            "overflow" => BitFieldOp::Overflow(match next()?.to_ascii_lowercase().as_str() {
+                "wrap" => BitFieldOverflow::Wrap,
+                "sat" => BitFieldOverflow::Sat,
+                "fail" => BitFieldOverflow::Fail,
+                _ => return Err(ParseError::WrongArgument),
+            }),
+            _ => return Err(ParseError::WrongArgument),
+        };
+        operations.push(operation);
+    }
+    Ok(operations)
+}
+
+/// Parse the type of a `BITFIELD` integer: `i1` to `i64`, or `u1` to `u63`.
+fn parse_bitfield_type(kind: &str) -> Result<BitFieldType, ParseError> {
+    let (signed, bits) = match (kind.strip_prefix(['i', 'I']), kind.strip_prefix(['u', 'U'])) {
+        (Some(bits), _) => (true, bits),
+        (_, Some(bits)) => (false, bits),
+        (None, None) => return Err(ParseError::WrongArgument),
+    };
+    match parsed(bits)? {
+        bits @ 1..=63 => Ok(BitFieldType { signed, bits }),
+        64 if signed => Ok(BitFieldType { signed, bits: 64 }),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the offset of a `BITFIELD` integer, in bits or, prefixed with `#`, in integers of its type.
+fn parse_bitfield_offset(offset: &str, kind: BitFieldType) -> Result<usize, ParseError> {
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => parsed::<usize>(index)?
+            .checked_mul(kind.bits as usize)
+            .ok_or(ParseError::WrongArgument)?,
+        None => parsed(offset)?,
+    };
+    match offset.checked_add(kind.bits as usize - 1) {
+        Some(last) if last <= MAX_BIT_OFFSET => Ok(offset),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the filter of `CLIENT LIST`: either `TYPE type` or `ID id [id ...]`.
+fn parse_client_filter(arguments: &[String]) -> Result<Option<Filter>, ParseError> {
+    match arguments {
+        [] => Ok(None),
+        [option, kind] if option.eq_ignore_ascii_case("type") => Kind::from_name(kind)
+            .map(|kind| Some(Filter::Kind(kind)))
+            .ok_or(ParseError::WrongArgument),
+        [option, ids @ ..] if option.eq_ignore_ascii_case("id") && !ids.is_empty() => {
+            let ids = ids.iter().map(|id| parsed(id)).collect::<Result<_, _>>()?;
+            Ok(Some(Filter::Ids(ids)))
+        }
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse a switch of `CLIENT`, which is either `on` or `off`.
+fn parse_on_off(switch: &str) -> Result<bool, ParseError> {
+    match switch.to_ascii_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ParseError::WrongArgument),
+    }
+}
+
+/// Parse the filters of `CLIENT KILL`, given as pairs of an option and its value,
+/// along with `SKIPME`, which spares the client itself unless it is `no`.
+fn parse_client_kill(arguments: &[String]) -> Result<Command, ParseError> {
+    if arguments.len() % 2 != 0 {
+        return Err(ParseError::WrongArgument);
+    }
+    let (mut filters, mut skip_me) = (vec![], true);
+    for pair in arguments.chunks(2) {
+        let value = pair[1].clone();
+        match pair[0].to_ascii_lowercase().as_str() {
+            "id" => filters.push(Filter::Ids(vec![parsed(&value)?])),
+            "type" => {
+                let kind = Kind::from_name(&value).ok_or(ParseError::WrongArgument)?;
+                filters.push(Filter::Kind(kind));
+            }
+            "addr" => filters.push(Filter::Addr(value)),
+            "laddr" => filters.push(Filter::LocalAddr(value)),
+            "skipme" => {
+                skip_me = match value.to_ascii_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(ParseError::WrongArgument),
+                }
+            }
+            _ => return Err(ParseError::WrongArgument),
+        }
+    }
+    Ok(Command::ClientKill { filters, skip_me })
+}
+
+/// Parse the range of `XPENDING`: `[IDLE min-idle-time] start end count [consumer]`.
+fn parse_pending_range(arguments: &[String]) -> Result<PendingRange, ParseError> {
+    let (min_idle, arguments) = match arguments {
+        [idle, min_idle, rest @ ..] if idle.eq_ignore_ascii_case("idle") => {
+            (parsed(min_idle)?, rest)
+        }
+        _ => (0, arguments),
+    };
+    let (start, end, count, consumer) = match arguments {
+        [start, end, count] => (start, end, count, None),
+        [start, end, count, consumer] => (start, end, count, Some(consumer.clone())),
+        [..] => return Err(ParseError::WrongArgument),
+    };
+    Ok(PendingRange {
+        min_idle,
+        start: parsed(start)?,
+        end: parsed(end)?,
+        count: parsed(count)?,
+        consumer,
+    })
+}
+
+/// A cursor over the arguments of a [`Command`], skipping the command name itself.
+#[derive(Debug)]
+struct Arguments {
+    args: std::iter::Skip<std::vec::IntoIter<Vec<u8>>>,
+}
+
+impl Arguments {
+    fn new(args: Vec<Vec<u8>>) -> Self {
+        Self {
+            args: args.into_iter().skip(1),
+        }
+    }
+
+    /// Take the next argument, which must be present, as text.
+    fn next(&mut self) -> Result<String, ParseError> {
+        text(self.next_bytes()?)
+    }
+
+    /// Take the next argument, which must be present, as the bytes that it is.
+    fn next_bytes(&mut self) -> Result<Vec<u8>, ParseError> {
+        self.args.next().ok_or(ParseError::MissingArgument)
+    }
+
+    /// Take the next argument, which must be present, and parse it into a `T`.
+    fn next_parsed<T: std::str::FromStr>(&mut self) -> Result<T, ParseError> {
+        parsed(&self.next()?)
+    }
+
+    /// Take the next argument, if there is one, and parse it into a `T`.
+    fn optional_parsed<T: std::str::FromStr>(&mut self) -> Result<Option<T>, ParseError> {
+        self.args.next().map(|arg| parsed(&text(arg)?)).transpose()
+    }
+
+    /// Take all the remaining arguments, if any.
+    fn remaining(&mut self) -> Result<Vec<String>, ParseError> {
+        self.args.by_ref().map(text).collect()
+    }
+
+    /// Take all the remaining arguments as the bytes that they are, if any.
+    fn remaining_bytes(&mut self) -> Vec<Vec<u8>> {
+        self.args.by_ref().collect()
+    }
+
+    /// Take all the remaining arguments, of which there must be at least one.
+    fn rest(&mut self) -> Result<Vec<String>, ParseError> {
+        let rest = self.remaining()?;
+        if rest.is_empty() {
+            return Err(ParseError::MissingArgument);
+        }
+        Ok(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use crate::clients::{Filter, Kind, Pause};
+    use crate::database::{Aggregate, Coordinates, DistanceUnit, ZAddOptions, ZRange};
+    use crate::database::{AutoClaimOptions, BitOperation, BitRange, BitUnit, PendingRange};
+    use crate::database::{BitFieldOp, BitFieldOverflow, BitFieldType};
+    use crate::database::{GeoOrigin, GeoSearch, GeoShape};
+    use crate::database::{IdSpec, ReadFrom, ReadGroupFrom, StreamId};
+    use crate::database::{LexBound, ScanOptions, Score, ScoreBound, SetOperation, Value};
+    use crate::database::{Trim, TrimStrategy};
+    use crate::database::{XAddOptions, XClaimOptions};
+    use crate::replication::ReplicaOf;
+    use crate::resp::Token;
+    use crate::scripting::functions::RestorePolicy;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn parse_ping() {
+        let tokens = Token::try_from("+ping\r\n").unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(command, Command::Ping { message: None });
+    }
+
+    #[test]
+    fn parse_quit_and_reset() {
+        assert_eq!(parse_args(&["QUIT"]).unwrap(), Command::Quit);
+        assert_eq!(parse_args(&["reset"]).unwrap(), Command::Reset);
+    }
+
+    #[test]
+    fn parse_transactions() {
+        assert_eq!(parse_args(&["MULTI"]).unwrap(), Command::Multi);
+        assert_eq!(parse_args(&["exec"]).unwrap(), Command::Exec);
+        assert_eq!(parse_args(&["Discard"]).unwrap(), Command::Discard);
+        assert_eq!(
+            parse_args(&["WATCH", "a", "b"]).unwrap(),
+            Command::Watch {
+                keys: vec!["a".into(), "b".into()]
+            }
+        );
+        assert!(parse_args(&["WATCH"]).is_err());
+        assert_eq!(parse_args(&["unwatch"]).unwrap(), Command::Unwatch);
+    }
+
+    #[test]
+    fn parse_eval() {
+        assert_eq!(
+            parse_args(&["EVAL", "return 1", "1", "k", "a", "b"]).unwrap(),
+            Command::Eval {
+                script: "return 1".into(),
+                keys: vec!["k".into()],
+                args: vec!["a".into(), "b".into()],
+            }
+        );
+        assert_eq!(
+            parse_args(&["eval", "return 1", "0"]).unwrap(),
+            Command::Eval {
+                script: "return 1".into(),
+                keys: vec![],
+                args: vec![],
+            }
+        );
+        assert!(parse_args(&["EVAL", "return 1", "2", "k"]).is_err());
+        assert!(parse_args(&["EVAL", "return 1", "-1"]).is_err());
+        assert!(parse_args(&["EVAL", "return 1"]).is_err());
+        assert_eq!(
+            parse_args(&[
+                "EVALSHA",
+                "c664a3bf70bd1d45c4284ffebb65a6f2299bfc9f",
+                "1",
+                "k"
+            ])
+            .unwrap(),
+            Command::EvalSha {
+                sha1: "c664a3bf70bd1d45c4284ffebb65a6f2299bfc9f".into(),
+                keys: vec!["k".into()],
+                args: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_script() {
+        assert_eq!(
+            parse_args(&["SCRIPT", "load", "return 1"]).unwrap(),
+            Command::ScriptLoad {
+                script: "return 1".into()
+            }
+        );
+        assert_eq!(
+            parse_args(&["script", "EXISTS", "a", "b"]).unwrap(),
+            Command::ScriptExists {
+                sha1s: vec!["a".into(), "b".into()]
+            }
+        );
+        assert!(parse_args(&["SCRIPT", "EXISTS"]).is_err());
+        assert_eq!(
+            parse_args(&["SCRIPT", "FLUSH"]).unwrap(),
+            Command::ScriptFlush
+        );
+        assert_eq!(
+            parse_args(&["SCRIPT", "FLUSH", "async"]).unwrap(),
+            Command::ScriptFlush
+        );
+        assert!(parse_args(&["SCRIPT", "FLUSH", "later"]).is_err());
+        assert!(parse_args(&["SCRIPT", "KILL"]).is_err());
+    }
+
+    #[test]
+    fn parse_function() {
+        assert_eq!(
+            parse_args(&["FUNCTION", "LOAD", "code"]).unwrap(),
+            Command::FunctionLoad {
+                code: "code".into(),
+                replace: false
+            }
+        );
+        assert_eq!(
+            parse_args(&["function", "load", "REPLACE", "code"]).unwrap(),
+            Command::FunctionLoad {
+                code: "code".into(),
+                replace: true
+            }
+        );
+        assert!(parse_args(&["FUNCTION", "LOAD", "code", "REPLACE"]).is_err());
+        assert_eq!(
+            parse_args(&["FUNCTION", "LIST", "WITHCODE", "LIBRARYNAME", "l*"]).unwrap(),
+            Command::FunctionList {
+                pattern: Some("l*".into()),
+                with_code: true
+            }
+        );
+        assert!(parse_args(&["FUNCTION", "LIST", "LIBRARYNAME"]).is_err());
+        assert_eq!(
+            parse_args(&["FUNCTION", "DUMP"]).unwrap(),
+            Command::FunctionDump
+        );
+        assert_eq!(
+            parse_args(&["FUNCTION", "RESTORE", "payload", "flush"]).unwrap(),
+            Command::FunctionRestore {
+                payload: "payload".into(),
+                policy: RestorePolicy::Flush
+            }
+        );
+        assert!(parse_args(&["FUNCTION", "RESTORE", "payload", "merge"]).is_err());
+        assert_eq!(
+            parse_args(&["FUNCTION", "FLUSH", "SYNC"]).unwrap(),
+            Command::FunctionFlush
+        );
+        assert_eq!(
+            parse_args(&["FCALL_RO", "peek", "1", "k", "a"]).unwrap(),
+            Command::FCall {
+                function: "peek".into(),
+                keys: vec!["k".into()],
+                args: vec!["a".into()],
+                read_only: true
+            }
+        );
+        assert!(parse_args(&["FCALL", "peek", "2", "k"]).is_err());
+    }
+
+    #[test]
+    fn parse_echo() {
+        let tokens = Token::try_from("*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n").unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::Echo {
+                message: String::from("hey")
+            }
+        );
+    }
+
+    #[test]
+    fn parse_publish() {
+        assert_eq!(
+            parse_args(&["PUBLISH", "news", "hello world"]).unwrap(),
+            Command::Publish {
+                channel: "news".to_string(),
+                message: "hello world".to_string(),
+            }
+        );
+        assert!(parse_args(&["PUBLISH", "news"]).is_err());
+        assert_eq!(
+            parse_args(&["PSUBSCRIBE", "news.*", "h?llo"]).unwrap(),
+            Command::PSubscribe {
+                patterns: vec!["news.*".to_string(), "h?llo".to_string()],
+            }
+        );
+        assert!(parse_args(&["PSUBSCRIBE"]).is_err());
+        assert_eq!(
+            parse_args(&["PUNSUBSCRIBE"]).unwrap(),
+            Command::PUnsubscribe { patterns: vec![] }
+        );
+    }
+
+    #[test]
+    fn parse_sharded_pubsub() {
+        assert_eq!(
+            parse_args(&["SSUBSCRIBE", "a", "b"]).unwrap(),
+            Command::SSubscribe {
+                channels: vec!["a".to_string(), "b".to_string()]
+            }
+        );
+        assert!(parse_args(&["SSUBSCRIBE"]).is_err());
+        assert_eq!(
+            parse_args(&["SUNSUBSCRIBE"]).unwrap(),
+            Command::SUnsubscribe { channels: vec![] }
+        );
+        assert_eq!(
+            parse_args(&["SPUBLISH", "a", "hi"]).unwrap(),
+            Command::SPublish {
+                channel: "a".to_string(),
+                message: "hi".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_pubsub() {
+        assert_eq!(
+            parse_args(&["PUBSUB", "CHANNELS"]).unwrap(),
+            Command::PubSubChannels { pattern: None }
+        );
+        assert_eq!(
+            parse_args(&["pubsub", "channels", "n*"]).unwrap(),
+            Command::PubSubChannels {
+                pattern: Some("n*".to_string())
+            }
+        );
+        assert_eq!(
+            parse_args(&["PUBSUB", "NUMSUB", "a", "b"]).unwrap(),
+            Command::PubSubNumSub {
+                channels: vec!["a".to_string(), "b".to_string()]
+            }
+        );
+        assert_eq!(
+            parse_args(&["PUBSUB", "NUMPAT"]).unwrap(),
+            Command::PubSubNumPat
+        );
+        assert!(parse_args(&["PUBSUB", "NOPE"]).is_err());
+    }
+
+    #[test]
+    fn parse_get() {
+        let tokens = Token::try_from("*2\r\n$4\r\nGET\r\n$3\r\nfoo\r\n").unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::Get {
+                key: "foo".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_sadd() {
+        let tokens =
+            Token::try_from("*4\r\n$4\r\nSADD\r\n$1\r\ns\r\n$1\r\na\r\n$1\r\nb\r\n").unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::SAdd {
+                key: "s".to_string(),
+                members: vec!["a".to_string(), "b".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_sadd_without_members() {
+        let tokens = Token::try_from("*2\r\n$4\r\nSADD\r\n$1\r\ns\r\n").unwrap();
+        assert!(Command::try_from(tokens).is_err());
+    }
+
+    #[test]
+    fn parse_sinterstore() {
+        let tokens =
+            Token::try_from("*4\r\n$11\r\nSINTERSTORE\r\n$3\r\ndst\r\n$1\r\na\r\n$1\r\nb\r\n")
+                .unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::SetOperationStore {
+                operation: SetOperation::Intersection,
+                destination: "dst".to_string(),
+                keys: vec!["a".to_string(), "b".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_compare_and_set() {
+        let tokens = Token::try_from(
+            "*6\r\n$7\r\nEXT.CAS\r\n$4\r\nlock\r\n$1\r\na\r\n$1\r\nb\r\n$2\r\nPX\r\n$3\r\n500\r\n",
+        )
+        .unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::CompareAndSet {
+                key: "lock".to_string(),
+                expected: b"a".to_vec(),
+                new: b"b".to_vec(),
+                ttl: Some(Duration::from_millis(500)),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_sequence() {
+        assert_eq!(
+            parse_args(&["EXT.SEQUENCE", "ids", "100"]).unwrap(),
+            Command::Sequence {
+                key: "ids".to_string(),
+                count: 100,
+            }
+        );
+        assert!(parse_args(&["EXT.SEQUENCE", "ids", "0"]).is_err());
+        assert!(parse_args(&["EXT.SEQUENCE", "ids", "-5"]).is_err());
+    }
+
+    #[test]
+    fn parse_when_expires() {
+        assert_eq!(
+            parse_args(&["EXT.WHENEXPIRES", "k"]).unwrap(),
+            Command::WhenExpires {
+                key: "k".to_string()
+            }
+        );
+        assert!(parse_args(&["EXT.WHENEXPIRES"]).is_err());
+    }
+
+    #[test]
+    fn parse_compress() {
+        assert_eq!(
+            parse_args(&["EXT.COMPRESS", "4096"]).unwrap(),
+            Command::Compress {
+                min_len: Some(4096)
+            }
+        );
+        assert_eq!(
+            parse_args(&["EXT.COMPRESS", "off"]).unwrap(),
+            Command::Compress { min_len: None }
+        );
+        assert!(parse_args(&["EXT.COMPRESS", "zstd"]).is_err());
+        assert!(parse_args(&["EXT.COMPRESS"]).is_err());
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(
+            parse_args(&["KEYS", "user:*"]).unwrap(),
+            Command::Keys {
+                pattern: "user:*".to_string()
+            }
+        );
+        assert!(parse_args(&["KEYS"]).is_err());
+    }
+
+    #[test]
+    fn parse_del() {
+        assert_eq!(
+            parse_args(&["DEL", "a", "b"]).unwrap(),
+            Command::Del {
+                keys: vec!["a".to_string(), "b".to_string()]
+            }
+        );
+        assert_eq!(
+            parse_args(&["UNLINK", "a"]).unwrap(),
+            Command::Del {
+                keys: vec!["a".to_string()]
+            }
+        );
+        assert!(parse_args(&["DEL"]).is_err());
+    }
+
+    #[test]
+    fn parse_scan() {
+        let tokens = Token::try_from(
+            "*6\r\n$4\r\nSCAN\r\n$2\r\n42\r\n$5\r\nMATCH\r\n$2\r\nk*\r\n$5\r\nCOUNT\r\n$3\r\n100\r\n",
+        )
+        .unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::Scan {
+                cursor: 42,
+                options: ScanOptions {
+                    pattern: Some("k*".to_string()),
+                    count: Some(100),
+                    kind: None,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn parse_set() {
+        let tokens = Token::try_from("*3\r\n$4\r\nSET\r\n$3\r\nfoo\r\n+bar\r\n").unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::Set {
+                key: "foo".to_string(),
+                value: Value::without_ttl("bar".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn parse_shutdown() {
+        assert_eq!(
+            parse_args(&["SHUTDOWN"]).unwrap(),
+            Command::Shutdown { save: false }
+        );
+        assert_eq!(
+            parse_args(&["SHUTDOWN", "save"]).unwrap(),
+            Command::Shutdown { save: true }
+        );
+        assert!(parse_args(&["SHUTDOWN", "ABORT"]).is_err());
+    }
+
+    #[test]
+    fn parse_save() {
+        assert_eq!(parse_args(&["SAVE"]).unwrap(), Command::Save);
+        assert_eq!(
+            parse_args(&["BGSAVE"]).unwrap(),
+            Command::BgSave { schedule: false }
+        );
+        assert_eq!(
+            parse_args(&["bgsave", "schedule"]).unwrap(),
+            Command::BgSave { schedule: true }
+        );
+        assert!(parse_args(&["BGSAVE", "NOW"]).is_err());
+        assert_eq!(
+            parse_args(&["BGREWRITEAOF"]).unwrap(),
+            Command::BgRewriteAof
+        );
+        assert_eq!(parse_args(&["LASTSAVE"]).unwrap(), Command::LastSave);
+    }
+
+    #[test]
+    fn parse_replication_handshake() {
+        assert_eq!(
+            parse_args(&[
+                "REPLCONF",
+                "listening-port",
+                "6380",
+                "capa",
+                "eof",
+                "capa",
+                "psync2"
+            ])
+            .unwrap(),
+            Command::ReplConf {
+                listening_port: Some(6380),
+                capabilities: vec!["eof".to_string(), "psync2".to_string()],
+            }
+        );
+        assert!(parse_args(&["REPLCONF", "listening-port"]).is_err());
+        assert!(parse_args(&["REPLCONF", "listening-port", "port"]).is_err());
+        assert!(parse_args(&["REPLCONF", "unknown", "option"]).is_err());
+        assert_eq!(
+            parse_args(&["REPLCONF", "GETACK", "*"]).unwrap(),
+            Command::ReplConfGetAck
+        );
+        assert_eq!(
+            parse_args(&["replconf", "ack", "154"]).unwrap(),
+            Command::ReplConfAck { offset: 154 }
+        );
+        assert!(parse_args(&["REPLCONF", "ACK", "-1"]).is_err());
+        assert_eq!(
+            parse_args(&["PSYNC", "?", "-1"]).unwrap(),
+            Command::PSync {
+                replid: "?".to_string(),
+                offset: -1,
+            }
+        );
+        assert!(parse_args(&["PSYNC", "?"]).is_err());
+        assert_eq!(parse_args(&["ROLE"]).unwrap(), Command::Role);
+        assert_eq!(
+            parse_args(&["REPLICAOF", "no", "one"]).unwrap(),
+            Command::ReplicaOf { master: None }
+        );
+        assert_eq!(
+            parse_args(&["SLAVEOF", "localhost", "6379"]).unwrap(),
+            Command::ReplicaOf {
+                master: Some(ReplicaOf {
+                    host: "localhost".to_string(),
+                    port: 6379,
+                })
+            }
+        );
+        assert!(parse_args(&["REPLICAOF", "localhost", "one"]).is_err());
+        assert!(parse_args(&["REPLICAOF", "NO"]).is_err());
+        assert_eq!(
+            parse_args(&["WAIT", "2", "500"]).unwrap(),
+            Command::Wait {
+                replicas: 2,
+                timeout: Duration::from_millis(500),
+            }
+        );
+        assert!(parse_args(&["WAIT", "2", "-1"]).is_err());
+        assert!(parse_args(&["WAIT", "2"]).is_err());
+    }
+
+    #[test]
+    fn parse_set_options() {
+        let command = parse_args(&["SET", "foo", "bar", "PX", "100", "IDLETIME", "60"]).unwrap();
+        let Command::Set { value, .. } = command else {
+            panic!("Expected a SET, got {command:?}");
+        };
+        assert!(value.ttl_remaining() > Some(Duration::from_millis(50)));
+        assert!(value.idle_time() >= Duration::from_secs(60));
+
+        let command = parse_args(&["SET", "foo", "bar", "FREQ", "42"]).unwrap();
+        let Command::Set { value, .. } = command else {
+            panic!("Expected a SET, got {command:?}");
+        };
+        assert_eq!(value.frequency(), 42);
+
+        assert!(parse_args(&["SET", "foo", "bar", "IDLETIME", "1", "FREQ", "1"]).is_err());
+        assert!(parse_args(&["SET", "foo", "bar", "FREQ", "256"]).is_err());
+        assert!(parse_args(&["SET", "foo", "bar", "PX"]).is_err());
+
+        let in_a_minute = SystemTime::now() + Duration::from_secs(60);
+        let at = in_a_minute.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        let command = parse_args(&["SET", "foo", "bar", "PXAT", &at.to_string()]).unwrap();
+        let Command::Set { value, .. } = command else {
+            panic!("Expected a SET, got {command:?}");
+        };
+        assert!(value.ttl_remaining() > Some(Duration::from_secs(50)));
+        let command = parse_args(&["SET", "foo", "bar", "EXAT", "1"]).unwrap();
+        let Command::Set { value, .. } = command else {
+            panic!("Expected a SET, got {command:?}");
+        };
+        assert_eq!(value.ttl_remaining(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn parse_import() {
+        let command = parse_args(&["EXT.IMPORT", "a", "1", "10", "5", "b", "2", "0", "7"]);
+        let Ok(Command::Import { entries }) = command else {
+            panic!("Expected an EXT.IMPORT, got {command:?}");
+        };
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].0, "b");
+        assert_eq!(entries[1].1.frequency(), 7);
+        assert!(parse_args(&["EXT.IMPORT", "a", "1", "10"]).is_err());
+    }
+
+    #[test]
+    fn parse_zadd() {
+        let tokens = Token::try_from(
+            "*7\r\n$4\r\nZADD\r\n$1\r\nz\r\n$2\r\nxx\r\n$2\r\nCH\r\n$3\r\n1.5\r\n$1\r\na\r\n$4\r\n-inf\r\n",
+        )
+        .unwrap();
+        assert!(Command::try_from(tokens).is_err());
+
+        let tokens = Token::try_from(
+            "*8\r\n$4\r\nZADD\r\n$1\r\nz\r\n$2\r\nxx\r\n$2\r\nCH\r\n$3\r\n1.5\r\n$1\r\na\r\n$4\r\n-inf\r\n$1\r\nb\r\n",
+        )
+        .unwrap();
+        let command = Command::try_from(tokens).unwrap();
+        assert_eq!(
+            command,
+            Command::ZAdd {
+                key: "z".to_string(),
+                options: ZAddOptions {
+                    only_existing: true,
+                    changed: true,
+                    ..ZAddOptions::default()
+                },
+                members: vec![
+                    (Score(1.5), "a".to_string()),
+                    (Score(f64::NEG_INFINITY), "b".to_string()),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_zadd_incompatible_flags() {
+        let tokens = Token::try_from(
+            "*6\r\n$4\r\nZADD\r\n$1\r\nz\r\n$2\r\nNX\r\n$2\r\nGT\r\n$1\r\n1\r\n$1\r\na\r\n",
+        )
+        .unwrap();
+        assert!(Command::try_from(tokens).is_err());
+
+        let tokens = Token::try_from(
+            "*7\r\n$4\r\nZADD\r\n$1\r\nz\r\n$4\r\nINCR\r\n$1\r\n1\r\n$1\r\na\r\n$1\r\n2\r\n$1\r\nb\r\n",
+        )
+        .unwrap();
+        assert!(Command::try_from(tokens).is_err());
+    }
+
+    fn parse_args(args: &[&str]) -> Result<Command, super::ParseError> {
+        Command::try_from(Token::Array {
+            tokens: args
+                .iter()
+                .map(|arg| Token::from(arg.to_string()))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn parse_geo() {
+        assert_eq!(
+            parse_args(&["GEOADD", "g", "xx", "CH", "13.5", "-38", "a"]).unwrap(),
+            Command::GeoAdd {
+                key: "g".to_string(),
+                options: ZAddOptions {
+                    only_existing: true,
+                    changed: true,
+                    ..ZAddOptions::default()
+                },
+                locations: vec![(
+                    Coordinates {
+                        longitude: 13.5,
+                        latitude: -38.0,
+                    },
+                    "a".to_string()
+                )],
+            }
+        );
+        assert!(parse_args(&["GEOADD", "g", "GT", "1", "2", "a"]).is_err());
+        assert!(parse_args(&["GEOADD", "g", "NX", "XX", "1", "2", "a"]).is_err());
+        assert!(parse_args(&["GEOADD", "g", "1", "2"]).is_err());
+        assert!(parse_args(&["GEOADD", "g", "nan", "2", "a"]).is_err());
+
+        assert_eq!(
+            parse_args(&["GEODIST", "g", "a", "b", "KM"]).unwrap(),
+            Command::GeoDist {
+                key: "g".to_string(),
+                from: "a".to_string(),
+                to: "b".to_string(),
+                unit: DistanceUnit::Kilometers,
+            }
+        );
+        assert!(parse_args(&["GEODIST", "g", "a", "b", "parsecs"]).is_err());
+
+        let search = GeoSearch {
+            origin: GeoOrigin::Member("a".to_string()),
+            shape: GeoShape::Box {
+                width: 1.0,
+                height: 2.5,
+            },
+            unit: DistanceUnit::Miles,
+            sort: Some(true),
+            count: Some(3),
+            any: true,
+        };
+        assert_eq!(
+            parse_args(&[
+                "GEOSEARCH",
+                "g",
+                "BYBOX",
+                "1",
+                "2.5",
+                "mi",
+                "desc",
+                "FROMMEMBER",
+                "a",
+                "COUNT",
+                "3",
+                "ANY",
+                "WITHHASH",
+            ])
+            .unwrap(),
+            Command::GeoSearch {
+                key: "g".to_string(),
+                search: search.clone(),
+                with_dist: false,
+                with_hash: true,
+                with_coord: false,
+            }
+        );
+        assert_eq!(
+            parse_args(&[
+                "GEOSEARCHSTORE",
+                "d",
+                "g",
+                "FROMMEMBER",
+                "a",
+                "BYBOX",
+                "1",
+                "2.5",
+                "MI",
+                "DESC",
+                "COUNT",
+                "3",
+                "ANY",
+                "STOREDIST",
+            ])
+            .unwrap(),
+            Command::GeoSearchStore {
+                destination: "d".to_string(),
+                source: "g".to_string(),
+                search,
+                store_dist: true,
+            }
+        );
+        let by_radius = [
+            "GEOSEARCH",
+            "g",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "BYRADIUS",
+            "200",
+            "km",
+        ];
+        assert!(parse_args(&by_radius).is_ok());
+        assert!(parse_args(&by_radius[..5]).is_err());
+        assert!(parse_args(&[&by_radius[..], &["FROMMEMBER", "a"]].concat()).is_err());
+        assert!(parse_args(&[&by_radius[..], &["COUNT", "0"]].concat()).is_err());
+        assert!(parse_args(&[&by_radius[..], &["STOREDIST"]].concat()).is_err());
+        assert!(parse_args(&["GEOSEARCH", "g", "FROMMEMBER", "a", "BYRADIUS", "-1", "m"]).is_err());
+        assert_eq!(
+            parse_args(&["GEOPOS", "g"]).unwrap(),
+            Command::GeoPos {
+                key: "g".to_string(),
+                members: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_zincrby() {
+        assert_eq!(
+            parse_args(&["ZINCRBY", "z", "-2.5", "a"]).unwrap(),
+            Command::ZIncrBy {
+                key: "z".to_string(),
+                increment: Score(-2.5),
+                member: "a".to_string(),
+            }
+        );
+        assert!(parse_args(&["ZINCRBY", "z", "nan", "a"]).is_err());
+    }
+
+    #[test]
+    fn parse_bzpop() {
+        assert_eq!(
+            parse_args(&["BZPOPMAX", "a", "b", "0.5"]).unwrap(),
+            Command::BZPop {
+                keys: vec!["a".to_string(), "b".to_string()],
+                max: true,
+                timeout: Duration::from_millis(500),
+            }
+        );
+        assert!(parse_args(&["BZPOPMIN", "a", "-1"]).is_err());
+        assert!(parse_args(&["BZPOPMIN", "0"]).is_err());
+    }
+
+    #[test]
+    fn parse_zrandmember() {
+        assert_eq!(
+            parse_args(&["ZRANDMEMBER", "z", "-5", "WITHSCORES"]).unwrap(),
+            Command::ZRandMember {
+                key: "z".to_string(),
+                count: Some(-5),
+                with_scores: true,
+            }
+        );
+        assert_eq!(
+            parse_args(&["ZRANDMEMBER", "z"]).unwrap(),
+            Command::ZRandMember {
+                key: "z".to_string(),
+                count: None,
+                with_scores: false,
+            }
+        );
+        assert!(parse_args(&["ZRANDMEMBER", "z", "WITHSCORES"]).is_err());
+
+        let min = i64::MIN.to_string();
+        assert!(parse_args(&["ZRANDMEMBER", "z", &min]).is_err());
+        assert!(parse_args(&["SRANDMEMBER", "s", &min]).is_err());
+        let count = (-i64::MAX).to_string();
+        assert!(parse_args(&["SRANDMEMBER", "s", &count]).is_ok());
+    }
+
+    #[test]
+    fn parse_zremrange() {
+        assert_eq!(
+            parse_args(&["ZREMRANGEBYRANK", "z", "0", "-3"]).unwrap(),
+            Command::ZRemRange {
+                key: "z".to_string(),
+                range: ZRange::Index { start: 0, stop: -3 },
+            }
+        );
+        assert_eq!(
+            parse_args(&["ZREMRANGEBYSCORE", "z", "-inf", "(100"]).unwrap(),
+            Command::ZRemRange {
+                key: "z".to_string(),
+                range: ZRange::Score {
+                    min: ScoreBound::Inclusive(Score(f64::NEG_INFINITY)),
+                    max: ScoreBound::Exclusive(Score(100.0)),
+                },
+            }
+        );
+        assert!(parse_args(&["ZREMRANGEBYRANK", "z", "0", "x"]).is_err());
+    }
+
+    #[test]
+    fn parse_zunionstore() {
+        let command = parse_args(&[
+            "ZUNIONSTORE",
+            "dst",
+            "2",
+            "a",
+            "b",
+            "WEIGHTS",
+            "2",
+            "0.5",
+            "AGGREGATE",
+            "MAX",
+        ]);
+        assert_eq!(
+            command.unwrap(),
+            Command::ZSetOperationStore {
+                operation: SetOperation::Union,
+                destination: "dst".to_string(),
+                keys: vec!["a".to_string(), "b".to_string()],
+                weights: vec![Score(2.0), Score(0.5)],
+                aggregate: Aggregate::Max,
+            }
+        );
+        assert!(parse_args(&["ZINTERSTORE", "dst", "2", "a", "b", "WEIGHTS", "1"]).is_err());
+        assert!(parse_args(&["ZDIFFSTORE", "dst", "1", "a", "AGGREGATE", "MIN"]).is_err());
+        assert!(parse_args(&["ZUNIONSTORE", "dst", "3", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn parse_xadd() {
+        let command = parse_args(&[
+            "XADD",
+            "s",
+            "NOMKSTREAM",
+            "MAXLEN",
+            "~",
+            "100",
+            "LIMIT",
+            "10",
+            "5-*",
+            "a",
+            "1",
+        ]);
+        assert_eq!(
+            command.unwrap(),
+            Command::XAdd {
+                key: "s".to_string(),
+                options: XAddOptions {
+                    no_make_stream: true,
+                    trim: Some(Trim {
+                        strategy: TrimStrategy::MaxLen(100),
+                        approximate: true,
+                        limit: 10,
+                    }),
+                },
+                id: IdSpec::AutoSequence(5),
+                fields: vec![("a".to_string(), "1".to_string())],
+            }
+        );
+        let command = parse_args(&["XADD", "s", "MINID", "7-1", "*", "a", "1", "b", "2"]);
+        assert!(matches!(
+            command.unwrap(),
+            Command::XAdd {
+                options: XAddOptions {
+                    no_make_stream: false,
+                    trim: Some(Trim {
+                        strategy: TrimStrategy::MinId(StreamId { ms: 7, seq: 1 }),
+                        approximate: false,
+                        ..
+                    }),
+                },
+                id: IdSpec::Auto,
+                ..
+            }
+        ));
+        assert!(
+            parse_args(&["XADD", "s", "MAXLEN", "=", "1", "LIMIT", "5", "*", "a", "1"]).is_err()
+        );
+        assert!(parse_args(&["XADD", "s", "1-x", "a", "1"]).is_err());
+        assert!(parse_args(&["XADD", "s", "*", "a"]).is_err());
+        assert!(parse_args(&["XADD", "s", "*"]).is_err());
+    }
+
+    #[test]
+    fn parse_xdel_and_xtrim() {
+        assert_eq!(
+            parse_args(&["XDEL", "s", "1-1", "2"]).unwrap(),
+            Command::XDel {
+                key: "s".to_string(),
+                ids: vec![StreamId { ms: 1, seq: 1 }, StreamId { ms: 2, seq: 0 }],
+            }
+        );
+        assert!(parse_args(&["XDEL", "s"]).is_err());
+        assert!(parse_args(&["XDEL", "s", "1-x"]).is_err());
+        assert_eq!(
+            parse_args(&["XTRIM", "s", "MAXLEN", "~", "10", "LIMIT", "5"]).unwrap(),
+            Command::XTrim {
+                key: "s".to_string(),
+                trim: Trim {
+                    strategy: TrimStrategy::MaxLen(10),
+                    approximate: true,
+                    limit: 5,
+                },
+            }
+        );
+        assert_eq!(
+            parse_args(&["XTRIM", "s", "minid", "3-0"]).unwrap(),
+            Command::XTrim {
+                key: "s".to_string(),
+                trim: Trim {
+                    strategy: TrimStrategy::MinId(StreamId { ms: 3, seq: 0 }),
+                    approximate: false,
+                    limit: 0,
+                },
+            }
+        );
+        assert!(parse_args(&["XTRIM", "s", "MAXLEN", "=", "10", "LIMIT", "5"]).is_err());
+        assert!(parse_args(&["XTRIM", "s", "COUNT", "10"]).is_err());
+        assert_eq!(
+            parse_args(&[
+                "XSETID",
+                "s",
+                "5-1",
+                "ENTRIESADDED",
+                "7",
+                "MAXDELETEDID",
+                "4-0"
+            ])
+            .unwrap(),
+            Command::XSetId {
+                key: "s".to_string(),
+                last_id: StreamId { ms: 5, seq: 1 },
+                entries_added: Some(7),
+                max_deleted_id: Some(StreamId { ms: 4, seq: 0 }),
+            }
+        );
+        assert!(parse_args(&["XSETID", "s", "5-1", "ENTRIESADDED"]).is_err());
+        assert!(parse_args(&["XSETID", "s", "5-1", "LIMIT", "1"]).is_err());
+    }
+
+    #[test]
+    fn parse_consumer_groups() {
+        assert_eq!(
+            parse_args(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]).unwrap(),
+            Command::XGroupCreate {
+                key: "s".to_string(),
+                group: "g".to_string(),
+                from: ReadFrom::New,
+                make_stream: true,
+            }
+        );
+        assert!(parse_args(&["XGROUP", "CREATE", "s", "g", "0", "NOPE"]).is_err());
+        assert!(parse_args(&["XGROUP", "CREATE", "s", "g", ">"]).is_err());
+        assert!(parse_args(&["XGROUP", "NOPE", "s", "g"]).is_err());
+
+        let command = parse_args(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "alice",
+            "COUNT",
+            "1",
+            "NOACK",
+            "STREAMS",
+            "a",
+            "b",
+            ">",
+            "0",
+        ]);
+        assert_eq!(
+            command.unwrap(),
+            Command::XReadGroup {
+                group: "g".to_string(),
+                consumer: "alice".to_string(),
+                streams: vec![
+                    ("a".to_string(), ReadGroupFrom::New),
+                    ("b".to_string(), ReadGroupFrom::Pending(StreamId::MIN)),
+                ],
+                count: Some(1),
+                block: None,
+                no_ack: true,
+            }
+        );
+        assert!(parse_args(&["XREADGROUP", "g", "alice", "STREAMS", "a", ">"]).is_err());
+        assert!(parse_args(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "a", "$"]).is_err());
+
+        assert_eq!(
+            parse_args(&["XACK", "s", "g", "1-0", "2"]).unwrap(),
+            Command::XAck {
+                key: "s".to_string(),
+                group: "g".to_string(),
+                ids: vec![StreamId { ms: 1, seq: 0 }, StreamId { ms: 2, seq: 0 }],
+            }
+        );
+        assert!(parse_args(&["XACK", "s", "g"]).is_err());
+
+        assert_eq!(
+            parse_args(&["XINFO", "consumers", "s", "g"]).unwrap(),
+            Command::XInfoConsumers {
+                key: "s".to_string(),
+                group: "g".to_string(),
+            }
+        );
+        assert!(parse_args(&["XINFO", "GROUPS"]).is_err());
+    }
+
+    #[test]
+    fn parse_setbit_and_getbit() {
+        assert_eq!(
+            parse_args(&["SETBIT", "k", "7", "1"]).unwrap(),
+            Command::SetBit {
+                key: "k".to_string(),
+                offset: 7,
+                bit: true,
+            }
+        );
+        assert!(parse_args(&["SETBIT", "k", "7", "2"]).is_err());
+        assert!(parse_args(&["SETBIT", "k", "-1", "1"]).is_err());
+        assert!(parse_args(&["SETBIT", "k", "4294967296", "1"]).is_err());
+        assert_eq!(
+            parse_args(&["GETBIT", "k", "4294967295"]).unwrap(),
+            Command::GetBit {
+                key: "k".to_string(),
+                offset: 4_294_967_295,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_bitop() {
+        assert_eq!(
+            parse_args(&["BITOP", "xor", "dst", "a", "b"]).unwrap(),
+            Command::BitOp {
+                operation: BitOperation::Xor,
+                destination: "dst".to_string(),
+                keys: vec!["a".to_string(), "b".to_string()],
+            }
+        );
+        assert!(parse_args(&["BITOP", "NOT", "dst", "a", "b"]).is_err());
+        assert!(parse_args(&["BITOP", "NAND", "dst", "a"]).is_err());
+        assert!(parse_args(&["BITOP", "AND", "dst"]).is_err());
+    }
+
+    #[test]
+    fn parse_bit_ranges() {
+        assert_eq!(
+            parse_args(&["BITCOUNT", "k"]).unwrap(),
+            Command::BitCount {
+                key: "k".to_string(),
+                range: None,
+            }
+        );
+        assert_eq!(
+            parse_args(&["BITCOUNT", "k", "1", "-2", "bit"]).unwrap(),
+            Command::BitCount {
+                key: "k".to_string(),
+                range: Some(BitRange {
+                    start: 1,
+                    end: Some(-2),
+                    unit: BitUnit::Bit,
+                }),
+            }
+        );
+        assert!(parse_args(&["BITCOUNT", "k", "1"]).is_err());
+        assert!(parse_args(&["BITCOUNT", "k", "1", "2", "nibble"]).is_err());
+
+        assert_eq!(
+            parse_args(&["BITPOS", "k", "0", "2"]).unwrap(),
+            Command::BitPos {
+                key: "k".to_string(),
+                bit: false,
+                range: Some(BitRange {
+                    start: 2,
+                    end: None,
+                    unit: BitUnit::Byte,
+                }),
+            }
+        );
+        assert!(parse_args(&["BITPOS", "k", "2"]).is_err());
+    }
+
+    #[test]
+    fn parse_hyperloglog() {
+        assert_eq!(
+            parse_args(&["PFADD", "h"]).unwrap(),
+            Command::PfAdd {
+                key: "h".to_string(),
+                elements: vec![],
+            }
+        );
+        assert_eq!(
+            parse_args(&["PFCOUNT", "a", "b"]).unwrap(),
+            Command::PfCount {
+                keys: vec!["a".to_string(), "b".to_string()],
+            }
+        );
+        assert!(parse_args(&["PFCOUNT"]).is_err());
+        assert_eq!(
+            parse_args(&["PFMERGE", "d", "a"]).unwrap(),
+            Command::PfMerge {
+                destination: "d".to_string(),
+                sources: vec!["a".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_bitfield() {
+        let u8 = BitFieldType {
+            signed: false,
+            bits: 8,
+        };
+        let i64 = BitFieldType {
+            signed: true,
+            bits: 64,
+        };
+        assert_eq!(
+            parse_args(&[
+                "BITFIELD", "k", "GET", "u8", "#2", "OVERFLOW", "sat", "SET", "i64", "3", "-7",
+                "incrby", "U8", "100", "1",
+            ])
+            .unwrap(),
+            Command::BitField {
+                key: "k".to_string(),
+                operations: vec![
+                    BitFieldOp::Get {
+                        kind: u8,
+                        offset: 16
+                    },
+                    BitFieldOp::Overflow(BitFieldOverflow::Sat),
+                    BitFieldOp::Set {
+                        kind: i64,
+                        offset: 3,
+                        value: -7
+                    },
+                    BitFieldOp::IncrBy {
+                        kind: u8,
+                        offset: 100,
+                        increment: 1
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            parse_args(&["BITFIELD", "k"]).unwrap(),
+            Command::BitField {
+                key: "k".to_string(),
+                operations: vec![],
+            }
+        );
+        assert!(parse_args(&["BITFIELD", "k", "GET", "u64", "0"]).is_err());
+        assert!(parse_args(&["BITFIELD", "k", "GET", "i0", "0"]).is_err());
+        assert!(parse_args(&["BITFIELD", "k", "GET", "u8"]).is_err());
+        assert!(parse_args(&["BITFIELD", "k", "SET", "u8", "4294967295", "1"]).is_err());
+        assert!(parse_args(&["BITFIELD", "k", "OVERFLOW", "panic"]).is_err());
+
+        assert!(parse_args(&["BITFIELD_RO", "k", "GET", "i5", "0"]).is_ok());
+        assert!(parse_args(&["BITFIELD_RO", "k", "SET", "i5", "0", "1"]).is_err());
+    }
+
+    #[test]
+    fn parse_pending_and_claims() {
+        let id = |ms| StreamId { ms, seq: 0 };
+        assert_eq!(
+            parse_args(&["XPENDING", "s", "g"]).unwrap(),
+            Command::XPending {
+                key: "s".to_string(),
+                group: "g".to_string(),
+                range: None,
+            }
+        );
+        assert_eq!(
+            parse_args(&["XPENDING", "s", "g", "IDLE", "10", "-", "(5", "3", "alice"]).unwrap(),
+            Command::XPending {
+                key: "s".to_string(),
+                group: "g".to_string(),
+                range: Some(PendingRange {
+                    min_idle: 10,
+                    start: "-".parse().unwrap(),
+                    end: "(5".parse().unwrap(),
+                    count: 3,
+                    consumer: Some("alice".to_string()),
+                }),
+            }
+        );
+        assert!(parse_args(&["XPENDING", "s", "g", "-", "+"]).is_err());
+
+        assert_eq!(
+            parse_args(&[
+                "XCLAIM",
+                "s",
+                "g",
+                "bob",
+                "100",
+                "1",
+                "2-0",
+                "FORCE",
+                "RETRYCOUNT",
+                "5"
+            ])
+            .unwrap(),
+            Command::XClaim {
+                key: "s".to_string(),
+                group: "g".to_string(),
+                consumer: "bob".to_string(),
+                min_idle: 100,
+                ids: vec![id(1), id(2)],
+                options: XClaimOptions {
+                    retry_count: Some(5),
+                    force: true,
+                    ..XClaimOptions::default()
+                },
+            }
+        );
+        assert!(parse_args(&["XCLAIM", "s", "g", "bob", "100", "JUSTID"]).is_err());
+        assert!(parse_args(&["XCLAIM", "s", "g", "bob", "100", "1", "IDLE"]).is_err());
+
+        assert_eq!(
+            parse_args(&["XAUTOCLAIM", "s", "g", "bob", "0", "0-0", "JUSTID"]).unwrap(),
+            Command::XAutoClaim {
+                key: "s".to_string(),
+                group: "g".to_string(),
+                consumer: "bob".to_string(),
+                min_idle: 0,
+                start: "0-0".parse().unwrap(),
+                options: AutoClaimOptions {
+                    count: 100,
+                    just_id: true,
+                },
+            }
+        );
+        assert!(parse_args(&["XAUTOCLAIM", "s", "g", "bob", "0", "-", "COUNT", "0"]).is_err());
+    }
+
+    #[test]
+    fn parse_xread() {
+        let command = parse_args(&["XREAD", "COUNT", "2", "STREAMS", "a", "b", "0-1", "$"]);
+        assert_eq!(
+            command.unwrap(),
+            Command::XRead {
+                streams: vec![
+                    ("a".to_string(), ReadFrom::After(StreamId { ms: 0, seq: 1 })),
+                    ("b".to_string(), ReadFrom::New),
+                ],
+                count: Some(2),
+                block: None,
+            }
+        );
+        let command = parse_args(&["XREAD", "BLOCK", "0", "STREAMS", "a", "$"]);
+        assert_eq!(
+            command.unwrap(),
+            Command::XRead {
+                streams: vec![("a".to_string(), ReadFrom::New)],
+                count: None,
+                block: Some(Duration::ZERO),
+            }
+        );
+        assert!(parse_args(&["XREAD", "BLOCK", "-1", "STREAMS", "a", "$"]).is_err());
+        assert!(parse_args(&["XREAD", "STREAMS", "a", "b", "0-1"]).is_err());
+        assert!(parse_args(&["XREAD", "STREAMS"]).is_err());
+        assert!(parse_args(&["XREAD", "a", "0"]).is_err());
+    }
+
+    #[test]
+    fn parse_xrevrange() {
+        let command = parse_args(&["XREVRANGE", "s", "+", "(5", "COUNT", "3"]).unwrap();
+        assert_eq!(
+            command,
+            Command::XRange {
+                key: "s".to_string(),
+                start: "(5".parse().unwrap(),
+                end: "+".parse().unwrap(),
+                count: Some(3),
+                reverse: true,
+            }
+        );
+        assert!(parse_args(&["XRANGE", "s", "-", "+", "LIMIT", "3"]).is_err());
+        assert!(parse_args(&["XRANGE", "s", "(-", "+"]).is_err());
+    }
+
+    #[test]
+    fn parse_config_set() {
+        assert_eq!(
+            parse_args(&[
+                "CONFIG",
+                "SET",
+                "Notify-Keyspace-Exclude",
+                "cache:*",
+                "dir",
+                "/tmp"
+            ])
+            .unwrap(),
+            Command::ConfigSet {
+                parameters: vec![
+                    ("notify-keyspace-exclude".to_string(), "cache:*".to_string()),
+                    ("dir".to_string(), "/tmp".to_string()),
+                ],
+            }
+        );
+        assert!(parse_args(&["CONFIG", "SET", "dir"]).is_err());
+        assert!(parse_args(&["CONFIG", "SET"]).is_err());
+    }
+
+    #[test]
+    fn parse_debug() {
+        assert_eq!(
+            parse_args(&["DEBUG", "SLEEP", "0.25"]).unwrap(),
+            Command::DebugSleep {
+                duration: Duration::from_millis(250)
+            }
+        );
+        assert_eq!(
+            parse_args(&["debug", "object", "melon"]).unwrap(),
+            Command::DebugObject {
+                key: "melon".into()
+            }
+        );
+        assert_eq!(
+            parse_args(&["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).unwrap(),
+            Command::DebugSetActiveExpire { enabled: false }
+        );
+        assert_eq!(
+            parse_args(&["DEBUG", "set-active-expire", "1"]).unwrap(),
+            Command::DebugSetActiveExpire { enabled: true }
+        );
+        assert_eq!(parse_args(&["DEBUG", "JMAP"]).unwrap(), Command::DebugJmap);
+        assert!(parse_args(&["DEBUG", "SLEEP", "-1"]).is_err());
+        assert!(parse_args(&["DEBUG", "SLEEP", "forever"]).is_err());
+        assert!(parse_args(&["DEBUG", "RELOAD"]).is_err());
+    }
+
+    #[test]
+    fn parse_memory() {
+        assert_eq!(
+            parse_args(&["MEMORY", "USAGE", "melon"]).unwrap(),
+            Command::MemoryUsage {
+                key: "melon".into(),
+                samples: Some(5)
+            }
+        );
+        assert_eq!(
+            parse_args(&["memory", "usage", "melon", "samples", "0"]).unwrap(),
+            Command::MemoryUsage {
+                key: "melon".into(),
+                samples: None
+            }
+        );
+        assert_eq!(
+            parse_args(&["MEMORY", "STATS"]).unwrap(),
+            Command::MemoryStats
+        );
+        assert!(parse_args(&["MEMORY", "USAGE", "melon", "SAMPLES"]).is_err());
+        assert!(parse_args(&["MEMORY", "USAGE", "melon", "SAMPLES", "-1"]).is_err());
+        assert!(parse_args(&["MEMORY", "USAGE"]).is_err());
+    }
+
+    #[test]
+    fn parse_slowlog() {
+        assert_eq!(
+            parse_args(&["SLOWLOG", "GET"]).unwrap(),
+            Command::SlowlogGet { count: Some(10) }
+        );
+        assert_eq!(
+            parse_args(&["slowlog", "get", "3"]).unwrap(),
+            Command::SlowlogGet { count: Some(3) }
+        );
+        assert_eq!(
+            parse_args(&["SLOWLOG", "GET", "-1"]).unwrap(),
+            Command::SlowlogGet { count: None }
+        );
+        assert_eq!(
+            parse_args(&["SLOWLOG", "LEN"]).unwrap(),
+            Command::SlowlogLen
+        );
+        assert_eq!(
+            parse_args(&["SLOWLOG", "RESET"]).unwrap(),
+            Command::SlowlogReset
+        );
+        assert_eq!(
+            parse_args(&["SLOWLOG", "HELP"]).unwrap(),
+            Command::SlowlogHelp
+        );
+        assert!(parse_args(&["SLOWLOG", "GET", "-2"]).is_err());
+        assert!(parse_args(&["SLOWLOG"]).is_err());
+    }
+
+    #[test]
+    fn parse_client() {
+        assert_eq!(
+            parse_args(&["CLIENT", "LIST"]).unwrap(),
+            Command::ClientList { filter: None }
+        );
+        assert_eq!(
+            parse_args(&["client", "list", "type", "PubSub"]).unwrap(),
+            Command::ClientList {
+                filter: Some(Filter::Kind(Kind::PubSub))
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "LIST", "ID", "1", "3"]).unwrap(),
+            Command::ClientList {
+                filter: Some(Filter::Ids(vec![1, 3]))
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "INFO"]).unwrap(),
+            Command::ClientInfo
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "SETNAME", "worker"]).unwrap(),
+            Command::ClientSetName {
+                name: "worker".to_string()
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "GETNAME"]).unwrap(),
+            Command::ClientGetName
+        );
+        assert_eq!(parse_args(&["client", "id"]).unwrap(), Command::ClientId);
+        assert!(parse_args(&["CLIENT", "SETNAME"]).is_err());
+        assert_eq!(
+            parse_args(&["CLIENT", "KILL", "127.0.0.1:50000"]).unwrap(),
+            Command::ClientKillAddr {
+                addr: "127.0.0.1:50000".to_string()
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "KILL", "TYPE", "normal", "ID", "3", "SKIPME", "no"]).unwrap(),
+            Command::ClientKill {
+                filters: vec![Filter::Kind(Kind::Normal), Filter::Ids(vec![3])],
+                skip_me: false,
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "KILL", "LADDR", "127.0.0.1:6379"]).unwrap(),
+            Command::ClientKill {
+                filters: vec![Filter::LocalAddr("127.0.0.1:6379".to_string())],
+                skip_me: true,
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "PAUSE", "1500"]).unwrap(),
+            Command::ClientPause {
+                timeout: Duration::from_millis(1500),
+                pause: Pause::All,
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "PAUSE", "10", "write"]).unwrap(),
+            Command::ClientPause {
+                timeout: Duration::from_millis(10),
+                pause: Pause::Write,
+            }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "UNPAUSE"]).unwrap(),
+            Command::ClientUnpause
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "NO-EVICT", "on"]).unwrap(),
+            Command::ClientNoEvict { enabled: true }
+        );
+        assert_eq!(
+            parse_args(&["CLIENT", "NO-TOUCH", "OFF"]).unwrap(),
+            Command::ClientNoTouch { enabled: false }
+        );
+        assert!(parse_args(&["CLIENT", "NO-TOUCH", "yes"]).is_err());
+        assert!(parse_args(&["CLIENT", "PAUSE", "-1"]).is_err());
+        assert!(parse_args(&["CLIENT", "PAUSE", "10", "READS"]).is_err());
+        assert!(parse_args(&["CLIENT", "KILL"]).is_err());
+        assert!(parse_args(&["CLIENT", "KILL", "ID", "3", "SKIPME"]).is_err());
+        assert!(parse_args(&["CLIENT", "KILL", "SKIPME", "maybe"]).is_err());
+        assert!(parse_args(&["CLIENT", "LIST", "TYPE", "nope"]).is_err());
+        assert!(parse_args(&["CLIENT", "LIST", "ID"]).is_err());
+        assert!(parse_args(&["CLIENT", "LIST", "ID", "one"]).is_err());
+        assert!(parse_args(&["CLIENT"]).is_err());
+    }
+
+    #[test]
+    fn parse_command() {
+        assert_eq!(
+            parse_args(&["COMMAND"]).unwrap(),
+            Command::CommandInfo { names: vec![] }
+        );
+        assert_eq!(
+            parse_args(&["command", "INFO", "get", "set"]).unwrap(),
+            Command::CommandInfo {
+                names: vec!["get".to_string(), "set".to_string()]
+            }
+        );
+        assert_eq!(
+            parse_args(&["COMMAND", "COUNT"]).unwrap(),
+            Command::CommandCount
+        );
+        assert_eq!(
+            parse_args(&["COMMAND", "DOCS", "get"]).unwrap(),
+            Command::CommandDocs {
+                names: vec!["get".to_string()]
+            }
+        );
+        assert_eq!(
+            parse_args(&["COMMAND", "GETKEYS", "GET", "melon"]).unwrap(),
+            Command::CommandGetKeys {
+                args: vec!["GET".to_string(), "melon".to_string()]
+            }
+        );
+        assert!(parse_args(&["COMMAND", "GETKEYS"]).is_err());
+        assert!(parse_args(&["COMMAND", "HELPME"]).is_err());
+    }
+
+    #[test]
+    fn parse_zrange() {
+        let command = parse_args(&["ZRANGE", "z", "0", "-1", "WITHSCORES"]).unwrap();
+        assert_eq!(
+            command,
+            Command::ZRange {
+                key: "z".to_string(),
+                range: ZRange::Index { start: 0, stop: -1 },
+                reverse: false,
+                limit: None,
+                with_scores: true,
+            }
+        );
+
+        let command = parse_args(&[
+            "ZRANGE", "z", "+inf", "(1.5", "BYSCORE", "REV", "LIMIT", "1", "2",
+        ]);
+        assert_eq!(
+            command.unwrap(),
+            Command::ZRange {
+                key: "z".to_string(),
+                range: ZRange::Score {
+                    min: ScoreBound::Exclusive(Score(1.5)),
+                    max: ScoreBound::Inclusive(Score(f64::INFINITY)),
+                },
+                reverse: true,
+                limit: Some((1, 2)),
+                with_scores: false,
+            }
+        );
+
+        let command = parse_args(&["ZRANGE", "z", "[a", "+", "BYLEX"]).unwrap();
+        assert_eq!(
+            command,
+            Command::ZRange {
+                key: "z".to_string(),
+                range: ZRange::Lex {
+                    min: LexBound::Inclusive("a".to_string()),
+                    max: LexBound::Max,
+                },
+                reverse: false,
+                limit: None,
+                with_scores: false,
+            }
+        );
+
+        assert!(parse_args(&["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"]).is_err());
+        assert!(parse_args(&["ZRANGE", "z", "-", "+", "BYLEX", "WITHSCORES"]).is_err());
+        assert!(parse_args(&["ZRANGE", "z", "a", "+", "BYLEX"]).is_err());
     }
 }
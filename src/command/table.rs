@@ -0,0 +1,481 @@
+//! # The command table: what `COMMAND` tells clients about every supported command.
+//!
+//! Client libraries look commands up in here on connect, to learn which arguments of
+//! a command are keys and whether it writes. Every [`Spec`] follows the legacy format
+//! of Redis, which all clients still understand:
+//!
+//! - The arity counts the name of the command too, and is negative if the command
+//!   takes at least that many arguments, rather than exactly that many.
+//! - The keys are the arguments from the first key to the last one, `step` apart.
+//!   A negative last key counts from the end, `-1` being the last argument. Commands
+//!   that tell their keys by other arguments, like the `numkeys` of `EVAL`, are
+//!   flagged `movablekeys` instead, with all three positions `0`.
+//!
+//! Container commands like `CONFIG` have no keys or flags of their own, but list their
+//! subcommands, whose names are joined to theirs with a `|`, like `config|get`.
+
+use crate::resp::Token;
+
+/// The metadata of a command, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+    pub name: &'static str,
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    pub first_key: i64,
+    pub last_key: i64,
+    pub step: i64,
+    /// The group of the command in the Redis docs, like `string` or `sorted-set`.
+    pub group: &'static str,
+    pub summary: &'static str,
+    pub subcommands: &'static [Spec],
+}
+
+impl Spec {
+    /// The entry of the command in the reply to `COMMAND INFO`: the legacy fields, followed by
+    /// the ACL categories, the tips and the key specifications, all empty, and the subcommands.
+    pub fn info(&self) -> Token {
+        let flags = self
+            .flags
+            .iter()
+            .map(|flag| Token::SimpleString {
+                data: (*flag).to_string(),
+            })
+            .collect();
+        let empty = || Token::Array { tokens: vec![] };
+        Token::Array {
+            tokens: vec![
+                Token::from(self.name.to_string()),
+                Token::from(self.arity),
+                Token::Array { tokens: flags },
+                Token::from(self.first_key),
+                Token::from(self.last_key),
+                Token::from(self.step),
+                empty(),
+                empty(),
+                empty(),
+                Token::Array {
+                    tokens: self.subcommands.iter().map(Self::info).collect(),
+                },
+            ],
+        }
+    }
+
+    /// The documentation of the command in the reply to `COMMAND DOCS`.
+    pub fn docs(&self) -> Token {
+        let mut pairs = vec![
+            (
+                Token::from("summary".to_string()),
+                Token::from(self.summary.to_string()),
+            ),
+            (
+                Token::from("group".to_string()),
+                Token::from(self.group.to_string()),
+            ),
+        ];
+        if !self.subcommands.is_empty() {
+            let subcommands = self
+                .subcommands
+                .iter()
+                .map(|subcommand| (Token::from(subcommand.name.to_string()), subcommand.docs()))
+                .collect();
+            pairs.push((
+                Token::from("subcommands".to_string()),
+                Token::Map { pairs: subcommands },
+            ));
+        }
+        Token::Map { pairs }
+    }
+}
+
+/// Look up the [`Spec`] of the command `name`, in any case.
+pub fn lookup(name: &str) -> Option<&'static Spec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+/// The full name of the command in `args`, like `config|get` for a subcommand,
+/// as `CLIENT LIST` tells the last command of a client.
+pub fn full_name(args: &[Vec<u8>]) -> String {
+    let name = args.first().map_or_else(String::new, |name| {
+        String::from_utf8_lossy(name).to_ascii_lowercase()
+    });
+    match (lookup(&name), args.get(1)) {
+        (Some(spec), Some(subcommand)) if !spec.subcommands.is_empty() => {
+            let subcommand = String::from_utf8_lossy(subcommand).to_ascii_lowercase();
+            format!("{name}|{subcommand}")
+        }
+        _ => name,
+    }
+}
+
+/// Possible errors that can arise when telling the keys of a command line apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum KeysError {
+    #[error("ERR Invalid command specified")]
+    UnknownCommand,
+    #[error("ERR Invalid number of arguments specified for command")]
+    WrongArity,
+    #[error("ERR The command has no key arguments")]
+    NoKeys,
+}
+
+/// Pick the keys out of the command line `args`, the name of the command included.
+///
+/// The keys of most commands are at the positions in their [`Spec`], while those
+/// of `movablekeys` commands are told by the other arguments, as in `EVAL`.
+pub fn keys(args: &[String]) -> Result<Vec<String>, KeysError> {
+    let name = args.first().ok_or(KeysError::UnknownCommand)?;
+    let mut spec = lookup(name).ok_or(KeysError::UnknownCommand)?;
+    if !spec.subcommands.is_empty() {
+        let subcommand = args.get(1).ok_or(KeysError::WrongArity)?;
+        spec = spec
+            .subcommands
+            .iter()
+            .find(|spec| spec.name[name.len() + 1..].eq_ignore_ascii_case(subcommand))
+            .ok_or(KeysError::UnknownCommand)?;
+    }
+    let arity = usize::try_from(spec.arity.unsigned_abs()).unwrap_or(usize::MAX);
+    if (spec.arity > 0 && args.len() != arity) || args.len() < arity {
+        return Err(KeysError::WrongArity);
+    }
+    let positions = if spec.flags.contains(&"movablekeys") {
+        movable_keys(spec.name, args).ok_or(KeysError::WrongArity)?
+    } else {
+        fixed_keys(spec, args.len())
+    };
+    if positions.is_empty() {
+        return Err(KeysError::NoKeys);
+    }
+    Ok(positions
+        .into_iter()
+        .map(|position| args[position].clone())
+        .collect())
+}
+
+/// The positions of the keys of a command of `len` arguments, as in its `spec`.
+fn fixed_keys(spec: &Spec, len: usize) -> Vec<usize> {
+    let position = |index: i64| {
+        let index = if index < 0 { len as i64 + index } else { index };
+        usize::try_from(index).unwrap_or(0)
+    };
+    if spec.first_key == 0 {
+        return vec![];
+    }
+    let (first, last) = (position(spec.first_key), position(spec.last_key));
+    let step = usize::try_from(spec.step).unwrap_or(1).max(1);
+    (first..=last.min(len - 1)).step_by(step).collect()
+}
+
+/// The positions of the keys of the `movablekeys` command `name`, told by its `args`,
+/// or [`None`] if they tell of more keys than there are arguments.
+fn movable_keys(name: &str, args: &[String]) -> Option<Vec<usize>> {
+    // The keys follow their count, which is at `numkeys`, and the keys before it.
+    let counted = |numkeys: usize| {
+        let count: usize = args.get(numkeys)?.parse().ok()?;
+        let keys = numkeys + 1..numkeys + 1 + count;
+        (keys.end <= args.len()).then_some(keys)
+    };
+    match name {
+        "eval" | "evalsha" | "fcall" | "fcall_ro" => counted(2).map(Iterator::collect),
+        "sintercard" => counted(1).map(Iterator::collect),
+        "zdiffstore" | "zinterstore" | "zunionstore" => {
+            counted(2).map(|keys| std::iter::once(1).chain(keys).collect())
+        }
+        // The streams are followed by as many IDs, one for each of them.
+        "xread" | "xreadgroup" => {
+            let streams = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case("streams"))?;
+            let count = args.len() - streams - 1;
+            (count > 0 && count % 2 == 0).then(|| (streams + 1..=streams + count / 2).collect())
+        }
+        _ => Some(vec![]),
+    }
+}
+
+const fn command(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    (first_key, last_key, step): (i64, i64, i64),
+    group: &'static str,
+    summary: &'static str,
+) -> Spec {
+    Spec {
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        group,
+        summary,
+        subcommands: &[],
+    }
+}
+
+const fn container(
+    name: &'static str,
+    group: &'static str,
+    summary: &'static str,
+    subcommands: &'static [Spec],
+) -> Spec {
+    Spec {
+        subcommands,
+        ..command(name, -2, &[], NO_KEYS, group, summary)
+    }
+}
+
+const NO_KEYS: (i64, i64, i64) = (0, 0, 0);
+const KEY: (i64, i64, i64) = (1, 1, 1);
+const ALL_KEYS: (i64, i64, i64) = (1, -1, 1);
+
+/// Every command that the server supports, in alphabetical order.
+pub const COMMANDS: &[Spec] = &[
+    command("bgrewriteaof", 1, &["admin", "noscript"], NO_KEYS, "server", "Asynchronously rewrites the append-only file to disk."),
+    command("bgsave", -1, &["admin", "noscript"], NO_KEYS, "server", "Asynchronously saves the database(s) to disk."),
+    command("bitcount", -2, &["readonly"], KEY, "bitmap", "Counts the number of set bits (population counting) in a string."),
+    command("bitfield", -2, &["write", "denyoom"], KEY, "bitmap", "Performs arbitrary bitfield integer operations on strings."),
+    command("bitfield_ro", -2, &["readonly", "fast"], KEY, "bitmap", "Performs arbitrary read-only bitfield integer operations on strings."),
+    command("bitop", -4, &["write", "denyoom"], (2, -1, 1), "bitmap", "Performs bitwise operations on multiple strings, and stores the result."),
+    command("bitpos", -3, &["readonly"], KEY, "bitmap", "Finds the first set (1) or clear (0) bit in a string."),
+    command("bzpopmax", -3, &["write", "blocking", "fast"], (1, -2, 1), "sorted-set", "Removes and returns the member with the highest score from one or more sorted sets. Blocks until a member is available otherwise."),
+    command("bzpopmin", -3, &["write", "blocking", "fast"], (1, -2, 1), "sorted-set", "Removes and returns the member with the lowest score from one or more sorted sets. Blocks until a member is available otherwise."),
+    container("client", "connection", "A container for client connection commands.", &[
+        command("client|getname", 2, &["noscript", "loading", "stale"], NO_KEYS, "connection", "Returns the name of the connection."),
+        command("client|id", 2, &["noscript", "loading", "stale"], NO_KEYS, "connection", "Returns the unique client ID of the connection."),
+        command("client|info", 2, &["noscript", "loading", "stale"], NO_KEYS, "connection", "Returns information about the connection."),
+        command("client|kill", -3, &["admin", "noscript", "loading", "stale"], NO_KEYS, "connection", "Terminates open connections."),
+        command("client|list", -2, &["admin", "noscript", "loading", "stale"], NO_KEYS, "connection", "Lists open connections."),
+        command("client|no-evict", 3, &["admin", "noscript", "loading", "stale"], NO_KEYS, "connection", "Sets the client eviction mode of the connection."),
+        command("client|no-touch", 3, &["noscript", "loading", "stale"], NO_KEYS, "connection", "Controls whether commands sent by the client affect the LRU/LFU of accessed keys."),
+        command("client|pause", -3, &["admin", "noscript", "loading", "stale"], NO_KEYS, "connection", "Suspends commands processing."),
+        command("client|setname", 3, &["noscript", "loading", "stale"], NO_KEYS, "connection", "Sets the connection name."),
+        command("client|unpause", 2, &["admin", "noscript", "loading", "stale"], NO_KEYS, "connection", "Resumes processing commands from paused clients."),
+    ]),
+    container("command", "server", "A container for command introspection commands.", &[
+        command("command|count", 2, &["loading", "stale"], NO_KEYS, "server", "Returns a count of commands."),
+        command("command|docs", -2, &["loading", "stale"], NO_KEYS, "server", "Returns documentary information about one, multiple or all commands."),
+        command("command|getkeys", -3, &["loading", "stale"], NO_KEYS, "server", "Extracts the key names from an arbitrary command."),
+        command("command|info", -2, &["loading", "stale"], NO_KEYS, "server", "Returns information about one, multiple or all commands."),
+    ]),
+    container("config", "server", "A container for server configuration commands.", &[
+        command("config|get", 3, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server", "Returns the effective value of a configuration parameter."),
+        command("config|set", -4, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server", "Sets configuration parameters in-flight."),
+    ]),
+    command("debug", -2, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server", "A container for debugging commands."),
+    command("del", -2, &["write"], ALL_KEYS, "generic", "Deletes one or more keys."),
+    command("discard", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "transactions", "Discards a transaction."),
+    command("echo", 2, &["fast"], NO_KEYS, "connection", "Returns the given string."),
+    command("eval", -3, &["noscript", "stale", "movablekeys"], NO_KEYS, "scripting", "Executes a server-side Lua script."),
+    command("evalsha", -3, &["noscript", "stale", "movablekeys"], NO_KEYS, "scripting", "Executes a server-side Lua script by SHA1 digest."),
+    command("exec", 1, &["noscript", "loading", "stale"], NO_KEYS, "transactions", "Executes all commands in a transaction."),
+    command("ext.cas", -4, &["write", "denyoom"], KEY, "string", "Sets the string value of a key, but only if it currently equals the expected one."),
+    command("ext.compress", 2, &["fast"], NO_KEYS, "connection", "Compresses the replies to the connection that are at least as long as given."),
+    command("ext.import", -5, &["write", "denyoom"], (1, -4, 4), "generic", "Imports keys along with their access metadata."),
+    command("ext.sequence", 3, &["write", "denyoom"], KEY, "string", "Reserves a range of values of a sequence."),
+    command("ext.whenexpires", 2, &["readonly", "fast"], KEY, "generic", "Returns when a key expires, or when and how it did."),
+    command("fcall", -3, &["noscript", "stale", "movablekeys"], NO_KEYS, "scripting", "Invokes a function."),
+    command("fcall_ro", -3, &["noscript", "stale", "movablekeys"], NO_KEYS, "scripting", "Invokes a read-only function."),
+    container("function", "scripting", "A container for function commands.", &[
+        command("function|dump", 2, &["noscript"], NO_KEYS, "scripting", "Dumps all libraries into a serialized binary payload."),
+        command("function|flush", -2, &["write", "noscript"], NO_KEYS, "scripting", "Deletes all libraries and functions."),
+        command("function|list", -2, &["noscript"], NO_KEYS, "scripting", "Returns information about all libraries."),
+        command("function|load", -3, &["write", "denyoom", "noscript"], NO_KEYS, "scripting", "Creates a library."),
+        command("function|restore", -3, &["write", "denyoom", "noscript"], NO_KEYS, "scripting", "Restores all libraries from a payload."),
+    ]),
+    command("geoadd", -5, &["write", "denyoom"], KEY, "geo", "Adds one or more members to a geospatial index."),
+    command("geodist", -4, &["readonly"], KEY, "geo", "Returns the distance between two members of a geospatial index."),
+    command("geopos", -2, &["readonly"], KEY, "geo", "Returns the longitude and latitude of members from a geospatial index."),
+    command("geosearch", -7, &["readonly"], KEY, "geo", "Queries a geospatial index for members inside an area of a box or a circle."),
+    command("geosearchstore", -8, &["write", "denyoom"], (1, 2, 1), "geo", "Queries a geospatial index for members inside an area of a box or a circle, optionally stores the result."),
+    command("get", 2, &["readonly", "fast"], KEY, "string", "Returns the string value of a key."),
+    command("getbit", 3, &["readonly", "fast"], KEY, "bitmap", "Returns a bit value by offset."),
+    command("hello", -1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "connection", "Handshakes with the Redis server."),
+    command("info", -1, &["loading", "stale"], NO_KEYS, "server", "Returns information and statistics about the server."),
+    command("keys", 2, &["readonly"], NO_KEYS, "generic", "Returns all key names that match a pattern."),
+    command("lastsave", 1, &["loading", "stale", "fast"], NO_KEYS, "server", "Returns the Unix timestamp of the last successful save to disk."),
+    container("memory", "server", "A container for memory diagnostics commands.", &[
+        command("memory|stats", 2, &["readonly"], NO_KEYS, "server", "Returns details about memory usage."),
+        command("memory|usage", -3, &["readonly"], (2, 2, 1), "server", "Estimates the memory usage of a key."),
+    ]),
+    command("multi", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "transactions", "Starts a transaction."),
+    container("object", "generic", "A container for object introspection commands.", &[
+        command("object|freq", 3, &["readonly"], (2, 2, 1), "generic", "Returns the logarithmic access frequency counter of a Redis object."),
+        command("object|idletime", 3, &["readonly"], (2, 2, 1), "generic", "Returns the time since the last access to a Redis object."),
+    ]),
+    command("pfadd", -2, &["write", "denyoom", "fast"], KEY, "hyperloglog", "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist."),
+    command("pfcount", -2, &["readonly"], ALL_KEYS, "hyperloglog", "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s)."),
+    command("pfmerge", -2, &["write", "denyoom"], ALL_KEYS, "hyperloglog", "Merges one or more HyperLogLog values into a single key."),
+    command("ping", -1, &["fast"], NO_KEYS, "connection", "Returns the server's liveliness response."),
+    command("psubscribe", -2, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub", "Listens for messages published to channels that match one or more patterns."),
+    command("psync", -3, &["admin", "noscript"], NO_KEYS, "server", "An internal command used in replication."),
+    command("pttl", 2, &["readonly", "fast"], KEY, "generic", "Returns the expiration time in milliseconds of a key."),
+    command("publish", 3, &["pubsub", "loading", "stale", "fast"], NO_KEYS, "pubsub", "Posts a message to a channel."),
+    container("pubsub", "pubsub", "A container for Pub/Sub commands.", &[
+        command("pubsub|channels", -2, &["pubsub", "loading", "stale"], NO_KEYS, "pubsub", "Returns the active channels."),
+        command("pubsub|numpat", 2, &["pubsub", "loading", "stale"], NO_KEYS, "pubsub", "Returns a count of unique pattern subscriptions."),
+        command("pubsub|numsub", -2, &["pubsub", "loading", "stale"], NO_KEYS, "pubsub", "Returns a count of subscribers to channels."),
+    ]),
+    command("punsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub", "Stops listening to messages published to channels that match one or more patterns."),
+    command("quit", -1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "connection", "Closes the connection."),
+    command("replconf", -1, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server", "An internal command for configuring the replication stream."),
+    command("replicaof", 3, &["admin", "noscript", "stale"], NO_KEYS, "server", "Configures a server as replica of another, or promotes it to a master."),
+    command("reset", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "connection", "Resets the connection."),
+    command("role", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "server", "Returns the replication role."),
+    command("sadd", -3, &["write", "denyoom", "fast"], KEY, "set", "Adds one or more members to a set. Creates the key if it doesn't exist."),
+    command("save", 1, &["admin", "noscript"], NO_KEYS, "server", "Synchronously saves the database(s) to disk."),
+    command("scan", -2, &["readonly"], NO_KEYS, "generic", "Iterates over the key names in the database."),
+    command("scard", 2, &["readonly", "fast"], KEY, "set", "Returns the number of members in a set."),
+    container("script", "scripting", "A container for Lua scripts management commands.", &[
+        command("script|exists", -3, &["noscript"], NO_KEYS, "scripting", "Determines whether server-side Lua scripts exist in the script cache."),
+        command("script|flush", -2, &["noscript"], NO_KEYS, "scripting", "Removes all server-side Lua scripts from the script cache."),
+        command("script|load", 3, &["noscript", "stale"], NO_KEYS, "scripting", "Loads a server-side Lua script to the script cache."),
+    ]),
+    command("sdiff", -2, &["readonly"], ALL_KEYS, "set", "Returns the difference of multiple sets."),
+    command("sdiffstore", -3, &["write", "denyoom"], ALL_KEYS, "set", "Stores the difference of multiple sets in a key."),
+    command("set", -3, &["write", "denyoom"], KEY, "string", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."),
+    command("setbit", 4, &["write", "denyoom"], KEY, "bitmap", "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist."),
+    command("shutdown", -1, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server", "Synchronously saves the database(s) to disk and shuts down the Redis server."),
+    command("sinter", -2, &["readonly"], ALL_KEYS, "set", "Returns the intersect of multiple sets."),
+    command("sintercard", -3, &["readonly", "movablekeys"], NO_KEYS, "set", "Returns the number of members of the intersect of multiple sets."),
+    command("sinterstore", -3, &["write", "denyoom"], ALL_KEYS, "set", "Stores the intersect of multiple sets in a key."),
+    command("sismember", 3, &["readonly", "fast"], KEY, "set", "Determines whether a member belongs to a set."),
+    command("slaveof", 3, &["admin", "noscript", "stale"], NO_KEYS, "server", "Sets a Redis server as a replica of another, or promotes it to being a master."),
+    container("slowlog", "server", "A container for slow log commands.", &[
+        command("slowlog|get", -2, &["admin", "loading", "stale"], NO_KEYS, "server", "Returns the slow log's entries."),
+        command("slowlog|help", 2, &["loading", "stale"], NO_KEYS, "server", "Show helpful text about the different subcommands."),
+        command("slowlog|len", 2, &["admin", "loading", "stale"], NO_KEYS, "server", "Returns the number of entries in the slow log."),
+        command("slowlog|reset", 2, &["admin", "loading", "stale"], NO_KEYS, "server", "Clears all entries from the slow log."),
+    ]),
+    command("smembers", 2, &["readonly"], KEY, "set", "Returns all members of a set."),
+    command("smismember", -3, &["readonly", "fast"], KEY, "set", "Determines whether multiple members belong to a set."),
+    command("smove", 4, &["write", "fast"], (1, 2, 1), "set", "Moves a member from one set to another."),
+    container("snapshot", "generic", "A container for commands that read the RDB snapshots on disk.", &[
+        command("snapshot|get", 4, &["readonly"], (3, 3, 1), "generic", "Returns the string value that a key held at a given time."),
+    ]),
+    command("spop", -2, &["write", "fast"], KEY, "set", "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped."),
+    command("spublish", 3, &["pubsub", "loading", "stale", "fast"], KEY, "pubsub", "Post a message to a shard channel."),
+    command("srandmember", -2, &["readonly"], KEY, "set", "Get one or multiple random members from a set."),
+    command("srem", -3, &["write", "fast"], KEY, "set", "Removes one or more members from a set. Deletes the set if the last member was removed."),
+    command("ssubscribe", -2, &["pubsub", "noscript", "loading", "stale"], ALL_KEYS, "pubsub", "Listens for messages published to shard channels."),
+    command("subscribe", -2, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub", "Listens for messages published to channels."),
+    command("sunion", -2, &["readonly"], ALL_KEYS, "set", "Returns the union of multiple sets."),
+    command("sunionstore", -3, &["write", "denyoom"], ALL_KEYS, "set", "Stores the union of multiple sets in a key."),
+    command("sunsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], ALL_KEYS, "pubsub", "Stops listening to messages posted to shard channels."),
+    command("type", 2, &["readonly", "fast"], KEY, "generic", "Determines the type of value stored at a key."),
+    command("unlink", -2, &["write", "fast"], ALL_KEYS, "generic", "Asynchronously deletes one or more keys."),
+    command("unsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub", "Stops listening to messages posted to channels."),
+    command("unwatch", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "transactions", "Forgets about watched keys of a transaction."),
+    command("wait", 3, &["noscript"], NO_KEYS, "generic", "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed."),
+    command("watch", -2, &["noscript", "loading", "stale", "fast"], ALL_KEYS, "transactions", "Monitors changes to keys to determine the execution of a transaction."),
+    command("xack", -4, &["write", "fast"], KEY, "stream", "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream."),
+    command("xadd", -5, &["write", "denyoom", "fast"], KEY, "stream", "Appends a new message to a stream. Creates the key if it doesn't exist."),
+    command("xautoclaim", -6, &["write", "fast"], KEY, "stream", "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as consumer group member."),
+    command("xclaim", -6, &["write", "fast"], KEY, "stream", "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member."),
+    command("xdel", -3, &["write", "fast"], KEY, "stream", "Returns the number of messages after removing them from a stream."),
+    container("xgroup", "stream", "A container for consumer groups commands.", &[
+        command("xgroup|create", -5, &["write", "denyoom"], (2, 2, 1), "stream", "Creates a consumer group."),
+        command("xgroup|createconsumer", 5, &["write", "denyoom"], (2, 2, 1), "stream", "Creates a consumer in a consumer group."),
+        command("xgroup|delconsumer", 5, &["write"], (2, 2, 1), "stream", "Deletes a consumer from a consumer group."),
+        command("xgroup|destroy", 4, &["write"], (2, 2, 1), "stream", "Destroys a consumer group."),
+        command("xgroup|setid", 5, &["write"], (2, 2, 1), "stream", "Sets the last-delivered ID of a consumer group."),
+    ]),
+    container("xinfo", "stream", "A container for stream introspection commands.", &[
+        command("xinfo|consumers", 4, &["readonly"], (2, 2, 1), "stream", "Returns a list of the consumers in a consumer group."),
+        command("xinfo|groups", 3, &["readonly"], (2, 2, 1), "stream", "Returns a list of the consumer groups of a stream."),
+        command("xinfo|stream", 3, &["readonly"], (2, 2, 1), "stream", "Returns information about a stream."),
+    ]),
+    command("xlen", 2, &["readonly", "fast"], KEY, "stream", "Return the number of messages in a stream."),
+    command("xpending", -3, &["readonly"], KEY, "stream", "Returns the information and entries from a stream consumer group's pending entries list."),
+    command("xrange", -4, &["readonly"], KEY, "stream", "Returns the messages from a stream within a range of IDs."),
+    command("xread", -4, &["readonly", "blocking", "movablekeys"], NO_KEYS, "stream", "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise."),
+    command("xreadgroup", -7, &["write", "blocking", "movablekeys"], NO_KEYS, "stream", "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise."),
+    command("xrevrange", -4, &["readonly"], KEY, "stream", "Returns the messages from a stream within a range of IDs in reverse order."),
+    command("xsetid", -3, &["write", "denyoom", "fast"], KEY, "stream", "An internal command for replicating stream values."),
+    command("xtrim", -4, &["write"], KEY, "stream", "Deletes messages from the beginning of a stream."),
+    command("zadd", -4, &["write", "denyoom", "fast"], KEY, "sorted-set", "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist."),
+    command("zcard", 2, &["readonly", "fast"], KEY, "sorted-set", "Returns the number of members in a sorted set."),
+    command("zdiffstore", -4, &["write", "denyoom", "movablekeys"], KEY, "sorted-set", "Stores the difference of multiple sorted sets in a key."),
+    command("zincrby", 4, &["write", "denyoom", "fast"], KEY, "sorted-set", "Increments the score of a member in a sorted set."),
+    command("zinterstore", -4, &["write", "denyoom", "movablekeys"], KEY, "sorted-set", "Stores the intersect of multiple sorted sets in a key."),
+    command("zpopmax", -2, &["write", "fast"], KEY, "sorted-set", "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped."),
+    command("zpopmin", -2, &["write", "fast"], KEY, "sorted-set", "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped."),
+    command("zrandmember", -2, &["readonly"], KEY, "sorted-set", "Returns one or more random members from a sorted set."),
+    command("zrange", -4, &["readonly"], KEY, "sorted-set", "Returns members in a sorted set within a range of indexes."),
+    command("zrank", -3, &["readonly", "fast"], KEY, "sorted-set", "Returns the index of a member in a sorted set ordered by ascending scores."),
+    command("zremrangebyrank", 4, &["write"], KEY, "sorted-set", "Removes members in a sorted set within a range of indexes. Deletes the sorted set if all members were removed."),
+    command("zremrangebyscore", 4, &["write"], KEY, "sorted-set", "Removes members in a sorted set within a range of scores. Deletes the sorted set if all members were removed."),
+    command("zrevrank", -3, &["readonly", "fast"], KEY, "sorted-set", "Returns the index of a member in a sorted set ordered by descending scores."),
+    command("zscore", 3, &["readonly", "fast"], KEY, "sorted-set", "Returns the score of a member in a sorted set."),
+    command("zunionstore", -4, &["write", "denyoom", "movablekeys"], KEY, "sorted-set", "Stores the union of multiple sorted sets in a key."),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{full_name, keys, lookup, KeysError, COMMANDS};
+    use crate::resp::Protocol;
+
+    #[test]
+    fn sorted_and_consistent() {
+        assert!(COMMANDS.windows(2).all(|pair| pair[0].name < pair[1].name));
+        for spec in COMMANDS {
+            assert_ne!(spec.arity, 0, "{}", spec.name);
+            let movable = spec.flags.contains(&"movablekeys");
+            let keyless = (spec.first_key, spec.last_key, spec.step) == (0, 0, 0);
+            assert!(!movable || keyless || spec.first_key == 1, "{}", spec.name);
+            for subcommand in spec.subcommands {
+                assert!(subcommand.name.starts_with(&format!("{}|", spec.name)));
+            }
+        }
+    }
+
+    #[test]
+    fn lookups_and_replies() {
+        let get = lookup("GET").unwrap();
+        assert_eq!(
+            get.info().encode(Protocol::Resp2),
+            "*10\r\n$3\r\nget\r\n:2\r\n*2\r\n+readonly\r\n+fast\r\n:1\r\n:1\r\n:1\r\n*0\r\n*0\r\n*0\r\n*0\r\n"
+        );
+        assert_eq!(
+            get.docs().encode(Protocol::Resp3),
+            "%2\r\n$7\r\nsummary\r\n$34\r\nReturns the string value of a key.\r\n$5\r\ngroup\r\n$6\r\nstring\r\n"
+        );
+        let config = lookup("config").unwrap();
+        assert_eq!(config.subcommands.len(), 2);
+        assert!(config.docs().encode(Protocol::Resp3).contains("config|get"));
+        assert!(lookup("config|get").is_none());
+        assert!(lookup("nope").is_none());
+        let name = |line: &str| full_name(&line.split(' ').map(Vec::from).collect::<Vec<_>>());
+        assert_eq!(name("CLIENT List"), "client|list");
+        assert_eq!(name("GET melon"), "get");
+        assert_eq!(name("NOPE melon"), "nope");
+    }
+
+    #[test]
+    fn keys_of_command_lines() {
+        let keys = |line: &str| keys(&line.split(' ').map(String::from).collect::<Vec<_>>());
+        let strings = |line: &str| Ok(line.split(' ').map(String::from).collect());
+        assert_eq!(keys("GET melon"), strings("melon"));
+        assert_eq!(keys("del a b c"), strings("a b c"));
+        assert_eq!(keys("BZPOPMIN a b 0"), strings("a b"));
+        assert_eq!(keys("ext.import a v 0 0 b v 0 0"), strings("a b"));
+        assert_eq!(keys("OBJECT FREQ melon"), strings("melon"));
+        assert_eq!(keys("EVAL s 2 a b c"), strings("a b"));
+        assert_eq!(
+            keys("ZUNIONSTORE out 2 a b WEIGHTS 1 2"),
+            strings("out a b")
+        );
+        assert_eq!(keys("XREAD COUNT 2 STREAMS a b 0 0"), strings("a b"));
+        assert_eq!(keys("EVAL s 0"), Err(KeysError::NoKeys));
+        assert_eq!(keys("PING"), Err(KeysError::NoKeys));
+        assert_eq!(keys("EVAL s 3 a b"), Err(KeysError::WrongArity));
+        assert_eq!(keys("XREAD STREAMS a b 0"), Err(KeysError::WrongArity));
+        assert_eq!(keys("GET a b"), Err(KeysError::WrongArity));
+        assert_eq!(keys("OBJECT"), Err(KeysError::WrongArity));
+        assert_eq!(keys("OBJECT NOPE melon"), Err(KeysError::UnknownCommand));
+        assert_eq!(keys("NOPE melon"), Err(KeysError::UnknownCommand));
+    }
+}
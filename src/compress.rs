@@ -0,0 +1,192 @@
+//! # Compression of large replies, negotiated per connection with `EXT.COMPRESS`.
+//!
+//! Bandwidth-constrained clients can opt in with `EXT.COMPRESS <min-bytes>`, after which
+//! the replies to a request, once they encode to at least `min-bytes`, are sent as one
+//! compressed frame instead (`EXT.COMPRESS OFF` goes back to plain replies):
+//!
+//! ```text
+//! &<original-length>:<compressed-length>\r\n<compressed bytes>\r\n
+//! ```
+//!
+//! The `&` type byte is not used by RESP2 nor RESP3, so the frames cannot be mistaken
+//! for anything else. The compressed bytes are a single LZ4 block, which every LZ4
+//! library can decompress given the original length. Replies that do not get any
+//! smaller are sent as they are, even above the threshold.
+//!
+//! The compressor is a plain greedy one with a small hash table of recent positions,
+//! which is nowhere near as thorough as `zstd` but costs next to nothing per reply.
+
+/// Matches are at least this long, as shorter ones cost more than their literals.
+const MIN_MATCH: usize = 4;
+
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+
+/// Matches must start at least this far from the end of a block.
+const MATCH_FIND_LIMIT: usize = 12;
+
+/// Matches are encoded as 16-bit offsets back into the input.
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+/// The number of bits of the hash of 4 bytes, which indexes the table of recent positions.
+const HASH_LOG: u32 = 12;
+
+/// The type byte of a compressed frame.
+const FRAME_START: char = '&';
+
+/// Compress `input` into a single LZ4 block.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 16);
+    // The most recent position of each hash, plus one so that zero means none.
+    let mut recent = vec![0; 1 << HASH_LOG];
+    let (mut anchor, mut position) = (0, 0);
+    let match_end_limit = input.len().saturating_sub(LAST_LITERALS);
+    while position + MATCH_FIND_LIMIT < input.len() {
+        let sequence = read_u32(input, position);
+        let slot = hash(sequence);
+        let candidate = std::mem::replace(&mut recent[slot], position + 1);
+        let found = candidate
+            .checked_sub(1)
+            .filter(|&start| position - start <= MAX_OFFSET)
+            .filter(|&start| read_u32(input, start) == sequence);
+        let Some(start) = found else {
+            position += 1;
+            continue;
+        };
+        let mut len = MIN_MATCH;
+        while position + len < match_end_limit && input[start + len] == input[position + len] {
+            len += 1;
+        }
+        write_sequence(
+            &mut output,
+            &input[anchor..position],
+            Some((position - start, len)),
+        );
+        position += len;
+        anchor = position;
+    }
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+/// Wrap `replies`, encoded to `original_len` bytes, into a compressed frame if that makes them smaller.
+pub fn frame(replies: &[&[u8]], original_len: usize) -> Option<Vec<u8>> {
+    let compressed = compress(&replies.concat());
+    let mut frame = format!("{FRAME_START}{original_len}:{}\r\n", compressed.len()).into_bytes();
+    frame.extend_from_slice(&compressed);
+    frame.extend_from_slice(b"\r\n");
+    (frame.len() < original_len).then_some(frame)
+}
+
+fn read_u32(input: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes([
+        input[position],
+        input[position + 1],
+        input[position + 2],
+        input[position + 3],
+    ])
+}
+
+/// Knuth's multiplicative hash, keeping the top [`HASH_LOG`] bits.
+const fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+/// Write a sequence of `literals` followed by a match at `(offset, len)`, which only the last one lacks.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let match_len = found.map_or(0, |(_, len)| len - MIN_MATCH);
+    output.push((literals.len().min(15) << 4 | match_len.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_len(output, literals.len() - 15);
+    }
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = found {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_len(output, match_len - 15);
+        }
+    }
+}
+
+/// Write the part of a length that does not fit into its 4 bits of the token.
+fn write_len(output: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        output.push(255);
+        len -= 255;
+    }
+    output.push(len as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, frame};
+    use crate::random::Rng;
+
+    /// A straightforward LZ4 block decompressor, to check the compressor against.
+    fn decompress(input: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        let mut position = 0;
+        let read_len = |position: &mut usize, nibble: u8| {
+            let mut len = usize::from(nibble);
+            if nibble == 15 {
+                loop {
+                    let byte = input[*position];
+                    *position += 1;
+                    len += usize::from(byte);
+                    if byte != 255 {
+                        break;
+                    }
+                }
+            }
+            len
+        };
+        loop {
+            let token = input[position];
+            position += 1;
+            let literals = read_len(&mut position, token >> 4);
+            output.extend_from_slice(&input[position..position + literals]);
+            position += literals;
+            if position == input.len() {
+                return output;
+            }
+            let offset = usize::from(u16::from_le_bytes([input[position], input[position + 1]]));
+            position += 2;
+            let len = read_len(&mut position, token & 15) + 4;
+            // Matches may overlap what they produce, so copy byte by byte.
+            for _ in 0..len {
+                output.push(output[output.len() - offset]);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut rng = Rng::with_seed(11);
+        let alphabets: [&[u8]; 3] = [b"a", b"abc", b"0123456789abcdef"];
+        for len in [0, 1, 12, 13, 17, 100, 1000, 70_000] {
+            for alphabet in alphabets {
+                let input: Vec<u8> = (0..len)
+                    .map(|_| alphabet[rng.below(alphabet.len())])
+                    .collect();
+                assert_eq!(decompress(&compress(&input)), input, "{len} bytes");
+            }
+            let noise: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            assert_eq!(decompress(&compress(&noise)), noise, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn frames_only_what_shrinks() {
+        let reply = "$6000\r\n".to_string() + &"value ".repeat(1000) + "\r\n";
+        let replies = [reply.as_bytes()];
+        let framed = frame(&replies, reply.len()).unwrap();
+        assert!(framed.len() < reply.len() / 10);
+        let header_end = framed.iter().position(|&byte| byte == b'\n').unwrap() + 1;
+        let header = std::str::from_utf8(&framed[..header_end]).unwrap();
+        let compressed = &framed[header_end..framed.len() - 2];
+        assert_eq!(header, format!("&{}:{}\r\n", reply.len(), compressed.len()));
+        assert_eq!(decompress(compressed), reply.as_bytes());
+
+        assert_eq!(frame(&[b"+OK\r\n"], 5), None);
+    }
+}
@@ -1,16 +1,213 @@
 //! # Redis server configuration.
 //!
 //! Things like the directory and filename of the [`Database`].
+//!
+//! Besides command-line flags, the server accepts the path to a `redis.conf` file
+//! (just like `redis-server /etc/redis/redis.conf` does), so that the configuration
+//! of an existing Redis installation can be reused as is. Only a subset of the
+//! directives is understood, everything else is skipped with a warning:
+//!
+//! | Directive                  | Maps to                               |
+//! |----------------------------|---------------------------------------|
+//! | `port`                     | [`Config::port`]                      |
+//! | `dir`                      | [`Config::dir`]                       |
+//! | `dbfilename`               | [`Config::dbfilename`]                |
+//! | `save`                     | [`Config::save`]                      |
+//! | `rdbcompression`           | [`Config::rdbcompression`]            |
+//! | `appendonly`               | [`Config::appendonly`]                |
+//! | `appendfilename`           | [`Config::appendfilename`]            |
+//! | `appendfsync`              | [`Config::appendfsync`]               |
+//! | `aof-use-rdb-preamble`     | [`Config::aof_use_rdb_preamble`]      |
+//! | `replication-port`         | [`Config::replication_port`]          |
+//! | `replicaof`                | [`Config::replicaof`]                 |
+//! | `replica-serve-stale-data` | [`Config::replica_serve_stale_data`]  |
+//! | `min-replicas-to-write`    | [`Config::min_replicas_to_write`]     |
+//! | `min-replicas-max-lag`     | [`Config::min_replicas_max_lag`]      |
+//! | `snapshot-dir`             | [`Config::snapshot_dir`]              |
+//! | `notify-keyspace-events`   | [`Config::notify_keyspace_events`]    |
+//! | `notify-keyspace-include`  | [`Config::notify_keyspace_include`]   |
+//! | `notify-keyspace-exclude`  | [`Config::notify_keyspace_exclude`]   |
+//! | `script-memory-limit`      | [`Config::script_memory_limit`]       |
+//! | `script-instruction-limit` | [`Config::script_instruction_limit`]  |
+//! | `slowlog-log-slower-than`  | [`Config::slowlog_log_slower_than`]   |
+//! | `slowlog-max-len`          | [`Config::slowlog_max_len`]           |
+//! | `enable-debug-chaos`       | [`Config::enable_debug_chaos`]        |
+//! | `loglevel`                 | [`Config::loglevel`]                  |
+//!
+//! Flags given on the command line always take precedence over the file.
+//!
+//! To cut down on the flags needed for common scenarios, a [`Profile`] presets groups
+//! of settings at once. The presets are written as `redis.conf` directives, and have
+//! the lowest precedence of all, below both the config file and the flags:
+//!
+//! | Profile   | Presets                                            | Meant for                                        |
+//! |-----------|----------------------------------------------------|--------------------------------------------------|
+//! | `dev`     | `loglevel debug`                                   | Local development, with everything logged        |
+//! | `bench`   | `loglevel warning`                                 | Benchmarks, where logging would skew the results |
+//! | `durable` | `loglevel notice`, `save "3600 1 300 100 60 10000"` | Production, where no data may be lost            |
+//!
+//! [`Database`]: crate::database::Database
 
-use std::path::PathBuf;
+use crate::aof::Fsync;
+use crate::notify::Events;
+use crate::persistence::SavePoints;
+use crate::replication::ReplicaOf;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fs, io};
 use structopt::StructOpt;
+use tracing::level_filters::LevelFilter;
 
+const DEFAULT_PORT: &str = "6379";
 const DEFAULT_DIR: &str = ".";
 const DEFAULT_FILE: &str = "db.rdb";
+const DEFAULT_AOF_FILE: &str = "appendonly.aof";
+const DEFAULT_LOGLEVEL: &str = "debug";
+const DEFAULT_SCRIPT_MEMORY_LIMIT: &str = "268435456";
+const DEFAULT_SCRIPT_INSTRUCTION_LIMIT: &str = "100000000";
+const DEFAULT_MIN_REPLICAS_MAX_LAG: &str = "10";
+const DEFAULT_SLOWLOG_LOG_SLOWER_THAN: &str = "10000";
+const DEFAULT_SLOWLOG_MAX_LEN: &str = "128";
+
+/// Possible errors that can arise while loading a `redis.conf` file.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not read the config file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unbalanced quotes in the config file at line {line}")]
+    UnbalancedQuotes { line: usize },
+    #[error("Wrong number of arguments for {directive:?} at line {line}")]
+    WrongArity { directive: String, line: usize },
+    #[error("Invalid value for {directive:?} at line {line}")]
+    InvalidValue { directive: String, line: usize },
+}
+
+/// Alternative modes of operation, instead of serving clients.
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+pub enum Mode {
+    /// Compare the keyspaces of a master and its replica, reporting any divergence.
+    VerifyReplica {
+        /// Address of the master, as `host:port`.
+        #[structopt(long)]
+        master: String,
+        /// Address of the replica, as `host:port`.
+        #[structopt(long)]
+        replica: String,
+        /// Largest acceptable difference between the TTLs of a key, in milliseconds.
+        #[structopt(long, default_value = "1000")]
+        ttl_tolerance: u64,
+    },
+    /// Walk an RDB file, verify its checksum, and summarize its contents by type.
+    CheckRdb {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+/// Groups of settings preset for common scenarios, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Bench,
+    Durable,
+}
+
+impl Profile {
+    /// The settings of this profile, as `redis.conf` directives.
+    const fn presets(self) -> &'static str {
+        match self {
+            Self::Dev => "loglevel debug\n",
+            Self::Bench => "loglevel warning\n",
+            Self::Durable => "loglevel notice\nsave \"3600 1 300 100 60 10000\"\n",
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.to_ascii_lowercase().as_str() {
+            "dev" => Ok(Self::Dev),
+            "bench" => Ok(Self::Bench),
+            "durable" => Ok(Self::Durable),
+            _ => Err(format!(
+                "Unknown profile {string:?}, expected dev, bench or durable"
+            )),
+        }
+    }
+}
+
+/// How much the server logs, named like the Redis `loglevel` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Self::Debug),
+            "verbose" => Ok(Self::Verbose),
+            "notice" => Ok(Self::Notice),
+            "warning" => Ok(Self::Warning),
+            _ => Err(format!(
+                "Unknown log level {string:?}, expected debug, verbose, notice or warning"
+            )),
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Debug => "debug",
+            Self::Verbose => "verbose",
+            Self::Notice => "notice",
+            Self::Warning => "warning",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Debug => Self::TRACE,
+            LogLevel::Verbose => Self::DEBUG,
+            LogLevel::Notice => Self::INFO,
+            LogLevel::Warning => Self::WARN,
+        }
+    }
+}
 
 /// Redis server configuration.
 #[derive(Debug, Clone, StructOpt)]
 pub struct Config {
+    /// Run a tool instead of the server.
+    #[structopt(subcommand)]
+    pub(crate) mode: Option<Mode>,
+    /// Path to a `redis.conf` file to read additional configuration from.
+    #[structopt(parse(from_os_str))]
+    pub(crate) config_file: Option<PathBuf>,
+    /// Preset groups of settings for a common scenario: `dev`, `bench` or `durable`.
+    #[structopt(long)]
+    pub(crate) profile: Option<Profile>,
+    /// Check the integrity of the persistence files and of the server itself, then exit.
+    #[structopt(long)]
+    pub(crate) check: bool,
+    /// How much to log: `debug`, `verbose`, `notice` or `warning`.
+    #[structopt(long, default_value = DEFAULT_LOGLEVEL)]
+    pub(crate) loglevel: LogLevel,
+    /// The port to listen for clients on.
+    #[structopt(long, default_value = DEFAULT_PORT)]
+    pub(crate) port: u16,
     // Redis uses `.rdb` files for persistence.
     // There are two config values that determine where RDB files are stored:
     //
@@ -20,4 +217,542 @@ pub struct Config {
     /// The name of the RDB file.
     #[structopt(long, default_value = DEFAULT_FILE, parse(from_os_str))]
     pub(crate) dbfilename: PathBuf,
+    /// When to save in the background, as pairs of seconds and changes
+    /// like `"3600 1 300 100"`, or never by itself if empty.
+    #[structopt(long, default_value = "", parse(try_from_str = SavePoints::parse))]
+    pub(crate) save: SavePoints,
+    /// Whether to compress the strings in the RDB file with LZF: `yes` or `no`.
+    #[structopt(long, default_value = "yes", parse(try_from_str = parse_yes_no))]
+    pub(crate) rdbcompression: bool,
+    /// Whether to log every write to the append-only file and load the dataset from it
+    /// instead of the RDB file: `yes` or `no`, see [`crate::aof`].
+    #[structopt(long, default_value = "no", parse(try_from_str = parse_yes_no))]
+    pub(crate) appendonly: bool,
+    /// The name of the append-only file, in the directory of the RDB file as of startup.
+    #[structopt(long, default_value = DEFAULT_AOF_FILE, parse(from_os_str))]
+    pub(crate) appendfilename: PathBuf,
+    /// When to flush the append-only file to the disk: `always`, `everysec` or `no`.
+    #[structopt(long, default_value = "everysec")]
+    pub(crate) appendfsync: Fsync,
+    /// Whether rewrites of the append-only file start with the dataset as an RDB file
+    /// rather than as commands: `yes` or `no`.
+    #[structopt(long, default_value = "yes", parse(try_from_str = parse_yes_no))]
+    pub(crate) aof_use_rdb_preamble: bool,
+    /// A dedicated port for replicas to connect to, in addition to the one for clients.
+    ///
+    /// Replica links get larger buffers and a replication timeout instead of the client
+    /// settings, so the kind of every connection is told by the port it came in through.
+    #[structopt(long)]
+    pub(crate) replication_port: Option<u16>,
+    /// The master to replicate, as `"<host> <port>"`, which makes this server a replica.
+    #[structopt(long, parse(try_from_str = ReplicaOf::parse))]
+    pub(crate) replicaof: Option<ReplicaOf>,
+    /// Whether a replica keeps serving its possibly stale data while the link to its master
+    /// is down: `yes` or `no`, which rejects all but a few commands with `-MASTERDOWN`.
+    #[structopt(long, default_value = "yes", parse(try_from_str = parse_yes_no))]
+    pub(crate) replica_serve_stale_data: bool,
+    /// How many good replicas a master needs to accept writes, rejecting them
+    /// with `-NOREPLICAS` otherwise, or `0` to accept writes regardless.
+    #[structopt(long, default_value = "0")]
+    pub(crate) min_replicas_to_write: usize,
+    /// How many seconds may have passed since the last acknowledgement of a replica
+    /// for it to count as good, see [`Config::min_replicas_to_write`].
+    #[structopt(long, default_value = DEFAULT_MIN_REPLICAS_MAX_LAG)]
+    pub(crate) min_replicas_max_lag: u64,
+    /// A directory of RDB snapshots to serve `SNAPSHOT GET` from (experimental).
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) snapshot_dir: Option<PathBuf>,
+    /// The keyspace notifications to send, as flags like `KEA` (none by default).
+    #[structopt(long, default_value = "", parse(try_from_str = Events::parse))]
+    pub(crate) notify_keyspace_events: Events,
+    /// Only send keyspace notifications about keys matching one of these
+    /// space-separated glob-style patterns, or about all keys if there are none.
+    #[structopt(long, default_value = "")]
+    pub(crate) notify_keyspace_include: String,
+    /// Never send keyspace notifications about keys matching one of these
+    /// space-separated glob-style patterns.
+    #[structopt(long, default_value = "")]
+    pub(crate) notify_keyspace_exclude: String,
+    /// How many bytes a Lua script may allocate in total before it is stopped.
+    #[structopt(long, default_value = DEFAULT_SCRIPT_MEMORY_LIMIT)]
+    pub(crate) script_memory_limit: usize,
+    /// How many instructions a Lua script may run before it is stopped.
+    #[structopt(long, default_value = DEFAULT_SCRIPT_INSTRUCTION_LIMIT)]
+    pub(crate) script_instruction_limit: u64,
+    /// How many microseconds a command has to take to get into the slow log,
+    /// or a negative number to keep it empty, see [`crate::slowlog`].
+    #[structopt(long, default_value = DEFAULT_SLOWLOG_LOG_SLOWER_THAN, allow_hyphen_values = true)]
+    pub(crate) slowlog_log_slower_than: i64,
+    /// How many entries the slow log keeps at most.
+    #[structopt(long, default_value = DEFAULT_SLOWLOG_MAX_LEN)]
+    pub(crate) slowlog_max_len: usize,
+    /// Whether `DEBUG CHAOS` may inject faults into commands: `yes` or `no`,
+    /// see [`crate::chaos`].
+    #[structopt(long, default_value = "no", parse(try_from_str = parse_yes_no))]
+    pub(crate) enable_debug_chaos: bool,
+}
+
+impl Config {
+    /// Build the [`Config`] from the command line, the [`Profile`] and, if given, a `redis.conf` file.
+    pub fn load() -> Result<Self, Error> {
+        let matches = Self::clap().get_matches();
+        let mut config = Self::from_clap(&matches);
+        // Directives are named like the flags, but with dashes instead of underscores.
+        let explicit = |directive: &str| matches.occurrences_of(directive.replace('-', "_")) > 0;
+        if let Some(profile) = config.profile {
+            config.apply_profile(profile, explicit)?;
+        }
+        if let Some(path) = config.config_file.clone() {
+            config.apply_file(&path, explicit)?;
+        }
+        Ok(config)
+    }
+
+    /// Apply the presets of the `profile`, skipping those for which
+    /// `explicit` tells that a command-line flag was given.
+    fn apply_profile(
+        &mut self,
+        profile: Profile,
+        explicit: impl Fn(&str) -> bool,
+    ) -> Result<(), Error> {
+        tracing::info!(?profile, "Applying profile");
+        self.apply(parse(profile.presets())?, explicit)
+    }
+
+    /// Apply the directives of the `redis.conf` file at `path`, skipping
+    /// those for which `explicit` tells that a command-line flag was given.
+    fn apply_file(&mut self, path: &Path, explicit: impl Fn(&str) -> bool) -> Result<(), Error> {
+        tracing::info!(?path, "Loading config file");
+        let contents = fs::read_to_string(path)?;
+        self.apply(parse(&contents)?, explicit)
+    }
+
+    /// Apply `directives`, skipping those for which `explicit` tells that a command-line flag was given.
+    fn apply(
+        &mut self,
+        directives: Vec<(String, Vec<String>, usize)>,
+        explicit: impl Fn(&str) -> bool,
+    ) -> Result<(), Error> {
+        // Like in Redis, `save` lines add up, but the first one replaces what was there.
+        let mut save_points = None::<SavePoints>;
+        for (directive, args, line) in directives {
+            if explicit(&directive) {
+                tracing::debug!(directive, line, "Overridden by a command-line flag");
+                continue;
+            }
+            match (directive.as_str(), args.as_slice()) {
+                ("port", [port]) => {
+                    self.port = port.parse().map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                ("dir", [dir]) => self.dir = PathBuf::from(dir),
+                ("dbfilename", [file]) => self.dbfilename = PathBuf::from(file),
+                ("save", [_, ..]) => {
+                    let points =
+                        SavePoints::parse(&args.join(" ")).map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                    save_points
+                        .get_or_insert_with(SavePoints::default)
+                        .extend(points);
+                }
+                ("rdbcompression", [flag]) => {
+                    self.rdbcompression = parse_yes_no(flag).map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                ("appendonly", [flag]) => {
+                    self.appendonly = parse_yes_no(flag).map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                ("appendfilename", [file]) => self.appendfilename = PathBuf::from(file),
+                ("appendfsync", [policy]) => {
+                    self.appendfsync = policy.parse().map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                ("aof-use-rdb-preamble", [flag]) => {
+                    self.aof_use_rdb_preamble =
+                        parse_yes_no(flag).map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("replication-port", [port]) => {
+                    self.replication_port =
+                        Some(port.parse().map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?);
+                }
+                ("replicaof", [host, port]) => {
+                    self.replicaof =
+                        Some(ReplicaOf::parse(&format!("{host} {port}")).map_err(|_| {
+                            Error::InvalidValue {
+                                directive: directive.clone(),
+                                line,
+                            }
+                        })?);
+                }
+                ("replica-serve-stale-data", [flag]) => {
+                    self.replica_serve_stale_data =
+                        parse_yes_no(flag).map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("min-replicas-to-write", [count]) => {
+                    self.min_replicas_to_write =
+                        count.parse().map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("min-replicas-max-lag", [seconds]) => {
+                    self.min_replicas_max_lag =
+                        seconds.parse().map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("snapshot-dir", [dir]) => self.snapshot_dir = Some(PathBuf::from(dir)),
+                ("notify-keyspace-events", [flags]) => {
+                    self.notify_keyspace_events =
+                        Events::parse(flags).map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("notify-keyspace-include", [patterns]) => {
+                    self.notify_keyspace_include = patterns.clone();
+                }
+                ("notify-keyspace-exclude", [patterns]) => {
+                    self.notify_keyspace_exclude = patterns.clone();
+                }
+                ("script-memory-limit", [bytes]) => {
+                    self.script_memory_limit = bytes.parse().map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                ("script-instruction-limit", [count]) => {
+                    self.script_instruction_limit =
+                        count.parse().map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("slowlog-log-slower-than", [micros]) => {
+                    self.slowlog_log_slower_than =
+                        micros.parse().map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("slowlog-max-len", [len]) => {
+                    self.slowlog_max_len = len.parse().map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                ("enable-debug-chaos", [flag]) => {
+                    self.enable_debug_chaos =
+                        parse_yes_no(flag).map_err(|_| Error::InvalidValue {
+                            directive: directive.clone(),
+                            line,
+                        })?;
+                }
+                ("loglevel", [level]) => {
+                    self.loglevel = level.parse().map_err(|_| Error::InvalidValue {
+                        directive: directive.clone(),
+                        line,
+                    })?;
+                }
+                (
+                    "port"
+                    | "dir"
+                    | "dbfilename"
+                    | "save"
+                    | "rdbcompression"
+                    | "appendonly"
+                    | "appendfilename"
+                    | "appendfsync"
+                    | "aof-use-rdb-preamble"
+                    | "replication-port"
+                    | "replicaof"
+                    | "replica-serve-stale-data"
+                    | "min-replicas-to-write"
+                    | "min-replicas-max-lag"
+                    | "snapshot-dir"
+                    | "notify-keyspace-events"
+                    | "notify-keyspace-include"
+                    | "notify-keyspace-exclude"
+                    | "script-memory-limit"
+                    | "script-instruction-limit"
+                    | "slowlog-log-slower-than"
+                    | "slowlog-max-len"
+                    | "enable-debug-chaos"
+                    | "loglevel",
+                    _,
+                ) => return Err(Error::WrongArity { directive, line }),
+                _ => tracing::warn!(directive, line, "Unsupported config directive, skipping"),
+            }
+        }
+        if let Some(points) = save_points {
+            self.save = points;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a boolean setting, which Redis spells `yes` or `no`.
+fn parse_yes_no(flag: &str) -> Result<bool, String> {
+    match flag.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("Expected yes or no, got {flag:?}")),
+    }
+}
+
+/// Split the contents of a `redis.conf` file into `(directive, arguments, line)` triples.
+///
+/// Directive names are lowercased; comments and blank lines are skipped.
+fn parse(contents: &str) -> Result<Vec<(String, Vec<String>, usize)>, Error> {
+    let mut directives = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = split_args(line)
+            .ok_or(Error::UnbalancedQuotes { line: line_number })?
+            .into_iter();
+        if let Some(directive) = words.next() {
+            directives.push((directive.to_ascii_lowercase(), words.collect(), line_number));
+        }
+    }
+    Ok(directives)
+}
+
+/// Split a line into arguments the way Redis does, honoring
+/// `"double quotes"` (with backslash escapes) and `'single quotes'`.
+///
+/// Returns [`None`] if the quotes are unbalanced.
+fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Some(args);
+        };
+        let mut arg = String::new();
+        match first {
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => arg.push('\n'),
+                        'r' => arg.push('\r'),
+                        't' => arg.push('\t'),
+                        escaped => arg.push(escaped),
+                    },
+                    c => arg.push(c),
+                }
+            },
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    c => arg.push(c),
+                }
+            },
+            c => {
+                arg.push(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, split_args, Config, Error, LogLevel, Mode, Profile};
+    use crate::aof::Fsync;
+    use std::{env, fs, path::PathBuf};
+    use structopt::StructOpt;
+
+    #[test]
+    fn split_quoted_args() {
+        assert_eq!(
+            split_args(r#"save "" 'a b' "c\"d""#).unwrap(),
+            vec!["save", "", "a b", "c\"d"]
+        );
+        assert!(split_args(r#"dir "/tmp"#).is_none());
+    }
+
+    #[test]
+    fn parse_skips_comments() {
+        let directives = parse("# comment\n\nDIR /tmp\n  port 6380\n").unwrap();
+        assert_eq!(
+            directives,
+            vec![
+                ("dir".to_string(), vec!["/tmp".to_string()], 3),
+                ("port".to_string(), vec!["6380".to_string()], 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn subcommand_or_config_file() {
+        let config = Config::from_iter(["redis", "/etc/redis/redis.conf", "--dir", "/tmp"]);
+        assert_eq!(
+            config.config_file,
+            Some(PathBuf::from("/etc/redis/redis.conf"))
+        );
+        assert_eq!(config.mode, None);
+
+        let config = Config::from_iter([
+            "redis",
+            "verify-replica",
+            "--master",
+            "127.0.0.1:6379",
+            "--replica",
+            "127.0.0.1:6380",
+        ]);
+        assert_eq!(config.config_file, None);
+        assert_eq!(
+            config.mode,
+            Some(Mode::VerifyReplica {
+                master: "127.0.0.1:6379".into(),
+                replica: "127.0.0.1:6380".into(),
+                ttl_tolerance: 1000,
+            })
+        );
+    }
+
+    #[test]
+    fn negative_flags() {
+        let config = Config::from_iter(["redis", "--slowlog-log-slower-than", "-1"]);
+        assert_eq!(config.slowlog_log_slower_than, -1);
+        assert_eq!(config.slowlog_max_len, 128);
+    }
+
+    #[test]
+    fn profiles_are_overridden_by_flags() {
+        let mut config = Config::from_iter(["redis", "--profile", "bench"]);
+        assert_eq!(config.profile, Some(Profile::Bench));
+        config.apply_profile(Profile::Bench, |_| false).unwrap();
+        assert_eq!(config.loglevel, LogLevel::Warning);
+
+        let mut config =
+            Config::from_iter(["redis", "--profile", "durable", "--loglevel", "verbose"]);
+        config
+            .apply_profile(Profile::Durable, |flag| flag == "loglevel")
+            .unwrap();
+        assert_eq!(config.loglevel, LogLevel::Verbose);
+
+        assert!("production".parse::<Profile>().is_err());
+        for profile in [Profile::Dev, Profile::Bench, Profile::Durable] {
+            let mut config = Config::from_iter(["redis"]);
+            assert!(config.apply_profile(profile, |_| false).is_ok());
+        }
+    }
+
+    #[test]
+    fn apply_file() {
+        let path = env::temp_dir().join("redis-starter-rust-apply-file.conf");
+        fs::write(
+            &path,
+            "port 6380\ndir /var/lib/redis\ndbfilename dump.rdb\nappendonly yes\nreplication-port 16379\n\
+             notify-keyspace-events Ex\nnotify-keyspace-exclude \"cache:* lock:*\"\n",
+        )
+        .unwrap();
+
+        let mut config = Config::from_iter(["redis"]);
+        config
+            .apply_file(&path, |flag| flag == "dbfilename")
+            .unwrap();
+        assert_eq!(config.port, 6380);
+        assert_eq!(config.dir, PathBuf::from("/var/lib/redis"));
+        assert_eq!(config.dbfilename, PathBuf::from("db.rdb"));
+        assert_eq!(config.replication_port, Some(16379));
+        assert!(config.appendonly);
+        assert_eq!(config.replicaof, None);
+        assert_eq!(config.notify_keyspace_events.to_string(), "xE");
+        assert_eq!(config.notify_keyspace_exclude, "cache:* lock:*");
+
+        fs::write(&path, "notify-keyspace-events KQ\n").unwrap();
+        let err = config.apply_file(&path, |_| false).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { line: 1, .. }));
+
+        fs::write(&path, "dir\n").unwrap();
+        let err = config.apply_file(&path, |_| false).unwrap_err();
+        assert!(matches!(err, Error::WrongArity { line: 1, .. }));
+
+        // The first `save` line replaces the save points of the profile, the rest add up.
+        config.apply_profile(Profile::Durable, |_| false).unwrap();
+        assert_eq!(config.save.to_string(), "3600 1 300 100 60 10000");
+        fs::write(&path, "save 900 1\nsave \"300 10\"\n").unwrap();
+        config.apply_file(&path, |_| false).unwrap();
+        assert_eq!(config.save.to_string(), "900 1 300 10");
+        fs::write(&path, "save \"\"\n").unwrap();
+        config.apply_file(&path, |_| false).unwrap();
+        assert!(config.save.is_empty());
+        fs::write(&path, "rdbcompression no\n").unwrap();
+        assert!(config.rdbcompression);
+        config.apply_file(&path, |_| false).unwrap();
+        assert!(!config.rdbcompression);
+        fs::write(&path, "appendfsync always\nappendfilename log.aof\n").unwrap();
+        assert_eq!(config.appendfsync, Fsync::EverySec);
+        config.apply_file(&path, |_| false).unwrap();
+        assert_eq!(config.appendfsync, Fsync::Always);
+        assert_eq!(config.appendfilename, PathBuf::from("log.aof"));
+        fs::write(&path, "enable-debug-chaos yes\n").unwrap();
+        assert!(!config.enable_debug_chaos);
+        config.apply_file(&path, |_| false).unwrap();
+        assert!(config.enable_debug_chaos);
+        fs::write(&path, "aof-use-rdb-preamble no\n").unwrap();
+        assert!(config.aof_use_rdb_preamble);
+        config.apply_file(&path, |_| false).unwrap();
+        assert!(!config.aof_use_rdb_preamble);
+        fs::write(&path, "replicaof 10.0.0.1 6380\n").unwrap();
+        config.apply_file(&path, |_| false).unwrap();
+        assert_eq!(
+            config.replicaof.as_ref().unwrap().to_string(),
+            "10.0.0.1 6380"
+        );
+        assert!(config.replica_serve_stale_data);
+        fs::write(&path, "replica-serve-stale-data no\n").unwrap();
+        config.apply_file(&path, |_| false).unwrap();
+        assert!(!config.replica_serve_stale_data);
+        fs::write(&path, "min-replicas-to-write 2\nmin-replicas-max-lag 5\n").unwrap();
+        config.apply_file(&path, |_| false).unwrap();
+        assert_eq!(config.min_replicas_to_write, 2);
+        assert_eq!(config.min_replicas_max_lag, 5);
+        fs::write(&path, "slowlog-log-slower-than -1\nslowlog-max-len 16\n").unwrap();
+        config.apply_file(&path, |_| false).unwrap();
+        assert_eq!(config.slowlog_log_slower_than, -1);
+        assert_eq!(config.slowlog_max_len, 16);
+        fs::write(&path, "min-replicas-max-lag -1\n").unwrap();
+        let err = config.apply_file(&path, |_| false).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { line: 1, .. }));
+        fs::write(&path, "replicaof 10.0.0.1\n").unwrap();
+        let err = config.apply_file(&path, |_| false).unwrap_err();
+        assert!(matches!(err, Error::WrongArity { line: 1, .. }));
+        fs::write(&path, "save 900\n").unwrap();
+        let err = config.apply_file(&path, |_| false).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { line: 1, .. }));
+        fs::remove_file(path).unwrap();
+    }
 }
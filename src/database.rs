@@ -1,20 +1,105 @@
 //! # Redis database, holds [`Key`]-[`Value`] pairs along with associated data like TTLs.
 
+mod bits;
+mod expiry;
+mod hyperloglog;
+mod keyspace;
+mod memory;
+mod object;
+mod set;
+mod storage;
+mod stream;
+mod watch;
+mod zset;
+
+pub use bits::{BitFieldOp, BitFieldOverflow, BitFieldType};
+pub use bits::{BitOperation, BitRange, BitUnit, MAX_BIT_OFFSET};
+pub use expiry::{Expiration, ExpiryReport, Removal};
+pub use keyspace::ScanOptions;
+pub use memory::{MemoryStats, DEFAULT_SAMPLES};
+pub use object::ObjectInfo;
+pub use set::{IndexedSet, SetOperation};
+pub use storage::Snapshot;
+pub use stream::{AutoClaim, PendingEntry, PendingRange, PendingSummary, ReadGroupFrom};
+pub use stream::{AutoClaimOptions, XAddOptions, XClaimOptions};
+pub use stream::{Consumer, ConsumerGroup, Pending};
+pub use stream::{ConsumerInfo, GroupInfo, StreamInfo, Trim, TrimStrategy};
+pub use stream::{Entry, Fields, IdSpec, ReadFrom, Stream, StreamBound, StreamId};
+pub use watch::Watch;
+pub use zset::{Aggregate, LexBound, Score, ScoreBound, SortedSet, ZAddOptions, ZRange};
+pub use zset::{Coordinates, DistanceUnit, GeoMatch, GeoOrigin, GeoSearch, GeoShape};
+
+use crate::notify::{Class, Events, Notification};
+use crate::random::Rng;
 use derivative::Derivative;
-use std::{collections::HashMap, time};
+use expiry::ExpiryLog;
+use std::time;
+use storage::Storage;
 use tracing::instrument;
+use watch::Watches;
+
+/// The LFU counter of new values, so that they are not evicted before they get a chance to be used.
+const LFU_INIT_VAL: u8 = 5;
+
+/// How quickly the LFU counter saturates, see `lfu-log-factor` in Redis.
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// How long a value has to stay idle for its LFU counter to decay by one.
+const LFU_DECAY_TIME: time::Duration = time::Duration::from_secs(60);
 
 /// The identifier of a [`Value`] inside the [`Database`].
 pub type Key = String;
 
+/// The actual payload of a [`Value`], one variant per Redis data type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Data {
+    /// A binary-safe string, as set by `SET` or built bit by bit by `SETBIT`.
+    String(Vec<u8>),
+    /// An unordered collection of unique strings, as built by `SADD`.
+    Set(IndexedSet),
+    /// A collection of unique strings ordered by their scores, as built by `ZADD`.
+    SortedSet(SortedSet),
+    /// An append-only log of field-value entries, as built by `XADD`.
+    Stream(Stream),
+}
+
+impl Data {
+    /// The name of the type of this [`Data`], as reported by the `TYPE` command.
+    pub const fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        }
+    }
+}
+
+impl From<String> for Data {
+    fn from(string: String) -> Self {
+        Self::String(string.into_bytes())
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::String(bytes)
+    }
+}
+
 /// The value that is associated with a [`Key`] inside the [`Database`].
 #[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct Value {
-    pub data: String,
+    pub data: Data,
     ttl: Option<time::Duration>,
     #[derivative(Debug = "ignore")]
     created: time::Instant,
+    /// When this [`Value`] was last accessed, see [`Value::idle_time`].
+    #[derivative(Debug = "ignore")]
+    accessed: time::Instant,
+    /// The logarithmic access frequency counter, see [`Value::frequency`].
+    frequency: u8,
 }
 
 impl Eq for Value {}
@@ -26,32 +111,98 @@ impl PartialEq for Value {
 
 impl Value {
     /// Create a new [`Value`] with an optional TTL.
-    pub fn new(data: String, ttl: Option<time::Duration>) -> Self {
+    pub fn new(data: impl Into<Data>, ttl: Option<time::Duration>) -> Self {
+        let now = time::Instant::now();
         Self {
-            data,
+            data: data.into(),
             ttl,
-            created: time::Instant::now(),
+            created: now,
+            accessed: now,
+            frequency: LFU_INIT_VAL,
         }
     }
 
-    /// Create a new [`Value`] with no TTL.
+    /// Create a new string [`Value`] with no TTL.
     #[allow(dead_code)]
     pub fn without_ttl(data: String) -> Self {
-        Self {
-            data,
-            ttl: None,
-            created: time::Instant::now(),
-        }
+        Self::new(data, None)
     }
 
-    /// Create a new [`Value`] with a known TTL.
+    /// Create a new string [`Value`] with a known TTL.
     #[allow(dead_code)]
     pub fn with_ttl(data: String, ttl: time::Duration) -> Self {
-        Self {
-            data,
-            ttl: Some(ttl),
-            created: time::Instant::now(),
+        Self::new(data, Some(ttl))
+    }
+
+    /// Preset the access metadata of this [`Value`], as if it had last been accessed
+    /// `idle` ago, or as if its accesses had brought its LFU counter up to `frequency`.
+    ///
+    /// This is meant for warming up a cache from an exported snapshot, without making
+    /// every imported key look like it has just been accessed.
+    pub fn with_access(mut self, idle: Option<time::Duration>, frequency: Option<u8>) -> Self {
+        if let Some(accessed) = idle.and_then(|idle| self.accessed.checked_sub(idle)) {
+            self.accessed = accessed;
         }
+        if let Some(frequency) = frequency {
+            self.frequency = frequency;
+        }
+        self
+    }
+
+    /// How long ago this [`Value`] was last accessed, as reported by `OBJECT IDLETIME`.
+    pub fn idle_time(&self) -> time::Duration {
+        self.accessed.elapsed()
+    }
+
+    /// The logarithmic access frequency counter of this [`Value`], as reported by `OBJECT FREQ`.
+    ///
+    /// Works like the LFU counter of Redis: it grows slower the higher it gets,
+    /// and decays by one for every minute that the [`Value`] is not accessed.
+    pub fn frequency(&self) -> u8 {
+        let decay = self.idle_time().as_secs() / LFU_DECAY_TIME.as_secs();
+        self.frequency
+            .saturating_sub(u8::try_from(decay).unwrap_or(u8::MAX))
+    }
+
+    /// Record an access to this [`Value`].
+    fn touch(&mut self) {
+        let counter = self.frequency();
+        let base = f64::from(counter.saturating_sub(LFU_INIT_VAL));
+        let chance = (Rng::new().next_u64() >> 11) as f64 / (1_u64 << 53) as f64;
+        self.frequency = if chance < 1.0 / base.mul_add(LFU_LOG_FACTOR, 1.0) {
+            counter.saturating_add(1)
+        } else {
+            counter
+        };
+        self.accessed = time::Instant::now();
+    }
+
+    /// Create a new [`Value`] that expires at a given wall-clock moment.
+    pub fn expiring_at(data: impl Into<Data>, deadline: time::SystemTime) -> Self {
+        let ttl = deadline
+            .duration_since(time::SystemTime::now())
+            .unwrap_or_default();
+        Self::new(data, Some(ttl))
+    }
+
+    /// The wall-clock moment at which this [`Value`] expires (or expired), if it has a TTL.
+    pub fn expires_at(&self) -> Option<time::SystemTime> {
+        let (now, elapsed) = (time::SystemTime::now(), self.created.elapsed());
+        self.ttl.map(|ttl| match ttl.checked_sub(elapsed) {
+            Some(remaining) => now + remaining,
+            None => now - (elapsed - ttl),
+        })
+    }
+
+    /// How much longer this [`Value`] has to live, if it has a TTL.
+    pub fn ttl_remaining(&self) -> Option<time::Duration> {
+        self.ttl
+            .map(|ttl| ttl.saturating_sub(self.created.elapsed()))
+    }
+
+    /// Whether the TTL of this [`Value`] (if any) has run out.
+    pub fn is_expired(&self) -> bool {
+        self.ttl.is_some_and(|ttl| self.created.elapsed() > ttl)
     }
 }
 
@@ -62,25 +213,101 @@ pub enum Error {
     KeyNotFound,
     #[error("This key-value pair has expired")]
     Expired,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR value is not a valid float")]
+    NotAFloat,
+    #[error("ERR resulting score is not a number (NaN)")]
+    NaN,
+    #[error("ERR min or max not valid string range item")]
+    InvalidLexRange,
+    #[error("ERR Invalid stream ID specified as stream command argument")]
+    InvalidStreamId,
+    #[error("ERR The ID specified in XADD must be greater than 0-0")]
+    StreamIdZero,
+    #[error("ERR The ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+    #[error("ERR The stream has exhausted the last possible ID, unable to add more items")]
+    StreamIdExhausted,
+    #[error("ERR The ID specified in XSETID is smaller than the target stream top item")]
+    SetIdTooSmall,
+    #[error("ERR The entries_added specified in XSETID is smaller than the target stream length")]
+    EntriesAddedTooSmall,
+    #[error("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id")]
+    MaxDeletedIdTooLarge,
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+    #[error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")]
+    NoStreamForGroup,
+    #[error("NOGROUP No such consumer group '{group}' for key name '{key}'")]
+    NoGroup { key: Key, group: String },
+    #[error(
+        "NOGROUP No such key '{key}' or consumer group '{group}' in XREADGROUP with GROUP option"
+    )]
+    NoGroupToRead { key: Key, group: String },
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("NOGROUP No such key '{key}' or consumer group '{group}'")]
+    NoKeyOrGroup { key: Key, group: String },
+    #[error("WRONGTYPE Key is not a valid HyperLogLog string value.")]
+    InvalidHyperLogLog,
+    #[error("ERR invalid longitude,latitude pair {0}")]
+    InvalidCoordinates(String),
+    #[error("ERR could not decode requested zset member")]
+    UndecodableMember,
 }
 
-/// The Redis database. Owns a [`HashMap`] with [`Key`] - [`Value`] pairs.
+/// The Redis database. Owns the [`Key`] - [`Value`] pairs, see [`Snapshot`] for how.
 #[derive(Debug, Clone)]
 pub struct Database {
-    storage: HashMap<Key, Value>,
+    storage: Storage,
+    expiry: ExpiryLog,
+    /// The keyspace events to record, as set by `notify-keyspace-events`.
+    events: Events,
+    /// The keyspace events recorded since they were last taken, oldest first.
+    notifications: Vec<Notification>,
+    /// The keys watched by clients for their transactions, see `WATCH`.
+    watches: Watches,
+    /// How many writes were made since the last save, see [`Database::saved`].
+    dirty: u64,
 }
 
 impl Database {
     pub fn new() -> Self {
         Self {
-            storage: HashMap::new(),
+            storage: Storage::default(),
+            expiry: ExpiryLog::default(),
+            events: Events::default(),
+            notifications: vec![],
+            watches: Watches::default(),
+            dirty: 0,
         }
     }
 
     #[instrument(name = "db_get", skip(self))]
-    pub fn get(&self, key: &str) -> Result<&Value, Error> {
+    pub fn get(&mut self, key: &str) -> Result<&Value, Error> {
+        self.read(key, true)
+    }
+
+    /// Like [`Database::get`], but without recording an access to the value,
+    /// which the server does for the reads of all commands at once, see [`Database::access`].
+    #[instrument(name = "db_peek", skip(self))]
+    pub fn peek(&mut self, key: &str) -> Result<&Value, Error> {
+        self.read(key, false)
+    }
+
+    /// Record an access to the value at `key`, if there is one, as reading it does.
+    pub fn access(&mut self, key: &str) {
+        let _ = self.read(key, true);
+    }
+
+    fn read(&mut self, key: &str, touch: bool) -> Result<&Value, Error> {
         let now = time::Instant::now();
-        let value = self.storage.get(key).ok_or_else(|| {
+        let value = self.storage.get_mut(key).ok_or_else(|| {
             tracing::error!("No such key found");
             Error::KeyNotFound
         })?;
@@ -91,6 +318,9 @@ impl Database {
             }
             _ => {
                 tracing::debug!("Valid key found");
+                if touch {
+                    value.touch();
+                }
                 Ok(value)
             }
         }
@@ -98,20 +328,185 @@ impl Database {
 
     #[instrument(name = "db_set", skip(self))]
     pub fn set(&mut self, key: Key, value: Value) {
+        self.notify(Class::String, "set", &key);
+        if value.ttl.is_some() {
+            self.notify(Class::Generic, "expire", &key);
+        }
         let _ = self.storage.insert(key, value);
     }
+
+    /// Atomically replace the string at `key` with `new`, but only if it currently equals `expected`.
+    ///
+    /// If a `ttl` is given, it replaces the TTL of the key, otherwise the current one is kept.
+    /// Returns whether the swap took place; a missing key never matches.
+    #[instrument(name = "db_compare_and_set", skip(self))]
+    pub fn compare_and_set(
+        &mut self,
+        key: &str,
+        expected: &[u8],
+        new: Vec<u8>,
+        ttl: Option<time::Duration>,
+    ) -> Result<bool, Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Ok(false);
+        };
+        let Data::String(current) = &value.data else {
+            return Err(Error::WrongType);
+        };
+        if current != expected {
+            return Ok(false);
+        }
+        match ttl {
+            Some(ttl) => *value = Value::new(new, Some(ttl)),
+            None => value.data = Data::String(new),
+        }
+        self.notify(Class::String, "set", key);
+        if ttl.is_some() {
+            self.notify(Class::Generic, "expire", key);
+        }
+        Ok(true)
+    }
+
+    /// Reserve a block of `count` sequential IDs from the counter stored at `key`, which is
+    /// an integer string that starts out at zero, by adding `count` to it in one go.
+    ///
+    /// Returns the first and the last ID of the block. The TTL of the key, if any, is kept.
+    #[instrument(name = "db_sequence", skip(self))]
+    pub fn sequence(&mut self, key: Key, count: u32) -> Result<(i64, i64), Error> {
+        let current = match self.live_mut(&key).map(|value| &value.data) {
+            Some(Data::String(current)) => std::str::from_utf8(current)
+                .ok()
+                .and_then(|current| current.parse::<i64>().ok())
+                .ok_or(Error::NotAnInteger)?,
+            Some(_) => return Err(Error::WrongType),
+            None => 0,
+        };
+        let last = current
+            .checked_add(i64::from(count))
+            .ok_or(Error::Overflow)?;
+        match self.live_mut(&key) {
+            Some(value) => value.data = Data::from(last.to_string()),
+            None => {
+                let _ = self
+                    .storage
+                    .insert(key.clone(), Value::new(last.to_string(), None));
+            }
+        }
+        self.notify(Class::String, "incrby", &key);
+        Ok((current + 1, last))
+    }
+
+    /// Store all the `entries`, overwriting any existing keys, and return how many there were.
+    ///
+    /// Unlike [`Database::set`], this keeps the access metadata of the [`Value`]s as they
+    /// are, so they should be [preset](Value::with_access) for imported data.
+    #[instrument(name = "db_import", skip_all, fields(count = entries.len()))]
+    pub fn import(&mut self, entries: Vec<(Key, Value)>) -> usize {
+        let count = entries.len();
+        for (key, _) in &entries {
+            self.touch(key);
+        }
+        self.storage.extend(entries);
+        self.dirty += count as u64;
+        count
+    }
+
+    /// How many writes were made since the last save.
+    pub const fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    /// Note that a save has the first `changes` of the [dirty](Database::dirty) writes in it.
+    pub fn saved(&mut self, changes: u64) {
+        self.dirty = self.dirty.saturating_sub(changes);
+    }
+
+    /// Get the idle time and the LFU counter of the value at `key`, see `OBJECT`.
+    pub fn access_metadata(&self, key: &str) -> Option<(time::Duration, u8)> {
+        self.live(key)
+            .map(|value| (value.idle_time(), value.frequency()))
+    }
+
+    /// Iterate over all the [`Key`]-[`Value`] pairs that have not expired yet.
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.storage.iter().filter(|(_, value)| !value.is_expired())
+    }
+
+    /// Record the keyspace events enabled by `events` from now on, see [`notify`](crate::notify).
+    pub fn set_notify_events(&mut self, events: Events) {
+        self.events = events;
+        self.notifications
+            .retain(|notification| events.enabled(notification.class));
+    }
+
+    /// Take the keyspace events recorded since the last time.
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// Record the `event` that happened to `key`, if events of its `class` are enabled.
+    ///
+    /// Every write goes through here, so this is also where watched keys
+    /// get touched, and where the writes since the last save are counted.
+    fn notify(&mut self, class: Class, event: &'static str, key: &str) {
+        self.touch(key);
+        self.dirty += 1;
+        if self.events.enabled(class) {
+            self.notifications.push(Notification {
+                class,
+                event,
+                key: key.to_string(),
+            });
+        }
+    }
+
+    /// Record the `event` that changed `key`, followed by a `del` if the change left it removed.
+    fn notify_change(&mut self, class: Class, event: &'static str, key: &str) {
+        self.notify(class, event, key);
+        if !self.storage.contains_key(key) {
+            self.notify(Class::Generic, "del", key);
+        }
+    }
+
+    /// Store `data` at `key` without a TTL, overwriting it, and record the `event`,
+    /// or remove the key if there is no `data`, recording a `del` if it existed.
+    fn store(&mut self, key: Key, data: Option<Data>, class: Class, event: &'static str) {
+        match data {
+            Some(data) => {
+                self.notify(class, event, &key);
+                let _ = self.storage.insert(key, Value::new(data, None));
+            }
+            None => {
+                if self.storage.remove(&key).is_some() {
+                    self.notify(Class::Generic, "del", &key);
+                }
+            }
+        }
+    }
+
+    /// Get a mutable reference to a live [`Value`], lazily evicting it if its TTL ran out.
+    fn live_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.remove_if_expired(key, Removal::Lazy);
+        self.storage.get_mut(key)
+    }
+
+    /// Get a shared reference to a live [`Value`], treating expired ones as missing.
+    fn live(&self, key: &str) -> Option<&Value> {
+        self.storage.get(key).filter(|value| !value.is_expired())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::database::{Database, Error, Value};
+    use crate::database::{Data, Database, Error, Value};
+    use crate::notify::{Class, Events};
     use std::{thread, time::Duration};
 
     #[test]
     fn no_ttl() {
         let mut db = Database::new();
         db.set("foo".into(), Value::without_ttl("bar".into()));
-        assert_eq!(db.get("foo").unwrap().data, "bar");
+        assert_eq!(db.get("foo").unwrap().data, Data::String("bar".into()));
     }
 
     #[test]
@@ -127,6 +522,136 @@ mod tests {
         );
         thread::sleep(Duration::from_millis(20));
         assert_eq!(db.get("foo"), Err(Error::Expired));
-        assert_eq!(db.get("bar").unwrap().data, "baz");
+        assert_eq!(db.get("bar").unwrap().data, Data::String("baz".into()));
+    }
+
+    #[test]
+    fn dirty_writes() {
+        let mut db = Database::new();
+        db.set("foo".into(), Value::without_ttl("bar".into()));
+        let _ = db.get("foo");
+        assert_eq!(db.dirty(), 1);
+        let _ = db.import(vec![("bar".into(), Value::without_ttl("baz".into()))]);
+        assert_eq!(db.dirty(), 2);
+        // Writes made while a save is in progress stay dirty.
+        db.set("foo".into(), Value::without_ttl("qux".into()));
+        db.saved(2);
+        assert_eq!(db.dirty(), 1);
+    }
+
+    #[test]
+    fn access_metadata() {
+        let mut db = Database::new();
+        let idle = Duration::from_secs(600);
+        let imported = Value::without_ttl("bar".into()).with_access(Some(idle), Some(100));
+        assert_eq!(db.import(vec![("foo".into(), imported)]), 1);
+        let (idle_time, frequency) = db.access_metadata("foo").unwrap();
+        assert!(idle_time >= idle);
+        // Ten minutes of idling have decayed the counter by ten.
+        assert_eq!(frequency, 90);
+
+        let _ = db.peek("foo").unwrap();
+        assert!(db.access_metadata("foo").unwrap().0 >= idle_time);
+        let _ = db.get("foo").unwrap();
+        let (idle_time, frequency) = db.access_metadata("foo").unwrap();
+        assert!(idle_time < Duration::from_secs(1));
+        assert!((90..=91).contains(&frequency));
+        db.access("foo");
+        assert!((90..=92).contains(&db.access_metadata("foo").unwrap().1));
+        db.access("bar");
+        assert_eq!(db.access_metadata("bar"), None);
+    }
+
+    #[test]
+    fn compare_and_set() {
+        let mut db = Database::new();
+        assert_eq!(
+            db.compare_and_set("foo", b"bar", "baz".into(), None),
+            Ok(false)
+        );
+
+        db.set(
+            "foo".into(),
+            Value::with_ttl("bar".into(), Duration::from_secs(60)),
+        );
+        assert_eq!(
+            db.compare_and_set("foo", b"nope", "baz".into(), None),
+            Ok(false)
+        );
+        assert_eq!(
+            db.compare_and_set("foo", b"bar", "baz".into(), None),
+            Ok(true)
+        );
+        assert_eq!(db.get("foo").unwrap().data, Data::String("baz".into()));
+        assert!(db.get("foo").unwrap().expires_at().is_some());
+
+        let ttl = Some(Duration::from_millis(10));
+        assert_eq!(
+            db.compare_and_set("foo", b"baz", "qux".into(), ttl),
+            Ok(true)
+        );
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            db.compare_and_set("foo", b"qux", "quux".into(), None),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn sequence() {
+        let mut db = Database::new();
+        assert_eq!(db.sequence("ids".into(), 10), Ok((1, 10)));
+        assert_eq!(db.sequence("ids".into(), 1), Ok((11, 11)));
+        assert_eq!(db.get("ids").unwrap().data, Data::String("11".into()));
+
+        db.set(
+            "ids".into(),
+            Value::with_ttl("41".into(), Duration::from_secs(60)),
+        );
+        assert_eq!(db.sequence("ids".into(), 2), Ok((42, 43)));
+        assert!(db.get("ids").unwrap().expires_at().is_some());
+
+        db.set("text".into(), Value::new("abc".to_string(), None));
+        assert_eq!(db.sequence("text".into(), 1), Err(Error::NotAnInteger));
+        let max = i64::MAX.to_string();
+        db.set("full".into(), Value::new(max, None));
+        assert_eq!(db.sequence("full".into(), 1), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn keyspace_notifications() {
+        let mut db = Database::new();
+        db.set("quiet".into(), Value::new("1".to_string(), None));
+        assert!(db.take_notifications().is_empty());
+
+        db.set_notify_events(Events::parse("KEA").unwrap());
+        let ttl = Some(Duration::from_millis(5));
+        db.set("volatile".into(), Value::new("1".to_string(), ttl));
+        let _ = db.sadd("set".into(), vec!["a".into()]).unwrap();
+        let _ = db.srem("set", &["nope".into()]).unwrap();
+        let _ = db.srem("set", &["a".into()]).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(db.expire_cycle(10), 1);
+        let events: Vec<_> = db
+            .take_notifications()
+            .into_iter()
+            .map(|notification| (notification.class, notification.event, notification.key))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (Class::String, "set", "volatile".to_string()),
+                (Class::Generic, "expire", "volatile".to_string()),
+                (Class::Set, "sadd", "set".to_string()),
+                (Class::Set, "srem", "set".to_string()),
+                (Class::Generic, "del", "set".to_string()),
+                (Class::Expired, "expired", "volatile".to_string()),
+            ]
+        );
+
+        // Only the enabled classes are recorded.
+        db.set_notify_events(Events::parse("Ex").unwrap());
+        db.set("quiet".into(), Value::new("2".to_string(), None));
+        assert!(db.take_notifications().is_empty());
     }
 }
@@ -0,0 +1,419 @@
+//! # Bit-level commands over strings: `SETBIT`, `GETBIT`, `BITCOUNT`, `BITPOS` and `BITOP`.
+//!
+//! Strings are treated as arrays of bits, where bit `0` is the most significant
+//! bit of the first byte. Bits past the end of a string read as clear, and setting
+//! one grows the string with zero bytes, so any string can be used as a bitmap.
+//!
+//! `BITCOUNT` and `BITPOS` can be narrowed down to a range, given either in bytes
+//! (the default) or, since Redis 7, in bits:
+//!
+//! | Range               | Bits looked at                     |
+//! |---------------------|------------------------------------|
+//! | `0 0` or `0 0 BYTE` | The first byte, so bits `0` to `7` |
+//! | `0 0 BIT`           | Just the first bit                 |
+//! | `-2 -1 BIT`         | The last two bits of the string    |
+//!
+//! Like everywhere else in Redis, negative offsets count from the end, and
+//! ranges are clamped to the string instead of failing.
+
+use super::{Data, Database, Error, Key, Value};
+use crate::notify::Class;
+use tracing::instrument;
+
+mod field;
+
+pub use field::{BitFieldOp, BitFieldOverflow, BitFieldType};
+
+/// The highest bit offset that `SETBIT` accepts, as strings are limited to 512 MiB.
+pub const MAX_BIT_OFFSET: usize = 512 * 1024 * 1024 * 8 - 1;
+
+/// The bitwise operations of `BITOP`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    /// Inverts the bits of a single string.
+    Not,
+}
+
+impl BitOperation {
+    /// Apply the operation to the `bytes` at the same position of each string.
+    fn apply(self, mut bytes: impl Iterator<Item = u8>) -> u8 {
+        let first = bytes.next().unwrap_or(0);
+        match self {
+            Self::And => bytes.fold(first, |result, byte| result & byte),
+            Self::Or => bytes.fold(first, |result, byte| result | byte),
+            Self::Xor => bytes.fold(first, |result, byte| result ^ byte),
+            Self::Not => !first,
+        }
+    }
+}
+
+/// The unit of the offsets of a [`BitRange`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitUnit {
+    #[default]
+    Byte,
+    Bit,
+}
+
+/// An inclusive range of a string to run a bit-level command on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitRange {
+    pub start: i64,
+    /// The end of the range, which only `BITPOS` lets leave out to mean the end of the string.
+    pub end: Option<i64>,
+    pub unit: BitUnit,
+}
+
+impl BitRange {
+    /// Work out the inclusive range of bits of a string of `len` bytes that this range covers,
+    /// if any.
+    fn bits(&self, len: usize) -> Option<(usize, usize)> {
+        let end = self.end.unwrap_or(-1);
+        match self.unit {
+            BitUnit::Byte => {
+                normalize(self.start, end, len).map(|(start, end)| (start * 8, end * 8 + 7))
+            }
+            BitUnit::Bit => normalize(self.start, end, len.saturating_mul(8)),
+        }
+    }
+}
+
+/// Turn the inclusive range from `start` to `end` over `len` units into indices, where
+/// negative offsets count from the end and out-of-bounds ones are clamped to the ends.
+///
+/// Returns [`None`] if the range is empty.
+fn normalize(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let resolve = |offset: i64| match offset {
+        offset if offset < 0 => (len + offset).max(0),
+        offset => offset,
+    };
+    let (start, end) = (resolve(start), resolve(end).min(len - 1));
+    if len == 0 || start > end {
+        return None;
+    }
+    Some((usize::try_from(start).ok()?, usize::try_from(end).ok()?))
+}
+
+/// Mask the bits of the byte at `index` that fall within the bits `first..=last`.
+fn mask(index: usize, first: usize, last: usize) -> u8 {
+    let head = if index == first / 8 {
+        0xFF >> (first % 8)
+    } else {
+        0xFF
+    };
+    let tail = if index == last / 8 {
+        0xFF << (7 - last % 8)
+    } else {
+        0xFF
+    };
+    head & tail
+}
+
+/// Count the set bits among the bits `first..=last` of `bytes`.
+fn count_ones(bytes: &[u8], first: usize, last: usize) -> usize {
+    (first / 8..=last / 8)
+        .map(|index| (bytes[index] & mask(index, first, last)).count_ones() as usize)
+        .sum()
+}
+
+/// Find the first bit set to `bit` among the bits `first..=last` of `bytes`.
+fn position(bytes: &[u8], bit: bool, first: usize, last: usize) -> Option<usize> {
+    (first / 8..=last / 8).find_map(|index| {
+        let byte = if bit { bytes[index] } else { !bytes[index] };
+        match byte & mask(index, first, last) {
+            0 => None,
+            found => Some(index * 8 + found.leading_zeros() as usize),
+        }
+    })
+}
+
+impl Database {
+    /// Get the string stored at `key` as bytes, or [`None`] if there is no such key.
+    fn lookup_bytes(&self, key: &str) -> Result<Option<&[u8]>, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::String(string)) => Ok(Some(string)),
+            Some(_) => Err(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Set or clear the bit at `offset` of the string stored at `key`, growing the string
+    /// (or creating it) as needed. The `offset` must not exceed [`MAX_BIT_OFFSET`].
+    ///
+    /// Returns the previous value of the bit.
+    #[instrument(name = "db_setbit", skip(self))]
+    pub fn setbit(&mut self, key: Key, offset: usize, bit: bool) -> Result<bool, Error> {
+        if self.live_mut(&key).is_none() {
+            let _ = self
+                .storage
+                .insert(key.clone(), Value::new(Data::String(vec![]), None));
+        }
+        let Some(Data::String(bytes)) = self.storage.get_mut(&key).map(|value| &mut value.data)
+        else {
+            return Err(Error::WrongType);
+        };
+        let index = offset / 8;
+        if bytes.len() <= index {
+            bytes.resize(index + 1, 0);
+        }
+        let mask = 0x80 >> (offset % 8);
+        let previous = bytes[index] & mask != 0;
+        if bit {
+            bytes[index] |= mask;
+        } else {
+            bytes[index] &= !mask;
+        }
+        self.notify(Class::String, "setbit", &key);
+        Ok(previous)
+    }
+
+    /// Get the bit at `offset` of the string stored at `key`, which is clear past its end.
+    #[instrument(name = "db_getbit", skip(self))]
+    pub fn getbit(&self, key: &str, offset: usize) -> Result<bool, Error> {
+        let bytes = self.lookup_bytes(key)?.unwrap_or_default();
+        let byte = bytes.get(offset / 8).copied().unwrap_or(0);
+        Ok(byte & (0x80 >> (offset % 8)) != 0)
+    }
+
+    /// Compute the result of `operation` over the strings stored at `keys` and store it
+    /// at `destination`, overwriting it. Strings shorter than the longest one count as
+    /// padded with zero bytes, and missing keys as empty strings.
+    ///
+    /// If the result is empty, `destination` is removed instead. Returns the length of the result.
+    #[instrument(name = "db_bitop", skip(self))]
+    pub fn bitop(
+        &mut self,
+        operation: BitOperation,
+        destination: Key,
+        keys: &[Key],
+    ) -> Result<usize, Error> {
+        let strings = keys
+            .iter()
+            .map(|key| Ok(self.lookup_bytes(key)?.unwrap_or_default()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let len = strings.iter().map(|bytes| bytes.len()).max().unwrap_or(0);
+        let result: Vec<u8> = (0..len)
+            .map(|index| {
+                let bytes = strings.iter().map(|bytes| bytes.get(index).copied());
+                operation.apply(bytes.map(Option::unwrap_or_default))
+            })
+            .collect();
+        let result = (!result.is_empty()).then_some(Data::String(result));
+        self.store(destination, result, Class::String, "set");
+        Ok(len)
+    }
+
+    /// Count the set bits of the string stored at `key`, or just of its `range`.
+    #[instrument(name = "db_bitcount", skip(self))]
+    pub fn bitcount(&self, key: &str, range: Option<BitRange>) -> Result<usize, Error> {
+        let bytes = self.lookup_bytes(key)?.unwrap_or_default();
+        let bits = match range {
+            Some(range) => range.bits(bytes.len()),
+            None => normalize(0, -1, bytes.len().saturating_mul(8)),
+        };
+        Ok(bits.map_or(0, |(first, last)| count_ones(bytes, first, last)))
+    }
+
+    /// Find the first bit set to `bit` in the string stored at `key`, or just in its `range`.
+    ///
+    /// Returns `-1` if there is no such bit. When looking for a clear bit without the end
+    /// of the range given, the string counts as padded with clear bits on the right.
+    #[instrument(name = "db_bitpos", skip(self))]
+    pub fn bitpos(&self, key: &str, bit: bool, range: Option<BitRange>) -> Result<i64, Error> {
+        let Some(bytes) = self.lookup_bytes(key)? else {
+            return Ok(if bit { -1 } else { 0 });
+        };
+        let range = range.unwrap_or(BitRange {
+            start: 0,
+            end: None,
+            unit: BitUnit::Byte,
+        });
+        let Some((first, last)) = range.bits(bytes.len()) else {
+            return Ok(-1);
+        };
+        let found = match position(bytes, bit, first, last) {
+            Some(found) => Some(found),
+            None if !bit && range.end.is_none() => Some(last + 1),
+            None => None,
+        };
+        Ok(found.map_or(-1, |found| i64::try_from(found).unwrap_or(i64::MAX)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitOperation, BitRange, BitUnit};
+    use crate::database::{Data, Database, Error, Value};
+    use crate::random::Rng;
+
+    /// The bits of `bytes` within the `range`, as (index, bit) pairs, worked out the slow way.
+    fn naive_bits(bytes: &[u8], range: BitRange) -> Vec<(usize, bool)> {
+        let bits: Vec<bool> = bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |shift| byte >> shift & 1 == 1))
+            .collect();
+        let per_unit = match range.unit {
+            BitUnit::Byte => 8,
+            BitUnit::Bit => 1,
+        };
+        let units = (bits.len() / per_unit) as i64;
+        // Like in Redis, negative offsets past the start clamp to it, even for the end.
+        let offset = |offset: i64| {
+            if offset < 0 {
+                (units + offset).max(0)
+            } else {
+                offset
+            }
+        };
+        let (start, end) = (offset(range.start), offset(range.end.unwrap_or(-1)));
+        bits.into_iter()
+            .enumerate()
+            .filter(|(index, _)| {
+                let unit = (*index / per_unit) as i64;
+                unit >= start && unit <= end
+            })
+            .collect()
+    }
+
+    fn db_with(key: &str, string: &str) -> Database {
+        let mut db = Database::new();
+        db.set(key.into(), Value::new(string.to_string(), None));
+        db
+    }
+
+    #[test]
+    fn byte_and_bit_ranges() {
+        // "foobar" is the example of the Redis documentation.
+        let db = db_with("s", "foobar");
+        let range = |start, end, unit| {
+            Some(BitRange {
+                start,
+                end: Some(end),
+                unit,
+            })
+        };
+        assert_eq!(db.bitcount("s", None), Ok(26));
+        assert_eq!(db.bitcount("s", range(0, 0, BitUnit::Byte)), Ok(4));
+        assert_eq!(db.bitcount("s", range(1, 1, BitUnit::Byte)), Ok(6));
+        assert_eq!(db.bitcount("s", range(5, 30, BitUnit::Bit)), Ok(17));
+        assert_eq!(db.bitcount("s", range(-1, -2, BitUnit::Bit)), Ok(0));
+        assert_eq!(db.bitcount("missing", None), Ok(0));
+
+        let db = db_with("s", "\u{0}\u{7f}\u{0}");
+        assert_eq!(db.bitpos("s", true, None), Ok(9));
+        assert_eq!(db.bitpos("s", true, range(2, -1, BitUnit::Byte)), Ok(-1));
+        assert_eq!(db.bitpos("s", true, range(10, 12, BitUnit::Bit)), Ok(10));
+        assert_eq!(db.bitpos("s", false, range(9, 15, BitUnit::Bit)), Ok(-1));
+        // Without an end, the string counts as padded with clear bits.
+        let db = db_with("s", "\u{7f}");
+        let open = Some(BitRange {
+            start: 1,
+            end: None,
+            unit: BitUnit::Bit,
+        });
+        assert_eq!(db.bitpos("s", false, open), Ok(8));
+        assert_eq!(db.bitpos("missing", false, None), Ok(0));
+        assert_eq!(db.bitpos("missing", true, None), Ok(-1));
+    }
+
+    #[test]
+    fn setbit_grows_the_string() {
+        let mut db = db_with("s", "a");
+        assert_eq!(db.setbit("s".into(), 6, true), Ok(false));
+        assert_eq!(db.setbit("s".into(), 6, false), Ok(true));
+        assert_eq!(db.getbit("s", 1), Ok(true));
+        assert_eq!(db.getbit("s", 100), Ok(false));
+
+        assert_eq!(db.setbit("bitmap".into(), 23, true), Ok(false));
+        assert_eq!(db.get("bitmap").unwrap().data, Data::String(vec![0, 0, 1]));
+        // Bitmaps need not be valid UTF-8.
+        assert_eq!(db.setbit("bitmap".into(), 0, true), Ok(false));
+        assert_eq!(db.bitcount("bitmap", None), Ok(2));
+        assert_eq!(db.bitpos("bitmap", false, None), Ok(1));
+
+        assert_eq!(db.getbit("missing", 0), Ok(false));
+    }
+
+    #[test]
+    fn bitop() {
+        let mut db = db_with("a", "\u{f}\u{f}");
+        db.set("b".into(), Value::new(Data::String(vec![0xFF]), None));
+        let keys = |keys: &[&str]| keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let bytes = |db: &mut Database| db.get("dst").map(|value| value.data.clone());
+
+        assert_eq!(
+            db.bitop(BitOperation::And, "dst".into(), &keys(&["a", "b"])),
+            Ok(2)
+        );
+        assert_eq!(bytes(&mut db), Ok(Data::String(vec![0x0F, 0])));
+        assert_eq!(
+            db.bitop(BitOperation::Or, "dst".into(), &keys(&["a", "b"])),
+            Ok(2)
+        );
+        assert_eq!(bytes(&mut db), Ok(Data::String(vec![0xFF, 0x0F])));
+        assert_eq!(
+            db.bitop(
+                BitOperation::Xor,
+                "dst".into(),
+                &keys(&["a", "b", "missing"])
+            ),
+            Ok(2)
+        );
+        assert_eq!(bytes(&mut db), Ok(Data::String(vec![0xF0, 0x0F])));
+        assert_eq!(
+            db.bitop(BitOperation::Not, "dst".into(), &keys(&["a"])),
+            Ok(2)
+        );
+        assert_eq!(bytes(&mut db), Ok(Data::String(vec![0xF0, 0xF0])));
+
+        // An empty result removes the destination.
+        assert_eq!(
+            db.bitop(BitOperation::Or, "dst".into(), &keys(&["missing"])),
+            Ok(0)
+        );
+        assert_eq!(bytes(&mut db), Err(Error::KeyNotFound));
+    }
+
+    #[test]
+    fn matches_a_naive_implementation() {
+        let mut rng = Rng::with_seed(7);
+        for _ in 0..500 {
+            let len = rng.below(6);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            let mut db = Database::new();
+            db.set("s".into(), Value::new(Data::String(bytes.clone()), None));
+            let unit = if rng.below(2) == 0 {
+                BitUnit::Byte
+            } else {
+                BitUnit::Bit
+            };
+            let mut offset = || rng.below(100) as i64 - 50;
+            let range = BitRange {
+                start: offset(),
+                end: Some(offset()),
+                unit,
+            };
+
+            let bits = naive_bits(&bytes, range);
+            let ones = bits.iter().filter(|(_, bit)| *bit).count();
+            assert_eq!(
+                db.bitcount("s", Some(range)),
+                Ok(ones),
+                "{bytes:?} {range:?}"
+            );
+            for bit in [false, true] {
+                let found = bits.iter().find(|(_, value)| *value == bit);
+                let expected = found.map_or(-1, |(index, _)| *index as i64);
+                assert_eq!(
+                    db.bitpos("s", bit, Some(range)),
+                    Ok(expected),
+                    "{bytes:?} {range:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! # `BITFIELD`: reading and writing integers of arbitrary width packed into strings.
+//!
+//! Each operation works on an integer of 1 to 64 bits (signed) or 1 to 63 bits (unsigned,
+//! so that every value fits into a reply) stored at any bit offset, most significant bit
+//! first. Writes that do not fit into the integer are handled according to the current
+//! overflow behaviour, which can change between operations of the same call:
+//!
+//! | Behaviour | Result of an overflowing write                       |
+//! |-----------|------------------------------------------------------|
+//! | `WRAP`    | Wraps around, like fixed-width integers do (default) |
+//! | `SAT`     | Saturates at the minimum or maximum value            |
+//! | `FAIL`    | Nothing is written and the reply is nil              |
+
+use crate::database::{Data, Database, Error, Key, Value};
+use crate::notify::Class;
+use tracing::instrument;
+
+/// The type of an integer within a string, like `i16` or `u8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl BitFieldType {
+    /// The smallest value of this type.
+    const fn min(self) -> i128 {
+        if self.signed {
+            -(1 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    /// The largest value of this type.
+    const fn max(self) -> i128 {
+        if self.signed {
+            (1 << (self.bits - 1)) - 1
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+
+    /// Fit `value` into this type as the `overflow` behaviour says, if it can be.
+    fn fit(self, value: i128, overflow: BitFieldOverflow) -> Option<i64> {
+        let (min, max) = (self.min(), self.max());
+        let value = match overflow {
+            _ if (min..=max).contains(&value) => value,
+            BitFieldOverflow::Wrap => (value - min).rem_euclid(max - min + 1) + min,
+            BitFieldOverflow::Sat => value.clamp(min, max),
+            BitFieldOverflow::Fail => return None,
+        };
+        i64::try_from(value).ok()
+    }
+}
+
+/// What to do when a write does not fit into its integer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitFieldOverflow {
+    #[default]
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// A single operation of `BITFIELD`, where offsets are in bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitFieldOp {
+    Get {
+        kind: BitFieldType,
+        offset: usize,
+    },
+    /// Set the integer, replying with its previous value.
+    Set {
+        kind: BitFieldType,
+        offset: usize,
+        value: i64,
+    },
+    /// Add to the integer, replying with its new value.
+    IncrBy {
+        kind: BitFieldType,
+        offset: usize,
+        increment: i64,
+    },
+    /// Change the overflow behaviour of the operations that follow.
+    Overflow(BitFieldOverflow),
+}
+
+impl BitFieldOp {
+    /// The number of bytes that a string needs to hold the integer written by this operation.
+    fn written_len(&self) -> Option<usize> {
+        match *self {
+            Self::Set { kind, offset, .. } | Self::IncrBy { kind, offset, .. } => {
+                Some((offset + kind.bits as usize + 7) / 8)
+            }
+            Self::Get { .. } | Self::Overflow(_) => None,
+        }
+    }
+}
+
+/// Read the integer of the given `kind` at `offset` of `bytes`, past whose end bits are clear.
+fn read(bytes: &[u8], kind: BitFieldType, offset: usize) -> i64 {
+    let raw = (offset..offset + kind.bits as usize).fold(0_u64, |raw, bit| {
+        let byte = bytes.get(bit / 8).copied().unwrap_or(0);
+        raw << 1 | u64::from(byte >> (7 - bit % 8) & 1)
+    });
+    let unused = 64 - kind.bits;
+    if kind.signed {
+        // Shift the sign bit to the top, then back with sign extension.
+        ((raw << unused) as i64) >> unused
+    } else {
+        raw as i64
+    }
+}
+
+/// Write `value` as an integer of the given `kind` at `offset` of `bytes`, which must be long enough.
+fn write(bytes: &mut [u8], kind: BitFieldType, offset: usize, value: i64) {
+    for index in 0..kind.bits as usize {
+        let bit = offset + index;
+        let mask = 0x80 >> (bit % 8);
+        if value >> (kind.bits as usize - 1 - index) & 1 == 1 {
+            bytes[bit / 8] |= mask;
+        } else {
+            bytes[bit / 8] &= !mask;
+        }
+    }
+}
+
+impl Database {
+    /// Run the `operations` of `BITFIELD` over the string stored at `key`, which is
+    /// created or grown as needed if any of them write.
+    ///
+    /// Returns one reply for each operation other than [`BitFieldOp::Overflow`],
+    /// which is [`None`] if a write failed because of [`BitFieldOverflow::Fail`].
+    #[instrument(name = "db_bitfield", skip(self))]
+    pub fn bitfield(
+        &mut self,
+        key: Key,
+        operations: &[BitFieldOp],
+    ) -> Result<Vec<Option<i64>>, Error> {
+        let Some(len) = operations.iter().filter_map(BitFieldOp::written_len).max() else {
+            let bytes = self.lookup_bytes(&key)?.unwrap_or_default();
+            return Ok(operations
+                .iter()
+                .filter_map(|operation| match *operation {
+                    BitFieldOp::Get { kind, offset } => Some(Some(read(bytes, kind, offset))),
+                    _ => None,
+                })
+                .collect());
+        };
+        if self.live_mut(&key).is_none() {
+            let _ = self
+                .storage
+                .insert(key.clone(), Value::new(Data::String(vec![]), None));
+        }
+        let Some(Data::String(bytes)) = self.storage.get_mut(&key).map(|value| &mut value.data)
+        else {
+            return Err(Error::WrongType);
+        };
+        if bytes.len() < len {
+            bytes.resize(len, 0);
+        }
+        let mut overflow = BitFieldOverflow::default();
+        let mut replies = vec![];
+        for operation in operations {
+            let reply = match *operation {
+                BitFieldOp::Get { kind, offset } => Some(read(bytes, kind, offset)),
+                BitFieldOp::Set {
+                    kind,
+                    offset,
+                    value,
+                } => {
+                    let previous = read(bytes, kind, offset);
+                    kind.fit(i128::from(value), overflow).map(|value| {
+                        write(bytes, kind, offset, value);
+                        previous
+                    })
+                }
+                BitFieldOp::IncrBy {
+                    kind,
+                    offset,
+                    increment,
+                } => {
+                    let current = i128::from(read(bytes, kind, offset));
+                    kind.fit(current + i128::from(increment), overflow)
+                        .map(|value| {
+                            write(bytes, kind, offset, value);
+                            value
+                        })
+                }
+                BitFieldOp::Overflow(behaviour) => {
+                    overflow = behaviour;
+                    continue;
+                }
+            };
+            replies.push(reply);
+        }
+        self.notify(Class::String, "setbit", &key);
+        Ok(replies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitFieldOp, BitFieldOverflow, BitFieldType};
+    use crate::database::{Data, Database};
+    use crate::random::Rng;
+
+    const U8: BitFieldType = BitFieldType {
+        signed: false,
+        bits: 8,
+    };
+    const I5: BitFieldType = BitFieldType {
+        signed: true,
+        bits: 5,
+    };
+
+    #[test]
+    fn get_set_and_incrby() {
+        let mut db = Database::new();
+        let get = |kind, offset| BitFieldOp::Get { kind, offset };
+        assert_eq!(db.bitfield("k".into(), &[get(U8, 0)]), Ok(vec![Some(0)]));
+        // Reading alone does not create the key.
+        assert!(db.get("k").is_err());
+
+        let operations = [
+            BitFieldOp::Set {
+                kind: U8,
+                offset: 4,
+                value: 0xAB,
+            },
+            get(U8, 4),
+            get(U8, 0),
+            BitFieldOp::IncrBy {
+                kind: I5,
+                offset: 100,
+                increment: -3,
+            },
+        ];
+        assert_eq!(
+            db.bitfield("k".into(), &operations),
+            Ok(vec![Some(0), Some(0xAB), Some(0x0A), Some(-3)])
+        );
+        let data = db.get("k").unwrap().data.clone();
+        let Data::String(bytes) = data else {
+            panic!("BITFIELD should create a string");
+        };
+        assert_eq!(bytes.len(), 14);
+        assert_eq!(&bytes[..2], &[0x0A, 0xB0]);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut db = Database::new();
+        let incr = |increment| BitFieldOp::IncrBy {
+            kind: I5,
+            offset: 0,
+            increment,
+        };
+        let operations = [
+            incr(15),
+            incr(1),
+            BitFieldOp::Overflow(BitFieldOverflow::Sat),
+            incr(-100),
+            BitFieldOp::Overflow(BitFieldOverflow::Fail),
+            incr(-1),
+            BitFieldOp::Set {
+                kind: U8,
+                offset: 8,
+                value: 256,
+            },
+            incr(1),
+        ];
+        assert_eq!(
+            db.bitfield("k".into(), &operations),
+            Ok(vec![Some(15), Some(-16), Some(-16), None, None, Some(-15)])
+        );
+    }
+
+    #[test]
+    fn round_trips_at_any_offset() {
+        let mut db = Database::new();
+        let mut rng = Rng::with_seed(3);
+        for _ in 0..500 {
+            let kind = BitFieldType {
+                signed: rng.below(2) == 0,
+                bits: 1 + rng.below(63) as u32,
+            };
+            let offset = rng.below(200);
+            let value = kind
+                .fit(i128::from(rng.next_u64() as i64), BitFieldOverflow::Wrap)
+                .unwrap();
+            let operations = [
+                BitFieldOp::Set {
+                    kind,
+                    offset,
+                    value,
+                },
+                BitFieldOp::Get { kind, offset },
+            ];
+            let replies = db.bitfield("k".into(), &operations).unwrap();
+            assert_eq!(replies[1], Some(value), "{kind:?} at {offset}");
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! # Expiry of keys, and the record of it that `EXT.WHENEXPIRES` reports.
+//!
+//! Like in Redis, keys whose TTL ran out are removed in one of two ways:
+//!
+//! - Lazily, by the first command that touches them.
+//! - Actively, by the expire cycle that the server cron runs, which removes up to a fixed
+//!   number of expired keys each time, so that keys nobody touches do not linger forever.
+//!
+//! Until then, expired keys are only treated as missing. Every removal is traced, and the
+//! most recent ones are remembered, so that when and how a key went away can be looked up
+//! after the fact instead of guessed.
+//!
+//! Replicas do not remove expired keys by themselves, as their clocks and their timing
+//! would make their datasets drift apart from that of their master. The master takes the
+//! keys that it removed instead (see [`Database::take_expired`]), and propagates a `DEL`
+//! for each of them, ahead of any write that could depend on the removal.
+
+use super::{Database, Key};
+use crate::notify::Class;
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::instrument;
+
+/// How many of the most recent removals of expired keys are remembered.
+const EXPIRY_LOG_LEN: usize = 1024;
+
+/// How an expired key got removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    /// By a command that touched it.
+    Lazy,
+    /// By the expire cycle with the given number, counting from `1`.
+    Active { cycle: u64 },
+}
+
+/// The removal of an expired key, with wall-clock times as Unix milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiration {
+    pub deadline_ms: u64,
+    pub removed_at_ms: u64,
+    pub removal: Removal,
+}
+
+/// Everything known about the expiry of a key, as reported by `EXT.WHENEXPIRES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReport {
+    /// The key has no TTL.
+    Persistent,
+    /// The key expires at `deadline_ms`, `ttl_ms` from now.
+    Scheduled { deadline_ms: u64, ttl_ms: u64 },
+    /// The key expired at `deadline_ms`, but has not been removed yet.
+    Overdue { deadline_ms: u64 },
+    /// The key expired and got removed recently.
+    Expired(Expiration),
+    /// The key does not exist, and did not expire recently, if ever.
+    Missing,
+}
+
+/// The most recent removals of expired keys, along with the count of expire cycles.
+#[derive(Debug, Clone, Default)]
+pub struct ExpiryLog {
+    cycles: u64,
+    removals: HashMap<Key, Expiration>,
+    /// The keys of the `removals`, oldest first.
+    order: VecDeque<Key>,
+    /// The keys removed since they were last taken, see [`Database::take_expired`].
+    removed: Vec<Key>,
+    /// Whether expired keys are left for the master to remove, as replicas do.
+    kept: bool,
+}
+
+impl ExpiryLog {
+    fn record(&mut self, key: &str, deadline: SystemTime, removal: Removal) {
+        let expiration = Expiration {
+            deadline_ms: unix_millis(deadline),
+            removed_at_ms: unix_millis(SystemTime::now()),
+            removal,
+        };
+        tracing::debug!(key, ?expiration, "Removed an expired key");
+        self.removed.push(key.to_string());
+        if self.removals.insert(key.to_string(), expiration).is_none() {
+            self.order.push_back(key.to_string());
+        }
+        if self.order.len() > EXPIRY_LOG_LEN {
+            if let Some(oldest) = self.order.pop_front() {
+                let _ = self.removals.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| {
+        u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+    })
+}
+
+impl Database {
+    /// Remove the value at `key` if its TTL ran out, recording how.
+    pub(super) fn remove_if_expired(&mut self, key: &str, removal: Removal) {
+        if self.expiry.kept {
+            return;
+        }
+        let Some(deadline) = self
+            .storage
+            .get(key)
+            .filter(|value| value.is_expired())
+            .and_then(super::Value::expires_at)
+        else {
+            return;
+        };
+        let _ = self.storage.remove(key);
+        self.expiry.record(key, deadline, removal);
+        self.notify(Class::Expired, "expired", key);
+    }
+
+    /// Run an active expire cycle, removing up to `limit` expired keys.
+    ///
+    /// Returns how many keys got removed.
+    #[instrument(name = "db_expire_cycle", skip(self))]
+    pub fn expire_cycle(&mut self, limit: usize) -> usize {
+        if self.expiry.kept {
+            return 0;
+        }
+        self.expiry.cycles += 1;
+        let cycle = self.expiry.cycles;
+        let expired: Vec<Key> = self
+            .storage
+            .iter()
+            .filter(|(_, value)| value.is_expired())
+            .map(|(key, _)| key.clone())
+            .take(limit)
+            .collect();
+        for key in &expired {
+            self.remove_if_expired(key, Removal::Active { cycle });
+        }
+        if !expired.is_empty() {
+            tracing::debug!(cycle, removed = expired.len(), "Expire cycle done");
+        }
+        expired.len()
+    }
+
+    /// Keep the expired keys until they are removed explicitly, as replicas do,
+    /// or go back to removing them once `kept` is off again.
+    pub fn keep_expired(&mut self, kept: bool) {
+        self.expiry.kept = kept;
+    }
+
+    /// Take the keys that expired and got removed since the last time.
+    pub fn take_expired(&mut self) -> Vec<Key> {
+        std::mem::take(&mut self.expiry.removed)
+    }
+
+    /// Tell when the value at `key` expires, or when and how it got removed if it already did.
+    #[instrument(name = "db_when_expires", skip(self))]
+    pub fn when_expires(&self, key: &str) -> ExpiryReport {
+        let Some(value) = self.storage.get(key) else {
+            return self
+                .expiry
+                .removals
+                .get(key)
+                .map_or(ExpiryReport::Missing, |expiration| {
+                    ExpiryReport::Expired(*expiration)
+                });
+        };
+        let Some(deadline) = value.expires_at() else {
+            return ExpiryReport::Persistent;
+        };
+        let deadline_ms = unix_millis(deadline);
+        match value.ttl_remaining() {
+            Some(ttl) if !value.is_expired() => ExpiryReport::Scheduled {
+                deadline_ms,
+                ttl_ms: u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX),
+            },
+            _ => ExpiryReport::Overdue { deadline_ms },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExpiryLog, ExpiryReport, Removal, EXPIRY_LOG_LEN};
+    use crate::database::{Database, Value};
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn lazy_and_active_removals() {
+        let mut db = Database::new();
+        let ttl = Some(Duration::from_millis(5));
+        db.set("lazy".into(), Value::new("1".to_string(), ttl));
+        db.set("active".into(), Value::new("1".to_string(), ttl));
+        db.set(
+            "later".into(),
+            Value::new("1".to_string(), Some(Duration::from_secs(60))),
+        );
+        db.set("forever".into(), Value::new("1".to_string(), None));
+        assert_eq!(db.when_expires("forever"), ExpiryReport::Persistent);
+        assert_eq!(db.when_expires("nope"), ExpiryReport::Missing);
+        let ExpiryReport::Scheduled { ttl_ms, .. } = db.when_expires("later") else {
+            panic!("the key should be scheduled to expire");
+        };
+        assert!(ttl_ms > 59_000);
+
+        thread::sleep(Duration::from_millis(10));
+        assert!(matches!(
+            db.when_expires("lazy"),
+            ExpiryReport::Overdue { .. }
+        ));
+        // Touching the key removes it.
+        assert_eq!(
+            db.compare_and_set("lazy", b"1", "2".into(), None),
+            Ok(false)
+        );
+        let ExpiryReport::Expired(expiration) = db.when_expires("lazy") else {
+            panic!("the key should have been removed");
+        };
+        assert_eq!(expiration.removal, Removal::Lazy);
+        assert_eq!(db.expire_cycle(10), 1);
+        assert_eq!(db.expire_cycle(10), 0);
+
+        let ExpiryReport::Expired(expiration) = db.when_expires("active") else {
+            panic!("the key should have been removed");
+        };
+        assert_eq!(expiration.removal, Removal::Active { cycle: 1 });
+        assert!(expiration.deadline_ms <= expiration.removed_at_ms);
+        assert!(matches!(
+            db.when_expires("later"),
+            ExpiryReport::Scheduled { .. }
+        ));
+        // Once set again, a key is no longer reported as expired.
+        db.set("lazy".into(), Value::new("1".to_string(), None));
+        assert_eq!(db.when_expires("lazy"), ExpiryReport::Persistent);
+    }
+
+    #[test]
+    fn kept_for_the_master() {
+        let mut db = Database::new();
+        let ttl = Some(Duration::from_millis(5));
+        db.set("lazy".into(), Value::new("1".to_string(), ttl));
+        db.set("active".into(), Value::new("1".to_string(), ttl));
+        db.keep_expired(true);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(db.compare_and_set("lazy", b"1", "2".into(), None), Ok(true));
+        assert_eq!(db.expire_cycle(10), 0);
+        assert!(matches!(
+            db.when_expires("active"),
+            ExpiryReport::Overdue { .. }
+        ));
+        assert!(db.take_expired().is_empty());
+
+        db.keep_expired(false);
+        assert_eq!(db.expire_cycle(10), 2);
+        let mut expired = db.take_expired();
+        expired.sort();
+        assert_eq!(expired, ["active", "lazy"]);
+        assert!(db.take_expired().is_empty());
+    }
+
+    #[test]
+    fn log_is_bounded() {
+        let mut log = ExpiryLog::default();
+        for n in 0..=EXPIRY_LOG_LEN {
+            log.record(&n.to_string(), SystemTime::now(), Removal::Lazy);
+        }
+        assert_eq!(log.removals.len(), EXPIRY_LOG_LEN);
+        assert!(!log.removals.contains_key("0"));
+        assert!(log.removals.contains_key("1"));
+    }
+}
@@ -0,0 +1,366 @@
+//! # HyperLogLog: `PFADD`, `PFCOUNT` and `PFMERGE`.
+//!
+//! A HyperLogLog estimates the number of distinct elements added to it in a fixed
+//! 12 KiB, with a standard error of 0.81%. It is stored as a string in the same
+//! format as Redis uses, so that the two can exchange them through RDB files:
+//!
+//! ```text
+//! "HYLL" | encoding (1 byte) | unused (3 bytes) | cached cardinality (8 bytes) | registers
+//! ```
+//!
+//! There are 16384 registers of 6 bits, packed starting from the least significant bit
+//! of each byte. Only this dense encoding is ever written, but the sparse one that Redis
+//! starts HyperLogLogs out with is read as well, and turned dense on the first write.
+//! The cached cardinality is never relied upon, only marked as stale on writes.
+
+use super::{Data, Database, Error, Key, Value};
+use crate::notify::Class;
+use tracing::instrument;
+
+/// How many bits of the hash of an element choose its register.
+const INDEX_BITS: u32 = 14;
+
+/// The number of registers.
+const REGISTERS: usize = 1 << INDEX_BITS;
+
+/// The width of a single register.
+const REGISTER_BITS: usize = 6;
+
+/// The largest value of a register.
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+
+/// How many bits of the hash of an element are left to count zeroes in.
+const COUNT_BITS: u32 = 64 - INDEX_BITS;
+
+const MAGIC: &[u8; 4] = b"HYLL";
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + REGISTERS * REGISTER_BITS / 8;
+
+/// The encodings of the registers, as told by the byte after the [`MAGIC`].
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+/// The most significant bit of the cached cardinality, which marks it as stale.
+const STALE_CACHE: u8 = 0x80;
+
+/// The seed of the hash of elements, which has to match the one of Redis.
+const HASH_SEED: u64 = 0xadc8_3b19;
+
+/// An empty HyperLogLog, in the dense encoding.
+fn empty() -> Vec<u8> {
+    let mut bytes = vec![0; DENSE_LEN];
+    bytes[..MAGIC.len()].copy_from_slice(MAGIC);
+    bytes
+}
+
+/// Turn a HyperLogLog of either encoding into a dense one.
+fn dense(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(Error::InvalidHyperLogLog);
+    }
+    match bytes[MAGIC.len()] {
+        DENSE if bytes.len() == DENSE_LEN => Ok(bytes.to_vec()),
+        SPARSE => {
+            let mut dense = empty();
+            let (mut index, mut position) = (0, HEADER_LEN);
+            while let Some(&opcode) = bytes.get(position) {
+                position += 1;
+                let (len, value) = match opcode >> 6 {
+                    // ZERO: up to 64 empty registers.
+                    0 => (usize::from(opcode & 0x3f) + 1, 0),
+                    // XZERO: up to 16384 empty registers, with the length taking another byte.
+                    1 => {
+                        let low = *bytes.get(position).ok_or(Error::InvalidHyperLogLog)?;
+                        position += 1;
+                        ((usize::from(opcode & 0x3f) << 8 | usize::from(low)) + 1, 0)
+                    }
+                    // VAL: up to 4 registers of the same value, up to 32.
+                    _ => (usize::from(opcode & 0x3) + 1, (opcode >> 2 & 0x1f) + 1),
+                };
+                if index + len > REGISTERS {
+                    return Err(Error::InvalidHyperLogLog);
+                }
+                if value > 0 {
+                    let registers = &mut dense[HEADER_LEN..];
+                    (index..index + len).for_each(|index| set(registers, index, value));
+                }
+                index += len;
+            }
+            match index {
+                REGISTERS => Ok(dense),
+                _ => Err(Error::InvalidHyperLogLog),
+            }
+        }
+        _ => Err(Error::InvalidHyperLogLog),
+    }
+}
+
+/// Get the register at `index` of the dense `registers`.
+fn get(registers: &[u8], index: usize) -> u8 {
+    let (byte, shift) = (index * REGISTER_BITS / 8, index * REGISTER_BITS % 8);
+    let mut value = registers[byte] >> shift;
+    // Registers that do not fit into the rest of the byte continue into the next one.
+    if shift + REGISTER_BITS > 8 {
+        value |= registers[byte + 1] << (8 - shift);
+    }
+    value & REGISTER_MAX
+}
+
+/// Set the register at `index` of the dense `registers` to `value`.
+fn set(registers: &mut [u8], index: usize, value: u8) {
+    let (byte, shift) = (index * REGISTER_BITS / 8, index * REGISTER_BITS % 8);
+    registers[byte] &= !(REGISTER_MAX << shift);
+    registers[byte] |= value << shift;
+    if shift + REGISTER_BITS > 8 {
+        registers[byte + 1] &= !(REGISTER_MAX >> (8 - shift));
+        registers[byte + 1] |= value >> (8 - shift);
+    }
+}
+
+/// `MurmurHash64A`, which Redis hashes elements with.
+fn murmur_hash_64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut hash = seed ^ (data.len() as u64).wrapping_mul(M);
+    let chunks = data.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes long"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        hash ^= k;
+        hash = hash.wrapping_mul(M);
+    }
+    if !tail.is_empty() {
+        for (index, &byte) in tail.iter().enumerate() {
+            hash ^= u64::from(byte) << (8 * index);
+        }
+        hash = hash.wrapping_mul(M);
+    }
+    hash ^= hash >> R;
+    hash = hash.wrapping_mul(M);
+    hash ^= hash >> R;
+    hash
+}
+
+/// Find the register of `element` and the value it would set it to: one more than the
+/// number of trailing zeroes of the rest of its hash.
+fn observe(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash_64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // The sentinel bit keeps the count within the register.
+    let rest = hash >> INDEX_BITS | 1 << COUNT_BITS;
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// Estimate the cardinality out of the dense `registers`, with the estimator of
+/// Otmar Ertl's "New cardinality estimation algorithms for HyperLogLog sketches".
+fn estimate(registers: &[u8]) -> u64 {
+    let mut histogram = [0_u32; COUNT_BITS as usize + 2];
+    for index in 0..REGISTERS {
+        histogram[usize::from(get(registers, index))] += 1;
+    }
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - f64::from(histogram[COUNT_BITS as usize + 1])) / m);
+    for count in histogram[1..=COUNT_BITS as usize].iter().rev() {
+        z += f64::from(*count);
+        z *= 0.5;
+    }
+    z += m * sigma(f64::from(histogram[0]) / m);
+    (m * m / (2.0 * std::f64::consts::LN_2) / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+impl Database {
+    /// Get the registers of the HyperLogLog stored at `key`, in the dense encoding.
+    fn lookup_hyperloglog(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::String(bytes)) => dense(bytes).map(Some),
+            Some(_) => Err(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Add `elements` to the HyperLogLog stored at `key`, creating it if needed.
+    ///
+    /// Returns whether the HyperLogLog was created or its estimate may have changed.
+    #[instrument(name = "db_pfadd", skip(self))]
+    pub fn pfadd(&mut self, key: Key, elements: &[String]) -> Result<bool, Error> {
+        let created = self.live_mut(&key).is_none();
+        if created {
+            let _ = self
+                .storage
+                .insert(key.clone(), Value::new(Data::String(empty()), None));
+        }
+        let Some(Data::String(bytes)) = self.storage.get_mut(&key).map(|value| &mut value.data)
+        else {
+            return Err(Error::WrongType);
+        };
+        if bytes.get(MAGIC.len()) != Some(&DENSE) || bytes.len() != DENSE_LEN {
+            *bytes = dense(bytes)?;
+        }
+        let mut changed = created;
+        for element in elements {
+            let (index, count) = observe(element.as_bytes());
+            if get(&bytes[HEADER_LEN..], index) < count {
+                set(&mut bytes[HEADER_LEN..], index, count);
+                changed = true;
+            }
+        }
+        if changed {
+            bytes[HEADER_LEN - 1] |= STALE_CACHE;
+            self.notify(Class::String, "pfadd", &key);
+        }
+        Ok(changed)
+    }
+
+    /// Estimate the number of distinct elements added to any of the HyperLogLogs stored at `keys`.
+    #[instrument(name = "db_pfcount", skip(self))]
+    pub fn pfcount(&self, keys: &[Key]) -> Result<u64, Error> {
+        let union = self.union_of_hyperloglogs(keys)?;
+        Ok(estimate(&union[HEADER_LEN..]))
+    }
+
+    /// Store the union of the HyperLogLogs stored at `destination` and `sources` at `destination`.
+    #[instrument(name = "db_pfmerge", skip(self))]
+    pub fn pfmerge(&mut self, destination: Key, sources: &[Key]) -> Result<(), Error> {
+        let keys: Vec<_> = std::iter::once(destination.clone())
+            .chain(sources.iter().cloned())
+            .collect();
+        let mut union = self.union_of_hyperloglogs(&keys)?;
+        union[HEADER_LEN - 1] |= STALE_CACHE;
+        // Unlike `SET`, this keeps the TTL of the destination.
+        match self.live_mut(&destination) {
+            Some(value) => value.data = Data::String(union),
+            None => {
+                let _ = self
+                    .storage
+                    .insert(destination.clone(), Value::new(Data::String(union), None));
+            }
+        }
+        self.notify(Class::String, "pfadd", &destination);
+        Ok(())
+    }
+
+    /// Build a HyperLogLog with every register at its largest among the ones stored at `keys`.
+    fn union_of_hyperloglogs(&self, keys: &[Key]) -> Result<Vec<u8>, Error> {
+        let mut union = empty();
+        for key in keys {
+            let Some(other) = self.lookup_hyperloglog(key)? else {
+                continue;
+            };
+            for index in 0..REGISTERS {
+                let value = get(&other[HEADER_LEN..], index);
+                if get(&union[HEADER_LEN..], index) < value {
+                    set(&mut union[HEADER_LEN..], index, value);
+                }
+            }
+        }
+        Ok(union)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dense, estimate, get, set, HEADER_LEN, REGISTERS};
+    use crate::database::{Data, Database, Error, Value};
+
+    fn elements(range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(|n| format!("element:{n}")).collect()
+    }
+
+    #[test]
+    fn registers_are_packed() {
+        let mut registers = vec![0; REGISTERS * 6 / 8];
+        for index in 0..REGISTERS {
+            set(&mut registers, index, (index % 64) as u8);
+        }
+        for index in 0..REGISTERS {
+            assert_eq!(get(&registers, index), (index % 64) as u8);
+        }
+        // The first register takes the low 6 bits of the first byte.
+        assert_eq!(registers[0], 1 << 6);
+    }
+
+    #[test]
+    fn add_and_count() {
+        let mut db = Database::new();
+        assert_eq!(db.pfcount(&["h".into()]), Ok(0));
+        assert_eq!(db.pfadd("h".into(), &[]), Ok(true));
+        assert_eq!(db.pfadd("h".into(), &elements(0..7)), Ok(true));
+        assert_eq!(db.pfadd("h".into(), &elements(0..7)), Ok(false));
+        assert_eq!(db.pfcount(&["h".into()]), Ok(7));
+
+        let _ = db.pfadd("h".into(), &elements(0..100_000));
+        let count = db.pfcount(&["h".into()]).unwrap();
+        assert!(count.abs_diff(100_000) < 2_000, "estimated {count}");
+
+        db.set("s".into(), Value::new(Data::from("nope".to_string()), None));
+        assert_eq!(db.pfadd("s".into(), &[]), Err(Error::InvalidHyperLogLog));
+        assert_eq!(db.pfcount(&["s".into()]), Err(Error::InvalidHyperLogLog));
+    }
+
+    #[test]
+    fn merge() {
+        let mut db = Database::new();
+        let _ = db.pfadd("a".into(), &elements(0..1000));
+        let _ = db.pfadd("b".into(), &elements(500..1500));
+        let count = db
+            .pfcount(&["a".into(), "b".into(), "missing".into()])
+            .unwrap();
+        assert!(count.abs_diff(1500) < 30, "estimated {count}");
+
+        assert_eq!(db.pfmerge("c".into(), &["a".into(), "b".into()]), Ok(()));
+        assert_eq!(db.pfcount(&["c".into()]), Ok(count));
+        // The destination takes part in the union too.
+        let _ = db.pfadd("d".into(), &elements(1500..2000));
+        assert_eq!(db.pfmerge("d".into(), &["c".into()]), Ok(()));
+        let count = db.pfcount(&["d".into()]).unwrap();
+        assert!(count.abs_diff(2000) < 40, "estimated {count}");
+    }
+
+    #[test]
+    fn reads_the_sparse_encoding() {
+        let mut sparse = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        // VAL: a single register set to 3, then XZERO for all of the others.
+        sparse.extend_from_slice(&[0x80 | (3 - 1) << 2, 0x40 | 0x3f, 0xfe]);
+        let registers = dense(&sparse).unwrap();
+        assert_eq!(get(&registers[HEADER_LEN..], 0), 3);
+        assert_eq!(estimate(&registers[HEADER_LEN..]), 1);
+        // Registers past the last one make it invalid.
+        sparse.push(0);
+        assert_eq!(dense(&sparse), Err(Error::InvalidHyperLogLog));
+    }
+}
@@ -0,0 +1,439 @@
+//! # Generic keyspace commands, which work regardless of the type of the [`Value`].
+//!
+//! [`Value`]: super::Value
+
+use super::{Database, Key, Removal};
+use crate::glob;
+use crate::notify::Class;
+use std::time::Duration;
+use tracing::instrument;
+
+/// The options of a `SCAN` call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Only return keys matching this glob-style pattern.
+    pub pattern: Option<String>,
+    /// How many keys to look at, as a hint. Defaults to 10.
+    pub count: Option<usize>,
+    /// Only return keys holding values of this type.
+    pub kind: Option<String>,
+}
+
+impl Database {
+    /// Get the name of the type of the value stored at `key`, or `none` if there is no such key.
+    #[instrument(name = "db_type", skip(self))]
+    pub fn key_type(&self, key: &str) -> &'static str {
+        self.live(key)
+            .map_or("none", |value| value.data.type_name())
+    }
+
+    /// Get the remaining time to live of `key`, in milliseconds.
+    ///
+    /// Returns `-2` if there is no such key and `-1` if it has no TTL.
+    #[instrument(name = "db_pttl", skip(self))]
+    pub fn pttl(&self, key: &str) -> i64 {
+        match self.live(key).map(super::Value::ttl_remaining) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(ttl)) => i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX),
+        }
+    }
+
+    /// Count the keys that expire within each of the `horizons`.
+    ///
+    /// This looks at every key, so it is only meant for occasional introspection.
+    #[instrument(name = "db_expiry_forecast", skip(self))]
+    pub fn expiry_forecast(&self, horizons: &[Duration]) -> Vec<usize> {
+        let mut forecast = vec![0; horizons.len()];
+        for ttl in self.iter().filter_map(|(_, value)| value.ttl_remaining()) {
+            for (count, horizon) in forecast.iter_mut().zip(horizons) {
+                *count += usize::from(ttl < *horizon);
+            }
+        }
+        forecast
+    }
+
+    /// Get all the keys matching the glob-style `pattern`, in no particular order.
+    ///
+    /// This looks at every key at once, which is what `SCAN` is there to avoid.
+    #[instrument(name = "db_keys", skip(self))]
+    pub fn keys(&self, pattern: &str) -> Vec<Key> {
+        self.iter()
+            .filter(|(key, _)| glob::matches(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Remove the `keys`, returning how many of them existed.
+    #[instrument(name = "db_del", skip(self))]
+    pub fn del(&mut self, keys: &[Key]) -> usize {
+        let mut removed = 0;
+        for key in keys {
+            self.remove_if_expired(key, Removal::Lazy);
+            if self.storage.remove(key).is_some() {
+                self.notify(Class::Generic, "del", key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Remove every key, like `FLUSHALL`, returning how many there were.
+    #[instrument(name = "db_flush", skip(self))]
+    pub fn flush(&mut self) -> usize {
+        let storage = std::mem::take(&mut self.storage);
+        for (key, _) in storage.iter() {
+            self.touch(key);
+        }
+        self.dirty += storage.len() as u64;
+        storage.len()
+    }
+
+    /// Incrementally iterate over the keyspace, starting at `cursor`.
+    ///
+    /// Keys are spread over the buckets of a virtual hash table, sized like the one
+    /// Redis would use for this many keys, and cursors are bucket indices that advance
+    /// in the same reverse binary order as in Redis (see [`next_bucket`]). Cursors are
+    /// thus opaque, but take the very same values that Redis would hand out.
+    ///
+    /// This order is what makes the guarantees of `SCAN` hold even as the table grows
+    /// or shrinks between calls:
+    ///
+    /// - Every key present during the whole iteration is returned at least once.
+    /// - Keys may be returned more than once, if the table shrinks.
+    /// - Keys added or removed during the iteration may or may not be returned.
+    ///
+    /// The `options` filter the keys of the visited buckets, so a call may return no keys
+    /// at all without the iteration being complete, and the guarantees hold as is for the
+    /// keys that match them, like the ones whose type stays the same throughout (`TYPE`).
+    /// Returns the next cursor, which is `0` once the iteration is complete.
+    #[instrument(name = "db_scan", skip(self))]
+    pub fn scan(&self, cursor: u64, options: &ScanOptions) -> (u64, Vec<Key>) {
+        let mask = scan_table_size(self.storage.len()) - 1;
+        // The storage keeps the keys by their reversed hashes, so the top bits of those are
+        // where the bucket of a key comes in the order of the visit, see [`next_bucket`].
+        let shift = u64::BITS - mask.count_ones();
+        let first = (cursor & mask).reverse_bits() >> shift;
+
+        let count = options.count.unwrap_or(10).max(1);
+        // Like Redis, visit at most ten buckets per requested key, in case most are empty.
+        let visits = u64::try_from(count.saturating_mul(10)).unwrap_or(u64::MAX);
+        let mut last = first.saturating_add(visits - 1).min(mask);
+        let mut visited: Vec<&Key> = vec![];
+        let mut current = first;
+        for (order, key) in self.storage.scan_order(first << shift) {
+            if self.live(key).is_none() {
+                continue;
+            }
+            let bucket = order >> shift;
+            if bucket != current && visited.len() >= count {
+                break;
+            }
+            if bucket > last {
+                break;
+            }
+            visited.push(key);
+            current = bucket;
+        }
+        if visited.len() >= count {
+            last = current;
+        }
+        let cursor = next_bucket((last << shift).reverse_bits(), mask);
+
+        let keys = visited
+            .into_iter()
+            .filter(|key| {
+                options
+                    .pattern
+                    .as_deref()
+                    .map_or(true, |pattern| glob::matches(pattern, key))
+            })
+            .filter(|key| {
+                options
+                    .kind
+                    .as_deref()
+                    .map_or(true, |kind| kind.eq_ignore_ascii_case(self.key_type(key)))
+            })
+            .cloned()
+            .collect();
+        (cursor, keys)
+    }
+}
+
+/// The number of buckets that a Redis hash table holding `len` keys has.
+fn scan_table_size(len: usize) -> u64 {
+    const MIN_TABLE_SIZE: u64 = 4;
+    (len as u64).next_power_of_two().max(MIN_TABLE_SIZE)
+}
+
+/// The bucket to visit after `cursor`, in a table with the given `mask` (its size minus one).
+///
+/// Redis increments cursors starting from their highest bit instead of the lowest one, so
+/// that the buckets that a bucket splits into when the table grows (or the one that it
+/// merges into when the table shrinks) come right where it was in the order of the visit.
+/// Wrapping around back to `0` means that every bucket has been visited.
+const fn next_bucket(cursor: u64, mask: u64) -> u64 {
+    (cursor | !mask)
+        .reverse_bits()
+        .wrapping_add(1)
+        .reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_bucket, scan_table_size, ScanOptions};
+    use crate::database::{Database, Value};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    fn scan_all(db: &Database, options: &ScanOptions) -> Vec<String> {
+        let mut keys = vec![];
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = db.scan(cursor, options);
+            keys.extend(batch);
+            if next == 0 {
+                return keys;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let mut db = Database::new();
+        db.set("a".into(), Value::without_ttl("x".into()));
+        db.sadd("b".into(), vec!["a".into()]).unwrap();
+        let watch = db.watch("a".into());
+        assert_eq!(db.flush(), 2);
+        assert_eq!(db.iter().count(), 0);
+        assert!(db.touched(&[watch]));
+        assert_eq!(db.flush(), 0);
+    }
+
+    #[test]
+    fn del() {
+        let mut db = Database::new();
+        db.set("a".into(), Value::without_ttl("x".into()));
+        db.sadd("b".into(), vec!["a".into()]).unwrap();
+        db.set(
+            "expired".into(),
+            Value::with_ttl("x".into(), Duration::from_millis(1)),
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        let watch = db.watch("a".into());
+        let keys = ["a", "b", "c", "expired"].map(String::from);
+        assert_eq!(db.del(&keys), 2);
+        assert_eq!(db.iter().count(), 0);
+        assert!(db.touched(&[watch]));
+        assert_eq!(db.del(&keys), 0);
+    }
+
+    #[test]
+    fn type_and_pttl() {
+        let mut db = Database::new();
+        db.set("str".into(), Value::without_ttl("x".into()));
+        db.set(
+            "ttl".into(),
+            Value::with_ttl("x".into(), Duration::from_secs(10)),
+        );
+        db.sadd("set".into(), vec!["a".into()]).unwrap();
+        assert_eq!(db.key_type("str"), "string");
+        assert_eq!(db.key_type("set"), "set");
+        assert_eq!(db.key_type("nope"), "none");
+        assert_eq!(db.pttl("nope"), -2);
+        assert_eq!(db.pttl("str"), -1);
+        assert!((9_000..=10_000).contains(&db.pttl("ttl")));
+    }
+
+    #[test]
+    fn keys() {
+        let mut db = Database::new();
+        for key in ["apple", "apricot", "banana"] {
+            db.set(key.into(), Value::without_ttl("x".into()));
+        }
+        let mut keys = db.keys("ap*");
+        keys.sort();
+        assert_eq!(keys, ["apple", "apricot"]);
+        assert_eq!(db.keys("*").len(), 3);
+        assert!(db.keys("cherry").is_empty());
+    }
+
+    #[test]
+    fn expiry_forecast() {
+        let mut db = Database::new();
+        db.set("forever".into(), Value::without_ttl("x".into()));
+        for (key, secs) in [("soon", 5), ("later", 50), ("much-later", 500)] {
+            db.set(
+                key.into(),
+                Value::with_ttl("x".into(), Duration::from_secs(secs)),
+            );
+        }
+        let horizons = [1, 10, 60, 3600].map(Duration::from_secs);
+        assert_eq!(db.expiry_forecast(&horizons), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn scan_visits_every_key() {
+        let mut db = Database::new();
+        for i in 0..100 {
+            db.set(format!("key:{i}"), Value::without_ttl(i.to_string()));
+        }
+        db.sadd("set".into(), vec!["a".into()]).unwrap();
+
+        let options = ScanOptions {
+            count: Some(7),
+            ..ScanOptions::default()
+        };
+        let keys: HashSet<String> = scan_all(&db, &options).into_iter().collect();
+        assert_eq!(keys.len(), 101);
+
+        let options = ScanOptions {
+            pattern: Some("key:1*".into()),
+            ..ScanOptions::default()
+        };
+        assert_eq!(scan_all(&db, &options).len(), 11);
+
+        let options = ScanOptions {
+            kind: Some("set".into()),
+            ..ScanOptions::default()
+        };
+        assert_eq!(scan_all(&db, &options), vec!["set".to_string()]);
+    }
+
+    #[test]
+    fn scan_keeps_up_with_writes() {
+        let mut db = Database::new();
+        for i in 0..50 {
+            db.set(format!("key:{i}"), Value::without_ttl(i.to_string()));
+        }
+        let options = ScanOptions {
+            count: Some(usize::MAX),
+            ..ScanOptions::default()
+        };
+        let (cursor, keys) = db.scan(0, &options);
+        assert_eq!((cursor, keys.len()), (0, 50));
+
+        let removed: Vec<String> = (0..25).map(|i| format!("key:{i}")).collect();
+        assert_eq!(db.del(&removed), 25);
+        db.set("added".into(), Value::without_ttl("x".into()));
+        let mut keys = scan_all(&db, &ScanOptions::default());
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 26);
+        assert!(keys.contains(&"added".to_string()));
+        assert!(!keys.contains(&"key:0".to_string()));
+    }
+
+    #[test]
+    fn cursors_follow_the_redis_order() {
+        let mut cursor = 0;
+        let mut order = vec![];
+        loop {
+            order.push(cursor);
+            cursor = next_bucket(cursor, 7);
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert_eq!(order, [0, 4, 2, 6, 1, 5, 3, 7]);
+        assert_eq!(scan_table_size(0), 4);
+        assert_eq!(scan_table_size(5), 8);
+
+        let mut db = Database::new();
+        db.set("a".into(), Value::without_ttl("x".into()));
+        db.set("b".into(), Value::without_ttl("x".into()));
+        let options = ScanOptions {
+            count: Some(1),
+            ..ScanOptions::default()
+        };
+        let (mut cursor, mut seen) = (0, 0);
+        loop {
+            let (next, keys) = db.scan(cursor, &options);
+            seen += keys.len();
+            assert!([0, 2, 1, 3].contains(&next));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen, 2);
+    }
+
+    /// Scan the whole keyspace, calling `mutate` with the number of the call before each one.
+    fn scan_mutating(
+        db: &mut Database,
+        options: &ScanOptions,
+        mut mutate: impl FnMut(&mut Database, usize),
+    ) -> HashSet<String> {
+        let (mut cursor, mut calls, mut seen) = (0, 0, HashSet::new());
+        loop {
+            mutate(db, calls);
+            let (next, keys) = db.scan(cursor, options);
+            seen.extend(keys);
+            calls += 1;
+            if next == 0 {
+                return seen;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn scan_guarantee_while_growing() {
+        let mut db = Database::new();
+        let stable: Vec<String> = (0..50).map(|i| format!("stable:{i}")).collect();
+        for key in &stable {
+            db.sadd(key.clone(), vec!["a".into()]).unwrap();
+        }
+        let options = ScanOptions {
+            count: Some(5),
+            ..ScanOptions::default()
+        };
+        // The first calls add keys, making the table grow several times over.
+        let seen = scan_mutating(&mut db, &options, |db, call| {
+            for i in (call < 10).then_some(0..100).into_iter().flatten() {
+                db.set(format!("new:{call}:{i}"), Value::without_ttl("x".into()));
+            }
+            let _ = db
+                .storage
+                .remove(&format!("new:{}:0", call.saturating_sub(1)));
+        });
+        assert!(stable.iter().all(|key| seen.contains(key)));
+
+        // Filtering by type narrows down what is returned, not what is visited.
+        let options = ScanOptions {
+            kind: Some("set".into()),
+            ..options
+        };
+        let seen = scan_mutating(&mut db, &options, |db, call| {
+            for i in (call < 10).then_some(0..100).into_iter().flatten() {
+                db.set(format!("more:{call}:{i}"), Value::without_ttl("x".into()));
+            }
+        });
+        assert_eq!(seen, stable.iter().cloned().collect());
+    }
+
+    #[test]
+    fn scan_guarantee_while_shrinking() {
+        let mut db = Database::new();
+        let stable: Vec<String> = (0..50).map(|i| format!("stable:{i}")).collect();
+        for key in &stable {
+            db.set(key.clone(), Value::without_ttl("x".into()));
+        }
+        for i in 0..2000 {
+            db.set(format!("doomed:{i}"), Value::without_ttl("x".into()));
+        }
+        let options = ScanOptions {
+            count: Some(20),
+            ..ScanOptions::default()
+        };
+        // Every call removes keys, making the table shrink several times over.
+        let seen = scan_mutating(&mut db, &options, |db, call| {
+            for i in call * 300..(call + 1) * 300 {
+                let _ = db.storage.remove(&format!("doomed:{i}"));
+            }
+        });
+        assert!(stable.iter().all(|key| seen.contains(key)));
+    }
+}
@@ -0,0 +1,205 @@
+//! # Memory usage estimates, as reported by `MEMORY USAGE` and `MEMORY STATS`.
+//!
+//! The server doesn't hook into the allocator, so the numbers are estimates made from the
+//! sizes of the structures holding the data: the bytes of every string, plus what each
+//! element costs the collection that holds it. Like in Redis, collections are estimated
+//! from a sample of their elements, so that `MEMORY USAGE` stays cheap on big keys.
+
+use super::stream::{Consumer, ConsumerGroup, Pending};
+use super::{Data, Database, Fields, IndexedSet, Key, Score, SortedSet, Stream, StreamId, Value};
+use crate::resp::Token;
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// How many elements of a collection `MEMORY USAGE` looks at, unless told otherwise.
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// What a hash table spends on each entry besides the entry itself: a control byte.
+const HASH_ENTRY_OVERHEAD: usize = 1;
+
+/// What an entry of the keyspace costs the table holding it, besides the key and the value.
+const KEYSPACE_ENTRY: usize = size_of::<(Key, Arc<Value>)>() + HASH_ENTRY_OVERHEAD;
+
+/// What the [`Value`] of a key takes, without what its [`Data`] points to:
+/// the value itself, behind the two reference counts of its [`Arc`].
+const VALUE: usize = size_of::<Value>() + 2 * size_of::<usize>();
+
+/// How much memory the dataset takes, as reported by `MEMORY STATS`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub keys: usize,
+    /// The bytes taken by the keys and their values.
+    pub dataset: usize,
+    /// The bytes taken by the keyspace table itself, for the keys to point to their values.
+    pub overhead: usize,
+}
+
+impl MemoryStats {
+    pub fn total(&self) -> usize {
+        self.dataset + self.overhead
+    }
+
+    /// The reply to `MEMORY STATS`, a map of the same fields as Redis replies with,
+    /// minus the ones about the allocator.
+    pub fn reply(&self) -> Token {
+        let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+        let total = self.total();
+        let bytes_per_key = match self.keys {
+            0 => 0,
+            keys => total / keys,
+        };
+        let percentage = match total {
+            0 => 0.0,
+            total => self.dataset as f64 * 100.0 / total as f64,
+        };
+        Token::Map {
+            pairs: vec![
+                field("total.allocated", Token::from(total)),
+                field("overhead.total", Token::from(self.overhead)),
+                field("keys.count", Token::from(self.keys)),
+                field("keys.bytes-per-key", Token::from(bytes_per_key)),
+                field("dataset.bytes", Token::from(self.dataset)),
+                field("dataset.percentage", Token::Double { data: percentage }),
+                field(
+                    "db.0",
+                    Token::Map {
+                        pairs: vec![field("overhead.hashtable.main", Token::from(self.overhead))],
+                    },
+                ),
+            ],
+        }
+    }
+}
+
+impl Database {
+    /// Estimate how many bytes the `key` and its value take, looking at `samples` elements
+    /// of a collection or all of them, see `MEMORY USAGE`. [`None`] if there's no such key.
+    pub fn memory_usage(&self, key: &str, samples: Option<usize>) -> Option<usize> {
+        let value = self.live(key)?;
+        Some(KEYSPACE_ENTRY + key.len() + VALUE + value.data.memory_usage(samples))
+    }
+
+    /// Estimate how much memory the whole dataset takes, see `MEMORY STATS`.
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.iter()
+            .fold(MemoryStats::default(), |mut stats, (key, value)| {
+                stats.keys += 1;
+                stats.overhead += KEYSPACE_ENTRY;
+                stats.dataset += key.len() + VALUE + value.data.memory_usage(Some(DEFAULT_SAMPLES));
+                stats
+            })
+    }
+}
+
+impl Data {
+    /// Estimate how many bytes this data points to, besides the [`Data`] itself.
+    fn memory_usage(&self, samples: Option<usize>) -> usize {
+        match self {
+            Self::String(bytes) => bytes.len(),
+            Self::Set(set) => set_usage(set, samples),
+            Self::SortedSet(zset) => sorted_set_usage(zset, samples),
+            Self::Stream(stream) => stream_usage(stream, samples),
+        }
+    }
+}
+
+/// Estimate the size of `len` elements from the sizes of the first of them, up to `samples`.
+fn sampled(len: usize, sizes: impl Iterator<Item = usize>, samples: Option<usize>) -> usize {
+    let (sampled, total) = sizes
+        .take(samples.unwrap_or(usize::MAX))
+        .fold((0, 0), |(count, total), size| (count + 1, total + size));
+    match sampled {
+        0 => 0,
+        sampled => total * len / sampled,
+    }
+}
+
+/// A member is held twice, once in order and once as the key of its position.
+fn set_usage(set: &IndexedSet, samples: Option<usize>) -> usize {
+    let member = |member: &String| {
+        size_of::<String>() + size_of::<(String, usize)>() + HASH_ENTRY_OVERHEAD + 2 * member.len()
+    };
+    sampled(set.len(), set.iter().map(member), samples)
+}
+
+/// A member is held twice, once in score order and once as the key of its score.
+fn sorted_set_usage(zset: &SortedSet, samples: Option<usize>) -> usize {
+    let member = |(member, _): (&String, Score)| {
+        size_of::<(Score, String)>()
+            + size_of::<(String, Score)>()
+            + HASH_ENTRY_OVERHEAD
+            + 2 * member.len()
+    };
+    sampled(zset.len(), zset.iter().map(member), samples)
+}
+
+/// The entries are sampled, but consumer groups are counted in full, as there are seldom
+/// more than a few of them. A pending entry is held by its group and by its consumer.
+fn stream_usage(stream: &Stream, samples: Option<usize>) -> usize {
+    let entry = |(_, fields): (&StreamId, &Fields)| {
+        size_of::<(StreamId, Fields)>()
+            + fields
+                .iter()
+                .map(|(field, value)| size_of::<(String, String)>() + field.len() + value.len())
+                .sum::<usize>()
+    };
+    let entries = sampled(
+        stream.len(),
+        stream.after(StreamId::MIN).map(entry),
+        samples,
+    );
+    let groups: usize = stream
+        .groups()
+        .map(|(name, group)| {
+            let pending = group.pending.values().map(|pending| {
+                size_of::<(StreamId, Pending)>() + size_of::<StreamId>() + pending.consumer.len()
+            });
+            let consumers = group
+                .consumers
+                .keys()
+                .map(|consumer| size_of::<(String, Consumer)>() + consumer.len());
+            size_of::<(String, ConsumerGroup)>()
+                + name.len()
+                + pending.sum::<usize>()
+                + consumers.sum::<usize>()
+        })
+        .sum();
+    entries + groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sampled, MemoryStats, KEYSPACE_ENTRY, VALUE};
+    use crate::database::{Database, Value};
+
+    #[test]
+    fn sampling() {
+        assert_eq!(sampled(0, std::iter::empty(), Some(5)), 0);
+        // Only the first two elements are looked at.
+        assert_eq!(sampled(4, [10, 20, 1000, 1000].into_iter(), Some(2)), 60);
+        assert_eq!(sampled(4, [10, 20, 1000, 1000].into_iter(), None), 2030);
+    }
+
+    #[test]
+    fn usage_and_stats() {
+        let mut db = Database::new();
+        assert_eq!(db.memory_usage("melon", None), None);
+        assert_eq!(db.memory_stats(), MemoryStats::default());
+
+        db.set("melon".into(), Value::without_ttl("x".repeat(100)));
+        let string = db.memory_usage("melon", None).unwrap();
+        assert_eq!(string, KEYSPACE_ENTRY + 5 + VALUE + 100);
+
+        let members = (0..10).map(|member| format!("member:{member}")).collect();
+        db.sadd("fruits".into(), members).unwrap();
+        let all = db.memory_usage("fruits", None).unwrap();
+        assert!(all > KEYSPACE_ENTRY + 6 + VALUE + 10 * 2 * "member:0".len());
+        // Every member is of the same size, so sampling makes no difference.
+        assert_eq!(db.memory_usage("fruits", Some(1)), Some(all));
+
+        let stats = db.memory_stats();
+        assert_eq!(stats.keys, 2);
+        assert_eq!(stats.overhead, 2 * KEYSPACE_ENTRY);
+        assert_eq!(stats.total(), string + all);
+    }
+}
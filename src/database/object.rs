@@ -0,0 +1,121 @@
+//! # Low-level details about single values, as reported by `DEBUG OBJECT`.
+
+use super::{Data, Database};
+use crate::rdb;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+/// The longest string that Redis would allocate along with its header, see [`Data::encoding`].
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// What `DEBUG OBJECT` tells about a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    /// Where the value lives in memory.
+    pub address: usize,
+    /// How many references there are to the value, including the ones held by snapshots.
+    pub ref_count: usize,
+    pub encoding: &'static str,
+    /// How many bytes the value takes in an RDB file, without its key.
+    pub serialized_len: usize,
+    pub idle: Duration,
+}
+
+impl Display for ObjectInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Value at:{:#x} refcount:{} encoding:{} serializedlength:{} lru_seconds_idle:{}",
+            self.address,
+            self.ref_count,
+            self.encoding,
+            self.serialized_len,
+            self.idle.as_secs()
+        )
+    }
+}
+
+impl Database {
+    /// Describe the value at `key`, if there is one, see [`ObjectInfo`].
+    pub fn object_info(&self, key: &str) -> Option<ObjectInfo> {
+        let value = self.live(key)?;
+        let mut serialized = vec![];
+        rdb::write_value(&mut serialized, &value.data, false);
+        Some(ObjectInfo {
+            address: value as *const _ as usize,
+            ref_count: self.storage.ref_count(key)?,
+            encoding: value.data.encoding(),
+            serialized_len: serialized.len(),
+            idle: value.idle_time(),
+        })
+    }
+}
+
+impl Data {
+    /// The name of the encoding of this [`Data`].
+    ///
+    /// Strings are all stored the same way here, so they are named after how Redis would
+    /// store them: as an integer if they are one, and along with their header if short.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::String(bytes) if is_integer(bytes) => "int",
+            Self::String(bytes) if bytes.len() <= EMBSTR_MAX_LEN => "embstr",
+            Self::String(_) => "raw",
+            Self::Set(_) => "hashtable",
+            Self::SortedSet(zset) => zset.encoding(),
+            Self::Stream(_) => "stream",
+        }
+    }
+}
+
+/// Whether `bytes` are an integer written the way Redis would print it back.
+fn is_integer(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|string| string.parse::<i64>().ok())
+        .map_or(false, |integer| integer.to_string().as_bytes() == bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Database, Score, Value};
+
+    #[test]
+    fn encodings() {
+        let mut db = Database::new();
+        assert_eq!(db.object_info("melon"), None);
+        for (key, value, encoding) in [
+            ("int", "-42".to_string(), "int"),
+            ("padded", "042".to_string(), "embstr"),
+            ("embstr", "x".repeat(44), "embstr"),
+            ("raw", "x".repeat(45), "raw"),
+        ] {
+            db.set(key.into(), Value::without_ttl(value));
+            let info = db.object_info(key).unwrap();
+            assert_eq!(info.encoding, encoding, "{key}");
+            assert_eq!(info.ref_count, 1);
+        }
+        db.sadd("set".into(), vec!["a".into()]).unwrap();
+        assert_eq!(db.object_info("set").unwrap().encoding, "hashtable");
+
+        let zset = |members: usize| {
+            (0..members)
+                .map(|member| (Score(0.0), member.to_string()))
+                .collect()
+        };
+        db.zadd("small".into(), Default::default(), zset(1))
+            .unwrap();
+        assert_eq!(db.object_info("small").unwrap().encoding, "listpack");
+        db.zadd("big".into(), Default::default(), zset(1000))
+            .unwrap();
+        assert_eq!(db.object_info("big").unwrap().encoding, "skiplist");
+
+        let snapshot = db.snapshot();
+        let info = db.object_info("raw").unwrap();
+        assert_eq!(info.ref_count, 2);
+        // A length byte, then the bytes.
+        assert_eq!(info.serialized_len, 46);
+        assert!(info.to_string().starts_with("Value at:0x"), "{info}");
+        drop(snapshot);
+    }
+}
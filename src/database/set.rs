@@ -0,0 +1,517 @@
+//! # Set commands, operating on [`Data::Set`] values.
+
+use super::{Data, Database, Error, Key, Value};
+use crate::notify::Class;
+use crate::random::{self, Rng};
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// An unordered set of unique strings that also supports access by index,
+/// so that random members can be picked in constant time.
+///
+/// Members live in a dense [`Vec`], with a [`HashMap`] from each member to its
+/// position for constant time lookups. Removal swaps the last member into the
+/// freed slot, keeping the [`Vec`] dense.
+#[derive(Debug, Clone, Default)]
+pub struct IndexedSet {
+    members: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl IndexedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a `member`, returning whether it was not present before.
+    pub fn insert(&mut self, member: String) -> bool {
+        if self.positions.contains_key(&member) {
+            return false;
+        }
+        let _ = self.positions.insert(member.clone(), self.members.len());
+        self.members.push(member);
+        true
+    }
+
+    /// Remove a `member`, returning whether it was present.
+    pub fn remove(&mut self, member: &str) -> bool {
+        let Some(position) = self.positions.remove(member) else {
+            return false;
+        };
+        let _ = self.members.swap_remove(position);
+        if let Some(moved) = self.members.get(position) {
+            let _ = self.positions.insert(moved.clone(), position);
+        }
+        true
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        self.positions.contains_key(member)
+    }
+
+    /// Get the member at `index`, which is stable only as long as the set is not modified.
+    pub fn get(&self, index: usize) -> Option<&String> {
+        self.members.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        self.members.iter()
+    }
+}
+
+impl Eq for IndexedSet {}
+impl PartialEq for IndexedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(member))
+    }
+}
+
+impl FromIterator<String> for IndexedSet {
+    fn from_iter<I: IntoIterator<Item = String>>(members: I) -> Self {
+        let mut set = Self::new();
+        for member in members {
+            let _ = set.insert(member);
+        }
+        set
+    }
+}
+
+impl IntoIterator for IndexedSet {
+    type Item = String;
+    type IntoIter = std::vec::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.members.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a IndexedSet {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The multi-key operations of set algebra.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    /// Members present in all of the sets (`SINTER`).
+    Intersection,
+    /// Members present in any of the sets (`SUNION`).
+    Union,
+    /// Members of the first set that are not present in any of the others (`SDIFF`).
+    Difference,
+}
+
+impl Database {
+    /// Get the set stored at `key`, or [`None`] if there is no such key.
+    fn lookup_set(&self, key: &str) -> Result<Option<&IndexedSet>, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the set stored at `key` for modification, creating an empty one if needed.
+    fn lookup_set_mut_or_default(&mut self, key: Key) -> Result<&mut IndexedSet, Error> {
+        if self.live_mut(&key).is_none() {
+            let _ = self
+                .storage
+                .insert(key.clone(), Value::new(Data::Set(IndexedSet::new()), None));
+        }
+        match self.storage.get_mut(&key).map(|value| &mut value.data) {
+            Some(Data::Set(set)) => Ok(set),
+            _ => Err(Error::WrongType),
+        }
+    }
+
+    /// Add the specified members to the set stored at `key`.
+    ///
+    /// Returns the number of members that were added,
+    /// not including all the members already present in the set.
+    #[instrument(name = "db_sadd", skip(self))]
+    pub fn sadd(&mut self, key: Key, members: Vec<String>) -> Result<usize, Error> {
+        let set = self.lookup_set_mut_or_default(key.clone())?;
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        if added > 0 {
+            self.notify(Class::Set, "sadd", &key);
+        }
+        Ok(added)
+    }
+
+    /// Remove the specified members from the set stored at `key`.
+    ///
+    /// Returns the number of members that were removed. If the set
+    /// becomes empty, the key is removed from the [`Database`].
+    #[instrument(name = "db_srem", skip(self))]
+    pub fn srem(&mut self, key: &str, members: &[String]) -> Result<usize, Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Ok(0);
+        };
+        let Data::Set(set) = &mut value.data else {
+            return Err(Error::WrongType);
+        };
+        let removed = members.iter().filter(|member| set.remove(member)).count();
+        if set.is_empty() {
+            let _ = self.storage.remove(key);
+        }
+        if removed > 0 {
+            self.notify_change(Class::Set, "srem", key);
+        }
+        Ok(removed)
+    }
+
+    /// Move `member` from the set stored at `source` to the set stored at `destination`.
+    ///
+    /// Returns whether the member was moved. If `source` becomes empty, it is removed.
+    #[instrument(name = "db_smove", skip(self))]
+    pub fn smove(&mut self, source: &str, destination: Key, member: String) -> Result<bool, Error> {
+        // Check the destination type upfront, so a failure leaves the source untouched.
+        let _ = self.lookup_set(&destination)?;
+        if !self
+            .lookup_set(source)?
+            .is_some_and(|set| set.contains(&member))
+        {
+            return Ok(false);
+        }
+        if source != destination {
+            let _ = self.srem(source, std::slice::from_ref(&member))?;
+            let _ = self.sadd(destination, vec![member])?;
+        }
+        Ok(true)
+    }
+
+    /// Get all the members of the set stored at `key`.
+    #[instrument(name = "db_smembers", skip(self))]
+    pub fn smembers(&self, key: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .lookup_set(key)?
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// Check whether `member` is a member of the set stored at `key`.
+    #[instrument(name = "db_sismember", skip(self))]
+    pub fn sismember(&self, key: &str, member: &str) -> Result<bool, Error> {
+        Ok(self
+            .lookup_set(key)?
+            .is_some_and(|set| set.contains(member)))
+    }
+
+    /// Check whether each of `members` is a member of the set stored at `key`.
+    #[instrument(name = "db_smismember", skip(self))]
+    pub fn smismember(&self, key: &str, members: &[String]) -> Result<Vec<bool>, Error> {
+        let set = self.lookup_set(key)?;
+        Ok(members
+            .iter()
+            .map(|member| set.is_some_and(|set| set.contains(member)))
+            .collect())
+    }
+
+    /// Get the cardinality (number of elements) of the set stored at `key`.
+    #[instrument(name = "db_scard", skip(self))]
+    pub fn scard(&self, key: &str) -> Result<usize, Error> {
+        Ok(self.lookup_set(key)?.map_or(0, IndexedSet::len))
+    }
+
+    /// Remove and return up to `count` random members from the set stored at `key`.
+    ///
+    /// If the set becomes empty, the key is removed from the [`Database`].
+    #[instrument(name = "db_spop", skip(self))]
+    pub fn spop(&mut self, key: &str, count: usize) -> Result<Vec<String>, Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Ok(vec![]);
+        };
+        let Data::Set(set) = &mut value.data else {
+            return Err(Error::WrongType);
+        };
+        let count = i64::try_from(count).unwrap_or(i64::MAX);
+        let popped: Vec<String> = random::sample_indices(&mut Rng::new(), set.len(), count)
+            .into_iter()
+            .filter_map(|index| set.get(index).cloned())
+            .collect();
+        for member in &popped {
+            let _ = set.remove(member);
+        }
+        if set.is_empty() {
+            let _ = self.storage.remove(key);
+        }
+        if !popped.is_empty() {
+            self.notify_change(Class::Set, "spop", key);
+        }
+        Ok(popped)
+    }
+
+    /// Return random members from the set stored at `key`, without removing them.
+    ///
+    /// See [`random::sample_indices`] for the meaning of `count`.
+    #[instrument(name = "db_srandmember", skip(self))]
+    pub fn srandmember(&self, key: &str, count: i64) -> Result<Vec<String>, Error> {
+        let Some(set) = self.lookup_set(key)? else {
+            return Ok(vec![]);
+        };
+        Ok(random::sample_indices(&mut Rng::new(), set.len(), count)
+            .into_iter()
+            .filter_map(|index| set.get(index).cloned())
+            .collect())
+    }
+
+    /// Compute the result of `operation` over the sets stored at `keys`.
+    ///
+    /// Missing keys are considered to be empty sets.
+    #[instrument(name = "db_set_operation", skip(self))]
+    pub fn set_operation(
+        &self,
+        operation: SetOperation,
+        keys: &[String],
+    ) -> Result<IndexedSet, Error> {
+        let empty = IndexedSet::new();
+        let sets = keys
+            .iter()
+            .map(|key| Ok(self.lookup_set(key)?.unwrap_or(&empty)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let Some((first, others)) = sets.split_first() else {
+            return Ok(IndexedSet::new());
+        };
+        let result = match operation {
+            SetOperation::Intersection => first
+                .iter()
+                .filter(|member| others.iter().all(|set| set.contains(member)))
+                .cloned()
+                .collect(),
+            SetOperation::Union => sets.iter().flat_map(|set| set.iter()).cloned().collect(),
+            SetOperation::Difference => first
+                .iter()
+                .filter(|member| !others.iter().any(|set| set.contains(member)))
+                .cloned()
+                .collect(),
+        };
+        Ok(result)
+    }
+
+    /// Get the cardinality of the intersection of the sets stored at `keys`,
+    /// without computing the intersection itself.
+    ///
+    /// Counting stops as soon as `limit` is reached, unless it is zero.
+    #[instrument(name = "db_sintercard", skip(self))]
+    pub fn sintercard(&self, keys: &[String], limit: usize) -> Result<usize, Error> {
+        let sets = keys
+            .iter()
+            .map(|key| self.lookup_set(key))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let Some(mut sets) = sets.into_iter().collect::<Option<Vec<_>>>() else {
+            return Ok(0);
+        };
+        sets.sort_by_key(|set| set.len());
+        let Some((smallest, others)) = sets.split_first() else {
+            return Ok(0);
+        };
+        let common = smallest
+            .iter()
+            .filter(|member| others.iter().all(|set| set.contains(member)));
+        Ok(match limit {
+            0 => common.count(),
+            limit => common.take(limit).count(),
+        })
+    }
+
+    /// Compute the result of `operation` over the sets stored at `keys` and store it at `destination`.
+    ///
+    /// If `destination` already exists, it is overwritten. If the resulting set is empty,
+    /// `destination` is removed instead. Returns the cardinality of the resulting set.
+    #[instrument(name = "db_set_operation_store", skip(self))]
+    pub fn set_operation_store(
+        &mut self,
+        operation: SetOperation,
+        destination: Key,
+        keys: &[String],
+    ) -> Result<usize, Error> {
+        let result = self.set_operation(operation, keys)?;
+        let cardinality = result.len();
+        let event = match operation {
+            SetOperation::Intersection => "sinterstore",
+            SetOperation::Union => "sunionstore",
+            SetOperation::Difference => "sdiffstore",
+        };
+        let result = (!result.is_empty()).then_some(Data::Set(result));
+        self.store(destination, result, Class::Set, event);
+        Ok(cardinality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexedSet;
+    use super::SetOperation::{Difference, Intersection, Union};
+    use crate::database::{Database, Error, Value};
+
+    fn members(members: &[&str]) -> Vec<String> {
+        members.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut db = Database::new();
+        assert_eq!(db.sadd("s".into(), members(&["a", "b", "a"])), Ok(2));
+        assert_eq!(db.sadd("s".into(), members(&["b", "c"])), Ok(1));
+        assert_eq!(db.scard("s"), Ok(3));
+        assert_eq!(db.sismember("s", "c"), Ok(true));
+        assert_eq!(db.srem("s", &members(&["c", "x"])), Ok(1));
+        assert_eq!(db.sismember("s", "c"), Ok(false));
+
+        let mut all = db.smembers("s").unwrap();
+        all.sort();
+        assert_eq!(all, members(&["a", "b"]));
+    }
+
+    #[test]
+    fn empty_set_is_removed() {
+        let mut db = Database::new();
+        db.sadd("s".into(), members(&["a"])).unwrap();
+        assert_eq!(db.srem("s", &members(&["a"])), Ok(1));
+        assert_eq!(db.get("s").unwrap_err(), Error::KeyNotFound);
+        assert_eq!(db.scard("s"), Ok(0));
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        members(keys)
+    }
+
+    fn set(members: &[&str]) -> IndexedSet {
+        members.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn indexed_set_stays_dense() {
+        let mut set = set(&["a", "b", "c", "d"]);
+        assert!(set.remove("b"));
+        assert!(!set.remove("b"));
+        assert!(set.remove("d"));
+        assert_eq!(set.len(), 2);
+        assert!((0..set.len()).all(|index| set.get(index).is_some()));
+        assert!(set.contains("a") && set.contains("c"));
+        assert!(set.insert("b".into()));
+        assert_eq!(set.get(2).map(String::as_str), Some("b"));
+    }
+
+    #[test]
+    fn move_between_sets() {
+        let mut db = Database::new();
+        db.sadd("src".into(), members(&["a", "b"])).unwrap();
+        assert_eq!(db.smove("src", "dst".into(), "a".into()), Ok(true));
+        assert_eq!(db.smove("src", "dst".into(), "a".into()), Ok(false));
+        assert_eq!(db.smove("src", "src".into(), "b".into()), Ok(true));
+        assert_eq!(db.sismember("dst", "a"), Ok(true));
+
+        db.set("str".into(), Value::without_ttl("x".to_string()));
+        assert_eq!(
+            db.smove("src", "str".into(), "b".into()),
+            Err(Error::WrongType)
+        );
+        assert_eq!(db.sismember("src", "b"), Ok(true));
+
+        assert_eq!(db.smove("src", "dst".into(), "b".into()), Ok(true));
+        assert_eq!(db.get("src").unwrap_err(), Error::KeyNotFound);
+        assert_eq!(db.scard("dst"), Ok(2));
+    }
+
+    #[test]
+    fn pop_and_sample() {
+        let mut db = Database::new();
+        db.sadd("s".into(), members(&["a", "b", "c"])).unwrap();
+
+        let sampled = db.srandmember("s", 2).unwrap();
+        assert_eq!(sampled.len(), 2);
+        assert_ne!(sampled[0], sampled[1]);
+        assert_eq!(db.srandmember("s", 10).unwrap().len(), 3);
+        assert_eq!(db.srandmember("s", -10).unwrap().len(), 10);
+        assert_eq!(db.scard("s"), Ok(3));
+
+        let popped = db.spop("s", 2).unwrap();
+        assert_eq!(popped.len(), 2);
+        assert_eq!(db.scard("s"), Ok(1));
+        assert!(popped
+            .iter()
+            .all(|member| db.sismember("s", member) == Ok(false)));
+        assert_eq!(db.spop("s", 5).unwrap().len(), 1);
+        assert_eq!(db.get("s").unwrap_err(), Error::KeyNotFound);
+        assert_eq!(db.spop("s", 1), Ok(vec![]));
+    }
+
+    #[test]
+    fn algebra() {
+        let mut db = Database::new();
+        db.sadd("a".into(), members(&["1", "2", "3"])).unwrap();
+        db.sadd("b".into(), members(&["2", "3", "4"])).unwrap();
+        let ab = keys(&["a", "b"]);
+        assert_eq!(db.set_operation(Intersection, &ab), Ok(set(&["2", "3"])));
+        assert_eq!(db.set_operation(Union, &ab), Ok(set(&["1", "2", "3", "4"])));
+        assert_eq!(db.set_operation(Difference, &ab), Ok(set(&["1"])));
+        assert_eq!(
+            db.set_operation(Intersection, &keys(&["a", "missing"])),
+            Ok(set(&[]))
+        );
+        assert_eq!(
+            db.set_operation(Difference, &keys(&["a", "missing"])),
+            Ok(set(&["1", "2", "3"]))
+        );
+    }
+
+    #[test]
+    fn bulk_membership_and_intersection_cardinality() {
+        let mut db = Database::new();
+        db.sadd("a".into(), members(&["1", "2", "3", "4"])).unwrap();
+        db.sadd("b".into(), members(&["2", "3", "4", "5"])).unwrap();
+        assert_eq!(
+            db.smismember("a", &members(&["1", "5"])),
+            Ok(vec![true, false])
+        );
+        assert_eq!(db.smismember("nope", &members(&["1"])), Ok(vec![false]));
+        assert_eq!(db.sintercard(&keys(&["a", "b"]), 0), Ok(3));
+        assert_eq!(db.sintercard(&keys(&["a", "b"]), 2), Ok(2));
+        assert_eq!(db.sintercard(&keys(&["a", "missing"]), 0), Ok(0));
+    }
+
+    #[test]
+    fn algebra_store() {
+        let mut db = Database::new();
+        db.sadd("a".into(), members(&["1", "2"])).unwrap();
+        db.sadd("b".into(), members(&["2"])).unwrap();
+        db.set("dst".into(), Value::without_ttl("string".to_string()));
+        assert_eq!(
+            db.set_operation_store(Union, "dst".into(), &keys(&["a", "b"])),
+            Ok(2)
+        );
+        assert_eq!(db.scard("dst"), Ok(2));
+        assert_eq!(
+            db.set_operation_store(Difference, "dst".into(), &keys(&["b", "a"])),
+            Ok(0)
+        );
+        assert_eq!(db.get("dst").unwrap_err(), Error::KeyNotFound);
+    }
+
+    #[test]
+    fn wrong_type() {
+        let mut db = Database::new();
+        db.set("str".into(), Value::without_ttl("bar".to_string()));
+        assert_eq!(
+            db.sadd("str".into(), members(&["a"])),
+            Err(Error::WrongType)
+        );
+        assert_eq!(db.smembers("str"), Err(Error::WrongType));
+    }
+}
@@ -0,0 +1,168 @@
+//! # The key-value pairs of the [`Database`], which a [`Snapshot`] freezes cheaply.
+//!
+//! Saving the dataset in the background shouldn't keep the database locked for as long
+//! as the values take to serialize, so the values are reference-counted, and taking a
+//! [`Snapshot`] only copies the keys and bumps the counts. As long as a snapshot holds on
+//! to a value, the first write to it makes a copy for the database to go on with, leaving
+//! the snapshot as it was. Reads count too, as they update the access metadata of values.
+//!
+//! The keys are also kept in the order in which `SCAN` visits them, so that every call
+//! picks up where the last one stopped instead of sorting the whole keyspace again.
+
+use super::{Database, Key, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::ops::RangeFrom;
+use std::sync::Arc;
+use tracing::instrument;
+
+/// A [`HashMap`] of [`Key`]-[`Value`] pairs, with the values copied on write while shared.
+#[derive(Debug, Clone, Default)]
+pub struct Storage {
+    map: HashMap<Key, Arc<Value>>,
+    /// The keys by the reversed bits of their [`hash`], the order of their `SCAN` buckets.
+    scan_order: BTreeSet<(u64, Key)>,
+}
+
+impl Storage {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.map.get(key).map(Arc::as_ref)
+    }
+
+    /// Get a mutable reference to the value at `key`, copying it first if a [`Snapshot`] has it.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.map.get_mut(key).map(Arc::make_mut)
+    }
+
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Arc<Value>> {
+        let previous = self.map.insert(key.clone(), Arc::new(value));
+        if previous.is_none() {
+            self.scan_order.insert((hash(&key).reverse_bits(), key));
+        }
+        previous
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Arc<Value>> {
+        let removed = self.map.remove(key)?;
+        self.scan_order
+            .remove(&(hash(key).reverse_bits(), key.to_owned()));
+        Some(removed)
+    }
+
+    /// How many references there are to the value at `key`, the [`Snapshot`]s' included.
+    pub fn ref_count(&self, key: &str) -> Option<usize> {
+        self.map.get(key).map(Arc::strong_count)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.map.iter().map(|(key, value)| (key, value.as_ref()))
+    }
+
+    /// The keys whose reversed [`hash`] is at least `from`, in that order, along with it.
+    pub fn scan_order(&self, from: u64) -> impl Iterator<Item = (u64, &Key)> {
+        let range: RangeFrom<(u64, Key)> = (from, Key::new())..;
+        self.scan_order
+            .range(range)
+            .map(|(order, key)| (*order, key))
+    }
+}
+
+impl Extend<(Key, Value)> for Storage {
+    fn extend<T: IntoIterator<Item = (Key, Value)>>(&mut self, entries: T) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Hash a [`Key`] deterministically, so that `SCAN` cursors stay valid between calls.
+pub fn hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The live [`Key`]-[`Value`] pairs of the [`Database`] as of when it was taken,
+/// no matter what happens to the database since, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    entries: Vec<(Key, Arc<Value>)>,
+}
+
+impl Snapshot {
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key, value.as_ref()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl Database {
+    /// Take a [`Snapshot`] of the keys that have not expired yet.
+    #[instrument(name = "db_snapshot", skip(self))]
+    pub fn snapshot(&self) -> Snapshot {
+        let entries = self
+            .storage
+            .map
+            .iter()
+            .filter(|(_, value)| !value.is_expired())
+            .map(|(key, value)| (key.clone(), Arc::clone(value)))
+            .collect();
+        Snapshot { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Data, Database, Value};
+
+    #[test]
+    fn snapshots_stay_frozen() {
+        let mut db = Database::new();
+        db.set("kept".into(), Value::without_ttl("1".into()));
+        db.set("changed".into(), Value::without_ttl("2".into()));
+        let _ = db.sadd("grown".into(), vec!["a".into()]).unwrap();
+        let _ = db.sadd("removed".into(), vec!["a".into()]).unwrap();
+        let snapshot = db.snapshot();
+
+        db.set("changed".into(), Value::without_ttl("two".into()));
+        db.set("added".into(), Value::without_ttl("3".into()));
+        let _ = db.sadd("grown".into(), vec!["b".into()]).unwrap();
+        let _ = db.srem("removed", &["a".into()]).unwrap();
+        assert_eq!(db.iter().count(), 4);
+        assert_eq!(db.get("changed").unwrap().data, Data::String("two".into()));
+
+        let mut frozen: Vec<_> = snapshot
+            .iter()
+            .map(|(key, value)| match &value.data {
+                Data::String(string) => (key.as_str(), String::from_utf8_lossy(string).into()),
+                Data::Set(set) => (key.as_str(), format!("{} member(s)", set.len())),
+                data => panic!("Unexpected {data:?}"),
+            })
+            .collect();
+        frozen.sort();
+        assert_eq!(
+            frozen,
+            [
+                ("changed", "2".to_string()),
+                ("grown", "1 member(s)".to_string()),
+                ("kept", "1".to_string()),
+                ("removed", "1 member(s)".to_string()),
+            ]
+        );
+        assert_eq!(snapshot.len(), 4);
+    }
+}
@@ -0,0 +1,930 @@
+//! # Stream commands: `XADD` and friends, operating on [`Data::Stream`] values.
+
+mod group;
+mod info;
+
+pub use group::{AutoClaim, AutoClaimOptions, Consumer, ConsumerGroup, Delivered, Pending};
+pub use group::{PendingEntry, PendingRange, PendingSummary, ReadGroupFrom, XClaimOptions};
+pub use info::{ConsumerInfo, GroupInfo, StreamInfo};
+
+use super::{Data, Database, Error, Key, Value};
+use crate::notify::Class;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::instrument;
+
+/// The ID of a [`Stream`] entry: a millisecond timestamp and a sequence
+/// number that tells apart the entries added during the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+
+    /// The smallest ID that is greater than this one, if any.
+    pub fn successor(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { seq, ..self }),
+            None => Some(Self {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    /// The greatest ID that is smaller than this one, if any.
+    pub fn predecessor(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self { seq, ..self }),
+            None => Some(Self {
+                ms: self.ms.checked_sub(1)?,
+                seq: u64::MAX,
+            }),
+        }
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = Error;
+
+    /// Parse an ID like `1526919030474-55`, or like `1526919030474`, meaning a sequence number of `0`.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = string.split_once('-').unwrap_or((string, "0"));
+        match (ms.parse(), seq.parse()) {
+            (Ok(ms), Ok(seq)) => Ok(Self { ms, seq }),
+            _ => Err(Error::InvalidStreamId),
+        }
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The ID that `XADD` should give to a new entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdSpec {
+    /// `*`: generate the whole ID from the current time.
+    Auto,
+    /// `<ms>-*`: use the given timestamp, and generate the sequence number.
+    AutoSequence(u64),
+    /// `<ms>-<seq>`: use exactly this ID.
+    Explicit(StreamId),
+}
+
+impl FromStr for IdSpec {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string == "*" {
+            return Ok(Self::Auto);
+        }
+        match string.strip_suffix("-*") {
+            Some(ms) => ms
+                .parse()
+                .map(Self::AutoSequence)
+                .map_err(|_| Error::InvalidStreamId),
+            None => string.parse().map(Self::Explicit),
+        }
+    }
+}
+
+/// The field-value pairs of a single [`Stream`] entry.
+pub type Fields = Vec<(String, String)>;
+
+/// A single [`Stream`] entry, as returned by the commands that read streams.
+pub type Entry = (StreamId, Fields);
+
+/// One end of an `XRANGE` interval: `-`, `+`, an ID whose sequence number may be
+/// left out, or such an ID preceded by `(` to exclude it from the interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamBound {
+    ms: u64,
+    seq: Option<u64>,
+    exclusive: bool,
+}
+
+impl StreamBound {
+    /// The first ID within an interval that starts at this bound, if any.
+    fn as_start(self) -> Option<StreamId> {
+        let id = StreamId {
+            ms: self.ms,
+            seq: self.seq.unwrap_or(0),
+        };
+        if self.exclusive {
+            id.successor()
+        } else {
+            Some(id)
+        }
+    }
+
+    /// The last ID within an interval that ends at this bound, if any.
+    fn as_end(self) -> Option<StreamId> {
+        let id = StreamId {
+            ms: self.ms,
+            seq: self.seq.unwrap_or(u64::MAX),
+        };
+        if self.exclusive {
+            id.predecessor()
+        } else {
+            Some(id)
+        }
+    }
+}
+
+impl FromStr for StreamBound {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (id, exclusive) = match string.strip_prefix('(') {
+            Some(id) => (id, true),
+            None => (string, false),
+        };
+        let (ms, seq) = match (id, id.split_once('-')) {
+            ("-", _) if !exclusive => (Ok(0), Some(Ok(0))),
+            ("+", _) if !exclusive => (Ok(u64::MAX), Some(Ok(u64::MAX))),
+            (_, Some((ms, seq))) => (ms.parse(), Some(seq.parse())),
+            (ms, None) => (ms.parse(), None),
+        };
+        let (Ok(ms), Ok(seq)) = (ms, seq.transpose()) else {
+            return Err(Error::InvalidStreamId);
+        };
+        Ok(Self { ms, seq, exclusive })
+    }
+}
+
+/// Where `XREAD` starts reading a stream from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadFrom {
+    /// Read the entries with IDs greater than this one.
+    After(StreamId),
+    /// `$`: read only the entries added after the call.
+    New,
+}
+
+impl FromStr for ReadFrom {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "$" => Ok(Self::New),
+            id => id.parse().map(Self::After),
+        }
+    }
+}
+
+/// Which entries trimming evicts from a [`Stream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// `MAXLEN`: the oldest entries, until at most this many remain.
+    MaxLen(usize),
+    /// `MINID`: the entries with IDs lower than this one.
+    MinId(StreamId),
+}
+
+/// How to trim a [`Stream`], as given to `XADD` (and later `XTRIM`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trim {
+    pub strategy: TrimStrategy,
+    /// `~`: trimming may stop early. This implementation always trims exactly,
+    /// which is allowed, but honors the `limit` that only applies in this mode.
+    pub approximate: bool,
+    /// `LIMIT`: evict at most this many entries, `0` meaning no limit.
+    pub limit: usize,
+}
+
+/// Flags that alter the behaviour of `XADD`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XAddOptions {
+    /// `NOMKSTREAM`: do not create the stream if it does not exist.
+    pub no_make_stream: bool,
+    pub trim: Option<Trim>,
+}
+
+/// An append-only log of entries, each being a list of field-value pairs
+/// identified by an ever-growing [`StreamId`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// The ID of the last entry ever added, which stays even if that entry is removed.
+    last_id: StreamId,
+    /// How many entries were ever added, including the ones removed since.
+    entries_added: u64,
+    /// The highest ID of all the removed entries.
+    max_deleted_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a stream back together from what an RDB file holds of it.
+    pub fn restored(
+        entries: BTreeMap<StreamId, Fields>,
+        last_id: StreamId,
+        entries_added: u64,
+        max_deleted_id: StreamId,
+        groups: BTreeMap<String, ConsumerGroup>,
+    ) -> Self {
+        Self {
+            entries,
+            last_id,
+            entries_added,
+            max_deleted_id,
+            groups,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The ID of the last entry ever added, even if that entry has been removed since.
+    pub const fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// How many entries were ever added, including the ones removed since.
+    pub const fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// The highest ID of all the removed entries.
+    pub const fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    /// Work out the ID that `spec` stands for, given that it is now `now_ms`.
+    ///
+    /// Fails if the ID would not be greater than [`Stream::last_id`].
+    pub fn next_id(&self, spec: IdSpec, now_ms: u64) -> Result<StreamId, Error> {
+        let last = self.last_id;
+        let id = match spec {
+            IdSpec::Auto if now_ms > last.ms => StreamId { ms: now_ms, seq: 0 },
+            IdSpec::Auto => last.successor().ok_or(Error::StreamIdExhausted)?,
+            IdSpec::AutoSequence(ms) if ms == last.ms => {
+                let seq = last.seq.checked_add(1).ok_or(Error::StreamIdTooSmall)?;
+                StreamId { ms, seq }
+            }
+            IdSpec::AutoSequence(ms) => StreamId { ms, seq: 0 },
+            IdSpec::Explicit(id) => id,
+        };
+        if id <= last {
+            return Err(Error::StreamIdTooSmall);
+        }
+        Ok(id)
+    }
+
+    /// Append an entry with the given `id`, which must come from [`Stream::next_id`].
+    pub fn append(&mut self, id: StreamId, fields: Fields) {
+        let _ = self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Iterate over the entries with IDs between `start` and `end`, inclusive.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        // `BTreeMap::range` panics on inverted ranges, which users can ask for.
+        let end = if start <= end {
+            Bound::Included(end)
+        } else {
+            Bound::Excluded(start)
+        };
+        self.entries.range((Bound::Included(start), end))
+    }
+
+    /// Iterate over the consumer groups, by name.
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    /// Iterate over the entries with IDs greater than `id`.
+    pub fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.range((Bound::Excluded(id), Bound::Unbounded))
+    }
+
+    /// Remove the entry with the given `id`, returning whether there was one.
+    ///
+    /// [`Stream::last_id`] stays the same, so IDs are never reused.
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        let removed = self.entries.remove(&id).is_some();
+        if removed {
+            self.max_deleted_id = self.max_deleted_id.max(id);
+        }
+        removed
+    }
+
+    /// Evict entries as described by `trim`, returning how many were evicted.
+    pub fn trim(&mut self, trim: Trim) -> usize {
+        let limit = match trim.limit {
+            0 => usize::MAX,
+            limit => limit,
+        };
+        let excess = match trim.strategy {
+            TrimStrategy::MaxLen(max) => self.len().saturating_sub(max),
+            TrimStrategy::MinId(min) => self.entries.range(..min).count(),
+        };
+        let evicted = excess.min(limit);
+        for _ in 0..evicted {
+            if let Some((id, _)) = self.entries.pop_first() {
+                self.max_deleted_id = self.max_deleted_id.max(id);
+            }
+        }
+        evicted
+    }
+}
+
+/// The current wall-clock time, in milliseconds since the Unix epoch.
+fn unix_millis_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+impl Database {
+    /// Get the stream stored at `key`, or [`None`] if there is no such key.
+    fn lookup_stream(&self, key: &str) -> Result<Option<&Stream>, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Append an entry with the given `fields` to the stream stored at `key`, creating
+    /// the stream if needed (unless [`XAddOptions::no_make_stream`]), then trim it.
+    ///
+    /// Returns the ID of the new entry, or [`None`] if the stream was not created.
+    #[instrument(name = "db_xadd", skip(self))]
+    pub fn xadd(
+        &mut self,
+        key: Key,
+        id: IdSpec,
+        fields: Fields,
+        options: XAddOptions,
+    ) -> Result<Option<StreamId>, Error> {
+        if id == IdSpec::Explicit(StreamId::MIN) {
+            return Err(Error::StreamIdZero);
+        }
+        if self.live_mut(&key).is_none() {
+            if options.no_make_stream {
+                return Ok(None);
+            }
+            let _ = self
+                .storage
+                .insert(key.clone(), Value::new(Data::Stream(Stream::new()), None));
+        }
+        let Some(Data::Stream(stream)) = self.storage.get_mut(&key).map(|value| &mut value.data)
+        else {
+            return Err(Error::WrongType);
+        };
+        let id = stream.next_id(id, unix_millis_now())?;
+        stream.append(id, fields);
+        let trimmed = options.trim.map_or(0, |trim| stream.trim(trim));
+        self.notify(Class::Stream, "xadd", &key);
+        if trimmed > 0 {
+            self.notify(Class::Stream, "xtrim", &key);
+        }
+        Ok(Some(id))
+    }
+
+    /// Get the number of entries in the stream stored at `key`.
+    #[instrument(name = "db_xlen", skip(self))]
+    pub fn xlen(&self, key: &str) -> Result<usize, Error> {
+        Ok(self.lookup_stream(key)?.map_or(0, Stream::len))
+    }
+
+    /// Remove the entries with the given `ids` from the stream stored at `key`.
+    ///
+    /// Returns the number of entries removed. The stream is kept even if it is left empty.
+    #[instrument(name = "db_xdel", skip(self))]
+    pub fn xdel(&mut self, key: &str, ids: &[StreamId]) -> Result<usize, Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Ok(0);
+        };
+        let Data::Stream(stream) = &mut value.data else {
+            return Err(Error::WrongType);
+        };
+        let removed = ids.iter().filter(|&&id| stream.remove(id)).count();
+        if removed > 0 {
+            self.notify(Class::Stream, "xdel", key);
+        }
+        Ok(removed)
+    }
+
+    /// Trim the stream stored at `key` as described by `trim`, see [`Stream::trim`].
+    ///
+    /// Returns the number of entries evicted.
+    #[instrument(name = "db_xtrim", skip(self))]
+    pub fn xtrim(&mut self, key: &str, trim: Trim) -> Result<usize, Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Ok(0);
+        };
+        let Data::Stream(stream) = &mut value.data else {
+            return Err(Error::WrongType);
+        };
+        let trimmed = stream.trim(trim);
+        if trimmed > 0 {
+            self.notify(Class::Stream, "xtrim", key);
+        }
+        Ok(trimmed)
+    }
+
+    /// Set the ID of the last entry ever added to the stream stored at `key`, and optionally
+    /// how many entries were ever added and the highest ID of the removed ones (`XSETID`).
+    ///
+    /// The last ID may go back, but never below the ID of an entry that is still there.
+    #[instrument(name = "db_xsetid", skip(self))]
+    pub fn xsetid(
+        &mut self,
+        key: &str,
+        last_id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    ) -> Result<(), Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Err(Error::NoSuchKey);
+        };
+        let Data::Stream(stream) = &mut value.data else {
+            return Err(Error::WrongType);
+        };
+        if max_deleted_id.map_or(false, |id| id > last_id) {
+            return Err(Error::MaxDeletedIdTooLarge);
+        }
+        if entries_added.map_or(false, |added| added < stream.len() as u64) {
+            return Err(Error::EntriesAddedTooSmall);
+        }
+        if stream
+            .entries
+            .keys()
+            .next_back()
+            .map_or(false, |&top| top > last_id)
+        {
+            return Err(Error::SetIdTooSmall);
+        }
+        stream.last_id = last_id;
+        stream.entries_added = entries_added.unwrap_or(stream.entries_added);
+        stream.max_deleted_id = max_deleted_id.unwrap_or(stream.max_deleted_id);
+        self.notify(Class::Stream, "xsetid", key);
+        Ok(())
+    }
+
+    /// Get up to `count` entries of the stream stored at `key` with IDs between `start` and
+    /// `end`, in ascending order of their IDs, or descending if `reverse` (`XREVRANGE`).
+    #[instrument(name = "db_xrange", skip(self))]
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: StreamBound,
+        end: StreamBound,
+        count: Option<usize>,
+        reverse: bool,
+    ) -> Result<Vec<Entry>, Error> {
+        let Some(stream) = self.lookup_stream(key)? else {
+            return Ok(vec![]);
+        };
+        let (Some(start), Some(end)) = (start.as_start(), end.as_end()) else {
+            return Ok(vec![]);
+        };
+        let entries = stream.range(start, end);
+        let entries: Box<dyn Iterator<Item = _>> = if reverse {
+            Box::new(entries.rev())
+        } else {
+            Box::new(entries)
+        };
+        Ok(entries
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect())
+    }
+
+    /// Work out the ID after which `XREAD` reads each of the `streams`.
+    ///
+    /// `$` stands for the ID of the last entry added to the stream so far (`0-0` if there
+    /// is no such stream), so it has to be resolved once, when the call comes in.
+    #[instrument(name = "db_xread_resolve", skip(self))]
+    pub fn xread_resolve(
+        &self,
+        streams: Vec<(Key, ReadFrom)>,
+    ) -> Result<Vec<(Key, StreamId)>, Error> {
+        streams
+            .into_iter()
+            .map(|(key, from)| {
+                let id = match from {
+                    ReadFrom::After(id) => id,
+                    ReadFrom::New => self
+                        .lookup_stream(&key)?
+                        .map_or(StreamId::MIN, Stream::last_id),
+                };
+                Ok((key, id))
+            })
+            .collect()
+    }
+
+    /// Get up to `count` entries from each of the `streams`, with IDs greater than the given one.
+    ///
+    /// Streams with no such entries are left out, as well as the ones that do not exist.
+    #[instrument(name = "db_xread", skip(self))]
+    pub fn xread(
+        &self,
+        streams: &[(Key, StreamId)],
+        count: Option<usize>,
+    ) -> Result<Vec<(Key, Vec<Entry>)>, Error> {
+        let mut read = vec![];
+        for (key, after) in streams {
+            let Some(stream) = self.lookup_stream(key)? else {
+                continue;
+            };
+            let entries: Vec<_> = stream
+                .after(*after)
+                .take(count.unwrap_or(usize::MAX))
+                .map(|(id, fields)| (*id, fields.clone()))
+                .collect();
+            if !entries.is_empty() {
+                read.push((key.clone(), entries));
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdSpec, ReadFrom, Stream, StreamBound, StreamId, Trim, TrimStrategy, XAddOptions};
+    use crate::database::{Data, Database, Error, Value};
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_ids() {
+        assert_eq!("5-3".parse(), Ok(id(5, 3)));
+        assert_eq!("5".parse(), Ok(id(5, 0)));
+        assert_eq!("5-x".parse::<StreamId>(), Err(Error::InvalidStreamId));
+        assert_eq!("-1".parse::<StreamId>(), Err(Error::InvalidStreamId));
+        assert_eq!("*".parse(), Ok(IdSpec::Auto));
+        assert_eq!("7-*".parse(), Ok(IdSpec::AutoSequence(7)));
+        assert_eq!("7-3".parse(), Ok(IdSpec::Explicit(id(7, 3))));
+        assert_eq!("x-*".parse::<IdSpec>(), Err(Error::InvalidStreamId));
+        assert_eq!(id(1526919030474, 55).to_string(), "1526919030474-55");
+    }
+
+    #[test]
+    fn generated_ids_are_monotonic() {
+        let mut stream = Stream::new();
+        // Nothing precedes `0-1`, since `0-0` is not a valid ID.
+        assert_eq!(stream.next_id(IdSpec::AutoSequence(0), 100), Ok(id(0, 1)));
+        assert_eq!(stream.next_id(IdSpec::Auto, 100), Ok(id(100, 0)));
+        stream.append(id(100, 0), fields(&[("a", "1")]));
+
+        // The clock going backwards must not make IDs go backwards.
+        assert_eq!(stream.next_id(IdSpec::Auto, 90), Ok(id(100, 1)));
+        assert_eq!(
+            stream.next_id(IdSpec::AutoSequence(100), 90),
+            Ok(id(100, 1))
+        );
+        assert_eq!(
+            stream.next_id(IdSpec::AutoSequence(101), 90),
+            Ok(id(101, 0))
+        );
+        assert_eq!(
+            stream.next_id(IdSpec::AutoSequence(99), 90),
+            Err(Error::StreamIdTooSmall)
+        );
+        assert_eq!(
+            stream.next_id(IdSpec::Explicit(id(100, 0)), 90),
+            Err(Error::StreamIdTooSmall)
+        );
+
+        stream.append(id(u64::MAX, u64::MAX), fields(&[("b", "2")]));
+        assert_eq!(
+            stream.next_id(IdSpec::Auto, 90),
+            Err(Error::StreamIdExhausted)
+        );
+    }
+
+    #[test]
+    fn trimming() {
+        let mut stream = Stream::new();
+        for ms in 1..=10 {
+            stream.append(id(ms, 0), fields(&[("n", "x")]));
+        }
+        let limited = Trim {
+            strategy: TrimStrategy::MaxLen(2),
+            approximate: true,
+            limit: 3,
+        };
+        assert_eq!(stream.trim(limited), 3);
+        assert_eq!(stream.len(), 7);
+        let min_id = Trim {
+            strategy: TrimStrategy::MinId(id(6, 0)),
+            approximate: false,
+            limit: 0,
+        };
+        assert_eq!(stream.trim(min_id), 2);
+        assert_eq!(stream.entries.keys().next().copied(), Some(id(6, 0)));
+        assert_eq!(stream.last_id, id(10, 0));
+    }
+
+    #[test]
+    fn xadd() {
+        let mut db = Database::new();
+        let nomkstream = XAddOptions {
+            no_make_stream: true,
+            trim: None,
+        };
+        let entry = fields(&[("temperature", "36")]);
+        assert_eq!(
+            db.xadd("s".into(), IdSpec::Auto, entry.clone(), nomkstream),
+            Ok(None)
+        );
+        assert_eq!(
+            db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(0, 0)),
+                entry.clone(),
+                nomkstream
+            ),
+            Err(Error::StreamIdZero)
+        );
+        assert!(db.get("s").is_err());
+
+        let options = XAddOptions::default();
+        assert_eq!(
+            db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(1, 1)),
+                entry.clone(),
+                options
+            ),
+            Ok(Some(id(1, 1)))
+        );
+        assert_eq!(
+            db.xadd(
+                "s".into(),
+                IdSpec::AutoSequence(1),
+                entry.clone(),
+                nomkstream
+            ),
+            Ok(Some(id(1, 2)))
+        );
+        assert_eq!(
+            db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(1, 2)),
+                entry.clone(),
+                options
+            ),
+            Err(Error::StreamIdTooSmall)
+        );
+        assert_eq!(db.get("s").unwrap().data.type_name(), "stream");
+
+        let _ = db.sadd("set".into(), vec!["a".into()]);
+        assert_eq!(
+            db.xadd("set".into(), IdSpec::Auto, entry, options),
+            Err(Error::WrongType)
+        );
+    }
+
+    #[test]
+    fn xlen_xdel_xtrim() {
+        let mut db = Database::new();
+        for ms in 1..=5 {
+            let _ = db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(ms, 0)),
+                fields(&[("n", "x")]),
+                XAddOptions::default(),
+            );
+        }
+        assert_eq!(db.xlen("s"), Ok(5));
+        assert_eq!(db.xlen("missing"), Ok(0));
+        assert_eq!(db.xdel("s", &[id(2, 0), id(2, 0), id(9, 0)]), Ok(1));
+        assert_eq!(db.xdel("missing", &[id(1, 0)]), Ok(0));
+        assert_eq!(db.xlen("s"), Ok(4));
+
+        let min_id = Trim {
+            strategy: TrimStrategy::MinId(id(4, 0)),
+            approximate: false,
+            limit: 0,
+        };
+        assert_eq!(db.xtrim("s", min_id), Ok(2));
+        assert_eq!(db.xtrim("missing", min_id), Ok(0));
+        let everything = Trim {
+            strategy: TrimStrategy::MaxLen(0),
+            approximate: false,
+            limit: 0,
+        };
+        assert_eq!(db.xtrim("s", everything), Ok(2));
+        assert_eq!(db.xlen("s"), Ok(0));
+        // Neither deleting nor trimming lets IDs get reused.
+        assert_eq!(
+            db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(5, 0)),
+                fields(&[("n", "x")]),
+                XAddOptions::default()
+            ),
+            Err(Error::StreamIdTooSmall)
+        );
+
+        db.set("string".into(), Value::without_ttl("x".to_string()));
+        assert_eq!(db.xlen("string"), Err(Error::WrongType));
+        assert_eq!(db.xdel("string", &[id(1, 0)]), Err(Error::WrongType));
+    }
+
+    #[test]
+    fn xsetid() {
+        let mut db = Database::new();
+        for ms in 1..=3 {
+            let _ = db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(ms, 0)),
+                fields(&[("n", "x")]),
+                XAddOptions::default(),
+            );
+        }
+        assert_eq!(
+            db.xsetid("s", id(2, 0), None, None),
+            Err(Error::SetIdTooSmall)
+        );
+        assert_eq!(
+            db.xsetid("s", id(9, 0), Some(2), None),
+            Err(Error::EntriesAddedTooSmall)
+        );
+        assert_eq!(
+            db.xsetid("s", id(9, 0), None, Some(id(10, 0))),
+            Err(Error::MaxDeletedIdTooLarge)
+        );
+        assert_eq!(db.xsetid("s", id(9, 0), Some(7), Some(id(8, 0))), Ok(()));
+        let Data::Stream(stream) = &db.get("s").unwrap().data else {
+            panic!("Expected a stream");
+        };
+        assert_eq!(
+            (
+                stream.last_id(),
+                stream.entries_added(),
+                stream.max_deleted_id()
+            ),
+            (id(9, 0), 7, id(8, 0))
+        );
+        assert_eq!(
+            db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(9, 0)),
+                fields(&[("n", "x")]),
+                XAddOptions::default()
+            ),
+            Err(Error::StreamIdTooSmall)
+        );
+        // Back down to the top entry, which is allowed.
+        assert_eq!(db.xsetid("s", id(3, 0), None, Some(id(0, 0))), Ok(()));
+        assert_eq!(
+            db.xsetid("missing", id(1, 0), None, None),
+            Err(Error::NoSuchKey)
+        );
+        db.set("string".into(), Value::without_ttl("x".to_string()));
+        assert_eq!(
+            db.xsetid("string", id(1, 0), None, None),
+            Err(Error::WrongType)
+        );
+    }
+
+    #[test]
+    fn parse_bounds() {
+        let bound = |string: &str| string.parse::<StreamBound>();
+        assert_eq!(bound("-").unwrap().as_start(), Some(id(0, 0)));
+        assert_eq!(bound("+").unwrap().as_end(), Some(id(u64::MAX, u64::MAX)));
+        assert_eq!(bound("5").unwrap().as_start(), Some(id(5, 0)));
+        assert_eq!(bound("5").unwrap().as_end(), Some(id(5, u64::MAX)));
+        assert_eq!(bound("(5-3").unwrap().as_start(), Some(id(5, 4)));
+        assert_eq!(bound("(5-0").unwrap().as_end(), Some(id(4, u64::MAX)));
+        assert_eq!(bound("(0-0").unwrap().as_end(), None);
+        assert_eq!(bound("(-"), Err(Error::InvalidStreamId));
+        assert_eq!(bound("5-"), Err(Error::InvalidStreamId));
+        assert_eq!(bound("x"), Err(Error::InvalidStreamId));
+    }
+
+    #[test]
+    fn xrange() {
+        let mut db = Database::new();
+        for (ms, seq) in [(1, 0), (1, 1), (2, 0), (3, 5)] {
+            let entry = fields(&[("n", &format!("{ms}-{seq}"))]);
+            let _ = db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(ms, seq)),
+                entry,
+                XAddOptions::default(),
+            );
+        }
+        const NONE: [&str; 0] = [];
+        let bound = |string: &str| string.parse::<StreamBound>().unwrap();
+        let ids = |entries: Result<Vec<(StreamId, _)>, _>| {
+            entries
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(db.xrange("s", bound("-"), bound("+"), None, false)),
+            ["1-0", "1-1", "2-0", "3-5"]
+        );
+        assert_eq!(
+            ids(db.xrange("s", bound("1"), bound("2"), None, false)),
+            ["1-0", "1-1", "2-0"]
+        );
+        assert_eq!(
+            ids(db.xrange("s", bound("(1-0"), bound("(3-5"), None, false)),
+            ["1-1", "2-0"]
+        );
+        assert_eq!(
+            ids(db.xrange("s", bound("-"), bound("+"), Some(2), true)),
+            ["3-5", "2-0"]
+        );
+        assert_eq!(
+            ids(db.xrange("s", bound("3"), bound("1"), None, false)),
+            NONE
+        );
+        assert_eq!(
+            ids(db.xrange("s", bound("-"), bound("+"), Some(0), false)),
+            NONE
+        );
+        assert_eq!(
+            ids(db.xrange("missing", bound("-"), bound("+"), None, false)),
+            NONE
+        );
+
+        let entries = db.xrange("s", bound("3-5"), bound("3-5"), None, false);
+        assert_eq!(entries, Ok(vec![(id(3, 5), fields(&[("n", "3-5")]))]));
+    }
+
+    #[test]
+    fn xread() {
+        let mut db = Database::new();
+        for (key, ms) in [("a", 1), ("a", 2), ("a", 3), ("b", 7)] {
+            let entry = fields(&[("n", &ms.to_string())]);
+            let _ = db.xadd(
+                key.into(),
+                IdSpec::Explicit(id(ms, 0)),
+                entry,
+                XAddOptions::default(),
+            );
+        }
+        let _ = db.sadd("set".into(), vec!["x".into()]);
+
+        let streams = vec![
+            ("a".to_string(), ReadFrom::After(id(1, 0))),
+            ("b".to_string(), ReadFrom::New),
+            ("missing".to_string(), ReadFrom::New),
+        ];
+        let resolved = db.xread_resolve(streams).unwrap();
+        assert_eq!(
+            resolved,
+            [
+                ("a".to_string(), id(1, 0)),
+                ("b".to_string(), id(7, 0)),
+                ("missing".to_string(), id(0, 0)),
+            ]
+        );
+        assert_eq!(
+            db.xread(&resolved, Some(1)),
+            Ok(vec![(
+                "a".to_string(),
+                vec![(id(2, 0), fields(&[("n", "2")]))]
+            )])
+        );
+        let read = db.xread(&resolved, None).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].1.len(), 2);
+
+        let resolved = [("b".to_string(), id(0, 0)), ("set".to_string(), id(0, 0))];
+        assert_eq!(db.xread(&resolved, None), Err(Error::WrongType));
+        assert_eq!("$".parse(), Ok(ReadFrom::New));
+        assert_eq!("5".parse(), Ok(ReadFrom::After(id(5, 0))));
+    }
+}
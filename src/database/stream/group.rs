@@ -0,0 +1,999 @@
+//! # Stream consumer groups: `XGROUP`, `XREADGROUP`, `XACK` and the recovery commands.
+//!
+//! A consumer group remembers the last entry that it delivered to any of its
+//! consumers, so that every new entry goes to exactly one of them. Delivered
+//! entries stay in the *pending entries list* (PEL) of the group, along with the
+//! consumer that owns them, until that consumer acknowledges them, so that they
+//! can be read again after a crash. Entries that stay pending for too long, say
+//! because their consumer is gone for good, can be inspected with `XPENDING`
+//! and handed over to another consumer with `XCLAIM` or `XAUTOCLAIM`.
+
+use super::{unix_millis_now, Entry, Fields, ReadFrom, Stream, StreamBound, StreamId};
+use crate::database::{Data, Database, Error, Key, Value};
+use crate::notify::Class;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::str::FromStr;
+use tracing::instrument;
+
+/// An entry delivered to a consumer, whose fields are [`None`] if it has been deleted since.
+pub type Delivered = (StreamId, Option<Fields>);
+
+/// Which entries `XREADGROUP` reads from a [`Stream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadGroupFrom {
+    /// `>`: the entries never delivered to any consumer of the group.
+    New,
+    /// The entries pending for the consumer with IDs greater than this one.
+    Pending(StreamId),
+}
+
+impl FromStr for ReadGroupFrom {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            ">" => Ok(Self::New),
+            id => id.parse().map(Self::Pending),
+        }
+    }
+}
+
+/// An entry that was delivered to a consumer, but not acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    /// The consumer that owns the entry.
+    pub consumer: String,
+    /// When the entry was last delivered, in milliseconds since the Unix epoch.
+    pub delivered_at: u64,
+    /// How many times the entry has been delivered.
+    pub deliveries: u64,
+}
+
+/// The overview of a PEL given by `XPENDING` without a range.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PendingSummary {
+    pub count: usize,
+    /// The lowest and the highest pending IDs, if any.
+    pub bounds: Option<(StreamId, StreamId)>,
+    /// Every consumer with pending entries, along with their number.
+    pub consumers: Vec<(String, usize)>,
+}
+
+/// Which pending entries `XPENDING` lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRange {
+    /// `IDLE`: only the entries delivered at least this many milliseconds ago.
+    pub min_idle: u64,
+    pub start: StreamBound,
+    pub end: StreamBound,
+    pub count: usize,
+    /// Only the entries pending for this consumer.
+    pub consumer: Option<String>,
+}
+
+/// A pending entry as listed by `XPENDING`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub id: StreamId,
+    pub consumer: String,
+    /// How many milliseconds ago the entry was last delivered.
+    pub idle: u64,
+    pub deliveries: u64,
+}
+
+/// Flags that alter the behaviour of `XCLAIM`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XClaimOptions {
+    /// `IDLE`: set the idle time of the claimed entries to this many milliseconds.
+    pub idle: Option<u64>,
+    /// `TIME`: set the delivery time of the claimed entries to this Unix time in milliseconds.
+    pub time: Option<u64>,
+    /// `RETRYCOUNT`: set the delivery count of the claimed entries.
+    pub retry_count: Option<u64>,
+    /// `FORCE`: claim entries that are not pending, as long as they exist.
+    pub force: bool,
+    /// `JUSTID`: reply with just the IDs, without counting the claim as a delivery.
+    pub just_id: bool,
+    /// `LASTID`: move the last delivered ID of the group forward to this one.
+    pub last_id: Option<StreamId>,
+}
+
+/// Flags that alter the behaviour of `XAUTOCLAIM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoClaimOptions {
+    /// `COUNT`: the most entries to claim.
+    pub count: usize,
+    /// `JUSTID`: reply with just the IDs, without counting the claim as a delivery.
+    pub just_id: bool,
+}
+
+impl Default for AutoClaimOptions {
+    fn default() -> Self {
+        Self {
+            count: 100,
+            just_id: false,
+        }
+    }
+}
+
+/// The outcome of `XAUTOCLAIM`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AutoClaim {
+    /// Where to continue scanning the PEL from, `0-0` once the scan is complete.
+    pub next: StreamId,
+    pub claimed: Vec<Entry>,
+    /// The IDs of pending entries that no longer exist, and were dropped from the PEL.
+    pub deleted: Vec<StreamId>,
+}
+
+/// A consumer of a [`ConsumerGroup`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Consumer {
+    /// When the consumer last tried to read, in milliseconds since the Unix epoch.
+    pub seen_at: u64,
+    /// When the consumer was last handed any entries, if ever.
+    pub active_at: Option<u64>,
+    /// The IDs of the entries pending for this consumer.
+    pub pending: BTreeSet<StreamId>,
+}
+
+/// A group of consumers that share the entries of a [`Stream`] between themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    /// The ID of the last entry delivered to any consumer.
+    pub last_delivered: StreamId,
+    /// The pending entries list of the whole group.
+    pub pending: BTreeMap<StreamId, Pending>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId) -> Self {
+        Self {
+            last_delivered,
+            ..Self::default()
+        }
+    }
+
+    /// Get the consumer called `name`, creating it if needed.
+    fn consumer(&mut self, name: &str, now_ms: u64) -> &mut Consumer {
+        self.consumers
+            .entry(name.to_string())
+            .or_insert_with(|| Consumer {
+                seen_at: now_ms,
+                active_at: None,
+                pending: BTreeSet::new(),
+            })
+    }
+
+    /// Make the entry with the given `id` pending for the `consumer`, as delivered
+    /// for the first time. An entry that is pending already (which it can only be
+    /// if `SETID` moved the group back) changes hands.
+    fn deliver(&mut self, id: StreamId, consumer: &str, now_ms: u64) {
+        let _ = self.ack(id);
+        let pending = Pending {
+            consumer: consumer.to_string(),
+            delivered_at: now_ms,
+            deliveries: 1,
+        };
+        let _ = self.pending.insert(id, pending);
+        let _ = self.consumer(consumer, now_ms).pending.insert(id);
+    }
+
+    /// Hand the entry with the given `id` over to the `consumer`, as `XCLAIM` does.
+    /// An entry that is not pending yet (which `FORCE` allows) counts as never delivered.
+    fn claim(&mut self, id: StreamId, consumer: &str, options: &XClaimOptions, now_ms: u64) {
+        let mut pending = self.pending.remove(&id).unwrap_or_else(|| Pending {
+            consumer: String::new(),
+            delivered_at: now_ms,
+            deliveries: 0,
+        });
+        if let Some(owner) = self.consumers.get_mut(&pending.consumer) {
+            let _ = owner.pending.remove(&id);
+        }
+        pending.consumer = consumer.to_string();
+        pending.delivered_at = match (options.time, options.idle) {
+            (Some(time), _) => time,
+            (None, Some(idle)) => now_ms.saturating_sub(idle),
+            (None, None) => now_ms,
+        };
+        match options.retry_count {
+            Some(deliveries) => pending.deliveries = deliveries,
+            None if !options.just_id => pending.deliveries += 1,
+            None => {}
+        }
+        let _ = self.pending.insert(id, pending);
+        let consumer = self.consumer(consumer, now_ms);
+        consumer.active_at = Some(now_ms);
+        let _ = consumer.pending.insert(id);
+    }
+
+    /// Check whether the entry with the given `id` is pending and was
+    /// delivered at least `min_idle` milliseconds before `now_ms`.
+    fn is_idle(&self, id: StreamId, min_idle: u64, now_ms: u64) -> bool {
+        self.pending
+            .get(&id)
+            .is_some_and(|pending| now_ms.saturating_sub(pending.delivered_at) >= min_idle)
+    }
+
+    /// Acknowledge the entry with the given `id`, returning whether it was pending.
+    fn ack(&mut self, id: StreamId) -> bool {
+        let Some(pending) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+            let _ = consumer.pending.remove(&id);
+        }
+        true
+    }
+}
+
+impl Stream {
+    /// Work out the ID that `from` stands for, where `$` is the last ID of the stream.
+    const fn resolve(&self, from: ReadFrom) -> StreamId {
+        match from {
+            ReadFrom::After(id) => id,
+            ReadFrom::New => self.last_id,
+        }
+    }
+
+    fn group_mut(&mut self, key: &str, group: &str) -> Result<&mut ConsumerGroup, Error> {
+        self.groups.get_mut(group).ok_or_else(|| Error::NoGroup {
+            key: key.to_string(),
+            group: group.to_string(),
+        })
+    }
+
+    /// Deliver entries to the `consumer` of the `group`, as `XREADGROUP` does.
+    ///
+    /// Reading new entries moves them into the PEL, unless `no_ack` is set,
+    /// while reading pending ones only replies with them again.
+    fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        from: ReadGroupFrom,
+        count: usize,
+        no_ack: bool,
+        now_ms: u64,
+    ) -> Vec<Delivered> {
+        let Some(group) = self.groups.get_mut(group) else {
+            return vec![];
+        };
+        match from {
+            ReadGroupFrom::New => {
+                let delivered: Vec<_> = self
+                    .entries
+                    .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                    .take(count)
+                    .map(|(id, fields)| (*id, Some(fields.clone())))
+                    .collect();
+                let owner = group.consumer(consumer, now_ms);
+                owner.seen_at = now_ms;
+                if !delivered.is_empty() {
+                    owner.active_at = Some(now_ms);
+                }
+                for (id, _) in &delivered {
+                    group.last_delivered = *id;
+                    if !no_ack {
+                        group.deliver(*id, consumer, now_ms);
+                    }
+                }
+                delivered
+            }
+            ReadGroupFrom::Pending(after) => {
+                let owner = group.consumer(consumer, now_ms);
+                owner.seen_at = now_ms;
+                owner
+                    .pending
+                    .range((Bound::Excluded(after), Bound::Unbounded))
+                    .take(count)
+                    .map(|id| (*id, self.entries.get(id).cloned()))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Stream {
+    /// Summarize the PEL of the consumer `group`, as `XPENDING` does without a range.
+    fn pending_summary(&self, group: &str) -> PendingSummary {
+        let Some(group) = self.groups.get(group) else {
+            return PendingSummary::default();
+        };
+        let first = group.pending.keys().next();
+        let last = group.pending.keys().next_back();
+        PendingSummary {
+            count: group.pending.len(),
+            bounds: first.zip(last).map(|(first, last)| (*first, *last)),
+            consumers: group
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| (name.clone(), consumer.pending.len()))
+                .collect(),
+        }
+    }
+
+    /// List the pending entries of the consumer `group` within the `range`.
+    fn pending_range(&self, group: &str, range: &PendingRange, now_ms: u64) -> Vec<PendingEntry> {
+        let Some(group) = self.groups.get(group) else {
+            return vec![];
+        };
+        let (Some(start), Some(end)) = (range.start.as_start(), range.end.as_end()) else {
+            return vec![];
+        };
+        if start > end {
+            return vec![];
+        }
+        group
+            .pending
+            .range(start..=end)
+            .map(|(id, pending)| PendingEntry {
+                id: *id,
+                consumer: pending.consumer.clone(),
+                idle: now_ms.saturating_sub(pending.delivered_at),
+                deliveries: pending.deliveries,
+            })
+            .filter(|entry| entry.idle >= range.min_idle)
+            .filter(|entry| {
+                range
+                    .consumer
+                    .as_ref()
+                    .map_or(true, |consumer| &entry.consumer == consumer)
+            })
+            .take(range.count)
+            .collect()
+    }
+
+    /// Hand the entries with the given `ids` that have been idle for at least `min_idle`
+    /// milliseconds over to the `consumer` of the `group`, as `XCLAIM` does.
+    ///
+    /// Entries that have been deleted from the stream are dropped from the PEL instead.
+    fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[StreamId],
+        options: &XClaimOptions,
+        now_ms: u64,
+    ) -> Vec<Entry> {
+        let Some(group) = self.groups.get_mut(group) else {
+            return vec![];
+        };
+        if let Some(last_id) = options.last_id {
+            group.last_delivered = group.last_delivered.max(last_id);
+        }
+        let mut claimed = vec![];
+        for id in ids {
+            let Some(fields) = self.entries.get(id) else {
+                let _ = group.ack(*id);
+                continue;
+            };
+            let forced = options.force && !group.pending.contains_key(id);
+            if forced || group.is_idle(*id, min_idle, now_ms) {
+                group.claim(*id, consumer, options, now_ms);
+                claimed.push((*id, fields.clone()));
+            }
+        }
+        claimed
+    }
+
+    /// Hand up to [`AutoClaimOptions::count`] pending entries of the `group`, starting from `start`, that have been
+    /// idle for at least `min_idle` milliseconds over to the `consumer`, as `XAUTOCLAIM` does.
+    fn auto_claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: StreamId,
+        options: AutoClaimOptions,
+        now_ms: u64,
+    ) -> AutoClaim {
+        let Some(group) = self.groups.get_mut(group) else {
+            return AutoClaim::default();
+        };
+        let AutoClaimOptions { count, just_id } = options;
+        let options = XClaimOptions {
+            just_id,
+            ..XClaimOptions::default()
+        };
+        // Like Redis, look at no more than ten times as many entries as may be claimed,
+        // so that a PEL full of busy entries cannot make a single call scan all of it.
+        let mut attempts = count.saturating_mul(10);
+        let mut scanned = group.pending.range(start..).map(|(id, _)| *id);
+        let mut outcome = AutoClaim::default();
+        let mut claimed = 0;
+        let mut candidates = vec![];
+        while attempts > 0 && claimed < count {
+            let Some(id) = scanned.next() else {
+                break;
+            };
+            attempts -= 1;
+            if !self.entries.contains_key(&id) {
+                outcome.deleted.push(id);
+            } else if group.is_idle(id, min_idle, now_ms) {
+                candidates.push(id);
+                claimed += 1;
+            }
+        }
+        outcome.next = scanned.next().unwrap_or(StreamId::MIN);
+        for id in &outcome.deleted {
+            let _ = group.ack(*id);
+        }
+        for id in candidates {
+            group.claim(id, consumer, &options, now_ms);
+            outcome.claimed.push((id, self.entries[&id].clone()));
+        }
+        outcome
+    }
+}
+
+impl Database {
+    /// Get the stream stored at `key` to work with its consumer groups, which requires the key to exist.
+    fn lookup_stream_for_group(&mut self, key: &str) -> Result<&mut Stream, Error> {
+        match self.live_mut(key).map(|value| &mut value.data) {
+            Some(Data::Stream(stream)) => Ok(stream),
+            Some(_) => Err(Error::WrongType),
+            None => Err(Error::NoStreamForGroup),
+        }
+    }
+
+    /// Create the consumer `group` of the stream stored at `key`, which starts out having
+    /// delivered every entry up to `from`. The stream is created if needed and `make_stream`.
+    #[instrument(name = "db_xgroup_create", skip(self))]
+    pub fn xgroup_create(
+        &mut self,
+        key: Key,
+        group: String,
+        from: ReadFrom,
+        make_stream: bool,
+    ) -> Result<(), Error> {
+        if make_stream && self.lookup_stream(&key)?.is_none() {
+            let _ = self
+                .storage
+                .insert(key.clone(), Value::new(Data::Stream(Stream::new()), None));
+        }
+        let stream = self.lookup_stream_for_group(&key)?;
+        if stream.groups.contains_key(&group) {
+            return Err(Error::BusyGroup);
+        }
+        let last_delivered = stream.resolve(from);
+        let _ = stream
+            .groups
+            .insert(group, ConsumerGroup::new(last_delivered));
+        self.notify(Class::Stream, "xgroup-create", &key);
+        Ok(())
+    }
+
+    /// Destroy the consumer `group` of the stream stored at `key`, along with its PEL.
+    ///
+    /// Returns whether there was such a group.
+    #[instrument(name = "db_xgroup_destroy", skip(self))]
+    pub fn xgroup_destroy(&mut self, key: &str, group: &str) -> Result<bool, Error> {
+        let destroyed = self
+            .lookup_stream_for_group(key)?
+            .groups
+            .remove(group)
+            .is_some();
+        if destroyed {
+            self.notify(Class::Stream, "xgroup-destroy", key);
+        }
+        Ok(destroyed)
+    }
+
+    /// Create the `consumer` in the consumer `group` of the stream stored at `key`.
+    ///
+    /// Returns whether the consumer was created, as opposed to existing already.
+    #[instrument(name = "db_xgroup_createconsumer", skip(self))]
+    pub fn xgroup_create_consumer(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<bool, Error> {
+        let group = self.lookup_stream_for_group(key)?.group_mut(key, group)?;
+        if group.consumers.contains_key(consumer) {
+            return Ok(false);
+        }
+        let _ = group.consumer(consumer, unix_millis_now());
+        self.notify(Class::Stream, "xgroup-createconsumer", key);
+        Ok(true)
+    }
+
+    /// Delete the `consumer` from the consumer `group` of the stream stored at `key`,
+    /// dropping its pending entries.
+    ///
+    /// Returns the number of entries that were pending for the consumer.
+    #[instrument(name = "db_xgroup_delconsumer", skip(self))]
+    pub fn xgroup_del_consumer(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<usize, Error> {
+        let group = self.lookup_stream_for_group(key)?.group_mut(key, group)?;
+        let Some(consumer) = group.consumers.remove(consumer) else {
+            return Ok(0);
+        };
+        for id in &consumer.pending {
+            let _ = group.pending.remove(id);
+        }
+        self.notify(Class::Stream, "xgroup-delconsumer", key);
+        Ok(consumer.pending.len())
+    }
+
+    /// Set the last delivered entry of the consumer `group` of the stream stored at `key`.
+    #[instrument(name = "db_xgroup_setid", skip(self))]
+    pub fn xgroup_setid(&mut self, key: &str, group: &str, from: ReadFrom) -> Result<(), Error> {
+        let stream = self.lookup_stream_for_group(key)?;
+        let last_delivered = stream.resolve(from);
+        stream.group_mut(key, group)?.last_delivered = last_delivered;
+        self.notify(Class::Stream, "xgroup-setid", key);
+        Ok(())
+    }
+
+    /// Read entries from `streams` as the `consumer` of the consumer `group`, up to `count`
+    /// from each of them, see [`ReadGroupFrom`]. The consumer is created if needed.
+    ///
+    /// Streams read from with [`ReadGroupFrom::New`] are left out if there is nothing
+    /// new in them, while the pending entries are always replied with, even if there are none.
+    #[instrument(name = "db_xreadgroup", skip(self))]
+    pub fn xreadgroup(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(Key, ReadGroupFrom)],
+        count: Option<usize>,
+        no_ack: bool,
+    ) -> Result<Vec<(Key, Vec<Delivered>)>, Error> {
+        // Check every stream upfront, so that a failure does not leave some of them read.
+        for (key, _) in streams {
+            let exists = self
+                .lookup_stream(key)?
+                .is_some_and(|stream| stream.groups.contains_key(group));
+            if !exists {
+                return Err(Error::NoGroupToRead {
+                    key: key.clone(),
+                    group: group.to_string(),
+                });
+            }
+        }
+        let now_ms = unix_millis_now();
+        let count = count.filter(|&count| count > 0).unwrap_or(usize::MAX);
+        let mut read = vec![];
+        for (key, from) in streams {
+            let stream = self.lookup_stream_for_group(key)?;
+            let delivered = stream.read_group(group, consumer, *from, count, no_ack, now_ms);
+            if !delivered.is_empty() {
+                self.touch(key);
+            }
+            if !delivered.is_empty() || matches!(from, ReadGroupFrom::Pending(_)) {
+                read.push((key.clone(), delivered));
+            }
+        }
+        Ok(read)
+    }
+
+    /// Get the stream stored at `key` to work with its consumer `group`, which requires both to exist.
+    fn lookup_stream_with_group(&mut self, key: &str, group: &str) -> Result<&mut Stream, Error> {
+        match self.live_mut(key).map(|value| &mut value.data) {
+            Some(Data::Stream(stream)) if stream.groups.contains_key(group) => Ok(stream),
+            Some(Data::Stream(_)) | None => Err(Error::NoKeyOrGroup {
+                key: key.to_string(),
+                group: group.to_string(),
+            }),
+            Some(_) => Err(Error::WrongType),
+        }
+    }
+
+    /// Summarize the PEL of the consumer `group` of the stream stored at `key`.
+    #[instrument(name = "db_xpending_summary", skip(self))]
+    pub fn xpending_summary(&mut self, key: &str, group: &str) -> Result<PendingSummary, Error> {
+        Ok(self
+            .lookup_stream_with_group(key, group)?
+            .pending_summary(group))
+    }
+
+    /// List the entries within the `range` of the PEL of the consumer `group` of the stream stored at `key`.
+    #[instrument(name = "db_xpending", skip(self))]
+    pub fn xpending(
+        &mut self,
+        key: &str,
+        group: &str,
+        range: &PendingRange,
+    ) -> Result<Vec<PendingEntry>, Error> {
+        let now_ms = unix_millis_now();
+        Ok(self
+            .lookup_stream_with_group(key, group)?
+            .pending_range(group, range, now_ms))
+    }
+
+    /// Transfer the ownership of the pending entries with the given `ids` of the consumer `group`
+    /// of the stream stored at `key` to the `consumer`, if they have been idle for at least
+    /// `min_idle` milliseconds. The consumer is created if needed.
+    ///
+    /// Returns the claimed entries.
+    #[instrument(name = "db_xclaim", skip(self))]
+    pub fn xclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[StreamId],
+        options: XClaimOptions,
+    ) -> Result<Vec<Entry>, Error> {
+        let now_ms = unix_millis_now();
+        let stream = self.lookup_stream_with_group(key, group)?;
+        let claimed = stream.claim(group, consumer, min_idle, ids, &options, now_ms);
+        if !claimed.is_empty() {
+            self.touch(key);
+        }
+        Ok(claimed)
+    }
+
+    /// Transfer the ownership of up to [`AutoClaimOptions::count`] pending entries of the consumer `group` of the
+    /// stream stored at `key` to the `consumer`, scanning the PEL from `start` for the entries
+    /// idle for at least `min_idle` milliseconds. The consumer is created if needed.
+    #[instrument(name = "db_xautoclaim", skip(self))]
+    pub fn xautoclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: StreamBound,
+        options: AutoClaimOptions,
+    ) -> Result<AutoClaim, Error> {
+        let now_ms = unix_millis_now();
+        let stream = self.lookup_stream_with_group(key, group)?;
+        let Some(start) = start.as_start() else {
+            return Ok(AutoClaim::default());
+        };
+        let claim = stream.auto_claim(group, consumer, min_idle, start, options, now_ms);
+        if !claim.claimed.is_empty() || !claim.deleted.is_empty() {
+            self.touch(key);
+        }
+        Ok(claim)
+    }
+
+    /// Acknowledge the entries with the given `ids` in the consumer `group` of the stream
+    /// stored at `key`, removing them from its PEL.
+    ///
+    /// Returns the number of entries that were pending.
+    #[instrument(name = "db_xack", skip(self))]
+    pub fn xack(&mut self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, Error> {
+        let Some(value) = self.live_mut(key) else {
+            return Ok(0);
+        };
+        let Data::Stream(stream) = &mut value.data else {
+            return Err(Error::WrongType);
+        };
+        let Some(group) = stream.groups.get_mut(group) else {
+            return Ok(0);
+        };
+        let acked = ids.iter().filter(|&&id| group.ack(id)).count();
+        if acked > 0 {
+            self.touch(key);
+        }
+        Ok(acked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoClaimOptions, PendingRange, PendingSummary, ReadGroupFrom, XClaimOptions};
+    use crate::database::{Database, Error, IdSpec, ReadFrom, StreamId, XAddOptions};
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    fn add(db: &mut Database, key: &str, ms: u64) {
+        let fields = vec![("n".to_string(), ms.to_string())];
+        let id = IdSpec::Explicit(id(ms, 0));
+        let _ = db.xadd(key.into(), id, fields, XAddOptions::default());
+    }
+
+    /// Read as `consumer` from the stream `s`, returning just the IDs and whether they still exist.
+    fn read(
+        db: &mut Database,
+        consumer: &str,
+        from: ReadGroupFrom,
+        count: Option<usize>,
+    ) -> Option<Vec<(StreamId, bool)>> {
+        let read = db
+            .xreadgroup("g", consumer, &[("s".into(), from)], count, false)
+            .unwrap();
+        read.into_iter().next().map(|(_, entries)| {
+            entries
+                .into_iter()
+                .map(|(id, fields)| (id, fields.is_some()))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn create_and_destroy() {
+        let mut db = Database::new();
+        assert_eq!(
+            db.xgroup_create("s".into(), "g".into(), ReadFrom::New, false),
+            Err(Error::NoStreamForGroup)
+        );
+        assert_eq!(
+            db.xgroup_create("s".into(), "g".into(), ReadFrom::New, true),
+            Ok(())
+        );
+        assert_eq!(
+            db.xgroup_create("s".into(), "g".into(), ReadFrom::New, true),
+            Err(Error::BusyGroup)
+        );
+        assert_eq!(db.xgroup_create_consumer("s", "g", "alice"), Ok(true));
+        assert_eq!(db.xgroup_create_consumer("s", "g", "alice"), Ok(false));
+        assert_eq!(
+            db.xgroup_create_consumer("s", "nope", "alice"),
+            Err(Error::NoGroup {
+                key: "s".into(),
+                group: "nope".into()
+            })
+        );
+        assert_eq!(db.xgroup_destroy("s", "g"), Ok(true));
+        assert_eq!(db.xgroup_destroy("s", "g"), Ok(false));
+        assert_eq!(
+            db.xgroup_destroy("missing", "g"),
+            Err(Error::NoStreamForGroup)
+        );
+    }
+
+    #[test]
+    fn new_entries_go_to_one_consumer_each() {
+        let mut db = Database::new();
+        for ms in 1..=3 {
+            add(&mut db, "s", ms);
+        }
+        db.xgroup_create("s".into(), "g".into(), ReadFrom::After(id(1, 0)), false)
+            .unwrap();
+
+        let alice = read(&mut db, "alice", ReadGroupFrom::New, Some(1));
+        assert_eq!(alice, Some(vec![(id(2, 0), true)]));
+        let bob = read(&mut db, "bob", ReadGroupFrom::New, None);
+        assert_eq!(bob, Some(vec![(id(3, 0), true)]));
+        assert_eq!(read(&mut db, "bob", ReadGroupFrom::New, None), None);
+
+        // The history of each consumer holds just what it was delivered.
+        let history = ReadGroupFrom::Pending(StreamId::MIN);
+        assert_eq!(
+            read(&mut db, "alice", history, None),
+            Some(vec![(id(2, 0), true)])
+        );
+        assert_eq!(db.xdel("s", &[id(3, 0)]), Ok(1));
+        assert_eq!(
+            read(&mut db, "bob", history, None),
+            Some(vec![(id(3, 0), false)])
+        );
+
+        assert_eq!(db.xack("s", "g", &[id(2, 0), id(3, 0), id(9, 0)]), Ok(2));
+        assert_eq!(db.xack("s", "nope", &[id(2, 0)]), Ok(0));
+        assert_eq!(read(&mut db, "alice", history, None), Some(vec![]));
+        assert_eq!(read(&mut db, "bob", history, None), Some(vec![]));
+
+        // Moving the group back delivers the entries again.
+        db.xgroup_setid("s", "g", ReadFrom::After(StreamId::MIN))
+            .unwrap();
+        assert_eq!(
+            read(&mut db, "carol", ReadGroupFrom::New, None),
+            Some(vec![(id(1, 0), true), (id(2, 0), true)])
+        );
+        assert_eq!(db.xgroup_del_consumer("s", "g", "carol"), Ok(2));
+        assert_eq!(db.xack("s", "g", &[id(1, 0)]), Ok(0));
+    }
+
+    #[test]
+    fn no_ack_and_missing_groups() {
+        let mut db = Database::new();
+        add(&mut db, "s", 1);
+        add(&mut db, "other", 1);
+        db.xgroup_create(
+            "s".into(),
+            "g".into(),
+            ReadFrom::After(StreamId::MIN),
+            false,
+        )
+        .unwrap();
+        let streams = [
+            ("s".to_string(), ReadGroupFrom::New),
+            ("other".to_string(), ReadGroupFrom::New),
+        ];
+        assert_eq!(
+            db.xreadgroup("g", "alice", &streams, None, false),
+            Err(Error::NoGroupToRead {
+                key: "other".into(),
+                group: "g".into()
+            })
+        );
+        // Nothing was read from the first stream either.
+        let delivered = db
+            .xreadgroup("g", "alice", &streams[..1], None, true)
+            .unwrap();
+        assert_eq!(delivered.len(), 1);
+        let history = ReadGroupFrom::Pending(StreamId::MIN);
+        assert_eq!(read(&mut db, "alice", history, None), Some(vec![]));
+    }
+
+    #[test]
+    fn pending_entries() {
+        let mut db = Database::new();
+        for ms in 1..=3 {
+            add(&mut db, "s", ms);
+        }
+        db.xgroup_create(
+            "s".into(),
+            "g".into(),
+            ReadFrom::After(StreamId::MIN),
+            false,
+        )
+        .unwrap();
+        assert_eq!(db.xpending_summary("s", "g"), Ok(PendingSummary::default()));
+        let _ = read(&mut db, "alice", ReadGroupFrom::New, Some(2));
+        let _ = read(&mut db, "bob", ReadGroupFrom::New, None);
+        assert_eq!(
+            db.xpending_summary("s", "g"),
+            Ok(PendingSummary {
+                count: 3,
+                bounds: Some((id(1, 0), id(3, 0))),
+                consumers: vec![("alice".into(), 2), ("bob".into(), 1)],
+            })
+        );
+
+        let mut range = PendingRange {
+            min_idle: 0,
+            start: "(1".parse().unwrap(),
+            end: "+".parse().unwrap(),
+            count: 10,
+            consumer: None,
+        };
+        let listed = |db: &mut Database, range: &PendingRange| -> Vec<(StreamId, String)> {
+            let entries = db.xpending("s", "g", range).unwrap();
+            entries
+                .into_iter()
+                .map(|entry| (entry.id, entry.consumer))
+                .collect()
+        };
+        assert_eq!(
+            listed(&mut db, &range),
+            vec![(id(2, 0), "alice".into()), (id(3, 0), "bob".into())]
+        );
+        range.consumer = Some("bob".into());
+        assert_eq!(listed(&mut db, &range), vec![(id(3, 0), "bob".into())]);
+        range.min_idle = 60_000;
+        assert_eq!(listed(&mut db, &range), vec![]);
+        assert_eq!(
+            db.xpending_summary("s", "nope"),
+            Err(Error::NoKeyOrGroup {
+                key: "s".into(),
+                group: "nope".into()
+            })
+        );
+    }
+
+    #[test]
+    fn claims() {
+        let mut db = Database::new();
+        for ms in 1..=4 {
+            add(&mut db, "s", ms);
+        }
+        db.xgroup_create(
+            "s".into(),
+            "g".into(),
+            ReadFrom::After(StreamId::MIN),
+            false,
+        )
+        .unwrap();
+        let _ = read(&mut db, "alice", ReadGroupFrom::New, Some(3));
+        let history = ReadGroupFrom::Pending(StreamId::MIN);
+
+        // Nothing has been idle for a minute yet.
+        let ids = [id(1, 0), id(2, 0)];
+        let claimed = db.xclaim("s", "g", "bob", 60_000, &ids, XClaimOptions::default());
+        assert_eq!(claimed, Ok(vec![]));
+
+        let options = XClaimOptions {
+            retry_count: Some(7),
+            ..XClaimOptions::default()
+        };
+        let claimed = db.xclaim("s", "g", "bob", 0, &ids, options).unwrap();
+        assert_eq!(claimed.len(), 2);
+        assert_eq!(
+            read(&mut db, "bob", history, None),
+            Some(vec![(id(1, 0), true), (id(2, 0), true)])
+        );
+        assert_eq!(
+            read(&mut db, "alice", history, None),
+            Some(vec![(id(3, 0), true)])
+        );
+        let all = PendingRange {
+            min_idle: 0,
+            start: "-".parse().unwrap(),
+            end: "+".parse().unwrap(),
+            count: 10,
+            consumer: None,
+        };
+        let pending = db.xpending("s", "g", &all).unwrap();
+        assert_eq!(pending[0].deliveries, 7);
+
+        // Deleted entries are dropped, and forced ones are claimed even if not pending.
+        assert_eq!(db.xdel("s", &[id(1, 0)]), Ok(1));
+        let options = XClaimOptions {
+            force: true,
+            just_id: true,
+            last_id: Some(id(9, 0)),
+            ..XClaimOptions::default()
+        };
+        let claimed = db.xclaim("s", "g", "carol", 0, &[id(1, 0), id(4, 0)], options);
+        assert_eq!(claimed.map(|claimed| claimed.len()), Ok(1));
+        let pending = db.xpending("s", "g", &all).unwrap();
+        let ids: Vec<_> = pending.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![id(2, 0), id(3, 0), id(4, 0)]);
+        assert_eq!(pending[2].deliveries, 0);
+        // `LASTID` moved the group past every entry.
+        assert_eq!(read(&mut db, "dave", ReadGroupFrom::New, None), None);
+    }
+
+    #[test]
+    fn auto_claims() {
+        let mut db = Database::new();
+        for ms in 1..=5 {
+            add(&mut db, "s", ms);
+        }
+        db.xgroup_create(
+            "s".into(),
+            "g".into(),
+            ReadFrom::After(StreamId::MIN),
+            false,
+        )
+        .unwrap();
+        let _ = read(&mut db, "alice", ReadGroupFrom::New, None);
+        assert_eq!(db.xdel("s", &[id(2, 0)]), Ok(1));
+
+        let options = AutoClaimOptions {
+            count: 2,
+            just_id: false,
+        };
+        let start = "-".parse().unwrap();
+        let outcome = db.xautoclaim("s", "g", "bob", 0, start, options).unwrap();
+        let claimed: Vec<_> = outcome.claimed.iter().map(|(id, _)| *id).collect();
+        assert_eq!(claimed, vec![id(1, 0), id(3, 0)]);
+        assert_eq!(outcome.deleted, vec![id(2, 0)]);
+        assert_eq!(outcome.next, id(4, 0));
+
+        let start = outcome.next.to_string().parse().unwrap();
+        let outcome = db.xautoclaim("s", "g", "bob", 0, start, options).unwrap();
+        assert_eq!(outcome.claimed.len(), 2);
+        assert_eq!(outcome.next, StreamId::MIN);
+        assert_eq!(
+            read(
+                &mut db,
+                "alice",
+                ReadGroupFrom::Pending(StreamId::MIN),
+                None
+            ),
+            Some(vec![])
+        );
+
+        let start = "-".parse().unwrap();
+        let outcome = db
+            .xautoclaim("s", "g", "carol", 60_000, start, options)
+            .unwrap();
+        assert!(outcome.claimed.is_empty());
+        assert_eq!(
+            db.xautoclaim("missing", "g", "carol", 0, start, options),
+            Err(Error::NoKeyOrGroup {
+                key: "missing".into(),
+                group: "g".into()
+            })
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! # Stream introspection: `XINFO STREAM`, `XINFO GROUPS` and `XINFO CONSUMERS`.
+
+use super::{unix_millis_now, ConsumerGroup, Entry, Stream, StreamId};
+use crate::database::{Data, Database, Error};
+use std::ops::Bound;
+use tracing::instrument;
+
+/// The overview of a [`Stream`] given by `XINFO STREAM`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_generated_id: StreamId,
+    pub max_deleted_entry_id: StreamId,
+    pub entries_added: u64,
+    /// The ID of the first entry, or `0-0` if the stream is empty.
+    pub recorded_first_entry_id: StreamId,
+    pub groups: usize,
+    pub first_entry: Option<Entry>,
+    pub last_entry: Option<Entry>,
+}
+
+/// The overview of a [`ConsumerGroup`] given by `XINFO GROUPS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub name: String,
+    pub consumers: usize,
+    pub pending: usize,
+    pub last_delivered_id: StreamId,
+    /// How many entries of the stream the group has read, or [`None`] if entries
+    /// deleted after the last delivered one make that impossible to tell.
+    pub entries_read: Option<u64>,
+    /// How many entries of the stream are still to be delivered to the group.
+    pub lag: usize,
+}
+
+/// The overview of a consumer given by `XINFO CONSUMERS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerInfo {
+    pub name: String,
+    pub pending: usize,
+    /// How many milliseconds ago the consumer last tried to read.
+    pub idle: u64,
+    /// How many milliseconds ago the consumer was last handed any entries, if ever.
+    pub inactive: Option<u64>,
+}
+
+impl Stream {
+    fn group_info(&self, name: &str, group: &ConsumerGroup) -> GroupInfo {
+        GroupInfo {
+            name: name.to_string(),
+            consumers: group.consumers.len(),
+            pending: group.pending.len(),
+            last_delivered_id: group.last_delivered,
+            entries_read: self.entries_read(group),
+            lag: self.lag(group),
+        }
+    }
+
+    /// How many entries were added up to the last one delivered to the `group`, if known.
+    pub fn entries_read(&self, group: &ConsumerGroup) -> Option<u64> {
+        // Every entry added after the last delivered one is still there, so it is
+        // known exactly how many were added up to it.
+        (self.max_deleted_id <= group.last_delivered)
+            .then_some(self.entries_added.saturating_sub(self.lag(group) as u64))
+    }
+
+    /// How many entries are left for the `group` to read.
+    fn lag(&self, group: &ConsumerGroup) -> usize {
+        self.entries
+            .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+            .count()
+    }
+}
+
+impl Database {
+    /// Get the stream stored at `key` for introspection, which requires the key to exist.
+    fn lookup_stream_to_inspect(&self, key: &str) -> Result<&Stream, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::Stream(stream)) => Ok(stream),
+            Some(_) => Err(Error::WrongType),
+            None => Err(Error::NoSuchKey),
+        }
+    }
+
+    /// Describe the stream stored at `key`.
+    #[instrument(name = "db_xinfo_stream", skip(self))]
+    pub fn xinfo_stream(&self, key: &str) -> Result<StreamInfo, Error> {
+        let stream = self.lookup_stream_to_inspect(key)?;
+        let first_entry = stream.entries.first_key_value();
+        let last_entry = stream.entries.last_key_value();
+        Ok(StreamInfo {
+            length: stream.len(),
+            last_generated_id: stream.last_id,
+            max_deleted_entry_id: stream.max_deleted_id,
+            entries_added: stream.entries_added,
+            recorded_first_entry_id: first_entry.map_or(StreamId::MIN, |(id, _)| *id),
+            groups: stream.groups.len(),
+            first_entry: first_entry.map(|(id, fields)| (*id, fields.clone())),
+            last_entry: last_entry.map(|(id, fields)| (*id, fields.clone())),
+        })
+    }
+
+    /// Describe every consumer group of the stream stored at `key`.
+    #[instrument(name = "db_xinfo_groups", skip(self))]
+    pub fn xinfo_groups(&self, key: &str) -> Result<Vec<GroupInfo>, Error> {
+        let stream = self.lookup_stream_to_inspect(key)?;
+        Ok(stream
+            .groups
+            .iter()
+            .map(|(name, group)| stream.group_info(name, group))
+            .collect())
+    }
+
+    /// Describe every consumer of the consumer `group` of the stream stored at `key`.
+    #[instrument(name = "db_xinfo_consumers", skip(self))]
+    pub fn xinfo_consumers(&self, key: &str, group: &str) -> Result<Vec<ConsumerInfo>, Error> {
+        let stream = self.lookup_stream_to_inspect(key)?;
+        let group = stream.groups.get(group).ok_or_else(|| Error::NoGroup {
+            key: key.to_string(),
+            group: group.to_string(),
+        })?;
+        let now_ms = unix_millis_now();
+        Ok(group
+            .consumers
+            .iter()
+            .map(|(name, consumer)| ConsumerInfo {
+                name: name.clone(),
+                pending: consumer.pending.len(),
+                idle: now_ms.saturating_sub(consumer.seen_at),
+                inactive: consumer
+                    .active_at
+                    .map(|active_at| now_ms.saturating_sub(active_at)),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Database, Error, IdSpec, ReadFrom, ReadGroupFrom, StreamId};
+    use crate::database::{Trim, TrimStrategy, XAddOptions};
+
+    fn id(ms: u64) -> StreamId {
+        StreamId { ms, seq: 0 }
+    }
+
+    #[test]
+    fn stream_groups_and_consumers() {
+        let mut db = Database::new();
+        assert_eq!(db.xinfo_stream("s"), Err(Error::NoSuchKey));
+        for ms in 1..=5 {
+            let fields = vec![("n".to_string(), ms.to_string())];
+            let _ = db.xadd(
+                "s".into(),
+                IdSpec::Explicit(id(ms)),
+                fields,
+                XAddOptions::default(),
+            );
+        }
+        let trim = Trim {
+            strategy: TrimStrategy::MaxLen(4),
+            approximate: false,
+            limit: 0,
+        };
+        assert_eq!(db.xtrim("s", trim), Ok(1));
+        db.xgroup_create(
+            "s".into(),
+            "g".into(),
+            ReadFrom::After(StreamId::MIN),
+            false,
+        )
+        .unwrap();
+        let streams = [("s".to_string(), ReadGroupFrom::New)];
+        let _ = db.xreadgroup("g", "alice", &streams, Some(2), false);
+        let _ = db.xgroup_create_consumer("s", "g", "bob");
+
+        let info = db.xinfo_stream("s").unwrap();
+        assert_eq!(info.length, 4);
+        assert_eq!(info.entries_added, 5);
+        assert_eq!(info.last_generated_id, id(5));
+        assert_eq!(info.max_deleted_entry_id, id(1));
+        assert_eq!(info.recorded_first_entry_id, id(2));
+        assert_eq!(info.groups, 1);
+        assert_eq!(info.last_entry.map(|(id, _)| id), Some(id(5)));
+
+        let groups = db.xinfo_groups("s").unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].consumers, 2);
+        assert_eq!(groups[0].pending, 2);
+        assert_eq!(groups[0].last_delivered_id, id(3));
+        assert_eq!(groups[0].entries_read, Some(3));
+        assert_eq!(groups[0].lag, 2);
+        // Deleting an entry that the group has yet to read makes the count unknowable.
+        assert_eq!(db.xdel("s", &[id(4)]), Ok(1));
+        let groups = db.xinfo_groups("s").unwrap();
+        assert_eq!((groups[0].entries_read, groups[0].lag), (None, 1));
+
+        let consumers = db.xinfo_consumers("s", "g").unwrap();
+        let names: Vec<_> = consumers
+            .iter()
+            .map(|consumer| consumer.name.as_str())
+            .collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert_eq!(consumers[0].pending, 2);
+        assert!(consumers[0].inactive.is_some());
+        assert_eq!(consumers[1].inactive, None);
+        assert_eq!(
+            db.xinfo_consumers("s", "nope"),
+            Err(Error::NoGroup {
+                key: "s".into(),
+                group: "nope".into()
+            })
+        );
+    }
+}
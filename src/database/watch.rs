@@ -0,0 +1,122 @@
+//! # Versions of the watched keys, which `EXEC` compares to tell if a transaction may run.
+//!
+//! Every write to a key bumps its version, but only while some client watches it, so that
+//! keys nobody watches cost nothing. Like in Redis, a key whose TTL runs out after it got
+//! watched counts as written to, even before it is actually removed.
+
+use super::{Database, Key, Removal};
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// The versions of the keys that are watched by at least one client.
+#[derive(Debug, Clone, Default)]
+pub struct Watches {
+    keys: HashMap<Key, Watched>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Watched {
+    /// How many clients watch the key.
+    watchers: usize,
+    version: u64,
+}
+
+/// A key watched by a client, as of the version it had back then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub key: Key,
+    version: u64,
+}
+
+impl Watches {
+    fn version(&self, key: &str) -> u64 {
+        self.keys.get(key).map_or(0, |watched| watched.version)
+    }
+}
+
+impl Database {
+    /// Start watching `key`, returning what [`Database::touched`] compares it against later.
+    #[instrument(name = "db_watch", skip(self))]
+    pub fn watch(&mut self, key: Key) -> Watch {
+        // A key that already expired is just missing, its removal changes nothing.
+        self.remove_if_expired(&key, Removal::Lazy);
+        let watched = self.watches.keys.entry(key.clone()).or_default();
+        watched.watchers += 1;
+        Watch {
+            key,
+            version: watched.version,
+        }
+    }
+
+    /// Stop watching the keys of `watches`.
+    #[instrument(name = "db_unwatch", skip(self))]
+    pub fn unwatch(&mut self, watches: &[Watch]) {
+        for Watch { key, .. } in watches {
+            let Some(watched) = self.watches.keys.get_mut(key) else {
+                continue;
+            };
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                let _ = self.watches.keys.remove(key);
+            }
+        }
+    }
+
+    /// Whether any of the keys of `watches` has been written to, or has expired, since.
+    #[instrument(name = "db_touched", skip(self))]
+    pub fn touched(&self, watches: &[Watch]) -> bool {
+        watches.iter().any(|Watch { key, version }| {
+            self.watches.version(key) != *version
+                || self.storage.get(key).is_some_and(super::Value::is_expired)
+        })
+    }
+
+    /// Bump the version of `key`, if it is watched.
+    pub(super) fn touch(&mut self, key: &str) {
+        if let Some(watched) = self.watches.keys.get_mut(key) {
+            watched.version += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Database, Value};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn writes_and_expiry_touch_watched_keys() {
+        let mut db = Database::new();
+        db.set("a".into(), Value::new("1".to_string(), None));
+        let watches = vec![db.watch("a".into()), db.watch("b".into())];
+        let _ = db.get("a");
+        assert!(!db.touched(&watches));
+        // Creating a missing key is a write too.
+        let _ = db.sadd("b".into(), vec!["x".into()]);
+        assert!(db.touched(&watches));
+        assert!(!db.touched(&watches[..1]));
+
+        // Versions outlive any single watcher, but not all of them.
+        let again = db.watch("a".into());
+        db.unwatch(&watches);
+        db.set("a".into(), Value::new("2".to_string(), None));
+        assert!(db.touched(&[again.clone()]));
+        db.unwatch(&[again]);
+        assert!(db.watches.keys.is_empty());
+
+        let ttl = Some(Duration::from_millis(5));
+        db.set("volatile".into(), Value::new("1".to_string(), ttl));
+        db.set("expired".into(), Value::new("1".to_string(), ttl));
+        thread::sleep(Duration::from_millis(10));
+        let watches = vec![db.watch("expired".into())];
+        assert!(!db.touched(&watches));
+        let _ = db.expire_cycle(10);
+        assert!(!db.touched(&watches));
+
+        db.set("volatile".into(), Value::new("1".to_string(), ttl));
+        let watches = vec![db.watch("volatile".into())];
+        assert!(!db.touched(&watches));
+        thread::sleep(Duration::from_millis(10));
+        assert!(db.touched(&watches));
+    }
+}
@@ -0,0 +1,1308 @@
+//! # Sorted set commands: `ZADD`, `ZSCORE`, `ZCARD`, `ZRANK` and friends.
+
+mod geo;
+mod skiplist;
+
+pub use geo::{Coordinates, DistanceUnit, GeoMatch, GeoOrigin, GeoSearch, GeoShape};
+
+use super::{Data, Database, Error, IndexedSet, Key, SetOperation, Value};
+use crate::notify::Class;
+use crate::random::{self, Rng};
+use skiplist::SkipList;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+use tracing::instrument;
+
+/// The score of a [`SortedSet`] member: a double that is never `NaN`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for Score {
+    type Err = Error;
+
+    /// Parse a score like Redis does, accepting `inf`, `+inf` and `-inf`, but not `NaN`.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.parse::<f64>() {
+            Ok(score) if !score.is_nan() => Ok(Self(score)),
+            _ => Err(Error::NotAFloat),
+        }
+    }
+}
+
+impl Display for Score {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Normalize `-0` to `0`, since they are the same score.
+        write!(f, "{}", self.0 + 0.0)
+    }
+}
+
+/// Sorted sets with up to this many members are stored as a plain sorted [`Vec`].
+const MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// Sorted sets with members longer than this are always stored as a [`SkipList`].
+const MAX_LISTPACK_VALUE: usize = 64;
+
+/// The operations that every encoding of the ordered part of a [`SortedSet`] supports.
+///
+/// Entries are `(score, member)` pairs, ordered by score first and member second,
+/// and positions are 0-based.
+trait Ordered {
+    fn len(&self) -> usize;
+    /// Insert an entry, which must not be present yet.
+    fn insert(&mut self, score: Score, member: String);
+    /// Remove an entry, returning whether it was present.
+    fn remove(&mut self, score: Score, member: &str) -> bool;
+    /// The position of an entry, if it is present.
+    fn rank(&self, score: Score, member: &str) -> Option<usize>;
+    /// The number of leading entries that satisfy `predicate`,
+    /// which has to hold for all the entries before the first one it rejects.
+    fn partition_point(&self, predicate: &dyn Fn(Score, &str) -> bool) -> usize;
+    /// Iterate over the entries at `positions`.
+    fn range(&self, positions: Range<usize>) -> Iter<'_>;
+    /// Remove and return the entries at `positions`.
+    fn drain(&mut self, positions: Range<usize>) -> Vec<(Score, String)>;
+}
+
+/// A plain sorted [`Vec`], which is compact and fast for small sorted sets
+/// (this is what the `listpack` encoding is to Redis).
+impl Ordered for Vec<(Score, String)> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn insert(&mut self, score: Score, member: String) {
+        let index = self
+            .binary_search_by(|(s, m)| (*s, m.as_str()).cmp(&(score, member.as_str())))
+            .unwrap_or_else(|index| index);
+        self.insert(index, (score, member));
+    }
+
+    fn remove(&mut self, score: Score, member: &str) -> bool {
+        self.rank(score, member)
+            .map(|index| self.remove(index))
+            .is_some()
+    }
+
+    fn rank(&self, score: Score, member: &str) -> Option<usize> {
+        self.binary_search_by(|(s, m)| (*s, m.as_str()).cmp(&(score, member)))
+            .ok()
+    }
+
+    fn partition_point(&self, predicate: &dyn Fn(Score, &str) -> bool) -> usize {
+        self.as_slice()
+            .partition_point(|(score, member)| predicate(*score, member))
+    }
+
+    fn range(&self, positions: Range<usize>) -> Iter<'_> {
+        let end = positions.end.min(self.len());
+        Iter::Listpack(self[positions.start.min(end)..end].iter())
+    }
+
+    fn drain(&mut self, positions: Range<usize>) -> Vec<(Score, String)> {
+        let end = positions.end.min(self.len());
+        self.drain(positions.start.min(end)..end).collect()
+    }
+}
+
+/// How the ordered part of a [`SortedSet`] is stored. Small sets start out as a
+/// [listpack](Vec) and get converted to a [`SkipList`] once they grow too big.
+#[derive(Debug, Clone)]
+enum Encoding {
+    Listpack(Vec<(Score, String)>),
+    SkipList(Box<SkipList>),
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Listpack(vec![])
+    }
+}
+
+impl Encoding {
+    fn get(&self) -> &dyn Ordered {
+        match self {
+            Self::Listpack(entries) => entries,
+            Self::SkipList(list) => list.as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Ordered {
+        match self {
+            Self::Listpack(entries) => entries,
+            Self::SkipList(list) => list.as_mut(),
+        }
+    }
+
+    /// Switch to a [`SkipList`] if a listpack is about to get an entry that it should not hold.
+    fn prepare_for(&mut self, member: &str) {
+        let Self::Listpack(entries) = self else {
+            return;
+        };
+        if entries.len() < MAX_LISTPACK_ENTRIES && member.len() <= MAX_LISTPACK_VALUE {
+            return;
+        }
+        let mut list = Box::<SkipList>::default();
+        for (score, member) in entries.drain(..) {
+            list.insert(score, member);
+        }
+        *self = Self::SkipList(list);
+    }
+}
+
+/// An iterator over the members of a [`SortedSet`] and their scores, in ascending score order.
+#[derive(Debug, Clone)]
+pub enum Iter<'a> {
+    Listpack(std::slice::Iter<'a, (Score, String)>),
+    SkipList(skiplist::Iter<'a>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, Score);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Listpack(entries) => entries.next().map(|(score, member)| (member, *score)),
+            Self::SkipList(entries) => entries.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Listpack(entries) => entries.size_hint(),
+            Self::SkipList(entries) => entries.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Listpack(entries) => entries.next_back().map(|(score, member)| (member, *score)),
+            Self::SkipList(entries) => entries.next_back(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+/// A collection of unique strings, each associated with a [`Score`],
+/// kept ordered by score first and lexicographically second.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, Score>,
+    /// All the members along with their scores, in order.
+    ordered: Encoding,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the score of `member`, returning its previous score if it was already present.
+    pub fn insert(&mut self, member: String, score: Score) -> Option<Score> {
+        let previous = self.remove(&member);
+        self.ordered.prepare_for(&member);
+        self.ordered.get_mut().insert(score, member.clone());
+        let _ = self.scores.insert(member, score);
+        previous
+    }
+
+    /// Remove `member`, returning its score if it was present.
+    pub fn remove(&mut self, member: &str) -> Option<Score> {
+        let score = self.scores.remove(member)?;
+        let _ = self.ordered.get_mut().remove(score, member);
+        Some(score)
+    }
+
+    /// Remove and return the member with the lowest score, or the highest one if `max`.
+    pub fn pop(&mut self, max: bool) -> Option<(String, Score)> {
+        let index = if max { self.len().checked_sub(1)? } else { 0 };
+        let (score, member) = self.ordered.get_mut().drain(index..index + 1).pop()?;
+        let _ = self.scores.remove(&member);
+        Some((member, score))
+    }
+
+    /// Remove all the members at `positions`, as returned by [`SortedSet::positions`].
+    ///
+    /// Returns the number of removed members.
+    pub fn remove_positions(&mut self, positions: Range<usize>) -> usize {
+        let removed = self.ordered.get_mut().drain(positions);
+        for (_, member) in &removed {
+            let _ = self.scores.remove(member);
+        }
+        removed.len()
+    }
+
+    /// The score of `member`, if it is present.
+    pub fn score(&self, member: &str) -> Option<Score> {
+        self.scores.get(member).copied()
+    }
+
+    /// The 0-based position of `member` in ascending score order, if it is present.
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        self.ordered.get().rank(self.score(member)?, member)
+    }
+
+    /// The member at the 0-based `index` in ascending score order, along with its score.
+    pub fn get(&self, index: usize) -> Option<(&String, Score)> {
+        self.range(index..index + 1).next()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// The name of how the members are ordered, as reported by `DEBUG OBJECT`.
+    pub const fn encoding(&self) -> &'static str {
+        match self.ordered {
+            Encoding::Listpack(_) => "listpack",
+            Encoding::SkipList(_) => "skiplist",
+        }
+    }
+
+    /// Iterate over the members and their scores, in ascending score order.
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(0..self.len())
+    }
+
+    /// Iterate over the members at `positions`, as returned by [`SortedSet::positions`].
+    pub fn range(&self, positions: Range<usize>) -> Iter<'_> {
+        self.ordered.get().range(positions)
+    }
+
+    /// The positions of the members that fall into `range`, in ascending score order.
+    ///
+    /// [`ZRange::Index`] counts from the highest score if `reverse`, the other ranges
+    /// are not affected by the direction of iteration.
+    pub fn positions(&self, range: &ZRange, reverse: bool) -> Range<usize> {
+        let len = self.len();
+        let ordered = self.ordered.get();
+        let (start, end) = match range {
+            ZRange::Index { start, stop } => {
+                let Some((start, stop)) = normalize_indices(*start, *stop, len) else {
+                    return 0..0;
+                };
+                if reverse {
+                    (len - 1 - stop, len - start)
+                } else {
+                    (start, stop + 1)
+                }
+            }
+            ZRange::Score { min, max } => (
+                ordered.partition_point(&|score, _| !min.admits_from_below(score)),
+                ordered.partition_point(&|score, _| max.admits_from_above(score)),
+            ),
+            ZRange::Lex { min, max } => (
+                ordered.partition_point(&|_, member| !min.admits_from_below(member)),
+                ordered.partition_point(&|_, member| max.admits_from_above(member)),
+            ),
+        };
+        start..end.max(start)
+    }
+}
+
+impl Eq for SortedSet {}
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl FromIterator<(String, Score)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (String, Score)>>(members: I) -> Self {
+        let mut set = Self::new();
+        for (member, score) in members {
+            let _ = set.insert(member, score);
+        }
+        set
+    }
+}
+
+/// Turn possibly negative (counting from the end) inclusive `start` and `stop`
+/// indices into positive ones within `0..len`, or [`None`] if the range is empty.
+fn normalize_indices(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let stop = if stop < 0 { stop + len } else { stop }.min(len - 1);
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((usize::try_from(start).ok()?, usize::try_from(stop).ok()?))
+}
+
+/// One end of a score range, like `1.5` or `(1.5` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreBound {
+    Inclusive(Score),
+    Exclusive(Score),
+}
+
+impl ScoreBound {
+    /// Whether `score` lies within a range that has this bound as its minimum.
+    fn admits_from_below(self, score: Score) -> bool {
+        match self {
+            Self::Inclusive(min) => score >= min,
+            Self::Exclusive(min) => score > min,
+        }
+    }
+
+    /// Whether `score` lies within a range that has this bound as its maximum.
+    fn admits_from_above(self, score: Score) -> bool {
+        match self {
+            Self::Inclusive(max) => score <= max,
+            Self::Exclusive(max) => score < max,
+        }
+    }
+}
+
+impl FromStr for ScoreBound {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.strip_prefix('(') {
+            Some(score) => Ok(Self::Exclusive(score.parse()?)),
+            None => Ok(Self::Inclusive(string.parse()?)),
+        }
+    }
+}
+
+/// One end of a lexicographic range: `[a` (inclusive), `(a` (exclusive), `-` or `+`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    Inclusive(String),
+    Exclusive(String),
+    /// `-`, the lowest possible string.
+    Min,
+    /// `+`, the highest possible string.
+    Max,
+}
+
+impl LexBound {
+    /// Whether `member` lies within a range that has this bound as its minimum.
+    fn admits_from_below(&self, member: &str) -> bool {
+        match self {
+            Self::Inclusive(min) => member >= min.as_str(),
+            Self::Exclusive(min) => member > min.as_str(),
+            Self::Min => true,
+            Self::Max => false,
+        }
+    }
+
+    /// Whether `member` lies within a range that has this bound as its maximum.
+    fn admits_from_above(&self, member: &str) -> bool {
+        match self {
+            Self::Inclusive(max) => member <= max.as_str(),
+            Self::Exclusive(max) => member < max.as_str(),
+            Self::Min => false,
+            Self::Max => true,
+        }
+    }
+}
+
+impl FromStr for LexBound {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.split_at(string.len().min(1)) {
+            ("-", "") => Ok(Self::Min),
+            ("+", "") => Ok(Self::Max),
+            ("[", member) => Ok(Self::Inclusive(member.to_string())),
+            ("(", member) => Ok(Self::Exclusive(member.to_string())),
+            _ => Err(Error::InvalidLexRange),
+        }
+    }
+}
+
+/// Which members of a sorted set a `ZRANGE`-like command selects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZRange {
+    /// By their 0-based rank, with negative indices counting from the end. Both ends are inclusive.
+    Index { start: i64, stop: i64 },
+    /// By their score (`BYSCORE`).
+    Score { min: ScoreBound, max: ScoreBound },
+    /// Lexicographically (`BYLEX`), assuming all the members have the same score.
+    Lex { min: LexBound, max: LexBound },
+}
+
+/// How the scores of a member present in several sorted sets are combined (`AGGREGATE`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn combine(self, a: f64, b: f64) -> f64 {
+        match self {
+            // Adding up infinities of opposite signs yields zero, just like in Redis.
+            Self::Sum => nan_to_zero(a + b),
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+}
+
+fn nan_to_zero(score: f64) -> f64 {
+    if score.is_nan() {
+        0.0
+    } else {
+        score
+    }
+}
+
+/// An input of a sorted set operation: plain sets count as sorted sets with all scores being `1`.
+#[derive(Debug, Clone, Copy)]
+enum Scored<'a> {
+    Set(&'a IndexedSet),
+    Sorted(&'a SortedSet),
+}
+
+impl<'a> Scored<'a> {
+    fn score(self, member: &str) -> Option<f64> {
+        match self {
+            Self::Set(set) => set.contains(member).then_some(1.0),
+            Self::Sorted(zset) => zset.score(member).map(|Score(score)| score),
+        }
+    }
+
+    fn iter(self) -> Box<dyn Iterator<Item = (&'a String, f64)> + 'a> {
+        match self {
+            Self::Set(set) => Box::new(set.iter().map(|member| (member, 1.0))),
+            Self::Sorted(zset) => {
+                Box::new(zset.iter().map(|(member, Score(score))| (member, score)))
+            }
+        }
+    }
+}
+
+/// Flags that alter the behaviour of `ZADD`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ZAddOptions {
+    /// `NX`: only add new members, never update existing ones.
+    pub only_new: bool,
+    /// `XX`: only update existing members, never add new ones.
+    pub only_existing: bool,
+    /// `GT`: only update existing members if the new score is greater.
+    pub greater: bool,
+    /// `LT`: only update existing members if the new score is less.
+    pub less: bool,
+    /// `CH`: count the members whose score changed, not only the added ones.
+    pub changed: bool,
+    /// `INCR`: increment the score of the (single) member instead of setting it.
+    pub increment: bool,
+}
+
+impl ZAddOptions {
+    /// Whether these flags can be used together.
+    pub const fn are_compatible(&self) -> bool {
+        !(self.only_new && (self.only_existing || self.greater || self.less)
+            || self.greater && self.less)
+    }
+}
+
+impl Database {
+    /// Get the sorted set stored at `key`, or [`None`] if there is no such key.
+    fn lookup_zset(&self, key: &str) -> Result<Option<&SortedSet>, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::SortedSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Run `f` on the sorted set stored at `key`, creating an empty one if needed.
+    ///
+    /// The key is removed again if the sorted set is left empty.
+    fn with_zset_mut<T>(
+        &mut self,
+        key: Key,
+        f: impl FnOnce(&mut SortedSet) -> T,
+    ) -> Result<T, Error> {
+        if self.live_mut(&key).is_none() {
+            let _ = self.storage.insert(
+                key.clone(),
+                Value::new(Data::SortedSet(SortedSet::new()), None),
+            );
+        }
+        let Some(Data::SortedSet(zset)) = self.storage.get_mut(&key).map(|value| &mut value.data)
+        else {
+            return Err(Error::WrongType);
+        };
+        let result = f(zset);
+        if zset.is_empty() {
+            let _ = self.storage.remove(&key);
+        }
+        Ok(result)
+    }
+
+    /// Add the `members` with their scores to the sorted set stored at `key`,
+    /// or update the scores of the ones already present, according to the `options`.
+    ///
+    /// Returns the number of added members or, with [`ZAddOptions::changed`],
+    /// the number of added members plus the number of updated ones.
+    #[instrument(name = "db_zadd", skip(self))]
+    pub fn zadd(
+        &mut self,
+        key: Key,
+        options: ZAddOptions,
+        members: Vec<(Score, String)>,
+    ) -> Result<usize, Error> {
+        let (count, changed) = self.with_zset_mut(key.clone(), |zset| {
+            let (mut count, mut changed) = (0, false);
+            for (score, member) in members {
+                let previous = zset.score(&member);
+                if let Some(score) = zadd_one(zset, options, member, score, false)? {
+                    if previous.is_none() || options.changed && previous != Some(score) {
+                        count += 1;
+                    }
+                    changed |= previous != Some(score);
+                }
+            }
+            Ok((count, changed))
+        })??;
+        if changed {
+            self.notify(Class::SortedSet, "zadd", &key);
+        }
+        Ok(count)
+    }
+
+    /// Increment the score of `member` in the sorted set stored at `key` by `increment`,
+    /// according to the `options` (`ZADD ... INCR` and `ZINCRBY`).
+    ///
+    /// Returns the new score of the member, or [`None`] if the options prevented the update.
+    #[instrument(name = "db_zadd_incr", skip(self))]
+    pub fn zadd_incr(
+        &mut self,
+        key: Key,
+        options: ZAddOptions,
+        increment: Score,
+        member: String,
+    ) -> Result<Option<Score>, Error> {
+        let score = self.with_zset_mut(key.clone(), |zset| {
+            zadd_one(zset, options, member, increment, true)
+        })??;
+        if score.is_some() {
+            self.notify(Class::SortedSet, "zincr", &key);
+        }
+        Ok(score)
+    }
+
+    /// Returns the score of `member` in the sorted set stored at `key`.
+    #[instrument(name = "db_zscore", skip(self))]
+    pub fn zscore(&self, key: &str, member: &str) -> Result<Option<Score>, Error> {
+        Ok(self.lookup_zset(key)?.and_then(|zset| zset.score(member)))
+    }
+
+    /// Returns the number of members in the sorted set stored at `key`.
+    #[instrument(name = "db_zcard", skip(self))]
+    pub fn zcard(&self, key: &str) -> Result<usize, Error> {
+        Ok(self.lookup_zset(key)?.map_or(0, SortedSet::len))
+    }
+
+    /// Returns the rank of `member` in the sorted set stored at `key`, along with its score.
+    ///
+    /// The rank is 0-based and counts from the lowest score, or from the highest one if `reverse`.
+    #[instrument(name = "db_zrank", skip(self))]
+    pub fn zrank(
+        &self,
+        key: &str,
+        member: &str,
+        reverse: bool,
+    ) -> Result<Option<(usize, Score)>, Error> {
+        let Some(zset) = self.lookup_zset(key)? else {
+            return Ok(None);
+        };
+        Ok(zset
+            .rank(member)
+            .zip(zset.score(member))
+            .map(|(rank, score)| {
+                if reverse {
+                    (zset.len() - 1 - rank, score)
+                } else {
+                    (rank, score)
+                }
+            }))
+    }
+}
+
+impl Database {
+    /// Get the sorted set or plain set stored at `key`, or [`None`] if there is no such key.
+    fn lookup_scored(&self, key: &str) -> Result<Option<Scored<'_>>, Error> {
+        match self.live(key).map(|value| &value.data) {
+            Some(Data::SortedSet(zset)) => Ok(Some(Scored::Sorted(zset))),
+            Some(Data::Set(set)) => Ok(Some(Scored::Set(set))),
+            Some(_) => Err(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Compute the result of `operation` over the sorted sets stored at `keys`.
+    ///
+    /// The scores from each input are multiplied by the corresponding `weights` (which
+    /// default to `1`), and the scores of members present in several inputs are combined
+    /// according to `aggregate`. A difference keeps the scores from the first input.
+    /// Missing keys are considered to be empty sets.
+    #[instrument(name = "db_zset_operation", skip(self))]
+    pub fn zset_operation(
+        &self,
+        operation: SetOperation,
+        keys: &[String],
+        weights: &[Score],
+        aggregate: Aggregate,
+    ) -> Result<SortedSet, Error> {
+        let inputs = keys
+            .iter()
+            .map(|key| self.lookup_scored(key))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let weight = |index: usize| weights.get(index).map_or(1.0, |Score(weight)| *weight);
+        let weighted = |index: usize, score: f64| nan_to_zero(score * weight(index));
+        let result = match (operation, inputs.split_first()) {
+            (SetOperation::Union, _) => union(&inputs, weighted, aggregate),
+            (SetOperation::Intersection, Some((Some(first), others))) => first
+                .iter()
+                .filter_map(|(member, score)| {
+                    let mut combined = weighted(0, score);
+                    for (index, other) in others.iter().enumerate() {
+                        let score = other.and_then(|other| other.score(member))?;
+                        combined = aggregate.combine(combined, weighted(index + 1, score));
+                    }
+                    Some((member.clone(), Score(combined)))
+                })
+                .collect(),
+            (SetOperation::Difference, Some((Some(first), others))) => first
+                .iter()
+                .filter(|(member, _)| {
+                    !others
+                        .iter()
+                        .flatten()
+                        .any(|other| other.score(member).is_some())
+                })
+                .map(|(member, score)| (member.clone(), Score(score)))
+                .collect(),
+            // Intersecting with or subtracting from a missing key yields nothing.
+            _ => SortedSet::new(),
+        };
+        Ok(result)
+    }
+
+    /// Compute the result of `operation` over the sorted sets stored at `keys`, like
+    /// [`Database::zset_operation`] does, and store it at `destination`.
+    ///
+    /// If `destination` already exists, it is overwritten. If the resulting sorted set is
+    /// empty, `destination` is removed instead. Returns the cardinality of the result.
+    #[instrument(name = "db_zset_operation_store", skip(self))]
+    pub fn zset_operation_store(
+        &mut self,
+        operation: SetOperation,
+        destination: Key,
+        keys: &[String],
+        weights: &[Score],
+        aggregate: Aggregate,
+    ) -> Result<usize, Error> {
+        let result = self.zset_operation(operation, keys, weights, aggregate)?;
+        let cardinality = result.len();
+        let event = match operation {
+            SetOperation::Intersection => "zinterstore",
+            SetOperation::Union => "zunionstore",
+            SetOperation::Difference => "zdiffstore",
+        };
+        let result = (!result.is_empty()).then_some(Data::SortedSet(result));
+        self.store(destination, result, Class::SortedSet, event);
+        Ok(cardinality)
+    }
+
+    /// Return random members from the sorted set stored at `key`, along with their scores.
+    ///
+    /// See [`random::sample_indices`] for the meaning of `count`.
+    #[instrument(name = "db_zrandmember", skip(self))]
+    pub fn zrandmember(&self, key: &str, count: i64) -> Result<Vec<(String, Score)>, Error> {
+        let Some(zset) = self.lookup_zset(key)? else {
+            return Ok(vec![]);
+        };
+        Ok(random::sample_indices(&mut Rng::new(), zset.len(), count)
+            .into_iter()
+            .filter_map(|index| zset.get(index))
+            .map(|(member, score)| (member.clone(), score))
+            .collect())
+    }
+
+    /// Remove and return up to `count` members with the lowest scores
+    /// from the sorted set stored at `key`, or with the highest ones if `max`.
+    ///
+    /// The members are ordered by the order in which they were popped.
+    #[instrument(name = "db_zpop", skip(self))]
+    pub fn zpop(
+        &mut self,
+        key: &str,
+        max: bool,
+        count: usize,
+    ) -> Result<Vec<(String, Score)>, Error> {
+        if self.lookup_zset(key)?.is_none() {
+            return Ok(vec![]);
+        }
+        let popped: Vec<_> = self.with_zset_mut(key.to_string(), |zset| {
+            std::iter::from_fn(|| zset.pop(max)).take(count).collect()
+        })?;
+        if !popped.is_empty() {
+            let event = if max { "zpopmax" } else { "zpopmin" };
+            self.notify_change(Class::SortedSet, event, key);
+        }
+        Ok(popped)
+    }
+
+    /// Returns the members of the sorted set stored at `key` that fall into `range`,
+    /// along with their scores.
+    ///
+    /// The members are ordered from the lowest to the highest score, or the other way
+    /// around if `reverse`. With a `limit` of `(offset, count)`, only `count` members
+    /// (or all of them, if `count` is negative) are returned, after skipping `offset` ones.
+    #[instrument(name = "db_zrange", skip(self))]
+    pub fn zrange(
+        &self,
+        key: &str,
+        range: &ZRange,
+        reverse: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<(String, Score)>, Error> {
+        let Some(zset) = self.lookup_zset(key)? else {
+            return Ok(vec![]);
+        };
+        let positions = zset.positions(range, reverse);
+        let (offset, count) = match limit {
+            Some((offset, _)) if offset < 0 => return Ok(vec![]),
+            Some((offset, count)) => (
+                usize::try_from(offset).unwrap_or(usize::MAX),
+                usize::try_from(count).unwrap_or(usize::MAX),
+            ),
+            None => (0, usize::MAX),
+        };
+        let members = zset.range(positions);
+        let members: Box<dyn Iterator<Item = _>> = if reverse {
+            Box::new(members.rev())
+        } else {
+            Box::new(members)
+        };
+        Ok(members
+            .skip(offset)
+            .take(count)
+            .map(|(member, score)| (member.clone(), score))
+            .collect())
+    }
+
+    /// Remove the members of the sorted set stored at `key` that fall into `range`.
+    ///
+    /// Returns the number of removed members.
+    #[instrument(name = "db_zremrange", skip(self))]
+    pub fn zremrange(&mut self, key: &str, range: &ZRange) -> Result<usize, Error> {
+        if self.lookup_zset(key)?.is_none() {
+            return Ok(0);
+        }
+        let removed = self.with_zset_mut(key.to_string(), |zset| {
+            zset.remove_positions(zset.positions(range, false))
+        })?;
+        if removed > 0 {
+            let event = match range {
+                ZRange::Index { .. } => "zremrangebyrank",
+                ZRange::Score { .. } => "zremrangebyscore",
+                ZRange::Lex { .. } => "zremrangebylex",
+            };
+            self.notify_change(Class::SortedSet, event, key);
+        }
+        Ok(removed)
+    }
+}
+
+/// Combine all the members of all the `inputs`, see [`Database::zset_operation`].
+fn union(
+    inputs: &[Option<Scored<'_>>],
+    weighted: impl Fn(usize, f64) -> f64,
+    aggregate: Aggregate,
+) -> SortedSet {
+    let mut combined: HashMap<&String, f64> = HashMap::new();
+    for (index, input) in inputs.iter().enumerate() {
+        for (member, score) in input.iter().flat_map(|input| input.iter()) {
+            let score = weighted(index, score);
+            let _ = combined
+                .entry(member)
+                .and_modify(|combined| *combined = aggregate.combine(*combined, score))
+                .or_insert(score);
+        }
+    }
+    combined
+        .into_iter()
+        .map(|(member, score)| (member.clone(), Score(score)))
+        .collect()
+}
+
+/// Add or update a single `member` of `zset`, honoring the `ZADD` `options`.
+///
+/// Returns the resulting score of the member, or [`None`] if it was left untouched.
+fn zadd_one(
+    zset: &mut SortedSet,
+    options: ZAddOptions,
+    member: String,
+    score: Score,
+    increment: bool,
+) -> Result<Option<Score>, Error> {
+    let previous = zset.score(&member);
+    match previous {
+        Some(_) if options.only_new => return Ok(None),
+        None if options.only_existing => return Ok(None),
+        _ => {}
+    }
+    let score = match previous {
+        Some(Score(previous)) if increment => {
+            let sum = previous + score.0;
+            if sum.is_nan() {
+                return Err(Error::NaN);
+            }
+            Score(sum)
+        }
+        _ => score,
+    };
+    if let Some(previous) = previous {
+        if options.greater && score <= previous || options.less && score >= previous {
+            return Ok(None);
+        }
+    }
+    let _ = zset.insert(member, score);
+    Ok(Some(score))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aggregate, LexBound, Score, ScoreBound, SortedSet, ZAddOptions, ZRange};
+    use super::{Encoding, MAX_LISTPACK_ENTRIES, MAX_LISTPACK_VALUE};
+    use crate::database::{Database, Error, SetOperation};
+
+    fn pairs(pairs: &[(f64, &str)]) -> Vec<(Score, String)> {
+        pairs
+            .iter()
+            .map(|&(score, member)| (Score(score), member.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn ordered_by_score_then_member() {
+        let zset: SortedSet = [("b", 1.0), ("a", 1.0), ("c", 0.5), ("d", 2.0)]
+            .into_iter()
+            .map(|(member, score)| (member.to_string(), Score(score)))
+            .collect();
+        let members: Vec<_> = zset.iter().map(|(member, _)| member.as_str()).collect();
+        assert_eq!(members, ["c", "a", "b", "d"]);
+        assert_eq!(zset.rank("b"), Some(2));
+        assert_eq!(zset.rank("x"), None);
+    }
+
+    #[test]
+    fn converts_to_skip_list() {
+        let mut zset: SortedSet = (0..MAX_LISTPACK_ENTRIES)
+            .map(|index| (format!("m{index:03}"), Score(index as f64)))
+            .collect();
+        assert!(matches!(zset.ordered, Encoding::Listpack(_)));
+        let _ = zset.insert("m128".to_string(), Score(-1.0));
+        assert!(matches!(zset.ordered, Encoding::SkipList(_)));
+        assert_eq!(zset.len(), MAX_LISTPACK_ENTRIES + 1);
+        assert_eq!(zset.rank("m128"), Some(0));
+        assert_eq!(zset.rank("m127"), Some(128));
+        assert_eq!(zset.pop(true), Some(("m127".to_string(), Score(127.0))));
+        assert_eq!(zset.get(1), Some((&"m000".to_string(), Score(0.0))));
+        let range = ZRange::Score {
+            min: ScoreBound::Exclusive(Score(9.0)),
+            max: ScoreBound::Inclusive(Score(12.0)),
+        };
+        assert_eq!(zset.positions(&range, false), 11..14);
+        assert_eq!(zset.remove_positions(11..14), 3);
+        assert_eq!(zset.score("m010"), None);
+
+        let mut long = SortedSet::new();
+        let _ = long.insert("x".repeat(MAX_LISTPACK_VALUE + 1), Score(1.0));
+        assert!(matches!(long.ordered, Encoding::SkipList(_)));
+    }
+
+    #[test]
+    fn score_parsing_and_formatting() {
+        assert_eq!("1.5".parse(), Ok(Score(1.5)));
+        assert_eq!("-inf".parse(), Ok(Score(f64::NEG_INFINITY)));
+        assert_eq!("+inf".parse(), Ok(Score(f64::INFINITY)));
+        assert_eq!("nan".parse::<Score>(), Err(Error::NotAFloat));
+        assert_eq!("abc".parse::<Score>(), Err(Error::NotAFloat));
+        assert_eq!(Score(3.0).to_string(), "3");
+        assert_eq!(Score(-0.0).to_string(), "0");
+        assert_eq!(Score(f64::INFINITY).to_string(), "inf");
+    }
+
+    #[test]
+    fn add_score_rank_card() {
+        let mut db = Database::new();
+        let options = ZAddOptions::default();
+        let added = db.zadd(
+            "z".into(),
+            options,
+            pairs(&[(1.0, "a"), (2.0, "b"), (3.0, "c")]),
+        );
+        assert_eq!(added, Ok(3));
+        assert_eq!(db.zadd("z".into(), options, pairs(&[(5.0, "a")])), Ok(0));
+        assert_eq!(db.zscore("z", "a"), Ok(Some(Score(5.0))));
+        assert_eq!(db.zscore("z", "x"), Ok(None));
+        assert_eq!(db.zcard("z"), Ok(3));
+        assert_eq!(db.zcard("missing"), Ok(0));
+        assert_eq!(db.zrank("z", "a", false), Ok(Some((2, Score(5.0)))));
+        assert_eq!(db.zrank("z", "a", true), Ok(Some((0, Score(5.0)))));
+        assert_eq!(db.zrank("z", "x", false), Ok(None));
+    }
+
+    #[test]
+    fn add_flags() {
+        let mut db = Database::new();
+        let _ = db.zadd("z".into(), ZAddOptions::default(), pairs(&[(1.0, "a")]));
+
+        let nx = ZAddOptions {
+            only_new: true,
+            ..ZAddOptions::default()
+        };
+        assert_eq!(
+            db.zadd("z".into(), nx, pairs(&[(9.0, "a"), (2.0, "b")])),
+            Ok(1)
+        );
+        assert_eq!(db.zscore("z", "a"), Ok(Some(Score(1.0))));
+
+        let xx_ch = ZAddOptions {
+            only_existing: true,
+            changed: true,
+            ..ZAddOptions::default()
+        };
+        assert_eq!(
+            db.zadd("z".into(), xx_ch, pairs(&[(3.0, "a"), (4.0, "c")])),
+            Ok(1)
+        );
+        assert_eq!(db.zscore("z", "c"), Ok(None));
+
+        let gt = ZAddOptions {
+            greater: true,
+            changed: true,
+            ..ZAddOptions::default()
+        };
+        assert_eq!(
+            db.zadd("z".into(), gt, pairs(&[(1.0, "a"), (5.0, "b")])),
+            Ok(1)
+        );
+        assert_eq!(db.zscore("z", "a"), Ok(Some(Score(3.0))));
+        assert_eq!(db.zscore("z", "b"), Ok(Some(Score(5.0))));
+
+        let lt = ZAddOptions {
+            less: true,
+            ..ZAddOptions::default()
+        };
+        assert_eq!(
+            db.zadd("z".into(), lt, pairs(&[(4.0, "a"), (0.0, "d")])),
+            Ok(1)
+        );
+        assert_eq!(db.zscore("z", "a"), Ok(Some(Score(3.0))));
+        assert_eq!(db.zscore("z", "d"), Ok(Some(Score(0.0))));
+
+        // Nothing gets created by an update-only `ZADD` on a missing key.
+        assert_eq!(db.zadd("y".into(), xx_ch, pairs(&[(1.0, "a")])), Ok(0));
+        assert_eq!(db.key_type("y"), "none");
+
+        assert!(!ZAddOptions {
+            only_new: true,
+            greater: true,
+            ..ZAddOptions::default()
+        }
+        .are_compatible());
+        assert!(!ZAddOptions {
+            greater: true,
+            less: true,
+            ..ZAddOptions::default()
+        }
+        .are_compatible());
+    }
+
+    #[test]
+    fn add_incr() {
+        let mut db = Database::new();
+        let options = ZAddOptions {
+            increment: true,
+            ..ZAddOptions::default()
+        };
+        assert_eq!(
+            db.zadd_incr("z".into(), options, Score(2.0), "a".into()),
+            Ok(Some(Score(2.0)))
+        );
+        assert_eq!(
+            db.zadd_incr("z".into(), options, Score(1.5), "a".into()),
+            Ok(Some(Score(3.5)))
+        );
+
+        let nx = ZAddOptions {
+            only_new: true,
+            ..options
+        };
+        assert_eq!(
+            db.zadd_incr("z".into(), nx, Score(1.0), "a".into()),
+            Ok(None)
+        );
+
+        let _ = db.zadd_incr("z".into(), options, Score(f64::INFINITY), "b".into());
+        assert_eq!(
+            db.zadd_incr("z".into(), options, Score(f64::NEG_INFINITY), "b".into()),
+            Err(Error::NaN)
+        );
+    }
+
+    fn members(range: &[(String, Score)]) -> Vec<&str> {
+        range.iter().map(|(member, _)| member.as_str()).collect()
+    }
+
+    #[test]
+    fn ranges() {
+        let mut db = Database::new();
+        let scores = [(1.0, "a"), (2.0, "b"), (2.5, "c"), (3.0, "d"), (4.0, "e")];
+        let _ = db.zadd("z".into(), ZAddOptions::default(), pairs(&scores));
+
+        let index = |start, stop| ZRange::Index { start, stop };
+        assert_eq!(
+            members(&db.zrange("z", &index(0, -1), false, None).unwrap()),
+            ["a", "b", "c", "d", "e"]
+        );
+        assert_eq!(
+            members(&db.zrange("z", &index(1, 2), false, None).unwrap()),
+            ["b", "c"]
+        );
+        assert_eq!(
+            members(&db.zrange("z", &index(0, 1), true, None).unwrap()),
+            ["e", "d"]
+        );
+        assert_eq!(
+            members(&db.zrange("z", &index(-2, 100), false, None).unwrap()),
+            ["d", "e"]
+        );
+        assert!(db
+            .zrange("z", &index(3, 1), false, None)
+            .unwrap()
+            .is_empty());
+        assert!(db
+            .zrange("missing", &index(0, -1), false, None)
+            .unwrap()
+            .is_empty());
+
+        let by_score = ZRange::Score {
+            min: ScoreBound::Exclusive(Score(1.0)),
+            max: ScoreBound::Inclusive(Score(3.0)),
+        };
+        assert_eq!(
+            members(&db.zrange("z", &by_score, false, None).unwrap()),
+            ["b", "c", "d"]
+        );
+        assert_eq!(
+            members(&db.zrange("z", &by_score, true, Some((1, 1))).unwrap()),
+            ["c"]
+        );
+        assert_eq!(
+            members(&db.zrange("z", &by_score, false, Some((1, -1))).unwrap()),
+            ["c", "d"]
+        );
+        let everything = ZRange::Score {
+            min: "-inf".parse().unwrap(),
+            max: "+inf".parse().unwrap(),
+        };
+        assert_eq!(db.zrange("z", &everything, false, None).unwrap().len(), 5);
+
+        let _ = db.zadd(
+            "l".into(),
+            ZAddOptions::default(),
+            pairs(&[(0.0, "a"), (0.0, "b"), (0.0, "c")]),
+        );
+        let by_lex = |min: &str, max: &str| ZRange::Lex {
+            min: min.parse().unwrap(),
+            max: max.parse().unwrap(),
+        };
+        assert_eq!(
+            members(&db.zrange("l", &by_lex("-", "+"), false, None).unwrap()),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            members(&db.zrange("l", &by_lex("(a", "[c"), true, None).unwrap()),
+            ["c", "b"]
+        );
+        assert_eq!(
+            members(&db.zrange("l", &by_lex("[b", "(c"), false, None).unwrap()),
+            ["b"]
+        );
+        assert_eq!("x".parse::<LexBound>(), Err(Error::InvalidLexRange));
+    }
+
+    #[test]
+    fn remove_ranges() {
+        let mut db = Database::new();
+        let scores = [(1.0, "a"), (2.0, "b"), (3.0, "c"), (4.0, "d")];
+        let _ = db.zadd("z".into(), ZAddOptions::default(), pairs(&scores));
+        let by_rank = ZRange::Index {
+            start: -2,
+            stop: -1,
+        };
+        assert_eq!(db.zremrange("z", &by_rank), Ok(2));
+        let everything = ZRange::Index { start: 0, stop: -1 };
+        assert_eq!(
+            members(&db.zrange("z", &everything, false, None).unwrap()),
+            ["a", "b"]
+        );
+        let by_score = ZRange::Score {
+            min: ScoreBound::Exclusive(Score(1.0)),
+            max: ScoreBound::Inclusive(Score(f64::INFINITY)),
+        };
+        assert_eq!(db.zremrange("z", &by_score), Ok(1));
+        assert_eq!(db.zscore("z", "b"), Ok(None));
+        assert_eq!(db.zremrange("z", &everything), Ok(1));
+        assert_eq!(db.key_type("z"), "none");
+        assert_eq!(db.zremrange("z", &everything), Ok(0));
+    }
+
+    #[test]
+    fn random_members() {
+        let mut db = Database::new();
+        let scores = [(1.0, "a"), (2.0, "b"), (3.0, "c")];
+        let _ = db.zadd("z".into(), ZAddOptions::default(), pairs(&scores));
+
+        let mut distinct = db.zrandmember("z", 5).unwrap();
+        distinct.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            distinct,
+            pairs(&scores)
+                .into_iter()
+                .map(|(s, m)| (m, s))
+                .collect::<Vec<_>>()
+        );
+
+        let repeated = db.zrandmember("z", -10).unwrap();
+        assert_eq!(repeated.len(), 10);
+        assert!(repeated
+            .iter()
+            .all(|(member, score)| db.zscore("z", member) == Ok(Some(*score))));
+        assert_eq!(db.zrandmember("missing", 3), Ok(vec![]));
+    }
+
+    #[test]
+    fn pop_min_and_max() {
+        let mut db = Database::new();
+        let scores = [(1.0, "a"), (2.0, "b"), (3.0, "c")];
+        let _ = db.zadd("z".into(), ZAddOptions::default(), pairs(&scores));
+        assert_eq!(
+            db.zpop("z", false, 1),
+            Ok(vec![("a".to_string(), Score(1.0))])
+        );
+        assert_eq!(members(&db.zpop("z", true, 5).unwrap()), ["c", "b"]);
+        assert_eq!(db.key_type("z"), "none");
+        assert_eq!(db.zpop("z", true, 1), Ok(vec![]));
+    }
+
+    #[test]
+    fn set_operations() {
+        let mut db = Database::new();
+        let _ = db.zadd(
+            "a".into(),
+            ZAddOptions::default(),
+            pairs(&[(1.0, "x"), (2.0, "y")]),
+        );
+        let _ = db.zadd(
+            "b".into(),
+            ZAddOptions::default(),
+            pairs(&[(10.0, "y"), (20.0, "z")]),
+        );
+        let _ = db.sadd("s".into(), vec!["y".into(), "w".into()]);
+        let keys = |keys: &[&str]| keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let scores = |set: SortedSet| {
+            set.iter()
+                .map(|(member, score)| (score, member.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let union = db.zset_operation(
+            SetOperation::Union,
+            &keys(&["a", "b", "missing"]),
+            &[Score(2.0), Score(1.0)],
+            Aggregate::Sum,
+        );
+        assert_eq!(
+            scores(union.unwrap()),
+            pairs(&[(2.0, "x"), (14.0, "y"), (20.0, "z")])
+        );
+
+        // Plain sets take part with a score of 1.
+        let max = db.zset_operation(
+            SetOperation::Intersection,
+            &keys(&["a", "b", "s"]),
+            &[],
+            Aggregate::Max,
+        );
+        assert_eq!(scores(max.unwrap()), pairs(&[(10.0, "y")]));
+        let min = db.zset_operation(
+            SetOperation::Intersection,
+            &keys(&["b", "s"]),
+            &[],
+            Aggregate::Min,
+        );
+        assert_eq!(scores(min.unwrap()), pairs(&[(1.0, "y")]));
+
+        let diff = db.zset_operation_store(
+            SetOperation::Difference,
+            "d".into(),
+            &keys(&["a", "b"]),
+            &[],
+            Aggregate::Sum,
+        );
+        assert_eq!(diff, Ok(1));
+        assert_eq!(db.zscore("d", "x"), Ok(Some(Score(1.0))));
+
+        let empty = db.zset_operation_store(
+            SetOperation::Intersection,
+            "d".into(),
+            &keys(&["a", "missing"]),
+            &[],
+            Aggregate::Sum,
+        );
+        assert_eq!(empty, Ok(0));
+        assert_eq!(db.key_type("d"), "none");
+    }
+
+    #[test]
+    fn wrong_type() {
+        let mut db = Database::new();
+        db.set("s".into(), crate::database::Value::without_ttl("x".into()));
+        assert_eq!(db.zcard("s"), Err(Error::WrongType));
+        assert_eq!(
+            db.zadd("s".into(), ZAddOptions::default(), pairs(&[(1.0, "a")])),
+            Err(Error::WrongType)
+        );
+    }
+}
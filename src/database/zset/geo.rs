@@ -0,0 +1,527 @@
+//! # Geospatial commands: `GEOADD`, `GEOPOS`, `GEODIST` and `GEOSEARCH`.
+//!
+//! Locations are stored as members of a plain [`SortedSet`], scored by the 52-bit
+//! geohash of their coordinates, exactly like Redis does. The geohash interleaves
+//! 26 bits of the longitude with 26 bits of the latitude, so nearby locations get similar
+//! scores, and decoding it gives back the center of a cell of well under a meter across.
+//!
+//! Searches decode every member of the sorted set and measure its distance to the
+//! center of the search area, which gives exactly the same matches as Redis, as it
+//! measures the same distances to the same decoded coordinates.
+
+use super::{Score, SortedSet, ZAddOptions};
+use crate::database::{Data, Database, Error, Key};
+use crate::notify::Class;
+use std::cmp::Ordering;
+use tracing::instrument;
+
+/// The bits of each coordinate in a geohash.
+const STEP: u32 = 26;
+
+const LONGITUDE_MIN: f64 = -180.0;
+const LONGITUDE_MAX: f64 = 180.0;
+
+/// The latitudes that the Web Mercator projection covers, beyond which locations cannot be indexed.
+const LATITUDE_MIN: f64 = -85.051_128_78;
+const LATITUDE_MAX: f64 = 85.051_128_78;
+
+/// The radius of the Earth used for distances, which has to match the one of Redis.
+const EARTH_RADIUS_IN_METERS: f64 = 6_372_797.560_856;
+
+/// A location on Earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+// Coordinates are parsed to be finite, so they are never `NaN`.
+impl Eq for Coordinates {}
+
+impl Coordinates {
+    /// Check that these coordinates can be indexed.
+    fn validate(self) -> Result<Self, Error> {
+        let valid = (LONGITUDE_MIN..=LONGITUDE_MAX).contains(&self.longitude)
+            && (LATITUDE_MIN..=LATITUDE_MAX).contains(&self.latitude);
+        if valid {
+            Ok(self)
+        } else {
+            let pair = format!("{:.6},{:.6}", self.longitude, self.latitude);
+            Err(Error::InvalidCoordinates(pair))
+        }
+    }
+
+    /// The 52-bit geohash of these coordinates, which must be valid, as a sorted set score.
+    fn score(self) -> Score {
+        let offset = |value: f64, min: f64, max: f64| {
+            ((value - min) / (max - min) * f64::from(1 << STEP)) as u64
+        };
+        let longitude = offset(self.longitude, LONGITUDE_MIN, LONGITUDE_MAX);
+        let latitude = offset(self.latitude, LATITUDE_MIN, LATITUDE_MAX);
+        // The longitude takes the odd bits and the latitude the even ones.
+        Score((spread(longitude) << 1 | spread(latitude)) as f64)
+    }
+
+    /// The coordinates of the center of the cell with the geohash `score`.
+    fn from_score(Score(score): Score) -> Self {
+        let hash = score as u64;
+        let center = |offset: u64, min: f64, max: f64| {
+            let cell = (max - min) / f64::from(1 << STEP);
+            let center = min + cell * (offset as f64 + 0.5);
+            center.clamp(min, max)
+        };
+        Self {
+            longitude: center(squash(hash >> 1), LONGITUDE_MIN, LONGITUDE_MAX),
+            latitude: center(squash(hash), LATITUDE_MIN, LATITUDE_MAX),
+        }
+    }
+
+    /// The great-circle distance to `other` in meters, with the haversine formula.
+    pub fn distance(self, other: Self) -> f64 {
+        let v = ((other.longitude - self.longitude).to_radians() / 2.0).sin();
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        if v == 0.0 {
+            return EARTH_RADIUS_IN_METERS * (lat2 - lat1).abs();
+        }
+        let u = ((lat2 - lat1) / 2.0).sin();
+        let a = u * u + lat1.cos() * lat2.cos() * v * v;
+        2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+    }
+}
+
+/// Spread the low 32 bits of `bits` out to the even bits of the result.
+const fn spread(bits: u64) -> u64 {
+    let mut bits = bits & 0xffff_ffff;
+    bits = (bits | bits << 16) & 0x0000_ffff_0000_ffff;
+    bits = (bits | bits << 8) & 0x00ff_00ff_00ff_00ff;
+    bits = (bits | bits << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    bits = (bits | bits << 2) & 0x3333_3333_3333_3333;
+    (bits | bits << 1) & 0x5555_5555_5555_5555
+}
+
+/// Gather the even bits of `bits` into the low 32 bits of the result, undoing [`spread`].
+const fn squash(bits: u64) -> u64 {
+    let mut bits = bits & 0x5555_5555_5555_5555;
+    bits = (bits | bits >> 1) & 0x3333_3333_3333_3333;
+    bits = (bits | bits >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    bits = (bits | bits >> 4) & 0x00ff_00ff_00ff_00ff;
+    bits = (bits | bits >> 8) & 0x0000_ffff_0000_ffff;
+    (bits | bits >> 16) & 0xffff_ffff
+}
+
+/// The units that distances can be given in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DistanceUnit {
+    #[default]
+    Meters,
+    Kilometers,
+    Miles,
+    Feet,
+}
+
+impl DistanceUnit {
+    /// How many meters one of this unit is.
+    pub const fn meters(self) -> f64 {
+        match self {
+            Self::Meters => 1.0,
+            Self::Kilometers => 1000.0,
+            Self::Miles => 1609.34,
+            Self::Feet => 0.3048,
+        }
+    }
+}
+
+/// Where a `GEOSEARCH` is centered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeoOrigin {
+    /// `FROMMEMBER`: at the location of a member of the sorted set.
+    Member(String),
+    /// `FROMLONLAT`: at the given coordinates.
+    Coordinates(Coordinates),
+}
+
+/// The area covered by a `GEOSEARCH`, with sizes in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    /// `BYRADIUS`: a circle.
+    Radius(f64),
+    /// `BYBOX`: an axis-aligned rectangle.
+    Box { width: f64, height: f64 },
+}
+
+// Sizes are parsed to be finite, so they are never `NaN`.
+impl Eq for GeoShape {}
+
+/// The options of `GEOSEARCH` and `GEOSEARCHSTORE` that pick the matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoSearch {
+    pub origin: GeoOrigin,
+    pub shape: GeoShape,
+    /// The unit of the sizes of the `shape` as given, and of the distances in the results.
+    pub unit: DistanceUnit,
+    /// Sort the matches by their distance, nearest first unless `descending`.
+    pub sort: Option<bool>,
+    /// Return at most this many matches.
+    pub count: Option<usize>,
+    /// `ANY`: return the first `count` matches found, instead of the nearest ones.
+    pub any: bool,
+}
+
+/// A member found by a `GEOSEARCH`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoMatch {
+    pub member: String,
+    /// The distance to the center of the search area, in the unit of the search.
+    pub distance: f64,
+    pub hash: u64,
+    pub coordinates: Coordinates,
+}
+
+impl GeoShape {
+    /// Get the distance from `center` to `point` if it lies within this shape around `center`.
+    fn distance(self, center: Coordinates, point: Coordinates) -> Option<f64> {
+        match self {
+            Self::Radius(radius) => Some(center.distance(point)).filter(|&d| d <= radius),
+            Self::Box { width, height } => {
+                // The latitude is cheaper to check, and the longitude is measured along
+                // the parallel of the point, both just like Redis does.
+                let along_meridian = Coordinates {
+                    longitude: point.longitude,
+                    ..center
+                };
+                let along_parallel = Coordinates {
+                    latitude: point.latitude,
+                    ..center
+                };
+                (along_meridian.distance(point) <= height / 2.0
+                    && along_parallel.distance(point) <= width / 2.0)
+                    .then(|| center.distance(point))
+            }
+        }
+    }
+}
+
+impl GeoSearch {
+    /// Find the members of `zset` within the search area.
+    fn run(&self, zset: &SortedSet) -> Result<Vec<GeoMatch>, Error> {
+        let center = match &self.origin {
+            GeoOrigin::Coordinates(coordinates) => *coordinates,
+            GeoOrigin::Member(member) => zset
+                .score(member)
+                .map(Coordinates::from_score)
+                .ok_or(Error::UndecodableMember)?,
+        };
+        let meters = self.unit.meters();
+        let shape = match self.shape {
+            GeoShape::Radius(radius) => GeoShape::Radius(radius * meters),
+            GeoShape::Box { width, height } => GeoShape::Box {
+                width: width * meters,
+                height: height * meters,
+            },
+        };
+        let matches = zset.iter().filter_map(|(member, score)| {
+            let coordinates = Coordinates::from_score(score);
+            let distance = shape.distance(center, coordinates)?;
+            Some(GeoMatch {
+                member: member.clone(),
+                distance: distance / meters,
+                hash: score.0 as u64,
+                coordinates,
+            })
+        });
+        let mut matches: Vec<_> = match self.count {
+            Some(count) if self.any => matches.take(count).collect(),
+            _ => matches.collect(),
+        };
+        // Without `ANY`, a count keeps the nearest matches.
+        let sort = self
+            .sort
+            .or((self.count.is_some() && !self.any).then_some(false));
+        if let Some(descending) = sort {
+            matches.sort_by(|a, b| {
+                let ordering = a
+                    .distance
+                    .partial_cmp(&b.distance)
+                    .unwrap_or(Ordering::Equal);
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        matches.truncate(self.count.unwrap_or(usize::MAX));
+        Ok(matches)
+    }
+}
+
+impl Database {
+    /// Add the `locations` to the sorted set stored at `key`, or move the ones already
+    /// present, according to the `options` (which exclude [`ZAddOptions::greater`],
+    /// [`ZAddOptions::less`] and [`ZAddOptions::increment`]).
+    ///
+    /// Nothing is added if any of the coordinates cannot be indexed.
+    /// Returns the same count as [`Database::zadd`].
+    #[instrument(name = "db_geoadd", skip(self))]
+    pub fn geoadd(
+        &mut self,
+        key: Key,
+        options: ZAddOptions,
+        locations: Vec<(Coordinates, String)>,
+    ) -> Result<usize, Error> {
+        let members = locations
+            .into_iter()
+            .map(|(coordinates, member)| Ok((coordinates.validate()?.score(), member)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.zadd(key, options, members)
+    }
+
+    /// Get the coordinates of each of the `members` of the sorted set stored at `key`.
+    #[instrument(name = "db_geopos", skip(self))]
+    pub fn geopos(&self, key: &str, members: &[String]) -> Result<Vec<Option<Coordinates>>, Error> {
+        let zset = self.lookup_zset(key)?;
+        Ok(members
+            .iter()
+            .map(|member| zset.and_then(|zset| zset.score(member)))
+            .map(|score| score.map(Coordinates::from_score))
+            .collect())
+    }
+
+    /// Get the distance between the members `from` and `to` of the sorted set stored at `key`,
+    /// or [`None`] if either of them is missing.
+    #[instrument(name = "db_geodist", skip(self))]
+    pub fn geodist(
+        &self,
+        key: &str,
+        from: &str,
+        to: &str,
+        unit: DistanceUnit,
+    ) -> Result<Option<f64>, Error> {
+        let Some(zset) = self.lookup_zset(key)? else {
+            return Ok(None);
+        };
+        let (Some(from), Some(to)) = (zset.score(from), zset.score(to)) else {
+            return Ok(None);
+        };
+        let meters = Coordinates::from_score(from).distance(Coordinates::from_score(to));
+        Ok(Some(meters / unit.meters()))
+    }
+
+    /// Find the members of the sorted set stored at `key` within the area of the `search`.
+    #[instrument(name = "db_geosearch", skip(self))]
+    pub fn geosearch(&self, key: &str, search: &GeoSearch) -> Result<Vec<GeoMatch>, Error> {
+        self.lookup_zset(key)?
+            .map_or(Ok(vec![]), |zset| search.run(zset))
+    }
+
+    /// Store the members of the sorted set stored at `source` within the area of the `search`
+    /// at `destination`, scored by their geohashes, or by their distances if `store_distance`.
+    ///
+    /// If `destination` already exists, it is overwritten. If nothing matches, `destination`
+    /// is removed instead. Returns the number of matches.
+    #[instrument(name = "db_geosearchstore", skip(self))]
+    pub fn geosearchstore(
+        &mut self,
+        destination: Key,
+        source: &str,
+        search: &GeoSearch,
+        store_distance: bool,
+    ) -> Result<usize, Error> {
+        let matches = self.geosearch(source, search)?;
+        let count = matches.len();
+        let result: SortedSet = matches
+            .into_iter()
+            .map(|found| {
+                let score = if store_distance {
+                    Score(found.distance)
+                } else {
+                    Score(found.hash as f64)
+                };
+                (found.member, score)
+            })
+            .collect();
+        let result = (!result.is_empty()).then_some(Data::SortedSet(result));
+        self.store(destination, result, Class::SortedSet, "geosearchstore");
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coordinates, DistanceUnit, GeoOrigin, GeoSearch, GeoShape};
+    use crate::database::{Database, Error, Score, ZAddOptions};
+
+    const PALERMO: Coordinates = Coordinates {
+        longitude: 13.361_389,
+        latitude: 38.115_556,
+    };
+    const CATANIA: Coordinates = Coordinates {
+        longitude: 15.087_269,
+        latitude: 37.502_669,
+    };
+
+    fn sicily() -> Database {
+        let mut db = Database::new();
+        let locations = vec![
+            (PALERMO, "Palermo".to_string()),
+            (CATANIA, "Catania".to_string()),
+        ];
+        let added = db.geoadd("Sicily".into(), ZAddOptions::default(), locations);
+        assert_eq!(added, Ok(2));
+        db
+    }
+
+    #[test]
+    fn geohash_scores() {
+        // The scores that Redis gives to the same locations.
+        assert_eq!(PALERMO.score(), Score(3_479_099_956_230_698.0));
+        assert_eq!(CATANIA.score(), Score(3_479_447_370_796_909.0));
+        let decoded = Coordinates::from_score(PALERMO.score());
+        assert!((decoded.longitude - 13.361_389_338_970_184).abs() < 1e-12);
+        assert!((decoded.latitude - 38.115_556_395_496_3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn positions_and_distances() {
+        let db = sicily();
+        let positions = db.geopos("Sicily", &["Palermo".into(), "Rome".into()]);
+        let positions = positions.unwrap();
+        assert!(positions[0].is_some());
+        assert_eq!(positions[1], None);
+        assert_eq!(db.geopos("nope", &["Palermo".into()]), Ok(vec![None]));
+
+        let distance = |unit| {
+            db.geodist("Sicily", "Palermo", "Catania", unit)
+                .unwrap()
+                .map(|distance| format!("{distance:.4}"))
+        };
+        assert_eq!(distance(DistanceUnit::Meters).unwrap(), "166274.1516");
+        assert_eq!(distance(DistanceUnit::Kilometers).unwrap(), "166.2742");
+        assert_eq!(distance(DistanceUnit::Miles).unwrap(), "103.3182");
+        let missing = db.geodist("Sicily", "Palermo", "Rome", DistanceUnit::Meters);
+        assert_eq!(missing, Ok(None));
+    }
+
+    fn search(origin: GeoOrigin, shape: GeoShape, unit: DistanceUnit) -> GeoSearch {
+        GeoSearch {
+            origin,
+            shape,
+            unit,
+            sort: None,
+            count: None,
+            any: false,
+        }
+    }
+
+    #[test]
+    fn searches() {
+        let mut db = sicily();
+        let locations = vec![
+            (
+                Coordinates {
+                    longitude: 12.758_489,
+                    latitude: 38.788_135,
+                },
+                "edge1".to_string(),
+            ),
+            (
+                Coordinates {
+                    longitude: 17.241_510,
+                    latitude: 38.788_135,
+                },
+                "edge2".to_string(),
+            ),
+        ];
+        let _ = db.geoadd("Sicily".into(), ZAddOptions::default(), locations);
+        let members = |matches: Vec<super::GeoMatch>| -> Vec<String> {
+            matches.into_iter().map(|found| found.member).collect()
+        };
+
+        // The examples of the Redis documentation of `GEOSEARCH`.
+        let center = GeoOrigin::Coordinates(Coordinates {
+            longitude: 15.0,
+            latitude: 37.0,
+        });
+        let mut by_radius = search(
+            center.clone(),
+            GeoShape::Radius(200.0),
+            DistanceUnit::Kilometers,
+        );
+        by_radius.sort = Some(false);
+        let found = db.geosearch("Sicily", &by_radius).unwrap();
+        assert_eq!(members(found.clone()), ["Catania", "Palermo"]);
+        assert_eq!(format!("{:.4}", found[0].distance), "56.4413");
+        assert_eq!(format!("{:.4}", found[1].distance), "190.4424");
+
+        let by_box = GeoShape::Box {
+            width: 400.0,
+            height: 400.0,
+        };
+        let mut by_box = search(center, by_box, DistanceUnit::Kilometers);
+        by_box.sort = Some(false);
+        let found = db.geosearch("Sicily", &by_box).unwrap();
+        assert_eq!(members(found), ["Catania", "Palermo", "edge2", "edge1"]);
+
+        by_box.sort = Some(true);
+        by_box.count = Some(1);
+        let found = db.geosearch("Sicily", &by_box).unwrap();
+        assert_eq!(members(found), ["edge1"]);
+
+        // Without an order, a count still keeps the nearest members.
+        let from_palermo = search(
+            GeoOrigin::Member("Palermo".into()),
+            GeoShape::Radius(1000.0),
+            DistanceUnit::Kilometers,
+        );
+        let nearest = GeoSearch {
+            count: Some(2),
+            ..from_palermo.clone()
+        };
+        let found = db.geosearch("Sicily", &nearest).unwrap();
+        assert_eq!(members(found), ["Palermo", "edge1"]);
+
+        let missing = GeoSearch {
+            origin: GeoOrigin::Member("Rome".into()),
+            ..from_palermo
+        };
+        assert_eq!(
+            db.geosearch("Sicily", &missing),
+            Err(Error::UndecodableMember)
+        );
+        assert_eq!(db.geosearch("nope", &missing), Ok(vec![]));
+
+        assert_eq!(
+            db.geosearchstore("near".into(), "Sicily", &nearest, true),
+            Ok(2)
+        );
+        assert_eq!(db.zscore("near", "Palermo"), Ok(Some(Score(0.0))));
+        assert_eq!(
+            db.geosearchstore("near".into(), "Sicily", &missing, false),
+            Err(Error::UndecodableMember)
+        );
+        assert_eq!(
+            db.geosearchstore("near".into(), "nope", &nearest, false),
+            Ok(0)
+        );
+        assert_eq!(db.zcard("near"), Ok(0));
+    }
+
+    #[test]
+    fn rejects_unindexable_coordinates() {
+        let mut db = sicily();
+        let north_pole = Coordinates {
+            longitude: 0.0,
+            latitude: 90.0,
+        };
+        let locations = vec![
+            (PALERMO, "Elsewhere".to_string()),
+            (north_pole, "North Pole".to_string()),
+        ];
+        let added = db.geoadd("Sicily".into(), ZAddOptions::default(), locations);
+        assert_eq!(
+            added,
+            Err(Error::InvalidCoordinates("0.000000,90.000000".into()))
+        );
+        assert_eq!(db.zcard("Sicily"), Ok(2));
+    }
+}
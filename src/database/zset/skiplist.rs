@@ -0,0 +1,370 @@
+//! # The skip list encoding of sorted sets, for when they outgrow a plain sorted [`Vec`].
+//!
+//! This follows the design of the Redis skip list: every link knows its *span*,
+//! the number of entries it jumps over, so positions can be found and counted
+//! in `O(log n)` on top of the usual lookups, and every node links back to its
+//! predecessor, so ranges can be walked in both directions.
+//!
+//! Nodes live in an arena and refer to each other by index, which keeps the whole
+//! thing free of `unsafe` code. The slots of removed nodes get reused.
+
+use super::{Ordered, Score};
+use crate::random::Rng;
+use std::ops::Range;
+
+/// The maximum number of levels, enough for 2^64 entries with [`LEVEL_UP_ONE_IN`].
+const MAX_LEVEL: usize = 32;
+
+/// A node gets promoted to every next level with a probability of 1 in this.
+const LEVEL_UP_ONE_IN: u64 = 4;
+
+/// The index of a [`Node`] in the arena, or [`None`] for the head of the list.
+type NodeId = Option<usize>;
+
+/// A link from a node to the next one on some level.
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    next: NodeId,
+    /// How many entries the link jumps over, counting the one it leads to.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    score: Score,
+    member: String,
+    levels: Vec<Link>,
+    backward: NodeId,
+}
+
+/// An ordered index of `(score, member)` entries, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    head: [Link; MAX_LEVEL],
+    tail: NodeId,
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    level: usize,
+    len: usize,
+    rng: Rng,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self {
+            head: [Link::default(); MAX_LEVEL],
+            tail: None,
+            nodes: vec![],
+            free: vec![],
+            level: 1,
+            len: 0,
+            rng: Rng::new(),
+        }
+    }
+}
+
+impl SkipList {
+    fn node(&self, id: usize) -> &Node {
+        self.nodes[id]
+            .as_ref()
+            .expect("links only lead to live nodes")
+    }
+
+    fn node_mut(&mut self, id: usize) -> &mut Node {
+        self.nodes[id]
+            .as_mut()
+            .expect("links only lead to live nodes")
+    }
+
+    fn link(&self, from: NodeId, level: usize) -> Link {
+        match from {
+            None => self.head[level],
+            Some(id) => self.node(id).levels[level],
+        }
+    }
+
+    fn link_mut(&mut self, from: NodeId, level: usize) -> &mut Link {
+        match from {
+            None => &mut self.head[level],
+            Some(id) => &mut self.node_mut(id).levels[level],
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL && self.rng.next_u64() % LEVEL_UP_ONE_IN == 0 {
+            level += 1;
+        }
+        level
+    }
+
+    /// Walk down the list, staying before the first entry for which `advance` is false.
+    ///
+    /// Returns the last node visited on each level, along with the number
+    /// of entries that the walk went past by the time it reached that node.
+    fn descend(
+        &self,
+        advance: impl Fn(&Node, usize) -> bool,
+    ) -> ([NodeId; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [None; MAX_LEVEL];
+        let mut ranks = [0; MAX_LEVEL];
+        let (mut at, mut rank) = (None, 0);
+        for level in (0..self.level).rev() {
+            loop {
+                let link = self.link(at, level);
+                match link.next {
+                    Some(next) if advance(self.node(next), rank + link.span) => {
+                        rank += link.span;
+                        at = Some(next);
+                    }
+                    _ => break,
+                }
+            }
+            update[level] = at;
+            ranks[level] = rank;
+        }
+        (update, ranks)
+    }
+
+    /// The node at the 0-based `index`, if any.
+    fn node_at(&self, index: usize) -> NodeId {
+        if index >= self.len {
+            return None;
+        }
+        if index + 1 == self.len {
+            return self.tail;
+        }
+        self.descend(|_, rank| rank <= index + 1).0[0]
+    }
+
+    /// Unlink the node `id`, given the last node before it on every level.
+    fn unlink(&mut self, id: usize, update: &[NodeId; MAX_LEVEL]) -> (Score, String) {
+        for (level, &from) in update.iter().enumerate().take(self.level) {
+            let link = self.link(from, level);
+            if link.next == Some(id) {
+                let skipped = self.node(id).levels[level];
+                *self.link_mut(from, level) = Link {
+                    next: skipped.next,
+                    span: link.span + skipped.span - 1,
+                };
+            } else {
+                self.link_mut(from, level).span -= 1;
+            }
+        }
+        let node = self.nodes[id]
+            .take()
+            .expect("links only lead to live nodes");
+        match node.levels[0].next {
+            Some(next) => self.node_mut(next).backward = node.backward,
+            None => self.tail = node.backward,
+        }
+        while self.level > 1 && self.head[self.level - 1].next.is_none() {
+            self.level -= 1;
+        }
+        self.free.push(id);
+        self.len -= 1;
+        (node.score, node.member)
+    }
+}
+
+impl Ordered for SkipList {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn insert(&mut self, score: Score, member: String) {
+        let key = (score, member.as_str());
+        let (mut update, mut ranks) =
+            self.descend(|node, _| (node.score, node.member.as_str()) < key);
+
+        let level = self.random_level();
+        for new_level in self.level..level {
+            ranks[new_level] = 0;
+            update[new_level] = None;
+            self.head[new_level].span = self.len;
+        }
+        self.level = self.level.max(level);
+
+        let mut levels = vec![Link::default(); level];
+        for (index, link) in levels.iter_mut().enumerate() {
+            let previous = self.link(update[index], index);
+            let distance = ranks[0] - ranks[index];
+            link.next = previous.next;
+            link.span = previous.span - distance;
+        }
+        let node = Node {
+            score,
+            member,
+            levels,
+            backward: update[0],
+        };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = Some(node);
+                id
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        for index in 0..level {
+            *self.link_mut(update[index], index) = Link {
+                next: Some(id),
+                span: ranks[0] - ranks[index] + 1,
+            };
+        }
+        for (index, &from) in update.iter().enumerate().take(self.level).skip(level) {
+            self.link_mut(from, index).span += 1;
+        }
+        match self.node(id).levels[0].next {
+            Some(next) => self.node_mut(next).backward = Some(id),
+            None => self.tail = Some(id),
+        }
+        self.len += 1;
+    }
+
+    fn remove(&mut self, score: Score, member: &str) -> bool {
+        let key = (score, member);
+        let (update, _) = self.descend(|node, _| (node.score, node.member.as_str()) < key);
+        match self.link(update[0], 0).next {
+            Some(id) if (self.node(id).score, self.node(id).member.as_str()) == key => {
+                let _ = self.unlink(id, &update);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rank(&self, score: Score, member: &str) -> Option<usize> {
+        let key = (score, member);
+        let (update, ranks) = self.descend(|node, _| (node.score, node.member.as_str()) <= key);
+        let id = update[0]?;
+        ((self.node(id).score, self.node(id).member.as_str()) == key).then(|| ranks[0] - 1)
+    }
+
+    fn partition_point(&self, predicate: &dyn Fn(Score, &str) -> bool) -> usize {
+        self.descend(|node, _| predicate(node.score, &node.member))
+            .1[0]
+    }
+
+    fn range(&self, positions: Range<usize>) -> super::Iter<'_> {
+        let positions = positions.start..positions.end.min(self.len);
+        super::Iter::SkipList(Iter {
+            list: self,
+            front: self.node_at(positions.start),
+            back: positions
+                .end
+                .checked_sub(1)
+                .and_then(|last| self.node_at(last)),
+            remaining: positions.len(),
+        })
+    }
+
+    fn drain(&mut self, positions: Range<usize>) -> Vec<(Score, String)> {
+        let (update, _) = self.descend(|_, rank| rank <= positions.start);
+        let mut at = self.link(update[0], 0).next;
+        let mut removed = Vec::with_capacity(positions.len());
+        while let Some(id) = at.filter(|_| removed.len() < positions.len()) {
+            at = self.node(id).levels[0].next;
+            removed.push(self.unlink(id, &update));
+        }
+        removed
+    }
+}
+
+/// An iterator over a range of [`SkipList`] entries, see [`Ordered::range`].
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: NodeId,
+    back: NodeId,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, Score);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let node = self.list.node(self.front?);
+        self.front = node.levels[0].next;
+        Some((&node.member, node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let node = self.list.node(self.back?);
+        self.back = node.backward;
+        Some((&node.member, node.score))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ordered, Score, SkipList};
+    use crate::random::Rng;
+
+    fn entries<'a>(iter: impl Iterator<Item = (&'a String, Score)>) -> Vec<(Score, String)> {
+        iter.map(|(member, score)| (score, member.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn matches_a_sorted_vec() {
+        let mut list = SkipList::default();
+        let mut expected: Vec<(Score, String)> = vec![];
+        let mut rng = Rng::with_seed(42);
+        for step in 0..2000 {
+            let score = Score((rng.next_u64() % 50) as f64);
+            let member = format!("m{}", rng.next_u64() % 300);
+            match expected.iter().position(|(_, m)| *m == member) {
+                Some(index) if step % 3 == 0 => {
+                    let (score, member) = expected.remove(index);
+                    assert!(list.remove(score, &member));
+                }
+                Some(_) => {}
+                None => {
+                    list.insert(score, member.clone());
+                    expected.push((score, member));
+                    expected.sort();
+                }
+            }
+            assert_eq!(list.len(), expected.len());
+        }
+
+        for (index, (score, member)) in expected.iter().enumerate() {
+            assert_eq!(list.rank(*score, member), Some(index));
+        }
+        assert_eq!(list.rank(Score(1000.0), "m0"), None);
+        let below_ten = expected
+            .as_slice()
+            .partition_point(|(score, _)| *score < Score(10.0));
+        assert_eq!(
+            list.partition_point(&|score, _| score < Score(10.0)),
+            below_ten
+        );
+
+        let len = expected.len();
+        assert_eq!(entries(list.range(0..len)), expected);
+        assert_eq!(entries(list.range(5..25)), expected[5..25]);
+        let mut reversed = expected[10..30].to_vec();
+        reversed.reverse();
+        assert_eq!(entries(list.range(10..30).rev()), reversed);
+        assert_eq!(list.range(len..len + 3).len(), 0);
+
+        assert_eq!(list.drain(3..13), expected.drain(3..13).collect::<Vec<_>>());
+        assert_eq!(entries(list.range(0..list.len())), expected);
+        for (index, (score, member)) in expected.iter().enumerate() {
+            assert_eq!(list.rank(*score, member), Some(index));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! # Glob-style pattern matching, as used by `SCAN MATCH`, `KEYS` and friends.
+//!
+//! Supported patterns, just like in Redis:
+//!
+//! - `?` matches exactly one character.
+//! - `*` matches any number of characters, including none.
+//! - `[abc]` matches one of the listed characters, `[^abc]` any other one,
+//!   and `[a-z]` any character in the range.
+//! - `\` escapes the following character, so that it matches literally.
+
+/// Check whether `string` matches the glob-style `pattern`.
+pub fn matches(pattern: &str, string: &str) -> bool {
+    matches_bytes(pattern.as_bytes(), string.as_bytes())
+}
+
+fn matches_bytes(pattern: &[u8], string: &[u8]) -> bool {
+    // Backtracking only ever needs to resume after the last `*` seen so far.
+    let (mut p, mut s) = (0, 0);
+    let mut resume: Option<(usize, usize)> = None;
+    while s < string.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                resume = Some((p + 1, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(&pattern[p..], string[s]).map(|len| p + len),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == string[s]).then_some(p + 2),
+            Some(&literal) => (literal == string[s]).then_some(p + 1),
+            None => None,
+        };
+        match (step, resume) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            (None, Some((star_p, star_s))) => {
+                p = star_p;
+                s = star_s + 1;
+                resume = Some((star_p, star_s + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Match a single `byte` against the character class at the start of `class`.
+///
+/// Returns the length of the class in the pattern if the `byte` matches.
+fn match_class(class: &[u8], byte: u8) -> Option<usize> {
+    let mut i = 1;
+    let negated = class.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    loop {
+        match class.get(i) {
+            // An unterminated class is treated as if it ended with the pattern.
+            None => break,
+            Some(b']') => {
+                i += 1;
+                break;
+            }
+            Some(b'\\') if i + 1 < class.len() => {
+                matched |= class[i + 1] == byte;
+                i += 2;
+            }
+            Some(&start) if class.get(i + 1) == Some(&b'-') && i + 2 < class.len() => {
+                let end = class[i + 2];
+                let (low, high) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= (low..=high).contains(&byte);
+                i += 3;
+            }
+            Some(&literal) => {
+                matched |= literal == byte;
+                i += 1;
+            }
+        }
+    }
+    (matched != negated).then_some(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("user:*:name", "user:42:name"));
+        assert!(!matches("user:*:name", "user:42:age"));
+        assert!(matches("*a*b*c", "xaybzc"));
+        assert!(!matches("*a*b*c", "xaybz"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(!matches("h[a-b]llo", "hcllo"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(r"h\*llo", "h*llo"));
+        assert!(!matches(r"h\*llo", "hello"));
+        assert!(matches(r"[\]]", "]"));
+    }
+}
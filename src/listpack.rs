@@ -0,0 +1,233 @@
+//! # Listpacks, the compact lists that Redis stores small collections in.
+//!
+//! RDB files hold the entries of streams as listpacks, see [`rdb`](crate::rdb).
+//! A listpack is a 4-byte total length and a 2-byte element count, both little-endian,
+//! then the elements, and a final `0xFF`. Every element is an encoding byte, its data,
+//! and the length of those two written backwards, so that the list can be walked both ways:
+//!
+//! - `0xxxxxxx`: A 7-bit unsigned integer.
+//! - `10LLLLLL`: A string of up to 63 bytes follows.
+//! - `110xxxxx`: A 13-bit signed integer, continuing with the next byte.
+//! - `1110LLLL`: A string of up to 4095 bytes follows, its length continuing with the next byte.
+//! - `0xF0`: A string follows, after its 32-bit length.
+//! - `0xF1` to `0xF4`: A 16, 24, 32 or 64-bit signed integer follows.
+//!
+//! All the multi-byte integers are little-endian.
+
+/// The byte that ends every listpack.
+const END: u8 = 0xFF;
+
+/// The element count of listpacks with more elements than it can tell.
+const UNKNOWN_COUNT: u16 = u16::MAX;
+
+/// An element of a listpack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Integer(i64),
+    String(Vec<u8>),
+}
+
+impl Element {
+    /// The element as the string that Redis would reply with.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Integer(integer) => integer.to_string().into_bytes(),
+            Self::String(string) => string,
+        }
+    }
+
+    /// The element as an integer, if it is one.
+    pub fn integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(integer) => Some(*integer),
+            Self::String(string) => std::str::from_utf8(string).ok()?.parse().ok(),
+        }
+    }
+}
+
+impl From<i64> for Element {
+    fn from(integer: i64) -> Self {
+        Self::Integer(integer)
+    }
+}
+
+impl From<&str> for Element {
+    fn from(string: &str) -> Self {
+        Self::String(string.as_bytes().to_vec())
+    }
+}
+
+/// Encode `elements` as a listpack.
+pub fn encode(elements: &[Element]) -> Vec<u8> {
+    let mut out = vec![0; 6];
+    for element in elements {
+        let start = out.len();
+        match element {
+            Element::Integer(integer @ 0..=0x7F) => out.push(*integer as u8),
+            Element::Integer(integer @ -4096..=4095) => {
+                let bits = (*integer as u16) & 0x1FFF;
+                out.extend_from_slice(&[0xC0 | (bits >> 8) as u8, bits as u8]);
+            }
+            Element::Integer(integer) => {
+                let (encoding, width) = match integer {
+                    -0x8000..=0x7FFF => (0xF1, 2),
+                    -0x80_0000..=0x7F_FFFF => (0xF2, 3),
+                    -0x8000_0000..=0x7FFF_FFFF => (0xF3, 4),
+                    _ => (0xF4, 8),
+                };
+                out.push(encoding);
+                out.extend_from_slice(&integer.to_le_bytes()[..width]);
+            }
+            Element::String(string) => {
+                match string.len() {
+                    len @ 0..=0x3F => out.push(0x80 | len as u8),
+                    len @ 0x40..=0xFFF => {
+                        out.extend_from_slice(&[0xE0 | (len >> 8) as u8, len as u8])
+                    }
+                    len => {
+                        out.push(0xF0);
+                        out.extend_from_slice(&(len as u32).to_le_bytes());
+                    }
+                }
+                out.extend_from_slice(string);
+            }
+        }
+        let len = out.len() - start;
+        write_backlen(&mut out, len);
+    }
+    out.push(END);
+    let total = out.len() as u32;
+    let count = u16::try_from(elements.len()).unwrap_or(UNKNOWN_COUNT);
+    out[..4].copy_from_slice(&total.to_le_bytes());
+    out[4..6].copy_from_slice(&count.to_le_bytes());
+    out
+}
+
+/// Write the length of an element backwards, 7 bits per byte, with all but the first
+/// byte flagged with the top bit, so that a reader going backwards knows where it ends.
+/// The lengths at which it takes another byte are off by one, but that's how Redis has it.
+fn write_backlen(out: &mut Vec<u8>, len: usize) {
+    let groups = match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2_097_150 => 3,
+        2_097_151..=268_435_454 => 4,
+        _ => 5,
+    };
+    for group in (0..groups).rev() {
+        let bits = (len >> (7 * group)) as u8 & 0x7F;
+        out.push(if group == groups - 1 {
+            bits
+        } else {
+            bits | 0x80
+        });
+    }
+}
+
+/// Decode a listpack, or [`None`] if it is malformed.
+pub fn decode(bytes: &[u8]) -> Option<Vec<Element>> {
+    let total = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    if total != bytes.len() || bytes.last() != Some(&END) {
+        return None;
+    }
+    let mut elements = vec![];
+    let mut rest = &bytes[6..bytes.len() - 1];
+    while let Some(&encoding) = rest.first() {
+        let take = |start: usize, len: usize| rest.get(start..start + len);
+        let (element, len) = match encoding {
+            0x00..=0x7F => (Element::Integer(encoding.into()), 1),
+            0x80..=0xBF => {
+                let len = usize::from(encoding & 0x3F);
+                (Element::String(take(1, len)?.to_vec()), 1 + len)
+            }
+            0xC0..=0xDF => {
+                let bits = u16::from_be_bytes([encoding & 0x1F, *rest.get(1)?]);
+                // Shift the sign bit of the 13 bits up to the top, and back with the sign.
+                (Element::Integer(i64::from((bits << 3) as i16 >> 3)), 2)
+            }
+            0xE0..=0xEF => {
+                let len = usize::from(u16::from_be_bytes([encoding & 0x0F, *rest.get(1)?]));
+                (Element::String(take(2, len)?.to_vec()), 2 + len)
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(take(1, 4)?.try_into().ok()?) as usize;
+                (Element::String(take(5, len)?.to_vec()), 5 + len)
+            }
+            0xF1..=0xF4 => {
+                let width = [2, 3, 4, 8][usize::from(encoding - 0xF1)];
+                let mut integer = [0; 8];
+                integer[..width].copy_from_slice(take(1, width)?);
+                // Sign-extend from the top byte that was read.
+                let shift = 64 - 8 * width as u32;
+                let integer = i64::from_le_bytes(integer) << shift >> shift;
+                (Element::Integer(integer), 1 + width)
+            }
+            _ => return None,
+        };
+        let mut backlen = vec![];
+        write_backlen(&mut backlen, len);
+        if take(len, backlen.len())? != backlen.as_slice() {
+            return None;
+        }
+        elements.push(element);
+        rest = &rest[len + backlen.len()..];
+    }
+    Some(elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, write_backlen, Element};
+
+    #[test]
+    fn round_trip() {
+        let long = "x".repeat(100);
+        let longer = "y".repeat(5000);
+        let elements: Vec<Element> = vec![
+            0.into(),
+            127.into(),
+            128.into(),
+            (-1).into(),
+            (-4096).into(),
+            4096.into(),
+            (-70_000).into(),
+            (1 << 30).into(),
+            i64::MIN.into(),
+            "".into(),
+            "fruit".into(),
+            long.as_str().into(),
+            longer.as_str().into(),
+        ];
+        let encoded = encode(&elements);
+        assert_eq!(u16::from_le_bytes([encoded[4], encoded[5]]), 13);
+        assert_eq!(decode(&encoded), Some(elements));
+    }
+
+    #[test]
+    fn layout() {
+        // As Redis would write `["a", 1]`.
+        let expected = [0x0C, 0, 0, 0, 0x02, 0, 0x81, b'a', 0x02, 0x01, 0x01, 0xFF];
+        assert_eq!(encode(&["a".into(), 1.into()]), expected);
+        let mut backlen = vec![];
+        write_backlen(&mut backlen, 200);
+        assert_eq!(backlen, [0x01, 0xC8]);
+        backlen.clear();
+        write_backlen(&mut backlen, 16383);
+        assert_eq!(backlen, [0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut encoded = encode(&["apple".into()]);
+        assert_eq!(decode(&encoded[..encoded.len() - 1]), None);
+        encoded[6] = 0x85 + 1;
+        assert_eq!(decode(&encoded), None);
+    }
+
+    #[test]
+    fn integers_as_strings() {
+        assert_eq!(Element::from(-42).into_bytes(), b"-42");
+        assert_eq!(Element::from("42").integer(), Some(42));
+        assert_eq!(Element::from("4x").integer(), None);
+    }
+}
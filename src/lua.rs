@@ -0,0 +1,74 @@
+//! # A small Lua interpreter, for running the scripts of `EVAL`.
+//!
+//! Redis embeds Lua 5.1, but no Lua crate can be embedded here, so this is an interpreter
+//! of the subset of Lua 5.1 that scripts actually use, written from scratch the same way
+//! [`compress`](crate::compress) does LZ4:
+//!
+//! - All of the syntax, walked as a tree instead of compiled to bytecode. There are no
+//!   coroutines, no metatables, and integers are doubles, just like in Lua 5.1.
+//! - The base library, along with `string` (patterns included), `table` and `math`. There
+//!   is nothing that reaches outside of the script, like `io`, `os`, `require` or `load`,
+//!   and neither `cjson`, `cmsgpack`, `struct` nor `bit` are there yet.
+//! - Like in Redis, scripts can't create global variables, and reading a global that
+//!   doesn't exist is an error rather than `nil`, to catch typos early.
+//!
+//! Scripts run while the whole database stays locked, so every script is capped by its
+//! [`Limits`]: one on how many instructions it may run (counting statements, loop
+//! iterations and function calls), and one on how many bytes it may allocate in total
+//! (counting strings, tables and closures, approximately). Either one stops the script
+//! with an error that `pcall` can't catch, instead of blocking the server indefinitely.
+
+mod interpreter;
+mod lexer;
+mod parser;
+mod pattern;
+mod stdlib;
+mod value;
+
+pub use interpreter::Interpreter;
+pub use value::{Builtin, Table, Value};
+
+use std::thread;
+
+/// The stack size of the threads that scripts run on, see [`isolated`].
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Possible errors that can arise while running a script.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The script does not compile, with the message saying where and why.
+    #[error("{0}")]
+    Syntax(String),
+    /// An error raised while running, either by `error()` or by the interpreter itself.
+    ///
+    /// Like in Lua, the error can be any value, usually a message along with where it was raised.
+    #[error("{0}")]
+    Runtime(Value),
+    #[error("Script used more than {0} bytes of memory")]
+    Memory(usize),
+    #[error("Script ran more than {0} instructions")]
+    Instructions(u64),
+}
+
+/// The caps on the resources of a single script, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// How many bytes the script may allocate in total, even if it frees them again.
+    pub memory: usize,
+    /// How many statements, loop iterations and function calls the script may run.
+    pub instructions: u64,
+}
+
+/// Run `f` on a thread of its own, with a stack large enough for scripts to recurse as
+/// deeply as the interpreter lets them, which the default stack of a thread is not.
+pub fn isolated<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .name("lua".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("Could not spawn a thread for the script")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
@@ -0,0 +1,794 @@
+//! # Running the syntax tree, within the [`Limits`] of the script.
+
+use super::parser::{self, BinaryOp, Block, Expr, Field, Stat, StatKind, UnaryOp};
+use super::value::{format_number, Closure, Table, Value};
+use super::{stdlib, Error, Limits};
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::rc::Rc;
+
+/// How deeply Lua functions may call each other, which keeps the Rust stack in check too.
+const MAX_CALL_DEPTH: usize = 200;
+
+/// Roughly how many bytes a table costs, besides its contents.
+const TABLE_COST: usize = 64;
+/// Roughly how many bytes a field of a table costs, besides its value.
+const FIELD_COST: usize = 32;
+/// Roughly how many bytes a closure costs.
+const CLOSURE_COST: usize = 64;
+
+/// Runs the commands of `redis.call` and `redis.pcall`, see [`Interpreter::new`].
+pub type Host<'a> = dyn FnMut(Vec<String>) -> Value + 'a;
+
+/// Runs Lua scripts, see the [module docs](super).
+pub struct Interpreter<'a> {
+    globals: Rc<RefCell<Table>>,
+    /// A table that only builtins can reach, like the registry of Lua.
+    registry: Rc<RefCell<Table>>,
+    host: Box<Host<'a>>,
+    limits: Limits,
+    instructions: u64,
+    allocated: usize,
+    /// The line of the statement being run, which runtime errors point to.
+    line: usize,
+    depth: usize,
+}
+
+/// The local variables of a block, each one in a cell that closures can share.
+pub struct Scope {
+    variables: RefCell<Vec<(String, Rc<RefCell<Value>>)>>,
+    parent: Option<Rc<Scope>>,
+    /// The extra arguments of the function, set on its outermost scope only.
+    varargs: Option<Vec<Value>>,
+}
+
+impl Scope {
+    fn new(parent: Option<Rc<Self>>, varargs: Option<Vec<Value>>) -> Rc<Self> {
+        Rc::new(Self {
+            variables: RefCell::default(),
+            parent,
+            varargs,
+        })
+    }
+
+    fn child(parent: &Rc<Self>) -> Rc<Self> {
+        Self::new(Some(parent.clone()), None)
+    }
+
+    fn declare(&self, name: &str, value: Value) {
+        self.variables
+            .borrow_mut()
+            .push((name.to_string(), Rc::new(RefCell::new(value))));
+    }
+
+    fn lookup(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let found = self
+            .variables
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(variable, _)| variable == name)
+            .map(|(_, cell)| cell.clone());
+        found.or_else(|| self.parent.as_ref()?.lookup(name))
+    }
+
+    fn varargs(&self) -> &[Value] {
+        match (&self.varargs, &self.parent) {
+            (Some(varargs), _) => varargs,
+            (None, Some(parent)) => parent.varargs(),
+            (None, None) => &[],
+        }
+    }
+}
+
+/// What running a statement leads to.
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+impl<'a> Interpreter<'a> {
+    /// Construct an [`Interpreter`] with the standard libraries, which runs the Redis
+    /// commands of the scripts with `host`, turning their replies into Lua values.
+    pub fn new(limits: Limits, host: Box<Host<'a>>) -> Self {
+        Self {
+            globals: Rc::new(RefCell::new(stdlib::globals())),
+            registry: Rc::default(),
+            host,
+            limits,
+            instructions: 0,
+            allocated: 0,
+            line: 0,
+            depth: 0,
+        }
+    }
+
+    /// Set the global variable `name`, which scripts themselves can't do.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_field(name, value);
+    }
+
+    /// Get the global variable `name`, or `nil` if there is none.
+    pub fn global(&self, name: &str) -> Value {
+        self.globals.borrow().field(name)
+    }
+
+    /// The table where builtins can keep state of their own, out of reach of scripts.
+    pub fn registry(&self) -> Rc<RefCell<Table>> {
+        self.registry.clone()
+    }
+
+    /// Compile and run the `source` of a script, returning the values it returns.
+    pub fn run(&mut self, source: &str) -> Result<Vec<Value>, Error> {
+        let function = Rc::new(parser::parse(source)?);
+        let closure = Closure {
+            function,
+            scope: Scope::new(None, None),
+        };
+        self.call(&Value::Function(Rc::new(closure)), vec![])
+    }
+
+    /// Run a Redis command on behalf of the script.
+    pub fn host(&mut self, args: Vec<String>) -> Value {
+        (self.host)(args)
+    }
+
+    /// A runtime error with `message`, pointing at the current line.
+    pub fn error(&self, message: impl Display) -> Error {
+        Error::Runtime(Value::string(format!(
+            "user_script:{}: {message}",
+            self.line
+        )))
+    }
+
+    /// Account for `bytes` more of allocated memory.
+    pub fn charge(&mut self, bytes: usize) -> Result<(), Error> {
+        self.allocated = self.allocated.saturating_add(bytes);
+        if self.allocated > self.limits.memory {
+            return Err(Error::Memory(self.limits.memory));
+        }
+        Ok(())
+    }
+
+    /// A new string, accounting for its memory.
+    pub fn new_string(&mut self, string: impl Into<Rc<str>>) -> Result<Value, Error> {
+        let string = string.into();
+        self.charge(string.len())?;
+        Ok(Value::String(string))
+    }
+
+    /// A new table, accounting for its memory.
+    pub fn new_table(&mut self, table: Table) -> Result<Value, Error> {
+        self.charge(TABLE_COST + table.len() * FIELD_COST)?;
+        Ok(Value::table(table))
+    }
+
+    /// Set a field of a table, accounting for its memory.
+    pub fn set(&mut self, table: &RefCell<Table>, key: Value, value: Value) -> Result<(), Error> {
+        if !value.is_nil() {
+            self.charge(FIELD_COST)?;
+        }
+        table
+            .borrow_mut()
+            .set(key, value)
+            .map_err(|message| self.error(message))
+    }
+
+    /// Account for one more instruction.
+    fn tick(&mut self) -> Result<(), Error> {
+        self.instructions += 1;
+        if self.instructions > self.limits.instructions {
+            return Err(Error::Instructions(self.limits.instructions));
+        }
+        Ok(())
+    }
+
+    /// Call `function` with `args`, returning all of its results.
+    pub fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+        self.tick()?;
+        match function {
+            Value::Builtin(_, builtin) => builtin(self, args),
+            Value::Function(closure) => {
+                if self.depth >= MAX_CALL_DEPTH {
+                    return Err(self.error("stack overflow"));
+                }
+                let line = self.line;
+                self.depth += 1;
+                let results = self.call_closure(closure, args);
+                self.depth -= 1;
+                self.line = line;
+                results
+            }
+            other => Err(self.error(format_args!(
+                "attempt to call a {} value",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: &Closure,
+        mut args: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        let function = &closure.function;
+        let varargs = if function.varargs && args.len() > function.params.len() {
+            args.split_off(function.params.len())
+        } else {
+            vec![]
+        };
+        let scope = Scope::new(Some(closure.scope.clone()), Some(varargs));
+        args.resize(function.params.len(), Value::Nil);
+        for (name, value) in function.params.iter().zip(args) {
+            scope.declare(name, value);
+        }
+        match self.statements(&function.body, &scope)? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(vec![]),
+        }
+    }
+
+    fn block(&mut self, block: &Block, scope: &Rc<Scope>) -> Result<Flow, Error> {
+        self.statements(block, &Scope::child(scope))
+    }
+
+    fn statements(&mut self, block: &Block, scope: &Rc<Scope>) -> Result<Flow, Error> {
+        for Stat { line, kind } in block {
+            self.line = *line;
+            self.tick()?;
+            match self.statement(kind, scope)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn statement(&mut self, statement: &StatKind, scope: &Rc<Scope>) -> Result<Flow, Error> {
+        match statement {
+            StatKind::Local { names, values } => {
+                let mut values = self.eval_list(values, scope)?;
+                values.resize(names.len(), Value::Nil);
+                for (name, value) in names.iter().zip(values) {
+                    scope.declare(name, value);
+                }
+            }
+            StatKind::LocalFunction { name, function } => {
+                scope.declare(name, Value::Nil);
+                let closure = self.closure(function, scope)?;
+                if let Some(cell) = scope.lookup(name) {
+                    *cell.borrow_mut() = closure;
+                }
+            }
+            StatKind::Assign { targets, values } => self.assign(targets, values, scope)?,
+            StatKind::Call(call) => {
+                let _ = self.eval_multi(call, scope)?;
+            }
+            StatKind::Do(body) => return self.block(body, scope),
+            StatKind::While { condition, body } => {
+                while self.eval(condition, scope)?.is_truthy() {
+                    self.tick()?;
+                    match self.block(body, scope)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+            }
+            StatKind::Repeat { body, condition } => loop {
+                self.tick()?;
+                // The condition can see the locals of the body.
+                let scope = Scope::child(scope);
+                match self.statements(body, &scope)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    flow @ Flow::Return(_) => return Ok(flow),
+                }
+                if self.eval(condition, &scope)?.is_truthy() {
+                    break;
+                }
+            },
+            StatKind::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    if self.eval(condition, scope)?.is_truthy() {
+                        return self.block(body, scope);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.block(body, scope);
+                }
+            }
+            StatKind::NumericFor {
+                name,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let mut number = |expr: &Expr, what: &str| match self.eval(expr, scope)?.to_number()
+                {
+                    Some(number) => Ok(number),
+                    None => Err(self.error(format_args!("'for' {what} must be a number"))),
+                };
+                let start = number(start, "initial value")?;
+                let limit = number(limit, "limit")?;
+                let step = match step {
+                    Some(step) => number(step, "step")?,
+                    None => 1.0,
+                };
+                let mut counter = start;
+                while (step > 0.0 && counter <= limit) || (step <= 0.0 && counter >= limit) {
+                    let scope = Scope::child(scope);
+                    scope.declare(name, Value::Number(counter));
+                    match self.statements(body, &scope)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                    self.tick()?;
+                    counter += step;
+                }
+            }
+            StatKind::GenericFor {
+                names,
+                values,
+                body,
+            } => {
+                let mut values = self.eval_list(values, scope)?;
+                values.resize(3, Value::Nil);
+                let (function, state, mut control) =
+                    (values[0].clone(), values[1].clone(), values[2].clone());
+                loop {
+                    let mut results = self.call(&function, vec![state.clone(), control])?;
+                    results.resize(names.len(), Value::Nil);
+                    if results[0].is_nil() {
+                        break;
+                    }
+                    control = results[0].clone();
+                    let scope = Scope::child(scope);
+                    for (name, value) in names.iter().zip(results) {
+                        scope.declare(name, value);
+                    }
+                    match self.statements(body, &scope)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+            }
+            StatKind::Return(values) => return Ok(Flow::Return(self.eval_list(values, scope)?)),
+            StatKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn assign(
+        &mut self,
+        targets: &[Expr],
+        values: &[Expr],
+        scope: &Rc<Scope>,
+    ) -> Result<(), Error> {
+        // The tables and keys of the targets are evaluated before any of them gets assigned.
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Expr::Index(table, key) => {
+                    let table_value = self.eval(table, scope)?;
+                    let key = self.eval(key, scope)?;
+                    let Value::Table(table_ref) = table_value else {
+                        return Err(self.error(format_args!(
+                            "attempt to index a {} value{}",
+                            table_value.type_name(),
+                            describe(table, scope)
+                        )));
+                    };
+                    Some((table_ref, key))
+                }
+                _ => None,
+            });
+        }
+        let mut values = self.eval_list(values, scope)?;
+        values.resize(targets.len(), Value::Nil);
+        for ((target, place), value) in targets.iter().zip(places).zip(values) {
+            match (target, place) {
+                (_, Some((table, key))) => self.set(&table, key, value)?,
+                (Expr::Name(name), None) => match scope.lookup(name) {
+                    Some(cell) => *cell.borrow_mut() = value,
+                    None => {
+                        return Err(self.error(format_args!(
+                            "Script attempted to create global variable '{name}'"
+                        )))
+                    }
+                },
+                _ => unreachable!("the parser only allows assigning to names and fields"),
+            }
+        }
+        Ok(())
+    }
+
+    fn closure(
+        &mut self,
+        function: &Rc<parser::Function>,
+        scope: &Rc<Scope>,
+    ) -> Result<Value, Error> {
+        self.charge(CLOSURE_COST)?;
+        Ok(Value::Function(Rc::new(Closure {
+            function: function.clone(),
+            scope: scope.clone(),
+        })))
+    }
+
+    /// Evaluate `exprs`, expanding all the values of the last one if it's a call or `...`.
+    fn eval_list(&mut self, exprs: &[Expr], scope: &Rc<Scope>) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (index, expr) in exprs.iter().enumerate() {
+            if index + 1 == exprs.len() {
+                values.extend(self.eval_multi(expr, scope)?);
+            } else {
+                values.push(self.eval(expr, scope)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluate `expr` to all of its values, of which only calls and `...` have more than one.
+    fn eval_multi(&mut self, expr: &Expr, scope: &Rc<Scope>) -> Result<Vec<Value>, Error> {
+        match expr {
+            Expr::Call(function, args) => {
+                let callee = self.eval(function, scope)?;
+                let args = self.eval_list(args, scope)?;
+                self.call_described(&callee, args, || describe(function, scope))
+            }
+            Expr::Method(object, name, args) => {
+                let object = self.eval(object, scope)?;
+                let method = self.index(&object, &Value::string(name.as_str()), || {
+                    format!(" (method '{name}')")
+                })?;
+                let mut all = vec![object];
+                all.extend(self.eval_list(args, scope)?);
+                self.call_described(&method, all, || format!(" (method '{name}')"))
+            }
+            Expr::VarArgs => Ok(scope.varargs().to_vec()),
+            _ => Ok(vec![self.eval(expr, scope)?]),
+        }
+    }
+
+    /// Call `function`, naming it in the error if it is not a function.
+    fn call_described(
+        &mut self,
+        function: &Value,
+        args: Vec<Value>,
+        description: impl FnOnce() -> String,
+    ) -> Result<Vec<Value>, Error> {
+        if !matches!(function, Value::Function(_) | Value::Builtin(..)) {
+            return Err(self.error(format_args!(
+                "attempt to call a {} value{}",
+                function.type_name(),
+                description()
+            )));
+        }
+        self.call(function, args)
+    }
+
+    /// Evaluate `expr` to its first value.
+    fn eval(&mut self, expr: &Expr, scope: &Rc<Scope>) -> Result<Value, Error> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Boolean(true),
+            Expr::False => Value::Boolean(false),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::String(string) => Value::String(string.clone()),
+            Expr::VarArgs => scope.varargs().first().cloned().unwrap_or(Value::Nil),
+            Expr::Function(function) => self.closure(function, scope)?,
+            Expr::Name(name) => {
+                if let Some(cell) = scope.lookup(name) {
+                    return Ok(cell.borrow().clone());
+                }
+                let value = self.global(name);
+                if value.is_nil() {
+                    return Err(self.error(format_args!(
+                        "Script attempted to access nonexistent global variable '{name}'"
+                    )));
+                }
+                value
+            }
+            Expr::Index(table, key) => {
+                let table_value = self.eval(table, scope)?;
+                let key = self.eval(key, scope)?;
+                self.index(&table_value, &key, || describe(table, scope))?
+            }
+            Expr::Call(..) | Expr::Method(..) => self
+                .eval_multi(expr, scope)?
+                .into_iter()
+                .next()
+                .unwrap_or(Value::Nil),
+            Expr::Paren(inner) => self.eval(inner, scope)?,
+            Expr::Table(fields) => self.table(fields, scope)?,
+            Expr::Binary(BinaryOp::And, left, right) => {
+                let left = self.eval(left, scope)?;
+                if left.is_truthy() {
+                    self.eval(right, scope)?
+                } else {
+                    left
+                }
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                let left = self.eval(left, scope)?;
+                if left.is_truthy() {
+                    left
+                } else {
+                    self.eval(right, scope)?
+                }
+            }
+            Expr::Binary(op, left_expr, right_expr) => {
+                let left = self.eval(left_expr, scope)?;
+                let right = self.eval(right_expr, scope)?;
+                self.binary(*op, left, right, || {
+                    describe(left_expr, scope) + &describe(right_expr, scope)
+                })?
+            }
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, scope)?;
+                match (op, &value) {
+                    (UnaryOp::Not, _) => Value::Boolean(!value.is_truthy()),
+                    (UnaryOp::Len, Value::String(string)) => Value::Number(string.len() as f64),
+                    (UnaryOp::Len, Value::Table(table)) => {
+                        Value::Number(table.borrow().len() as f64)
+                    }
+                    (UnaryOp::Neg, _) if value.to_number().is_some() => {
+                        Value::Number(-value.to_number().unwrap_or_default())
+                    }
+                    (UnaryOp::Len, _) => {
+                        return Err(self.error(format_args!(
+                            "attempt to get length of a {} value{}",
+                            value.type_name(),
+                            describe(operand, scope)
+                        )))
+                    }
+                    (UnaryOp::Neg, _) => {
+                        return Err(self.error(format_args!(
+                            "attempt to perform arithmetic on a {} value{}",
+                            value.type_name(),
+                            describe(operand, scope)
+                        )))
+                    }
+                }
+            }
+        })
+    }
+
+    /// Index `table` with `key`, where strings get the functions of the `string` library.
+    fn index(
+        &mut self,
+        table: &Value,
+        key: &Value,
+        description: impl FnOnce() -> String,
+    ) -> Result<Value, Error> {
+        match table {
+            Value::Table(table) => Ok(table.borrow().get(key)),
+            Value::String(_) => match self.global("string") {
+                Value::Table(library) => Ok(library.borrow().get(key)),
+                _ => Ok(Value::Nil),
+            },
+            other => Err(self.error(format_args!(
+                "attempt to index a {} value{}",
+                other.type_name(),
+                description()
+            ))),
+        }
+    }
+
+    fn table(&mut self, fields: &[Field], scope: &Rc<Scope>) -> Result<Value, Error> {
+        let table = RefCell::new(Table::default());
+        self.charge(TABLE_COST)?;
+        let mut position = 0_u32;
+        for (index, field) in fields.iter().enumerate() {
+            match field {
+                // Like in argument lists, only the last field gets all the values of a call.
+                Field::Positional(expr) if index + 1 == fields.len() => {
+                    for value in self.eval_multi(expr, scope)? {
+                        position += 1;
+                        self.set(&table, Value::Number(f64::from(position)), value)?;
+                    }
+                }
+                Field::Positional(expr) => {
+                    let value = self.eval(expr, scope)?;
+                    position += 1;
+                    self.set(&table, Value::Number(f64::from(position)), value)?;
+                }
+                Field::Named(key, value) => {
+                    let key = self.eval(key, scope)?;
+                    let value = self.eval(value, scope)?;
+                    self.set(&table, key, value)?;
+                }
+            }
+        }
+        Ok(Value::Table(Rc::new(table)))
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        left: Value,
+        right: Value,
+        description: impl FnOnce() -> String,
+    ) -> Result<Value, Error> {
+        Ok(match op {
+            BinaryOp::Equal => Value::Boolean(left.raw_equals(&right)),
+            BinaryOp::NotEqual => Value::Boolean(!left.raw_equals(&right)),
+            BinaryOp::Less => Value::Boolean(self.less(&left, &right, false)?),
+            BinaryOp::LessEqual => Value::Boolean(self.less(&left, &right, true)?),
+            BinaryOp::Greater => Value::Boolean(self.less(&right, &left, false)?),
+            BinaryOp::GreaterEqual => Value::Boolean(self.less(&right, &left, true)?),
+            BinaryOp::Concat => match (left.to_str(), right.to_str()) {
+                (Some(left), Some(right)) => self.new_string(format!("{left}{right}"))?,
+                (left_str, _) => {
+                    let culprit = if left_str.is_none() { &left } else { &right };
+                    return Err(self.error(format_args!(
+                        "attempt to concatenate a {} value{}",
+                        culprit.type_name(),
+                        description()
+                    )));
+                }
+            },
+            BinaryOp::Add
+            | BinaryOp::Sub
+            | BinaryOp::Mul
+            | BinaryOp::Div
+            | BinaryOp::Mod
+            | BinaryOp::Pow => {
+                let (Some(a), Some(b)) = (left.to_number(), right.to_number()) else {
+                    let culprit = if left.to_number().is_none() { &left } else { &right };
+                    return Err(self.error(format_args!(
+                        "attempt to perform arithmetic on a {} value{}",
+                        culprit.type_name(),
+                        description()
+                    )));
+                };
+                Value::Number(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    BinaryOp::Mod => a - (a / b).floor() * b,
+                    _ => a.powf(b),
+                })
+            }
+            BinaryOp::And | BinaryOp::Or => unreachable!("`and` and `or` short-circuit"),
+        })
+    }
+
+    /// Whether `left < right`, or `left <= right` if `or_equal`.
+    pub fn less(&self, left: &Value, right: &Value, or_equal: bool) -> Result<bool, Error> {
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (Value::String(a), Value::String(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (a, b) if a.type_name() == b.type_name() => Err(self.error(format_args!(
+                "attempt to compare two {} values",
+                a.type_name()
+            ))),
+            (a, b) => Err(self.error(format_args!(
+                "attempt to compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        }
+    }
+}
+
+/// Describe the variable that `expr` reads for error messages, like ` (global 'x')`.
+fn describe(expr: &Expr, scope: &Scope) -> String {
+    match expr {
+        Expr::Name(name) if scope.lookup(name).is_some() => format!(" (local '{name}')"),
+        Expr::Name(name) => format!(" (global '{name}')"),
+        Expr::Index(_, key) => match key.as_ref() {
+            Expr::String(key) => format!(" (field '{key}')"),
+            _ => String::new(),
+        },
+        Expr::Number(number) => format!(" (constant '{}')", format_number(*number)),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use crate::lua::{isolated, Error, Limits, Value};
+
+    const LIMITS: Limits = Limits {
+        memory: 1 << 20,
+        instructions: 100_000,
+    };
+
+    /// Run `source`, returning its values or its error as strings, which unlike Lua values
+    /// can leave the thread of the script.
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        isolated(|| {
+            let mut interpreter = Interpreter::new(LIMITS, Box::new(|_| Value::Nil));
+            match interpreter.run(source) {
+                Ok(values) => Ok(values.iter().map(ToString::to_string).collect()),
+                Err(Error::Syntax(message)) => Err(format!("syntax: {message}")),
+                Err(err) => Err(err.to_string()),
+            }
+        })
+    }
+
+    #[test]
+    fn closures_and_control_flow() {
+        let source = r#"
+            local function counter()
+                local count = 0
+                return function() count = count + 1; return count end
+            end
+            local a, b = counter(), counter()
+            a(); a()
+            local fib = {}
+            for i = 1, 10 do fib[i] = i <= 2 and 1 or fib[i - 1] + fib[i - 2] end
+            local sum = 0
+            for _, value in ipairs(fib) do
+                if value > 20 then break end
+                sum = sum + value
+            end
+            local n = 0
+            repeat local done = n >= 3; n = n + 1 until done
+            return a(), b(), #fib, fib[10], sum, n, select('#', nil, nil), "x" .. 1 .. 2.5
+        "#;
+        assert_eq!(
+            run(source).unwrap(),
+            ["3", "1", "10", "55", "33", "4", "2", "x12.5"]
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let message = |source| run(source).unwrap_err();
+        assert_eq!(
+            message("local t = nil\nreturn t.x"),
+            "user_script:2: attempt to index a nil value (local 't')"
+        );
+        assert_eq!(
+            message("x = 1"),
+            "user_script:1: Script attempted to create global variable 'x'"
+        );
+        assert_eq!(
+            message("return undefined_function()"),
+            "user_script:1: Script attempted to access nonexistent global variable 'undefined_function'"
+        );
+        assert_eq!(
+            message("return 1 < 'x'"),
+            "user_script:1: attempt to compare number with string"
+        );
+        assert_eq!(message("error('boom', 0)"), "boom");
+        assert_eq!(
+            run("return pcall(function() error({code = 1}) end)").unwrap()[0],
+            "false"
+        );
+        assert_eq!(
+            message("local function f() return f() + 1 end return f()"),
+            "user_script:1: stack overflow"
+        );
+        assert!(message("return 1 +").starts_with("syntax: user_script:1:"));
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(
+            run("while true do end").unwrap_err(),
+            "Script ran more than 100000 instructions"
+        );
+        // Running out of instructions can't be caught.
+        assert_eq!(
+            run("pcall(function() while true do end end) return 1").unwrap_err(),
+            "Script ran more than 100000 instructions"
+        );
+        assert_eq!(
+            run("local s = 'x' for i = 1, 100 do s = s .. s end").unwrap_err(),
+            "Script used more than 1048576 bytes of memory"
+        );
+        assert!(run("local t = {} for i = 1, 1000 do t[i] = i end").is_ok());
+    }
+}
@@ -0,0 +1,283 @@
+//! # Splitting Lua source into tokens.
+
+use super::Error;
+
+/// A token of Lua source, see [`tokenize`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    String(String),
+    Number(f64),
+    /// A keyword, like `local` or `function`.
+    Keyword(&'static str),
+    /// An operator or punctuation, like `..` or `(`.
+    Symbol(&'static str),
+}
+
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Symbols, longest first so that e.g. `...` is not read as `..` followed by `.`.
+const SYMBOLS: [&str; 26] = [
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+/// Split `source` into tokens, each along with the line it starts on.
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut lexer = Lexer {
+        bytes: source.as_bytes(),
+        position: 0,
+        line: 1,
+    };
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Lexer<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    line: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.position + offset).copied()
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::Syntax(format!("user_script:{}: {message}", self.line))
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, usize)>, Error> {
+        self.skip_blanks_and_comments()?;
+        let Some(byte) = self.peek(0) else {
+            return Ok(None);
+        };
+        let line = self.line;
+        let token = match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                let start = self.position;
+                while self
+                    .peek(0)
+                    .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+                {
+                    self.position += 1;
+                }
+                let name = String::from_utf8_lossy(&self.bytes[start..self.position]);
+                match KEYWORDS.iter().find(|keyword| **keyword == name) {
+                    Some(keyword) => Token::Keyword(keyword),
+                    None => Token::Name(name.into_owned()),
+                }
+            }
+            b'0'..=b'9' => self.number()?,
+            b'.' if self.peek(1).is_some_and(|byte| byte.is_ascii_digit()) => self.number()?,
+            b'"' | b'\'' => Token::String(self.quoted(byte)?),
+            b'[' if matches!(self.peek(1), Some(b'[' | b'=')) => match self.long_bracket()? {
+                Some(string) => Token::String(string),
+                None => {
+                    self.position += 1;
+                    Token::Symbol("[")
+                }
+            },
+            _ => {
+                let rest = &self.bytes[self.position..];
+                let symbol = SYMBOLS
+                    .iter()
+                    .find(|symbol| rest.starts_with(symbol.as_bytes()))
+                    .ok_or_else(|| {
+                        self.error(&format!("unexpected symbol near '{}'", byte as char))
+                    })?;
+                self.position += symbol.len();
+                Token::Symbol(symbol)
+            }
+        };
+        Ok(Some((token, line)))
+    }
+
+    fn skip_blanks_and_comments(&mut self) -> Result<(), Error> {
+        loop {
+            match self.peek(0) {
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.position += 1;
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                Some(b'-') if self.peek(1) == Some(b'-') => {
+                    self.position += 2;
+                    if self.peek(0) == Some(b'[') && self.long_bracket()?.is_some() {
+                        continue;
+                    }
+                    while self.peek(0).is_some_and(|byte| byte != b'\n') {
+                        self.position += 1;
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Token, Error> {
+        let start = self.position;
+        let hex = self.peek(0) == Some(b'0') && matches!(self.peek(1), Some(b'x' | b'X'));
+        if hex {
+            self.position += 2;
+        }
+        while let Some(byte) = self.peek(0) {
+            let exponent = if hex { b"pP" } else { b"eE" };
+            if exponent.contains(&byte) && matches!(self.peek(1), Some(b'+' | b'-')) {
+                self.position += 2;
+            } else if byte.is_ascii_alphanumeric() || byte == b'.' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        let text = String::from_utf8_lossy(&self.bytes[start..self.position]);
+        parse_number(&text)
+            .map(Token::Number)
+            .ok_or_else(|| self.error(&format!("malformed number near '{text}'")))
+    }
+
+    fn quoted(&mut self, quote: u8) -> Result<String, Error> {
+        self.position += 1;
+        let mut string = vec![];
+        loop {
+            let Some(byte) = self.peek(0) else {
+                return Err(self.error("unfinished string"));
+            };
+            self.position += 1;
+            match byte {
+                b'\n' => return Err(self.error("unfinished string")),
+                byte if byte == quote => break,
+                b'\\' => {
+                    let Some(escaped) = self.peek(0) else {
+                        return Err(self.error("unfinished string"));
+                    };
+                    self.position += 1;
+                    match escaped {
+                        b'n' => string.push(b'\n'),
+                        b't' => string.push(b'\t'),
+                        b'r' => string.push(b'\r'),
+                        b'a' => string.push(7),
+                        b'b' => string.push(8),
+                        b'f' => string.push(12),
+                        b'v' => string.push(11),
+                        b'\n' => {
+                            self.line += 1;
+                            string.push(b'\n');
+                        }
+                        b'0'..=b'9' => {
+                            let mut code = u32::from(escaped - b'0');
+                            for _ in 0..2 {
+                                match self.peek(0) {
+                                    Some(digit @ b'0'..=b'9') => {
+                                        code = code * 10 + u32::from(digit - b'0');
+                                        self.position += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            let byte = u8::try_from(code)
+                                .map_err(|_| self.error("escape sequence too large"))?;
+                            string.push(byte);
+                        }
+                        other => string.push(other),
+                    }
+                }
+                byte => string.push(byte),
+            }
+        }
+        Ok(String::from_utf8_lossy(&string).into_owned())
+    }
+
+    /// Read a long bracket like `[==[ ... ]==]`, if there is one at the current position.
+    fn long_bracket(&mut self) -> Result<Option<String>, Error> {
+        let level = self.bytes[self.position + 1..]
+            .iter()
+            .take_while(|&&byte| byte == b'=')
+            .count();
+        if self.peek(level + 1) != Some(b'[') {
+            return Ok(None);
+        }
+        self.position += level + 2;
+        // A newline right after the opening bracket is skipped.
+        if self.peek(0) == Some(b'\n') {
+            self.line += 1;
+            self.position += 1;
+        }
+        let close = format!("]{}]", "=".repeat(level));
+        let start = self.position;
+        loop {
+            if self.bytes[self.position..].starts_with(close.as_bytes()) {
+                let string = String::from_utf8_lossy(&self.bytes[start..self.position]);
+                self.position += close.len();
+                return Ok(Some(string.into_owned()));
+            }
+            match self.peek(0) {
+                Some(byte) => {
+                    if byte == b'\n' {
+                        self.line += 1;
+                    }
+                    self.position += 1;
+                }
+                None => return Err(self.error("unfinished long string")),
+            }
+        }
+    }
+}
+
+/// Parse a Lua numeral, either decimal or hexadecimal, also used by `tonumber`.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as f64,
+        None if digits.starts_with(|char: char| char.is_ascii_digit() || char == '.') => {
+            digits.parse().ok()?
+        }
+        None => return None,
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Token};
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<_> =
+            tokenize("local x = 0x10 .. 'a\\n' -- comment\n--[[ long\n]] x ~= [[raw]]")
+                .unwrap()
+                .into_iter()
+                .collect();
+        assert_eq!(
+            tokens,
+            [
+                (Token::Keyword("local"), 1),
+                (Token::Name("x".into()), 1),
+                (Token::Symbol("="), 1),
+                (Token::Number(16.0), 1),
+                (Token::Symbol(".."), 1),
+                (Token::String("a\n".into()), 1),
+                (Token::Name("x".into()), 3),
+                (Token::Symbol("~="), 3),
+                (Token::String("raw".into()), 3),
+            ]
+        );
+        assert!(tokenize("'unfinished").is_err());
+        assert!(tokenize("x = 1 @ 2").is_err());
+    }
+}
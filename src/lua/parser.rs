@@ -0,0 +1,683 @@
+//! # Parsing Lua tokens into a syntax tree, which the interpreter walks.
+
+use super::lexer::{self, Token};
+use super::Error;
+use std::rc::Rc;
+
+/// A sequence of statements, like the body of a function or a loop.
+pub type Block = Vec<Stat>;
+
+/// A statement, along with the line it starts on, for error messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stat {
+    pub line: usize,
+    pub kind: StatKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatKind {
+    Local {
+        names: Vec<String>,
+        values: Vec<Expr>,
+    },
+    /// Assign the `values` to the `targets`, which are names or indexing expressions.
+    Assign {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
+    },
+    /// A function call whose results are discarded.
+    Call(Expr),
+    Do(Block),
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    Repeat {
+        body: Block,
+        condition: Expr,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    NumericFor {
+        name: String,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor {
+        names: Vec<String>,
+        values: Vec<Expr>,
+        body: Block,
+    },
+    /// `local function name`, which unlike `local name = function` can call itself.
+    LocalFunction {
+        name: String,
+        function: Rc<Function>,
+    },
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    String(Rc<str>),
+    VarArgs,
+    Function(Rc<Function>),
+    Name(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    /// A method call like `object:name(arguments)`.
+    Method(Box<Expr>, String, Vec<Expr>),
+    Table(Vec<Field>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    /// An expression in parentheses, which keeps only the first of multiple values.
+    Paren(Box<Expr>),
+}
+
+/// A field of a table constructor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// A value stored at the next array index.
+    Positional(Expr),
+    Named(Expr, Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    NotEqual,
+    Equal,
+    Concat,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    Len,
+}
+
+/// The definition of a function, shared by all the closures made from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub varargs: bool,
+    pub body: Block,
+}
+
+/// The priority of unary operators, between those of `*` and `^`.
+const UNARY_PRIORITY: u8 = 8;
+
+/// How deeply blocks and expressions may nest, like `LUAI_MAXCCALLS` in Lua.
+const MAX_DEPTH: usize = 200;
+
+impl BinaryOp {
+    fn from_symbol(token: &Token) -> Option<Self> {
+        Some(match token {
+            Token::Keyword("or") => Self::Or,
+            Token::Keyword("and") => Self::And,
+            Token::Symbol("<") => Self::Less,
+            Token::Symbol(">") => Self::Greater,
+            Token::Symbol("<=") => Self::LessEqual,
+            Token::Symbol(">=") => Self::GreaterEqual,
+            Token::Symbol("~=") => Self::NotEqual,
+            Token::Symbol("==") => Self::Equal,
+            Token::Symbol("..") => Self::Concat,
+            Token::Symbol("+") => Self::Add,
+            Token::Symbol("-") => Self::Sub,
+            Token::Symbol("*") => Self::Mul,
+            Token::Symbol("/") => Self::Div,
+            Token::Symbol("%") => Self::Mod,
+            Token::Symbol("^") => Self::Pow,
+            _ => return None,
+        })
+    }
+
+    /// The left and right priorities, where a right one lower than the left makes it right-associative.
+    const fn priority(self) -> (u8, u8) {
+        match self {
+            Self::Or => (1, 1),
+            Self::And => (2, 2),
+            Self::Less
+            | Self::Greater
+            | Self::LessEqual
+            | Self::GreaterEqual
+            | Self::NotEqual
+            | Self::Equal => (3, 3),
+            Self::Concat => (5, 4),
+            Self::Add | Self::Sub => (6, 6),
+            Self::Mul | Self::Div | Self::Mod => (7, 7),
+            Self::Pow => (10, 9),
+        }
+    }
+}
+
+/// Parse the Lua `source` of a whole chunk, which is the body of a vararg function.
+pub fn parse(source: &str) -> Result<Function, Error> {
+    let mut parser = Parser {
+        tokens: lexer::tokenize(source)?,
+        position: 0,
+        depth: 0,
+    };
+    let body = parser.block()?;
+    if let Some(token) = parser.peek() {
+        return Err(parser.error(&format!("'<eof>' expected near {}", describe(token))));
+    }
+    Ok(Function {
+        params: vec![],
+        varargs: true,
+        body,
+    })
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// How deeply the blocks and expressions being parsed are nested.
+    depth: usize,
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Name(name) => format!("'{name}'"),
+        Token::String(string) => format!("'{string}'"),
+        Token::Number(number) => format!("'{number}'"),
+        Token::Keyword(word) | Token::Symbol(word) => format!("'{word}'"),
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::Syntax(format!("user_script:{}: {message}", self.line()))
+    }
+
+    fn check(&self, expected: &Token) -> bool {
+        self.peek() == Some(expected)
+    }
+
+    fn accept(&mut self, expected: &Token) -> bool {
+        let found = self.check(expected);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), Error> {
+        if self.accept(expected) {
+            return Ok(());
+        }
+        let near = self.peek().map_or("'<eof>'".to_string(), describe);
+        Err(self.error(&format!("{} expected near {near}", describe(expected))))
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::Name(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            token => {
+                let near = token.map_or("'<eof>'".to_string(), describe);
+                Err(self.error(&format!("<name> expected near {near}")))
+            }
+        }
+    }
+
+    /// Whether the current token ends a block.
+    fn block_ends(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(Token::Keyword("end" | "else" | "elseif" | "until"))
+        )
+    }
+
+    /// Run `parse` one level deeper, failing if the nesting gets too deep.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("chunk has too many syntax levels"));
+        }
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn block(&mut self) -> Result<Block, Error> {
+        self.nested(Self::statements)
+    }
+
+    fn statements(&mut self) -> Result<Block, Error> {
+        let mut block = vec![];
+        while !self.block_ends() {
+            let line = self.line();
+            let kind = self.statement()?;
+            let last = matches!(kind, StatKind::Return(_) | StatKind::Break);
+            block.push(Stat { line, kind });
+            let _ = self.accept(&Token::Symbol(";"));
+            if last {
+                break;
+            }
+        }
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<StatKind, Error> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.error("unexpected end of script"));
+        };
+        match token {
+            Token::Keyword("local") => {
+                self.position += 1;
+                if self.accept(&Token::Keyword("function")) {
+                    let name = self.name()?;
+                    let function = Rc::new(self.function_body()?);
+                    return Ok(StatKind::LocalFunction { name, function });
+                }
+                let mut names = vec![self.name()?];
+                while self.accept(&Token::Symbol(",")) {
+                    names.push(self.name()?);
+                }
+                let values = if self.accept(&Token::Symbol("=")) {
+                    self.expression_list()?
+                } else {
+                    vec![]
+                };
+                Ok(StatKind::Local { names, values })
+            }
+            Token::Keyword("function") => {
+                self.position += 1;
+                let mut target = Expr::Name(self.name()?);
+                while self.accept(&Token::Symbol(".")) {
+                    let key = Expr::String(self.name()?.into());
+                    target = Expr::Index(Box::new(target), Box::new(key));
+                }
+                let method = self.accept(&Token::Symbol(":"));
+                if method {
+                    let key = Expr::String(self.name()?.into());
+                    target = Expr::Index(Box::new(target), Box::new(key));
+                }
+                let mut function = self.function_body()?;
+                if method {
+                    function.params.insert(0, "self".to_string());
+                }
+                Ok(StatKind::Assign {
+                    targets: vec![target],
+                    values: vec![Expr::Function(Rc::new(function))],
+                })
+            }
+            Token::Keyword("return") => {
+                self.position += 1;
+                let values = if self.block_ends() || self.check(&Token::Symbol(";")) {
+                    vec![]
+                } else {
+                    self.expression_list()?
+                };
+                Ok(StatKind::Return(values))
+            }
+            Token::Keyword("break") => {
+                self.position += 1;
+                Ok(StatKind::Break)
+            }
+            Token::Keyword("do") => {
+                self.position += 1;
+                let body = self.block()?;
+                self.expect(&Token::Keyword("end"))?;
+                Ok(StatKind::Do(body))
+            }
+            Token::Keyword("while") => {
+                self.position += 1;
+                let condition = self.expression(0)?;
+                self.expect(&Token::Keyword("do"))?;
+                let body = self.block()?;
+                self.expect(&Token::Keyword("end"))?;
+                Ok(StatKind::While { condition, body })
+            }
+            Token::Keyword("repeat") => {
+                self.position += 1;
+                let body = self.block()?;
+                self.expect(&Token::Keyword("until"))?;
+                let condition = self.expression(0)?;
+                Ok(StatKind::Repeat { body, condition })
+            }
+            Token::Keyword("if") => {
+                self.position += 1;
+                let mut branches = vec![];
+                let mut otherwise = None;
+                loop {
+                    let condition = self.expression(0)?;
+                    self.expect(&Token::Keyword("then"))?;
+                    branches.push((condition, self.block()?));
+                    if self.accept(&Token::Keyword("elseif")) {
+                        continue;
+                    }
+                    if self.accept(&Token::Keyword("else")) {
+                        otherwise = Some(self.block()?);
+                    }
+                    self.expect(&Token::Keyword("end"))?;
+                    break;
+                }
+                Ok(StatKind::If {
+                    branches,
+                    otherwise,
+                })
+            }
+            Token::Keyword("for") => {
+                self.position += 1;
+                let name = self.name()?;
+                if self.accept(&Token::Symbol("=")) {
+                    let start = self.expression(0)?;
+                    self.expect(&Token::Symbol(","))?;
+                    let limit = self.expression(0)?;
+                    let step = if self.accept(&Token::Symbol(",")) {
+                        Some(self.expression(0)?)
+                    } else {
+                        None
+                    };
+                    self.expect(&Token::Keyword("do"))?;
+                    let body = self.block()?;
+                    self.expect(&Token::Keyword("end"))?;
+                    return Ok(StatKind::NumericFor {
+                        name,
+                        start,
+                        limit,
+                        step,
+                        body,
+                    });
+                }
+                let mut names = vec![name];
+                while self.accept(&Token::Symbol(",")) {
+                    names.push(self.name()?);
+                }
+                self.expect(&Token::Keyword("in"))?;
+                let values = self.expression_list()?;
+                self.expect(&Token::Keyword("do"))?;
+                let body = self.block()?;
+                self.expect(&Token::Keyword("end"))?;
+                Ok(StatKind::GenericFor {
+                    names,
+                    values,
+                    body,
+                })
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    /// A function call, or an assignment.
+    fn expression_statement(&mut self) -> Result<StatKind, Error> {
+        let first = self.suffixed_expression()?;
+        if matches!(first, Expr::Call(..) | Expr::Method(..)) {
+            return Ok(StatKind::Call(first));
+        }
+        let mut targets = vec![first];
+        while self.accept(&Token::Symbol(",")) {
+            targets.push(self.suffixed_expression()?);
+        }
+        if targets
+            .iter()
+            .any(|target| !matches!(target, Expr::Name(_) | Expr::Index(..)))
+        {
+            return Err(self.error("syntax error, cannot assign to that"));
+        }
+        self.expect(&Token::Symbol("="))?;
+        let values = self.expression_list()?;
+        Ok(StatKind::Assign { targets, values })
+    }
+
+    fn function_body(&mut self) -> Result<Function, Error> {
+        self.expect(&Token::Symbol("("))?;
+        let mut params = vec![];
+        let mut varargs = false;
+        if !self.accept(&Token::Symbol(")")) {
+            loop {
+                if self.accept(&Token::Symbol("...")) {
+                    varargs = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.accept(&Token::Symbol(",")) {
+                    break;
+                }
+            }
+            self.expect(&Token::Symbol(")"))?;
+        }
+        let body = self.block()?;
+        self.expect(&Token::Keyword("end"))?;
+        Ok(Function {
+            params,
+            varargs,
+            body,
+        })
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expr>, Error> {
+        let mut expressions = vec![self.expression(0)?];
+        while self.accept(&Token::Symbol(",")) {
+            expressions.push(self.expression(0)?);
+        }
+        Ok(expressions)
+    }
+
+    /// Parse an expression whose binary operators all have a left priority above `limit`.
+    fn expression(&mut self, limit: u8) -> Result<Expr, Error> {
+        self.nested(|parser| parser.operators(limit))
+    }
+
+    fn operators(&mut self, limit: u8) -> Result<Expr, Error> {
+        let unary = match self.peek() {
+            Some(Token::Keyword("not")) => Some(UnaryOp::Not),
+            Some(Token::Symbol("-")) => Some(UnaryOp::Neg),
+            Some(Token::Symbol("#")) => Some(UnaryOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(op) => {
+                self.position += 1;
+                let operand = self.expression(UNARY_PRIORITY)?;
+                match (op, operand) {
+                    (UnaryOp::Neg, Expr::Number(number)) => Expr::Number(-number),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple_expression()?,
+        };
+        while let Some(op) = self.peek().and_then(BinaryOp::from_symbol) {
+            let (left_priority, right_priority) = op.priority();
+            if left_priority <= limit {
+                break;
+            }
+            self.position += 1;
+            let right = self.expression(right_priority)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expr, Error> {
+        let expression = match self.peek() {
+            Some(Token::Number(number)) => Expr::Number(*number),
+            Some(Token::String(string)) => Expr::String(string.as_str().into()),
+            Some(Token::Keyword("nil")) => Expr::Nil,
+            Some(Token::Keyword("true")) => Expr::True,
+            Some(Token::Keyword("false")) => Expr::False,
+            Some(Token::Symbol("...")) => Expr::VarArgs,
+            Some(Token::Symbol("{")) => return self.table(),
+            Some(Token::Keyword("function")) => {
+                self.position += 1;
+                return Ok(Expr::Function(Rc::new(self.function_body()?)));
+            }
+            _ => return self.suffixed_expression(),
+        };
+        self.position += 1;
+        Ok(expression)
+    }
+
+    /// A name or parenthesized expression, followed by any indexing and calls.
+    fn suffixed_expression(&mut self) -> Result<Expr, Error> {
+        let mut expression = if self.accept(&Token::Symbol("(")) {
+            let inner = self.expression(0)?;
+            self.expect(&Token::Symbol(")"))?;
+            Expr::Paren(Box::new(inner))
+        } else {
+            Expr::Name(self.name()?)
+        };
+        loop {
+            match self.peek() {
+                Some(Token::Symbol(".")) => {
+                    self.position += 1;
+                    let key = Expr::String(self.name()?.into());
+                    expression = Expr::Index(Box::new(expression), Box::new(key));
+                }
+                Some(Token::Symbol("[")) => {
+                    self.position += 1;
+                    let key = self.expression(0)?;
+                    self.expect(&Token::Symbol("]"))?;
+                    expression = Expr::Index(Box::new(expression), Box::new(key));
+                }
+                Some(Token::Symbol(":")) => {
+                    self.position += 1;
+                    let name = self.name()?;
+                    let arguments = self.call_arguments()?;
+                    expression = Expr::Method(Box::new(expression), name, arguments);
+                }
+                Some(Token::Symbol("(" | "{") | Token::String(_)) => {
+                    let arguments = self.call_arguments()?;
+                    expression = Expr::Call(Box::new(expression), arguments);
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn call_arguments(&mut self) -> Result<Vec<Expr>, Error> {
+        match self.peek() {
+            Some(Token::String(string)) => {
+                let argument = Expr::String(string.as_str().into());
+                self.position += 1;
+                Ok(vec![argument])
+            }
+            Some(Token::Symbol("{")) => Ok(vec![self.table()?]),
+            _ => {
+                self.expect(&Token::Symbol("("))?;
+                if self.accept(&Token::Symbol(")")) {
+                    return Ok(vec![]);
+                }
+                let arguments = self.expression_list()?;
+                self.expect(&Token::Symbol(")"))?;
+                Ok(arguments)
+            }
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, Error> {
+        self.expect(&Token::Symbol("{"))?;
+        let mut fields = vec![];
+        while !self.accept(&Token::Symbol("}")) {
+            let named = matches!(self.peek(), Some(Token::Name(_)))
+                && self.tokens.get(self.position + 1).map(|(token, _)| token)
+                    == Some(&Token::Symbol("="));
+            if named {
+                let key = Expr::String(self.name()?.into());
+                self.position += 1;
+                fields.push(Field::Named(key, self.expression(0)?));
+            } else if self.accept(&Token::Symbol("[")) {
+                let key = self.expression(0)?;
+                self.expect(&Token::Symbol("]"))?;
+                self.expect(&Token::Symbol("="))?;
+                fields.push(Field::Named(key, self.expression(0)?));
+            } else {
+                fields.push(Field::Positional(self.expression(0)?));
+            }
+            if !self.accept(&Token::Symbol(",")) && !self.accept(&Token::Symbol(";")) {
+                self.expect(&Token::Symbol("}"))?;
+                break;
+            }
+        }
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, BinaryOp, Expr, StatKind};
+
+    #[test]
+    fn precedence() {
+        let chunk = parse("return 1 + 2 * 3 ^ 2 ^ 0.5, 'a' .. 'b' .. 'c', not x == y").unwrap();
+        let [stat] = chunk.body.as_slice() else {
+            panic!("expected a single statement");
+        };
+        let StatKind::Return(values) = &stat.kind else {
+            panic!("expected a return");
+        };
+        let number = |number| Box::new(Expr::Number(number));
+        let string = |string: &str| Box::new(Expr::String(string.into()));
+        assert_eq!(
+            values[0],
+            Expr::Binary(
+                BinaryOp::Add,
+                number(1.0),
+                Box::new(Expr::Binary(
+                    BinaryOp::Mul,
+                    number(2.0),
+                    Box::new(Expr::Binary(
+                        BinaryOp::Pow,
+                        number(3.0),
+                        Box::new(Expr::Binary(BinaryOp::Pow, number(2.0), number(0.5)))
+                    ))
+                ))
+            )
+        );
+        assert_eq!(
+            values[1],
+            Expr::Binary(
+                BinaryOp::Concat,
+                string("a"),
+                Box::new(Expr::Binary(BinaryOp::Concat, string("b"), string("c")))
+            )
+        );
+        assert!(matches!(values[2], Expr::Binary(BinaryOp::Equal, ..)));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for source in ["x = ", "local = 1", "if x then", "f() = 1", "return return"] {
+            assert!(parse(source).is_err(), "{source:?} should not parse");
+        }
+        let deep = format!("return {}1{}", "(".repeat(500), ")".repeat(500));
+        assert!(parse(&deep).is_err());
+        assert!(parse("local t = {1, 2; x = 3, ['y'] = 4,} t.x, t[1] = t:f 'a', f{}").is_ok());
+    }
+}
@@ -0,0 +1,418 @@
+//! # Lua patterns, as used by `string.find`, `string.match`, `string.gmatch` and `string.gsub`.
+//!
+//! A straight port of the matcher of Lua 5.1: character classes like `%d` and `[a-z]`,
+//! the `*`, `+`, `-` and `?` repetitions, anchors, captures (including position captures
+//! and back-references like `%1`), `%b()` for balanced pairs and `%f[set]` frontiers.
+
+/// How deeply the matcher may recurse, like `MAXCCALLS` in Lua.
+const MAX_DEPTH: usize = 200;
+
+/// A capture of a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// The byte range of a substring.
+    Range(usize, usize),
+    /// The position of an empty `()` capture, counting from `1`.
+    Position(usize),
+}
+
+/// A match of a pattern: its byte range in the source, and its explicit captures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub captures: Vec<Capture>,
+}
+
+impl Match {
+    /// The captures, or the whole match if the pattern has none, like `string.match` returns.
+    pub fn captures_or_whole(&self) -> Vec<Capture> {
+        if self.captures.is_empty() {
+            vec![Capture::Range(self.start, self.end)]
+        } else {
+            self.captures.clone()
+        }
+    }
+}
+
+/// Whether `pattern` has no special characters, so that a plain search does the same.
+pub fn is_plain(pattern: &[u8]) -> bool {
+    !pattern.iter().any(|byte| b"^$*+?.([%-".contains(byte))
+}
+
+/// Find the first match of `pattern` in `source`, from the byte `init` on.
+///
+/// A `^` at the start of the pattern anchors the match at `init`.
+pub fn find(source: &[u8], pattern: &[u8], init: usize) -> Result<Option<Match>, String> {
+    let (anchored, pattern) = match pattern.strip_prefix(b"^") {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+    let mut start = init;
+    loop {
+        if let Some(found) = match_at(source, pattern, start)? {
+            return Ok(Some(found));
+        }
+        start += 1;
+        if anchored || start > source.len() {
+            return Ok(None);
+        }
+    }
+}
+
+/// Match `pattern` (without any leading `^`) right at the byte `start` of `source`.
+pub fn match_at(source: &[u8], pattern: &[u8], start: usize) -> Result<Option<Match>, String> {
+    let mut matcher = Matcher {
+        source,
+        pattern,
+        captures: vec![],
+        depth: 0,
+    };
+    let Some(end) = matcher.matches(start, 0)? else {
+        return Ok(None);
+    };
+    let captures = matcher
+        .captures
+        .iter()
+        .map(|&(start, len)| match len {
+            Len::Position => Ok(Capture::Position(start + 1)),
+            Len::Closed(len) => Ok(Capture::Range(start, start + len)),
+            Len::Unfinished => Err("unfinished capture".to_string()),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Some(Match {
+        start,
+        end,
+        captures,
+    }))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Len {
+    Unfinished,
+    Position,
+    Closed(usize),
+}
+
+struct Matcher<'a> {
+    source: &'a [u8],
+    pattern: &'a [u8],
+    /// The start and length of every capture opened so far.
+    captures: Vec<(usize, Len)>,
+    depth: usize,
+}
+
+impl Matcher<'_> {
+    /// Match the pattern from `p` on against the source from `s` on, returning where the match ends.
+    fn matches(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("pattern too complex".to_string());
+        }
+        let result = self.matches_inner(s, p);
+        self.depth -= 1;
+        result
+    }
+
+    fn matches_inner(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        let pattern = self.pattern;
+        loop {
+            let Some(&current) = pattern.get(p) else {
+                return Ok(Some(s));
+            };
+            match current {
+                b'(' if pattern.get(p + 1) == Some(&b')') => {
+                    return self.capture(s, p + 2, Len::Position);
+                }
+                b'(' => return self.capture(s, p + 1, Len::Unfinished),
+                b')' => return self.close_capture(s, p + 1),
+                b'$' if p + 1 == pattern.len() => {
+                    return Ok((s == self.source.len()).then_some(s));
+                }
+                b'%' if pattern.get(p + 1) == Some(&b'b') => {
+                    let Some(end) = self.balance(s, p + 2)? else {
+                        return Ok(None);
+                    };
+                    s = end;
+                    p += 4;
+                    continue;
+                }
+                b'%' if pattern.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if pattern.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let end = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.source[s - 1] };
+                    let next = self.source.get(s).copied().unwrap_or(0);
+                    if self.bracket_class(previous, p, end - 1)
+                        || !self.bracket_class(next, p, end - 1)
+                    {
+                        return Ok(None);
+                    }
+                    p = end;
+                    continue;
+                }
+                b'%' if pattern.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    let Some(end) = self.back_reference(s, pattern[p + 1])? else {
+                        return Ok(None);
+                    };
+                    s = end;
+                    p += 2;
+                    continue;
+                }
+                _ => {}
+            }
+            let end = self.class_end(p)?;
+            let matched = self
+                .source
+                .get(s)
+                .is_some_and(|&byte| self.single(byte, p, end));
+            match pattern.get(end) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(found) = self.matches(s + 1, end + 1)? {
+                            return Ok(Some(found));
+                        }
+                    }
+                    p = end + 1;
+                }
+                Some(b'*') => return self.max_expand(s, p, end),
+                Some(b'+') if matched => return self.max_expand(s + 1, p, end),
+                Some(b'+') => return Ok(None),
+                Some(b'-') => return self.min_expand(s, p, end),
+                _ if matched => {
+                    s += 1;
+                    p = end;
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Where the single-character class starting at `p` ends.
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let pattern = self.pattern;
+        let current = pattern[p];
+        p += 1;
+        match current {
+            b'%' if p >= pattern.len() => Err("malformed pattern (ends with '%')".to_string()),
+            b'%' => Ok(p + 1),
+            b'[' => {
+                if pattern.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                // The first character is never the closing `]`, so that `[]]` works.
+                loop {
+                    let Some(&byte) = pattern.get(p) else {
+                        return Err("malformed pattern (missing ']')".to_string());
+                    };
+                    p += 1;
+                    if byte == b'%' && p < pattern.len() {
+                        p += 1;
+                    }
+                    if pattern.get(p) == Some(&b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    /// Whether `byte` matches the single-character class from `p` to `end`.
+    fn single(&self, byte: u8, p: usize, end: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => class(byte, self.pattern[p + 1]),
+            b'[' => self.bracket_class(byte, p, end - 1),
+            literal => literal == byte,
+        }
+    }
+
+    /// Whether `byte` matches the set from the `[` at `p` to the `]` at `end`.
+    fn bracket_class(&self, byte: u8, mut p: usize, end: usize) -> bool {
+        let pattern = self.pattern;
+        let mut found = true;
+        if pattern.get(p + 1) == Some(&b'^') {
+            found = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if pattern[p] == b'%' {
+                p += 1;
+                if class(byte, pattern[p]) {
+                    return found;
+                }
+            } else if pattern.get(p + 1) == Some(&b'-') && p + 2 < end {
+                if pattern[p] <= byte && byte <= pattern[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if pattern[p] == byte {
+                return found;
+            }
+            p += 1;
+        }
+        !found
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+        while self
+            .source
+            .get(s + count)
+            .is_some_and(|&byte| self.single(byte, p, end))
+        {
+            count += 1;
+        }
+        // Try the longest repetition first, then back off one at a time.
+        loop {
+            if let Some(found) = self.matches(s + count, end + 1)? {
+                return Ok(Some(found));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(found) = self.matches(s, end + 1)? {
+                return Ok(Some(found));
+            }
+            if !self
+                .source
+                .get(s)
+                .is_some_and(|&byte| self.single(byte, p, end))
+            {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn capture(&mut self, s: usize, p: usize, len: Len) -> Result<Option<usize>, String> {
+        self.captures.push((s, len));
+        let found = self.matches(s, p)?;
+        if found.is_none() {
+            let _ = self.captures.pop();
+        }
+        Ok(found)
+    }
+
+    fn close_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let Some(index) = self
+            .captures
+            .iter()
+            .rposition(|(_, len)| matches!(len, Len::Unfinished))
+        else {
+            return Err("invalid pattern capture".to_string());
+        };
+        self.captures[index].1 = Len::Closed(s - self.captures[index].0);
+        let found = self.matches(s, p)?;
+        if found.is_none() {
+            self.captures[index].1 = Len::Unfinished;
+        }
+        Ok(found)
+    }
+
+    /// Match a `%b` pair, whose opening and closing characters start at `p`.
+    fn balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let (Some(&open), Some(&close)) = (self.pattern.get(p), self.pattern.get(p + 1)) else {
+            return Err("unbalanced pattern".to_string());
+        };
+        if self.source.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (offset, &byte) in self.source[s + 1..].iter().enumerate() {
+            if byte == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(s + offset + 2));
+                }
+            } else if byte == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Match the same text as the capture `%digit` did.
+    fn back_reference(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let index = usize::from(digit - b'0');
+        let Some(&(start, Len::Closed(len))) = index.checked_sub(1).and_then(|i| self.captures.get(i))
+        else {
+            return Err(format!("invalid capture index %{index}"));
+        };
+        let captured = &self.source[start..start + len];
+        Ok(self.source[s..].starts_with(captured).then_some(s + len))
+    }
+}
+
+/// Whether `byte` belongs to the class `%letter`, where uppercase letters negate the class.
+fn class(byte: u8, letter: u8) -> bool {
+    let matches = match letter.to_ascii_lowercase() {
+        b'a' => byte.is_ascii_alphabetic(),
+        b'c' => byte.is_ascii_control(),
+        b'd' => byte.is_ascii_digit(),
+        b'l' => byte.is_ascii_lowercase(),
+        b'p' => byte.is_ascii_punctuation(),
+        b's' => byte.is_ascii_whitespace() || byte == 0x0b,
+        b'u' => byte.is_ascii_uppercase(),
+        b'w' => byte.is_ascii_alphanumeric(),
+        b'x' => byte.is_ascii_hexdigit(),
+        b'z' => byte == 0,
+        _ => return letter == byte,
+    };
+    if letter.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, Capture};
+
+    fn captures(source: &str, pattern: &str) -> Option<Vec<String>> {
+        let found = find(source.as_bytes(), pattern.as_bytes(), 0).unwrap()?;
+        Some(
+            found
+                .captures_or_whole()
+                .into_iter()
+                .map(|capture| match capture {
+                    Capture::Range(start, end) => source[start..end].to_string(),
+                    Capture::Position(position) => position.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(captures("key:123", "%d+").unwrap(), ["123"]);
+        assert_eq!(captures("key:123", "(%a+):(%d+)").unwrap(), ["key", "123"]);
+        assert_eq!(captures("hello world", "^(%w+)").unwrap(), ["hello"]);
+        assert_eq!(captures("  trim  ", "^%s*(.-)%s*$").unwrap(), ["trim"]);
+        assert_eq!(captures("f(a(b)c)d", "%b()").unwrap(), ["(a(b)c)"]);
+        assert_eq!(
+            captures("THE (quick) fox", "%f[%a]%a+%f[%A]").unwrap(),
+            ["THE"]
+        );
+        assert_eq!(captures("abcabc", "()b").unwrap(), ["2"]);
+        assert_eq!(captures("xyzzy", "(z)%1").unwrap(), ["z"]);
+        assert_eq!(captures("a]b", "[]]").unwrap(), ["]"]);
+        assert_eq!(captures("a-b", "[%a-]+").unwrap(), ["a-b"]);
+        assert_eq!(captures("abc", "^b"), None);
+        assert_eq!(captures("abc", "[^abc]"), None);
+        assert!(find(b"abc", b"[a", 0).is_err());
+        assert!(find(b"abc", b"%", 0).is_err());
+        assert!(find(b"abc", b"(a", 0).is_err());
+    }
+}
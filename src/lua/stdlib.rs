@@ -0,0 +1,1081 @@
+//! # The standard libraries that scripts get: the base functions, `string`, `table` and `math`.
+
+use super::interpreter::Interpreter;
+use super::pattern::{self, Capture, Match};
+use super::value::{format_exponent, format_general, Builtin, Table, Value};
+use super::Error;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The most values that `unpack` returns, like `LUAI_MAXCSTACK` in Lua.
+const MAX_UNPACK: usize = 8000;
+
+const BASE: [(&str, Builtin); 14] = [
+    ("assert", assert),
+    ("error", error),
+    ("ipairs", ipairs),
+    ("next", next),
+    ("pairs", pairs),
+    ("pcall", pcall),
+    ("rawequal", rawequal),
+    ("rawget", rawget),
+    ("rawset", rawset),
+    ("select", select),
+    ("tonumber", tonumber),
+    ("tostring", tostring),
+    ("type", type_of),
+    ("unpack", unpack),
+];
+
+const STRING: [(&str, Builtin); 13] = [
+    ("byte", string_byte),
+    ("char", string_char),
+    ("find", string_find),
+    ("format", string_format),
+    ("gmatch", string_gmatch),
+    ("gsub", string_gsub),
+    ("len", string_len),
+    ("lower", string_lower),
+    ("match", string_match),
+    ("rep", string_rep),
+    ("reverse", string_reverse),
+    ("sub", string_sub),
+    ("upper", string_upper),
+];
+
+const TABLE: [(&str, Builtin); 5] = [
+    ("concat", table_concat),
+    ("getn", table_getn),
+    ("insert", table_insert),
+    ("remove", table_remove),
+    ("sort", table_sort),
+];
+
+const MATH: [(&str, Builtin); 12] = [
+    ("abs", |interpreter, args| {
+        math(interpreter, &args, "abs", f64::abs)
+    }),
+    ("ceil", |interpreter, args| {
+        math(interpreter, &args, "ceil", f64::ceil)
+    }),
+    ("exp", |interpreter, args| {
+        math(interpreter, &args, "exp", f64::exp)
+    }),
+    ("floor", |interpreter, args| {
+        math(interpreter, &args, "floor", f64::floor)
+    }),
+    ("log", |interpreter, args| {
+        math(interpreter, &args, "log", f64::ln)
+    }),
+    ("log10", |interpreter, args| {
+        math(interpreter, &args, "log10", f64::log10)
+    }),
+    ("sqrt", |interpreter, args| {
+        math(interpreter, &args, "sqrt", f64::sqrt)
+    }),
+    ("fmod", math_fmod),
+    ("max", math_max),
+    ("min", math_min),
+    ("modf", math_modf),
+    ("pow", math_pow),
+];
+
+/// The global variables that every script starts with.
+pub fn globals() -> Table {
+    let library = |functions: &[(&'static str, Builtin)]| {
+        let mut table = Table::default();
+        for &(name, function) in functions {
+            table.set_field(name, Value::Builtin(name, function));
+        }
+        table
+    };
+    let mut globals = library(&BASE);
+    let mut math = library(&MATH);
+    math.set_field("huge", Value::Number(f64::INFINITY));
+    math.set_field("pi", Value::Number(std::f64::consts::PI));
+    globals.set_field("string", Value::table(library(&STRING)));
+    globals.set_field("table", Value::table(library(&TABLE)));
+    globals.set_field("math", Value::table(math));
+    globals.set_field("_VERSION", Value::string("Lua 5.1"));
+    globals
+}
+
+fn arg(args: &[Value], index: usize) -> Value {
+    args.get(index).cloned().unwrap_or(Value::Nil)
+}
+
+fn bad_argument(interpreter: &Interpreter<'_>, index: usize, name: &str, message: &str) -> Error {
+    interpreter.error(format_args!(
+        "bad argument #{} to '{name}' ({message})",
+        index + 1
+    ))
+}
+
+fn expected(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    index: usize,
+    name: &str,
+    wanted: &str,
+) -> Error {
+    let got = args.get(index).map_or("no value", Value::type_name);
+    bad_argument(
+        interpreter,
+        index,
+        name,
+        &format!("{wanted} expected, got {got}"),
+    )
+}
+
+fn check_table(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<Rc<RefCell<Table>>, Error> {
+    match args.get(index) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(expected(interpreter, args, index, name, "table")),
+    }
+}
+
+fn check_number(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<f64, Error> {
+    args.get(index)
+        .and_then(Value::to_number)
+        .ok_or_else(|| expected(interpreter, args, index, name, "number"))
+}
+
+fn optional_number(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    index: usize,
+    name: &str,
+    default: f64,
+) -> Result<f64, Error> {
+    match args.get(index) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_number(interpreter, args, index, name),
+    }
+}
+
+fn check_string(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<Rc<str>, Error> {
+    args.get(index)
+        .and_then(Value::to_str)
+        .ok_or_else(|| expected(interpreter, args, index, name, "string"))
+}
+
+fn assert(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    if arg(&args, 0).is_truthy() {
+        return Ok(args);
+    }
+    if args.is_empty() {
+        return Err(expected(interpreter, &args, 0, "assert", "value"));
+    }
+    Err(Error::Runtime(match arg(&args, 1) {
+        Value::Nil => Value::string("assertion failed!"),
+        message => message,
+    }))
+}
+
+fn error(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let level = optional_number(interpreter, &args, 1, "error", 1.0)?;
+    Err(match arg(&args, 0) {
+        Value::String(message) if level > 0.0 => interpreter.error(message),
+        value => Error::Runtime(value),
+    })
+}
+
+fn ipairs(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "ipairs")?;
+    Ok(vec![
+        Value::Builtin("ipairs_iterator", ipairs_iterator),
+        Value::Table(table),
+        Value::Number(0.0),
+    ])
+}
+
+fn ipairs_iterator(
+    interpreter: &mut Interpreter<'_>,
+    args: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "ipairs_iterator")?;
+    let index = Value::Number(check_number(interpreter, &args, 1, "ipairs_iterator")? + 1.0);
+    let value = table.borrow().get(&index);
+    Ok(if value.is_nil() {
+        vec![Value::Nil]
+    } else {
+        vec![index, value]
+    })
+}
+
+fn next(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "next")?;
+    let next = table.borrow().next(&arg(&args, 1));
+    Ok(next.map_or_else(|| vec![Value::Nil], |(key, value)| vec![key, value]))
+}
+
+fn pairs(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "pairs")?;
+    Ok(vec![
+        Value::Builtin("next", next),
+        Value::Table(table),
+        Value::Nil,
+    ])
+}
+
+fn pcall(interpreter: &mut Interpreter<'_>, mut args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    if args.is_empty() {
+        return Err(expected(interpreter, &args, 0, "pcall", "value"));
+    }
+    let function = args.remove(0);
+    match interpreter.call(&function, args) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(Error::Runtime(value)) => Ok(vec![Value::Boolean(false), value]),
+        // Running out of resources must stop the script, whatever it does.
+        Err(err) => Err(err),
+    }
+}
+
+fn rawequal(_: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    Ok(vec![Value::Boolean(
+        arg(&args, 0).raw_equals(&arg(&args, 1)),
+    )])
+}
+
+fn rawget(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "rawget")?;
+    let value = table.borrow().get(&arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn rawset(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "rawset")?;
+    interpreter.set(&table, arg(&args, 1), arg(&args, 2))?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(interpreter: &mut Interpreter<'_>, mut args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    if matches!(args.first(), Some(Value::String(string)) if &**string == "#") {
+        return Ok(vec![Value::Number((args.len() - 1) as f64)]);
+    }
+    let index = check_number(interpreter, &args, 0, "select")? as i64;
+    let count = args.len() as i64 - 1;
+    let index = if index < 0 { count + index + 1 } else { index };
+    if index < 1 {
+        return Err(bad_argument(interpreter, 0, "select", "index out of range"));
+    }
+    Ok(args.split_off((index as usize).min(args.len())))
+}
+
+fn tonumber(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let base = optional_number(interpreter, &args, 1, "tonumber", 10.0)?;
+    let value = arg(&args, 0);
+    if base == 10.0 {
+        return Ok(vec![value.to_number().map_or(Value::Nil, Value::Number)]);
+    }
+    if !(2.0..=36.0).contains(&base) {
+        return Err(bad_argument(
+            interpreter,
+            1,
+            "tonumber",
+            "base out of range",
+        ));
+    }
+    let digits = check_string(interpreter, &args, 0, "tonumber")?;
+    let parsed = i64::from_str_radix(&digits.trim().to_ascii_lowercase(), base as u32);
+    Ok(vec![
+        parsed.map_or(Value::Nil, |number| Value::Number(number as f64))
+    ])
+}
+
+fn tostring(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    match args.first() {
+        Some(string @ Value::String(_)) => Ok(vec![string.clone()]),
+        Some(value) => Ok(vec![interpreter.new_string(value.to_string())?]),
+        None => Err(expected(interpreter, &args, 0, "tostring", "value")),
+    }
+}
+
+fn type_of(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    match args.first() {
+        Some(value) => Ok(vec![Value::string(value.type_name())]),
+        None => Err(expected(interpreter, &args, 0, "type", "value")),
+    }
+}
+
+fn unpack(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "unpack")?;
+    let len = table.borrow().len() as f64;
+    let first = optional_number(interpreter, &args, 1, "unpack", 1.0)? as i64;
+    let last = optional_number(interpreter, &args, 2, "unpack", len)? as i64;
+    if last - first >= MAX_UNPACK as i64 {
+        return Err(interpreter.error("too many results to unpack"));
+    }
+    let table = table.borrow();
+    Ok((first..=last)
+        .map(|index| table.get(&Value::Number(index as f64)))
+        .collect())
+}
+
+/// Resolve a position of `string.sub` and friends, where negative ones count from the end.
+fn relative(position: f64, len: usize) -> i64 {
+    let position = position as i64;
+    if position < 0 {
+        len as i64 + position + 1
+    } else {
+        position
+    }
+}
+
+fn string_byte(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "byte")?;
+    let start = optional_number(interpreter, &args, 1, "byte", 1.0)?;
+    let end = optional_number(interpreter, &args, 2, "byte", start)?;
+    let start = relative(start, string.len()).max(1) as usize;
+    let end = relative(end, string.len()).min(string.len() as i64);
+    if end < start as i64 {
+        return Ok(vec![]);
+    }
+    Ok(string.as_bytes()[start - 1..end as usize]
+        .iter()
+        .map(|&byte| Value::Number(f64::from(byte)))
+        .collect())
+}
+
+fn string_char(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for index in 0..args.len() {
+        let code = check_number(interpreter, &args, index, "char")?;
+        let byte = u8::try_from(code as i64)
+            .map_err(|_| bad_argument(interpreter, index, "char", "invalid value"))?;
+        bytes.push(byte);
+    }
+    Ok(vec![
+        interpreter.new_string(String::from_utf8_lossy(&bytes))?
+    ])
+}
+
+/// The start of a search, if it's within `string` at all.
+fn search_start(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    string: &str,
+    name: &str,
+) -> Result<Option<usize>, Error> {
+    let init = optional_number(interpreter, args, 2, name, 1.0)?;
+    let init = relative(init, string.len()).max(1) as usize;
+    Ok((init <= string.len() + 1).then_some(init - 1))
+}
+
+fn capture_value(
+    interpreter: &mut Interpreter<'_>,
+    source: &str,
+    capture: Capture,
+) -> Result<Value, Error> {
+    match capture {
+        Capture::Range(start, end) => {
+            interpreter.new_string(String::from_utf8_lossy(&source.as_bytes()[start..end]))
+        }
+        Capture::Position(position) => Ok(Value::Number(position as f64)),
+    }
+}
+
+fn captures(
+    interpreter: &mut Interpreter<'_>,
+    source: &str,
+    captures: Vec<Capture>,
+) -> Result<Vec<Value>, Error> {
+    captures
+        .into_iter()
+        .map(|capture| capture_value(interpreter, source, capture))
+        .collect()
+}
+
+fn find_pattern(
+    interpreter: &Interpreter<'_>,
+    source: &str,
+    pattern: &str,
+    init: usize,
+) -> Result<Option<Match>, Error> {
+    pattern::find(source.as_bytes(), pattern.as_bytes(), init)
+        .map_err(|message| interpreter.error(message))
+}
+
+fn string_find(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "find")?;
+    let pattern = check_string(interpreter, &args, 1, "find")?;
+    let Some(init) = search_start(interpreter, &args, &string, "find")? else {
+        return Ok(vec![Value::Nil]);
+    };
+    if arg(&args, 3).is_truthy() || pattern::is_plain(pattern.as_bytes()) {
+        let found = string.as_bytes()[init..]
+            .windows(pattern.len().max(1))
+            .position(|window| pattern.is_empty() || window == pattern.as_bytes())
+            .or_else(|| pattern.is_empty().then_some(0));
+        return Ok(found.map_or_else(
+            || vec![Value::Nil],
+            |offset| {
+                let start = init + offset;
+                vec![
+                    Value::Number((start + 1) as f64),
+                    Value::Number((start + pattern.len()) as f64),
+                ]
+            },
+        ));
+    }
+    let Some(found) = find_pattern(interpreter, &string, &pattern, init)? else {
+        return Ok(vec![Value::Nil]);
+    };
+    let mut results = vec![
+        Value::Number((found.start + 1) as f64),
+        Value::Number(found.end as f64),
+    ];
+    results.extend(captures(interpreter, &string, found.captures)?);
+    Ok(results)
+}
+
+fn string_match(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "match")?;
+    let pattern = check_string(interpreter, &args, 1, "match")?;
+    let Some(init) = search_start(interpreter, &args, &string, "match")? else {
+        return Ok(vec![Value::Nil]);
+    };
+    match find_pattern(interpreter, &string, &pattern, init)? {
+        Some(found) => captures(interpreter, &string, found.captures_or_whole()),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+/// Returns an iterator for the generic `for`, whose state is a table holding the
+/// string, the pattern and the position to continue searching from.
+fn string_gmatch(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "gmatch")?;
+    let pattern = check_string(interpreter, &args, 1, "gmatch")?;
+    let mut state = Table::default();
+    state.set_field("source", Value::String(string));
+    state.set_field("pattern", Value::String(pattern));
+    state.set_field("position", Value::Number(0.0));
+    Ok(vec![
+        Value::Builtin("gmatch_iterator", gmatch_iterator),
+        interpreter.new_table(state)?,
+        Value::Nil,
+    ])
+}
+
+fn gmatch_iterator(
+    interpreter: &mut Interpreter<'_>,
+    args: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let state = check_table(interpreter, &args, 0, "gmatch_iterator")?;
+    let (source, pattern, position) = {
+        let state = state.borrow();
+        (
+            state
+                .field("source")
+                .to_str()
+                .unwrap_or_else(|| Rc::from("")),
+            state
+                .field("pattern")
+                .to_str()
+                .unwrap_or_else(|| Rc::from("")),
+            state.field("position").to_number().unwrap_or_default() as usize,
+        )
+    };
+    if position > source.len() {
+        return Ok(vec![Value::Nil]);
+    }
+    let Some(found) = find_pattern(interpreter, &source, &pattern, position)? else {
+        state.borrow_mut().set_field("position", Value::Number(f64::INFINITY));
+        return Ok(vec![Value::Nil]);
+    };
+    // An empty match moves on by one, so that it's not found again forever.
+    let next = found
+        .end
+        .max(found.start + usize::from(found.end == found.start));
+    state
+        .borrow_mut()
+        .set_field("position", Value::Number(next as f64));
+    captures(interpreter, &source, found.captures_or_whole())
+}
+
+fn string_gsub(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "gsub")?;
+    let pattern = check_string(interpreter, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+    if !matches!(
+        replacement,
+        Value::String(_)
+            | Value::Number(_)
+            | Value::Table(_)
+            | Value::Function(_)
+            | Value::Builtin(..)
+    ) {
+        return Err(bad_argument(
+            interpreter,
+            2,
+            "gsub",
+            "string/function/table expected",
+        ));
+    }
+    let max = match args.get(3) {
+        None | Some(Value::Nil) => None,
+        Some(_) => Some(check_number(interpreter, &args, 3, "gsub")? as i64),
+    };
+    let (anchored, pattern) = match pattern.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, &*pattern),
+    };
+    let source = string.as_bytes();
+    let mut result = Vec::with_capacity(source.len());
+    let mut position = 0;
+    let mut count = 0;
+    while max.map_or(true, |max| count < max) {
+        let found = pattern::match_at(source, pattern.as_bytes(), position)
+            .map_err(|message| interpreter.error(message))?;
+        if let Some(found) = &found {
+            count += 1;
+            replace(interpreter, &string, found, &replacement, &mut result)?;
+        }
+        match found {
+            Some(found) if found.end > position => position = found.end,
+            _ if position < source.len() => {
+                result.push(source[position]);
+                position += 1;
+            }
+            _ => break,
+        }
+        if anchored {
+            break;
+        }
+    }
+    result.extend_from_slice(&source[position.min(source.len())..]);
+    Ok(vec![
+        interpreter.new_string(String::from_utf8_lossy(&result))?,
+        Value::Number(count as f64),
+    ])
+}
+
+/// Append the replacement of the match `found` to `result`, like `string.gsub` does.
+fn replace(
+    interpreter: &mut Interpreter<'_>,
+    source: &str,
+    found: &Match,
+    replacement: &Value,
+    result: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let whole = &source.as_bytes()[found.start..found.end];
+    let captured = found.captures_or_whole();
+    let value = match replacement {
+        Value::Table(table) => {
+            let key = capture_value(interpreter, source, captured[0])?;
+            let value = table.borrow().get(&key);
+            value
+        }
+        Value::Function(_) | Value::Builtin(..) => {
+            let args = captures(interpreter, source, captured)?;
+            let results = interpreter.call(replacement, args)?;
+            results.into_iter().next().unwrap_or(Value::Nil)
+        }
+        _ => {
+            let template = replacement.to_str().unwrap_or_else(|| Rc::from(""));
+            let mut bytes = template.bytes();
+            while let Some(byte) = bytes.next() {
+                if byte != b'%' {
+                    result.push(byte);
+                    continue;
+                }
+                match bytes.next() {
+                    Some(b'0') => result.extend_from_slice(whole),
+                    Some(digit @ b'1'..=b'9') => {
+                        let index = usize::from(digit - b'1');
+                        let Some(&capture) = captured.get(index) else {
+                            return Err(interpreter.error("invalid capture index"));
+                        };
+                        match capture_value(interpreter, source, capture)? {
+                            Value::String(string) => result.extend_from_slice(string.as_bytes()),
+                            other => result.extend_from_slice(other.to_string().as_bytes()),
+                        }
+                    }
+                    Some(other) => result.push(other),
+                    None => {}
+                }
+            }
+            return Ok(());
+        }
+    };
+    match value {
+        Value::Nil | Value::Boolean(false) => result.extend_from_slice(whole),
+        value => match value.to_str() {
+            Some(string) => result.extend_from_slice(string.as_bytes()),
+            None => {
+                return Err(interpreter.error(format_args!(
+                    "invalid replacement value (a {})",
+                    value.type_name()
+                )))
+            }
+        },
+    }
+    Ok(())
+}
+
+fn string_len(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "len")?;
+    Ok(vec![Value::Number(string.len() as f64)])
+}
+
+fn string_lower(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "lower")?;
+    Ok(vec![interpreter.new_string(string.to_ascii_lowercase())?])
+}
+
+fn string_upper(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "upper")?;
+    Ok(vec![interpreter.new_string(string.to_ascii_uppercase())?])
+}
+
+fn string_rep(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "rep")?;
+    let count = check_number(interpreter, &args, 1, "rep")?.max(0.0) as usize;
+    // Charged upfront, so that a huge count fails before allocating anything.
+    interpreter.charge(string.len().saturating_mul(count))?;
+    Ok(vec![Value::string(string.repeat(count))])
+}
+
+fn string_reverse(
+    interpreter: &mut Interpreter<'_>,
+    args: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "reverse")?;
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.reverse();
+    Ok(vec![
+        interpreter.new_string(String::from_utf8_lossy(&bytes))?
+    ])
+}
+
+fn string_sub(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let string = check_string(interpreter, &args, 0, "sub")?;
+    let start = relative(
+        optional_number(interpreter, &args, 1, "sub", 1.0)?,
+        string.len(),
+    )
+    .max(1);
+    let end = relative(
+        optional_number(interpreter, &args, 2, "sub", -1.0)?,
+        string.len(),
+    )
+    .min(string.len() as i64);
+    if start > end {
+        return Ok(vec![Value::string("")]);
+    }
+    let bytes = &string.as_bytes()[start as usize - 1..end as usize];
+    Ok(vec![interpreter.new_string(String::from_utf8_lossy(bytes))?])
+}
+
+fn string_format(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let template = check_string(interpreter, &args, 0, "format")?;
+    let mut formatted = String::new();
+    let mut index = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(char) = chars.next() {
+        if char != '%' {
+            formatted.push(char);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            formatted.push('%');
+            continue;
+        }
+        let mut flags = String::new();
+        while let Some(flag) = chars.next_if(|char| "-+ #0".contains(*char)) {
+            flags.push(flag);
+        }
+        let mut width = 0;
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width = width * 10 + digit as usize - '0' as usize;
+        }
+        let precision = chars.next_if_eq(&'.').map(|_| {
+            let mut precision = 0;
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                precision = precision * 10 + digit as usize - '0' as usize;
+            }
+            precision
+        });
+        let conversion = chars.next().unwrap_or('%');
+        index += 1;
+        let (sign, body) = match conversion {
+            'd' | 'i' | 'u' | 'c' | 'x' | 'X' | 'o' | 'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+                let number = check_number(interpreter, &args, index, "format")?;
+                format_numeric(number, conversion, &flags, precision)
+            }
+            's' => {
+                let mut string = match args.get(index) {
+                    Some(value) => value.to_string(),
+                    None => return Err(expected(interpreter, &args, index, "format", "string")),
+                };
+                if let Some(precision) = precision {
+                    string = string.chars().take(precision).collect();
+                }
+                (String::new(), string)
+            }
+            'q' => {
+                let string = check_string(interpreter, &args, index, "format")?;
+                (String::new(), quote(&string))
+            }
+            other => {
+                return Err(interpreter.error(format_args!("invalid option '%{other}' to 'format'")))
+            }
+        };
+        let len = sign.len() + body.chars().count();
+        let padding = width.saturating_sub(len);
+        if flags.contains('-') {
+            formatted.push_str(&sign);
+            formatted.push_str(&body);
+            formatted.extend(std::iter::repeat(' ').take(padding));
+        } else if flags.contains('0') && !matches!(conversion, 's' | 'q' | 'c') {
+            formatted.push_str(&sign);
+            formatted.extend(std::iter::repeat('0').take(padding));
+            formatted.push_str(&body);
+        } else {
+            formatted.extend(std::iter::repeat(' ').take(padding));
+            formatted.push_str(&sign);
+            formatted.push_str(&body);
+        }
+    }
+    Ok(vec![interpreter.new_string(formatted)?])
+}
+
+/// Format `number` for `string.format`, returning the sign and the digits separately.
+fn format_numeric(
+    number: f64,
+    conversion: char,
+    flags: &str,
+    precision: Option<usize>,
+) -> (String, String) {
+    let sign = if number.is_sign_negative() && !(number == 0.0 && "diucxXo".contains(conversion)) {
+        "-"
+    } else if flags.contains('+') {
+        "+"
+    } else if flags.contains(' ') {
+        " "
+    } else {
+        ""
+    };
+    let magnitude = number.abs();
+    let body = match conversion {
+        'c' => return (String::new(), char::from(number as u8).to_string()),
+        'x' | 'X' | 'o' => {
+            let integer = number as i64 as u64;
+            let digits = match conversion {
+                'x' => format!("{integer:x}"),
+                'X' => format!("{integer:X}"),
+                _ => format!("{integer:o}"),
+            };
+            let prefix = match conversion {
+                'x' if flags.contains('#') && integer != 0 => "0x",
+                'X' if flags.contains('#') && integer != 0 => "0X",
+                _ => "",
+            };
+            return (prefix.to_string(), digits);
+        }
+        'd' | 'i' | 'u' => {
+            let digits = (magnitude as i64).to_string();
+            format!("{digits:0>width$}", width = precision.unwrap_or(1))
+        }
+        'e' => format_exponent(magnitude, precision.unwrap_or(6)),
+        'E' => format_exponent(magnitude, precision.unwrap_or(6)).to_ascii_uppercase(),
+        'f' | 'F' => {
+            let precision = precision.unwrap_or(6);
+            format!("{magnitude:.precision$}")
+        }
+        'g' => format_general(magnitude, precision.unwrap_or(6), flags.contains('#')),
+        _ => format_general(magnitude, precision.unwrap_or(6), flags.contains('#'))
+            .to_ascii_uppercase(),
+    };
+    (sign.to_string(), body)
+}
+
+/// Quote a string so that Lua can read it back, like `%q` does.
+fn quote(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\000"),
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn table_concat(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "concat")?;
+    let separator = match args.get(1) {
+        None | Some(Value::Nil) => Rc::from(""),
+        Some(_) => check_string(interpreter, &args, 1, "concat")?,
+    };
+    let len = table.borrow().len() as f64;
+    let first = optional_number(interpreter, &args, 2, "concat", 1.0)? as i64;
+    let last = optional_number(interpreter, &args, 3, "concat", len)? as i64;
+    let mut concatenated = String::new();
+    for index in first..=last {
+        let value = table.borrow().get(&Value::Number(index as f64));
+        let Some(string) = value.to_str() else {
+            return Err(interpreter.error(format_args!(
+                "invalid value (at index {index}) in table for 'concat'"
+            )));
+        };
+        if index > first {
+            concatenated.push_str(&separator);
+        }
+        concatenated.push_str(&string);
+        interpreter.charge(string.len() + separator.len())?;
+    }
+    Ok(vec![Value::string(concatenated)])
+}
+
+fn table_getn(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "getn")?;
+    let len = table.borrow().len();
+    Ok(vec![Value::Number(len as f64)])
+}
+
+fn table_insert(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "insert")?;
+    let len = table.borrow().len();
+    let (position, value) = match args.len() {
+        2 => (len + 1, arg(&args, 1)),
+        3 => {
+            let position = check_number(interpreter, &args, 1, "insert")?;
+            (position.max(1.0) as usize, arg(&args, 2))
+        }
+        _ => return Err(interpreter.error("wrong number of arguments to 'insert'")),
+    };
+    for index in (position..=len).rev() {
+        let moved = table.borrow().get(&Value::Number(index as f64));
+        interpreter.set(&table, Value::Number((index + 1) as f64), moved)?;
+    }
+    interpreter.set(&table, Value::Number(position as f64), value)?;
+    Ok(vec![])
+}
+
+fn table_remove(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "remove")?;
+    let len = table.borrow().len();
+    if len == 0 {
+        return Ok(vec![]);
+    }
+    let position = optional_number(interpreter, &args, 1, "remove", len as f64)?.max(1.0) as usize;
+    let removed = table.borrow().get(&Value::Number(position as f64));
+    for index in position..len {
+        let moved = table.borrow().get(&Value::Number((index + 1) as f64));
+        interpreter.set(&table, Value::Number(index as f64), moved)?;
+    }
+    interpreter.set(&table, Value::Number(len as f64), Value::Nil)?;
+    Ok(vec![removed])
+}
+
+fn table_sort(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &args, 0, "sort")?;
+    let comparator = arg(&args, 1);
+    let values = {
+        let table = table.borrow();
+        (1..=table.len())
+            .map(|index| table.get(&Value::Number(index as f64)))
+            .collect()
+    };
+    let sorted = merge_sort(interpreter, values, &comparator)?;
+    let mut table = table.borrow_mut();
+    for (index, value) in sorted.into_iter().enumerate() {
+        let _ = table.set(Value::Number((index + 1) as f64), value);
+    }
+    Ok(vec![])
+}
+
+/// A merge sort, since the comparisons can fail or call back into the script.
+fn merge_sort(
+    interpreter: &mut Interpreter<'_>,
+    mut values: Vec<Value>,
+    comparator: &Value,
+) -> Result<Vec<Value>, Error> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(interpreter, values, comparator)?;
+    let right = merge_sort(interpreter, right, comparator)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        let right_first = match comparator {
+            Value::Nil => interpreter.less(b, a, false)?,
+            comparator => interpreter
+                .call(comparator, vec![b.clone(), a.clone()])?
+                .first()
+                .is_some_and(Value::is_truthy),
+        };
+        merged.extend(if right_first {
+            right.next()
+        } else {
+            left.next()
+        });
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn math(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    name: &str,
+    function: fn(f64) -> f64,
+) -> Result<Vec<Value>, Error> {
+    Ok(vec![Value::Number(function(check_number(
+        interpreter,
+        args,
+        0,
+        name,
+    )?))])
+}
+
+fn math_fmod(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let a = check_number(interpreter, &args, 0, "fmod")?;
+    let b = check_number(interpreter, &args, 1, "fmod")?;
+    Ok(vec![Value::Number(a % b)])
+}
+
+fn math_pow(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let a = check_number(interpreter, &args, 0, "pow")?;
+    let b = check_number(interpreter, &args, 1, "pow")?;
+    Ok(vec![Value::Number(a.powf(b))])
+}
+
+fn math_modf(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let number = check_number(interpreter, &args, 0, "modf")?;
+    Ok(vec![
+        Value::Number(number.trunc()),
+        Value::Number(number.fract()),
+    ])
+}
+
+fn math_max(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    extremum(interpreter, &args, "max", f64::max)
+}
+
+fn math_min(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    extremum(interpreter, &args, "min", f64::min)
+}
+
+fn extremum(
+    interpreter: &Interpreter<'_>,
+    args: &[Value],
+    name: &str,
+    pick: fn(f64, f64) -> f64,
+) -> Result<Vec<Value>, Error> {
+    let mut extremum = check_number(interpreter, args, 0, name)?;
+    for index in 1..args.len() {
+        extremum = pick(extremum, check_number(interpreter, args, index, name)?);
+    }
+    Ok(vec![Value::Number(extremum)])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lua::{Interpreter, Limits, Value};
+
+    fn run(source: &str) -> Vec<String> {
+        let limits = Limits {
+            memory: 1 << 20,
+            instructions: 100_000,
+        };
+        let mut interpreter = Interpreter::new(limits, Box::new(|_| Value::Nil));
+        let values = interpreter.run(source).unwrap();
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn base() {
+        assert_eq!(
+            run("return type(nil), tostring(1.5), tonumber('0x10'), tonumber('z', 36), tonumber('x')"),
+            ["nil", "1.5", "16", "35", "nil"]
+        );
+        assert_eq!(
+            run("return select('#', select(2, 'a', 'b', 'c')), select(-1, 'a', 'b')"),
+            ["2", "b"]
+        );
+        assert_eq!(
+            run("local ok, err = pcall(error, 'x', 0) return ok, err, unpack({1, 2})"),
+            ["false", "x", "1", "2"]
+        );
+        assert_eq!(
+            run("local n = 0 for k, v in pairs({1, 2, a = 3}) do n = n + v end return n"),
+            ["6"]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            run("return ('hello'):upper(), string.sub('hello', 2, -2), ('ab'):rep(3), #'abc'"),
+            ["HELLO", "ell", "ababab", "3"]
+        );
+        assert_eq!(
+            run("return string.find('a.b', '.', 1, true), string.find('key:42', '(%d+)')"),
+            ["2", "5", "6", "42"]
+        );
+        assert_eq!(
+            run("return string.gsub('hello world', '(%w+)', '<%1>')"),
+            ["<hello> <world>", "2"]
+        );
+        assert_eq!(
+            run("return string.gsub('abc', '%w', {a = 1, b = false}), ('x'):byte(), string.char(72, 105)"),
+            ["1bc", "120", "Hi"]
+        );
+        assert_eq!(
+            run("local t = {} for k, v in string.gmatch('a=1, b=2', '(%w+)=(%w+)') do t[#t + 1] = k .. v end return table.concat(t, ',')"),
+            ["a1,b2"]
+        );
+        assert_eq!(
+            run("return string.format('%5.2f|%-3d|%03d|%x|%s|%q|%g', 3.14159, 7, 5, 255, nil, 'a\"b', 1e20)"),
+            [" 3.14|7  |005|ff|nil|\"a\\\"b\"|1e+20"]
+        );
+    }
+
+    #[test]
+    fn tables_and_math() {
+        assert_eq!(
+            run(
+                "local t = {3, 1, 2} table.sort(t) table.insert(t, 1, 0) table.insert(t, 4) \
+                 local removed = table.remove(t, 2) return table.concat(t, ' '), removed"
+            ),
+            ["0 2 3 4", "1"]
+        );
+        assert_eq!(
+            run("local t = {'b', 'c', 'a'} table.sort(t, function(a, b) return a > b end) return unpack(t)"),
+            ["c", "b", "a"]
+        );
+        assert_eq!(
+            run("return math.floor(-1.5), math.max(1, 5, 3), math.fmod(7, 3), 7 % -3, 2 ^ 10, math.huge"),
+            ["-2", "5", "1", "-2", "1024", "inf"]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! # Lua values, and the tables that hold them.
+
+use super::interpreter::{Interpreter, Scope};
+use super::parser::Function;
+use super::Error;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::Bound;
+use std::rc::Rc;
+
+/// A function implemented in Rust, called with the interpreter and the arguments.
+pub type Builtin = fn(&mut Interpreter<'_>, Vec<Value>) -> Result<Vec<Value>, Error>;
+
+/// A Lua value.
+///
+/// Tables and functions are references, which compare equal only to themselves.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<Closure>),
+    /// A function implemented in Rust, along with its name.
+    Builtin(&'static str, Builtin),
+}
+
+/// A function defined by the script, along with the variables it closes over.
+pub struct Closure {
+    pub(super) function: Rc<Function>,
+    pub(super) scope: Rc<Scope>,
+}
+
+impl Value {
+    pub fn string(string: impl Into<Rc<str>>) -> Self {
+        Self::String(string.into())
+    }
+
+    pub fn table(table: Table) -> Self {
+        Self::Table(Rc::new(RefCell::new(table)))
+    }
+
+    /// The name of the type of the value, as returned by `type()`.
+    pub const fn type_name(&self) -> &'static str {
+        match self {
+            Self::Nil => "nil",
+            Self::Boolean(_) => "boolean",
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Table(_) => "table",
+            Self::Function(_) | Self::Builtin(..) => "function",
+        }
+    }
+
+    pub const fn is_nil(&self) -> bool {
+        matches!(self, Self::Nil)
+    }
+
+    /// Whether the value counts as true in conditions, which all but `nil` and `false` do.
+    pub const fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Boolean(false))
+    }
+
+    /// The value as a number, converting strings like arithmetic does.
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(number) => Some(*number),
+            Self::String(string) => super::lexer::parse_number(string),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, converting numbers like concatenation does.
+    pub fn to_str(&self) -> Option<Rc<str>> {
+        match self {
+            Self::String(string) => Some(string.clone()),
+            Self::Number(number) => Some(format_number(*number).into()),
+            _ => None,
+        }
+    }
+
+    /// Compare two values without converting them, like `==` does.
+    pub fn raw_equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Boolean(a), Self::Boolean(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Table(a), Self::Table(b)) => Rc::ptr_eq(a, b),
+            (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(a, b),
+            (Self::Builtin(a, _), Self::Builtin(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Format like `tostring()` does, e.g. `table: 0x5581c9d3a2b0`.
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Boolean(boolean) => write!(f, "{boolean}"),
+            Self::Number(number) => write!(f, "{}", format_number(*number)),
+            Self::String(string) => write!(f, "{string}"),
+            Self::Table(table) => write!(f, "table: {:p}", Rc::as_ptr(table)),
+            Self::Function(closure) => write!(f, "function: {:p}", Rc::as_ptr(closure)),
+            Self::Builtin(name, _) => write!(f, "function: builtin: {name}"),
+        }
+    }
+}
+
+/// Tables and closures can refer to themselves, so only the outermost value is shown.
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(string) => write!(f, "{string:?}"),
+            other => write!(f, "{other}"),
+        }
+    }
+}
+
+/// Format a number like Lua does, with `%.14g`.
+pub fn format_number(number: f64) -> String {
+    format_general(number, 14, false)
+}
+
+/// Format a number like `printf("%.{precision}g")`, keeping trailing zeros if `alternate`.
+pub fn format_general(number: f64, precision: usize, alternate: bool) -> String {
+    if !number.is_finite() {
+        return format_special(number);
+    }
+    let precision = precision.max(1);
+    let (_, exponent) = split_exponent(&format!("{:.*e}", precision - 1, number));
+    let mut formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(number, precision - 1)
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        format!("{number:.decimals$}")
+    };
+    if !alternate && formatted.contains('.') {
+        let (mantissa, exponent) = match formatted.find('e') {
+            Some(at) => formatted.split_at(at),
+            None => (formatted.as_str(), ""),
+        };
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        formatted = format!("{mantissa}{exponent}");
+    }
+    formatted
+}
+
+/// Format a number like `printf("%.{precision}e")`, e.g. `1.500000e+02`.
+pub fn format_exponent(number: f64, precision: usize) -> String {
+    if !number.is_finite() {
+        return format_special(number);
+    }
+    let formatted = format!("{number:.precision$e}");
+    let (mantissa, exponent) = split_exponent(&formatted);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.abs())
+}
+
+fn format_special(number: f64) -> String {
+    match number {
+        number if number.is_nan() => "nan".to_string(),
+        number if number > 0.0 => "inf".to_string(),
+        _ => "-inf".to_string(),
+    }
+}
+
+/// Split the output of Rust's `{:e}` into its mantissa and exponent.
+fn split_exponent(formatted: &str) -> (&str, i32) {
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((formatted, "0"));
+    (mantissa, exponent.parse().unwrap_or(0))
+}
+
+/// A key of the hash part of a [`Table`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Boolean(bool),
+    /// The bits of the number, with `-0` normalized to `0`.
+    Number(u64),
+    String(Rc<str>),
+    /// The address of a table or closure, which the value in the table keeps alive.
+    Reference(usize),
+    Builtin(&'static str),
+}
+
+impl Key {
+    fn of(value: &Value) -> Result<Self, &'static str> {
+        Ok(match value {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(number) if number.is_nan() => return Err("table index is NaN"),
+            Value::Boolean(boolean) => Self::Boolean(*boolean),
+            Value::Number(number) => Self::Number((number + 0.0).to_bits()),
+            Value::String(string) => Self::String(string.clone()),
+            Value::Table(table) => Self::Reference(Rc::as_ptr(table) as *const () as usize),
+            Value::Function(closure) => Self::Reference(Rc::as_ptr(closure) as *const () as usize),
+            Value::Builtin(name, _) => Self::Builtin(name),
+        })
+    }
+}
+
+/// A Lua table, split into an array part for the keys `1..=n` and a hash part for the rest.
+///
+/// The array part never holds `nil`, so its length is always a valid result for `#`.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    /// The other keys, along with the values they were made from, in an arbitrary but stable order.
+    hash: BTreeMap<Key, (Value, Value)>,
+}
+
+/// The index into the array part that `number` refers to, if it's a positive integer.
+fn array_index(number: f64) -> Option<usize> {
+    (number >= 1.0 && number.fract() == 0.0 && number <= u32::MAX.into()).then_some(number as usize)
+}
+
+impl Table {
+    /// A table holding `values` at the keys `1..=n`.
+    pub fn from_array(values: Vec<Value>) -> Self {
+        let mut table = Self::default();
+        for value in values {
+            table.push(value);
+        }
+        table
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Value::Number(number) = key {
+            if let Some(value) = array_index(*number).and_then(|index| self.array.get(index - 1)) {
+                return value.clone();
+            }
+        }
+        Key::of(key)
+            .ok()
+            .and_then(|key| self.hash.get(&key))
+            .map_or(Value::Nil, |(_, value)| value.clone())
+    }
+
+    /// Get the value of the field `name`.
+    pub fn field(&self, name: &str) -> Value {
+        self.get(&Value::string(name))
+    }
+
+    /// Set `key` to `value`, or remove it if `value` is `nil`.
+    ///
+    /// Fails with the message of the error to raise if `key` is `nil` or `NaN`.
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        if let Some(index) = match key {
+            Value::Number(number) => array_index(number),
+            _ => None,
+        } {
+            let len = self.array.len();
+            if index <= len && value.is_nil() {
+                // Keep the array part free of holes, by moving everything after it to the hash part.
+                for (offset, moved) in self.array.drain(index - 1..).enumerate().skip(1) {
+                    let key = Value::Number((index + offset) as f64);
+                    let _ = self.hash.insert(Key::of(&key)?, (key, moved));
+                }
+                return Ok(());
+            }
+            if index <= len {
+                self.array[index - 1] = value;
+                return Ok(());
+            }
+            if index == len + 1 && !value.is_nil() {
+                let _ = self.hash.remove(&Key::of(&key)?);
+                self.array.push(value);
+                self.migrate();
+                return Ok(());
+            }
+        }
+        let key_of = Key::of(&key)?;
+        if value.is_nil() {
+            let _ = self.hash.remove(&key_of);
+        } else {
+            let _ = self.hash.insert(key_of, (key, value));
+        }
+        Ok(())
+    }
+
+    /// Set the field `name` to `value`.
+    pub fn set_field(&mut self, name: &str, value: Value) {
+        let _ = self.set(Value::string(name), value);
+    }
+
+    /// Append `value` to the array part.
+    pub fn push(&mut self, value: Value) {
+        let _ = self.set(Value::Number((self.array.len() + 1) as f64), value);
+    }
+
+    /// Move the keys right after the array part from the hash part into it.
+    fn migrate(&mut self) {
+        loop {
+            let key = Value::Number((self.array.len() + 1) as f64);
+            let Some((_, value)) = Key::of(&key).ok().and_then(|key| self.hash.remove(&key)) else {
+                return;
+            };
+            self.array.push(value);
+        }
+    }
+
+    /// The length of the table, as returned by `#`.
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// The key and value after `key`, or the first ones if `key` is `nil`, like `next()` returns.
+    pub fn next(&self, key: &Value) -> Option<(Value, Value)> {
+        let position = match key {
+            Value::Nil => 0,
+            Value::Number(number) => match array_index(*number) {
+                Some(index) if index <= self.array.len() => index,
+                _ => return self.next_in_hash(key),
+            },
+            _ => return self.next_in_hash(key),
+        };
+        match self.array.get(position) {
+            Some(value) => Some((Value::Number((position + 1) as f64), value.clone())),
+            None => self.hash.values().next().cloned(),
+        }
+    }
+
+    fn next_in_hash(&self, key: &Value) -> Option<(Value, Value)> {
+        let key = Key::of(key).ok()?;
+        self.hash
+            .range((Bound::Excluded(key), Bound::Unbounded))
+            .next()
+            .map(|(_, entry)| entry.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_exponent, format_general, format_number, Table, Value};
+
+    #[test]
+    fn numbers_format_like_lua() {
+        for (number, formatted) in [
+            (1.0, "1"),
+            (-0.5, "-0.5"),
+            (1e15, "1e+15"),
+            (123_456_789_012.0, "123456789012"),
+            (0.1 + 0.2, "0.3"),
+            (1e-5, "1e-05"),
+            (f64::INFINITY, "inf"),
+        ] {
+            assert_eq!(format_number(number), formatted);
+        }
+        assert_eq!(format_general(100_000.0, 6, false), "100000");
+        assert_eq!(format_general(1_000_000.0, 6, false), "1e+06");
+        assert_eq!(format_general(1.5, 6, true), "1.50000");
+        assert_eq!(format_exponent(150.0, 6), "1.500000e+02");
+    }
+
+    #[test]
+    fn array_and_hash_parts() {
+        let mut table = Table::default();
+        table.set(Value::Number(2.0), Value::string("b")).unwrap();
+        assert_eq!(table.len(), 0);
+        table.push(Value::string("a"));
+        assert_eq!(table.len(), 2);
+        table.set_field("x", Value::Boolean(true));
+        assert!(table.set(Value::Nil, Value::Nil).is_err());
+
+        let mut key = Value::Nil;
+        let mut keys = vec![];
+        while let Some((next, _)) = table.next(&key) {
+            keys.push(next.to_string());
+            key = next;
+        }
+        assert_eq!(keys, ["1", "2", "x"]);
+
+        table.set(Value::Number(1.0), Value::Nil).unwrap();
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.get(&Value::Number(2.0)).to_string(), "b");
+    }
+}
@@ -0,0 +1,151 @@
+//! # LZF compression of strings in RDB files.
+//!
+//! Redis compresses the strings that it saves with LZF (when `rdbcompression` is on,
+//! as it is by default), so loading the dumps of a real Redis needs [`decompress`].
+//! The compressed data is a sequence of chunks, each starting with a control byte:
+//!
+//! - `000LLLLL`: `L + 1` literal bytes follow.
+//! - `LLLOOOOO`: A back reference of `L + 2` bytes, starting `O + 1` bytes back, where
+//!   `O` continues with the next byte. If `L` is 7, the next byte is added to it first.
+//!
+//! Like [`compress`](crate::compress) for LZ4, the compressor is a plain greedy one with
+//! a hash table of recent positions, which is all that saving takes.
+
+/// Back references are at least this long, as shorter ones cost more than their literals.
+const MIN_MATCH: usize = 3;
+
+/// The longest back reference: 7 plus a whole extra byte, on top of the minimum of 2.
+const MAX_MATCH: usize = 7 + 255 + 2;
+
+/// Back references reach this far back at most, as offsets have 13 bits.
+const MAX_OFFSET: usize = 1 << 13;
+
+/// The most literal bytes that a single chunk holds.
+const MAX_LITERALS: usize = 32;
+
+/// The number of bits of the hash of 3 bytes, which indexes the table of recent positions.
+const HASH_LOG: u32 = 14;
+
+/// Compress `input`, which may well come out larger than it was for short or random data.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / MAX_LITERALS + 1);
+    // The most recent position of each hash, plus one so that zero means none.
+    let mut recent = vec![0; 1 << HASH_LOG];
+    let (mut anchor, mut position) = (0, 0);
+    while position + MIN_MATCH <= input.len() {
+        let sequence = &input[position..position + MIN_MATCH];
+        let slot = hash(sequence);
+        let candidate = std::mem::replace(&mut recent[slot], position + 1);
+        let found = candidate
+            .checked_sub(1)
+            .filter(|&start| position - start <= MAX_OFFSET)
+            .filter(|&start| &input[start..start + MIN_MATCH] == sequence);
+        let Some(start) = found else {
+            position += 1;
+            continue;
+        };
+        // Matches may overlap what they produce, which decompression copies byte by byte.
+        let len = input[start..]
+            .iter()
+            .zip(&input[position..])
+            .take(MAX_MATCH)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_literals(&mut output, &input[anchor..position]);
+        let (len, offset) = (len - 2, position - start - 1);
+        let high = (offset >> 8) as u8;
+        if len < 7 {
+            output.push((len as u8) << 5 | high);
+        } else {
+            output.push(7 << 5 | high);
+            output.push((len - 7) as u8);
+        }
+        output.push(offset as u8);
+        position += len + 2;
+        anchor = position;
+    }
+    write_literals(&mut output, &input[anchor..]);
+    output
+}
+
+/// Decompress `input` into the `len` bytes that it was compressed from,
+/// or return [`None`] if it does not decompress into exactly that many.
+pub fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(len);
+    let mut input = input.iter().copied();
+    while let Some(control) = input.next() {
+        if control < MAX_LITERALS as u8 {
+            for _ in 0..=control {
+                output.push(input.next()?);
+            }
+        } else {
+            let mut match_len = usize::from(control >> 5);
+            if match_len == 7 {
+                match_len += usize::from(input.next()?);
+            }
+            let offset = usize::from(control & 0x1F) << 8 | usize::from(input.next()?);
+            let start = output.len().checked_sub(offset + 1)?;
+            for index in start..start + match_len + 2 {
+                output.push(output[index]);
+            }
+        }
+        if output.len() > len {
+            return None;
+        }
+    }
+    (output.len() == len).then_some(output)
+}
+
+/// Knuth's multiplicative hash of 3 bytes, keeping the top [`HASH_LOG`] bits.
+fn hash(sequence: &[u8]) -> usize {
+    let sequence = u32::from_le_bytes([sequence[0], sequence[1], sequence[2], 0]);
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+/// Write `literals` in as many chunks as they take.
+fn write_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+    use crate::random::Rng;
+
+    #[test]
+    fn round_trips() {
+        let mut rng = Rng::with_seed(17);
+        let alphabets: [&[u8]; 3] = [b"a", b"abc", b"0123456789abcdef"];
+        for len in [0, 1, 3, 4, 33, 100, 1000, 20_000] {
+            for alphabet in alphabets {
+                let input: Vec<u8> = (0..len)
+                    .map(|_| alphabet[rng.below(alphabet.len())])
+                    .collect();
+                let compressed = compress(&input);
+                assert_eq!(decompress(&compressed, len), Some(input), "{len} bytes");
+            }
+            let noise: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            assert_eq!(
+                decompress(&compress(&noise), len),
+                Some(noise),
+                "{len} bytes"
+            );
+        }
+        assert!(compress(&[b'x'; 1000]).len() < 20);
+    }
+
+    #[test]
+    fn decompresses_by_the_spec() {
+        // A literal `a`, then 9 bytes starting 1 byte back.
+        let compressed = [0x00, b'a', 0xE0, 0x00, 0x00];
+        assert_eq!(decompress(&compressed, 10), Some(b"aaaaaaaaaa".to_vec()));
+        assert_eq!(decompress(&compressed, 9), None);
+        assert_eq!(decompress(&compressed, 11), None);
+        // A back reference to before the start, and a truncated literal.
+        assert_eq!(decompress(&[0x20, 0x05], 3), None);
+        assert_eq!(decompress(&[0x03, b'a', b'b'], 4), None);
+    }
+}
@@ -7,23 +7,49 @@
 //! **Note**: If you're viewing this repo on GitHub, head over to
 //! [codecrafters.io](https://codecrafters.io) to try the challenge.
 
+mod aof;
+mod blocking;
+mod chaos;
+mod check;
+mod client;
+mod clients;
 mod command;
+mod compress;
 mod config;
+mod crc64;
 mod database;
+mod glob;
+mod listpack;
+mod lua;
+mod lzf;
+mod notify;
+mod persistence;
+mod pubsub;
+mod random;
+mod rdb;
+mod replication;
 mod resp;
+mod scripting;
 mod server;
+mod sha1;
+mod shutdown;
+mod slowlog;
+mod snapshot;
+mod stats;
+mod transaction;
+mod verify;
 
 use async_once::AsyncOnce;
-use config::Config;
+use config::{Config, Mode};
 use lazy_static::lazy_static;
 use server::Server;
-use structopt::StructOpt;
-use tracing::Level;
-use tracing_subscriber::fmt;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt, prelude::*, reload, Registry};
 
 lazy_static! {
+    static ref CONFIG: Config = Config::load().expect("Could not load the configuration");
     static ref SERVER: AsyncOnce<Server> = AsyncOnce::new(async {
-        Server::new(Config::from_args())
+        Server::new(CONFIG.clone())
             .await
             .expect("Could not construct a server instance")
     });
@@ -31,18 +57,58 @@ lazy_static! {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    setup();
+    let log_level = setup();
+    // Loading the configuration is logged too, so the configured level only applies afterwards.
+    // It has to be loaded before changing the level, which logging during the change would deadlock.
+    let configured = LevelFilter::from(CONFIG.loglevel);
+    let _ = log_level.modify(|level| *level = configured);
 
-    let server = SERVER.get().await;
-    server.run().await?;
+    match &CONFIG.mode {
+        Some(Mode::VerifyReplica {
+            master,
+            replica,
+            ttl_tolerance,
+        }) => {
+            let divergences = verify::verify_replica(master, replica, *ttl_tolerance).await?;
+            for divergence in &divergences {
+                println!("{divergence}");
+            }
+            println!("{} divergent key(s)", divergences.len());
+            std::process::exit(i32::from(!divergences.is_empty()));
+        }
+        Some(Mode::CheckRdb { file }) => {
+            let summary = check::check_rdb(file);
+            match &summary {
+                Ok(summary) => println!("{summary}"),
+                Err(problem) => println!("FAIL {}: {problem}", file.display()),
+            }
+            std::process::exit(i32::from(summary.is_err()));
+        }
+        None => {}
+    }
+
+    if CONFIG.check {
+        let checks = check::run(&CONFIG);
+        for check in &checks {
+            println!("{check}");
+        }
+        std::process::exit(i32::from(!checks.iter().all(check::Check::passed)));
+    }
 
-    Ok(())
+    let server = SERVER.get().await;
+    let report = server.run().await?;
+    tracing::info!("{report}");
+    std::process::exit(report.exit_code());
 }
 
-fn setup() {
+/// Install the error and log reporting, returning a handle to change the log level later.
+fn setup() -> reload::Handle<LevelFilter, Registry> {
     let _ = color_eyre::install();
-    fmt::Subscriber::builder()
-        .with_max_level(Level::TRACE)
+    let (level, handle) = reload::Layer::new(LevelFilter::TRACE);
+    tracing_subscriber::registry()
+        .with(level)
+        .with(fmt::layer())
         .init();
     tracing::trace!("Setup hook finished");
+    handle
 }
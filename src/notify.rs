@@ -0,0 +1,329 @@
+//! # Keyspace notifications: which changes to the keyspace get announced to subscribers.
+//!
+//! Like in Redis, every change to a key can be published over Pub/Sub, as a message
+//! to `__keyspace@0__:<key>` holding the name of the event, and as a message to
+//! `__keyevent@0__:<event>` holding the key. Which of them get published is set by
+//! the `notify-keyspace-events` flags, which are off by default:
+//!
+//! | Flag | Events                                                      |
+//! |------|-------------------------------------------------------------|
+//! | `K`  | Keyspace events, published to `__keyspace@0__:<key>`       |
+//! | `E`  | Keyevent events, published to `__keyevent@0__:<event>`     |
+//! | `g`  | Generic commands, not specific to a type, like `expire`     |
+//! | `$`  | String commands                                             |
+//! | `l`  | List commands                                               |
+//! | `s`  | Set commands                                                |
+//! | `h`  | Hash commands                                               |
+//! | `z`  | Sorted set commands                                         |
+//! | `x`  | Expired keys, whenever they get removed                     |
+//! | `e`  | Evicted keys                                                |
+//! | `t`  | Stream commands                                             |
+//! | `m`  | Key misses                                                  |
+//! | `d`  | Module types                                                |
+//! | `n`  | New keys                                                    |
+//! | `A`  | Alias for `g$lshzxetd`                                      |
+//!
+//! At least one of `K` and `E` must be given, or nothing gets published at all.
+//! There are no lists, hashes, modules nor eviction here, so their flags are accepted
+//! but have no effect, and neither do `m` and `n` yet.
+//!
+//! On top of the event classes of Redis, the keys that notifications are sent about
+//! can be narrowed down with two lists of glob-style patterns, so that high-churn
+//! internal keys (like `cache:*` or `lock:*`) don't flood the subscribers:
+//!
+//! | Parameter                 | Effect                                                      |
+//! |---------------------------|-------------------------------------------------------------|
+//! | `notify-keyspace-include` | Only keys matching one of these patterns are notified about |
+//! | `notify-keyspace-exclude` | Keys matching one of these patterns never are               |
+//!
+//! Both are space-separated lists, which can be given as flags, in `redis.conf`,
+//! or changed at runtime with `CONFIG SET`. An empty include list lets every key
+//! through, and an exclusion always wins over an inclusion.
+
+use crate::database::Key;
+use crate::glob;
+use std::fmt::{self, Display, Formatter};
+
+/// The database that all the keys live in, as it appears in the notification channels.
+const DB_INDEX: u32 = 0;
+
+/// The kinds of events that can be enabled one by one, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    SortedSet,
+    Expired,
+    Evicted,
+    Stream,
+    KeyMiss,
+    Module,
+    New,
+}
+
+impl Class {
+    /// All the classes, in the order that their flags are written in.
+    const ALL: [Self; 12] = [
+        Self::Generic,
+        Self::String,
+        Self::List,
+        Self::Set,
+        Self::Hash,
+        Self::SortedSet,
+        Self::Expired,
+        Self::Evicted,
+        Self::Stream,
+        Self::Module,
+        Self::KeyMiss,
+        Self::New,
+    ];
+
+    const fn flag(self) -> char {
+        match self {
+            Self::Generic => 'g',
+            Self::String => '$',
+            Self::List => 'l',
+            Self::Set => 's',
+            Self::Hash => 'h',
+            Self::SortedSet => 'z',
+            Self::Expired => 'x',
+            Self::Evicted => 'e',
+            Self::Stream => 't',
+            Self::KeyMiss => 'm',
+            Self::Module => 'd',
+            Self::New => 'n',
+        }
+    }
+
+    const fn bit(self) -> u16 {
+        1 << self as u16
+    }
+
+    /// Whether the `A` alias covers this class; it leaves out the noisy ones.
+    const fn in_alias(self) -> bool {
+        !matches!(self, Self::KeyMiss | Self::New)
+    }
+}
+
+/// The events to publish, as set by `notify-keyspace-events`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Events {
+    classes: u16,
+    keyspace: bool,
+    keyevent: bool,
+}
+
+/// A `notify-keyspace-events` flag that does not exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "ERR CONFIG SET failed (possibly related to argument 'notify-keyspace-events') - \
+     Invalid event class character. Use 'Ag$lshzxeKEtmdn'."
+)]
+pub struct InvalidFlag;
+
+impl Events {
+    /// Parse the flags of `notify-keyspace-events`, like `KEA` or `Ex`.
+    pub fn parse(flags: &str) -> Result<Self, InvalidFlag> {
+        let mut events = Self::default();
+        for flag in flags.chars() {
+            match flag {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'A' => {
+                    for class in Class::ALL.into_iter().filter(|class| class.in_alias()) {
+                        events.classes |= class.bit();
+                    }
+                }
+                flag => {
+                    let class = Class::ALL
+                        .into_iter()
+                        .find(|class| class.flag() == flag)
+                        .ok_or(InvalidFlag)?;
+                    events.classes |= class.bit();
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Whether events of `class` get published at all.
+    pub const fn enabled(self, class: Class) -> bool {
+        (self.keyspace || self.keyevent) && self.classes & class.bit() != 0
+    }
+
+    /// The channels to publish `notification` to, each along with the message to publish.
+    pub fn messages(self, notification: &Notification) -> Vec<(String, String)> {
+        let Notification { event, key, .. } = notification;
+        let mut messages = vec![];
+        if self.keyspace {
+            messages.push((format!("__keyspace@{DB_INDEX}__:{key}"), event.to_string()));
+        }
+        if self.keyevent {
+            messages.push((format!("__keyevent@{DB_INDEX}__:{event}"), key.clone()));
+        }
+        messages
+    }
+}
+
+impl Display for Events {
+    /// Write the flags in the same canonical form that Redis reports them in.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let enabled = |class: Class| self.classes & class.bit() != 0;
+        let alias = Class::ALL
+            .into_iter()
+            .filter(|class| class.in_alias())
+            .all(enabled);
+        if alias {
+            write!(f, "A")?;
+        }
+        for class in Class::ALL {
+            if class.in_alias() && !alias && enabled(class) {
+                write!(f, "{}", class.flag())?;
+            }
+        }
+        if self.keyspace {
+            write!(f, "K")?;
+        }
+        if self.keyevent {
+            write!(f, "E")?;
+        }
+        for class in [Class::KeyMiss, Class::New] {
+            if enabled(class) {
+                write!(f, "{}", class.flag())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A change to the keyspace, waiting to be published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub class: Class,
+    /// The name of the event, like `set` or `expired`.
+    pub event: &'static str,
+    pub key: Key,
+}
+
+/// The key-pattern filter of keyspace notifications, see the [module](self) documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyFilter {
+    include: Patterns,
+    exclude: Patterns,
+}
+
+impl KeyFilter {
+    pub fn new(include: &str, exclude: &str) -> Self {
+        Self {
+            include: Patterns::parse(include),
+            exclude: Patterns::parse(exclude),
+        }
+    }
+
+    /// Check whether notifications about `key` should be sent.
+    pub fn allows(&self, key: &str) -> bool {
+        (self.include.0.is_empty() || self.include.matches(key)) && !self.exclude.matches(key)
+    }
+
+    /// The patterns of keys to notify about.
+    pub const fn include(&self) -> &Patterns {
+        &self.include
+    }
+
+    /// The patterns of keys to never notify about.
+    pub const fn exclude(&self) -> &Patterns {
+        &self.exclude
+    }
+
+    pub fn set_include(&mut self, patterns: &str) {
+        self.include = Patterns::parse(patterns);
+    }
+
+    pub fn set_exclude(&mut self, patterns: &str) {
+        self.exclude = Patterns::parse(patterns);
+    }
+}
+
+/// A list of glob-style patterns, written as a space-separated list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patterns(Vec<String>);
+
+impl Patterns {
+    fn parse(list: &str) -> Self {
+        Self(list.split_whitespace().map(ToString::to_string).collect())
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        self.0.iter().any(|pattern| glob::matches(pattern, key))
+    }
+}
+
+impl Display for Patterns {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Class, Events, InvalidFlag, KeyFilter, Notification};
+
+    #[test]
+    fn events() {
+        assert_eq!(Events::default().to_string(), "");
+        assert!(!Events::default().enabled(Class::Generic));
+        // Classes without `K` nor `E` publish nothing.
+        assert!(!Events::parse("g").unwrap().enabled(Class::Generic));
+
+        let events = Events::parse("KEA").unwrap();
+        assert_eq!(events.to_string(), "AKE");
+        assert!(events.enabled(Class::Expired));
+        assert!(!events.enabled(Class::New));
+        assert_eq!(Events::parse("AKEmn").unwrap().to_string(), "AKEmn");
+        assert_eq!(Events::parse("xE$").unwrap().to_string(), "$xE");
+        assert_eq!(Events::parse("KEx?"), Err(InvalidFlag));
+
+        let notification = Notification {
+            class: Class::String,
+            event: "set",
+            key: "user:1".to_string(),
+        };
+        assert_eq!(
+            Events::parse("KEA").unwrap().messages(&notification),
+            [
+                ("__keyspace@0__:user:1".to_string(), "set".to_string()),
+                ("__keyevent@0__:set".to_string(), "user:1".to_string()),
+            ]
+        );
+        assert_eq!(
+            Events::parse("E$").unwrap().messages(&notification),
+            [("__keyevent@0__:set".to_string(), "user:1".to_string())]
+        );
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let everything = KeyFilter::default();
+        assert!(everything.allows("anything"));
+
+        let mut filter = KeyFilter::new("", "cache:* lock:?");
+        assert!(filter.allows("user:1"));
+        assert!(!filter.allows("cache:user:1"));
+        assert!(!filter.allows("lock:a"));
+        assert!(filter.allows("lock:ab"));
+
+        filter.set_include("  user:*   cache:* ");
+        assert_eq!(filter.include().to_string(), "user:* cache:*");
+        assert!(filter.allows("user:1"));
+        assert!(!filter.allows("order:1"));
+        // An exclusion wins over an inclusion.
+        assert!(!filter.allows("cache:1"));
+
+        filter.set_exclude("");
+        assert_eq!(filter.exclude().to_string(), "");
+        assert!(filter.allows("cache:1"));
+    }
+}
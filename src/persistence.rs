@@ -0,0 +1,472 @@
+//! # Where the dataset is saved to, which `CONFIG SET dir|dbfilename` can change at runtime.
+//!
+//! Changing the path while a save is writing the dataset out could leave the RDB file
+//! split between two places, or land it somewhere nobody asked for. So a save holds on
+//! to a [`SaveGuard`] for as long as it runs, which pins the path it started with, and
+//! changes of the path are rejected until the guard is dropped rather than queued, so
+//! that `CONFIG SET` never replies `OK` for a change that has yet to happen.
+//!
+//! Saves first write to a temporary file next to the destination and then rename it
+//! over the destination, so the RDB file is always either the old or the new dataset
+//! in full, never a mix of the two.
+//!
+//! `BGSAVE` only [requests](Persistence::request_background_save) a save, which a task
+//! of the server then [waits for](Persistence::background_save_requested) and runs,
+//! so that the client gets its reply right away. At most one background save runs at
+//! a time, and at most one more can be scheduled to run right after it.
+//!
+//! The server also requests background saves by itself once one of its [`SavePoints`]
+//! is reached, but waits [a while](RETRY_DELAY) after one of them failed before trying
+//! again, rather than trying over and over while, say, the disk is full.
+
+use std::fmt::{self, Display, Formatter, Write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, MutexGuard, Notify};
+
+/// How long to wait after a failed background save before a save point may trigger another.
+pub const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Possible errors that can arise while changing the path of the RDB file or saving to it.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("ERR Background save already in progress")]
+    BackgroundSaveInProgress,
+    #[error("ERR Background save already in progress, cannot change '{0}' now")]
+    SaveInProgress(&'static str),
+    #[error("ERR CONFIG SET failed (possibly related to argument 'dir') - No such directory")]
+    NotADirectory,
+    #[error(
+        "ERR CONFIG SET failed (possibly related to argument 'dbfilename') - \
+         dbfilename can't be a path, just a filename"
+    )]
+    NotAFilename,
+}
+
+/// When to save in the background without being asked to, as set by the `save` setting.
+///
+/// Each [`SavePoint`] is reached once at least as many changes were made, and at
+/// least as many seconds passed, since the last successful save. There are none
+/// by default, so the server only saves when asked to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavePoints(Vec<SavePoint>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl SavePoints {
+    /// Parse pairs of seconds and changes, like `"3600 1 300 100"`, with `""` meaning none.
+    pub fn parse(points: &str) -> Result<Self, String> {
+        let numbers = points
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|_| format!("Invalid save points {points:?}, expected numbers"))?;
+        if numbers.len() % 2 != 0 {
+            return Err(format!(
+                "Invalid save points {points:?}, expected pairs of seconds and changes"
+            ));
+        }
+        Ok(Self(
+            numbers
+                .chunks_exact(2)
+                .map(|pair| SavePoint {
+                    seconds: pair[0],
+                    changes: pair[1],
+                })
+                .collect(),
+        ))
+    }
+
+    /// Add the save points of `other` to these.
+    pub fn extend(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any of the save points is reached, `elapsed` after the last save with `changes` since.
+    pub fn reached(&self, changes: u64, elapsed: Duration) -> bool {
+        self.0.iter().any(|point| {
+            changes >= point.changes.max(1) && elapsed >= Duration::from_secs(point.seconds)
+        })
+    }
+}
+
+impl Display for SavePoints {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, point) in self.0.iter().enumerate() {
+            let separator = if index == 0 { "" } else { " " };
+            write!(f, "{separator}{} {}", point.seconds, point.changes)?;
+        }
+        Ok(())
+    }
+}
+
+/// The path of the RDB file, split like in the `dir` and `dbfilename` settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RdbPath {
+    pub dir: PathBuf,
+    pub dbfilename: PathBuf,
+}
+
+impl RdbPath {
+    /// The full path of the RDB file.
+    pub fn file(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// The file that a save writes to before renaming it to [`Self::file`].
+    fn temp_file(&self) -> PathBuf {
+        self.dir.join(format!("temp-{}.rdb", std::process::id()))
+    }
+}
+
+/// The path of the RDB file, along with the coordination of saves and changes to it.
+#[derive(Debug)]
+pub struct Persistence {
+    path: RwLock<RdbPath>,
+    /// Held by the save in progress, if any.
+    saving: Mutex<()>,
+    /// When the last save succeeded, or when the server started if none did yet (`LASTSAVE`).
+    last_save: RwLock<SystemTime>,
+    background: RwLock<Background>,
+    /// Wakes up the task that runs the background saves.
+    background_requested: Notify,
+}
+
+/// The state of the background saves, see the [module docs](self).
+#[derive(Debug, Default)]
+struct Background {
+    /// Whether a background save has been requested and has not finished yet.
+    in_progress: bool,
+    /// Whether to run another background save once the one in progress finishes.
+    scheduled: bool,
+    /// Whether the last background save failed, which no background save did at first.
+    last_failed: bool,
+    /// When the last background save finished, if any did.
+    last_finished: Option<Instant>,
+}
+
+/// How a background save was started, see [`Persistence::request_background_save`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundSave {
+    Started,
+    /// Another background save is in progress, and this one will run right after it.
+    Scheduled,
+}
+
+/// Proof that a save is in progress, which keeps the path from changing until dropped.
+#[derive(Debug)]
+pub struct SaveGuard<'a> {
+    path: RdbPath,
+    last_save: &'a RwLock<SystemTime>,
+    _saving: MutexGuard<'a, ()>,
+}
+
+impl Persistence {
+    pub fn new(path: RdbPath) -> Self {
+        Self {
+            path: RwLock::new(path),
+            saving: Mutex::new(()),
+            last_save: RwLock::new(SystemTime::now()),
+            background: RwLock::default(),
+            background_requested: Notify::new(),
+        }
+    }
+
+    /// The current path of the RDB file.
+    pub fn path(&self) -> RdbPath {
+        self.path
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Read the RDB file at the current path, or [`None`] if there is no such file yet.
+    pub async fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path().file()).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Wait for the save in progress to finish, if any, and start another.
+    pub async fn begin_save(&self) -> SaveGuard<'_> {
+        let saving = self.saving.lock().await;
+        SaveGuard {
+            path: self.path(),
+            last_save: &self.last_save,
+            _saving: saving,
+        }
+    }
+
+    /// When the last save succeeded, or when the server started if none did yet.
+    pub fn last_save(&self) -> SystemTime {
+        *self
+            .last_save
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Request a background save, or if one is in progress already, fail unless `schedule`
+    /// is set, in which case another one will run right after it (`BGSAVE [SCHEDULE]`).
+    pub fn request_background_save(&self, schedule: bool) -> Result<BackgroundSave, Error> {
+        let mut background = self.background();
+        if !background.in_progress {
+            background.in_progress = true;
+            self.background_requested.notify_one();
+            Ok(BackgroundSave::Started)
+        } else if schedule {
+            background.scheduled = true;
+            Ok(BackgroundSave::Scheduled)
+        } else {
+            Err(Error::BackgroundSaveInProgress)
+        }
+    }
+
+    /// Wait until a background save is requested, then run it and
+    /// report whether it succeeded with [`Self::finish_background_save`].
+    pub async fn background_save_requested(&self) {
+        self.background_requested.notified().await;
+    }
+
+    /// Record the outcome of the background save in progress, and start the scheduled one, if any.
+    pub fn finish_background_save(&self, succeeded: bool) {
+        let mut background = self.background();
+        background.last_failed = !succeeded;
+        background.last_finished = Some(Instant::now());
+        if background.scheduled {
+            background.scheduled = false;
+            self.background_requested.notify_one();
+        } else {
+            background.in_progress = false;
+        }
+    }
+
+    /// Whether a background save is in progress.
+    pub fn is_background_saving(&self) -> bool {
+        self.background
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .in_progress
+    }
+
+    /// Whether a save point may request a background save now, see the [module docs](self).
+    pub fn may_save_automatically(&self) -> bool {
+        let background = self
+            .background
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let retrying_too_soon = background.last_failed
+            && background
+                .last_finished
+                .map_or(false, |finished| finished.elapsed() < RETRY_DELAY);
+        !background.in_progress && !retrying_too_soon
+    }
+
+    /// The `persistence` section of `INFO`, with the number of `changes` since the last save.
+    pub fn info(&self, changes: u64) -> String {
+        let background = self
+            .background
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut info = String::from("# Persistence\r\n");
+        let _ = write!(info, "rdb_changes_since_last_save:{changes}\r\n");
+        let _ = write!(
+            info,
+            "rdb_bgsave_in_progress:{}\r\n",
+            u8::from(background.in_progress)
+        );
+        let last_save = self
+            .last_save()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = write!(info, "rdb_last_save_time:{}\r\n", last_save.as_secs());
+        let status = if background.last_failed { "err" } else { "ok" };
+        let _ = write!(info, "rdb_last_bgsave_status:{status}\r\n");
+        info
+    }
+
+    fn background(&self) -> RwLockWriteGuard<'_, Background> {
+        self.background
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Change the `dir` and/or the `dbfilename`, either both or none of them.
+    ///
+    /// Fails if a save is in progress, so that it finishes writing where it started.
+    pub fn set(&self, dir: Option<&str>, dbfilename: Option<&str>) -> Result<(), Error> {
+        let Ok(_saving) = self.saving.try_lock() else {
+            let name = if dir.is_some() { "dir" } else { "dbfilename" };
+            return Err(Error::SaveInProgress(name));
+        };
+        if dir.map_or(false, |dir| !Path::new(dir).is_dir()) {
+            return Err(Error::NotADirectory);
+        }
+        if dbfilename.map_or(false, |file| {
+            Path::new(file).file_name() != Some(file.as_ref())
+        }) {
+            return Err(Error::NotAFilename);
+        }
+        let mut path = self.path.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(dir) = dir {
+            path.dir = PathBuf::from(dir);
+        }
+        if let Some(dbfilename) = dbfilename {
+            path.dbfilename = PathBuf::from(dbfilename);
+        }
+        tracing::info!(file = ?path.file(), "Changed the path of the RDB file");
+        Ok(())
+    }
+}
+
+impl SaveGuard<'_> {
+    /// The path that this save writes to, regardless of any later `CONFIG SET`.
+    pub const fn path(&self) -> &RdbPath {
+        &self.path
+    }
+
+    /// Replace the RDB file with `contents`, atomically, and note when that happened.
+    pub async fn write(&self, contents: Vec<u8>) -> io::Result<()> {
+        let temp = self.path.temp_file();
+        if let Err(err) = tokio::fs::write(&temp, contents).await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(err);
+        }
+        tokio::fs::rename(&temp, self.path.file()).await?;
+        *self
+            .last_save
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = SystemTime::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackgroundSave, Error, Persistence, RdbPath, SavePoints};
+    use std::time::Duration;
+    use std::{env, fs};
+
+    #[tokio::test]
+    async fn background_saves() {
+        let persistence = Persistence::new(RdbPath {
+            dir: env::temp_dir(),
+            dbfilename: "dump.rdb".into(),
+        });
+        assert!(persistence.info(0).contains("rdb_bgsave_in_progress:0\r\n"));
+        assert_eq!(
+            persistence.request_background_save(false),
+            Ok(BackgroundSave::Started)
+        );
+        persistence.background_save_requested().await;
+        assert!(persistence.is_background_saving());
+        assert_eq!(
+            persistence.request_background_save(false),
+            Err(Error::BackgroundSaveInProgress)
+        );
+        assert_eq!(
+            persistence.request_background_save(true),
+            Ok(BackgroundSave::Scheduled)
+        );
+
+        // The scheduled save starts as soon as the one in progress finishes.
+        persistence.finish_background_save(false);
+        assert!(!persistence.may_save_automatically());
+        assert!(persistence
+            .info(0)
+            .contains("rdb_last_bgsave_status:err\r\n"));
+        persistence.background_save_requested().await;
+        assert!(persistence.is_background_saving());
+        persistence.finish_background_save(true);
+        assert!(!persistence.is_background_saving());
+        let info = persistence.info(0);
+        assert!(info.contains("rdb_bgsave_in_progress:0\r\n"), "{info}");
+        assert!(info.contains("rdb_last_bgsave_status:ok\r\n"), "{info}");
+        assert!(persistence.may_save_automatically());
+    }
+
+    #[test]
+    fn save_points() {
+        let points = SavePoints::parse("3600 1 300 100").unwrap();
+        assert_eq!(points.to_string(), "3600 1 300 100");
+        assert!(!points.reached(0, Duration::from_secs(7200)));
+        assert!(!points.reached(99, Duration::from_secs(3599)));
+        assert!(points.reached(100, Duration::from_secs(300)));
+        assert!(points.reached(1, Duration::from_secs(3600)));
+
+        let none = SavePoints::parse("").unwrap();
+        assert!(none.is_empty());
+        assert!(!none.reached(1_000_000, Duration::from_secs(1_000_000)));
+        assert!(SavePoints::parse("3600").is_err());
+        assert!(SavePoints::parse("3600 often").is_err());
+    }
+
+    #[tokio::test]
+    async fn path_is_pinned_during_a_save() {
+        let root = env::temp_dir().join("redis-starter-rust-persistence");
+        let _ = fs::remove_dir_all(&root);
+        let (first, second) = (root.join("first"), root.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        let persistence = Persistence::new(RdbPath {
+            dir: first.clone(),
+            dbfilename: "dump.rdb".into(),
+        });
+
+        assert_eq!(persistence.read().await.unwrap(), None);
+        let started = persistence.last_save();
+        let save = persistence.begin_save().await;
+        let second_dir = second.to_str().unwrap();
+        assert_eq!(
+            persistence.set(Some(second_dir), None),
+            Err(Error::SaveInProgress("dir"))
+        );
+        assert_eq!(
+            persistence.set(None, Some("other.rdb")),
+            Err(Error::SaveInProgress("dbfilename"))
+        );
+        assert_eq!(persistence.path().dir, first);
+        save.write(b"first".to_vec()).await.unwrap();
+        drop(save);
+        assert_eq!(fs::read(first.join("dump.rdb")).unwrap(), b"first");
+        assert_eq!(persistence.read().await.unwrap().unwrap(), b"first");
+        assert!(persistence.last_save() >= started);
+
+        // Both parts change together, or not at all.
+        let missing = root.join("missing");
+        assert_eq!(
+            persistence.set(missing.to_str(), Some("other.rdb")),
+            Err(Error::NotADirectory)
+        );
+        assert_eq!(
+            persistence.set(Some(second_dir), Some("nested/other.rdb")),
+            Err(Error::NotAFilename)
+        );
+        assert_eq!(persistence.path().file(), first.join("dump.rdb"));
+        persistence
+            .set(Some(second_dir), Some("other.rdb"))
+            .unwrap();
+
+        let save = persistence.begin_save().await;
+        assert_eq!(save.path().file(), second.join("other.rdb"));
+        save.write(b"second".to_vec()).await.unwrap();
+        drop(save);
+        assert_eq!(fs::read(second.join("other.rdb")).unwrap(), b"second");
+        assert_eq!(fs::read(first.join("dump.rdb")).unwrap(), b"first");
+        // Only the RDB file is left behind, not the temporary one.
+        assert_eq!(fs::read_dir(&second).unwrap().count(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
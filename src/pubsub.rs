@@ -0,0 +1,520 @@
+//! # Pub/Sub subscriber mode: the state of subscribed clients and the shapes of their replies.
+//!
+//! Once a RESP2 client subscribes to a channel, the connection is in *subscriber
+//! mode*: it only accepts the commands that manage subscriptions, plus `PING`,
+//! `QUIT` and `RESET`, and every reply is an array that starts with its kind,
+//! so that clients can tell replies apart from messages. RESP3 clients get
+//! messages as out-of-band pushes instead, so they can keep running any command.
+//! A connection leaves subscriber mode once its last subscription ends, be it by
+//! unsubscribing or by `RESET`, which also switches it back to RESP2.
+//!
+//! The reply shapes below are exactly the ones that Redis uses:
+//!
+//! | Situation                            | Reply                                  |
+//! |--------------------------------------|----------------------------------------|
+//! | `SUBSCRIBE a`                        | `["subscribe", "a", <count>]`          |
+//! | `UNSUBSCRIBE a`                      | `["unsubscribe", "a", <count>]`        |
+//! | `UNSUBSCRIBE` without subscriptions | `["unsubscribe", nil, 0]`              |
+//! | `PING [message]` in subscriber mode  | `["pong", <message, or "">]`           |
+//! | `PUBLISH a hi`, to each subscriber   | `["message", "a", "hi"]`               |
+//! | `PSUBSCRIBE a*`                      | `["psubscribe", "a*", <count>]`        |
+//! | `PUNSUBSCRIBE a*`                    | `["punsubscribe", "a*", <count>]`      |
+//! | `PUBLISH ab hi`, to each `a*` one    | `["pmessage", "a*", "ab", "hi"]`       |
+//! | `SSUBSCRIBE a`                       | `["ssubscribe", "a", <count>]`         |
+//! | `SUNSUBSCRIBE a`                     | `["sunsubscribe", "a", <count>]`       |
+//! | `SPUBLISH a hi`, to each subscriber  | `["smessage", "a", "hi"]`              |
+//!
+//! The counts are of all the subscriptions of the client to channels and patterns alike,
+//! except for shard channels, which are counted on their own. Shard channels are a
+//! namespace of their own too: `PUBLISH` never reaches them, nor `SPUBLISH` the others.
+//! Without cluster mode, every shard channel lives on this one node.
+//!
+//! Messages arrive outside of the request/response cycle of the subscribers, so the
+//! [`Broker`] hands them to each subscribed connection through its own channel, which
+//! the connection drains in between requests.
+
+use crate::glob;
+use crate::resp::{Protocol, Token};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{PoisonError, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// The commands that a RESP2 connection in subscriber mode accepts.
+const SUBSCRIBER_COMMANDS: [&str; 9] = [
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+    "ping",
+    "quit",
+    "reset",
+];
+
+/// What a subscription is to: a single channel, or all the channels matching a glob-style pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Channel,
+    Pattern,
+    /// A shard channel, see `SSUBSCRIBE`.
+    Shard,
+}
+
+impl Kind {
+    pub const ALL: [Self; 3] = [Self::Channel, Self::Pattern, Self::Shard];
+
+    /// The kind of the confirmations of `SUBSCRIBE`-like commands for this kind of subscription.
+    const fn confirmations(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Channel => ("subscribe", "unsubscribe"),
+            Self::Pattern => ("psubscribe", "punsubscribe"),
+            Self::Shard => ("ssubscribe", "sunsubscribe"),
+        }
+    }
+}
+
+/// The channels and patterns that a single connection is subscribed to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subscriptions {
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+    shards: BTreeSet<String>,
+}
+
+/// The senders of the subscribed connections, by channel or pattern and then by client ID.
+type Subscribers = HashMap<String, HashMap<u64, UnboundedSender<Token>>>;
+
+/// The registry of which connections are subscribed to which channels and patterns,
+/// for `PUBLISH` to deliver to.
+#[derive(Debug, Default)]
+pub struct Broker {
+    channels: RwLock<Subscribers>,
+    patterns: RwLock<Subscribers>,
+    shards: RwLock<Subscribers>,
+}
+
+impl Broker {
+    const fn subscribers(&self, kind: Kind) -> &RwLock<Subscribers> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shards,
+        }
+    }
+
+    /// Deliver the messages published to the channel or pattern `name` to the client `id`, through `sender`.
+    pub fn subscribe(&self, kind: Kind, name: &str, id: u64, sender: &UnboundedSender<Token>) {
+        let mut subscribers = self
+            .subscribers(kind)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let _ = subscribers
+            .entry(name.to_string())
+            .or_default()
+            .insert(id, sender.clone());
+    }
+
+    /// Stop delivering the messages published to the channel or pattern `name` to the client `id`.
+    pub fn unsubscribe(&self, kind: Kind, name: &str, id: u64) {
+        let mut subscribers = self
+            .subscribers(kind)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(clients) = subscribers.get_mut(name) {
+            let _ = clients.remove(&id);
+            if clients.is_empty() {
+                let _ = subscribers.remove(name);
+            }
+        }
+    }
+
+    /// The channels with at least one subscriber, only those matching the glob-style `pattern` if given.
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let channels = self.channels.read().unwrap_or_else(PoisonError::into_inner);
+        let mut active: Vec<String> = channels
+            .keys()
+            .filter(|channel| pattern.map_or(true, |pattern| glob::matches(pattern, channel)))
+            .cloned()
+            .collect();
+        active.sort_unstable();
+        active
+    }
+
+    /// The number of clients subscribed to `channel`, not counting pattern subscriptions.
+    pub fn subscriber_count(&self, channel: &str) -> usize {
+        let channels = self.channels.read().unwrap_or_else(PoisonError::into_inner);
+        channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// The number of distinct patterns that clients are subscribed to.
+    pub fn pattern_count(&self) -> usize {
+        self.patterns
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Deliver `message` to everyone subscribed to `channel`, or to a pattern matching it,
+    /// returning how many deliveries there were.
+    ///
+    /// Like in Redis, a client subscribed both to the channel and to matching patterns
+    /// gets the message once for each of its subscriptions.
+    pub fn publish(&self, channel: &str, message: &str) -> usize {
+        let mut delivered = 0;
+        let channels = self.channels.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(clients) = channels.get(channel) {
+            let tokens = vec![text("message"), text(channel), text(message)];
+            delivered += deliver(clients, Token::Push { tokens });
+        }
+        let patterns = self.patterns.read().unwrap_or_else(PoisonError::into_inner);
+        for (pattern, clients) in patterns.iter() {
+            if glob::matches(pattern, channel) {
+                let tokens = vec![
+                    text("pmessage"),
+                    text(pattern),
+                    text(channel),
+                    text(message),
+                ];
+                delivered += deliver(clients, Token::Push { tokens });
+            }
+        }
+        delivered
+    }
+
+    /// Deliver `message` to everyone subscribed to the shard channel `channel`,
+    /// returning to how many clients.
+    pub fn publish_shard(&self, channel: &str, message: &str) -> usize {
+        let shards = self.shards.read().unwrap_or_else(PoisonError::into_inner);
+        shards.get(channel).map_or(0, |clients| {
+            let tokens = vec![text("smessage"), text(channel), text(message)];
+            deliver(clients, Token::Push { tokens })
+        })
+    }
+}
+
+fn text(text: &str) -> Token {
+    Token::from(text.to_string())
+}
+
+/// Send `message` to all the `clients`, returning to how many it got through.
+fn deliver(clients: &HashMap<u64, UnboundedSender<Token>>, message: Token) -> usize {
+    clients
+        .values()
+        .filter(|sender| sender.send(message.clone()).is_ok())
+        .count()
+}
+
+impl Subscriptions {
+    const fn names(&self, kind: Kind) -> &BTreeSet<String> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shards,
+        }
+    }
+
+    fn names_mut(&mut self, kind: Kind) -> &mut BTreeSet<String> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shards,
+        }
+    }
+
+    /// The channels or patterns subscribed to.
+    pub fn iter(&self, kind: Kind) -> impl Iterator<Item = &String> {
+        self.names(kind).iter()
+    }
+
+    /// The number of subscriptions, of all kinds.
+    pub fn len(&self) -> usize {
+        self.channels.len() + self.patterns.len() + self.shards.len()
+    }
+
+    /// The number of subscriptions that the confirmations for `kind` report.
+    fn count(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+            Kind::Shard => self.shards.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether a connection speaking `protocol` is restricted to the subscriber mode commands.
+    pub fn restricts(&self, protocol: Protocol) -> bool {
+        protocol == Protocol::Resp2 && !self.is_empty()
+    }
+
+    /// Subscribe to the channels or patterns `names`, returning one confirmation per name.
+    pub fn subscribe(&mut self, kind: Kind, names: Vec<String>) -> Vec<Token> {
+        let (subscribe, _) = kind.confirmations();
+        names
+            .into_iter()
+            .map(|name| {
+                let _ = self.names_mut(kind).insert(name.clone());
+                confirmation(subscribe, Some(name), self.count(kind))
+            })
+            .collect()
+    }
+
+    /// Unsubscribe from the channels or patterns `names`, or from all of them if none
+    /// are given, returning one confirmation per name.
+    pub fn unsubscribe(&mut self, kind: Kind, names: Vec<String>) -> Vec<Token> {
+        let (_, unsubscribe) = kind.confirmations();
+        let names = if names.is_empty() {
+            self.names(kind).iter().cloned().collect()
+        } else {
+            names
+        };
+        if names.is_empty() {
+            return vec![confirmation(unsubscribe, None, self.count(kind))];
+        }
+        names
+            .into_iter()
+            .map(|name| {
+                let _ = self.names_mut(kind).remove(&name);
+                confirmation(unsubscribe, Some(name), self.count(kind))
+            })
+            .collect()
+    }
+}
+
+/// Build a `SUBSCRIBE`-like confirmation: the `kind` of the reply,
+/// the `channel` it is about, and the number of remaining subscriptions.
+pub fn confirmation(kind: &str, channel: Option<String>, count: usize) -> Token {
+    Token::Push {
+        tokens: vec![
+            Token::from(kind.to_string()),
+            Token::from(channel),
+            Token::from(count),
+        ],
+    }
+}
+
+/// Build the reply to `PING` for a connection in subscriber mode.
+pub fn pong(message: Option<String>) -> Token {
+    Token::Array {
+        tokens: vec![
+            Token::from("pong".to_string()),
+            Token::from(message.unwrap_or_default()),
+        ],
+    }
+}
+
+/// Check whether a connection in subscriber mode may run `command`,
+/// returning the error to reply with if it may not.
+pub fn check_allowed(command: &str) -> Result<(), Token> {
+    if SUBSCRIBER_COMMANDS
+        .iter()
+        .any(|allowed| command.eq_ignore_ascii_case(allowed))
+    {
+        return Ok(());
+    }
+    Err(Token::SimpleError {
+        data: format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            command.to_ascii_lowercase()
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_allowed, pong, Broker, Kind, Subscriptions};
+    use crate::resp::{Protocol, Token};
+    use tokio::sync::mpsc;
+
+    fn encode(replies: &[Token], protocol: Protocol) -> String {
+        replies.iter().map(|reply| reply.encode(protocol)).collect()
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe_golden() {
+        let mut subscriptions = Subscriptions::default();
+        assert!(!subscriptions.restricts(Protocol::Resp2));
+        let replies =
+            subscriptions.subscribe(Kind::Channel, vec!["a".into(), "b".into(), "a".into()]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n\
+             *3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n\
+             *3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:2\r\n"
+        );
+        assert_eq!(
+            encode(&replies[..1], Protocol::Resp3),
+            ">3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n"
+        );
+        assert!(subscriptions.restricts(Protocol::Resp2));
+        assert!(!subscriptions.restricts(Protocol::Resp3));
+
+        let replies = subscriptions.unsubscribe(Kind::Channel, vec![]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n:1\r\n\
+             *3\r\n$11\r\nunsubscribe\r\n$1\r\nb\r\n:0\r\n"
+        );
+        let replies = subscriptions.unsubscribe(Kind::Channel, vec![]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n"
+        );
+        assert_eq!(
+            encode(&replies, Protocol::Resp3),
+            ">3\r\n$11\r\nunsubscribe\r\n_\r\n:0\r\n"
+        );
+    }
+
+    #[test]
+    fn publish_to_subscribers() {
+        let broker = Broker::default();
+        let (first, mut first_messages) = mpsc::unbounded_channel();
+        let (second, mut second_messages) = mpsc::unbounded_channel();
+        broker.subscribe(Kind::Channel, "news", 1, &first);
+        broker.subscribe(Kind::Channel, "news", 2, &second);
+        broker.subscribe(Kind::Channel, "sports", 2, &second);
+        assert_eq!(broker.publish("news", "hi"), 2);
+        assert_eq!(broker.publish("weather", "hi"), 0);
+        assert_eq!(
+            first_messages.try_recv().unwrap().encode(Protocol::Resp2),
+            "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert_eq!(
+            second_messages.try_recv().unwrap().encode(Protocol::Resp3),
+            ">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+
+        broker.unsubscribe(Kind::Channel, "news", 1);
+        assert_eq!(broker.publish("news", "bye"), 1);
+        assert!(first_messages.try_recv().is_err());
+        // Messages to connections that went away are not counted.
+        drop(second_messages);
+        assert_eq!(broker.publish("sports", "bye"), 0);
+        broker.unsubscribe(Kind::Channel, "news", 2);
+        broker.unsubscribe(Kind::Channel, "sports", 2);
+        assert!(broker.channels.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn patterns() {
+        let mut subscriptions = Subscriptions::default();
+        let _ = subscriptions.subscribe(Kind::Channel, vec!["news".into()]);
+        let replies = subscriptions.subscribe(Kind::Pattern, vec!["n*".into(), "w?".into()]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$10\r\npsubscribe\r\n$2\r\nn*\r\n:2\r\n\
+             *3\r\n$10\r\npsubscribe\r\n$2\r\nw?\r\n:3\r\n"
+        );
+        let replies = subscriptions.unsubscribe(Kind::Pattern, vec![]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$12\r\npunsubscribe\r\n$2\r\nn*\r\n:2\r\n\
+             *3\r\n$12\r\npunsubscribe\r\n$2\r\nw?\r\n:1\r\n"
+        );
+        let replies = subscriptions.unsubscribe(Kind::Pattern, vec![]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$12\r\npunsubscribe\r\n$-1\r\n:1\r\n"
+        );
+        assert!(subscriptions.restricts(Protocol::Resp2));
+
+        let broker = Broker::default();
+        let (sender, mut messages) = mpsc::unbounded_channel();
+        broker.subscribe(Kind::Channel, "news", 1, &sender);
+        broker.subscribe(Kind::Pattern, "n*", 1, &sender);
+        broker.subscribe(Kind::Pattern, "w?", 1, &sender);
+        assert_eq!(broker.publish("news", "hi"), 2);
+        assert_eq!(broker.publish("weather", "hi"), 0);
+        assert_eq!(
+            messages.try_recv().unwrap().encode(Protocol::Resp2),
+            "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert_eq!(
+            messages.try_recv().unwrap().encode(Protocol::Resp2),
+            "*4\r\n$8\r\npmessage\r\n$2\r\nn*\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert!(messages.try_recv().is_err());
+        broker.unsubscribe(Kind::Pattern, "n*", 1);
+        assert_eq!(broker.publish("news", "bye"), 1);
+    }
+
+    #[test]
+    fn shard_channels() {
+        let mut subscriptions = Subscriptions::default();
+        let _ = subscriptions.subscribe(Kind::Channel, vec!["news".into()]);
+        let replies = subscriptions.subscribe(Kind::Shard, vec!["news".into()]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$10\r\nssubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+        let _ = subscriptions.unsubscribe(Kind::Channel, vec![]);
+        assert!(subscriptions.restricts(Protocol::Resp2));
+        let replies = subscriptions.unsubscribe(Kind::Shard, vec![]);
+        assert_eq!(
+            encode(&replies, Protocol::Resp2),
+            "*3\r\n$12\r\nsunsubscribe\r\n$4\r\nnews\r\n:0\r\n"
+        );
+        assert!(!subscriptions.restricts(Protocol::Resp2));
+
+        let broker = Broker::default();
+        let (sender, mut messages) = mpsc::unbounded_channel();
+        broker.subscribe(Kind::Shard, "news", 1, &sender);
+        broker.subscribe(Kind::Pattern, "*", 1, &sender);
+        assert_eq!(broker.publish_shard("news", "hi"), 1);
+        assert_eq!(
+            messages.try_recv().unwrap().encode(Protocol::Resp2),
+            "*3\r\n$8\r\nsmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert!(messages.try_recv().is_err());
+        assert_eq!(broker.publish_shard("sports", "hi"), 0);
+        broker.unsubscribe(Kind::Pattern, "*", 1);
+        assert_eq!(broker.publish("news", "hi"), 0);
+    }
+
+    #[test]
+    fn introspection() {
+        let broker = Broker::default();
+        let (sender, _messages) = mpsc::unbounded_channel();
+        broker.subscribe(Kind::Channel, "news", 1, &sender);
+        broker.subscribe(Kind::Channel, "news", 2, &sender);
+        broker.subscribe(Kind::Channel, "notes", 2, &sender);
+        broker.subscribe(Kind::Channel, "sports", 3, &sender);
+        broker.subscribe(Kind::Pattern, "n*", 1, &sender);
+        broker.subscribe(Kind::Pattern, "n*", 2, &sender);
+        broker.subscribe(Kind::Pattern, "s*", 2, &sender);
+        assert_eq!(broker.channels(None), ["news", "notes", "sports"]);
+        assert_eq!(broker.channels(Some("n*")), ["news", "notes"]);
+        assert_eq!(broker.subscriber_count("news"), 2);
+        assert_eq!(broker.subscriber_count("weather"), 0);
+        assert_eq!(broker.pattern_count(), 2);
+        broker.unsubscribe(Kind::Channel, "sports", 3);
+        assert_eq!(broker.channels(None), ["news", "notes"]);
+    }
+
+    #[test]
+    fn pong_golden() {
+        assert_eq!(
+            pong(None).encode(Protocol::Resp2),
+            "*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+        assert_eq!(
+            pong(Some("hi".into())).encode(Protocol::Resp2),
+            "*2\r\n$4\r\npong\r\n$2\r\nhi\r\n"
+        );
+    }
+
+    #[test]
+    fn only_subscriber_commands_are_allowed() {
+        assert!(check_allowed("PING").is_ok());
+        assert!(check_allowed("unsubscribe").is_ok());
+        assert_eq!(
+            check_allowed("GET"),
+            Err(Token::SimpleError {
+                data: "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! # Randomness for commands like `SPOP` and `SRANDMEMBER`.
+//!
+//! There is no need for cryptographic quality here, so instead of pulling in
+//! a dependency this uses a tiny `xorshift64*` generator, seeded from the
+//! per-instance random keys of the standard library's [`RandomState`].
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher};
+
+/// A small, fast, non-cryptographic pseudo-random number generator.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new [`Rng`] with a random seed.
+    pub fn new() -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Self::with_seed(seed)
+    }
+
+    /// Create a new [`Rng`] with a known seed, which must not be zero.
+    pub const fn with_seed(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// Get the next pseudo-random [`u64`].
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Get a pseudo-random index in `0..bound`. The `bound` must not be zero.
+    pub fn below(&mut self, bound: usize) -> usize {
+        // The modulo bias is negligible for collection sizes this server deals with.
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Pick indices into a collection of `len` elements, following the `SRANDMEMBER` semantics:
+///
+/// - A positive `count` picks that many *distinct* indices (or all of them, if `count >= len`).
+/// - A negative `count` picks `|count|` indices, possibly repeating some of them.
+pub fn sample_indices(rng: &mut Rng, len: usize, count: i64) -> Vec<usize> {
+    if len == 0 {
+        return vec![];
+    }
+    let wanted = usize::try_from(count.unsigned_abs()).unwrap_or(usize::MAX);
+    if count < 0 {
+        // Not sized up front, as `count` may ask for more than could ever fit.
+        let mut indices = vec![];
+        for _ in 0..wanted {
+            indices.push(rng.below(len));
+        }
+        return indices;
+    }
+    if wanted >= len {
+        return (0..len).collect();
+    }
+
+    // Floyd's algorithm: exactly `wanted` iterations, no matter how large `len` is.
+    let mut picked = HashSet::with_capacity(wanted);
+    let mut indices = Vec::with_capacity(wanted);
+    for upper in len - wanted..len {
+        let candidate = rng.below(upper + 1);
+        let index = if picked.insert(candidate) {
+            candidate
+        } else {
+            let _ = picked.insert(upper);
+            upper
+        };
+        indices.push(index);
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_indices, Rng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn below_stays_in_bounds() {
+        let mut rng = Rng::with_seed(42);
+        assert!((0..1000).all(|_| rng.below(7) < 7));
+    }
+
+    #[test]
+    fn positive_count_is_distinct() {
+        let mut rng = Rng::with_seed(7);
+        for count in [0, 1, 5, 9, 10, 100] {
+            let indices = sample_indices(&mut rng, 10, count);
+            let unique: HashSet<_> = indices.iter().collect();
+            assert_eq!(indices.len(), count.min(10) as usize);
+            assert_eq!(unique.len(), indices.len());
+            assert!(indices.iter().all(|&index| index < 10));
+        }
+    }
+
+    #[test]
+    fn negative_count_repeats() {
+        let mut rng = Rng::with_seed(1);
+        let indices = sample_indices(&mut rng, 2, -50);
+        assert_eq!(indices.len(), 50);
+        assert!(indices.iter().all(|&index| index < 2));
+        assert!(sample_indices(&mut rng, 0, -5).is_empty());
+    }
+}
@@ -0,0 +1,1048 @@
+//! # Redis Database (RDB) file format
+//!
+//! RDB files are compact, point-in-time binary snapshots of the [`Database`].
+//! Each key-value pair is stored as a [value type](value_type) byte, the key
+//! and a type-specific encoding of the value, optionally preceded by an
+//! [expire opcode](opcode) holding the absolute expiry time of the key.
+//!
+//! Strings and lengths use the variable-length encodings described in
+//! [`Reader::length`] and [`Reader::bytes`]. With compression on, strings
+//! that get smaller with [LZF](crate::lzf) are written compressed.
+//!
+//! Streams are written the way Redis 7.2 writes them, see [`value_type::STREAM_LISTPACKS_3`],
+//! with their entries in [listpacks](crate::listpack) of up to [`STREAM_NODE_MAX_ENTRIES`].
+//!
+//! A whole file ([`read_file`], [`write_file`]) is the [`MAGIC`] string and a
+//! 4-digit version, auxiliary fields, the sources of the libraries of functions,
+//! a database selector, the key-value pairs, and an [`opcode::EOF`] followed by
+//! the [CRC-64](crate::crc64) of everything before it, little-endian. A checksum
+//! of zero means that the writer did not compute one, so it is not verified.
+//!
+//! [`Database`]: crate::database::Database
+
+use crate::database::{Consumer, ConsumerGroup, Fields, Pending, Stream, StreamId};
+use crate::database::{Data, IndexedSet, Key, Score, SortedSet, Value};
+use crate::listpack::{self, Element};
+use crate::{crc64, lzf};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The magic string that every RDB file starts with, followed by a 4-digit version.
+pub const MAGIC: &[u8; 5] = b"REDIS";
+
+/// The RDB version of the files written by this server.
+pub const VERSION: u32 = 11;
+
+/// The first RDB version whose files end with a checksum.
+const CHECKSUM_VERSION: u32 = 5;
+
+/// Strings this long or shorter are never compressed, as they would hardly get any smaller.
+const MIN_COMPRESSED_LEN: usize = 20;
+
+/// The special string encoding of LZF-compressed strings.
+const LZF_ENCODING: u8 = 3;
+
+/// The most entries that a single listpack of a stream holds, like Redis has it by default.
+pub const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// The flag of the entries of a stream listpack that are deleted.
+const STREAM_ITEM_DELETED: i64 = 1;
+
+/// The flag of the entries of a stream listpack with the same fields as the first entry,
+/// which are then left out.
+const STREAM_ITEM_SAME_FIELDS: i64 = 2;
+
+/// Opcodes that mark the special sections of an RDB file.
+pub mod opcode {
+    /// The source of a library of functions.
+    pub const FUNCTION2: u8 = 0xF5;
+    /// Auxiliary field, like the version of Redis that created the file.
+    pub const AUX: u8 = 0xFA;
+    /// Hash table sizes for the main keyspace and the expires.
+    pub const RESIZEDB: u8 = 0xFB;
+    /// Expire time of the following key, in milliseconds (8 bytes, little-endian).
+    pub const EXPIRETIME_MS: u8 = 0xFC;
+    /// Expire time of the following key, in seconds (4 bytes, little-endian).
+    pub const EXPIRETIME: u8 = 0xFD;
+    /// Database selector.
+    pub const SELECTDB: u8 = 0xFE;
+    /// End of the RDB file.
+    pub const EOF: u8 = 0xFF;
+}
+
+/// Value type identifiers that precede every key-value pair.
+///
+/// Only the types that the server can hold in memory are listed here;
+/// the rest of the (numerous) compact encodings are rejected when loading.
+pub mod value_type {
+    /// A string value.
+    pub const STRING: u8 = 0;
+    /// A set encoded as a length followed by that many strings.
+    pub const SET: u8 = 2;
+    /// A sorted set encoded as a length followed by that many
+    /// strings, each followed by its score as a binary double.
+    pub const ZSET_2: u8 = 5;
+    /// A set of integers, encoded as an `intset` blob.
+    pub const SET_INTSET: u8 = 11;
+    /// A stream, encoded as a length followed by that many nodes, each the ID of its first
+    /// entry as 16 big-endian bytes and a listpack of its entries, then:
+    ///
+    /// - The number of entries as a length, and the last ID as two.
+    /// - The number of consumer groups, each with its name, last delivered ID, and pending
+    ///   entries: an ID as 16 bytes, an 8-byte delivery time, and the number of deliveries.
+    /// - The consumers of each group, each with its name, an 8-byte time it was last seen,
+    ///   and the IDs of its pending entries.
+    pub const STREAM_LISTPACKS: u8 = 15;
+    /// Like [`STREAM_LISTPACKS`], with the first ID, the highest deleted ID and how many
+    /// entries were ever added after the last ID, and how many entries a group read after
+    /// its last delivered ID.
+    pub const STREAM_LISTPACKS_2: u8 = 19;
+    /// Like [`STREAM_LISTPACKS_2`], with an 8-byte time each consumer was last active at,
+    /// or `-1` if never, after the time it was last seen.
+    pub const STREAM_LISTPACKS_3: u8 = 21;
+}
+
+/// Possible errors that can arise while decoding RDB data.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("Unexpected end of RDB data")]
+    UnexpectedEof,
+    #[error("Unsupported RDB value type: {0}")]
+    UnsupportedValueType(u8),
+    #[error("Unsupported RDB string encoding: {0}")]
+    UnsupportedEncoding(u8),
+    #[error("Malformed RDB data: {0}")]
+    Malformed(&'static str),
+    #[error("Not an RDB file")]
+    NotRdb,
+    #[error(
+        "Wrong RDB checksum: expected {expected:016x}, got {actual:016x}, the file is corrupted"
+    )]
+    ChecksumMismatch { expected: u64, actual: u64 },
+}
+
+/// A key-value pair as stored in an RDB file, along with the absolute expiry time of the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: Key,
+    pub data: Data,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Entry {
+    /// Whether the key had already expired by the moment `at`.
+    pub fn is_expired_at(&self, at: SystemTime) -> bool {
+        self.expires_at.is_some_and(|deadline| deadline <= at)
+    }
+
+    /// Turn this into a [`Key`]-[`Value`] pair, with a TTL counting from now.
+    pub fn into_pair(self) -> (Key, Value) {
+        let value = match self.expires_at {
+            Some(deadline) => Value::expiring_at(self.data, deadline),
+            None => Value::new(self.data, None),
+        };
+        (self.key, value)
+    }
+}
+
+/// The contents of a whole RDB file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    pub version: u32,
+    /// Auxiliary fields, like `redis-ver` or `ctime`.
+    pub aux: Vec<(String, String)>,
+    /// The sources of the libraries of functions.
+    pub functions: Vec<String>,
+    pub entries: Vec<Entry>,
+}
+
+/// A decoded length, as produced by [`Reader::length`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Length {
+    /// A plain length.
+    Plain(usize),
+    /// A special string encoding, identified by the low 6 bits of the first byte.
+    Encoded(u8),
+}
+
+/// Write a length using the RDB variable-length encoding.
+pub fn write_length(out: &mut Vec<u8>, length: usize) {
+    match u32::try_from(length) {
+        Ok(length @ 0..=0x3F) => out.push(length as u8),
+        Ok(length @ 0x40..=0x3FFF) => {
+            out.extend_from_slice(&(0x4000 | length as u16).to_be_bytes());
+        }
+        Ok(length) => {
+            out.push(0x80);
+            out.extend_from_slice(&length.to_be_bytes());
+        }
+        Err(_) => {
+            out.push(0x81);
+            out.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+}
+
+/// Write a length-prefixed string.
+pub fn write_string(out: &mut Vec<u8>, string: impl AsRef<[u8]>) {
+    let string = string.as_ref();
+    write_length(out, string.len());
+    out.extend_from_slice(string);
+}
+
+/// Write a string like [`write_string`], but LZF-compressed if `compression`
+/// is on and that saves at least 4 bytes, just like Redis does.
+pub fn write_compressible_string(out: &mut Vec<u8>, string: impl AsRef<[u8]>, compression: bool) {
+    let string = string.as_ref();
+    if compression && string.len() > MIN_COMPRESSED_LEN {
+        let compressed = lzf::compress(string);
+        if compressed.len() + 4 <= string.len() {
+            out.push(0xC0 | LZF_ENCODING);
+            write_length(out, compressed.len());
+            write_length(out, string.len());
+            out.extend_from_slice(&compressed);
+            return;
+        }
+    }
+    write_string(out, string);
+}
+
+/// Write a key-value pair, preceded by an expire opcode if the [`Value`] has a TTL,
+/// and with the strings in it compressed if `compression` is on.
+pub fn write_entry(out: &mut Vec<u8>, key: &str, value: &Value, compression: bool) {
+    let write_string =
+        |out: &mut Vec<u8>, string: &[u8]| write_compressible_string(out, string, compression);
+    if let Some(deadline) = value.expires_at() {
+        out.push(opcode::EXPIRETIME_MS);
+        out.extend_from_slice(&unix_millis(deadline).to_le_bytes());
+    }
+    out.push(match value.data {
+        Data::String(_) => value_type::STRING,
+        Data::Set(_) => value_type::SET,
+        Data::SortedSet(_) => value_type::ZSET_2,
+        Data::Stream(_) => value_type::STREAM_LISTPACKS_3,
+    });
+    write_string(out, key.as_bytes());
+    write_value(out, &value.data, compression);
+}
+
+/// Write the payload of a value, the part that follows its type and its key.
+pub fn write_value(out: &mut Vec<u8>, data: &Data, compression: bool) {
+    let write_string =
+        |out: &mut Vec<u8>, string: &[u8]| write_compressible_string(out, string, compression);
+    match data {
+        Data::String(string) => write_string(out, string),
+        Data::Set(set) => {
+            write_length(out, set.len());
+            for member in set {
+                write_string(out, member.as_bytes());
+            }
+        }
+        Data::SortedSet(zset) => {
+            write_length(out, zset.len());
+            for (member, score) in zset.iter() {
+                write_string(out, member.as_bytes());
+                out.extend_from_slice(&score.0.to_le_bytes());
+            }
+        }
+        Data::Stream(stream) => write_stream(out, stream, compression),
+    }
+}
+
+/// Write a stream as [`value_type::STREAM_LISTPACKS_3`].
+fn write_stream(out: &mut Vec<u8>, stream: &Stream, compression: bool) {
+    let entries: Vec<_> = stream.after(StreamId::MIN).collect();
+    let nodes = entries.chunks(STREAM_NODE_MAX_ENTRIES);
+    write_length(out, nodes.len());
+    for node in nodes {
+        write_string(out, stream_id_bytes(*node[0].0));
+        let listpack = listpack::encode(&stream_node(node));
+        write_compressible_string(out, listpack, compression);
+    }
+    write_length(out, stream.len());
+    write_stream_id(out, stream.last_id());
+    let first = entries.first().map_or(StreamId::MIN, |(id, _)| **id);
+    write_stream_id(out, first);
+    write_stream_id(out, stream.max_deleted_id());
+    write_length(out, stream.entries_added() as usize);
+    write_length(out, stream.groups().count());
+    for (name, group) in stream.groups() {
+        write_string(out, name);
+        write_stream_id(out, group.last_delivered);
+        // Unknown is written as -1, like Redis does.
+        write_length(out, stream.entries_read(group).unwrap_or(u64::MAX) as usize);
+        write_length(out, group.pending.len());
+        for (id, pending) in &group.pending {
+            out.extend_from_slice(&stream_id_bytes(*id));
+            out.extend_from_slice(&pending.delivered_at.to_le_bytes());
+            write_length(out, pending.deliveries as usize);
+        }
+        write_length(out, group.consumers.len());
+        for (name, consumer) in &group.consumers {
+            write_string(out, name);
+            out.extend_from_slice(&consumer.seen_at.to_le_bytes());
+            let active_at = consumer.active_at.map_or(-1, |at| at as i64);
+            out.extend_from_slice(&active_at.to_le_bytes());
+            write_length(out, consumer.pending.len());
+            for id in &consumer.pending {
+                out.extend_from_slice(&stream_id_bytes(*id));
+            }
+        }
+    }
+}
+
+/// The elements of the listpack of a node of a stream, starting with the master entry:
+/// the number of entries, the number of deleted ones, the fields of the first entry and
+/// a `0`. Every entry then has its flags, its ID as the difference with the first one,
+/// its fields, and how many elements it took.
+fn stream_node(node: &[(&StreamId, &Fields)]) -> Vec<Element> {
+    let (master, master_fields) = node[0];
+    let string = |string: &String| Element::from(string.as_str());
+    let mut elements = vec![
+        Element::from(node.len() as i64),
+        Element::from(0),
+        Element::from(master_fields.len() as i64),
+    ];
+    elements.extend(master_fields.iter().map(|(field, _)| string(field)));
+    elements.push(Element::from(0));
+    for (id, fields) in node {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields.iter())
+                .all(|((field, _), (master, _))| field == master);
+        let start = elements.len();
+        elements.extend([
+            Element::from(if same_fields {
+                STREAM_ITEM_SAME_FIELDS
+            } else {
+                0
+            }),
+            Element::from(id.ms.wrapping_sub(master.ms) as i64),
+            Element::from(id.seq.wrapping_sub(master.seq) as i64),
+        ]);
+        if same_fields {
+            elements.extend(fields.iter().map(|(_, value)| string(value)));
+        } else {
+            elements.push(Element::from(fields.len() as i64));
+            for (field, value) in fields.iter() {
+                elements.extend([string(field), string(value)]);
+            }
+        }
+        let count = elements.len() - start;
+        elements.push(Element::from(count as i64));
+    }
+    elements
+}
+
+/// Write a stream ID as two lengths.
+fn write_stream_id(out: &mut Vec<u8>, id: StreamId) {
+    write_length(out, id.ms as usize);
+    write_length(out, id.seq as usize);
+}
+
+/// A stream ID as 16 big-endian bytes, which sort the same as the IDs.
+fn stream_id_bytes(id: StreamId) -> [u8; 16] {
+    (u128::from(id.ms) << 64 | u128::from(id.seq)).to_be_bytes()
+}
+
+/// Write a whole RDB file holding the libraries with the sources `functions`,
+/// and a single database with the given `entries`, compressed if `compression` is on.
+pub fn write_file<'a>(
+    out: &mut Vec<u8>,
+    aux: &[(&str, &str)],
+    functions: &[String],
+    entries: impl IntoIterator<Item = (&'a Key, &'a Value)>,
+    compression: bool,
+) {
+    let start = out.len();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(format!("{VERSION:04}").as_bytes());
+    for (field, value) in aux {
+        out.push(opcode::AUX);
+        write_string(out, field);
+        write_string(out, value);
+    }
+    for code in functions {
+        out.push(opcode::FUNCTION2);
+        write_string(out, code);
+    }
+    out.push(opcode::SELECTDB);
+    write_length(out, 0);
+    for (key, value) in entries {
+        write_entry(out, key, value, compression);
+    }
+    out.push(opcode::EOF);
+    let checksum = crc64::checksum(&out[start..]);
+    out.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Read a whole RDB file. Keys from all the databases end up in [`File::entries`].
+pub fn read_file(bytes: &[u8]) -> Result<File, Error> {
+    read_file_prefix(bytes).map(|(file, _)| file)
+}
+
+/// Read an RDB file at the start of `bytes`, along with how many bytes it takes up,
+/// like the preamble of an AOF that more data follows.
+pub fn read_file_prefix(bytes: &[u8]) -> Result<(File, usize), Error> {
+    let mut reader = Reader::new(bytes);
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(Error::NotRdb);
+    }
+    let version = std::str::from_utf8(&reader.array::<4>()?)
+        .ok()
+        .and_then(|version| version.parse().ok())
+        .ok_or(Error::NotRdb)?;
+    let mut file = File {
+        version,
+        aux: vec![],
+        functions: vec![],
+        entries: vec![],
+    };
+    loop {
+        match reader.peek().ok_or(Error::UnexpectedEof)? {
+            opcode::EOF => {
+                let _ = reader.u8()?;
+                break;
+            }
+            opcode::AUX => {
+                let _ = reader.u8()?;
+                file.aux.push((reader.string()?, reader.string()?));
+            }
+            opcode::FUNCTION2 => {
+                let _ = reader.u8()?;
+                file.functions.push(reader.string()?);
+            }
+            opcode::SELECTDB => {
+                let _ = reader.u8()?;
+                let _ = reader.plain_length()?;
+            }
+            opcode::RESIZEDB => {
+                let _ = reader.u8()?;
+                let _ = (reader.plain_length()?, reader.plain_length()?);
+            }
+            _ => file.entries.push(reader.stored_entry()?),
+        }
+    }
+    if version >= CHECKSUM_VERSION {
+        let checked = &bytes[..bytes.len() - reader.bytes.len()];
+        let expected = u64::from_le_bytes(reader.array()?);
+        let actual = crc64::checksum(checked);
+        if expected != 0 && expected != actual {
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok((file, bytes.len() - reader.bytes.len()))
+}
+
+/// A cursor over RDB-encoded bytes.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Whether all the bytes have been consumed.
+    pub const fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Look at the next byte without consuming it.
+    pub fn peek(&self) -> Option<u8> {
+        self.bytes.first().copied()
+    }
+
+    /// Consume exactly `count` bytes.
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < count {
+            return Err(Error::UnexpectedEof);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// Consume exactly `N` bytes into an array.
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    /// Consume a single byte.
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    /// Read a length, whose encoding depends on the two most significant bits of the first byte:
+    ///
+    /// - `00`: The next 6 bits represent the length.
+    /// - `01`: Read one additional byte. The combined 14 bits represent the length.
+    /// - `10`: Discard the remaining 6 bits. The next 4 (or 8, if the first byte
+    ///   is `0x81`) bytes represent the length, in big-endian.
+    /// - `11`: The next object is encoded in a special format. The remaining
+    ///   6 bits indicate the format, see [`Reader::string`].
+    pub fn length(&mut self) -> Result<Length, Error> {
+        let first = self.u8()?;
+        let length = match first >> 6 {
+            0b00 => usize::from(first & 0x3F),
+            0b01 => usize::from(u16::from_be_bytes([first & 0x3F, self.u8()?])),
+            0b10 if first == 0x80 => u32::from_be_bytes(self.array()?) as usize,
+            0b10 if first == 0x81 => usize::try_from(u64::from_be_bytes(self.array()?))
+                .map_err(|_| Error::Malformed("length does not fit in memory"))?,
+            0b10 => return Err(Error::Malformed("unknown length encoding")),
+            _ => return Ok(Length::Encoded(first & 0x3F)),
+        };
+        Ok(Length::Plain(length))
+    }
+
+    /// Read a plain length, rejecting special encodings.
+    pub fn plain_length(&mut self) -> Result<usize, Error> {
+        match self.length()? {
+            Length::Plain(length) => Ok(length),
+            Length::Encoded(_) => Err(Error::Malformed("expected a plain length")),
+        }
+    }
+
+    /// Read a string, which is either length-prefixed or uses one of the special encodings:
+    ///
+    /// - `0`: An 8 bit integer follows.
+    /// - `1`: A 16 bit integer follows (little-endian).
+    /// - `2`: A 32 bit integer follows (little-endian).
+    /// - `3`: An [LZF](crate::lzf)-compressed string follows, as the compressed length,
+    ///   the original length, and the compressed bytes.
+    pub fn bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let integer = match self.length()? {
+            Length::Plain(length) => return Ok(self.take(length)?.to_vec()),
+            Length::Encoded(LZF_ENCODING) => {
+                let compressed = self.plain_length()?;
+                let length = self.plain_length()?;
+                return lzf::decompress(self.take(compressed)?, length)
+                    .ok_or(Error::Malformed("invalid LZF-compressed string"));
+            }
+            Length::Encoded(0) => i8::from_le_bytes(self.array()?).to_string(),
+            Length::Encoded(1) => i16::from_le_bytes(self.array()?).to_string(),
+            Length::Encoded(2) => i32::from_le_bytes(self.array()?).to_string(),
+            Length::Encoded(encoding) => return Err(Error::UnsupportedEncoding(encoding)),
+        };
+        Ok(integer.into_bytes())
+    }
+
+    /// Read a string like [`Reader::bytes`] does, which has to be valid UTF-8.
+    pub fn string(&mut self) -> Result<String, Error> {
+        String::from_utf8(self.bytes()?).map_err(|_| Error::Malformed("string is not valid UTF-8"))
+    }
+
+    /// Read a key-value pair, including the expire opcode that may precede it.
+    ///
+    /// Keys that have already expired are returned as is, it is up to the
+    /// caller to check [`Value::is_expired`] and decide what to do with them.
+    #[cfg(test)]
+    pub fn entry(&mut self) -> Result<(Key, Value), Error> {
+        self.stored_entry().map(Entry::into_pair)
+    }
+
+    /// Read a key-value pair like [`Reader::entry`], but keep its absolute expiry time.
+    pub fn stored_entry(&mut self) -> Result<Entry, Error> {
+        let deadline = match self.peek() {
+            Some(opcode::EXPIRETIME_MS) => {
+                let _ = self.u8()?;
+                Some(Duration::from_millis(u64::from_le_bytes(self.array()?)))
+            }
+            Some(opcode::EXPIRETIME) => {
+                let _ = self.u8()?;
+                Some(Duration::from_secs(
+                    u32::from_le_bytes(self.array()?).into(),
+                ))
+            }
+            _ => None,
+        };
+        let value_type = self.u8()?;
+        let key = self.string()?;
+        let data = self.data(value_type)?;
+        Ok(Entry {
+            key,
+            data,
+            expires_at: deadline.map(|since_epoch| UNIX_EPOCH + since_epoch),
+        })
+    }
+
+    /// Read the type-specific encoding of a value.
+    fn data(&mut self, value_type: u8) -> Result<Data, Error> {
+        match value_type {
+            value_type::STRING => Ok(Data::String(self.bytes()?)),
+            value_type::SET => {
+                let length = self.plain_length()?;
+                let set = (0..length)
+                    .map(|_| self.string())
+                    .collect::<Result<IndexedSet, _>>()?;
+                Ok(Data::Set(set))
+            }
+            value_type::ZSET_2 => {
+                let length = self.plain_length()?;
+                let zset = (0..length)
+                    .map(|_| {
+                        let member = self.string()?;
+                        let score = f64::from_le_bytes(self.array()?);
+                        if score.is_nan() {
+                            return Err(Error::Malformed("NaN score in a sorted set"));
+                        }
+                        Ok((member, Score(score)))
+                    })
+                    .collect::<Result<SortedSet, _>>()?;
+                Ok(Data::SortedSet(zset))
+            }
+            value_type::SET_INTSET => {
+                let blob = self.string_bytes()?;
+                Ok(Data::Set(decode_intset(blob)?))
+            }
+            value_type::STREAM_LISTPACKS
+            | value_type::STREAM_LISTPACKS_2
+            | value_type::STREAM_LISTPACKS_3 => Ok(Data::Stream(self.stream(value_type)?)),
+            unknown => Err(Error::UnsupportedValueType(unknown)),
+        }
+    }
+
+    /// Read a stream written as any of the `value_type::STREAM_LISTPACKS` types.
+    fn stream(&mut self, value_type: u8) -> Result<Stream, Error> {
+        let mut entries = BTreeMap::new();
+        for _ in 0..self.plain_length()? {
+            let master = stream_id_from_bytes(&self.bytes()?)?;
+            let elements = listpack::decode(&self.bytes()?)
+                .ok_or(Error::Malformed("invalid listpack in a stream"))?;
+            decode_stream_node(master, elements, &mut entries)?;
+        }
+        let length = self.plain_length()?;
+        let last_id = self.stream_id()?;
+        let (max_deleted_id, entries_added) = if value_type >= value_type::STREAM_LISTPACKS_2 {
+            let _first = self.stream_id()?;
+            (self.stream_id()?, self.plain_length()? as u64)
+        } else {
+            (StreamId::MIN, length as u64)
+        };
+        let mut groups = BTreeMap::new();
+        for _ in 0..self.plain_length()? {
+            let name = self.string()?;
+            let mut group = ConsumerGroup {
+                last_delivered: self.stream_id()?,
+                ..ConsumerGroup::default()
+            };
+            // How many entries the group read is worked out from the stream instead.
+            if value_type >= value_type::STREAM_LISTPACKS_2 {
+                let _entries_read = self.plain_length()?;
+            }
+            let mut unowned = BTreeMap::new();
+            for _ in 0..self.plain_length()? {
+                let id = stream_id_from_bytes(self.take(16)?)?;
+                let delivered_at = u64::from_le_bytes(self.array()?);
+                let deliveries = self.plain_length()? as u64;
+                let _ = unowned.insert(id, (delivered_at, deliveries));
+            }
+            for _ in 0..self.plain_length()? {
+                let name = self.string()?;
+                let seen_at = u64::from_le_bytes(self.array()?);
+                // Older streams don't tell, and Redis takes the last time the consumer was seen.
+                let active_at = match value_type {
+                    value_type::STREAM_LISTPACKS_3 => {
+                        u64::try_from(i64::from_le_bytes(self.array()?)).ok()
+                    }
+                    _ => Some(seen_at),
+                };
+                let mut consumer = Consumer {
+                    seen_at,
+                    active_at,
+                    ..Consumer::default()
+                };
+                for _ in 0..self.plain_length()? {
+                    let id = stream_id_from_bytes(self.take(16)?)?;
+                    let (delivered_at, deliveries) = unowned
+                        .remove(&id)
+                        .ok_or(Error::Malformed("pending entry missing from its group"))?;
+                    let pending = Pending {
+                        consumer: name.clone(),
+                        delivered_at,
+                        deliveries,
+                    };
+                    let _ = group.pending.insert(id, pending);
+                    let _ = consumer.pending.insert(id);
+                }
+                let _ = group.consumers.insert(name, consumer);
+            }
+            if !unowned.is_empty() {
+                return Err(Error::Malformed("pending entry without a consumer"));
+            }
+            let _ = groups.insert(name, group);
+        }
+        Ok(Stream::restored(
+            entries,
+            last_id,
+            entries_added,
+            max_deleted_id,
+            groups,
+        ))
+    }
+
+    /// Read a stream ID written as two lengths.
+    fn stream_id(&mut self) -> Result<StreamId, Error> {
+        Ok(StreamId {
+            ms: self.plain_length()? as u64,
+            seq: self.plain_length()? as u64,
+        })
+    }
+
+    /// Read a length-prefixed binary blob.
+    fn string_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let length = self.plain_length()?;
+        self.take(length)
+    }
+}
+
+/// Decode an `intset` blob: a 4-byte integer width, a 4-byte
+/// count, and then that many integers of said width, all little-endian.
+fn decode_intset(blob: &[u8]) -> Result<IndexedSet, Error> {
+    let mut reader = Reader::new(blob);
+    let width = u32::from_le_bytes(reader.array()?) as usize;
+    let count = u32::from_le_bytes(reader.array()?) as usize;
+    (0..count)
+        .map(|_| {
+            let member = match width {
+                2 => i16::from_le_bytes(reader.array()?).to_string(),
+                4 => i32::from_le_bytes(reader.array()?).to_string(),
+                8 => i64::from_le_bytes(reader.array()?).to_string(),
+                _ => return Err(Error::Malformed("unknown intset width")),
+            };
+            Ok(member)
+        })
+        .collect()
+}
+
+/// Read a stream ID written as 16 big-endian bytes.
+fn stream_id_from_bytes(bytes: &[u8]) -> Result<StreamId, Error> {
+    let bytes: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| Error::Malformed("stream ID is not 16 bytes long"))?;
+    let id = u128::from_be_bytes(bytes);
+    Ok(StreamId {
+        ms: (id >> 64) as u64,
+        seq: id as u64,
+    })
+}
+
+/// Add the live entries of a node of a stream to `entries`, see [`stream_node`].
+fn decode_stream_node(
+    master: StreamId,
+    elements: Vec<Element>,
+    entries: &mut BTreeMap<StreamId, Fields>,
+) -> Result<(), Error> {
+    const MALFORMED: Error = Error::Malformed("invalid entry in a stream listpack");
+    let integer = |element: Option<Element>| element.as_ref().and_then(Element::integer);
+    let integer = |element| integer(element).ok_or(MALFORMED);
+    let string = |element: Option<Element>| {
+        let bytes = element.ok_or(MALFORMED)?.into_bytes();
+        String::from_utf8(bytes).map_err(|_| Error::Malformed("string is not valid UTF-8"))
+    };
+    let mut elements = elements.into_iter();
+    let (count, deleted) = (integer(elements.next())?, integer(elements.next())?);
+    let master_fields = (0..integer(elements.next())?)
+        .map(|_| string(elements.next()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if integer(elements.next())? != 0 {
+        return Err(MALFORMED);
+    }
+    for _ in 0..count + deleted {
+        let flags = integer(elements.next())?;
+        let id = StreamId {
+            ms: master.ms.wrapping_add(integer(elements.next())? as u64),
+            seq: master.seq.wrapping_add(integer(elements.next())? as u64),
+        };
+        let fields = if flags & STREAM_ITEM_SAME_FIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), string(elements.next())?)))
+                .collect::<Result<Fields, Error>>()?
+        } else {
+            (0..integer(elements.next())?)
+                .map(|_| Ok((string(elements.next())?, string(elements.next())?)))
+                .collect::<Result<Fields, Error>>()?
+        };
+        let _count = integer(elements.next())?;
+        if flags & STREAM_ITEM_DELETED == 0 {
+            let _ = entries.insert(id, fields);
+        }
+    }
+    Ok(())
+}
+
+/// Milliseconds elapsed between the Unix epoch and `time`.
+pub fn unix_millis(time: SystemTime) -> u64 {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_file, read_file_prefix, write_compressible_string, write_entry, write_file,
+        write_length, Error, Length, Reader,
+    };
+    use crate::crc64;
+    use crate::database::{Data, Database, IdSpec, IndexedSet, ReadFrom, ReadGroupFrom};
+    use crate::database::{Score, SortedSet, StreamId, Value, XAddOptions};
+    use crate::listpack::{self, Element};
+    use std::time::Duration;
+
+    #[test]
+    fn length_encodings() {
+        for length in [0, 0x3F, 0x40, 0x3FFF, 0x4000, 0xFFFF_FFFF, 0x1_0000_0000] {
+            let mut out = vec![];
+            write_length(&mut out, length);
+            assert_eq!(Reader::new(&out).length(), Ok(Length::Plain(length)));
+        }
+    }
+
+    #[test]
+    fn integer_strings() {
+        let mut reader = Reader::new(&[0xC0, 0xFB, 0xC1, 0x39, 0x30, 0xC2, 0x87, 0xD6, 0x12, 0x00]);
+        assert_eq!(reader.string().unwrap(), "-5");
+        assert_eq!(reader.string().unwrap(), "12345");
+        assert_eq!(reader.string().unwrap(), "1234567");
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn string_entry_with_expiry() {
+        let mut out = vec![];
+        let value = Value::with_ttl("bar".to_string(), Duration::from_secs(60));
+        write_entry(&mut out, "foo", &value, false);
+        assert_eq!(out[0], super::opcode::EXPIRETIME_MS);
+
+        let (key, decoded) = Reader::new(&out).entry().unwrap();
+        assert_eq!(key, "foo");
+        assert_eq!(decoded, value);
+        assert!(!decoded.is_expired());
+        assert!(decoded.expires_at().is_some());
+    }
+
+    #[test]
+    fn binary_string_entry() {
+        let mut out = vec![];
+        let value = Value::new(Data::String(vec![0xFF, 0, 0x80]), None);
+        write_entry(&mut out, "bitmap", &value, false);
+        let (key, decoded) = Reader::new(&out).entry().unwrap();
+        assert_eq!(key, "bitmap");
+        assert_eq!(decoded.data, value.data);
+    }
+
+    #[test]
+    fn set_entry() {
+        let set: IndexedSet = ["a", "b", "c"].into_iter().map(String::from).collect();
+        let value = Value::new(Data::Set(set), None);
+        let mut out = vec![];
+        write_entry(&mut out, "s", &value, false);
+        assert_eq!(out[0], super::value_type::SET);
+        assert_eq!(Reader::new(&out).entry().unwrap(), ("s".to_string(), value));
+    }
+
+    #[test]
+    fn sorted_set_entry() {
+        let zset: SortedSet = [("a", 1.5), ("b", -2.0), ("c", f64::INFINITY)]
+            .into_iter()
+            .map(|(member, score)| (member.to_string(), Score(score)))
+            .collect();
+        let value = Value::new(Data::SortedSet(zset), None);
+        let mut out = vec![];
+        write_entry(&mut out, "z", &value, false);
+        assert_eq!(out[0], super::value_type::ZSET_2);
+        assert_eq!(Reader::new(&out).entry().unwrap(), ("z".to_string(), value));
+    }
+
+    #[test]
+    fn intset_entry() {
+        let mut out = vec![super::value_type::SET_INTSET, 1, b's', 16];
+        out.extend_from_slice(&2_u32.to_le_bytes());
+        out.extend_from_slice(&4_u32.to_le_bytes());
+        for member in [1_i16, -2, 3, 400] {
+            out.extend_from_slice(&member.to_le_bytes());
+        }
+        let (_, value) = Reader::new(&out).entry().unwrap();
+        let expected: IndexedSet = ["1", "-2", "3", "400"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(value.data, Data::Set(expected));
+    }
+
+    #[test]
+    fn whole_file() {
+        let plain = Value::new("1".to_string(), None);
+        let expiring = Value::new("2".to_string(), Some(Duration::from_secs(60)));
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let mut out = vec![];
+        let functions = ["#!lua name=lib\n".to_string()];
+        write_file(
+            &mut out,
+            &[("redis-ver", "7.2.0")],
+            &functions,
+            [(&a, &plain), (&b, &expiring)],
+            false,
+        );
+        assert!(out.starts_with(b"REDIS0011"));
+
+        let file = read_file(&out).unwrap();
+        assert_eq!(file.version, 11);
+        assert_eq!(file.aux, [("redis-ver".to_string(), "7.2.0".to_string())]);
+        assert_eq!(file.functions, functions);
+        assert_eq!(file.entries.len(), 2);
+        assert_eq!(file.entries[0].expires_at, None);
+        let deadline = file.entries[1].expires_at.unwrap();
+        assert!(!file.entries[1].is_expired_at(deadline - Duration::from_secs(1)));
+        assert!(file.entries[1].is_expired_at(deadline));
+        assert_eq!(file.entries[1].clone().into_pair(), (b, expiring));
+
+        assert_eq!(read_file(b"HELLO0011"), Err(Error::NotRdb));
+        assert_eq!(read_file(&out[..out.len() - 9]), Err(Error::UnexpectedEof));
+        assert_eq!(read_file(&out[..out.len() - 1]), Err(Error::UnexpectedEof));
+
+        // Like the preamble of an AOF.
+        let len = out.len();
+        out.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(read_file_prefix(&out), Ok((file, len)));
+    }
+
+    #[test]
+    fn checksum() {
+        let value = Value::new("bar".to_string(), None);
+        let key = "foo".to_string();
+        let mut out = vec![];
+        write_file(&mut out, &[], &[], [(&key, &value)], false);
+        let checksum = u64::from_le_bytes(out[out.len() - 8..].try_into().unwrap());
+        assert_eq!(checksum, crc64::checksum(&out[..out.len() - 8]));
+
+        // Flip a bit of the value.
+        let mut corrupted = out.clone();
+        let position = corrupted.len() - 10;
+        corrupted[position] ^= 1;
+        assert!(matches!(
+            read_file(&corrupted),
+            Err(Error::ChecksumMismatch { expected, .. }) if expected == checksum
+        ));
+
+        // A zeroed checksum is not verified, and older versions have none at all.
+        let mut unchecked = corrupted;
+        let length = unchecked.len();
+        unchecked[length - 8..].fill(0);
+        assert!(read_file(&unchecked).is_ok());
+        let mut old = out[..out.len() - 8].to_vec();
+        old[5..9].copy_from_slice(b"0004");
+        assert!(read_file(&old).is_ok());
+    }
+
+    #[test]
+    fn compressed_strings() {
+        let long = "compressible ".repeat(10);
+        let value = Value::new(long.clone(), None);
+        let mut plain = vec![];
+        write_entry(&mut plain, "key", &value, false);
+        let mut compressed = vec![];
+        write_entry(&mut compressed, "key", &value, true);
+        assert!(compressed.len() < plain.len() / 2);
+        assert_eq!(compressed[5], 0xC3);
+        let (key, read) = Reader::new(&compressed).entry().unwrap();
+        assert_eq!(key, "key");
+        assert_eq!(read.data, Data::String(long.into()));
+
+        // Short strings, and those that would not get any smaller, stay as they are.
+        let mut out = vec![];
+        write_compressible_string(&mut out, "short", true);
+        assert_eq!(out, b"\x05short");
+        let noise: Vec<u8> = (0..=255).collect();
+        out.clear();
+        write_compressible_string(&mut out, &noise, true);
+        assert_eq!(Reader::new(&out).bytes().unwrap(), noise);
+        assert_eq!(out.len(), noise.len() + 2);
+
+        // A string compressed with a wrong length.
+        let mut corrupted = compressed.clone();
+        corrupted[7] += 1;
+        assert_eq!(
+            Reader::new(&corrupted).entry(),
+            Err(Error::Malformed("invalid LZF-compressed string"))
+        );
+    }
+
+    #[test]
+    fn stream_entry() {
+        let mut db = Database::new();
+        let id = |ms, seq| StreamId { ms, seq };
+        for ms in 1..=150 {
+            let mut fields = vec![("n".to_string(), ms.to_string())];
+            // Entries with other fields than the first one of their node spell them out.
+            if ms % 7 == 0 {
+                fields.push(("extra".to_string(), "x".repeat(50)));
+            }
+            let spec = IdSpec::Explicit(id(ms, 150 - ms));
+            let added = db.xadd("s".into(), spec, fields, XAddOptions::default());
+            assert!(added.is_ok());
+        }
+        assert_eq!(db.xdel("s", &[id(3, 147), id(150, 0)]), Ok(2));
+        let after = ReadFrom::After(StreamId::MIN);
+        assert!(db
+            .xgroup_create("s".into(), "g".into(), after, false)
+            .is_ok());
+        let streams = [("s".to_string(), ReadGroupFrom::New)];
+        assert!(db
+            .xreadgroup("g", "alice", &streams, Some(2), false)
+            .is_ok());
+        assert!(db.xreadgroup("g", "bob", &streams, Some(1), false).is_ok());
+        assert!(db.xgroup_create_consumer("s", "g", "carol").is_ok());
+        let value = db.iter().find(|(key, _)| *key == "s").unwrap().1.clone();
+
+        for compression in [false, true] {
+            let mut out = vec![];
+            write_entry(&mut out, "s", &value, compression);
+            assert_eq!(out[0], super::value_type::STREAM_LISTPACKS_3);
+            assert_eq!(
+                Reader::new(&out).entry().unwrap(),
+                ("s".to_string(), value.clone())
+            );
+        }
+
+        // The master entry of the first node, then the first entry, with the same fields.
+        let mut out = vec![];
+        write_entry(&mut out, "s", &value, false);
+        let mut reader = Reader::new(&out[3..]);
+        assert_eq!(reader.plain_length(), Ok(2));
+        assert_eq!(
+            reader.bytes().unwrap(),
+            [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 149]
+        );
+        let elements = listpack::decode(&reader.bytes().unwrap()).unwrap();
+        let expected: [Element; 10] = [
+            100.into(),
+            0.into(),
+            1.into(),
+            "n".into(),
+            0.into(),
+            super::STREAM_ITEM_SAME_FIELDS.into(),
+            0.into(),
+            0.into(),
+            "1".into(),
+            4.into(),
+        ];
+        assert_eq!(elements[..10], expected);
+    }
+
+    #[test]
+    fn unsupported_type() {
+        // A hash stored as a listpack, as there are no hashes here.
+        let mut reader = Reader::new(&[16, 1, b'x']);
+        assert_eq!(reader.entry(), Err(Error::UnsupportedValueType(16)));
+    }
+}
@@ -0,0 +1,863 @@
+//! # Replication: keeping a copy of the dataset of another server up to date.
+//!
+//! A server started with `--replicaof "<host> <port>"` is a replica of that master.
+//! It connects to the master and goes through the same handshake as Redis replicas do:
+//!
+//! 1. `PING`, to check that the master is there at all.
+//! 2. `REPLCONF listening-port <port>` and `REPLCONF capa psync2`, to introduce itself.
+//! 3. `PSYNC ? -1`, which asks for a full resynchronization. The master replies with
+//!    `+FULLRESYNC <replid> <offset>`, followed by its whole dataset as an RDB file,
+//!    sent like a bulk string but without the trailing CRLF.
+//!
+//! The replica then replaces its dataset with the one in the RDB file, and from then on
+//! applies the commands that the master propagates over the same connection, without
+//! replying to any of them. Those are the only writes that a replica takes: its clients
+//! get [`READONLY`] for theirs, as they would only make it diverge from its master.
+//!
+//! On the side of the master, a connection that sends `PSYNC` turns into a [`Replica`]
+//! instead of a normal client. There is no backlog of propagated commands to continue
+//! from, so every `PSYNC` gets a full resynchronization, whatever it asks for.
+//!
+//! Both sides count the bytes of the commands that go over the link: the master its
+//! replication offset, and the replica how much of it it processed. The replica tells
+//! its offset to the master with `REPLCONF ACK <offset>` every [`ACK_PERIOD`], and as
+//! soon as the master asks for it with `REPLCONF GETACK *`.
+//!
+//! Every write that succeeds is then [propagated](Replication::propagate) to the replicas
+//! as it was requested, while the database is still locked by it, so that the replicas
+//! apply the writes in the very order that the master made them. The writes that would
+//! play out differently on a replica, as they pick at random or make up IDs, are
+//! propagated as what they [did](effect) instead. The writes queued in
+//! transactions are wrapped in `MULTI` and `EXEC` again, and scripts propagate the writes
+//! that they make, rather than themselves. Blocking commands are the only writes that
+//! can't keep the database locked while they wait, and get propagated once they are done.
+//!
+//! `REPLICAOF` changes the master at runtime, which drops the link to the old one.
+//! `REPLICAOF NO ONE` promotes a replica: it keeps its dataset and offset, but takes
+//! on a new replication ID, as its history goes its own way from then on.
+
+use crate::client::Client;
+use crate::command::Command;
+use crate::database::XClaimOptions;
+use crate::random::Rng;
+use crate::resp::{Protocol, Token};
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+/// How often a replica tells its master how much of the propagated commands it processed.
+pub const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// How long a replica waits before it first tries to reconnect to its master.
+/// Every failed attempt doubles that, up to [`MAX_RECONNECT_DELAY`].
+pub const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest that a replica waits between two attempts to reconnect to its master.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The reply to `PSYNC` inside a transaction or a script.
+pub const PSYNC_NOT_ALLOWED: &str = "ERR PSYNC can't be sent inside MULTI or scripts";
+/// The reply to write commands from the clients of a replica.
+pub const READONLY: &str = "READONLY You can't write against a read only replica.";
+/// The reply to most commands on a replica that lost its master, if it may not serve stale data.
+pub const MASTERDOWN: &str =
+    "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.";
+/// The reply to writes on a master with fewer good replicas than `min-replicas-to-write`.
+pub const NOREPLICAS: &str = "NOREPLICAS Not enough good replicas to write.";
+/// The reply to `WAIT` on a replica, which has no replicas of its own to wait for.
+pub const WAIT_ON_REPLICA: &str = "ERR WAIT cannot be used with replica instances.";
+
+/// The master of a replica, as given to `--replicaof`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaOf {
+    pub host: String,
+    pub port: u16,
+}
+
+impl ReplicaOf {
+    /// Parse a master given as `"<host> <port>"`, like the `replicaof` directive takes it.
+    pub fn parse(string: &str) -> Result<Self, String> {
+        match string.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [host, port] => Ok(Self {
+                host: (*host).to_string(),
+                port: port
+                    .parse()
+                    .map_err(|_| format!("Invalid port of the master {port:?}"))?,
+            }),
+            _ => Err(format!("Expected \"<host> <port>\", got {string:?}")),
+        }
+    }
+}
+
+impl Display for ReplicaOf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.host, self.port)
+    }
+}
+
+/// What the master sends for a full resynchronization, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullResync {
+    /// The replication ID of the master.
+    pub replid: String,
+    /// The replication offset of the master, as of the RDB file.
+    pub offset: u64,
+    /// The whole dataset of the master.
+    pub rdb: Vec<u8>,
+}
+
+/// Go through the handshake with the `master`, telling it that this server listens on `port`.
+pub async fn handshake(master: &mut Client, port: u16) -> anyhow::Result<FullResync> {
+    expect(master, &["PING"], "PONG").await?;
+    expect(
+        master,
+        &["REPLCONF", "listening-port", &port.to_string()],
+        "OK",
+    )
+    .await?;
+    expect(master, &["REPLCONF", "capa", "psync2"], "OK").await?;
+    let reply = master.call(&["PSYNC", "?", "-1"]).await?;
+    let full_resync = match &reply {
+        Token::SimpleString { data } => data
+            .strip_prefix("FULLRESYNC ")
+            .and_then(|rest| rest.split_once(' '))
+            .and_then(|(replid, offset)| Some((replid.to_string(), offset.parse().ok()?))),
+        _ => None,
+    };
+    let Some((replid, offset)) = full_resync else {
+        anyhow::bail!("Unexpected reply to PSYNC: {reply:?}");
+    };
+    let rdb = master.read_rdb().await?;
+    Ok(FullResync {
+        replid,
+        offset,
+        rdb,
+    })
+}
+
+/// Send a command of the handshake, failing unless the `master` replies with the `expected` status.
+async fn expect(master: &mut Client, args: &[&str], expected: &str) -> anyhow::Result<()> {
+    match master.call(args).await? {
+        Token::SimpleString { data } if data.eq_ignore_ascii_case(expected) => Ok(()),
+        reply => anyhow::bail!("Unexpected reply to {}: {reply:?}", args.join(" ")),
+    }
+}
+
+/// A replica connected to this server, which is its master.
+#[derive(Debug, Clone)]
+pub struct Replica {
+    /// The ID of the connection that the replica synchronized over.
+    pub id: u64,
+    pub ip: IpAddr,
+    /// The port that the replica listens on, as told by `REPLCONF listening-port`.
+    pub port: Option<u16>,
+    /// Where the commands propagated to the replica go.
+    pub messages: mpsc::UnboundedSender<Token>,
+    /// The offset up to which the replica acknowledged the propagated commands.
+    acked: u64,
+    /// When the replica last acknowledged the propagated commands, or synchronized.
+    acked_at: Instant,
+}
+
+impl Replica {
+    pub fn new(
+        id: u64,
+        ip: IpAddr,
+        port: Option<u16>,
+        messages: mpsc::UnboundedSender<Token>,
+    ) -> Self {
+        Self {
+            id,
+            ip,
+            port,
+            messages,
+            acked: 0,
+            acked_at: Instant::now(),
+        }
+    }
+}
+
+/// The replication state of a server, which is a master unless it replicates one.
+#[derive(Debug)]
+pub struct Replication {
+    /// The master that this server replicates, watched by the task that follows it.
+    master: watch::Sender<Option<ReplicaOf>>,
+    /// Whether the link to the master is up, that is the initial sync is done.
+    link_up: AtomicBool,
+    /// The ID of the history of the dataset: either its own, or that of the master.
+    replid: RwLock<String>,
+    /// How many bytes of commands this server propagated to its replicas so far,
+    /// or processed from its master if it is a replica.
+    offset: AtomicU64,
+    replicas: Mutex<Vec<Replica>>,
+    /// Bumped on every acknowledgement from a replica, for `WAIT` to watch.
+    acks: watch::Sender<u64>,
+}
+
+impl Replication {
+    pub fn new(master: Option<ReplicaOf>) -> Self {
+        Self {
+            master: watch::channel(master).0,
+            link_up: AtomicBool::new(false),
+            replid: RwLock::new(new_replid()),
+            offset: AtomicU64::new(0),
+            replicas: Mutex::new(vec![]),
+            acks: watch::channel(0).0,
+        }
+    }
+
+    /// The master that this server replicates, if it is a replica.
+    pub fn master(&self) -> Option<ReplicaOf> {
+        self.master.borrow().clone()
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.master.borrow().is_some()
+    }
+
+    /// Get notified whenever the master changes, see [`Replication::set_master`].
+    pub fn masters(&self) -> watch::Receiver<Option<ReplicaOf>> {
+        self.master.subscribe()
+    }
+
+    /// Replicate the `master` from now on, or become a master if there is none,
+    /// returning whether anything changed. The link to the previous master goes down.
+    pub fn set_master(&self, master: Option<ReplicaOf>) -> bool {
+        let promoted = master.is_none();
+        let changed = self.master.send_if_modified(|current| {
+            let changed = *current != master;
+            *current = master;
+            changed
+        });
+        if changed {
+            self.set_link_up(false);
+            if promoted {
+                let replid = new_replid();
+                tracing::info!(replid, "Promoted to a master");
+                *self.replid.write().unwrap_or_else(PoisonError::into_inner) = replid;
+            }
+        }
+        changed
+    }
+
+    pub fn is_link_up(&self) -> bool {
+        self.link_up.load(Ordering::Relaxed)
+    }
+
+    pub fn set_link_up(&self, up: bool) {
+        self.link_up.store(up, Ordering::Relaxed);
+    }
+
+    /// Note that the replica synchronized with its master, taking on its `replid` and `offset`.
+    pub fn synced(&self, replid: String, offset: u64) {
+        *self.replid.write().unwrap_or_else(PoisonError::into_inner) = replid;
+        self.offset.store(offset, Ordering::Relaxed);
+        self.set_link_up(true);
+    }
+
+    /// The replication offset: how much was propagated, or processed as a replica.
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    /// Count `len` more bytes processed from the master.
+    pub fn processed(&self, len: usize) {
+        let _ = self.offset.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn replid(&self) -> String {
+        self.replid
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Start propagating commands to the `replica`, returning the replication ID and
+    /// offset that its synchronization starts from, to send with `+FULLRESYNC`.
+    pub fn register(&self, mut replica: Replica) -> (String, u64) {
+        tracing::info!(id = replica.id, ip = %replica.ip, port = replica.port, "Replica registered");
+        let mut replicas = self.replicas();
+        let offset = self.offset();
+        replica.acked = offset;
+        replicas.push(replica);
+        (self.replid(), offset)
+    }
+
+    /// Record that the replica that synchronized over connection `id` processed
+    /// the propagated commands up to `offset` (`REPLCONF ACK`).
+    pub fn ack(&self, id: u64, offset: u64) {
+        if let Some(replica) = self.replicas().iter_mut().find(|replica| replica.id == id) {
+            replica.acked = offset;
+            replica.acked_at = Instant::now();
+        }
+        self.acks.send_modify(|acks| *acks += 1);
+    }
+
+    /// Count the replicas that acknowledged the propagated commands up to `offset`.
+    pub fn acked(&self, offset: u64) -> usize {
+        self.replicas()
+            .iter()
+            .filter(|replica| replica.acked >= offset)
+            .count()
+    }
+
+    /// Count the good replicas: those that acknowledged the propagated commands within the
+    /// last `max_lag`, in whole seconds like the lag in `INFO`.
+    pub fn good_replicas(&self, max_lag: Duration) -> usize {
+        self.replicas()
+            .iter()
+            .filter(|replica| replica.acked_at.elapsed().as_secs() <= max_lag.as_secs())
+            .count()
+    }
+
+    /// Get notified of the acknowledgements from now on, see [`Replication::ack`].
+    pub fn acks(&self) -> watch::Receiver<u64> {
+        self.acks.subscribe()
+    }
+
+    /// Ask all the replicas to acknowledge the propagated commands right away.
+    pub fn request_acks(&self) {
+        self.propagate(&["REPLCONF", "GETACK", "*"].map(Vec::from));
+    }
+
+    /// Stop propagating commands to the replica that synchronized over connection `id`, if any.
+    pub fn unregister(&self, id: u64) {
+        let mut replicas = self.replicas();
+        if let Some(index) = replicas.iter().position(|replica| replica.id == id) {
+            let _ = replicas.remove(index);
+            tracing::info!(id, "Replica disconnected");
+        }
+    }
+
+    /// Propagate a write, given as the request that made it, to all the replicas.
+    ///
+    /// Nothing is counted towards the offset while there are no replicas to propagate to,
+    /// and neither is anything while this server is a replica itself, which counts what
+    /// it processed from the master instead.
+    pub fn propagate(&self, request: &[Vec<u8>]) {
+        let mut replicas = self.replicas();
+        if replicas.is_empty() {
+            return;
+        }
+        let command = Token::Array {
+            tokens: request
+                .iter()
+                .map(|arg| Token::BulkBytes { data: arg.clone() })
+                .collect(),
+        };
+        if !self.is_replica() {
+            let len = command.to_bytes(Protocol::Resp2).len() as u64;
+            let _ = self.offset.fetch_add(len, Ordering::Relaxed);
+        }
+        replicas.retain(|replica| replica.messages.send(command.clone()).is_ok());
+    }
+
+    fn replicas(&self) -> std::sync::MutexGuard<'_, Vec<Replica>> {
+        self.replicas.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The reply to `ROLE`: `master` with the offset and the replicas, each as its IP, port
+    /// and acknowledged offset, or `slave` with the master, the state of the link and the offset.
+    pub fn role(&self) -> Token {
+        let offset = Token::from(i64::try_from(self.offset()).unwrap_or(i64::MAX));
+        let tokens = match self.master() {
+            None => {
+                let replicas = self
+                    .replicas()
+                    .iter()
+                    .map(|replica| Token::Array {
+                        tokens: vec![
+                            Token::from(replica.ip.to_string()),
+                            Token::from(replica.port.unwrap_or_default().to_string()),
+                            Token::from(replica.acked.to_string()),
+                        ],
+                    })
+                    .collect();
+                vec![
+                    Token::from("master".to_string()),
+                    offset,
+                    Token::Array { tokens: replicas },
+                ]
+            }
+            Some(master) => {
+                let state = if self.link_up.load(Ordering::Relaxed) {
+                    "connected"
+                } else {
+                    "connect"
+                };
+                vec![
+                    Token::from("slave".to_string()),
+                    Token::from(master.host),
+                    Token::from(usize::from(master.port)),
+                    Token::from(state.to_string()),
+                    offset,
+                ]
+            }
+        };
+        Token::Array { tokens }
+    }
+
+    /// Render the `# Replication` section of `INFO`.
+    pub fn info(&self) -> String {
+        let mut info = String::from("# Replication\r\n");
+        match self.master() {
+            None => {
+                let replicas = self.replicas();
+                let _ = write!(
+                    info,
+                    "role:master\r\nconnected_slaves:{}\r\n",
+                    replicas.len()
+                );
+                for (index, replica) in replicas.iter().enumerate() {
+                    let port = replica.port.unwrap_or_default();
+                    let _ = write!(
+                        info,
+                        "slave{index}:ip={},port={port},state=online,offset={},lag={}\r\n",
+                        replica.ip,
+                        replica.acked,
+                        replica.acked_at.elapsed().as_secs()
+                    );
+                }
+            }
+            Some(master) => {
+                let status = if self.link_up.load(Ordering::Relaxed) {
+                    "up"
+                } else {
+                    "down"
+                };
+                let _ = write!(
+                    info,
+                    "role:slave\r\nmaster_host:{}\r\nmaster_port:{}\r\nmaster_link_status:{status}\r\n\
+                     slave_repl_offset:{}\r\n",
+                    master.host,
+                    master.port,
+                    self.offset()
+                );
+            }
+        }
+        let _ = write!(
+            info,
+            "master_replid:{}\r\nmaster_repl_offset:{}\r\n",
+            self.replid(),
+            self.offset()
+        );
+        info
+    }
+}
+
+/// What to propagate for a write that succeeded with `replies`, given as its `request`,
+/// or [`None`] if it changed nothing.
+///
+/// That's the request itself, but for the writes whose outcome isn't up to the request:
+/// `SPOP` turns into `SREM` of the members it popped, `BZPOPMIN` and `BZPOPMAX` into
+/// `ZPOPMIN` and `ZPOPMAX` of the key they popped from, and `XADD` gets the ID of the
+/// entry it added.
+///
+/// Nor should the outcome depend on the clock of the replica, or on how long the write
+/// took to get there: TTLs relative to now, as of `SET … PX` or `EXT.CAS … PX`, become
+/// the Unix time `PXAT` that they ran out at on the master, as of `now_ms`. `XCLAIM` and
+/// `XAUTOCLAIM` turn into an `XCLAIM` of just the entries they claimed, with the delivery
+/// `TIME` that they got, whether they are idle on the replica or not.
+pub fn effect<'a>(
+    request: &'a [Vec<u8>],
+    replies: &[Token],
+    now_ms: u64,
+) -> Option<Cow<'a, [Vec<u8>]>> {
+    let [reply] = replies else {
+        return Some(request.into());
+    };
+    let name = String::from_utf8_lossy(request.first()?).to_ascii_lowercase();
+    let srem = |members: &[Token]| {
+        let members = members.iter().filter_map(bytes);
+        let effect = [b"SREM".to_vec(), request[1].clone()]
+            .into_iter()
+            .chain(members);
+        Some(effect.collect::<Vec<_>>().into())
+    };
+    match (name.as_str(), reply) {
+        ("spop", Token::BulkString { .. }) => srem(replies),
+        ("spop", Token::Array { tokens }) if !tokens.is_empty() => srem(tokens),
+        // The replica holds the same members, so it pops the same one from the same key.
+        ("bzpopmin" | "bzpopmax", Token::Array { tokens }) => {
+            let key = bytes(tokens.first()?)?;
+            Some(vec![name[1..].to_ascii_uppercase().into_bytes(), key].into())
+        }
+        ("xadd", Token::BulkString { data: id }) => {
+            let Ok(Command::XAdd { fields, .. }) = Command::try_from(request.to_vec()) else {
+                return Some(request.into());
+            };
+            // The ID comes right before the fields, which are all at the end.
+            let mut effect = request.to_vec();
+            effect[request.len() - 2 * fields.len() - 1] = id.clone().into_bytes();
+            Some(effect.into())
+        }
+        ("set", _) => Some(absolute_ttl(request, now_ms)),
+        ("ext.cas", Token::Integer { data: 1 }) if request.len() == 6 => {
+            let ttl: u64 = std::str::from_utf8(&request[5]).ok()?.parse().ok()?;
+            let at = now_ms.saturating_add(ttl).to_string().into_bytes();
+            let [key, new] = [&request[1], &request[3]].map(Vec::clone);
+            Some(vec![b"SET".to_vec(), key, new, b"PXAT".to_vec(), at].into())
+        }
+        ("xclaim" | "xautoclaim", Token::Array { tokens }) => {
+            let (claimed, deleted, options) = match Command::try_from(request.to_vec()).ok()? {
+                Command::XClaim { options, .. } => (tokens.as_slice(), &[][..], options),
+                Command::XAutoClaim { options, .. } => match tokens.as_slice() {
+                    [_, Token::Array { tokens: claimed }, Token::Array { tokens: deleted }] => {
+                        let options = XClaimOptions {
+                            just_id: options.just_id,
+                            ..XClaimOptions::default()
+                        };
+                        (claimed.as_slice(), deleted.as_slice(), options)
+                    }
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            Some(xclaim(request, claimed, deleted, options, now_ms)?.into())
+        }
+        ("ext.cas", Token::Integer { data: 0 }) => None,
+        ("spop" | "bzpopmin" | "bzpopmax" | "xadd" | "xclaim" | "xautoclaim", _) => None,
+        _ => Some(request.into()),
+    }
+}
+
+/// The bytes of a string in a reply.
+fn bytes(token: &Token) -> Option<Vec<u8>> {
+    match token {
+        Token::BulkBytes { data } => Some(data.clone()),
+        token => token.extract().map(|data| data.as_bytes().to_vec()),
+    }
+}
+
+/// `SET` with its `EX` or `PX` TTL, if any, turned into the Unix time `PXAT` it runs out at.
+fn absolute_ttl(request: &[Vec<u8>], now_ms: u64) -> Cow<'_, [Vec<u8>]> {
+    // The options come in pairs after the key and the value, and the last TTL wins.
+    let ttl = request
+        .iter()
+        .enumerate()
+        .skip(3)
+        .step_by(2)
+        .rev()
+        .find_map(|(at, option)| {
+            let scale = match option.to_ascii_lowercase().as_slice() {
+                b"px" => 1,
+                b"ex" => 1000,
+                _ => return None,
+            };
+            let ttl: u64 = std::str::from_utf8(request.get(at + 1)?)
+                .ok()?
+                .parse()
+                .ok()?;
+            Some((at, ttl.saturating_mul(scale)))
+        });
+    let Some((at, ttl)) = ttl else {
+        return request.into();
+    };
+    let mut effect = request.to_vec();
+    effect[at] = b"PXAT".to_vec();
+    effect[at + 1] = now_ms.saturating_add(ttl).to_string().into_bytes();
+    effect.into()
+}
+
+/// The `XCLAIM` that hands the `claimed` entries to the same consumer on a replica, and drops
+/// the `deleted` ones from the PEL, as `request` did on the master with the given `options`.
+/// `FORCE` with no minimum idle time makes it claim them whether they are idle there or not.
+fn xclaim(
+    request: &[Vec<u8>],
+    claimed: &[Token],
+    deleted: &[Token],
+    options: XClaimOptions,
+    now_ms: u64,
+) -> Option<Vec<Vec<u8>>> {
+    // With `JUSTID` the entries are just their IDs, otherwise their IDs and fields.
+    let id = |entry: &Token| match entry {
+        Token::Array { tokens } => bytes(tokens.first()?),
+        entry => bytes(entry),
+    };
+    let ids = claimed
+        .iter()
+        .chain(deleted)
+        .map(id)
+        .collect::<Option<Vec<_>>>()?;
+    if ids.is_empty() {
+        return None;
+    }
+    let time = options
+        .time
+        .unwrap_or_else(|| now_ms.saturating_sub(options.idle.unwrap_or(0)));
+    let mut effect = vec![b"XCLAIM".to_vec()];
+    effect.extend_from_slice(&request[1..4]);
+    effect.push(b"0".to_vec());
+    effect.extend(ids);
+    let time = time.to_string().into_bytes();
+    effect.extend([b"TIME".to_vec(), time, b"FORCE".to_vec()]);
+    if let Some(retry_count) = options.retry_count {
+        let retry_count = retry_count.to_string().into_bytes();
+        effect.extend([b"RETRYCOUNT".to_vec(), retry_count]);
+    }
+    // Without it, the claim counts as a delivery on the replica just as it did on the master.
+    if options.just_id {
+        effect.push(b"JUSTID".to_vec());
+    }
+    if let Some(last_id) = options.last_id {
+        effect.extend([b"LASTID".to_vec(), last_id.to_string().into_bytes()]);
+    }
+    Some(effect)
+}
+
+/// How long to wait before the next attempt to reconnect to the master, after waiting `delay`.
+pub fn backoff(delay: Duration) -> Duration {
+    (delay * 2).clamp(MIN_RECONNECT_DELAY, MAX_RECONNECT_DELAY)
+}
+
+/// Whether a replica serves `command` while the link to its master is down, even if it may
+/// not serve stale data: only the commands that don't touch the dataset, like `INFO`, do.
+pub const fn serves_stale(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Ping { .. }
+            | Command::Echo { .. }
+            | Command::Hello { .. }
+            | Command::Quit
+            | Command::Reset
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::Info { .. }
+            | Command::Role
+            | Command::ReplicaOf { .. }
+            | Command::ConfigGet { .. }
+            | Command::ConfigSet { .. }
+            | Command::Shutdown { .. }
+    )
+}
+
+/// Generate a random replication ID: 40 hexadecimal characters, like in Redis.
+fn new_replid() -> String {
+    let mut rng = Rng::new();
+    let (high, middle, low) = (rng.next_u64(), rng.next_u64(), rng.next_u64() as u32);
+    format!("{high:016x}{middle:016x}{low:08x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replica, ReplicaOf, Replication, MIN_RECONNECT_DELAY};
+    use crate::resp::{Protocol, Token};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn replica_of() {
+        let master = ReplicaOf::parse("localhost 6379").unwrap();
+        assert_eq!(master.host, "localhost");
+        assert_eq!(master.port, 6379);
+        assert_eq!(master.to_string(), "localhost 6379");
+        assert!(ReplicaOf::parse("localhost").is_err());
+        assert!(ReplicaOf::parse("localhost port").is_err());
+
+        let replication = Replication::new(Some(master));
+        assert!(replication.info().contains("master_link_status:down\r\n"));
+        replication.synced("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string(), 100);
+        replication.processed(14);
+        let info = replication.info();
+        assert!(info.contains("role:slave\r\nmaster_host:localhost\r\nmaster_port:6379\r\n"));
+        assert!(info.contains("master_link_status:up\r\nslave_repl_offset:114\r\n"));
+        assert!(info.contains("master_replid:8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb\r\n"));
+        assert_eq!(
+            replication.role().encode(Protocol::Resp2),
+            "*5\r\n$5\r\nslave\r\n$9\r\nlocalhost\r\n:6379\r\n$9\r\nconnected\r\n:114\r\n"
+        );
+
+        let masters = replication.masters();
+        assert!(!replication.set_master(replication.master()));
+        assert!(!masters.has_changed().unwrap());
+        assert!(replication.set_master(None));
+        assert!(masters.has_changed().unwrap());
+        assert!(!replication.is_replica());
+        let info = replication.info();
+        assert!(info.contains("role:master\r\n"), "{info}");
+        assert!(info.contains("master_repl_offset:114\r\n"), "{info}");
+        assert!(
+            !info.contains("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"),
+            "{info}"
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let delays: Vec<_> = std::iter::successors(Some(MIN_RECONNECT_DELAY), |&delay| {
+            Some(super::backoff(delay))
+        })
+        .take(8)
+        .map(|delay| delay.as_millis())
+        .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1600, 3200, 5000, 5000]);
+    }
+
+    #[test]
+    fn registered_replicas() {
+        let replication = Replication::new(None);
+        let info = replication.info();
+        assert!(
+            info.contains("role:master\r\nconnected_slaves:0\r\n"),
+            "{info}"
+        );
+        assert!(info.contains("master_repl_offset:0\r\n"), "{info}");
+
+        let (messages, mut received) = mpsc::unbounded_channel();
+        let replica = Replica::new(7, IpAddr::V4(Ipv4Addr::LOCALHOST), Some(6380), messages);
+        let (replid, offset) = replication.register(replica);
+        assert_eq!(replid.len(), 40);
+        assert!(replid.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(offset, 0);
+        let info = replication.info();
+        assert!(info.contains("connected_slaves:1\r\n"), "{info}");
+        assert!(
+            info.contains("slave0:ip=127.0.0.1,port=6380,state=online,offset=0,lag=0\r\n"),
+            "{info}"
+        );
+        assert!(
+            info.contains(&format!("master_replid:{replid}\r\n")),
+            "{info}"
+        );
+
+        replication.propagate(&[b"SET".to_vec(), b"foo".to_vec(), b"1".to_vec()]);
+        assert_eq!(
+            received.try_recv().unwrap().encode(Protocol::Resp2),
+            "*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$1\r\n1\r\n"
+        );
+        assert!(replication.info().contains("master_repl_offset:29\r\n"));
+        assert_eq!(replication.acked(29), 0);
+        let acks = replication.acks();
+        replication.ack(7, 29);
+        assert!(acks.has_changed().unwrap());
+        assert_eq!(replication.acked(29), 1);
+        assert_eq!(replication.good_replicas(Duration::from_secs(10)), 1);
+        assert!(replication.info().contains("offset=29,lag=0\r\n"));
+        assert_eq!(
+            replication.role().encode(Protocol::Resp2),
+            "*3\r\n$6\r\nmaster\r\n:29\r\n*1\r\n*3\r\n$9\r\n127.0.0.1\r\n$4\r\n6380\r\n$2\r\n29\r\n"
+        );
+
+        replication.unregister(8);
+        assert!(replication.info().contains("connected_slaves:1\r\n"));
+        replication.unregister(7);
+        assert!(replication.info().contains("connected_slaves:0\r\n"));
+    }
+
+    #[test]
+    fn effects() {
+        let request = |args: &[&str]| {
+            args.iter()
+                .map(|arg| arg.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        let effect = |args: &[&str], reply: Token| {
+            super::effect(&request(args), &[reply], 1000).map(|effect| effect.into_owned())
+        };
+        let set = request(&["SET", "foo", "bar"]);
+        assert_eq!(
+            effect(&["SET", "foo", "bar"], Token::from("OK".to_string())),
+            Some(set)
+        );
+
+        let popped = Token::from(vec![
+            Token::from("a".to_string()),
+            Token::from("b".to_string()),
+        ]);
+        let srem = request(&["SREM", "fruits", "a", "b"]);
+        assert_eq!(effect(&["SPOP", "fruits", "2"], popped), Some(srem));
+        let popped = Token::BulkString { data: "a".into() };
+        let srem = request(&["SREM", "fruits", "a"]);
+        assert_eq!(effect(&["spop", "fruits"], popped), Some(srem));
+        assert_eq!(effect(&["SPOP", "fruits"], Token::NullBulkString), None);
+        assert_eq!(
+            effect(&["SPOP", "fruits", "2"], Token::Array { tokens: vec![] }),
+            None
+        );
+
+        let popped = Token::from(vec![
+            Token::from("second".to_string()),
+            Token::from("a".to_string()),
+            Token::from("1".to_string()),
+        ]);
+        let zpopmin = request(&["ZPOPMIN", "second"]);
+        assert_eq!(
+            effect(&["BZPOPMIN", "first", "second", "0"], popped),
+            Some(zpopmin)
+        );
+        assert_eq!(effect(&["BZPOPMAX", "first", "0"], Token::NullArray), None);
+
+        let id = Token::BulkString { data: "5-0".into() };
+        let xadd = request(&[
+            "XADD", "berry", "MAXLEN", "~", "10", "5-0", "*", "x", "y", "z",
+        ]);
+        assert_eq!(
+            effect(
+                &["XADD", "berry", "MAXLEN", "~", "10", "*", "*", "x", "y", "z"],
+                id
+            ),
+            Some(xadd)
+        );
+        let nomkstream = ["XADD", "berry", "NOMKSTREAM", "*", "x", "y"];
+        assert_eq!(effect(&nomkstream, Token::NullBulkString), None);
+
+        let ok = || Token::SimpleString { data: "OK".into() };
+        let set = request(&["SET", "foo", "bar", "PXAT", "1100"]);
+        assert_eq!(effect(&["SET", "foo", "bar", "PX", "100"], ok()), Some(set));
+        let set = request(&["SET", "foo", "bar", "PXAT", "3000", "IDLETIME", "5"]);
+        let ex = ["SET", "foo", "bar", "ex", "2", "IDLETIME", "5"];
+        assert_eq!(effect(&ex, ok()), Some(set));
+        let set = request(&["SET", "lock", "b", "PXAT", "1500"]);
+        let cas = ["EXT.CAS", "lock", "a", "b", "PX", "500"];
+        assert_eq!(effect(&cas, Token::from(true)), Some(set));
+        assert_eq!(effect(&cas, Token::from(false)), None);
+        let cas = ["EXT.CAS", "lock", "a", "b"];
+        assert_eq!(effect(&cas, Token::from(true)), Some(request(&cas)));
+
+        let claimed = Token::from(vec![Token::from("1-0".to_string())]);
+        let xclaim = request(&[
+            "XCLAIM", "berry", "g", "alice", "0", "1-0", "TIME", "700", "FORCE", "JUSTID",
+        ]);
+        assert_eq!(
+            effect(
+                &["XCLAIM", "berry", "g", "alice", "10", "1-0", "2-0", "IDLE", "300", "JUSTID"],
+                claimed
+            ),
+            Some(xclaim)
+        );
+        let entry = Token::from(vec![
+            Token::from("1-0".to_string()),
+            Token::from(vec![
+                Token::from("x".to_string()),
+                Token::from("y".to_string()),
+            ]),
+        ]);
+        let claimed = Token::from(vec![
+            Token::from("0-0".to_string()),
+            Token::from(vec![entry]),
+            Token::from(vec![Token::from("2-0".to_string())]),
+        ]);
+        let xclaim = request(&[
+            "XCLAIM", "berry", "g", "alice", "0", "1-0", "2-0", "TIME", "1000", "FORCE",
+        ]);
+        let xautoclaim = ["XAUTOCLAIM", "berry", "g", "alice", "10", "0"];
+        assert_eq!(effect(&xautoclaim, claimed), Some(xclaim));
+        let nothing = Token::from(vec![
+            Token::from("0-0".to_string()),
+            Token::from(Vec::<Token>::new()),
+            Token::from(Vec::<Token>::new()),
+        ]);
+        assert_eq!(effect(&xautoclaim, nothing), None);
+    }
+}
@@ -4,7 +4,8 @@
 //! Redis Serialization Protocol (RESP). While the protocol was designed specifically
 //! for Redis, you can use it for other client-server software projects.
 
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Display, Formatter, Write};
+use std::ops::Range;
 
 /// Possible errors that can arise during [`&str`] to [`Token`] translation.
 #[derive(Debug, Clone, thiserror::Error)]
@@ -13,15 +14,57 @@ pub enum ParseError {
     IncompleteMessage,
     #[error("Unknown RESP type: {0:?}")]
     UnknownType(char),
+    #[error("Malformed RESP message")]
+    Malformed,
 }
 
 pub const CRLF: &str = "\r\n";
 pub const SIMPLE_STRING_START: char = '+';
+pub const SIMPLE_ERROR_START: char = '-';
+pub const INTEGER_START: char = ':';
 pub const BULK_STRING_START: char = '$';
 pub const ARRAY_START: char = '*';
+pub const NULL_START: char = '_';
+pub const DOUBLE_START: char = ',';
+pub const MAP_START: char = '%';
+pub const PUSH_START: char = '>';
+
+/// Bulk strings at least this long get written straight out of their [`Token`], see [`Vectored`].
+const VECTORED_MIN_LEN: usize = 512;
+
+/// Versions of the protocol that a client can negotiate with `HELLO`.
+///
+/// Every connection starts out speaking RESP2. RESP3 adds more semantic types,
+/// like maps, doubles and out-of-band push data, which get encoded using the
+/// closest RESP2 type for clients that did not opt in (see [`Token::downgrade`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    /// Get the [`Protocol`] with the given version number, as passed to `HELLO`.
+    pub const fn from_version(version: i64) -> Option<Self> {
+        match version {
+            2 => Some(Self::Resp2),
+            3 => Some(Self::Resp3),
+            _ => None,
+        }
+    }
+
+    /// The version number of this [`Protocol`].
+    pub const fn version(self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        }
+    }
+}
 
 /// Known RESP tokens.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     /// RESP Simple strings are encoded as a plus (`+`) character,
     /// followed by a string. The string mustn't contain a CR (`\r`)
@@ -29,6 +72,18 @@ pub enum Token {
     ///
     /// Format: `+<data>\r\n`
     SimpleString { data: String },
+    /// RESP has specific data types for errors. Simple errors, or simply just errors,
+    /// are similar to simple strings, but their first character is the minus (`-`)
+    /// character. By convention, the first uppercase word after the `-` is the
+    /// error kind, e.g. `WRONGTYPE` or `ERR`.
+    ///
+    /// Format: `-<data>\r\n`
+    SimpleError { data: String },
+    /// This type is a CRLF-terminated string that represents a signed,
+    /// base-10, 64-bit integer.
+    ///
+    /// Format: `:[<+|->]<value>\r\n`
+    Integer { data: i64 },
     /// A bulk string represents a single binary string.
     /// The string can be of any size, but by default, Redis limits it to 512 MB.
     ///
@@ -51,6 +106,15 @@ pub enum Token {
     /// `$0\r\n\r\n`
     ///
     BulkString { data: String },
+    /// A bulk string of arbitrary bytes, like the value of a string key, which need not be
+    /// valid UTF-8. [`Vectored`] and [`Token::to_bytes`] write the bytes as they are, but
+    /// [`Token::encode`] makes a [`String`], so any invalid UTF-8 gets replaced there.
+    BulkBytes { data: Vec<u8> },
+    /// The null bulk string represents a non-existing value,
+    /// e.g. the reply to `GET` for a key that does not exist.
+    ///
+    /// Format: `$-1\r\n`
+    NullBulkString,
     /// RESP Arrays' encoding uses the following format:
     ///
     /// `*<number-of-elements>\r\n<element-1>...<element-n>`
@@ -64,43 +128,405 @@ pub enum Token {
     ///
     /// `*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n`
     Array { tokens: Vec<Token> },
+    /// The null array, e.g. the reply to a blocking command that timed out.
+    ///
+    /// Format: `*-1\r\n`
+    NullArray,
+    /// RESP3 maps are collections of key-value pairs:
+    ///
+    /// `%<number-of-entries>\r\n<key-1><value-1>...<key-n><value-n>`
+    ///
+    /// RESP2 has no maps, so they are sent as flat arrays of alternating keys and values.
+    Map { pairs: Vec<(Token, Token)> },
+    /// RESP3 pushes carry out-of-band data, like Pub/Sub messages, which the
+    /// server may send at any time, not only as a reply to a command:
+    ///
+    /// `><number-of-elements>\r\n<element-1>...<element-n>`
+    ///
+    /// RESP2 has no pushes, so they are sent as plain arrays.
+    #[allow(dead_code)]
+    Push { tokens: Vec<Token> },
+    /// RESP3 doubles are floating point numbers, including `inf`, `-inf` and `nan`:
+    ///
+    /// `,<value>\r\n`
+    ///
+    /// RESP2 has no doubles, so they are sent as bulk strings.
+    Double { data: f64 },
 }
 
+// The only doubles ever sent are sorted set scores, which are never `NaN`.
+impl Eq for Token {}
+
 impl Token {
     /// Get a slice of the contained [`String`], if any.
     pub fn extract(&self) -> Option<&str> {
-        use Token::{Array, BulkString, SimpleString};
+        use Token::{
+            Array, BulkBytes, BulkString, Double, Integer, Map, NullArray, NullBulkString, Push,
+            SimpleError, SimpleString,
+        };
+        match self {
+            SimpleString { data } | SimpleError { data } | BulkString { data } => Some(data),
+            BulkBytes { data } => std::str::from_utf8(data).ok(),
+            Integer { .. }
+            | NullBulkString
+            | Array { .. }
+            | NullArray
+            | Map { .. }
+            | Push { .. }
+            | Double { .. } => None,
+        }
+    }
+
+    /// Get the closest RESP2 equivalent of a RESP3-only [`Token`], or [`None`]
+    /// if this [`Token`] can be sent to RESP2 clients as is.
+    ///
+    /// | RESP3  | RESP2                                          |
+    /// |--------|------------------------------------------------|
+    /// | Map    | Flat array of alternating keys and values      |
+    /// | Double | Bulk string, formatted like `INCRBYFLOAT` does |
+    /// | Push   | Array                                          |
+    ///
+    /// Only the outermost [`Token`] is downgraded, nested ones are left as they
+    /// are and get downgraded in turn when they are encoded.
+    pub fn downgrade(&self) -> Option<Self> {
+        match self {
+            Self::Map { pairs } => Some(Self::Array {
+                tokens: pairs
+                    .iter()
+                    .flat_map(|(key, value)| [key.clone(), value.clone()])
+                    .collect(),
+            }),
+            Self::Double { data } => Some(Self::BulkString {
+                data: format_double(*data),
+            }),
+            Self::Push { tokens } => Some(Self::Array {
+                tokens: tokens.clone(),
+            }),
+            Self::SimpleString { .. }
+            | Self::SimpleError { .. }
+            | Self::Integer { .. }
+            | Self::BulkString { .. }
+            | Self::BulkBytes { .. }
+            | Self::NullBulkString
+            | Self::Array { .. }
+            | Self::NullArray => None,
+        }
+    }
+
+    /// Encode this [`Token`] for a client speaking the given [`Protocol`].
+    ///
+    /// The [`Display`] implementation is the same as encoding for [`Protocol::Resp2`].
+    pub fn encode(&self, protocol: Protocol) -> String {
+        let mut encoded = String::new();
+        // Writing into a `String` never fails.
+        let _ = self.write(&mut encoded, protocol);
+        encoded
+    }
+
+    /// Encode this [`Token`] like [`Token::encode`] does, but with the bytes of bulk
+    /// strings as they are, for when they have to get across whole.
+    pub fn to_bytes(&self, protocol: Protocol) -> Vec<u8> {
+        let mut encoded = Vectored::default();
+        encoded.push(self, protocol);
+        encoded.slices().concat()
+    }
+
+    fn write(&self, out: &mut impl fmt::Write, protocol: Protocol) -> fmt::Result {
+        if protocol == Protocol::Resp2 {
+            if let Some(downgraded) = self.downgrade() {
+                return downgraded.write(out, protocol);
+            }
+        }
         match self {
-            SimpleString { data } | BulkString { data } => Some(data),
-            Array { .. } => None,
+            Self::SimpleString { data } => write!(out, "{SIMPLE_STRING_START}{data}{CRLF}"),
+            Self::SimpleError { data } => write!(out, "{SIMPLE_ERROR_START}{data}{CRLF}"),
+            Self::Integer { data } => write!(out, "{INTEGER_START}{data}{CRLF}"),
+            Self::BulkString { data } => {
+                let len = data.len();
+                write!(out, "{BULK_STRING_START}{len}{CRLF}{data}{CRLF}")
+            }
+            Self::BulkBytes { data } => {
+                let len = data.len();
+                let data = String::from_utf8_lossy(data);
+                write!(out, "{BULK_STRING_START}{len}{CRLF}{data}{CRLF}")
+            }
+            Self::NullBulkString | Self::NullArray if protocol == Protocol::Resp3 => {
+                write!(out, "{NULL_START}{CRLF}")
+            }
+            Self::NullBulkString => write!(out, "{BULK_STRING_START}-1{CRLF}"),
+            Self::NullArray => write!(out, "{ARRAY_START}-1{CRLF}"),
+            Self::Array { tokens } => {
+                write!(out, "{ARRAY_START}{count}{CRLF}", count = tokens.len())?;
+                tokens
+                    .iter()
+                    .try_for_each(|token| token.write(out, protocol))
+            }
+            Self::Push { tokens } => {
+                write!(out, "{PUSH_START}{count}{CRLF}", count = tokens.len())?;
+                tokens
+                    .iter()
+                    .try_for_each(|token| token.write(out, protocol))
+            }
+            Self::Map { pairs } => {
+                write!(out, "{MAP_START}{count}{CRLF}", count = pairs.len())?;
+                pairs.iter().try_for_each(|(key, value)| {
+                    key.write(out, protocol)?;
+                    value.write(out, protocol)
+                })
+            }
+            Self::Double { data } => {
+                write!(
+                    out,
+                    "{DOUBLE_START}{data}{CRLF}",
+                    data = format_double(*data)
+                )
+            }
+        }
+    }
+}
+
+/// Format a double the way Redis does: `inf`, `-inf` and `nan` for the special
+/// values, the shortest representation that round-trips for everything else.
+fn format_double(data: f64) -> String {
+    if data.is_nan() {
+        String::from("nan")
+    } else {
+        // Normalize `-0` to `0`.
+        (data + 0.0).to_string()
+    }
+}
+
+impl From<String> for Token {
+    fn from(data: String) -> Self {
+        Self::BulkString { data }
+    }
+}
+
+impl From<i64> for Token {
+    fn from(data: i64) -> Self {
+        Self::Integer { data }
+    }
+}
+
+impl From<usize> for Token {
+    fn from(count: usize) -> Self {
+        Self::Integer {
+            data: i64::try_from(count).unwrap_or(i64::MAX),
         }
     }
 }
 
+impl From<bool> for Token {
+    fn from(flag: bool) -> Self {
+        Self::Integer {
+            data: i64::from(flag),
+        }
+    }
+}
+
+impl<T: Into<Self>> From<Option<T>> for Token {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Self::NullBulkString, Into::into)
+    }
+}
+
+impl<T: Into<Self>> From<Vec<T>> for Token {
+    fn from(items: Vec<T>) -> Self {
+        Self::Array {
+            tokens: items.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Token {
+    /// Decode a single [`Token`] from the start of `bytes`, which may hold more data after it.
+    ///
+    /// Returns the [`Token`] along with the number of bytes it spans, or [`None`] if
+    /// `bytes` does not hold a complete [`Token`] yet, so more data has to be read.
+    /// Null bulk strings (`$-1`) and the RESP3 null (`_`) decode into a
+    /// [`Token::NullBulkString`], null arrays (`*-1`) into a [`Token::NullArray`].
+    pub fn decode(bytes: &[u8]) -> Result<Option<(Self, usize)>, ParseError> {
+        let Some(line_end) = bytes.windows(2).position(|window| window == CRLF.as_bytes()) else {
+            return Ok(None);
+        };
+        // A bare CRLF has no type at all, which makes it of an unknown one.
+        let line = bytes.get(1..line_end).unwrap_or_default();
+        let line = std::str::from_utf8(line).map_err(|_| ParseError::Malformed)?;
+        let header_len = line_end + CRLF.len();
+        let length = || line.parse::<i64>().map_err(|_| ParseError::Malformed);
+        let token = match char::from(bytes[0]) {
+            SIMPLE_STRING_START => Self::SimpleString {
+                data: line.to_string(),
+            },
+            SIMPLE_ERROR_START => Self::SimpleError {
+                data: line.to_string(),
+            },
+            INTEGER_START => Self::Integer { data: length()? },
+            NULL_START => Self::NullBulkString,
+            DOUBLE_START => Self::Double {
+                data: match line {
+                    "inf" => f64::INFINITY,
+                    "-inf" => f64::NEG_INFINITY,
+                    "nan" => f64::NAN,
+                    _ => line.parse().map_err(|_| ParseError::Malformed)?,
+                },
+            },
+            BULK_STRING_START if length()? == -1 => Self::NullBulkString,
+            ARRAY_START if length()? == -1 => Self::NullArray,
+            BULK_STRING_START => {
+                let len = usize::try_from(length()?).map_err(|_| ParseError::Malformed)?;
+                let Some(data) = bytes.get(header_len..header_len + len + CRLF.len()) else {
+                    return Ok(None);
+                };
+                if !data.ends_with(CRLF.as_bytes()) {
+                    return Err(ParseError::Malformed);
+                }
+                let token = match String::from_utf8(data[..len].to_vec()) {
+                    Ok(data) => Self::BulkString { data },
+                    Err(err) => Self::BulkBytes {
+                        data: err.into_bytes(),
+                    },
+                };
+                return Ok(Some((token, header_len + len + CRLF.len())));
+            }
+            ARRAY_START | PUSH_START | MAP_START => {
+                let count = usize::try_from(length()?).map_err(|_| ParseError::Malformed)?;
+                let elements = if bytes[0] == MAP_START as u8 {
+                    count * 2
+                } else {
+                    count
+                };
+                let mut tokens = Vec::with_capacity(elements);
+                let mut consumed = header_len;
+                for _ in 0..elements {
+                    let Some((token, len)) = Self::decode(&bytes[consumed..])? else {
+                        return Ok(None);
+                    };
+                    tokens.push(token);
+                    consumed += len;
+                }
+                let token = match char::from(bytes[0]) {
+                    PUSH_START => Self::Push { tokens },
+                    MAP_START => {
+                        let mut tokens = tokens.into_iter();
+                        let pairs = std::iter::from_fn(|| Some((tokens.next()?, tokens.next()?)));
+                        Self::Map {
+                            pairs: pairs.collect(),
+                        }
+                    }
+                    _ => Self::Array { tokens },
+                };
+                return Ok(Some((token, consumed)));
+            }
+            unknown_type => return Err(ParseError::UnknownType(unknown_type)),
+        };
+        Ok(Some((token, header_len)))
+    }
+}
+
+/// Decode a request in the shape that virtually every client sends: an array of bulk strings.
+///
+/// This is the fast path for requests, which goes straight from bytes to arguments without
+/// building a tree of [`Token`]s, and validates every length along the way. Like
+/// [`Token::decode`], it decodes the request at the start of `bytes` and tells how many bytes
+/// it spans. Returns [`None`] if `bytes` don't start with a complete such array, in which
+/// case the request has to go through the general parser instead.
+pub fn decode_bulk_array(bytes: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+    let (count, mut rest) = length_line(bytes.strip_prefix(&[ARRAY_START as u8])?)?;
+    // Every bulk string takes at least 6 bytes (`$0\r\n\r\n`), which bounds the allocation.
+    let mut args = Vec::with_capacity(count.min(rest.len() / 6));
+    for _ in 0..count {
+        let (len, tail) = length_line(rest.strip_prefix(&[BULK_STRING_START as u8])?)?;
+        let data = tail.get(..len)?;
+        rest = tail[len..].strip_prefix(CRLF.as_bytes())?;
+        args.push(data.to_vec());
+    }
+    Some((args, bytes.len() - rest.len()))
+}
+
+/// Whether `bytes` start with an inline request rather than with a RESP [`Token`].
+pub fn is_inline(bytes: &[u8]) -> bool {
+    let starts = [
+        SIMPLE_STRING_START,
+        SIMPLE_ERROR_START,
+        INTEGER_START,
+        BULK_STRING_START,
+        ARRAY_START,
+        NULL_START,
+        DOUBLE_START,
+        MAP_START,
+        PUSH_START,
+    ];
+    bytes
+        .first()
+        .is_some_and(|&byte| !starts.contains(&char::from(byte)))
+}
+
+/// Decode an inline request, as typed into `telnet`: words separated by spaces, up to the
+/// end of the line. Like [`decode_bulk_array`], it tells how many bytes the request spans,
+/// or returns [`None`] if the line is not complete yet.
+pub fn decode_inline(bytes: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+    let end = bytes.iter().position(|&byte| byte == b'\n')?;
+    let args = bytes[..end]
+        .split(u8::is_ascii_whitespace)
+        .filter(|word| !word.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect();
+    Some((args, end + 1))
+}
+
+/// Parse the decimal length that `bytes` start with, up to the terminating CRLF.
+///
+/// Returns the length along with the bytes that follow the CRLF.
+fn length_line(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    let digits = bytes
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    if digits == 0 {
+        return None;
+    }
+    let length = bytes[..digits].iter().try_fold(0_usize, |length, digit| {
+        length
+            .checked_mul(10)?
+            .checked_add(usize::from(digit - b'0'))
+    })?;
+    Some((length, bytes[digits..].strip_prefix(CRLF.as_bytes())?))
+}
+
 impl TryFrom<&str> for Token {
     type Error = ParseError;
 
     fn try_from(str: &str) -> Result<Self, Self::Error> {
         let str = str.trim_matches('\0');
         let is_array = str.starts_with(ARRAY_START);
-        let mut parts = str
-            .split(CRLF)
-            .filter(|part| !part.is_empty() && !part.starts_with(ARRAY_START))
-            .peekable();
+        let mut parts = str.split(CRLF);
 
         let mut tokens: Vec<Self> = vec![];
         while let Some(str) = parts.next() {
+            // Array headers and blank lines are skipped here, but not as the payload
+            // of a bulk string, which may well be empty or start with an `*`, as in `XADD s * a 1`.
+            if str.is_empty() || str.starts_with(ARRAY_START) {
+                continue;
+            }
             match str.chars().next().ok_or(ParseError::IncompleteMessage)? {
-                BULK_STRING_START => {
-                    tokens.push(Self::BulkString {
-                        // HACK: Clippy suggested some dereference magic for a faster `to_string()`.
-                        data: (*parts.peek().ok_or(ParseError::IncompleteMessage)?).to_string(),
-                    });
-                    parts.next(); // Don't handle the bulk string twice.
-                }
+                BULK_STRING_START => tokens.push(Self::BulkString {
+                    data: parts
+                        .next()
+                        .ok_or(ParseError::IncompleteMessage)?
+                        .to_string(),
+                }),
                 SIMPLE_STRING_START => tokens.push(Self::SimpleString {
                     data: str[1..].to_string(),
                 }),
+                SIMPLE_ERROR_START => tokens.push(Self::SimpleError {
+                    data: str[1..].to_string(),
+                }),
+                INTEGER_START => tokens.push(Self::Integer {
+                    data: str[1..]
+                        .parse()
+                        .map_err(|_| ParseError::IncompleteMessage)?,
+                }),
                 unknown_type => return Err(ParseError::UnknownType(unknown_type)),
             }
         }
@@ -113,25 +539,108 @@ impl TryFrom<&str> for Token {
     }
 }
 
-impl Display for Token {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Token::SimpleString { data } => write!(f, "+{data}{CRLF}")?,
-            Token::BulkString { data } => write!(f, "${len}{CRLF}{data}{CRLF}", len = data.len())?,
-            Token::Array { tokens } => {
-                write!(f, "*{count}{CRLF}", count = tokens.len())?;
-                for token in tokens.iter() {
-                    write!(f, "{token}")?;
+/// Replies encoded for a single vectored write (`writev`).
+///
+/// Large bulk strings are borrowed from the [`Token`]s instead of being copied into
+/// one contiguous buffer, so that only the framing around them and the small values
+/// get copied, which saves a lot of memory traffic on replies like big `MGET`s.
+/// Bulk strings of bytes are always borrowed, as the buffer only holds UTF-8.
+#[derive(Debug, Default)]
+pub struct Vectored<'a> {
+    buffer: String,
+    pieces: Vec<Piece<'a>>,
+    /// How much of the `buffer` already belongs to the `pieces`.
+    flushed: usize,
+}
+
+#[derive(Debug)]
+enum Piece<'a> {
+    Buffered(Range<usize>),
+    Borrowed(&'a [u8]),
+}
+
+impl<'a> Vectored<'a> {
+    /// Encode `token` after whatever was encoded so far, for a client speaking `protocol`.
+    pub fn push(&mut self, token: &'a Token, protocol: Protocol) {
+        // Writing into a `String` never fails.
+        match token {
+            Token::BulkString { data } if data.len() >= VECTORED_MIN_LEN => {
+                self.borrow(data.as_bytes());
+            }
+            Token::BulkBytes { data } => self.borrow(data),
+            Token::Array { tokens } | Token::Push { tokens } => {
+                let start = match token {
+                    Token::Push { .. } if protocol == Protocol::Resp3 => PUSH_START,
+                    _ => ARRAY_START,
+                };
+                let _ = write!(self.buffer, "{start}{}{CRLF}", tokens.len());
+                tokens.iter().for_each(|token| self.push(token, protocol));
+            }
+            Token::Map { pairs } => {
+                // Downgrading a map flattens it into an array of twice the length.
+                let _ = match protocol {
+                    Protocol::Resp2 => {
+                        write!(self.buffer, "{ARRAY_START}{}{CRLF}", pairs.len() * 2)
+                    }
+                    Protocol::Resp3 => write!(self.buffer, "{MAP_START}{}{CRLF}", pairs.len()),
+                };
+                for (key, value) in pairs {
+                    self.push(key, protocol);
+                    self.push(value, protocol);
                 }
             }
-        };
-        Ok(())
+            _ => {
+                let _ = token.write(&mut self.buffer, protocol);
+            }
+        }
+    }
+
+    /// Encode a bulk string of `data`, borrowing the data instead of copying it.
+    fn borrow(&mut self, data: &'a [u8]) {
+        // Writing into a `String` never fails.
+        let _ = write!(self.buffer, "{BULK_STRING_START}{}{CRLF}", data.len());
+        self.flush();
+        self.pieces.push(Piece::Borrowed(data));
+        self.buffer.push_str(CRLF);
+    }
+
+    /// Move the newly encoded part of the `buffer` into the `pieces`.
+    fn flush(&mut self) {
+        if self.flushed < self.buffer.len() {
+            self.pieces
+                .push(Piece::Buffered(self.flushed..self.buffer.len()));
+            self.flushed = self.buffer.len();
+        }
+    }
+
+    /// The encoded replies, in the order in which they have to be written.
+    pub fn slices(&self) -> Vec<&[u8]> {
+        let tail =
+            (self.flushed < self.buffer.len()).then(|| &self.buffer.as_bytes()[self.flushed..]);
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Buffered(range) => &self.buffer.as_bytes()[range.clone()],
+                Piece::Borrowed(data) => *data,
+            })
+            .chain(tail)
+            .collect()
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write(f, Protocol::Resp2)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Token::{self, Array, BulkString, SimpleString};
+    use super::Protocol::{Resp2, Resp3};
+    use super::Token::{
+        self, Array, BulkString, Double, Integer, Map, Push, SimpleError, SimpleString,
+    };
+    use super::{decode_bulk_array, decode_inline, is_inline, Vectored};
 
     #[test]
     fn simple_string_pong() {
@@ -159,6 +668,27 @@ mod tests {
         assert_eq!(token.to_string(), RESP);
     }
 
+    #[test]
+    fn simple_error_wrongtype() {
+        const RESP: &str = "-WRONGTYPE Operation against a key\r\n";
+        let token = Token::try_from(RESP).unwrap();
+        assert_eq!(
+            token,
+            SimpleError {
+                data: String::from("WRONGTYPE Operation against a key")
+            }
+        );
+        assert_eq!(token.to_string(), RESP);
+    }
+
+    #[test]
+    fn integer_negative() {
+        const RESP: &str = ":-42\r\n";
+        let token = Token::try_from(RESP).unwrap();
+        assert_eq!(token, Integer { data: -42 });
+        assert_eq!(token.to_string(), RESP);
+    }
+
     #[test]
     fn bulk_string_hello() {
         const RESP: &str = "$5\r\nhello\r\n";
@@ -172,6 +702,21 @@ mod tests {
         assert_eq!(token.to_string(), RESP);
     }
 
+    #[test]
+    fn bulk_string_payload_looking_like_a_header() {
+        let token = Token::try_from("*3\r\n$1\r\n*\r\n$0\r\n\r\n$1\r\na\r\n").unwrap();
+        assert_eq!(
+            token,
+            Array {
+                tokens: vec![
+                    BulkString { data: "*".into() },
+                    BulkString { data: "".into() },
+                    BulkString { data: "a".into() },
+                ]
+            }
+        );
+    }
+
     #[test]
     fn bulk_string_array() {
         const RESP: &str = "*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n";
@@ -226,4 +771,350 @@ mod tests {
         );
         assert_eq!(token.to_string(), RESP);
     }
+
+    #[test]
+    fn decode_nested_arrays() {
+        const RESP: &[u8] = b"*2\r\n$1\r\n0\r\n*2\r\n$3\r\nfoo\r\n:5\r\n+extra";
+        let (token, consumed) = Token::decode(RESP).unwrap().unwrap();
+        assert_eq!(
+            token,
+            Array {
+                tokens: vec![
+                    BulkString {
+                        data: String::from("0")
+                    },
+                    Array {
+                        tokens: vec![
+                            BulkString {
+                                data: String::from("foo")
+                            },
+                            Integer { data: 5 }
+                        ]
+                    }
+                ]
+            }
+        );
+        assert_eq!(&RESP[consumed..], b"+extra");
+    }
+
+    #[test]
+    fn decode_incomplete() {
+        for partial in [&b"$5\r\nhel"[..], b"*2\r\n$1\r\na\r\n", b"+OK", b""] {
+            assert_eq!(Token::decode(partial).unwrap(), None);
+        }
+        assert!(Token::decode(b"$2\r\nabc\r\n").is_err());
+        assert!(Token::decode(b"\r\n").is_err());
+    }
+
+    #[test]
+    fn decode_bytes() {
+        let bytes = Token::BulkBytes {
+            data: b"\xff\x00".to_vec(),
+        };
+        assert_eq!(
+            Token::decode(b"$2\r\n\xff\x00\r\n").unwrap(),
+            Some((bytes, 8))
+        );
+    }
+
+    #[test]
+    fn decode_resp3() {
+        let map = Map {
+            pairs: vec![(
+                BulkString {
+                    data: String::from("proto"),
+                },
+                Integer { data: 3 },
+            )],
+        };
+        let encoded = map.encode(Resp3);
+        assert_eq!(
+            Token::decode(encoded.as_bytes()).unwrap(),
+            Some((map, encoded.len()))
+        );
+        assert_eq!(
+            Token::decode(b"_\r\n").unwrap(),
+            Some((Token::NullBulkString, 3))
+        );
+    }
+
+    #[test]
+    fn push_per_protocol() {
+        let token = Push {
+            tokens: vec![
+                BulkString {
+                    data: String::from("message"),
+                },
+                Integer { data: 1 },
+            ],
+        };
+        assert_eq!(token.encode(Resp3), ">2\r\n$7\r\nmessage\r\n:1\r\n");
+        assert_eq!(token.encode(Resp2), "*2\r\n$7\r\nmessage\r\n:1\r\n");
+    }
+
+    #[test]
+    fn map_per_protocol() {
+        let token = Map {
+            pairs: vec![(
+                BulkString {
+                    data: String::from("proto"),
+                },
+                Integer { data: 3 },
+            )],
+        };
+        assert_eq!(token.encode(Resp3), "%1\r\n$5\r\nproto\r\n:3\r\n");
+        assert_eq!(token.encode(Resp2), "*2\r\n$5\r\nproto\r\n:3\r\n");
+    }
+
+    #[test]
+    fn null_per_protocol() {
+        assert_eq!(Token::NullBulkString.encode(Resp3), "_\r\n");
+        assert_eq!(Token::NullBulkString.encode(Resp2), "$-1\r\n");
+        assert_eq!(Token::NullArray.encode(Resp3), "_\r\n");
+        assert_eq!(Token::NullArray.encode(Resp2), "*-1\r\n");
+    }
+
+    #[test]
+    fn double_per_protocol() {
+        assert_eq!(Double { data: 1.5 }.encode(Resp3), ",1.5\r\n");
+        assert_eq!(Double { data: 1.5 }.encode(Resp2), "$3\r\n1.5\r\n");
+        assert_eq!(Double { data: -0.0 }.encode(Resp2), "$1\r\n0\r\n");
+        assert_eq!(
+            Double {
+                data: f64::INFINITY
+            }
+            .encode(Resp3),
+            ",inf\r\n"
+        );
+        assert_eq!(
+            Double {
+                data: f64::NEG_INFINITY
+            }
+            .encode(Resp2),
+            "$4\r\n-inf\r\n"
+        );
+        assert_eq!(
+            Token::decode(b",-inf\r\n").unwrap(),
+            Some((
+                Double {
+                    data: f64::NEG_INFINITY
+                },
+                7
+            ))
+        );
+    }
+
+    #[test]
+    fn downgrade_matrix() {
+        let string = |data: &str| BulkString {
+            data: String::from(data),
+        };
+        let nested = Push {
+            tokens: vec![
+                string("message"),
+                Map {
+                    pairs: vec![(string("score"), Double { data: 2.0 })],
+                },
+            ],
+        };
+        assert_eq!(
+            nested.downgrade(),
+            Some(Array {
+                tokens: vec![
+                    string("message"),
+                    Map {
+                        pairs: vec![(string("score"), Double { data: 2.0 })],
+                    },
+                ]
+            })
+        );
+        // Nested tokens get downgraded too, once the encoder reaches them.
+        assert_eq!(
+            nested.encode(Resp2),
+            Array {
+                tokens: vec![
+                    string("message"),
+                    Array {
+                        tokens: vec![string("score"), string("2")]
+                    },
+                ]
+            }
+            .encode(Resp2)
+        );
+        for native in [
+            string("x"),
+            SimpleString {
+                data: String::from("OK"),
+            },
+            Integer { data: 1 },
+            Token::NullBulkString,
+            Token::NullArray,
+            Array { tokens: vec![] },
+        ] {
+            assert_eq!(native.downgrade(), None);
+            assert_eq!(native.encode(Resp2), native.to_string());
+        }
+    }
+
+    #[test]
+    fn vectored_matches_encode() {
+        let large = "x".repeat(super::VECTORED_MIN_LEN);
+        let replies = [
+            Array {
+                tokens: vec![
+                    BulkString {
+                        data: large.clone(),
+                    },
+                    Token::NullBulkString,
+                    BulkString {
+                        data: "small".into(),
+                    },
+                    Map {
+                        pairs: vec![(
+                            BulkString {
+                                data: large.clone(),
+                            },
+                            Double { data: 1.5 },
+                        )],
+                    },
+                ],
+            },
+            Push {
+                tokens: vec![
+                    BulkString {
+                        data: large.clone(),
+                    },
+                    Integer { data: 3 },
+                ],
+            },
+            SimpleString { data: "OK".into() },
+        ];
+        for protocol in [Resp2, Resp3] {
+            let mut vectored = Vectored::default();
+            replies
+                .iter()
+                .for_each(|reply| vectored.push(reply, protocol));
+            let slices = vectored.slices();
+            let expected: String = replies.iter().map(|reply| reply.encode(protocol)).collect();
+            assert_eq!(slices.concat(), expected.as_bytes());
+            // The large values are not copied, but written straight out of the replies.
+            let borrowed = slices
+                .iter()
+                .filter(|slice| slice.len() == large.len())
+                .count();
+            assert_eq!(borrowed, 3);
+        }
+    }
+
+    #[test]
+    fn vectored_bytes() {
+        let reply = Array {
+            tokens: vec![
+                Token::BulkBytes {
+                    data: b"a\r\n:1".to_vec(),
+                },
+                Token::BulkBytes {
+                    data: vec![0xff, 0x80],
+                },
+            ],
+        };
+        let mut vectored = Vectored::default();
+        vectored.push(&reply, Resp2);
+        assert_eq!(
+            vectored.slices().concat(),
+            b"*2\r\n$5\r\na\r\n:1\r\n$2\r\n\xff\x80\r\n"
+        );
+        // Only the bytes that are valid UTF-8 make it through `encode`.
+        assert_eq!(
+            reply.encode(Resp2),
+            "*2\r\n$5\r\na\r\n:1\r\n$2\r\n\u{fffd}\u{fffd}\r\n"
+        );
+    }
+
+    #[test]
+    fn inline_requests() {
+        assert_eq!(
+            decode_inline(b"PING\r\n"),
+            Some((vec![b"PING".to_vec()], 6))
+        );
+        let set = vec![b"SET".to_vec(), b"a".to_vec(), b"b".to_vec()];
+        assert_eq!(decode_inline(b"SET  a\tb\n"), Some((set, 9)));
+        assert_eq!(decode_inline(b"\r\n"), Some((vec![], 2)));
+        assert_eq!(decode_inline(b"PIN"), None);
+        assert!(is_inline(b"PING\r\n") && is_inline(b"\r\n"));
+        assert!(!is_inline(b"*1\r\n") && !is_inline(b"+PING\r\n") && !is_inline(b""));
+    }
+
+    #[test]
+    fn bulk_array_fast_path() {
+        assert_eq!(
+            decode_bulk_array(b"*3\r\n$3\r\nSET\r\n$1\r\n*\r\n$0\r\n\r\n"),
+            Some((vec![b"SET".to_vec(), b"*".to_vec(), vec![]], 26))
+        );
+        assert_eq!(decode_bulk_array(b"*0\r\n"), Some((vec![], 4)));
+        // A CRLF inside of the data is fine, since the length says where it ends.
+        assert_eq!(
+            decode_bulk_array(b"*1\r\n$4\r\na\r\nb\r\n"),
+            Some((vec![b"a\r\nb".to_vec()], 14))
+        );
+        // Pipelined requests are decoded one at a time.
+        assert_eq!(
+            decode_bulk_array(b"*1\r\n$4\r\nECHO\r\n*1\r\n$4\r\nPING\r\n"),
+            Some((vec![b"ECHO".to_vec()], 14))
+        );
+        // The data is whatever bytes the client sent, UTF-8 or not.
+        assert_eq!(
+            decode_bulk_array(b"*1\r\n$2\r\n\xff\xfe\r\n"),
+            Some((vec![b"\xff\xfe".to_vec()], 12))
+        );
+
+        for request in [
+            &b"*2\r\n$4\r\nECHO\r\n"[..],
+            b"*1\r\n$4\r\nECH",
+            b"*1\r\n$5\r\nECHO\r\n",
+            b"*1\r\n$3\r\nECHO\r\n",
+            b"*1\r\n$-1\r\n",
+            b"*1\r\n:1\r\n",
+            b"*1\r\n$99999999999999999999999\r\nECHO\r\n",
+            b"*18446744073709551615\r\n",
+            b"+PING\r\n",
+            b"*1\n$4\nECHO\n",
+        ] {
+            assert_eq!(decode_bulk_array(request), None, "{request:?}");
+        }
+    }
+
+    /// Compare the fast path against the general parsers. Run it with
+    /// `cargo test --release -- --ignored --nocapture bulk_array_benchmark`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bulk_array_benchmark() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        const ROUNDS: u32 = 100_000;
+        let value = "x".repeat(64);
+        let request = Token::Array {
+            tokens: ["SET", "some:key", &value, "PX", "100"]
+                .map(|arg| BulkString { data: arg.into() })
+                .to_vec(),
+        }
+        .to_string();
+        let request = request.as_bytes();
+
+        let time = |name: &str, parse: &dyn Fn(&[u8]) -> bool| {
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                assert!(parse(black_box(request)));
+            }
+            println!("{name:>12}: {:?} per request", start.elapsed() / ROUNDS);
+        };
+        time("fast path", &|bytes| decode_bulk_array(bytes).is_some());
+        time("decode", &|bytes| {
+            matches!(Token::decode(bytes), Ok(Some(_)))
+        });
+        time("legacy", &|bytes| {
+            std::str::from_utf8(bytes).is_ok_and(|str| Token::try_from(str).is_ok())
+        });
+    }
 }
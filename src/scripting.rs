@@ -0,0 +1,527 @@
+//! # Scripting: running Lua scripts with `EVAL` and functions with `FCALL`, on the [`lua`] interpreter.
+//!
+//! Scripts get the key names and the other arguments given to `EVAL` as the `KEYS` and
+//! `ARGV` tables, and run Redis commands through the `redis` library:
+//!
+//! | Function                     | Does                                                    |
+//! |------------------------------|---------------------------------------------------------|
+//! | `redis.call(command, ...)`   | Run a command, raising its error if it fails            |
+//! | `redis.pcall(command, ...)`  | Run a command, returning its error as `{err = ...}`     |
+//! | `redis.error_reply(message)` | Build an error reply, `{err = message}`                 |
+//! | `redis.status_reply(status)` | Build a status reply, `{ok = status}`                   |
+//! | `redis.log(level, ...)`      | Log a message at `redis.LOG_DEBUG` ... `LOG_WARNING`    |
+//!
+//! Replies turn into Lua values and back the same way they do in Redis, as if the client
+//! spoke RESP2: integers become numbers (truncated on the way back), bulk strings become
+//! strings, arrays become tables, simple strings and errors become `{ok = ...}` and
+//! `{err = ...}` tables, and nulls become `false`. Only the first value a script returns
+//! is replied with, and a table is replied as an array up to its first `nil`.
+//!
+//! Like transactions, a script runs under a single lock of the database, so that no other
+//! client can see or change the keyspace halfway through it. Commands that only make sense
+//! for a connection of their own, like `MULTI` or `SUBSCRIBE`, can't be run from a script.
+//!
+//! Every script run with `EVAL` or loaded with `SCRIPT LOAD` stays in the [`Cache`], named
+//! by the SHA-1 digest of its source, so that clients can run it again with `EVALSHA`
+//! without sending the whole source every time. The cache is only emptied by `SCRIPT FLUSH`.
+//!
+//! Functions are the newer take on the same idea, see [`functions`](self::functions).
+
+pub mod functions;
+
+use crate::command::Command;
+use crate::lua::{self, Error, Interpreter, Limits, Table, Value};
+use crate::resp::Token;
+use crate::sha1;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// A script to [`run`].
+#[derive(Debug, Clone, Copy)]
+pub enum Script<'a> {
+    /// The source of a script run with `EVAL` or `EVALSHA`.
+    Eval(&'a str),
+    /// The function `name` of the `library`, run with `FCALL` or `FCALL_RO`.
+    Function { library: &'a str, name: &'a str },
+}
+
+/// How deeply nested the tables returned by a script may be.
+const MAX_DEPTH: usize = 200;
+
+/// The reply to a script running a command that can't be run from scripts.
+pub const NOT_ALLOWED: &str = "ERR This Redis command is not allowed from script";
+/// The reply to a script running a command that does not parse.
+pub const UNKNOWN_COMMAND: &str = "ERR Unknown Redis command called from script";
+/// The reply to a read-only script running a write command.
+pub const READ_ONLY: &str = "ERR Write commands are not allowed from read-only scripts.";
+/// The reply to `EVALSHA` with a script that is not in the [`Cache`].
+pub const NO_SCRIPT: &str = "NOSCRIPT No matching script. Please use EVAL.";
+
+const REDIS: [(&str, lua::Builtin); 5] = [
+    ("call", |interpreter, args| call(interpreter, args, true)),
+    ("pcall", |interpreter, args| call(interpreter, args, false)),
+    ("error_reply", |interpreter, args| {
+        reply(interpreter, &args, "err", "error_reply")
+    }),
+    ("status_reply", |interpreter, args| {
+        reply(interpreter, &args, "ok", "status_reply")
+    }),
+    ("log", log),
+];
+
+const LOG_LEVELS: [&str; 4] = ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"];
+
+/// Whether `command` may be run from a script.
+pub const fn allowed(command: &Command) -> bool {
+    !matches!(
+        command,
+        Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch { .. }
+            | Command::Unwatch
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::SSubscribe { .. }
+            | Command::SUnsubscribe { .. }
+            | Command::Quit
+            | Command::Reset
+            | Command::Save
+            | Command::BgSave { .. }
+            | Command::ReplConf { .. }
+            | Command::ReplConfGetAck
+            | Command::ReplConfAck { .. }
+            | Command::PSync { .. }
+            | Command::Role
+            | Command::ClientList { .. }
+            | Command::ClientInfo
+            | Command::ClientSetName { .. }
+            | Command::ClientGetName
+            | Command::ClientId
+            | Command::ClientKill { .. }
+            | Command::ClientKillAddr { .. }
+            | Command::ClientPause { .. }
+            | Command::ClientUnpause
+            | Command::ClientNoEvict { .. }
+            | Command::ClientNoTouch { .. }
+            | Command::ReplicaOf { .. }
+            | Command::DebugSleep { .. }
+            | Command::DebugObject { .. }
+            | Command::DebugSetActiveExpire { .. }
+            | Command::DebugJmap
+            | Command::Wait { .. }
+            | Command::Eval { .. }
+            | Command::EvalSha { .. }
+            | Command::ScriptLoad { .. }
+            | Command::ScriptExists { .. }
+            | Command::ScriptFlush
+            | Command::FunctionLoad { .. }
+            | Command::FunctionList { .. }
+            | Command::FunctionDump
+            | Command::FunctionRestore { .. }
+            | Command::FunctionFlush
+            | Command::FCall { .. }
+    )
+}
+
+/// The scripts that `EVALSHA` can run, named by the SHA-1 digests of their sources.
+#[derive(Debug, Default)]
+pub struct Cache {
+    scripts: RwLock<HashMap<String, Arc<str>>>,
+}
+
+impl Cache {
+    /// Add `script` to the cache, unless it already is, returning its name.
+    pub fn load(&self, script: &str) -> String {
+        let sha1 = sha1::hex_digest(script.as_bytes());
+        self.scripts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(sha1.clone())
+            .or_insert_with(|| Arc::from(script));
+        sha1
+    }
+
+    /// The script named `sha1`, whose hex digits may be in either case.
+    pub fn get(&self, sha1: &str) -> Option<Arc<str>> {
+        self.scripts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&sha1.to_ascii_lowercase())
+            .cloned()
+    }
+
+    /// Remove every script from the cache.
+    pub fn flush(&self) {
+        self.scripts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+/// Run `script` with the given `keys` and `args` within `limits`, producing the reply to send.
+///
+/// Every command the script runs goes through `host`, which replies to it. The script runs on
+/// a thread of its own (see [`lua::isolated`]), so `host` must not rely on the current one.
+pub fn run(
+    script: Script<'_>,
+    keys: Vec<String>,
+    args: Vec<String>,
+    limits: Limits,
+    host: &mut (dyn FnMut(Vec<String>) -> Token + Send),
+) -> Token {
+    lua::isolated(move || {
+        let mut interpreter = Interpreter::new(limits, Box::new(|args| to_lua(host(args))));
+        let values = match script {
+            Script::Eval(source) => {
+                interpreter.set_global("KEYS", strings(keys));
+                interpreter.set_global("ARGV", strings(args));
+                interpreter.set_global("redis", library());
+                interpreter.run(source)
+            }
+            Script::Function { library, name } => {
+                functions::call(&mut interpreter, library, name, keys, args)
+            }
+        };
+        match values {
+            Ok(values) => values
+                .first()
+                .map_or(Token::NullBulkString, |value| to_resp(value, 0)),
+            Err(err) => error_reply(err),
+        }
+    })
+}
+
+/// A Lua array of `strings`.
+fn strings(strings: Vec<String>) -> Value {
+    Value::table(Table::from_array(
+        strings.into_iter().map(Value::string).collect(),
+    ))
+}
+
+/// The `redis` library, see the [module docs](self).
+fn library() -> Value {
+    let mut table = Table::default();
+    for (name, function) in REDIS {
+        table.set_field(name, Value::Builtin(name, function));
+    }
+    for (level, name) in LOG_LEVELS.into_iter().enumerate() {
+        table.set_field(name, Value::Number(level as f64));
+    }
+    Value::table(table)
+}
+
+/// `redis.call` and `redis.pcall`, which only differ in whether errors are `raised`.
+fn call(
+    interpreter: &mut Interpreter<'_>,
+    args: Vec<Value>,
+    raised: bool,
+) -> Result<Vec<Value>, Error> {
+    if args.is_empty() {
+        return Err(
+            interpreter.error("Please specify at least one argument for this redis lib call")
+        );
+    }
+    let args = args
+        .iter()
+        .map(|arg| match arg {
+            Value::String(_) | Value::Number(_) => arg.to_str(),
+            _ => None,
+        })
+        .map(|arg| arg.map(|arg| arg.to_string()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            interpreter.error("Lua redis lib command arguments must be strings or integers")
+        })?;
+    let reply = interpreter.host(args);
+    if raised && error_message(&reply).is_some() {
+        return Err(Error::Runtime(reply));
+    }
+    Ok(vec![reply])
+}
+
+/// `redis.error_reply` and `redis.status_reply`, building a table with a single `field`.
+fn reply(
+    interpreter: &mut Interpreter<'_>,
+    args: &[Value],
+    field: &str,
+    name: &str,
+) -> Result<Vec<Value>, Error> {
+    let Some(message @ Value::String(_)) = args.first() else {
+        return Err(interpreter.error(format!("wrong number or type of arguments to '{name}'")));
+    };
+    let mut table = Table::default();
+    table.set_field(field, message.clone());
+    Ok(vec![interpreter.new_table(table)?])
+}
+
+/// `redis.log`, logging its arguments separated by spaces.
+fn log(interpreter: &mut Interpreter<'_>, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let mut args = args.into_iter();
+    let level = args.next().and_then(|level| level.to_number());
+    let message = args
+        .map(|arg| arg.to_str().map(|arg| arg.to_string()))
+        .collect::<Option<Vec<_>>>();
+    let (Some(level), Some(message)) = (level, message) else {
+        return Err(interpreter.error("redis.log() requires two arguments or more."));
+    };
+    if message.is_empty() {
+        return Err(interpreter.error("redis.log() requires two arguments or more."));
+    }
+    let message = message.join(" ");
+    match level as i64 {
+        0 => tracing::trace!(target: "script", "{message}"),
+        1 => tracing::debug!(target: "script", "{message}"),
+        2 => tracing::info!(target: "script", "{message}"),
+        3 => tracing::warn!(target: "script", "{message}"),
+        _ => return Err(interpreter.error("Invalid debug level.")),
+    }
+    Ok(vec![])
+}
+
+/// The message of an error reply, i.e. a table with an `err` field.
+fn error_message(value: &Value) -> Option<String> {
+    match value {
+        Value::Table(table) => match table.borrow().field("err") {
+            Value::String(message) => Some(message.to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Turn the reply to a command into the Lua value that `redis.call` returns.
+fn to_lua(token: Token) -> Value {
+    match token {
+        Token::Integer { data } => Value::Number(data as f64),
+        Token::BulkString { data } => Value::string(data),
+        // Lua strings are UTF-8 here, so binary values can't make it into scripts intact.
+        Token::BulkBytes { data } => Value::string(String::from_utf8_lossy(&data).into_owned()),
+        Token::SimpleString { data } => status("ok", data),
+        Token::SimpleError { data } => status("err", data),
+        Token::NullBulkString | Token::NullArray => Value::Boolean(false),
+        Token::Array { tokens } => {
+            Value::table(Table::from_array(tokens.into_iter().map(to_lua).collect()))
+        }
+        token @ (Token::Map { .. } | Token::Double { .. } | Token::Push { .. }) => {
+            token.downgrade().map_or(Value::Nil, to_lua)
+        }
+    }
+}
+
+/// A table with a single string `field`, like the `{err = ...}` of errors.
+fn status(field: &str, data: impl Into<std::rc::Rc<str>>) -> Value {
+    let mut table = Table::default();
+    table.set_field(field, Value::string(data));
+    Value::table(table)
+}
+
+/// Turn a value returned by a script into the reply to send, `depth` tables deep.
+fn to_resp(value: &Value, depth: usize) -> Token {
+    match value {
+        Value::String(string) => Token::BulkString {
+            data: string.to_string(),
+        },
+        Value::Number(number) => Token::Integer {
+            data: *number as i64,
+        },
+        Value::Boolean(true) => Token::Integer { data: 1 },
+        Value::Table(table) => {
+            let table = table.borrow();
+            if let Value::String(message) = table.field("err") {
+                return Token::SimpleError {
+                    data: message.to_string(),
+                };
+            }
+            if let Value::String(status) = table.field("ok") {
+                return Token::SimpleString {
+                    data: status.to_string(),
+                };
+            }
+            if depth >= MAX_DEPTH {
+                return Token::SimpleError {
+                    data: "ERR reached lua stack limit".to_string(),
+                };
+            }
+            let tokens = (1..)
+                .map(|index| table.get(&Value::Number(f64::from(index))))
+                .take_while(|value| !value.is_nil())
+                .map(|value| to_resp(&value, depth + 1))
+                .collect();
+            Token::Array { tokens }
+        }
+        Value::Boolean(false) | Value::Nil | Value::Function(_) | Value::Builtin(..) => {
+            Token::NullBulkString
+        }
+    }
+}
+
+/// The reply to a script that failed with `err`.
+fn error_reply(err: Error) -> Token {
+    let data = match err {
+        Error::Syntax(message) => format!("ERR Error compiling script: {message}"),
+        Error::Runtime(value) => error_message(&value).unwrap_or_else(|| format!("ERR {value}")),
+        err @ (Error::Memory(_) | Error::Instructions(_)) => format!("ERR {err}"),
+    };
+    Token::SimpleError { data }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Cache, Limits, Script, Token};
+
+    const LIMITS: Limits = Limits {
+        memory: 1024 * 1024,
+        instructions: 10_000,
+    };
+
+    /// Run `script`, answering the commands it runs with `host`.
+    fn eval(script: &str, keys: &[&str], args: &[&str], host: fn(Vec<String>) -> Token) -> Token {
+        let strings = |strings: &[&str]| strings.iter().map(|s| s.to_string()).collect();
+        let script = Script::Eval(script);
+        run(script, strings(keys), strings(args), LIMITS, &mut |args| {
+            host(args)
+        })
+    }
+
+    fn bulk(data: &str) -> Token {
+        Token::BulkString {
+            data: data.to_string(),
+        }
+    }
+
+    fn error(data: &str) -> Token {
+        Token::SimpleError {
+            data: data.to_string(),
+        }
+    }
+
+    /// Echo back the arguments of every command.
+    fn echo(args: Vec<String>) -> Token {
+        Token::Array {
+            tokens: args.into_iter().map(Token::from).collect(),
+        }
+    }
+
+    #[test]
+    fn replies() {
+        let reply = |script| eval(script, &[], &[], echo);
+        assert_eq!(reply("return 'a'"), bulk("a"));
+        assert_eq!(reply("return 3.99"), Token::Integer { data: 3 });
+        assert_eq!(reply("return true"), Token::Integer { data: 1 });
+        assert_eq!(reply("return false"), Token::NullBulkString);
+        assert_eq!(reply("return"), Token::NullBulkString);
+        assert_eq!(
+            reply("return {1, 'b', {2}, nil, 'unreachable'}"),
+            Token::Array {
+                tokens: vec![
+                    Token::Integer { data: 1 },
+                    bulk("b"),
+                    Token::Array {
+                        tokens: vec![Token::Integer { data: 2 }]
+                    },
+                ]
+            }
+        );
+        assert_eq!(reply("return redis.error_reply('E no')"), error("E no"));
+        assert_eq!(
+            reply("return redis.status_reply('FINE')"),
+            Token::SimpleString {
+                data: "FINE".to_string()
+            }
+        );
+        assert_eq!(
+            reply("local t = {} t[1] = t return t"),
+            (0..200).fold(error("ERR reached lua stack limit"), |token, _| {
+                Token::Array {
+                    tokens: vec![token],
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn keys_args_and_calls() {
+        assert_eq!(
+            eval(
+                "return redis.call('SET', KEYS[1], ARGV[1], 10)",
+                &["k"],
+                &["v"],
+                echo
+            ),
+            Token::Array {
+                tokens: vec![bulk("SET"), bulk("k"), bulk("v"), bulk("10")]
+            }
+        );
+        let host = |args: Vec<String>| match args[0].as_str() {
+            "null" => Token::NullBulkString,
+            "status" => Token::SimpleString {
+                data: "OK".to_string(),
+            },
+            "double" => Token::Double { data: 1.5 },
+            _ => error("ERR nope"),
+        };
+        let reply = |script| eval(script, &[], &[], host);
+        assert_eq!(
+            reply("return redis.call('null') == false"),
+            Token::Integer { data: 1 }
+        );
+        assert_eq!(reply("return redis.call('status').ok"), bulk("OK"));
+        assert_eq!(reply("return redis.call('double')"), bulk("1.5"));
+        assert_eq!(
+            reply("return redis.call('status')"),
+            host(vec!["status".into()])
+        );
+        assert_eq!(reply("return redis.pcall('fail').err"), bulk("ERR nope"));
+        assert_eq!(reply("redis.call('fail') return 1"), error("ERR nope"));
+        assert_eq!(
+            reply("return pcall(redis.call, 'fail')"),
+            Token::NullBulkString
+        );
+    }
+
+    #[test]
+    fn cache() {
+        let cache = Cache::default();
+        let script = "return 'Immabe a cached script'";
+        let sha1 = cache.load(script);
+        assert_eq!(sha1, "c664a3bf70bd1d45c4284ffebb65a6f2299bfc9f");
+        assert_eq!(cache.load(script), sha1);
+        assert_eq!(cache.get(&sha1).as_deref(), Some(script));
+        assert_eq!(cache.get(&sha1.to_uppercase()).as_deref(), Some(script));
+        assert_eq!(cache.get("ffffffffffffffffffffffffffffffffffffffff"), None);
+        cache.flush();
+        assert_eq!(cache.get(&sha1), None);
+    }
+
+    #[test]
+    fn script_errors() {
+        let reply = |script| eval(script, &[], &[], echo);
+        assert_eq!(
+            reply("return redis.call('GET', {})"),
+            error("ERR user_script:1: Lua redis lib command arguments must be strings or integers")
+        );
+        assert_eq!(
+            reply("return redis.call()"),
+            error(
+                "ERR user_script:1: Please specify at least one argument for this redis lib call"
+            )
+        );
+        assert_eq!(reply("error('boom')"), error("ERR user_script:1: boom"));
+        assert!(matches!(
+            reply("return 1 +"),
+            Token::SimpleError { data } if data.starts_with("ERR Error compiling script: ")
+        ));
+        assert_eq!(
+            reply("while true do end"),
+            error("ERR Script ran more than 10000 instructions")
+        );
+        assert_eq!(
+            reply("x = 1"),
+            error("ERR user_script:1: Script attempted to create global variable 'x'")
+        );
+    }
+}
@@ -0,0 +1,597 @@
+//! # Functions: libraries of named Lua functions, loaded with `FUNCTION LOAD` and run with `FCALL`.
+//!
+//! A library is Lua source that starts with a shebang naming it, and registers its
+//! functions when run, instead of doing any work itself:
+//!
+//! ```lua
+//! #!lua name=counters
+//! redis.register_function('bump', function(keys, args)
+//!   return redis.call('INCRBY', keys[1], args[1] or 1)
+//! end)
+//! redis.register_function{function_name = 'peek', callback = ..., flags = {'no-writes'}}
+//! ```
+//!
+//! While a library is being loaded, `redis` only has `register_function` and `log`, so
+//! loading it can never touch the keyspace. Functions get the key names and the other
+//! arguments of `FCALL` as their two arguments, rather than as `KEYS` and `ARGV`, and
+//! functions flagged `no-writes` can't run write commands, which makes them the only
+//! ones that `FCALL_RO` runs.
+//!
+//! Lua values can't leave the thread they were made on (see [`lua::isolated`]), so only
+//! the source of every library is kept, and `FCALL` runs it again to find the function.
+//! The libraries are saved along with the dataset, as `FUNCTION2` records of the RDB file,
+//! and `FUNCTION DUMP` produces the same records, ready for `FUNCTION RESTORE`.
+
+use super::{error_message, library, log, status, strings, LOG_LEVELS};
+use crate::glob;
+use crate::lua::{self, Builtin, Error, Interpreter, Limits, Table, Value};
+use crate::rdb::{self, opcode, Reader};
+use crate::resp::Token;
+use std::collections::BTreeMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// The reply to `FCALL` with a function that no library registered.
+pub const NOT_FOUND: &str = "ERR Function not found";
+/// The reply to `FCALL_RO` with a function that is not flagged `no-writes`.
+pub const WRITE_FUNCTION: &str = "ERR Can not execute a script with write flag using *_ro command.";
+
+/// The flags that functions may be registered with.
+const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// The `redis` library while loading a library.
+const LOADER: [(&str, Builtin); 2] = [("register_function", register_function), ("log", log)];
+
+/// A library of functions, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Library {
+    pub name: String,
+    /// The whole source of the library, shebang included.
+    pub code: Arc<str>,
+    /// The functions of the library, in the order they were registered.
+    pub functions: Vec<Function>,
+}
+
+/// A function registered by a [`Library`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+impl Function {
+    /// Whether the function may only run read-only commands.
+    pub fn is_read_only(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "no-writes")
+    }
+}
+
+/// What `FUNCTION RESTORE` does with the libraries that are already loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestorePolicy {
+    /// Keep them, failing if any of them has the same name as a restored one.
+    #[default]
+    Append,
+    /// Keep them, but let the restored libraries replace those with the same names.
+    Replace,
+    /// Drop them all first.
+    Flush,
+}
+
+/// The loaded libraries, by name.
+#[derive(Debug, Default)]
+pub struct Libraries {
+    libraries: RwLock<BTreeMap<String, Library>>,
+}
+
+impl Libraries {
+    /// Add the `libraries`, replacing those with the same names only if `replace` is set.
+    ///
+    /// Either all of them are added, or none of them is and the error to reply with is returned.
+    pub fn insert(&self, libraries: Vec<Library>, replace: bool) -> Result<(), String> {
+        let mut loaded = self
+            .libraries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut updated = loaded.clone();
+        for library in libraries {
+            if !replace && updated.contains_key(&library.name) {
+                return Err(format!("ERR Library '{}' already exists", library.name));
+            }
+            updated.remove(&library.name);
+            let taken = library.functions.iter().find(|function| {
+                updated
+                    .values()
+                    .flat_map(|library| &library.functions)
+                    .any(|other| other.name == function.name)
+            });
+            if let Some(function) = taken {
+                return Err(format!("ERR Function {} already exists", function.name));
+            }
+            updated.insert(library.name.clone(), library);
+        }
+        *loaded = updated;
+        Ok(())
+    }
+
+    /// Add the `libraries` of a `FUNCTION RESTORE`, according to the `policy`.
+    pub fn restore(&self, libraries: Vec<Library>, policy: RestorePolicy) -> Result<(), String> {
+        if policy == RestorePolicy::Flush {
+            let backup = self.take();
+            return self.insert(libraries, false).map_err(|err| {
+                *self
+                    .libraries
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner) = backup;
+                err
+            });
+        }
+        self.insert(libraries, policy == RestorePolicy::Replace)
+    }
+
+    /// The function `name`, along with the source of its library.
+    pub fn function(&self, name: &str) -> Option<(Arc<str>, Function)> {
+        let libraries = self
+            .libraries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        libraries.values().find_map(|library| {
+            let function = library
+                .functions
+                .iter()
+                .find(|function| function.name == name)?;
+            Some((library.code.clone(), function.clone()))
+        })
+    }
+
+    /// The libraries whose names match the glob-style `pattern`, or all of them.
+    pub fn list(&self, pattern: Option<&str>) -> Vec<Library> {
+        let libraries = self
+            .libraries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        libraries
+            .values()
+            .filter(|library| pattern.map_or(true, |pattern| glob::matches(pattern, &library.name)))
+            .cloned()
+            .collect()
+    }
+
+    /// The sources of all the libraries, which is all it takes to load them again.
+    pub fn codes(&self) -> Vec<String> {
+        self.list(None)
+            .into_iter()
+            .map(|library| library.code.to_string())
+            .collect()
+    }
+
+    /// Remove all the libraries, returning them.
+    pub fn take(&self) -> BTreeMap<String, Library> {
+        std::mem::take(
+            &mut self
+                .libraries
+                .write()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+}
+
+/// Load the library with the source `code` within `limits`, or produce the error to reply with.
+pub fn load(code: &str, limits: Limits) -> Result<Library, String> {
+    let (name, body) = metadata(code)?;
+    lua::isolated(|| {
+        // Libraries can't run commands while loading, so the host is never called.
+        let mut interpreter = Interpreter::new(limits, Box::new(|_| Value::Nil));
+        register(&mut interpreter, &body).map_err(|err| match err {
+            Error::Syntax(message) => format!("ERR Error compiling function: {message}"),
+            Error::Runtime(value) => {
+                let message = error_message(&value).unwrap_or_else(|| value.to_string());
+                format!("ERR Error registering functions: {message}")
+            }
+            err => format!("ERR Error registering functions: {err}"),
+        })?;
+        let functions = registered(&interpreter)
+            .into_iter()
+            .map(|(function, _)| function)
+            .collect::<Vec<_>>();
+        if functions.is_empty() {
+            return Err("ERR No functions registered".to_string());
+        }
+        Ok(Library {
+            name,
+            code: Arc::from(code),
+            functions,
+        })
+    })
+}
+
+/// Run the function `name` of the `library` with `keys` and `args`, on behalf of [`run`](super::run).
+pub(super) fn call(
+    interpreter: &mut Interpreter<'_>,
+    code: &str,
+    name: &str,
+    keys: Vec<String>,
+    args: Vec<String>,
+) -> Result<Vec<Value>, Error> {
+    let (_, body) = metadata(code).map_err(|message| Error::Runtime(status("err", message)))?;
+    register(interpreter, &body)?;
+    let Some((_, callback)) = registered(interpreter)
+        .into_iter()
+        .find(|(function, _)| function.name == name)
+    else {
+        return Err(Error::Runtime(status("err", NOT_FOUND)));
+    };
+    // Functions run with the same `redis` library that scripts get.
+    interpreter.set_global("redis", library());
+    interpreter.call(&callback, vec![strings(keys), strings(args)])
+}
+
+/// Run the `body` of a library with the `redis` library of loading, registering its functions.
+fn register(interpreter: &mut Interpreter<'_>, body: &str) -> Result<(), Error> {
+    let mut redis = Table::default();
+    for (name, function) in LOADER {
+        redis.set_field(name, Value::Builtin(name, function));
+    }
+    for (level, name) in LOG_LEVELS.into_iter().enumerate() {
+        redis.set_field(name, Value::Number(level as f64));
+    }
+    interpreter.set_global("redis", Value::table(redis));
+    interpreter.run(body).map(|_| ())
+}
+
+/// The functions registered so far, along with their callbacks.
+fn registered(interpreter: &Interpreter<'_>) -> Vec<(Function, Value)> {
+    let registry = interpreter.registry();
+    let registry = registry.borrow();
+    (1..=registry.len())
+        .filter_map(|index| match registry.get(&Value::Number(index as f64)) {
+            Value::Table(entry) => {
+                let entry = entry.borrow();
+                let string = |field| entry.field(field).to_str().map(|s| s.to_string());
+                let flags = match entry.field("flags") {
+                    Value::Table(flags) => {
+                        let flags = flags.borrow();
+                        (1..=flags.len())
+                            .filter_map(|index| flags.get(&Value::Number(index as f64)).to_str())
+                            .map(|flag| flag.to_string())
+                            .collect()
+                    }
+                    _ => vec![],
+                };
+                let function = Function {
+                    name: string("name")?,
+                    description: string("description"),
+                    flags,
+                };
+                Some((function, entry.field("callback")))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// `redis.register_function(name, callback)`, or with a table of named arguments:
+/// `function_name`, `callback`, and optionally `flags` and `description`.
+fn register_function(
+    interpreter: &mut Interpreter<'_>,
+    args: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(table)] => {
+            let table = table.borrow();
+            let mut key = Value::Nil;
+            while let Some((next, _)) = table.next(&key) {
+                match next.to_str().as_deref() {
+                    Some("function_name" | "callback" | "flags" | "description") => key = next,
+                    _ => {
+                        return Err(
+                            interpreter.error("unknown argument given to redis.register_function")
+                        )
+                    }
+                }
+            }
+            (
+                table.field("function_name"),
+                table.field("callback"),
+                table.field("flags"),
+                table.field("description"),
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return Err(interpreter.error("wrong number of arguments to redis.register_function")),
+    };
+    let Value::String(name) = name else {
+        return Err(interpreter.error("function_name argument given to redis.register_function must be a string"));
+    };
+    if !is_valid_name(&name) {
+        return Err(interpreter.error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+    if !matches!(callback, Value::Function(_) | Value::Builtin(..)) {
+        return Err(interpreter
+            .error("callback argument given to redis.register_function must be a function"));
+    }
+    let flags = match flags {
+        Value::Nil => Value::table(Table::default()),
+        Value::Table(ref table) => {
+            let table = table.borrow();
+            let known = (1..=table.len()).all(|index| {
+                let flag = table.get(&Value::Number(index as f64)).to_str();
+                flag.is_some_and(|flag| FLAGS.contains(&&*flag))
+            });
+            if !known {
+                return Err(interpreter.error("unknown flag given"));
+            }
+            flags.clone()
+        }
+        _ => return Err(interpreter.error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+    if !matches!(description, Value::Nil | Value::String(_)) {
+        return Err(interpreter.error("function description must be a string"));
+    }
+    if registered(interpreter)
+        .iter()
+        .any(|(function, _)| *function.name == *name)
+    {
+        return Err(interpreter.error("Function already exists in the library"));
+    }
+    let mut entry = Table::default();
+    entry.set_field("name", Value::String(name));
+    entry.set_field("callback", callback);
+    entry.set_field("flags", flags);
+    entry.set_field("description", description);
+    let entry = interpreter.new_table(entry)?;
+    interpreter.registry().borrow_mut().push(entry);
+    Ok(vec![])
+}
+
+/// Whether `name` can name a library or a function.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+/// Split the shebang off the `code` of a library, producing the name of the library and
+/// the rest of the code, with the shebang blanked out so that line numbers stay the same.
+fn metadata(code: &str) -> Result<(String, String), String> {
+    let Some(shebang) = code.strip_prefix("#!") else {
+        return Err("ERR Missing library metadata".to_string());
+    };
+    let (shebang, body) = shebang.split_once('\n').unwrap_or((shebang, ""));
+    let mut parts = shebang.split_ascii_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{engine}' not found"));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(format!("ERR Invalid metadata value given: {part}")),
+        }
+    }
+    let Some(name) = name else {
+        return Err("ERR Library name was not given".to_string());
+    };
+    if !is_valid_name(&name) {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+    Ok((name, format!("\n{body}")))
+}
+
+/// The reply to `FUNCTION LIST`, for the given `libraries`.
+pub fn list_reply(libraries: Vec<Library>, with_code: bool) -> Token {
+    let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+    let tokens = libraries
+        .into_iter()
+        .map(|library| {
+            let functions = library
+                .functions
+                .into_iter()
+                .map(|function| Token::Map {
+                    pairs: vec![
+                        field("name", Token::from(function.name)),
+                        field(
+                            "description",
+                            function
+                                .description
+                                .map_or(Token::NullBulkString, Token::from),
+                        ),
+                        field(
+                            "flags",
+                            Token::Array {
+                                tokens: function.flags.into_iter().map(Token::from).collect(),
+                            },
+                        ),
+                    ],
+                })
+                .collect();
+            let mut pairs = vec![
+                field("library_name", Token::from(library.name)),
+                field("engine", Token::from("LUA".to_string())),
+                field("functions", Token::Array { tokens: functions }),
+            ];
+            if with_code {
+                pairs.push(field("library_code", Token::from(library.code.to_string())));
+            }
+            Token::Map { pairs }
+        })
+        .collect();
+    Token::Array { tokens }
+}
+
+/// Serialize the sources of libraries for `FUNCTION DUMP`, the way Redis does: a
+/// `FUNCTION2` record for every library, then the RDB version and a checksum.
+///
+/// Replies can only carry text, so every byte of the payload is sent as the character
+/// of the same code point, which [`undump`] turns back into the same byte.
+pub fn dump(codes: &[String]) -> String {
+    let mut out = vec![];
+    for code in codes {
+        out.push(opcode::FUNCTION2);
+        rdb::write_string(&mut out, code);
+    }
+    out.extend_from_slice(&(rdb::VERSION as u16).to_le_bytes());
+    // A zeroed checksum, which readers take as "checksum disabled", just like in RDB files.
+    out.extend_from_slice(&[0; 8]);
+    out.into_iter().map(char::from).collect()
+}
+
+/// Deserialize the sources of the libraries in a `payload` produced by [`dump`].
+pub fn undump(payload: &str) -> Result<Vec<String>, String> {
+    let invalid = || "ERR payload version or checksum are wrong".to_string();
+    let bytes = payload
+        .chars()
+        .map(|c| u8::try_from(c).ok())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    let Some(records) = bytes.len().checked_sub(10).map(|end| &bytes[..end]) else {
+        return Err(invalid());
+    };
+    let version = u16::from_le_bytes([bytes[records.len()], bytes[records.len() + 1]]);
+    if u32::from(version) > rdb::VERSION {
+        return Err(invalid());
+    }
+    let mut reader = Reader::new(records);
+    let mut codes = vec![];
+    while !reader.is_empty() {
+        if reader.u8().map_err(|_| invalid())? != opcode::FUNCTION2 {
+            return Err("ERR given type is not a function".to_string());
+        }
+        codes.push(reader.string().map_err(|_| invalid())?);
+    }
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump, load, undump, Libraries, Limits, RestorePolicy};
+    use crate::resp::Token;
+    use crate::scripting::{run, Script};
+
+    const LIMITS: Limits = Limits {
+        memory: 1024 * 1024,
+        instructions: 10_000,
+    };
+
+    const LIBRARY: &str = "#!lua name=lib\n\
+        redis.register_function('echo', function(keys, args) return {keys[1], args[1]} end)\n\
+        redis.register_function{function_name = 'peek', callback = function() return 1 end, \
+        flags = {'no-writes'}, description = 'Peeks'}";
+
+    #[test]
+    fn loading() {
+        let library = load(LIBRARY, LIMITS).unwrap();
+        assert_eq!(library.name, "lib");
+        let names: Vec<_> = library.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["echo", "peek"]);
+        assert!(!library.functions[0].is_read_only());
+        assert!(library.functions[1].is_read_only());
+        assert_eq!(library.functions[1].description.as_deref(), Some("Peeks"));
+
+        let error = |code: &str| load(code, LIMITS).unwrap_err();
+        assert_eq!(error("return 1"), "ERR Missing library metadata");
+        assert_eq!(error("#!js name=lib\n"), "ERR Engine 'js' not found");
+        assert_eq!(error("#!lua\n"), "ERR Library name was not given");
+        assert_eq!(
+            error("#!lua name=lib version=2\n"),
+            "ERR Invalid metadata value given: version=2"
+        );
+        assert_eq!(
+            error("#!lua name=lib\nlocal x = 1"),
+            "ERR No functions registered"
+        );
+        assert!(error("#!lua name=lib\nreturn +").starts_with("ERR Error compiling function: "));
+        assert_eq!(
+            error("#!lua name=lib\nredis.call('SET', 'a', 'b')"),
+            "ERR Error registering functions: user_script:2: \
+             attempt to call a nil value (field 'call')"
+        );
+        assert_eq!(
+            error("#!lua name=lib\nredis.register_function('a-b', function() end)"),
+            "ERR Error registering functions: user_script:2: Function names can only \
+             contain letters, numbers, or underscores(_) and must be at least one character long"
+        );
+        assert_eq!(
+            error(
+                "#!lua name=lib\nredis.register_function{function_name = 'f', \
+                 callback = function() end, flags = {'fast'}}"
+            ),
+            "ERR Error registering functions: user_script:2: unknown flag given"
+        );
+    }
+
+    #[test]
+    fn libraries() {
+        let libraries = Libraries::default();
+        let library = load(LIBRARY, LIMITS).unwrap();
+        libraries.insert(vec![library.clone()], false).unwrap();
+        assert_eq!(
+            libraries.insert(vec![library.clone()], false),
+            Err("ERR Library 'lib' already exists".to_string())
+        );
+        libraries.insert(vec![library.clone()], true).unwrap();
+        let other = load(&LIBRARY.replace("name=lib", "name=other"), LIMITS).unwrap();
+        assert_eq!(
+            libraries.insert(vec![other.clone()], false),
+            Err("ERR Function echo already exists".to_string())
+        );
+        assert_eq!(libraries.list(Some("l*")), [library]);
+        assert!(libraries.list(Some("o*")).is_empty());
+        assert!(libraries
+            .function("peek")
+            .is_some_and(|(_, f)| f.is_read_only()));
+
+        libraries
+            .restore(vec![other.clone()], RestorePolicy::Flush)
+            .unwrap();
+        assert_eq!(libraries.list(None), [other]);
+        assert!(libraries.function("missing").is_none());
+        libraries.take();
+        assert!(libraries.list(None).is_empty());
+    }
+
+    #[test]
+    fn calling() {
+        let call = |name: &str| {
+            let script = Script::Function {
+                library: LIBRARY,
+                name,
+            };
+            let (keys, args) = (vec!["k".to_string()], vec!["a".to_string()]);
+            run(script, keys, args, LIMITS, &mut |_| Token::NullBulkString)
+        };
+        assert_eq!(
+            call("echo"),
+            Token::Array {
+                tokens: vec![Token::from("k".to_string()), Token::from("a".to_string())]
+            }
+        );
+        assert_eq!(call("peek"), Token::Integer { data: 1 });
+        assert_eq!(
+            call("missing"),
+            Token::SimpleError {
+                data: "ERR Function not found".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn dump_and_undump() {
+        let codes = vec![LIBRARY.to_string(), "#!lua name=x\n".repeat(100)];
+        let payload = dump(&codes);
+        assert_eq!(undump(&payload), Ok(codes));
+        assert!(undump("").is_err());
+        assert!(undump(&payload.chars().skip(1).collect::<String>()).is_err());
+        assert!(undump("\u{100}").is_err());
+    }
+}
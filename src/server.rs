@@ -1,18 +1,57 @@
 //! # Redis server, handles clients and interacts with the [`Database`].
 
+use crate::aof::{self, Aof, Fsync};
+use crate::blocking::{Waiter, Waiters};
+use crate::chaos::Chaos;
+use crate::client::Client;
+use crate::clients::{self, Clients};
 use crate::command::{self, Command};
 use crate::config::Config;
-use crate::database::{Database, Error};
-use crate::resp::{Token, CRLF, SIMPLE_STRING_START};
+use crate::database::{ConsumerInfo, GroupInfo, PendingEntry, PendingSummary, Score, StreamId};
+use crate::database::{Coordinates, GeoMatch, StreamInfo, Value, ZAddOptions};
+use crate::database::{Data, Database, Entry, Error, ExpiryReport, Fields, ReadFrom};
+use crate::database::{ReadGroupFrom, Removal, Snapshot, Watch};
+use crate::lua::Limits;
+use crate::notify::{Events, KeyFilter};
+use crate::persistence::{self, BackgroundSave, Persistence, RdbPath};
+use crate::pubsub::{self, Broker, Kind, Subscriptions};
+use crate::replication::{self, Replica, ReplicaOf, Replication};
+use crate::resp::{self, Protocol, Token, Vectored};
+use crate::scripting::functions::{self, Libraries, RestorePolicy};
+use crate::scripting::{self, Script};
+use crate::shutdown::{self, Report, Request, Save, Shutdown, Trigger};
+use crate::slowlog::{self, Slowlog};
+use crate::stats::{Counter, Stats, TtlHistogram, TTL_BUCKETS};
+use crate::transaction::{self, Transaction};
+use crate::{compress, rdb, snapshot};
 use std::convert::Infallible;
-use std::{io, sync::Arc};
+use std::io::{self, IoSlice};
+use std::mem;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+use std::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch, Mutex, MutexGuard};
+use tokio::task;
 use tracing::instrument;
 
-/// The address and port on which the [`Server`] listens.
-pub const LISTEN_ADDR: &str = "127.0.0.1:6379";
+/// The address on which the [`Server`] listens, on the ports from its [`Config`].
+const LISTEN_HOST: &str = "127.0.0.1";
+
+/// How long a replica link may stay silent before it is dropped, like Redis' `repl-timeout`.
+const REPL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the [`Server`] runs its periodic background tasks, like Redis' `hz 10`.
+const CRON_PERIOD: Duration = Duration::from_millis(100);
+
+/// How many expired keys the cron removes at most each time, so that it never holds
+/// the database for long, even when lots of keys expire at once.
+const ACTIVE_EXPIRE_LIMIT: usize = 200;
 
 /// The Redis server.
 ///
@@ -21,30 +60,682 @@ pub const LISTEN_ADDR: &str = "127.0.0.1:6379";
 pub struct Server {
     pub db: Arc<Mutex<Database>>,
     listener: TcpListener,
+    /// Accepts the replica links, if a dedicated port for them is configured.
+    replication_listener: Option<TcpListener>,
     config: Config,
+    next_client_id: AtomicU64,
+    stats: Stats,
+    ttls: TtlHistogram,
+    /// Clients blocked by commands like `BZPOPMIN`, waiting for data to arrive.
+    waiters: Waiters,
+    /// The keyspace notifications to send, which `CONFIG SET` can change.
+    ///
+    /// The [`Database`] keeps its own copy, this one spares locking it when there are none.
+    events: RwLock<Events>,
+    /// The keys that keyspace notifications are sent about, which `CONFIG SET` can change.
+    key_filter: RwLock<KeyFilter>,
+    /// The path of the RDB file, which `CONFIG SET` can change too.
+    persistence: Persistence,
+    /// The append-only file, whose `appendfsync` policy `CONFIG SET` can change.
+    aof: Aof,
+    /// Delivers the messages published to Pub/Sub channels to the subscribed clients.
+    broker: Broker,
+    /// The scripts that `EVALSHA` can run.
+    scripts: scripting::Cache,
+    /// The libraries of functions that `FCALL` can run.
+    functions: Libraries,
+    /// Whether this server is a master or a replica, and of which master.
+    replication: Replication,
+    /// The connected clients, for `CLIENT LIST`.
+    clients: Clients,
+    /// The commands that took long to run, see [`slowlog`].
+    slowlog: Slowlog,
+    /// Whether the cron removes expired keys, see `DEBUG SET-ACTIVE-EXPIRE`.
+    active_expire: AtomicBool,
+    shutdown: Shutdown,
+    chaos: Chaos,
+}
+
+/// Per-connection state, which lives for as long as the client stays connected.
+#[derive(Debug)]
+struct Connection {
+    /// A unique, monotonically increasing identifier of the client.
+    id: u64,
+    /// The protocol version negotiated with `HELLO`.
+    protocol: Protocol,
+    /// What is on the other end of the connection.
+    link: Link,
+    /// The Pub/Sub channels that the client is subscribed to.
+    subscriptions: Subscriptions,
+    /// Where the [`Broker`] sends the messages published to the `subscriptions`.
+    messages: mpsc::UnboundedSender<Token>,
+    /// How long the replies to a request must be to get compressed, if at all (`EXT.COMPRESS`).
+    compression: Option<usize>,
+    /// The transaction opened with `MULTI`, if any.
+    transaction: Option<Transaction>,
+    /// The keys watched with `WATCH`, which abort the next transaction if they change.
+    watched: Vec<Watch>,
+    /// The port that the client listens on, if it is a replica (`REPLCONF listening-port`).
+    listening_port: Option<u16>,
+    /// Whether the client is exempt from client eviction (`CLIENT NO-EVICT`).
+    no_evict: bool,
+    /// Whether the reads of the client leave the access metadata be (`CLIENT NO-TOUCH`).
+    no_touch: bool,
+    /// What `CLIENT LIST` tells about the connection, see [`Connection::update_info`],
+    /// and the means for `CLIENT KILL` to kill it.
+    client: clients::Handle,
+}
+
+/// What is on the other end of a [`Connection`], as told by the port it came in through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Client,
+    Replica,
+    /// The master that this server replicates, which it connected to by itself.
+    Master,
+}
+
+impl Link {
+    /// How many bytes to read from the socket at once.
+    ///
+    /// Replicas stream whole batches of propagated writes, clients mostly send small commands.
+    const fn read_buffer_size(self) -> usize {
+        match self {
+            Self::Client => 512,
+            Self::Replica | Self::Master => 16 * 1024,
+        }
+    }
+
+    /// How long the peer may stay silent before the connection is dropped.
+    const fn idle_timeout(self) -> Option<Duration> {
+        match self {
+            Self::Client | Self::Master => None,
+            Self::Replica => Some(REPL_TIMEOUT),
+        }
+    }
+
+    /// The kind of client on the other end, as `CLIENT LIST` tells it.
+    const fn kind(self) -> clients::Kind {
+        match self {
+            Self::Client => clients::Kind::Normal,
+            Self::Replica => clients::Kind::Replica,
+            Self::Master => clients::Kind::Master,
+        }
+    }
+}
+
+impl Connection {
+    fn new(id: u64, link: Link, messages: mpsc::UnboundedSender<Token>) -> Self {
+        Self {
+            id,
+            protocol: Protocol::default(),
+            link,
+            subscriptions: Subscriptions::default(),
+            messages,
+            compression: None,
+            transaction: None,
+            watched: vec![],
+            listening_port: None,
+            no_evict: false,
+            no_touch: false,
+            client: clients::Handle::new(clients::Info::new(id, link.kind())),
+        }
+    }
+
+    /// Record the address of the client, and the local one that it connected to.
+    fn set_addrs(&self, (addr, laddr): (SocketAddr, SocketAddr)) {
+        let mut info = self.client.info();
+        (info.addr, info.laddr) = (Some(addr), Some(laddr));
+    }
+
+    /// Record that the client sent the command in `request`, which is about to run.
+    fn touch(&self, request: &[Vec<u8>]) {
+        let mut info = self.client.info();
+        info.last_interaction = Instant::now();
+        info.last_command = command::full_name(request);
+    }
+
+    /// Bring the [`clients::Info`] of the connection up to date, after it ran a command.
+    fn update_info(&self) {
+        let mut info = self.client.info();
+        info.kind = self.link.kind();
+        info.channels = self.subscriptions.iter(Kind::Channel).count();
+        info.patterns = self.subscriptions.iter(Kind::Pattern).count();
+        info.shard_channels = self.subscriptions.iter(Kind::Shard).count();
+        info.multi = self.transaction.as_ref().map(Transaction::queued);
+        info.watched = self.watched.len();
+        info.protocol = self.protocol;
+        info.no_evict = self.no_evict;
+        info.no_touch = self.no_touch;
+    }
+}
+
+/// The [`Database`] as seen by a running command: either locked anew for every access,
+/// or locked once for a whole transaction, so that no other client can interleave with it.
+enum Db<'a> {
+    Shared(&'a Mutex<Database>),
+    Held(MutexGuard<'a, Database>),
+}
+
+/// A locked [`Database`], see [`Db::lock`].
+enum DbGuard<'a> {
+    Locked(MutexGuard<'a, Database>),
+    Held(&'a mut Database),
+}
+
+impl Db<'_> {
+    /// Lock the database, unless it already is.
+    async fn lock(&mut self) -> DbGuard<'_> {
+        match self {
+            Self::Shared(db) => DbGuard::Locked(db.lock().await),
+            Self::Held(db) => DbGuard::Held(db),
+        }
+    }
+
+    /// Whether blocking commands may wait for other clients, which they can't
+    /// while the database stays locked.
+    const fn may_block(&self) -> bool {
+        matches!(self, Self::Shared(_))
+    }
+}
+
+impl Deref for DbGuard<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        match self {
+            Self::Locked(db) => db,
+            Self::Held(db) => db,
+        }
+    }
+}
+
+impl DerefMut for DbGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        match self {
+            Self::Locked(db) => db,
+            Self::Held(db) => db,
+        }
+    }
+}
+
+/// How many entries `XREAD` and `XREADGROUP` read at most, and how long they may block for.
+#[derive(Debug, Clone, Copy)]
+struct ReadOptions {
+    count: Option<usize>,
+    block: Option<Duration>,
+}
+
+impl ReadOptions {
+    /// Never block if the `db` is held by a transaction, see [`Db::may_block`].
+    fn new(count: Option<usize>, block: Option<Duration>, db: &Db<'_>) -> Self {
+        Self {
+            count,
+            block: block.filter(|_| db.may_block()),
+        }
+    }
 }
 
 impl Server {
     /// Construct a new [`Server`].
     pub async fn new(config: Config) -> io::Result<Self> {
-        Ok(Self {
-            db: Arc::new(Mutex::new(Database::new())),
-            listener: TcpListener::bind(LISTEN_ADDR).await?,
+        let replication_listener = match config.replication_port {
+            Some(port) => Some(TcpListener::bind((LISTEN_HOST, port)).await?),
+            None => None,
+        };
+        let key_filter = KeyFilter::new(
+            &config.notify_keyspace_include,
+            &config.notify_keyspace_exclude,
+        );
+        let persistence = Persistence::new(RdbPath {
+            dir: config.dir.clone(),
+            dbfilename: config.dbfilename.clone(),
+        });
+        let aof = Aof::new(
+            config.dir.join(&config.appendfilename),
+            config.appendonly,
+            config.appendfsync,
+        );
+        let replication = Replication::new(config.replicaof.clone());
+        let slowlog = Slowlog::new(config.slowlog_log_slower_than, config.slowlog_max_len);
+        let mut db = Database::new();
+        db.set_notify_events(config.notify_keyspace_events);
+        db.keep_expired(replication.is_replica());
+        let server = Self {
+            events: RwLock::new(config.notify_keyspace_events),
+            db: Arc::new(Mutex::new(db)),
+            listener: TcpListener::bind((LISTEN_HOST, config.port)).await?,
+            replication_listener,
             config,
-        })
+            next_client_id: AtomicU64::new(1),
+            stats: Stats::default(),
+            ttls: TtlHistogram::default(),
+            waiters: Waiters::default(),
+            key_filter: RwLock::new(key_filter),
+            persistence,
+            aof,
+            broker: Broker::default(),
+            scripts: scripting::Cache::default(),
+            functions: Libraries::default(),
+            replication,
+            clients: Clients::default(),
+            slowlog,
+            active_expire: AtomicBool::new(true),
+            shutdown: Shutdown::default(),
+            chaos: Chaos::default(),
+        };
+        server.load().await?;
+        Ok(server)
     }
 
-    /// Handle all incoming connections.
+    /// Load the dataset and the libraries of functions from the RDB file, if there is one,
+    /// or from the AOF instead if it is enabled, see [`aof`].
     ///
-    /// This function runs indefinitely and requires `&self` (The [`Server`])
-    /// To outlive `'static`. It only returns if an error occurs.
+    /// A file that can't be decoded fails the startup rather than silently starting
+    /// with an empty dataset, which the next save would then overwrite it with.
+    async fn load(&self) -> io::Result<()> {
+        if self.aof.is_enabled() {
+            let len = self.load_aof().await?;
+            return self.aof.open(len);
+        }
+        let path = self.persistence.path().file();
+        let Some(contents) = self.persistence.read().await? else {
+            tracing::info!(?path, "No RDB file to load, starting with an empty dataset");
+            return Ok(());
+        };
+        let keys = self.load_rdb(&contents).await.map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {err}"))
+        })?;
+        tracing::info!(?path, keys, "Loaded the dataset from the RDB file");
+        Ok(())
+    }
+
+    /// Replay the writes of the AOF, if there is one, returning how many bytes of it were replayed.
+    async fn load_aof(&self) -> io::Result<usize> {
+        let path = self.aof.path();
+        let Some(contents) = self.aof.read().await? else {
+            tracing::info!(?path, "No AOF to load, starting with an empty dataset");
+            return Ok(0);
+        };
+        let invalid =
+            |err: String| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {err}"));
+        // A rewritten AOF may start with the dataset as an RDB file, see `aof-use-rdb-preamble`.
+        let mut preamble = 0;
+        if contents.starts_with(rdb::MAGIC) {
+            let (file, len) =
+                rdb::read_file_prefix(&contents).map_err(|err| invalid(err.to_string()))?;
+            let keys = self.import_rdb(file).await.map_err(invalid)?;
+            tracing::info!(?path, keys, "Loaded the RDB preamble of the AOF");
+            preamble = len;
+        }
+        let replay = aof::parse(&contents[preamble..]).map_err(invalid)?;
+        // Like the writes of a master, those of the AOF are applied as they come.
+        let (messages, _) = mpsc::unbounded_channel();
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let mut connection = Connection::new(id, Link::Master, messages);
+        let writes = replay.requests.len();
+        for request in replay.requests {
+            let command =
+                Command::try_from(request.clone()).map_err(|err| invalid(err.to_string()))?;
+            let _ = self
+                .dispatch(command, &request, &mut connection)
+                .await
+                .map_err(|err| invalid(err.to_string()))?;
+        }
+        let mut db = self.db.lock().await;
+        let _ = db.take_notifications();
+        // What was just loaded is saved already.
+        let changes = db.dirty();
+        db.saved(changes);
+        tracing::info!(?path, writes, "Loaded the dataset from the AOF");
+        Ok(preamble + replay.len)
+    }
+
+    /// Replace the dataset and the libraries of functions with those of an RDB file,
+    /// returning how many keys were loaded.
+    ///
+    /// Keys that expired since the file was written are skipped.
+    async fn load_rdb(&self, contents: &[u8]) -> Result<usize, String> {
+        let file = rdb::read_file(contents).map_err(|err| err.to_string())?;
+        self.import_rdb(file).await
+    }
+
+    /// Replace the dataset and the libraries of functions with those of a decoded RDB file,
+    /// see [`Server::load_rdb`].
+    async fn import_rdb(&self, file: rdb::File) -> Result<usize, String> {
+        file.functions
+            .iter()
+            .map(|code| functions::load(code, self.script_limits()))
+            .collect::<Result<_, _>>()
+            .and_then(|libraries| self.functions.restore(libraries, RestorePolicy::Flush))?;
+        let now = SystemTime::now();
+        let entries = file
+            .entries
+            .into_iter()
+            .filter(|entry| !entry.is_expired_at(now))
+            .map(rdb::Entry::into_pair)
+            .collect();
+        let mut db = self.db.lock().await;
+        let _ = db.flush();
+        let keys = db.import(entries);
+        // What was just loaded is saved already.
+        let changes = db.dirty();
+        db.saved(changes);
+        Ok(keys)
+    }
+
+    /// Handle all incoming connections, until a shutdown is requested.
+    ///
+    /// This function requires `&self` (The [`Server`]) to outlive `'static`.
+    /// It returns after shutting down, or if an error occurs.
     /// See `main.rs` for an example initialization.
     #[instrument(name = "server", skip(self))]
-    pub async fn run(&'static self) -> anyhow::Result<Infallible> {
+    pub async fn run(&'static self) -> anyhow::Result<Report> {
+        tokio::spawn(self.cron());
+        tokio::spawn(self.handle_signals());
+        tokio::spawn(self.background_saves());
+        tokio::spawn(self.background_rewrites());
+        if self.aof.is_enabled() {
+            tokio::spawn(self.sync_aof());
+        }
+        tokio::spawn(self.replicate());
+        if let Some(listener) = &self.replication_listener {
+            tokio::spawn(async move {
+                if let Err(err) = self.accept(listener, Link::Replica).await {
+                    tracing::error!("Replication listener failed: {err}");
+                }
+            });
+        }
+        let request = tokio::select! {
+            accepted = self.accept(&self.listener, Link::Client) => match accepted? {},
+            request = self.shutdown.requested() => request,
+        };
+        Ok(self.shut_down(request).await)
+    }
+
+    /// Turn the first `SIGTERM` or `SIGINT` into a shutdown request,
+    /// and exit right away on any signal that arrives during the shutdown.
+    async fn handle_signals(&'static self) {
         loop {
-            let (mut socket, _) = self.listener.accept().await?;
+            let name = match shutdown::signal_received().await {
+                Ok(name) => name,
+                Err(err) => {
+                    tracing::error!("Could not listen for signals: {err}");
+                    return;
+                }
+            };
+            if self.shutdown.is_requested() {
+                tracing::warn!(signal = name, "Forcing the shutdown");
+                std::process::exit(shutdown::EXIT_FORCED);
+            }
+            // Like in Redis, only a server that saves by itself saves on its way out too.
+            self.shutdown.request(Request {
+                trigger: Trigger::Signal(name),
+                save: !self.config.save.is_empty(),
+            });
+        }
+    }
+
+    /// Go through the phases of a shutdown, see [`shutdown`].
+    async fn shut_down(&self, request: Request) -> Report {
+        tracing::info!(trigger = %request.trigger, "Shutting down");
+        let mut report = Report::new(request.trigger);
+        if request.save {
+            let mut db = Db::Shared(&self.db);
+            report.save = report.phase("save", self.save(&mut db)).await;
+        }
+        // Every client has been told to disconnect by the request itself.
+        report.clients_disconnected = self.shutdown.clients();
+        report
+            .phase("disconnect", async {
+                let deadline = Instant::now() + shutdown::DISCONNECT_GRACE;
+                while self.shutdown.clients() > 0 && Instant::now() < deadline {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await;
+        report
+    }
+
+    /// Run the saves requested by `BGSAVE`, one at a time.
+    ///
+    /// The dataset is serialized from a [`Snapshot`](crate::database::Snapshot), which
+    /// keeps it consistent without keeping the other clients from writing meanwhile.
+    async fn background_saves(&'static self) {
+        loop {
+            self.persistence.background_save_requested().await;
+            let save = self.save(&mut Db::Shared(&self.db)).await;
+            if let Save::Saved { keys } = save {
+                tracing::info!(keys, "Background saving terminated with success");
+            }
+            self.persistence
+                .finish_background_save(matches!(save, Save::Saved { .. }));
+        }
+    }
+
+    /// Run the rewrites of the AOF requested by `BGREWRITEAOF`, one at a time.
+    ///
+    /// Like a background save, a rewrite serializes a [`Snapshot`], which keeps it consistent
+    /// without keeping the other clients from writing meanwhile, see [`aof`].
+    async fn background_rewrites(&'static self) {
+        loop {
+            self.aof.rewrite_requested().await;
+            let rewritten = self.rewrite_aof().await;
+            match &rewritten {
+                Ok(()) => tracing::info!("Background AOF rewrite terminated with success"),
+                Err(err) => {
+                    let path = self.aof.path();
+                    tracing::error!(?path, "Could not rewrite the AOF: {err}");
+                }
+            }
+            self.aof.finish_rewrite(rewritten.is_ok());
+        }
+    }
+
+    /// Rewrite the AOF as the commands that rebuild the dataset and the libraries of functions,
+    /// or as an RDB file of them with `aof-use-rdb-preamble`.
+    ///
+    /// The writes made after the snapshot are kept aside from the moment it is taken,
+    /// for the rewritten file to end with them.
+    async fn rewrite_aof(&self) -> io::Result<()> {
+        let (snapshot, functions) = {
+            let db = self.db.lock().await;
+            self.aof.begin_rewrite();
+            (db.snapshot(), self.functions.codes())
+        };
+        let contents = task::block_in_place(|| {
+            if !self.config.aof_use_rdb_preamble {
+                return aof::rewrite(&functions, snapshot.iter());
+            }
+            let mut out = vec![];
+            rdb::write_file(
+                &mut out,
+                &[],
+                &functions,
+                snapshot.iter(),
+                self.config.rdbcompression,
+            );
+            out
+        });
+        self.aof.replace(contents).await
+    }
+
+    /// Flush the AOF to the disk every [`aof::FSYNC_PERIOD`], if `appendfsync` is `everysec`.
+    async fn sync_aof(&'static self) {
+        let mut interval = tokio::time::interval(aof::FSYNC_PERIOD);
+        loop {
+            let _ = interval.tick().await;
+            self.aof.sync_pending().await;
+        }
+    }
+
+    /// Save the whole dataset to the RDB file in the configured directory.
+    ///
+    /// The database is only locked for as long as it takes to [snapshot](Database::snapshot),
+    /// so that the clients can go on writing while the snapshot is serialized.
+    async fn save(&self, db: &mut Db<'_>) -> Save {
+        let save = self.persistence.begin_save().await;
+        let (snapshot, changes) = {
+            let db = db.lock().await;
+            (db.snapshot(), db.dirty())
+        };
+        let keys = snapshot.len();
+        let out = self.dump(&snapshot);
+        match save.write(out).await {
+            Ok(()) => {
+                db.lock().await.saved(changes);
+                Save::Saved { keys }
+            }
+            Err(err) => {
+                let path = save.path().file();
+                tracing::error!(?path, "Could not save the dataset: {err}");
+                Save::Failed {
+                    error: err.to_string(),
+                }
+            }
+        }
+    }
+
+    /// Serialize the `snapshot` and the libraries of functions into an RDB file.
+    fn dump(&self, snapshot: &Snapshot) -> Vec<u8> {
+        task::block_in_place(|| {
+            let mut out = vec![];
+            rdb::write_file(
+                &mut out,
+                &[],
+                &self.functions.codes(),
+                snapshot.iter(),
+                self.config.rdbcompression,
+            );
+            out
+        })
+    }
+
+    /// Replicate the `master`, see [`replication`].
+    ///
+    /// Whenever the link breaks, the replica tries again after a delay that doubles with
+    /// every failed attempt, see [`replication::backoff`].
+    async fn replicate(&self) {
+        let mut masters = self.replication.masters();
+        let mut delay = replication::MIN_RECONNECT_DELAY;
+        loop {
+            let master = masters.borrow_and_update().clone();
+            let Some(master) = master else {
+                if masters.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            };
+            tracing::info!(%master, "Connecting to the master");
+            match self.follow(&master, &mut masters).await {
+                // The master changed already, so there is nothing to wait for.
+                Ok(()) => {
+                    delay = replication::MIN_RECONNECT_DELAY;
+                    continue;
+                }
+                Err(err) => tracing::error!(%master, "Replication failed: {err}"),
+            }
+            // A link that was up before it broke starts the backoff over.
+            if self.replication.is_link_up() {
+                delay = replication::MIN_RECONNECT_DELAY;
+                self.replication.set_link_up(false);
+            }
+            tracing::info!(%master, ?delay, "Reconnecting to the master");
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => delay = replication::backoff(delay),
+                changed = masters.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    delay = replication::MIN_RECONNECT_DELAY;
+                }
+            }
+        }
+    }
+
+    /// Synchronize with the `master`, then apply the commands it propagates until the link
+    /// breaks, or until the master changes (see `REPLICAOF`), which returns with `Ok`.
+    async fn follow(
+        &self,
+        master: &ReplicaOf,
+        masters: &mut watch::Receiver<Option<ReplicaOf>>,
+    ) -> anyhow::Result<()> {
+        let sync = async {
+            let mut link = Client::connect((master.host.as_str(), master.port)).await?;
+            let sync = replication::handshake(&mut link, self.config.port).await?;
+            let keys = self
+                .load_rdb(&sync.rdb)
+                .await
+                .map_err(|err| anyhow::anyhow!("Invalid RDB file from the master: {err}"))?;
+            tracing::info!(keys, replid = sync.replid, "Synchronized with the master");
+            anyhow::Ok((link, sync))
+        };
+        let (mut link, sync) = tokio::select! {
+            sync = sync => sync?,
+            _ = masters.changed() => return Ok(()),
+        };
+        self.replication.synced(sync.replid, sync.offset);
+        // The AOF has to start over from the dataset of the master.
+        if self.aof.is_enabled() {
+            let _ = self.aof.request_rewrite(true);
+        }
+
+        let (messages, _) = mpsc::unbounded_channel();
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let mut connection = Connection::new(id, Link::Master, messages);
+        connection.set_addrs(link.addrs()?);
+        let _registered = self.clients.register(&connection.client);
+        let mut killed = connection.client.killed();
+        let mut acks = tokio::time::interval(replication::ACK_PERIOD);
+        loop {
+            let (request, len) = tokio::select! {
+                read = link.read_counted() => read?,
+                _ = masters.changed() => return Ok(()),
+                _ = killed.changed() => anyhow::bail!("The link to the master was killed"),
+                _ = acks.tick() => {
+                    self.ack(&mut link).await?;
+                    continue;
+                }
+            };
+            let parsed = command::arguments(request)
+                .and_then(|args| Ok((Command::try_from(args.clone())?, args)));
+            let (command, args) = match parsed {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    tracing::warn!("Skipping a command from the master: {err}");
+                    self.replication.processed(len);
+                    continue;
+                }
+            };
+            // The master does not expect replies, except to `REPLCONF GETACK`.
+            if command == Command::ReplConfGetAck {
+                self.ack(&mut link).await?;
+            } else {
+                connection.touch(&args);
+                let _ = self.dispatch(command, &args, &mut connection).await?;
+            }
+            self.replication.processed(len);
+            self.publish_notifications().await;
+        }
+    }
+
+    /// Tell the master how much of the propagated commands this replica processed.
+    async fn ack(&self, master: &mut Client) -> anyhow::Result<()> {
+        let offset = self.replication.offset().to_string();
+        master.send(&["REPLCONF", "ACK", &offset]).await
+    }
+
+    /// Accept connections from `listener`, treating them all as the given kind of [`Link`].
+    async fn accept(
+        &'static self,
+        listener: &TcpListener,
+        link: Link,
+    ) -> anyhow::Result<Infallible> {
+        loop {
+            let (mut socket, _) = listener.accept().await?;
+            self.stats.incr(Counter::ConnectionsReceived);
             tokio::spawn(async move {
-                match self.handle_client(&mut socket).await {
+                match self.handle_client(&mut socket, link).await {
                     Ok(_) => {}
                     Err(err) => tracing::error!("{err}"),
                 }
@@ -52,52 +743,1616 @@ impl Server {
         }
     }
 
-    /// Execute a [`Command`] on the contained [`Database`].
-    #[instrument(skip(self, stream))]
-    async fn exec(&self, command: Command, stream: &mut TcpStream) -> anyhow::Result<()> {
-        match command {
-            Command::Ping => {
-                let _ = stream
-                    .write(format!("{SIMPLE_STRING_START}PONG{CRLF}").as_bytes())
-                    .await?;
+    /// Run the periodic background tasks, every [`CRON_PERIOD`].
+    async fn cron(&self) {
+        let mut interval = tokio::time::interval(CRON_PERIOD);
+        loop {
+            let _ = interval.tick().await;
+            self.stats.aggregate();
+            self.ttls.decay(CRON_PERIOD);
+            let mut db = self.db.lock().await;
+            // Keys expiring during a pause would change the dataset behind its back.
+            if self.active_expire.load(Ordering::Relaxed) && !self.clients.is_paused() {
+                let _ = db.expire_cycle(ACTIVE_EXPIRE_LIMIT);
             }
-            Command::Echo { message } => {
-                let _ = stream
-                    .write((format!("{SIMPLE_STRING_START}{message}{CRLF}")).as_bytes())
-                    .await?;
+            self.propagate_expired(&mut db);
+            drop(db);
+            self.publish_notifications().await;
+            self.check_save_points().await;
+        }
+    }
+
+    /// Start a background save if one of the save points is reached, see [`SavePoints`].
+    ///
+    /// [`SavePoints`]: crate::persistence::SavePoints
+    async fn check_save_points(&self) {
+        if self.config.save.is_empty() || !self.persistence.may_save_automatically() {
+            return;
+        }
+        let changes = self.db.lock().await.dirty();
+        let elapsed = SystemTime::now()
+            .duration_since(self.persistence.last_save())
+            .unwrap_or_default();
+        if self.config.save.reached(changes, elapsed)
+            && self.persistence.request_background_save(false).is_ok()
+        {
+            tracing::info!(
+                changes,
+                seconds = elapsed.as_secs(),
+                "Save point reached, saving in the background"
+            );
+        }
+    }
+
+    /// Run a [`Command`] sent by the client, parsed from its `request`,
+    /// or queue it if a transaction is open.
+    async fn dispatch(
+        &self,
+        command: Command,
+        request: &[Vec<u8>],
+        connection: &mut Connection,
+    ) -> anyhow::Result<Vec<Token>> {
+        if let Some(denied) = self.denied(&command, connection) {
+            // Like a command that can't be queued, this dooms the whole transaction.
+            if let Some(transaction) = &mut connection.transaction {
+                transaction.abort();
+            }
+            return Ok(vec![transaction::error(denied)]);
+        }
+        let reply = match (command, &mut connection.transaction) {
+            (Command::Multi, None) => {
+                connection.transaction = Some(Transaction::default());
+                ok()
+            }
+            (Command::Multi, Some(_)) => transaction::error(transaction::NESTED),
+            (Command::Exec, None) => transaction::error(transaction::EXEC_WITHOUT_MULTI),
+            (Command::Discard, None) => transaction::error(transaction::DISCARD_WITHOUT_MULTI),
+            (Command::Discard, Some(_)) => {
+                connection.transaction = None;
+                self.unwatch_all(connection, &mut Db::Shared(&self.db))
+                    .await;
+                ok()
+            }
+            (Command::Exec, Some(_)) => {
+                let transaction = connection.transaction.take().unwrap_or_default();
+                self.exec_all(transaction, connection).await
+            }
+            (command, Some(transaction)) if !transaction::runs_immediately(&command) => {
+                transaction.queue(command, request.to_vec())
+            }
+            (command, _) if !command.is_write() || command.may_block() => {
+                let is_write = command.is_write();
+                let replies = self
+                    .exec(command, request, connection, &mut Db::Shared(&self.db))
+                    .await;
+                if is_write {
+                    self.propagate(request, &replies, &mut Db::Shared(&self.db))
+                        .await;
+                }
+                return Ok(replies);
+            }
+            (command, _) => {
+                // The database stays locked until the write is propagated, see `replication`.
+                let mut db = Db::Held(self.db.lock().await);
+                let replies = self.exec(command, request, connection, &mut db).await;
+                self.propagate(request, &replies, &mut db).await;
+                return Ok(replies);
+            }
+        };
+        Ok(vec![reply])
+    }
+
+    /// Record an access to the keys of the `request`, see [`Command::touches_keys`].
+    async fn touch(&self, request: &[Vec<u8>], db: &mut Db<'_>) {
+        let args: Vec<String> = request
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let Ok(keys) = command::keys(&args) else {
+            return;
+        };
+        let mut db = db.lock().await;
+        for key in keys {
+            db.access(&key);
+        }
+    }
+
+    /// The error to reply with if the `connection` may not run the `command`.
+    ///
+    /// On a master, writes need `min-replicas-to-write` good replicas to go through.
+    /// On a replica, writes are only for the master, see [`replication::READONLY`],
+    /// and most commands wait for the master to be back unless stale data may be served.
+    fn denied(&self, command: &Command, connection: &Connection) -> Option<&'static str> {
+        if !self.replication.is_replica() {
+            let min = self.config.min_replicas_to_write;
+            let max_lag = Duration::from_secs(self.config.min_replicas_max_lag);
+            let enough = min == 0 || self.replication.good_replicas(max_lag) >= min;
+            return (!enough && command.is_write()).then_some(replication::NOREPLICAS);
+        }
+        if connection.link == Link::Master {
+            return None;
+        }
+        if !self.config.replica_serve_stale_data
+            && !self.replication.is_link_up()
+            && !replication::serves_stale(command)
+        {
+            return Some(replication::MASTERDOWN);
+        }
+        command.is_write().then_some(replication::READONLY)
+    }
+
+    /// Propagate a write to the replicas, given as its `request`, unless it failed,
+    /// see [`replication::effect`].
+    ///
+    /// The keys that expired since the last propagation go first, as the write may well
+    /// depend on their removal.
+    async fn propagate(&self, request: &[Vec<u8>], replies: &[Token], db: &mut Db<'_>) {
+        self.propagate_expired(&mut *db.lock().await);
+        if matches!(replies, [Token::SimpleError { .. }]) {
+            return;
+        }
+        if let Some(effect) =
+            replication::effect(request, replies, rdb::unix_millis(SystemTime::now()))
+        {
+            self.propagate_write(&effect);
+        }
+    }
+
+    /// Send a write, as it is to be replayed, to the replicas and to the AOF.
+    fn propagate_write(&self, request: &[Vec<u8>]) {
+        self.replication.propagate(request);
+        self.aof.append(request);
+    }
+
+    /// Propagate a `DEL` for every key that expired since the last time, as replicas
+    /// leave expired keys for their master to remove.
+    fn propagate_expired(&self, db: &mut Database) {
+        for key in db.take_expired() {
+            self.propagate_write(&[b"DEL".to_vec(), key.into_bytes()]);
+        }
+    }
+
+    /// Wait until at least `replicas` replicas acknowledged all the writes propagated so far,
+    /// asking them to unless enough of them did already, or until `timeout` runs out.
+    /// Returns how many replicas acknowledged the writes.
+    async fn wait(&self, replicas: usize, timeout: Duration, db: &Db<'_>) -> usize {
+        let offset = self.replication.offset();
+        let acked = self.replication.acked(offset);
+        // Like blocking commands, `WAIT` can't wait inside a transaction.
+        if acked >= replicas || !db.may_block() {
+            return acked;
+        }
+        let mut acks = self.replication.acks();
+        self.replication.request_acks();
+        let deadline = (!timeout.is_zero()).then(|| tokio::time::Instant::now() + timeout);
+        loop {
+            let acked = self.replication.acked(offset);
+            if acked >= replicas {
+                return acked;
+            }
+            let changed = acks.changed();
+            let done = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, changed).await.is_err(),
+                None => changed.await.is_err(),
+            };
+            if done {
+                return self.replication.acked(offset);
+            }
+        }
+    }
+
+    /// Run the commands of a `transaction` under a single lock of the [`Database`],
+    /// unless any of the keys watched by the client changed, producing the reply of `EXEC`.
+    ///
+    /// A command that fails doesn't stop the others, its error is just its reply.
+    async fn exec_all(&self, transaction: Transaction, connection: &mut Connection) -> Token {
+        let mut db = Db::Held(self.db.lock().await);
+        let touched = db.lock().await.touched(&connection.watched);
+        self.unwatch_all(connection, &mut db).await;
+        let commands = match transaction.into_commands() {
+            Ok(_) if touched => return Token::NullArray,
+            Ok(commands) => commands,
+            Err(reply) => return reply,
+        };
+        let mut replies = Vec::with_capacity(commands.len());
+        // The writes are propagated once they all ran, so that replicas get all of them or none.
+        let mut writes = vec![];
+        for (command, request) in commands {
+            let is_write = command.is_write();
+            let reply = self.exec(command, &request, connection, &mut db).await;
+            if is_write {
+                writes.push((request, reply.clone()));
+            }
+            replies.push(transaction::merge(reply));
+        }
+        if !writes.is_empty() {
+            self.propagate_write(&[b"MULTI".to_vec()]);
+            for (request, reply) in &writes {
+                self.propagate(request, reply, &mut db).await;
+            }
+            self.propagate_write(&[b"EXEC".to_vec()]);
+        }
+        Token::Array { tokens: replies }
+    }
+
+    /// The [`Limits`] of every script and function, as configured.
+    const fn script_limits(&self) -> Limits {
+        Limits {
+            memory: self.config.script_memory_limit,
+            instructions: self.config.script_instruction_limit,
+        }
+    }
+
+    /// Run a Lua `script`, which may not run write commands if it is `read_only`.
+    async fn run_script(
+        &self,
+        script: Script<'_>,
+        keys: Vec<String>,
+        args: Vec<String>,
+        read_only: bool,
+        connection: &mut Connection,
+        db: &mut Db<'_>,
+    ) -> Token {
+        // Like transactions, scripts keep the database locked for as long as they run.
+        let mut held;
+        let db = if let Db::Shared(shared) = *db {
+            held = Db::Held(shared.lock().await);
+            &mut held
+        } else {
+            db
+        };
+        let limits = self.script_limits();
+        let handle = Handle::current();
+        task::block_in_place(|| {
+            scripting::run(script, keys, args, limits, &mut |args| {
+                handle.block_on(self.script_call(args, read_only, connection, db))
+            })
+        })
+    }
+
+    /// Run a command on behalf of a script, see [`scripting::run`].
+    async fn script_call(
+        &self,
+        args: Vec<String>,
+        read_only: bool,
+        connection: &mut Connection,
+        db: &mut Db<'_>,
+    ) -> Token {
+        let args: Vec<Vec<u8>> = args.into_iter().map(String::into_bytes).collect();
+        let command = match Command::try_from(args.clone()) {
+            Ok(command) if !scripting::allowed(&command) => {
+                return transaction::error(scripting::NOT_ALLOWED)
+            }
+            Ok(command) if read_only && command.is_write() => {
+                return transaction::error(scripting::READ_ONLY)
+            }
+            Ok(command) => match self.denied(&command, connection) {
+                Some(denied) => return transaction::error(denied),
+                None => command,
+            },
+            Err(command::ParseError::UnknownCommand(_)) => {
+                return transaction::error(scripting::UNKNOWN_COMMAND)
+            }
+            Err(err) => return transaction::error(&format!("ERR {err}")),
+        };
+        let is_write = command.is_write();
+        let replies = self.exec(command, &args, connection, db).await;
+        if is_write {
+            self.propagate(&args, &replies, db).await;
+        }
+        transaction::merge(replies)
+    }
+
+    /// Execute a [`Command`], parsed from its `request`, on the contained [`Database`],
+    /// producing its replies.
+    ///
+    /// Most commands have a single reply, but e.g. `SUBSCRIBE` confirms every channel separately.
+    #[instrument(skip(self, request, connection, db))]
+    async fn exec(
+        &self,
+        command: Command,
+        request: &[Vec<u8>],
+        connection: &mut Connection,
+        db: &mut Db<'_>,
+    ) -> Vec<Token> {
+        self.stats.incr(Counter::CommandsProcessed);
+        if command.touches_keys() && !connection.no_touch {
+            self.touch(request, db).await;
+        }
+        let reply = match command {
+            Command::Ping { message }
+                if connection.subscriptions.restricts(connection.protocol) =>
+            {
+                pubsub::pong(message)
+            }
+            Command::Ping { message: None } => Token::SimpleString {
+                data: "PONG".to_string(),
+            },
+            Command::Ping {
+                message: Some(message),
+            } => Token::from(message),
+            Command::Quit => ok(),
+            Command::Reset => {
+                self.reset(connection, db).await;
+                Token::SimpleString {
+                    data: "RESET".to_string(),
+                }
+            }
+            Command::Watch { .. } if connection.transaction.is_some() => {
+                transaction::error(transaction::WATCH_INSIDE_MULTI)
+            }
+            Command::Watch { keys } => {
+                let mut db = db.lock().await;
+                for key in keys {
+                    if !connection.watched.iter().any(|watch| watch.key == key) {
+                        connection.watched.push(db.watch(key));
+                    }
+                }
+                ok()
+            }
+            Command::Unwatch => {
+                self.unwatch_all(connection, db).await;
+                ok()
+            }
+            Command::Eval { script, keys, args } => {
+                self.scripts.load(&script);
+                let script = Script::Eval(&script);
+                self.run_script(script, keys, args, false, connection, db)
+                    .await
+            }
+            Command::EvalSha { sha1, keys, args } => match self.scripts.get(&sha1) {
+                Some(script) => {
+                    let script = Script::Eval(&script);
+                    self.run_script(script, keys, args, false, connection, db)
+                        .await
+                }
+                None => transaction::error(scripting::NO_SCRIPT),
+            },
+            Command::ScriptLoad { script } => Token::from(self.scripts.load(&script)),
+            Command::ScriptExists { sha1s } => Token::Array {
+                tokens: sha1s
+                    .iter()
+                    .map(|sha1| integer(self.scripts.get(sha1).is_some().into()))
+                    .collect(),
+            },
+            Command::ScriptFlush => {
+                self.scripts.flush();
+                ok()
+            }
+            Command::FunctionLoad { code, replace } => {
+                match functions::load(&code, self.script_limits()) {
+                    Ok(library) => {
+                        let name = library.name.clone();
+                        match self.functions.insert(vec![library], replace) {
+                            Ok(()) => Token::from(name),
+                            Err(err) => transaction::error(&err),
+                        }
+                    }
+                    Err(err) => transaction::error(&err),
+                }
+            }
+            Command::FunctionList { pattern, with_code } => {
+                functions::list_reply(self.functions.list(pattern.as_deref()), with_code)
+            }
+            Command::FunctionDump => Token::from(functions::dump(&self.functions.codes())),
+            Command::FunctionRestore { payload, policy } => {
+                let libraries = functions::undump(&payload).and_then(|codes| {
+                    codes
+                        .iter()
+                        .map(|code| functions::load(code, self.script_limits()))
+                        .collect()
+                });
+                match libraries.and_then(|libraries| self.functions.restore(libraries, policy)) {
+                    Ok(()) => ok(),
+                    Err(err) => transaction::error(&err),
+                }
+            }
+            Command::FunctionFlush => {
+                self.functions.take();
+                ok()
+            }
+            Command::FCall {
+                function: name,
+                keys,
+                args,
+                read_only,
+            } => match self.functions.function(&name) {
+                None => transaction::error(functions::NOT_FOUND),
+                Some((_, function)) if read_only && !function.is_read_only() => {
+                    transaction::error(functions::WRITE_FUNCTION)
+                }
+                Some((library, function)) => {
+                    let script = Script::Function {
+                        library: &library,
+                        name: &name,
+                    };
+                    let read_only = function.is_read_only();
+                    self.run_script(script, keys, args, read_only, connection, db)
+                        .await
+                }
+            },
+            Command::Multi | Command::Exec | Command::Discard => {
+                unreachable!("transactions are handled by `Server::dispatch`, and never queued")
+            }
+            Command::Subscribe { channels } => {
+                return self.subscribe(connection, Kind::Channel, channels)
+            }
+            Command::Unsubscribe { channels } => {
+                return self.unsubscribe(connection, Kind::Channel, channels)
+            }
+            Command::PSubscribe { patterns } => {
+                return self.subscribe(connection, Kind::Pattern, patterns)
+            }
+            Command::PUnsubscribe { patterns } => {
+                return self.unsubscribe(connection, Kind::Pattern, patterns)
+            }
+            Command::SSubscribe { channels } => {
+                return self.subscribe(connection, Kind::Shard, channels)
+            }
+            Command::SUnsubscribe { channels } => {
+                return self.unsubscribe(connection, Kind::Shard, channels)
+            }
+            Command::SPublish { channel, message } => {
+                integer(self.broker.publish_shard(&channel, &message) as u64)
+            }
+            Command::PubSubChannels { pattern } => Token::Array {
+                tokens: self
+                    .broker
+                    .channels(pattern.as_deref())
+                    .into_iter()
+                    .map(Token::from)
+                    .collect(),
+            },
+            Command::PubSubNumSub { channels } => Token::Map {
+                pairs: channels
+                    .into_iter()
+                    .map(|channel| {
+                        let count = self.broker.subscriber_count(&channel);
+                        (Token::from(channel), integer(count as u64))
+                    })
+                    .collect(),
+            },
+            Command::PubSubNumPat => integer(self.broker.pattern_count() as u64),
+            Command::Publish { channel, message } => {
+                integer(self.broker.publish(&channel, &message) as u64)
+            }
+            Command::Echo { message } => Token::SimpleString { data: message },
+            Command::Hello { version } => {
+                match version.map(|version| Protocol::from_version(version).ok_or(version)) {
+                    Some(Err(_)) => Token::SimpleError {
+                        data: "NOPROTO unsupported protocol version".to_string(),
+                    },
+                    Some(Ok(protocol)) => {
+                        connection.protocol = protocol;
+                        hello(connection, self.replication.is_replica())
+                    }
+                    None => hello(connection, self.replication.is_replica()),
+                }
+            }
+            Command::Shutdown { save } => {
+                self.shutdown.request(Request {
+                    trigger: Trigger::Command,
+                    save,
+                });
+                Token::SimpleString {
+                    data: "OK".to_string(),
+                }
+            }
+            Command::Save if self.persistence.is_background_saving() => {
+                transaction::error(&persistence::Error::BackgroundSaveInProgress.to_string())
+            }
+            Command::Save => match self.save(db).await {
+                Save::Failed { error } => transaction::error(&format!("ERR {error}")),
+                Save::Saved { .. } | Save::Skipped => ok(),
+            },
+            Command::BgSave { schedule } => {
+                match self.persistence.request_background_save(schedule) {
+                    Ok(BackgroundSave::Started) => Token::SimpleString {
+                        data: "Background saving started".to_string(),
+                    },
+                    Ok(BackgroundSave::Scheduled) => Token::SimpleString {
+                        data: "Background saving scheduled".to_string(),
+                    },
+                    Err(err) => transaction::error(&err.to_string()),
+                }
+            }
+            Command::BgRewriteAof => match self.aof.request_rewrite(false) {
+                Ok(()) => Token::SimpleString {
+                    data: "Background append only file rewriting started".to_string(),
+                },
+                Err(err) => transaction::error(&err.to_string()),
+            },
+            Command::LastSave => integer(
+                self.persistence
+                    .last_save()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            ),
+            Command::Info { section } => Token::BulkString {
+                data: self.info(section.as_deref(), db).await,
+            },
+            Command::ReplConf { listening_port, .. } => {
+                if listening_port.is_some() {
+                    connection.listening_port = listening_port;
+                }
+                ok()
             }
+            // Only the master gets an answer, see `Server::follow`.
+            Command::ReplConfGetAck => return vec![],
+            Command::ReplConfAck { offset } => {
+                self.replication.ack(connection.id, offset);
+                return vec![];
+            }
+            Command::Role => self.replication.role(),
+            Command::ClientList { filter } => {
+                connection.update_info();
+                let list = self.clients.list(filter.as_ref());
+                Token::from(
+                    list.iter()
+                        .map(|info| format!("{info}\n"))
+                        .collect::<String>(),
+                )
+            }
+            Command::ClientSetName { name } if !clients::valid_name(&name) => {
+                transaction::error(clients::INVALID_NAME)
+            }
+            Command::ClientSetName { name } => {
+                connection.client.info().name = name;
+                ok()
+            }
+            Command::ClientGetName => match connection.client.info().name.as_str() {
+                "" => Token::NullBulkString,
+                name => Token::from(name.to_string()),
+            },
+            Command::ClientId => integer(connection.id),
+            Command::ClientKill { filters, skip_me } => {
+                let spared = skip_me.then_some(connection.id);
+                Token::from(self.clients.kill(&filters, spared))
+            }
+            Command::ClientPause { timeout, pause } => {
+                self.clients.pause(pause, timeout);
+                ok()
+            }
+            Command::ClientUnpause => {
+                self.clients.unpause();
+                ok()
+            }
+            Command::ClientNoEvict { enabled } => {
+                connection.no_evict = enabled;
+                ok()
+            }
+            Command::ClientNoTouch { enabled } => {
+                connection.no_touch = enabled;
+                ok()
+            }
+            Command::SlowlogGet { count } => self.slowlog.get(count),
+            Command::SlowlogLen => Token::from(self.slowlog.count()),
+            Command::SlowlogReset => {
+                self.slowlog.reset();
+                ok()
+            }
+            Command::SlowlogHelp => Token::Array {
+                tokens: slowlog::HELP
+                    .iter()
+                    .map(|line| Token::SimpleString {
+                        data: line.to_string(),
+                    })
+                    .collect(),
+            },
+            Command::ClientKillAddr { addr } => {
+                let filters = [clients::Filter::Addr(addr)];
+                match self.clients.kill(&filters, None) {
+                    0 => transaction::error(clients::NO_SUCH_CLIENT),
+                    _ => ok(),
+                }
+            }
+            Command::ClientInfo => {
+                connection.update_info();
+                Token::from(format!("{}\n", *connection.client.info()))
+            }
+            Command::ReplicaOf { master } => {
+                if master.is_some() && master == self.replication.master() {
+                    Token::SimpleString {
+                        data: "OK Already connected to specified master".to_string(),
+                    }
+                } else {
+                    db.lock().await.keep_expired(master.is_some());
+                    let _ = self.replication.set_master(master);
+                    ok()
+                }
+            }
+            Command::Wait { .. } if self.replication.is_replica() => {
+                transaction::error(replication::WAIT_ON_REPLICA)
+            }
+            Command::Wait { replicas, timeout } => {
+                integer(self.wait(replicas, timeout, db).await as u64)
+            }
+            // Outside of transactions and scripts, `Server::serve_client` syncs the replica.
+            Command::PSync { .. } => transaction::error(replication::PSYNC_NOT_ALLOWED),
             Command::Set { key, value } => {
-                self.db.lock().await.set(key, value);
-                let _ = stream
-                    .write((format!("{SIMPLE_STRING_START}OK{CRLF}")).as_bytes())
-                    .await?;
+                if let Some(ttl) = value.ttl_remaining() {
+                    self.ttls.record(ttl);
+                }
+                db.lock().await.set(key, value);
+                Token::SimpleString {
+                    data: "OK".to_string(),
+                }
+            }
+            Command::WhenExpires { key } => expiry_report(db.lock().await.when_expires(&key)),
+            Command::ObjectIdleTime { key } => Token::from(
+                db.lock()
+                    .await
+                    .access_metadata(&key)
+                    .map(|(idle, _)| idle.as_secs() as i64),
+            ),
+            Command::ObjectFreq { key } => Token::from(
+                db.lock()
+                    .await
+                    .access_metadata(&key)
+                    .map(|(_, frequency)| i64::from(frequency)),
+            ),
+            Command::MemoryUsage { key, samples } => {
+                Token::from(db.lock().await.memory_usage(&key, samples))
+            }
+            Command::MemoryStats => db.lock().await.memory_stats().reply(),
+            Command::DebugSleep { duration } => {
+                // Holding the database is what stalls every other client.
+                let _db = db.lock().await;
+                tokio::time::sleep(duration).await;
+                ok()
+            }
+            Command::DebugObject { key } => match db.lock().await.object_info(&key) {
+                Some(info) => Token::SimpleString {
+                    data: info.to_string(),
+                },
+                None => Error::NoSuchKey.into(),
+            },
+            Command::DebugSetActiveExpire { enabled } => {
+                self.active_expire.store(enabled, Ordering::Relaxed);
+                ok()
+            }
+            Command::DebugJmap => {
+                let stats = db.lock().await.memory_stats();
+                tracing::info!(
+                    keys = stats.keys,
+                    dataset = stats.dataset,
+                    overhead = stats.overhead,
+                    "Estimated memory usage"
+                );
+                ok()
             }
+            Command::Import { entries } => Token::from(db.lock().await.import(entries)),
             Command::Get { key } => {
-                let db = self.db.lock().await;
-                let response: String = match db.get(&key) {
-                    Ok(value) => format!("+{}", value.data),
-                    Err(Error::KeyNotFound) => "-Key not found".to_string(),
-                    Err(Error::Expired) => "$-1".to_string(),
+                let mut db = db.lock().await;
+                // The access is recorded along with those of the other reads, see `exec`.
+                match db.peek(&key) {
+                    Ok(Value {
+                        data: Data::String(data),
+                        ..
+                    }) => Token::BulkBytes { data: data.clone() },
+                    Ok(_) => Error::WrongType.into(),
+                    Err(Error::KeyNotFound) => Token::SimpleError {
+                        data: "Key not found".to_string(),
+                    },
+                    Err(Error::Expired) => Token::NullBulkString,
+                    Err(err) => err.into(),
+                }
+            }
+            Command::Type { key } => Token::SimpleString {
+                data: db.lock().await.key_type(&key).to_string(),
+            },
+            Command::PTtl { key } => Token::from(db.lock().await.pttl(&key)),
+            Command::Keys { pattern } => Token::from(db.lock().await.keys(&pattern)),
+            Command::Del { keys } => Token::from(db.lock().await.del(&keys)),
+            Command::Scan { cursor, options } => {
+                let (cursor, keys) = db.lock().await.scan(cursor, &options);
+                Token::Array {
+                    tokens: vec![Token::from(cursor.to_string()), Token::from(keys)],
+                }
+            }
+            Command::SnapshotGet { at, key } => {
+                let Some(dir) = self.config.snapshot_dir.clone() else {
+                    return vec![Token::SimpleError {
+                        data: "ERR snapshot serving is disabled, set snapshot-dir".to_string(),
+                    }];
+                };
+                let at = UNIX_EPOCH + Duration::from_secs(at);
+                let found = tokio::task::spawn_blocking(move || snapshot::get(&dir, at, &key));
+                match found.await {
+                    Ok(Ok(Some(Data::String(data)))) => Token::BulkBytes { data },
+                    Ok(Ok(Some(_))) => Error::WrongType.into(),
+                    Ok(Ok(None)) => Token::NullBulkString,
+                    Ok(Err(err)) => Token::SimpleError {
+                        data: err.to_string(),
+                    },
+                    Err(err) => transaction::error(&format!("ERR {err}")),
+                }
+            }
+            Command::CompareAndSet {
+                key,
+                expected,
+                new,
+                ttl,
+            } => {
+                let result = db.lock().await.compare_and_set(&key, &expected, new, ttl);
+                if let (Ok(true), Some(ttl)) = (&result, ttl) {
+                    self.ttls.record(ttl);
+                }
+                reply(result)
+            }
+            Command::Compress { min_len } => {
+                connection.compression = min_len;
+                ok()
+            }
+            Command::Sequence { key, count } => {
+                let result = db.lock().await.sequence(key, count);
+                reply(result.map(|(first, last)| vec![Token::from(first), Token::from(last)]))
+            }
+            Command::CommandCount => Token::from(command::COMMANDS.len()),
+            Command::CommandInfo { names } if names.is_empty() => Token::Array {
+                tokens: command::COMMANDS.iter().map(command::Spec::info).collect(),
+            },
+            Command::CommandInfo { names } => Token::Array {
+                tokens: names
+                    .iter()
+                    .map(|name| {
+                        command::lookup(name).map_or(Token::NullBulkString, command::Spec::info)
+                    })
+                    .collect(),
+            },
+            Command::CommandDocs { names } => {
+                let specs: Vec<&command::Spec> = if names.is_empty() {
+                    command::COMMANDS.iter().collect()
+                } else {
+                    names
+                        .iter()
+                        .filter_map(|name| command::lookup(name))
+                        .collect()
                 };
-                let _ = stream.write(format!("{response}{CRLF}").as_bytes()).await?;
+                Token::Map {
+                    pairs: specs
+                        .into_iter()
+                        .map(|spec| (Token::from(spec.name.to_string()), spec.docs()))
+                        .collect(),
+                }
             }
+            Command::CommandGetKeys { args } => match command::keys(&args) {
+                Ok(keys) => Token::from(keys),
+                Err(error) => transaction::error(&error.to_string()),
+            },
             Command::ConfigGet { key } => {
-                let response = Token::Array {
-                    tokens: vec![
-                        Token::BulkString { data: key.clone() },
-                        Token::BulkString {
-                            data: match key.as_str() {
-                                "dir" => self.config.dir.to_string_lossy().to_string(),
-                                "filename" => self.config.dbfilename.to_string_lossy().to_string(),
-                                _ => return Err(command::ParseError::MissingArgument.into()),
-                            },
+                let value = match key.as_str() {
+                    "dir" => self.persistence.path().dir.to_string_lossy().to_string(),
+                    "dbfilename" => self
+                        .persistence
+                        .path()
+                        .dbfilename
+                        .to_string_lossy()
+                        .to_string(),
+                    "save" => self.config.save.to_string(),
+                    "rdbcompression" => if self.config.rdbcompression {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                    .to_string(),
+                    "appendonly" => if self.aof.is_enabled() { "yes" } else { "no" }.to_string(),
+                    "appendfilename" => self.config.appendfilename.to_string_lossy().to_string(),
+                    "appendfsync" => self.aof.fsync().to_string(),
+                    "aof-use-rdb-preamble" => if self.config.aof_use_rdb_preamble {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                    .to_string(),
+                    "loglevel" => self.config.loglevel.to_string(),
+                    "notify-keyspace-events" => self.events().to_string(),
+                    "notify-keyspace-include" => self.key_filter().include().to_string(),
+                    "notify-keyspace-exclude" => self.key_filter().exclude().to_string(),
+                    "min-replicas-to-write" => self.config.min_replicas_to_write.to_string(),
+                    "min-replicas-max-lag" => self.config.min_replicas_max_lag.to_string(),
+                    "slowlog-log-slower-than" => self.slowlog.slower_than().to_string(),
+                    "slowlog-max-len" => self.slowlog.max_len().to_string(),
+                    // Like a pattern that matches no parameter.
+                    _ => return vec![Token::Array { tokens: vec![] }],
+                };
+                Token::Array {
+                    tokens: vec![Token::from(key), Token::from(value)],
+                }
+            }
+            Command::ConfigSet { parameters } => self.config_set(&parameters, db).await,
+            Command::SAdd { key, members } => reply(db.lock().await.sadd(key, members)),
+            Command::SRem { key, members } => reply(db.lock().await.srem(&key, &members)),
+            Command::SMove {
+                source,
+                destination,
+                member,
+            } => reply(db.lock().await.smove(&source, destination, member)),
+            Command::SMembers { key } => reply(db.lock().await.smembers(&key)),
+            Command::SIsMember { key, member } => reply(db.lock().await.sismember(&key, &member)),
+            Command::SMIsMember { key, members } => {
+                reply(db.lock().await.smismember(&key, &members))
+            }
+            Command::SCard { key } => reply(db.lock().await.scard(&key)),
+            Command::SInterCard { keys, limit } => reply(db.lock().await.sintercard(&keys, limit)),
+            Command::SPop { key, count: None } => reply(db.lock().await.spop(&key, 1).map(first)),
+            Command::SPop {
+                key,
+                count: Some(count),
+            } => reply(db.lock().await.spop(&key, count)),
+            Command::SRandMember { key, count: None } => {
+                reply(db.lock().await.srandmember(&key, 1).map(first))
+            }
+            Command::SRandMember {
+                key,
+                count: Some(count),
+            } => reply(db.lock().await.srandmember(&key, count)),
+            Command::SetOperation { operation, keys } => reply(
+                db.lock()
+                    .await
+                    .set_operation(operation, &keys)
+                    .map(|set| set.into_iter().collect::<Vec<_>>()),
+            ),
+            Command::SetOperationStore {
+                operation,
+                destination,
+                keys,
+            } => reply(
+                db.lock()
+                    .await
+                    .set_operation_store(operation, destination, &keys),
+            ),
+            Command::ZAdd {
+                key,
+                options,
+                mut members,
+            } if options.increment => {
+                let (increment, member) = members.swap_remove(0);
+                let db = &mut db.lock().await;
+                let result = db.zadd_incr(key.clone(), options, increment, member);
+                self.waiters.wake(&key);
+                reply(result)
+            }
+            Command::ZAdd {
+                key,
+                options,
+                members,
+            } => {
+                let result = db.lock().await.zadd(key.clone(), options, members);
+                self.waiters.wake(&key);
+                reply(result)
+            }
+            Command::GeoAdd {
+                key,
+                options,
+                locations,
+            } => {
+                let result = db.lock().await.geoadd(key.clone(), options, locations);
+                self.waiters.wake(&key);
+                reply(result)
+            }
+            Command::GeoPos { key, members } => reply(
+                db.lock()
+                    .await
+                    .geopos(&key, &members)
+                    .map(|positions| positions.into_iter().map(position).collect::<Vec<_>>()),
+            ),
+            Command::GeoDist {
+                key,
+                from,
+                to,
+                unit,
+            } => reply(
+                db.lock()
+                    .await
+                    .geodist(&key, &from, &to, unit)
+                    .map(|distance| distance.map(|distance| format!("{distance:.4}"))),
+            ),
+            Command::GeoSearch {
+                key,
+                search,
+                with_dist,
+                with_hash,
+                with_coord,
+            } => reply(db.lock().await.geosearch(&key, &search).map(|matches| {
+                let reply = |found| geo_match(found, with_dist, with_hash, with_coord);
+                matches.into_iter().map(reply).collect::<Vec<_>>()
+            })),
+            Command::GeoSearchStore {
+                destination,
+                source,
+                search,
+                store_dist,
+            } => {
+                let db = &mut db.lock().await;
+                let result = db.geosearchstore(destination.clone(), &source, &search, store_dist);
+                self.waiters.wake(&destination);
+                reply(result)
+            }
+            Command::ZIncrBy {
+                key,
+                increment,
+                member,
+            } => {
+                let options = ZAddOptions {
+                    increment: true,
+                    ..ZAddOptions::default()
+                };
+                let db = &mut db.lock().await;
+                let result = db.zadd_incr(key.clone(), options, increment, member);
+                self.waiters.wake(&key);
+                reply(result)
+            }
+            Command::ZPop { key, max, count } => {
+                let popped = db.lock().await.zpop(&key, max, count.unwrap_or(1));
+                reply(popped.map(|members| {
+                    members
+                        .into_iter()
+                        .flat_map(|(member, score)| [Token::from(member), Token::from(score)])
+                        .collect::<Vec<_>>()
+                }))
+            }
+            Command::ZRandMember {
+                key, count: None, ..
+            } => reply(
+                db.lock()
+                    .await
+                    .zrandmember(&key, 1)
+                    .map(|members| first(members).map(|(member, _)| member)),
+            ),
+            Command::ZRandMember {
+                key,
+                count: Some(count),
+                with_scores,
+            } => reply(
+                db.lock()
+                    .await
+                    .zrandmember(&key, count)
+                    .map(|members| flatten_scored(members, with_scores)),
+            ),
+            Command::BZPop { keys, max, timeout } => self.bzpop(&keys, max, timeout, db).await,
+            Command::ZScore { key, member } => reply(db.lock().await.zscore(&key, &member)),
+            Command::SetBit { key, offset, bit } => reply(db.lock().await.setbit(key, offset, bit)),
+            Command::GetBit { key, offset } => reply(db.lock().await.getbit(&key, offset)),
+            Command::BitOp {
+                operation,
+                destination,
+                keys,
+            } => reply(db.lock().await.bitop(operation, destination, &keys)),
+            Command::BitCount { key, range } => reply(db.lock().await.bitcount(&key, range)),
+            Command::BitPos { key, bit, range } => reply(db.lock().await.bitpos(&key, bit, range)),
+            Command::PfAdd { key, elements } => reply(db.lock().await.pfadd(key, &elements)),
+            Command::PfCount { keys } => reply(db.lock().await.pfcount(&keys).map(integer)),
+            Command::PfMerge {
+                destination,
+                sources,
+            } => reply(
+                db.lock()
+                    .await
+                    .pfmerge(destination, &sources)
+                    .map(|()| ok()),
+            ),
+            Command::BitField { key, operations } => {
+                reply(db.lock().await.bitfield(key, &operations))
+            }
+            Command::ZCard { key } => reply(db.lock().await.zcard(&key)),
+            Command::ZRank {
+                key,
+                member,
+                reverse,
+                with_score,
+            } => reply(
+                db.lock()
+                    .await
+                    .zrank(&key, &member, reverse)
+                    .map(|found| match found {
+                        Some((rank, score)) if with_score => Token::Array {
+                            tokens: vec![Token::from(rank), Token::from(score)],
                         },
-                    ],
+                        Some((rank, _)) => Token::from(rank),
+                        None => Token::NullBulkString,
+                    }),
+            ),
+            Command::ZRange {
+                key,
+                range,
+                reverse,
+                limit,
+                with_scores,
+            } => reply(
+                db.lock()
+                    .await
+                    .zrange(&key, &range, reverse, limit)
+                    .map(|members| flatten_scored(members, with_scores)),
+            ),
+            Command::ZRemRange { key, range } => reply(db.lock().await.zremrange(&key, &range)),
+            Command::ZSetOperationStore {
+                operation,
+                destination,
+                keys,
+                weights,
+                aggregate,
+            } => {
+                let db = &mut db.lock().await;
+                let result = db.zset_operation_store(
+                    operation,
+                    destination.clone(),
+                    &keys,
+                    &weights,
+                    aggregate,
+                );
+                self.waiters.wake(&destination);
+                reply(result)
+            }
+            Command::XAdd {
+                key,
+                options,
+                id,
+                fields,
+            } => {
+                let result = db.lock().await.xadd(key.clone(), id, fields, options);
+                self.waiters.wake(&key);
+                reply(result.map(|id| id.map(|id| id.to_string())))
+            }
+            Command::XGroupCreate {
+                key,
+                group,
+                from,
+                make_stream,
+            } => {
+                let mut db = db.lock().await;
+                reply(
+                    db.xgroup_create(key, group, from, make_stream)
+                        .map(|()| ok()),
+                )
+            }
+            Command::XGroupSetId { key, group, from } => {
+                let result = db.lock().await.xgroup_setid(&key, &group, from);
+                reply(result.map(|()| ok()))
+            }
+            Command::XGroupDestroy { key, group } => {
+                reply(db.lock().await.xgroup_destroy(&key, &group))
+            }
+            Command::XGroupCreateConsumer {
+                key,
+                group,
+                consumer,
+            } => reply(
+                db.lock()
+                    .await
+                    .xgroup_create_consumer(&key, &group, &consumer),
+            ),
+            Command::XGroupDelConsumer {
+                key,
+                group,
+                consumer,
+            } => reply(db.lock().await.xgroup_del_consumer(&key, &group, &consumer)),
+            Command::XReadGroup {
+                group,
+                consumer,
+                streams,
+                count,
+                block,
+                no_ack,
+            } => {
+                let options = ReadOptions::new(count, block, db);
+                self.xreadgroup(&group, &consumer, &streams, options, no_ack, db)
+                    .await
+            }
+            Command::XAck { key, group, ids } => reply(db.lock().await.xack(&key, &group, &ids)),
+            Command::XPending { key, group, range } => {
+                let mut db = db.lock().await;
+                match range {
+                    None => reply(db.xpending_summary(&key, &group).map(pending_summary)),
+                    Some(range) => {
+                        reply(db.xpending(&key, &group, &range).map(|entries| {
+                            entries.into_iter().map(pending_entry).collect::<Vec<_>>()
+                        }))
+                    }
+                }
+            }
+            Command::XClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                ids,
+                options,
+            } => {
+                let result = db
+                    .lock()
+                    .await
+                    .xclaim(&key, &group, &consumer, min_idle, &ids, options);
+                reply(result.map(|claimed| claimed_entries(claimed, options.just_id)))
+            }
+            Command::XAutoClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                start,
+                options,
+            } => {
+                let result = db
+                    .lock()
+                    .await
+                    .xautoclaim(&key, &group, &consumer, min_idle, start, options);
+                reply(result.map(|outcome| {
+                    let deleted = outcome.deleted.iter().map(ToString::to_string);
+                    vec![
+                        Token::from(outcome.next.to_string()),
+                        Token::from(claimed_entries(outcome.claimed, options.just_id)),
+                        Token::from(deleted.collect::<Vec<_>>()),
+                    ]
+                }))
+            }
+            Command::XInfoStream { key } => {
+                reply(db.lock().await.xinfo_stream(&key).map(stream_info))
+            }
+            Command::XInfoGroups { key } => reply(
+                db.lock()
+                    .await
+                    .xinfo_groups(&key)
+                    .map(|groups| groups.into_iter().map(group_info).collect::<Vec<_>>()),
+            ),
+            Command::XInfoConsumers { key, group } => {
+                let result = db.lock().await.xinfo_consumers(&key, &group);
+                reply(
+                    result.map(|consumers| {
+                        consumers.into_iter().map(consumer_info).collect::<Vec<_>>()
+                    }),
+                )
+            }
+            Command::XLen { key } => reply(db.lock().await.xlen(&key)),
+            Command::XDel { key, ids } => reply(db.lock().await.xdel(&key, &ids)),
+            Command::XTrim { key, trim } => reply(db.lock().await.xtrim(&key, trim)),
+            Command::XSetId {
+                key,
+                last_id,
+                entries_added,
+                max_deleted_id,
+            } => {
+                let result = db
+                    .lock()
+                    .await
+                    .xsetid(&key, last_id, entries_added, max_deleted_id);
+                reply(result.map(|()| ok()))
+            }
+            Command::XRange {
+                key,
+                start,
+                end,
+                count,
+                reverse,
+            } => reply(
+                db.lock()
+                    .await
+                    .xrange(&key, start, end, count, reverse)
+                    .map(stream_entries),
+            ),
+            Command::XRead {
+                streams,
+                count,
+                block,
+            } => {
+                let options = ReadOptions::new(count, block, db);
+                self.xread(streams, options, db).await
+            }
+            Command::ChaosSet { .. } | Command::ChaosClear { .. } | Command::ChaosList
+                if !self.config.enable_debug_chaos =>
+            {
+                Token::SimpleError {
+                    data: "ERR DEBUG CHAOS is disabled, start the server with \
+                           enable-debug-chaos yes to use it"
+                        .to_string(),
+                }
+            }
+            Command::ChaosSet { command, fault } => {
+                self.chaos.set(&command, fault);
+                Token::SimpleString {
+                    data: "OK".to_string(),
+                }
+            }
+            Command::ChaosClear { command } => Token::from(self.chaos.clear(command.as_deref())),
+            Command::ChaosList => Token::Map {
+                pairs: self
+                    .chaos
+                    .list()
+                    .into_iter()
+                    .map(|(command, fault)| {
+                        let description = format!(
+                            "delay-ms {} jitter {} error-rate {}",
+                            fault.delay.as_millis(),
+                            fault.jitter.as_millis(),
+                            fault.error_rate
+                        );
+                        (Token::from(command), Token::from(description))
+                    })
+                    .collect(),
+            },
+        };
+
+        vec![reply]
+    }
+
+    /// Pop a member from the first non-empty sorted set out of `keys`, blocking until
+    /// one of them gets some members if needed, for up to `timeout` (zero meaning forever).
+    async fn bzpop(&self, keys: &[String], max: bool, timeout: Duration, db: &mut Db<'_>) -> Token {
+        let deadline = (!timeout.is_zero()).then(|| tokio::time::Instant::now() + timeout);
+        // Register before the first check, so that no wake-up can slip in between.
+        let waiter = self.waiters.register(keys);
+        loop {
+            {
+                let mut db = db.lock().await;
+                for key in keys {
+                    match db.zpop(key, max, 1).map(first) {
+                        Ok(Some((member, score))) => {
+                            return Token::from(vec![
+                                Token::from(key.clone()),
+                                Token::from(member),
+                                Token::from(score),
+                            ]);
+                        }
+                        Ok(None) => {}
+                        Err(err) => return err.into(),
+                    }
+                }
+            }
+            if !db.may_block() || !woken(&waiter, deadline).await {
+                return Token::NullArray;
+            }
+        }
+    }
+
+    /// Read the entries after the given IDs from `streams`, blocking until one of them
+    /// gets some new entries if needed and asked to, for up to `block` (zero meaning forever).
+    async fn xread(
+        &self,
+        streams: Vec<(String, ReadFrom)>,
+        ReadOptions { count, block }: ReadOptions,
+        db: &mut Db<'_>,
+    ) -> Token {
+        let keys: Vec<_> = streams.iter().map(|(key, _)| key.clone()).collect();
+        // Register before the first check, so that no wake-up can slip in between.
+        let waiter = block.map(|_| self.waiters.register(&keys));
+        // `$` means the entries added after the call, so it is resolved only once.
+        let streams = match db.lock().await.xread_resolve(streams) {
+            Ok(streams) => streams,
+            Err(err) => return err.into(),
+        };
+        let deadline = block
+            .filter(|block| !block.is_zero())
+            .map(|block| tokio::time::Instant::now() + block);
+        loop {
+            match db.lock().await.xread(&streams, count) {
+                Ok(read) if read.is_empty() => {}
+                read => {
+                    return reply(read.map(|streams| {
+                        read_streams(streams, |(id, fields)| stream_entry(id, Some(fields)))
+                    }))
+                }
+            }
+            let Some(waiter) = &waiter else {
+                return Token::NullArray;
+            };
+            if !woken(waiter, deadline).await {
+                return Token::NullArray;
+            }
+        }
+    }
+
+    /// Read entries from `streams` as the `consumer` of the consumer `group`, blocking until
+    /// new entries arrive if there are none and asked to, see [`Server::xread`].
+    async fn xreadgroup(
+        &self,
+        group: &str,
+        consumer: &str,
+        streams: &[(String, ReadGroupFrom)],
+        ReadOptions { count, block }: ReadOptions,
+        no_ack: bool,
+        db: &mut Db<'_>,
+    ) -> Token {
+        let keys: Vec<_> = streams.iter().map(|(key, _)| key.clone()).collect();
+        let waiter = block.map(|_| self.waiters.register(&keys));
+        let deadline = block
+            .filter(|block| !block.is_zero())
+            .map(|block| tokio::time::Instant::now() + block);
+        loop {
+            let read = db
+                .lock()
+                .await
+                .xreadgroup(group, consumer, streams, count, no_ack);
+            match read {
+                // Reading pending entries always replies, so only new entries are waited for.
+                Ok(read) if read.is_empty() => {}
+                read => {
+                    return reply(read.map(|streams| {
+                        read_streams(streams, |(id, fields)| stream_entry(id, fields))
+                    }))
+                }
+            }
+            let Some(waiter) = &waiter else {
+                return Token::NullArray;
+            };
+            if !woken(waiter, deadline).await {
+                return Token::NullArray;
+            }
+        }
+    }
+
+    /// Subscribe the client to the channels or patterns `names`, see [`Subscriptions::subscribe`].
+    fn subscribe(&self, connection: &mut Connection, kind: Kind, names: Vec<String>) -> Vec<Token> {
+        for name in &names {
+            self.broker
+                .subscribe(kind, name, connection.id, &connection.messages);
+        }
+        connection.subscriptions.subscribe(kind, names)
+    }
+
+    /// Unsubscribe the client from the channels or patterns `names`, or from all of them
+    /// if none are given, see [`Subscriptions::unsubscribe`].
+    fn unsubscribe(
+        &self,
+        connection: &mut Connection,
+        kind: Kind,
+        names: Vec<String>,
+    ) -> Vec<Token> {
+        let names = if names.is_empty() {
+            connection.subscriptions.iter(kind).cloned().collect()
+        } else {
+            names
+        };
+        for name in &names {
+            self.broker.unsubscribe(kind, name, connection.id);
+        }
+        connection.subscriptions.unsubscribe(kind, names)
+    }
+
+    /// Unsubscribe the client from everything, without confirming any of it.
+    fn unsubscribe_all(&self, connection: &mut Connection) {
+        for kind in Kind::ALL {
+            for name in connection.subscriptions.iter(kind) {
+                self.broker.unsubscribe(kind, name, connection.id);
+            }
+        }
+        connection.subscriptions = Subscriptions::default();
+    }
+
+    /// Stop watching all the keys that the client watches.
+    async fn unwatch_all(&self, connection: &mut Connection, db: &mut Db<'_>) {
+        if !connection.watched.is_empty() {
+            db.lock().await.unwatch(&mem::take(&mut connection.watched));
+        }
+    }
+
+    /// Bring the `connection` back to the state it was in right after connecting, like `RESET`.
+    async fn reset(&self, connection: &mut Connection, db: &mut Db<'_>) {
+        self.unsubscribe_all(connection);
+        self.unwatch_all(connection, db).await;
+        connection.protocol = Protocol::default();
+        connection.compression = None;
+        connection.transaction = None;
+    }
+
+    /// Set the configuration `parameters`, only if all of them can be set at runtime.
+    async fn config_set(&self, parameters: &[(String, String)], db: &mut Db<'_>) -> Token {
+        const SETTABLE: [&str; 8] = [
+            "dir",
+            "dbfilename",
+            "appendfsync",
+            "notify-keyspace-events",
+            "notify-keyspace-include",
+            "notify-keyspace-exclude",
+            "slowlog-log-slower-than",
+            "slowlog-max-len",
+        ];
+        if let Some((name, _)) = parameters
+            .iter()
+            .find(|(name, _)| !SETTABLE.contains(&name.as_str()))
+        {
+            return Token::SimpleError {
+                data: format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{name}'"
+                ),
+            };
+        }
+        let value_of = |wanted: &str| {
+            parameters
+                .iter()
+                .rev()
+                .find(|(name, _)| name == wanted)
+                .map(|(_, value)| value.as_str())
+        };
+        let invalid = |name: &str, value: &str| Token::SimpleError {
+            data: format!(
+                "ERR Invalid argument '{value}' for CONFIG SET '{name}' - \
+                argument couldn't be parsed into an integer"
+            ),
+        };
+        // The parts that can fail go first, so that nothing is set if any of them does.
+        let slower_than = match value_of("slowlog-log-slower-than") {
+            Some(value) => match value.parse::<i64>() {
+                Ok(micros) => Some(micros),
+                Err(_) => return invalid("slowlog-log-slower-than", value),
+            },
+            None => None,
+        };
+        let max_len = match value_of("slowlog-max-len") {
+            Some(value) => match value.parse::<usize>() {
+                Ok(max_len) => Some(max_len),
+                Err(_) => return invalid("slowlog-max-len", value),
+            },
+            None => None,
+        };
+        let fsync =
+            match value_of("appendfsync") {
+                Some(value) => match value.parse::<Fsync>() {
+                    Ok(fsync) => Some(fsync),
+                    Err(_) => return Token::SimpleError {
+                        data:
+                            "ERR CONFIG SET failed (possibly related to argument 'appendfsync') - \
+                               argument(s) must be one of the following: always, everysec, no"
+                                .to_string(),
+                    },
+                },
+                None => None,
+            };
+        let events = match value_of("notify-keyspace-events").map(Events::parse) {
+            Some(Err(err)) => {
+                return Token::SimpleError {
+                    data: err.to_string(),
+                }
+            }
+            Some(Ok(events)) => Some(events),
+            None => None,
+        };
+        let (dir, dbfilename) = (value_of("dir"), value_of("dbfilename"));
+        if dir.is_some() || dbfilename.is_some() {
+            if let Err(err) = self.persistence.set(dir, dbfilename) {
+                return Token::SimpleError {
+                    data: err.to_string(),
                 };
-                let _ = stream.write(response.to_string().as_bytes()).await?;
             }
         }
+        if let Some(fsync) = fsync {
+            self.aof.set_fsync(fsync);
+        }
+        if let Some(micros) = slower_than {
+            self.slowlog.set_slower_than(micros);
+        }
+        if let Some(max_len) = max_len {
+            self.slowlog.set_max_len(max_len);
+        }
+        if let Some(events) = events {
+            db.lock().await.set_notify_events(events);
+            *self.events.write().unwrap_or_else(PoisonError::into_inner) = events;
+        }
+        let mut key_filter = self
+            .key_filter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (name, value) in parameters {
+            match name.as_str() {
+                "notify-keyspace-include" => key_filter.set_include(value),
+                "notify-keyspace-exclude" => key_filter.set_exclude(value),
+                _ => {}
+            }
+        }
+        Token::SimpleString {
+            data: "OK".to_string(),
+        }
+    }
+
+    fn events(&self) -> Events {
+        *self.events.read().unwrap_or_else(PoisonError::into_inner)
+    }
 
+    /// Publish the keyspace notifications recorded by the [`Database`] since the last time.
+    async fn publish_notifications(&self) {
+        let events = self.events();
+        if events == Events::default() {
+            return;
+        }
+        let notifications = self.db.lock().await.take_notifications();
+        let key_filter = self.key_filter();
+        for notification in notifications
+            .iter()
+            .filter(|notification| key_filter.allows(&notification.key))
+        {
+            for (channel, message) in events.messages(notification) {
+                let _ = self.broker.publish(&channel, &message);
+            }
+        }
+    }
+
+    fn key_filter(&self) -> RwLockReadGuard<'_, KeyFilter> {
+        self.key_filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Render the `INFO` reply: either a single `section`, or all of them.
+    async fn info(&self, section: Option<&str>, db: &mut Db<'_>) -> String {
+        let wanted = section.map(str::to_ascii_lowercase);
+        let wants = |name: &str| match wanted.as_deref() {
+            None | Some("all" | "default" | "everything") => true,
+            Some(wanted) => name == wanted,
+        };
+        let mut sections = vec![];
+        if wants("persistence") {
+            let changes = db.lock().await.dirty();
+            sections.push(self.persistence.info(changes) + &self.aof.info());
+        }
+        if wants("stats") {
+            sections.push(self.stats.info());
+        }
+        if wants("replication") {
+            sections.push(self.replication.info());
+        }
+        if wants("ttl") {
+            let horizons = TTL_BUCKETS.map(|(horizon, _)| horizon);
+            let forecast = db.lock().await.expiry_forecast(&horizons);
+            sections.push(self.ttls.info(&forecast));
+        }
+        sections.join("\r\n")
+    }
+
+    /// Delay or fail the `request` according to the configured chaos faults.
+    ///
+    /// Returns the error reply to send instead of executing the command, if any.
+    /// `DEBUG` itself is exempt, so that faults can always be cleared again.
+    async fn inject_chaos(&self, request: &[Vec<u8>]) -> Option<Token> {
+        if !self.config.enable_debug_chaos {
+            return None;
+        }
+        let command = String::from_utf8_lossy(request.first()?);
+        if command.eq_ignore_ascii_case("debug") {
+            return None;
+        }
+        let injection = self.chaos.roll(&command)?;
+        tokio::time::sleep(injection.delay).await;
+        injection.fail.then(|| Token::SimpleError {
+            data: format!("ERR chaos: injected failure of {command:?}"),
+        })
+    }
+
+    /// Turn the client into a [`Replica`], sending it the whole dataset as an RDB file.
+    ///
+    /// The replica gets all the commands propagated after the snapshot in the file,
+    /// which wait in its queue of messages until the file is sent.
+    async fn full_resync(
+        &self,
+        stream: &mut TcpStream,
+        connection: &mut Connection,
+    ) -> anyhow::Result<()> {
+        let replica = Replica::new(
+            connection.id,
+            stream.peer_addr()?.ip(),
+            connection.listening_port,
+            connection.messages.clone(),
+        );
+        let (snapshot, (replid, offset)) = {
+            let db = self.db.lock().await;
+            (db.snapshot(), self.replication.register(replica))
+        };
+        let reply = Token::SimpleString {
+            data: format!("FULLRESYNC {replid} {offset}"),
+        };
+        stream
+            .write_all(reply.encode(connection.protocol).as_bytes())
+            .await?;
+        let rdb = self.dump(&snapshot);
+        tracing::info!(
+            keys = snapshot.len(),
+            bytes = rdb.len(),
+            "Sending the RDB file to a replica"
+        );
+        // Like a bulk string, but without the trailing CRLF.
+        let header = format!("${}\r\n", rdb.len());
+        write_all_vectored(stream, &[header.as_bytes(), &rdb]).await?;
+        // The replica acknowledges every `replication::ACK_PERIOD` from now on.
+        connection.link = Link::Replica;
         Ok(())
     }
 
@@ -107,26 +2362,574 @@ impl Server {
     ///
     /// This function only errors out if the incoming RESP-encoded stream is invalid,
     /// contains unknown commands, or wrong/missing arguments to commands.
-    async fn handle_client(&self, stream: &mut TcpStream) -> anyhow::Result<()> {
-        let mut request = [0; 512];
-
-        // `stream.read()` reads until a newline, so lets
-        // run it in a loop to read everything line-by-line.
-        while let Ok(read_bytes) = stream.read(&mut request).await {
-            // Having nothing to read is not an error, it's an Ok(0).
-            // Without this, the loop will run until an error occurs.
-            if read_bytes == 0 {
+    async fn handle_client(&self, stream: &mut TcpStream, link: Link) -> anyhow::Result<()> {
+        let (messages, mut published) = mpsc::unbounded_channel();
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let mut connection = Connection::new(id, link, messages);
+        connection.set_addrs((stream.peer_addr()?, stream.local_addr()?));
+        let _registered = self.clients.register(&connection.client);
+        let served = self
+            .serve_client(stream, &mut connection, &mut published)
+            .await;
+        self.unsubscribe_all(&mut connection);
+        self.unwatch_all(&mut connection, &mut Db::Shared(&self.db))
+            .await;
+        self.replication.unregister(connection.id);
+        served
+    }
+
+    /// Serve the requests on `stream`, and the messages `published` to the client
+    /// in between them, until either side hangs up.
+    async fn serve_client(
+        &self,
+        stream: &mut TcpStream,
+        connection: &mut Connection,
+        published: &mut mpsc::UnboundedReceiver<Token>,
+    ) -> anyhow::Result<()> {
+        // What was read but not run yet: any number of requests, the last of which may be partial.
+        let mut buffer = Vec::with_capacity(connection.link.read_buffer_size());
+        let _client = self.shutdown.client();
+        let mut shutdown = self.shutdown.subscribe();
+        let mut killed = connection.client.killed();
+
+        loop {
+            if shutdown.borrow_and_update().is_some() || *killed.borrow_and_update() {
                 break;
             }
+            // Run every complete request in the buffer before reading more.
+            let Some(args) = next_request(&mut buffer)? else {
+                buffer.reserve(connection.link.read_buffer_size());
+                let read = stream.read_buf(&mut buffer);
+                let read = async {
+                    match connection.link.idle_timeout() {
+                        Some(timeout) => tokio::time::timeout(timeout, read).await.ok(),
+                        None => Some(read.await),
+                    }
+                };
+                let read = tokio::select! {
+                    read = read => read,
+                    _ = shutdown.changed() => break,
+                    _ = killed.changed() => break,
+                    // The connection holds on to a sender, so this never runs out.
+                    Some(message) = published.recv() => {
+                        stream
+                            .write_all(&message.to_bytes(connection.protocol))
+                            .await?;
+                        continue;
+                    }
+                };
+                let Some(read) = read else {
+                    tracing::warn!(link = ?connection.link, "Dropping an idle connection");
+                    break;
+                };
+                let Ok(read_bytes) = read else {
+                    break;
+                };
 
-            // If we actually read something meaningful, respond to it.
-            let string = String::from_utf8(request.to_vec())?;
-            let syntax = Token::try_from(string.as_str())?;
-            let command = Command::try_from(syntax)?;
+                // Having nothing to read is not an error, it's an Ok(0).
+                // Without this, the loop will run until an error occurs.
+                if read_bytes == 0 {
+                    break;
+                }
+                continue;
+            };
+            if let Some(reply) = self.inject_chaos(&args).await {
+                stream
+                    .write_all(reply.encode(connection.protocol).as_bytes())
+                    .await?;
+                continue;
+            }
+            if connection.subscriptions.restricts(connection.protocol) {
+                if let Err(reply) = check_subscriber_mode(&args) {
+                    stream
+                        .write_all(reply.encode(connection.protocol).as_bytes())
+                        .await?;
+                    continue;
+                }
+            }
+            let command = match Command::try_from(args.clone()) {
+                Ok(command) => command,
+                Err(err) => {
+                    // A command that can't be queued dooms the whole transaction.
+                    if let Some(transaction) = &mut connection.transaction {
+                        transaction.abort();
+                    }
+                    let reply = parse_error(&err, &args);
+                    stream
+                        .write_all(reply.encode(connection.protocol).as_bytes())
+                        .await?;
+                    continue;
+                }
+            };
+            if matches!(command, Command::PSync { .. }) && connection.transaction.is_none() {
+                self.full_resync(stream, connection).await?;
+                continue;
+            }
+            let (quit, reset) = (command == Command::Quit, command == Command::Reset);
 
-            self.exec(command, stream).await?;
+            // Replicas and the master are never paused, see `CLIENT PAUSE`.
+            if connection.link == Link::Client && command != Command::ClientUnpause {
+                let write = match (&command, &connection.transaction) {
+                    (Command::Exec, Some(transaction)) => transaction.pauses_on_write(),
+                    (command, _) => command.pauses_on_write(),
+                };
+                tokio::select! {
+                    _ = self.clients.unpaused(write) => {}
+                    _ = shutdown.changed() => break,
+                    _ = killed.changed() => break,
+                }
+            }
+            connection.touch(&args);
+            // Blocking commands would fill the slow log with the time they spent waiting.
+            let timed = !command.may_block();
+            let started = Instant::now();
+            let replies = self.dispatch(command, &args, connection).await?;
+            let elapsed = started.elapsed();
+            if timed && self.slowlog.is_slow(elapsed) {
+                let (addr, name) = {
+                    let info = connection.client.info();
+                    let addr = info.addr.map(|addr| addr.to_string());
+                    (addr.unwrap_or_default(), info.name.clone())
+                };
+                self.slowlog.record(&args, elapsed, addr, name);
+            }
+            connection.update_info();
+            if reset {
+                // Drop the messages that were published before the subscriptions ended.
+                while published.try_recv().is_ok() {}
+            }
+            self.publish_notifications().await;
+            let mut encoded = Vectored::default();
+            replies
+                .iter()
+                .for_each(|reply| encoded.push(reply, connection.protocol));
+            let slices = encoded.slices();
+            let len = slices.iter().map(|slice| slice.len()).sum();
+            match connection.compression {
+                Some(min_len) if len >= min_len => match compress::frame(&slices, len) {
+                    Some(frame) => stream.write_all(&frame).await?,
+                    None => write_all_vectored(stream, &slices).await?,
+                },
+                _ => write_all_vectored(stream, &slices).await?,
+            }
+            if quit {
+                break;
+            }
         }
 
         Ok(())
     }
 }
+
+impl From<Error> for Token {
+    fn from(err: Error) -> Self {
+        Self::SimpleError {
+            data: err.to_string(),
+        }
+    }
+}
+
+impl From<Score> for Token {
+    fn from(Score(data): Score) -> Self {
+        Self::Double { data }
+    }
+}
+
+/// Write all of `slices` to `stream`, with as few vectored writes as it takes.
+async fn write_all_vectored(stream: &mut TcpStream, slices: &[&[u8]]) -> io::Result<()> {
+    let (mut index, mut offset) = (0, 0);
+    while index < slices.len() {
+        let remaining: Vec<_> = std::iter::once(&slices[index][offset..])
+            .chain(slices[index + 1..].iter().copied())
+            .map(IoSlice::new)
+            .collect();
+        let mut written = stream.write_vectored(&remaining).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        // Skip past everything that got written, which may end in the middle of a slice.
+        while index < slices.len() && written >= slices[index].len() - offset {
+            written -= slices[index].len() - offset;
+            (index, offset) = (index + 1, 0);
+        }
+        offset += written;
+    }
+    Ok(())
+}
+
+/// Build the reply to `HELLO`: a map describing the server, whose role depends on
+/// whether it is a `replica`, and the connection.
+fn hello(connection: &Connection, replica: bool) -> Token {
+    let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+    Token::Map {
+        pairs: vec![
+            field("server", Token::from("redis".to_string())),
+            field(
+                "version",
+                Token::from(env!("CARGO_PKG_VERSION").to_string()),
+            ),
+            field("proto", Token::from(connection.protocol.version())),
+            field(
+                "id",
+                Token::from(i64::try_from(connection.id).unwrap_or(i64::MAX)),
+            ),
+            field("mode", Token::from("standalone".to_string())),
+            field(
+                "role",
+                Token::from(if replica { "replica" } else { "master" }.to_string()),
+            ),
+            field("modules", Token::Array { tokens: vec![] }),
+        ],
+    }
+}
+
+/// Build the reply for the coordinates of a location, which is a nil array if it is missing.
+fn position(coordinates: Option<Coordinates>) -> Token {
+    coordinates.map_or(Token::NullArray, |coordinates| Token::Array {
+        tokens: vec![
+            Token::from(coordinates.longitude.to_string()),
+            Token::from(coordinates.latitude.to_string()),
+        ],
+    })
+}
+
+/// Build the reply to `EXT.WHENEXPIRES`: a map describing the expiry of a key.
+fn expiry_report(report: ExpiryReport) -> Token {
+    let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+    let status = |status: &str| field("status", Token::from(status.to_string()));
+    let pairs = match report {
+        ExpiryReport::Persistent => vec![status("persistent")],
+        ExpiryReport::Scheduled {
+            deadline_ms,
+            ttl_ms,
+        } => vec![
+            status("scheduled"),
+            field("expires-at-ms", integer(deadline_ms)),
+            field("ttl-ms", integer(ttl_ms)),
+        ],
+        ExpiryReport::Overdue { deadline_ms } => vec![
+            status("overdue"),
+            field("expires-at-ms", integer(deadline_ms)),
+        ],
+        ExpiryReport::Expired(expiration) => {
+            let (removed_by, cycle) = match expiration.removal {
+                Removal::Lazy => ("lazy", Token::NullBulkString),
+                Removal::Active { cycle } => ("active", integer(cycle)),
+            };
+            vec![
+                status("expired"),
+                field("expires-at-ms", integer(expiration.deadline_ms)),
+                field("removed-at-ms", integer(expiration.removed_at_ms)),
+                field("removed-by", Token::from(removed_by.to_string())),
+                field("cycle", cycle),
+            ]
+        }
+        ExpiryReport::Missing => vec![status("missing")],
+    };
+    Token::Map { pairs }
+}
+
+/// Build the reply for a member found by `GEOSEARCH`, which is just its name unless
+/// anything else is asked for.
+fn geo_match(found: GeoMatch, with_dist: bool, with_hash: bool, with_coord: bool) -> Token {
+    if !(with_dist || with_hash || with_coord) {
+        return Token::from(found.member);
+    }
+    let mut tokens = vec![Token::from(found.member)];
+    if with_dist {
+        tokens.push(Token::from(format!("{:.4}", found.distance)));
+    }
+    if with_hash {
+        tokens.push(integer(found.hash));
+    }
+    if with_coord {
+        tokens.push(position(Some(found.coordinates)));
+    }
+    Token::Array { tokens }
+}
+
+/// Keep only the first of `items`, for commands that reply with a single element unless given a count.
+fn first<T>(items: Vec<T>) -> Option<T> {
+    items.into_iter().next()
+}
+
+/// Take the first request off the front of the `buffer` and return its arguments,
+/// or [`None`] if the buffer doesn't hold a complete request yet.
+fn next_request(buffer: &mut Vec<u8>) -> anyhow::Result<Option<command::Request>> {
+    loop {
+        // Virtually all requests are arrays of bulk strings, which have a fast path.
+        let decoded = match resp::decode_bulk_array(buffer) {
+            Some(decoded) => Some(decoded),
+            // Whatever doesn't look like RESP is an inline command, as typed into `telnet`.
+            None if resp::is_inline(buffer) => resp::decode_inline(buffer),
+            None => match Token::decode(buffer)? {
+                Some((request, consumed)) => Some((command::arguments(request)?, consumed)),
+                None => None,
+            },
+        };
+        let Some((args, consumed)) = decoded else {
+            return Ok(None);
+        };
+        let _ = buffer.drain(..consumed);
+        // Like Redis, skip empty requests, such as blank lines between inline commands.
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// The reply to a `request` that doesn't parse, worded the way Redis words it.
+fn parse_error(err: &command::ParseError, request: &[Vec<u8>]) -> Token {
+    let request: Vec<_> = request
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg))
+        .collect();
+    let name = request.first().map_or("", |name| name);
+    let message = match err {
+        command::ParseError::UnknownCommand(_) | command::ParseError::MissingCommand => {
+            let args: String = request
+                .iter()
+                .skip(1)
+                .map(|arg| format!("'{arg}' "))
+                .collect();
+            format!("ERR unknown command '{name}', with args beginning with: {args}")
+        }
+        command::ParseError::MissingArgument => format!(
+            "ERR wrong number of arguments for '{}' command",
+            name.to_ascii_lowercase()
+        ),
+        command::ParseError::WrongArgument => "ERR syntax error".to_string(),
+        command::ParseError::NotText => "ERR invalid argument, not valid UTF-8".to_string(),
+    };
+    transaction::error(&message)
+}
+
+/// Check that a connection in subscriber mode may run the `request`, see [`pubsub::check_allowed`].
+fn check_subscriber_mode(request: &[Vec<u8>]) -> Result<(), Token> {
+    request.first().map_or(Ok(()), |name| {
+        pubsub::check_allowed(&String::from_utf8_lossy(name))
+    })
+}
+
+/// Turn sorted set members into a flat reply, with each member followed by its score if `with_scores`.
+fn flatten_scored(members: Vec<(String, Score)>, with_scores: bool) -> Vec<Token> {
+    members
+        .into_iter()
+        .flat_map(|(member, score)| {
+            let score = with_scores.then(|| Token::from(score));
+            std::iter::once(Token::from(member)).chain(score)
+        })
+        .collect()
+}
+
+/// Turn stream entries into a reply, see [`stream_entry`].
+fn stream_entries(entries: Vec<Entry>) -> Vec<Token> {
+    entries
+        .into_iter()
+        .map(|(id, fields)| stream_entry(id, Some(fields)))
+        .collect()
+}
+
+/// Turn a stream entry into a reply: its ID followed by a flat array of its fields,
+/// or by nil if the entry has been deleted.
+fn stream_entry(id: StreamId, fields: Option<Fields>) -> Token {
+    let fields = fields.map_or(Token::NullArray, |fields| Token::Array {
+        tokens: fields
+            .into_iter()
+            .flat_map(|(field, value)| [Token::from(field), Token::from(value)])
+            .collect(),
+    });
+    Token::Array {
+        tokens: vec![Token::from(id.to_string()), fields],
+    }
+}
+
+/// Turn the entries claimed by `XCLAIM` or `XAUTOCLAIM` into a reply, which is
+/// just their IDs with `JUSTID`.
+fn claimed_entries(claimed: Vec<Entry>, just_id: bool) -> Vec<Token> {
+    if just_id {
+        claimed
+            .into_iter()
+            .map(|(id, _)| Token::from(id.to_string()))
+            .collect()
+    } else {
+        stream_entries(claimed)
+    }
+}
+
+/// Turn the summary of a PEL into the reply of `XPENDING`: the number of pending entries,
+/// the lowest and the highest pending IDs, and the number of entries of each consumer.
+fn pending_summary(summary: PendingSummary) -> Vec<Token> {
+    let (first, last) = summary
+        .bounds
+        .map(|(first, last)| (first.to_string(), last.to_string()))
+        .unzip();
+    let consumers = summary
+        .consumers
+        .into_iter()
+        .map(|(name, count)| Token::Array {
+            tokens: vec![Token::from(name), Token::from(count.to_string())],
+        });
+    let consumers = match summary.count {
+        0 => Token::NullArray,
+        _ => Token::from(consumers.collect::<Vec<_>>()),
+    };
+    vec![
+        Token::from(summary.count),
+        Token::from(first),
+        Token::from(last),
+        consumers,
+    ]
+}
+
+/// Turn a pending entry into a reply: its ID, its owner, its idle time and its delivery count.
+fn pending_entry(entry: PendingEntry) -> Token {
+    Token::Array {
+        tokens: vec![
+            Token::from(entry.id.to_string()),
+            Token::from(entry.consumer),
+            integer(entry.idle),
+            integer(entry.deliveries),
+        ],
+    }
+}
+
+/// Turn the overview of a stream into the reply of `XINFO STREAM`.
+fn stream_info(info: StreamInfo) -> Token {
+    let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+    let entry = |entry: Option<Entry>| match entry {
+        Some((id, fields)) => stream_entry(id, Some(fields)),
+        None => Token::NullBulkString,
+    };
+    Token::Map {
+        pairs: vec![
+            field("length", Token::from(info.length)),
+            field(
+                "last-generated-id",
+                Token::from(info.last_generated_id.to_string()),
+            ),
+            field(
+                "max-deleted-entry-id",
+                Token::from(info.max_deleted_entry_id.to_string()),
+            ),
+            field("entries-added", integer(info.entries_added)),
+            field(
+                "recorded-first-entry-id",
+                Token::from(info.recorded_first_entry_id.to_string()),
+            ),
+            field("groups", Token::from(info.groups)),
+            field("first-entry", entry(info.first_entry)),
+            field("last-entry", entry(info.last_entry)),
+        ],
+    }
+}
+
+/// Turn the overview of a consumer group into an element of the reply of `XINFO GROUPS`.
+fn group_info(info: GroupInfo) -> Token {
+    let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+    Token::Map {
+        pairs: vec![
+            field("name", Token::from(info.name)),
+            field("consumers", Token::from(info.consumers)),
+            field("pending", Token::from(info.pending)),
+            field(
+                "last-delivered-id",
+                Token::from(info.last_delivered_id.to_string()),
+            ),
+            field(
+                "entries-read",
+                info.entries_read.map_or(Token::NullBulkString, integer),
+            ),
+            field("lag", Token::from(info.lag)),
+        ],
+    }
+}
+
+/// Turn the overview of a consumer into an element of the reply of `XINFO CONSUMERS`.
+fn consumer_info(info: ConsumerInfo) -> Token {
+    let field = |name: &str, value: Token| (Token::from(name.to_string()), value);
+    Token::Map {
+        pairs: vec![
+            field("name", Token::from(info.name)),
+            field("pending", Token::from(info.pending)),
+            field("idle", integer(info.idle)),
+            field(
+                "inactive",
+                info.inactive.map_or(Token::from(-1_i64), integer),
+            ),
+        ],
+    }
+}
+
+/// Turn an unsigned number into an integer reply, saturating at the largest one.
+fn integer(value: u64) -> Token {
+    Token::from(i64::try_from(value).unwrap_or(i64::MAX))
+}
+
+/// Turn the entries read from several streams into a reply, with each stream being its key
+/// followed by its entries, each turned into a reply by `entry`.
+fn read_streams<E>(streams: Vec<(String, Vec<E>)>, entry: impl Fn(E) -> Token) -> Vec<Token> {
+    streams
+        .into_iter()
+        .map(|(key, entries)| Token::Array {
+            tokens: vec![
+                Token::from(key),
+                Token::Array {
+                    tokens: entries.into_iter().map(&entry).collect(),
+                },
+            ],
+        })
+        .collect()
+}
+
+/// The reply of commands that just succeed.
+fn ok() -> Token {
+    Token::SimpleString {
+        data: "OK".to_string(),
+    }
+}
+
+/// Wait until the `waiter` is woken, but no longer than until the `deadline`, if any.
+///
+/// Returns whether the waiter was woken, as opposed to timing out.
+async fn woken(waiter: &Waiter<'_>, deadline: Option<tokio::time::Instant>) -> bool {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, waiter.wait())
+            .await
+            .is_ok(),
+        None => {
+            waiter.wait().await;
+            true
+        }
+    }
+}
+
+/// Turn the outcome of a [`Database`] operation into a reply [`Token`].
+fn reply<T: Into<Token>>(result: Result<T, Error>) -> Token {
+    result.map_or_else(Into::into, Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hello, Connection, Link};
+    use crate::resp::{Protocol, Token};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn hello_reply_per_protocol() {
+        let mut connection = Connection::new(7, Link::Client, mpsc::unbounded_channel().0);
+        let resp2 = hello(&connection, false).encode(connection.protocol);
+        assert!(resp2.starts_with("*14\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+        assert!(resp2.contains("$5\r\nproto\r\n:2\r\n$2\r\nid\r\n:7\r\n"));
+        assert!(resp2.contains("$4\r\nrole\r\n$6\r\nmaster\r\n"));
+        let replica = hello(&connection, true).encode(connection.protocol);
+        assert!(replica.contains("$4\r\nrole\r\n$7\r\nreplica\r\n"));
+
+        connection.protocol = Protocol::Resp3;
+        let reply = hello(&connection, false);
+        assert!(matches!(&reply, Token::Map { pairs } if pairs.len() == 7));
+        let resp3 = reply.encode(connection.protocol);
+        assert!(resp3.starts_with("%7\r\n"));
+        assert!(resp3.contains("$5\r\nproto\r\n:3\r\n"));
+    }
+}
@@ -0,0 +1,255 @@
+//! # Orderly shutdown, triggered by the `SHUTDOWN` command or by `SIGTERM`/`SIGINT`.
+//!
+//! Shutting down goes through a fixed sequence of phases: saving the dataset
+//! (only if asked to, or on a signal if there are save points, see `--save`),
+//! syncing the AOF, and disconnecting the clients. No more connections are
+//! accepted from the moment that the shutdown starts. Once done,
+//! a [`Report`] of what happened is logged as a single `logfmt` line, and the
+//! process exits with a code that tells orchestrators how it went:
+//!
+//! | Exit code | Meaning                                                     |
+//! |-----------|-------------------------------------------------------------|
+//! | `0`       | Clean shutdown                                              |
+//! | `2`       | The dataset could not be saved, so recent writes were lost |
+//! | `3`       | Forced termination by a second signal during the shutdown  |
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// The exit code of a clean shutdown.
+pub const EXIT_CLEAN: i32 = 0;
+
+/// The exit code of a shutdown during which the dataset could not be saved.
+pub const EXIT_PERSISTENCE_FAILURE: i32 = 2;
+
+/// The exit code of a shutdown that was cut short by a second signal.
+pub const EXIT_FORCED: i32 = 3;
+
+/// How long to wait for the clients to disconnect before exiting regardless.
+pub const DISCONNECT_GRACE: Duration = Duration::from_secs(1);
+
+/// What initiated a shutdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Command,
+    Signal(&'static str),
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Command => write!(f, "SHUTDOWN"),
+            Self::Signal(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A request to shut the server down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub trigger: Trigger,
+    /// Whether to save the dataset before exiting.
+    pub save: bool,
+}
+
+/// The coordination point between the server, its clients and the shutdown sequence.
+#[derive(Debug)]
+pub struct Shutdown {
+    requested: watch::Sender<Option<Request>>,
+    clients: AtomicUsize,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            requested: watch::channel(None).0,
+            clients: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Ask for a shutdown. Only the first request counts.
+    pub fn request(&self, request: Request) {
+        let _ = self.requested.send_if_modified(|requested| {
+            let first = requested.is_none();
+            requested.get_or_insert(request);
+            first
+        });
+    }
+
+    /// Whether a shutdown has been requested already.
+    pub fn is_requested(&self) -> bool {
+        self.requested.borrow().is_some()
+    }
+
+    /// Wait until a shutdown is requested.
+    pub async fn requested(&self) -> Request {
+        let mut receiver = self.requested.subscribe();
+        loop {
+            if let Some(request) = *receiver.borrow_and_update() {
+                return request;
+            }
+            // The sender lives in `self`, so it cannot have been dropped.
+            let _ = receiver.changed().await;
+        }
+    }
+
+    /// Subscribe to shutdown requests, which a client should react to by disconnecting.
+    pub fn subscribe(&self) -> watch::Receiver<Option<Request>> {
+        self.requested.subscribe()
+    }
+
+    /// Keep track of a connected client, until the returned guard is dropped.
+    pub fn client(&self) -> ClientGuard<'_> {
+        let _ = self.clients.fetch_add(1, Ordering::Relaxed);
+        ClientGuard(self)
+    }
+
+    /// The number of currently connected clients.
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+}
+
+/// A connected client, see [`Shutdown::client`].
+#[derive(Debug)]
+pub struct ClientGuard<'a>(&'a Shutdown);
+
+impl Drop for ClientGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Wait for `SIGTERM` or `SIGINT`, returning the name of the signal that arrived.
+pub async fn signal_received() -> std::io::Result<&'static str> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => Ok("SIGTERM"),
+        _ = interrupt.recv() => Ok("SIGINT"),
+    }
+}
+
+/// The outcome of saving the dataset on shutdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Save {
+    Skipped,
+    Saved { keys: usize },
+    Failed { error: String },
+}
+
+/// Everything that happened during a shutdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub trigger: Trigger,
+    pub save: Save,
+    pub clients_disconnected: usize,
+    /// Each phase that ran, along with how long it took.
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+impl Report {
+    pub fn new(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            save: Save::Skipped,
+            clients_disconnected: 0,
+            phases: vec![],
+        }
+    }
+
+    /// Run a single phase of the shutdown, timing it.
+    pub async fn phase<T>(
+        &mut self,
+        name: &'static str,
+        phase: impl std::future::Future<Output = T>,
+    ) -> T {
+        let start = Instant::now();
+        let output = phase.await;
+        self.phases.push((name, start.elapsed()));
+        output
+    }
+
+    /// The process exit code that reflects how the shutdown went.
+    pub const fn exit_code(&self) -> i32 {
+        match self.save {
+            Save::Failed { .. } => EXIT_PERSISTENCE_FAILURE,
+            Save::Skipped | Save::Saved { .. } => EXIT_CLEAN,
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "shutdown trigger={}", self.trigger)?;
+        match &self.save {
+            Save::Skipped => write!(f, " save=skipped")?,
+            Save::Saved { keys } => write!(f, " save=ok keys_saved={keys}")?,
+            Save::Failed { error } => write!(f, " save=failed save_error={error:?}")?,
+        }
+        // There is no AOF yet, so there is never anything to sync.
+        write!(f, " aof=disabled")?;
+        write!(f, " clients_disconnected={}", self.clients_disconnected)?;
+        let mut total = Duration::ZERO;
+        for (name, duration) in &self.phases {
+            write!(f, " {name}_ms={}", duration.as_millis())?;
+            total += *duration;
+        }
+        write!(f, " total_ms={}", total.as_millis())?;
+        write!(f, " exit_code={}", self.exit_code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Report, Request, Save, Shutdown, Trigger, EXIT_PERSISTENCE_FAILURE};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn first_request_wins() {
+        let shutdown = Shutdown::default();
+        let mut subscriber = shutdown.subscribe();
+        let clients = (shutdown.client(), shutdown.client());
+        assert_eq!(shutdown.clients(), 2);
+        drop(clients);
+        assert_eq!(shutdown.clients(), 0);
+
+        let first = Request {
+            trigger: Trigger::Signal("SIGTERM"),
+            save: false,
+        };
+        shutdown.request(first);
+        shutdown.request(Request {
+            trigger: Trigger::Command,
+            save: true,
+        });
+        assert!(subscriber.has_changed().unwrap());
+        assert_eq!(*subscriber.borrow_and_update(), Some(first));
+        assert_eq!(shutdown.requested().await, first);
+    }
+
+    #[test]
+    fn report_format_and_exit_code() {
+        let mut report = Report::new(Trigger::Command);
+        report.save = Save::Saved { keys: 3 };
+        report.clients_disconnected = 2;
+        report.phases = vec![
+            ("save", Duration::from_millis(5)),
+            ("disconnect", Duration::from_millis(1)),
+        ];
+        assert_eq!(
+            report.to_string(),
+            "shutdown trigger=SHUTDOWN save=ok keys_saved=3 aof=disabled clients_disconnected=2 \
+             save_ms=5 disconnect_ms=1 total_ms=6 exit_code=0"
+        );
+        report.save = Save::Failed {
+            error: "disk full".to_string(),
+        };
+        assert_eq!(report.exit_code(), EXIT_PERSISTENCE_FAILURE);
+    }
+}
@@ -0,0 +1,256 @@
+//! # The slow log, of the commands that took longer than `slowlog-log-slower-than` to run.
+//!
+//! Only the time spent running a command counts, not the time spent reading the request
+//! or sending the reply, so the log shows what kept the server busy. The log is bounded
+//! by `slowlog-max-len`, dropping the oldest entries first, and both settings can be
+//! changed at run time with `CONFIG SET`.
+
+use crate::resp::Token;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many entries `SLOWLOG GET` replies with, unless told otherwise.
+pub const DEFAULT_COUNT: usize = 10;
+
+/// How many arguments of a command are logged at most, the last one telling how many more there were.
+const MAX_ARGS: usize = 32;
+
+/// How many bytes of an argument are logged at most.
+const MAX_ARG_LEN: usize = 128;
+
+/// The reply to `SLOWLOG HELP`.
+pub const HELP: &[&str] = &[
+    "SLOWLOG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "GET [<count>]",
+    "    Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+    "    Entries are made of:",
+    "    id, timestamp, time in microseconds, arguments array, client IP and port,",
+    "    client name",
+    "LEN",
+    "    Return the length of the slowlog.",
+    "RESET",
+    "    Reset the slowlog.",
+    "HELP",
+    "    Print this help.",
+];
+
+/// The slow log, see the [module docs](self).
+#[derive(Debug)]
+pub struct Slowlog {
+    log: Mutex<Log>,
+    /// How many microseconds a command has to take to get logged, or a negative
+    /// number to log none (`slowlog-log-slower-than`).
+    slower_than: AtomicI64,
+    /// How many entries are kept at most (`slowlog-max-len`).
+    max_len: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    /// The newest entries first.
+    entries: VecDeque<Entry>,
+    next_id: i64,
+}
+
+/// A command that took long to run.
+#[derive(Debug, Clone)]
+struct Entry {
+    id: i64,
+    /// When the command was logged, in seconds since the Unix epoch.
+    timestamp: i64,
+    duration: Duration,
+    args: Vec<String>,
+    /// The address of the client that sent the command, as `ip:port`.
+    addr: String,
+    name: String,
+}
+
+impl Slowlog {
+    pub fn new(slower_than: i64, max_len: usize) -> Self {
+        Self {
+            log: Mutex::default(),
+            slower_than: AtomicI64::new(slower_than),
+            max_len: AtomicUsize::new(max_len),
+        }
+    }
+
+    pub fn slower_than(&self) -> i64 {
+        self.slower_than.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slower_than(&self, micros: i64) {
+        self.slower_than.store(micros, Ordering::Relaxed);
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len.load(Ordering::Relaxed)
+    }
+
+    /// Keep at most `max_len` entries from now on, dropping the oldest ones right away.
+    pub fn set_max_len(&self, max_len: usize) {
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.log().entries.truncate(max_len);
+    }
+
+    /// Whether a command that took `duration` to run belongs in the log.
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        let slower_than = self.slower_than();
+        slower_than >= 0 && duration.as_micros() >= u128::from(slower_than.unsigned_abs())
+    }
+
+    /// Log the command in `request`, which took `duration` to run for the client connected
+    /// from `addr` and named `name`, if it was [slow](Slowlog::is_slow).
+    pub fn record(&self, request: &[Vec<u8>], duration: Duration, addr: String, name: String) {
+        if !self.is_slow(duration) {
+            return;
+        }
+        let mut args: Vec<String> = request
+            .iter()
+            .take(MAX_ARGS)
+            .map(|arg| truncated(&String::from_utf8_lossy(arg)))
+            .collect();
+        if request.len() > MAX_ARGS {
+            args[MAX_ARGS - 1] = format!("... ({} more arguments)", request.len() - MAX_ARGS + 1);
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let timestamp = i64::try_from(timestamp).unwrap_or(i64::MAX);
+        let max_len = self.max_len();
+        let mut log = self.log();
+        let id = log.next_id;
+        log.next_id += 1;
+        log.entries.push_front(Entry {
+            id,
+            timestamp,
+            duration,
+            args,
+            addr,
+            name,
+        });
+        log.entries.truncate(max_len);
+    }
+
+    /// The newest `count` entries, or all of them, as the reply to `SLOWLOG GET`.
+    pub fn get(&self, count: Option<usize>) -> Token {
+        let log = self.log();
+        let count = count.unwrap_or(log.entries.len());
+        Token::Array {
+            tokens: log.entries.iter().take(count).map(Entry::reply).collect(),
+        }
+    }
+
+    /// How many entries there are, as the reply to `SLOWLOG LEN`.
+    pub fn count(&self) -> usize {
+        self.log().entries.len()
+    }
+
+    /// Drop all the entries, but keep counting their IDs up from where they were.
+    pub fn reset(&self) {
+        self.log().entries.clear();
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Entry {
+    fn reply(&self) -> Token {
+        Token::Array {
+            tokens: vec![
+                Token::from(self.id),
+                Token::from(self.timestamp),
+                Token::from(i64::try_from(self.duration.as_micros()).unwrap_or(i64::MAX)),
+                Token::from(self.args.clone()),
+                Token::from(self.addr.clone()),
+                Token::from(self.name.clone()),
+            ],
+        }
+    }
+}
+
+/// Cut `arg` down to [`MAX_ARG_LEN`] bytes, telling how many more there were.
+fn truncated(arg: &str) -> String {
+    if arg.len() <= MAX_ARG_LEN {
+        return arg.to_string();
+    }
+    let mut end = MAX_ARG_LEN;
+    while !arg.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} more bytes)", &arg[..end], arg.len() - end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Slowlog, MAX_ARGS, MAX_ARG_LEN};
+    use crate::resp::{Protocol, Token};
+    use std::time::Duration;
+
+    fn record(slowlog: &Slowlog, request: &[&str], micros: u64) {
+        let request: Vec<Vec<u8>> = request.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        let duration = Duration::from_micros(micros);
+        slowlog.record(&request, duration, "127.0.0.1:50000".into(), String::new());
+    }
+
+    #[test]
+    fn threshold_and_bounds() {
+        let slowlog = Slowlog::new(100, 2);
+        record(&slowlog, &["GET", "fast"], 99);
+        assert_eq!(slowlog.count(), 0);
+        record(&slowlog, &["GET", "a"], 100);
+        record(&slowlog, &["GET", "b"], 200);
+        record(&slowlog, &["GET", "c"], 300);
+        assert_eq!(slowlog.count(), 2);
+        let newest = slowlog.get(Some(1)).encode(Protocol::Resp2);
+        assert!(newest.starts_with("*1\r\n*6\r\n:2\r\n:"), "{newest:?}");
+        assert!(newest
+            .contains(":300\r\n*2\r\n$3\r\nGET\r\n$1\r\nc\r\n$15\r\n127.0.0.1:50000\r\n$0\r\n"));
+
+        slowlog.set_max_len(1);
+        assert_eq!(slowlog.count(), 1);
+        slowlog.reset();
+        assert_eq!(slowlog.count(), 0);
+        // The IDs keep counting up.
+        record(&slowlog, &["GET", "d"], 400);
+        assert!(slowlog
+            .get(None)
+            .encode(Protocol::Resp2)
+            .starts_with("*1\r\n*6\r\n:3\r\n"));
+
+        slowlog.set_slower_than(-1);
+        record(&slowlog, &["GET", "e"], u64::MAX);
+        assert_eq!(slowlog.count(), 1);
+    }
+
+    #[test]
+    fn truncation() {
+        let slowlog = Slowlog::new(0, 10);
+        let long = "x".repeat(MAX_ARG_LEN + 5);
+        let mut request = vec!["DEL"; MAX_ARGS + 9];
+        request[1] = &long;
+        record(&slowlog, &request, 0);
+        let Token::Array { tokens } = slowlog.get(None) else {
+            unreachable!()
+        };
+        let Token::Array { tokens: entry } = &tokens[0] else {
+            unreachable!()
+        };
+        let Token::Array { tokens: args } = &entry[3] else {
+            unreachable!()
+        };
+        assert_eq!(args.len(), MAX_ARGS);
+        assert_eq!(
+            args[1],
+            Token::from(format!("{}... (5 more bytes)", "x".repeat(MAX_ARG_LEN)))
+        );
+        assert_eq!(
+            args[MAX_ARGS - 1],
+            Token::from("... (10 more arguments)".to_string())
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! # Read-only queries against historical RDB snapshots (`SNAPSHOT GET`).
+//!
+//! This is an experimental debugging aid for "what was the value at time T"
+//! questions. When [`Config::snapshot_dir`] is set, every `*.rdb` file in that
+//! directory is considered a snapshot of the keyspace, taken at the moment the
+//! file was last modified (which is when `SAVE` or `BGSAVE` finished writing it).
+//!
+//! A query at time `T` is answered from the newest snapshot taken at or before
+//! `T`, treating keys that had expired by `T` as missing. Snapshots are read into
+//! memory for each query rather than memory-mapped, since mapping them would take
+//! `unsafe` code, which this crate forbids.
+//!
+//! [`Config::snapshot_dir`]: crate::config::Config::snapshot_dir
+
+use crate::database::Data;
+use crate::rdb;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fs, io};
+
+/// Possible errors that can arise while querying snapshots.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("ERR could not read the snapshots: {0}")]
+    Io(#[from] io::Error),
+    #[error("ERR corrupt snapshot: {0}")]
+    Rdb(#[from] rdb::Error),
+    #[error("ERR no snapshot was taken at or before the given time")]
+    NoSnapshot,
+}
+
+/// Find the newest snapshot in `dir` that was taken at or before `at`.
+pub fn find(dir: &Path, at: SystemTime) -> Result<PathBuf, Error> {
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "rdb")
+        {
+            continue;
+        }
+        let taken = fs::metadata(&path)?.modified()?;
+        if taken <= at && newest.as_ref().map_or(true, |(newest, _)| taken > *newest) {
+            newest = Some((taken, path));
+        }
+    }
+    newest.map(|(_, path)| path).ok_or(Error::NoSnapshot)
+}
+
+/// Look up the value that `key` held at the moment `at`, according to the snapshots in `dir`.
+pub fn get(dir: &Path, at: SystemTime, key: &str) -> Result<Option<Data>, Error> {
+    let path = find(dir, at)?;
+    tracing::debug!(?path, "Reading snapshot");
+    let file = rdb::read_file(&fs::read(path)?)?;
+    Ok(file
+        .entries
+        .into_iter()
+        .find(|entry| entry.key == key && !entry.is_expired_at(at))
+        .map(|entry| entry.data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get, Error};
+    use crate::database::{Data, Value};
+    use crate::rdb;
+    use std::time::{Duration, SystemTime};
+    use std::{env, fs};
+
+    #[test]
+    fn value_at_time() {
+        let dir = env::temp_dir().join("redis-starter-rust-snapshots");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        assert!(matches!(get(&dir, before, "a"), Err(Error::NoSnapshot)));
+
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let plain = Value::new("1".to_string(), None);
+        let expiring = Value::new("2".to_string(), Some(Duration::from_secs(10)));
+        let mut out = vec![];
+        rdb::write_file(&mut out, &[], &[], [(&a, &plain), (&b, &expiring)], false);
+        fs::write(dir.join("dump.rdb"), out).unwrap();
+        fs::write(dir.join("notes.txt"), "not a snapshot").unwrap();
+
+        let now = SystemTime::now();
+        assert_eq!(get(&dir, now, "a").unwrap(), Some(Data::String("1".into())));
+        assert_eq!(get(&dir, now, "b").unwrap(), Some(Data::String("2".into())));
+        assert_eq!(get(&dir, now, "c").unwrap(), None);
+        let later = now + Duration::from_secs(60);
+        assert_eq!(get(&dir, later, "b").unwrap(), None);
+        assert!(matches!(get(&dir, before, "a"), Err(Error::NoSnapshot)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! # Server statistics, as reported by `INFO stats`.
+//!
+//! Counters are bumped on every command, so they must not become a point of
+//! contention between the worker threads. Each thread increments its own
+//! cache-line-padded [`Shard`] with relaxed atomics, and the server cron
+//! periodically sums up the shards into a snapshot that readers look at.
+//! Reported values may therefore lag behind by up to one cron period.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{array, fmt::Write};
+
+/// Number of independent shards, more than enough for typical worker thread counts.
+const SHARDS: usize = 16;
+
+/// Things that the server keeps count of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    ConnectionsReceived,
+    CommandsProcessed,
+}
+
+impl Counter {
+    const COUNT: usize = 2;
+    const ALL: [Self; Self::COUNT] = [Self::ConnectionsReceived, Self::CommandsProcessed];
+
+    /// The name of the counter in `INFO`.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::ConnectionsReceived => "total_connections_received",
+            Self::CommandsProcessed => "total_commands_processed",
+        }
+    }
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// One set of counters, aligned so that shards never share a cache line.
+#[derive(Debug)]
+#[repr(align(64))]
+struct Shard([AtomicU64; Counter::COUNT]);
+
+impl Default for Shard {
+    fn default() -> Self {
+        Self(array::from_fn(|_| AtomicU64::new(0)))
+    }
+}
+
+thread_local! {
+    /// The shard that the current thread increments, assigned round-robin.
+    static SHARD: usize = {
+        static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+        NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS
+    };
+}
+
+/// The registry of server statistics.
+#[derive(Debug)]
+pub struct Stats {
+    shards: [Shard; SHARDS],
+    /// The sum of all the shards, as of the last [`Stats::aggregate`].
+    totals: [AtomicU64; Counter::COUNT],
+    /// Commands processed per second, as of the last [`Stats::aggregate`].
+    ops_per_sec: AtomicU64,
+    /// When and with what total of processed commands [`Stats::aggregate`] last ran.
+    last_sample: std::sync::Mutex<(Instant, u64)>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            shards: array::from_fn(|_| Shard::default()),
+            totals: array::from_fn(|_| AtomicU64::new(0)),
+            ops_per_sec: AtomicU64::new(0),
+            last_sample: std::sync::Mutex::new((Instant::now(), 0)),
+        }
+    }
+}
+
+impl Stats {
+    /// Increment `counter` by one. This never blocks.
+    pub fn incr(&self, counter: Counter) {
+        let shard = SHARD.with(|shard| *shard);
+        let _ = self.shards[shard].0[counter.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sum up the shards into the snapshot that [`Stats::get`] reads.
+    ///
+    /// Only the server cron calls this, so the lock around the last
+    /// sample is never contended and is not on the hot path.
+    pub fn aggregate(&self) {
+        for counter in Counter::ALL {
+            let total = self
+                .shards
+                .iter()
+                .map(|shard| shard.0[counter.index()].load(Ordering::Relaxed))
+                .sum();
+            self.totals[counter.index()].store(total, Ordering::Relaxed);
+        }
+
+        let processed = self.get(Counter::CommandsProcessed);
+        let mut last_sample = self
+            .last_sample
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (last_time, last_processed) = *last_sample;
+        let elapsed = last_time.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            let ops = (processed - last_processed) as f64 / elapsed;
+            self.ops_per_sec
+                .store(ops.round() as u64, Ordering::Relaxed);
+        }
+        *last_sample = (Instant::now(), processed);
+    }
+
+    /// Get the value of `counter` as of the last aggregation.
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.totals[counter.index()].load(Ordering::Relaxed)
+    }
+
+    /// Render the `# Stats` section of `INFO`.
+    pub fn info(&self) -> String {
+        let mut info = String::from("# Stats\r\n");
+        for counter in Counter::ALL {
+            let _ = write!(info, "{}:{}\r\n", counter.name(), self.get(counter));
+        }
+        let ops_per_sec = self.ops_per_sec.load(Ordering::Relaxed);
+        let _ = write!(info, "instantaneous_ops_per_sec:{ops_per_sec}\r\n");
+        info
+    }
+}
+
+/// Upper bounds of the buckets of the [`TtlHistogram`], along with their names in `INFO`.
+///
+/// TTLs of a day or longer fall into one more, unbounded bucket.
+pub const TTL_BUCKETS: [(Duration, &str); 5] = [
+    (Duration::from_secs(1), "1s"),
+    (Duration::from_secs(10), "10s"),
+    (Duration::from_secs(60), "1m"),
+    (Duration::from_secs(60 * 60), "1h"),
+    (Duration::from_secs(24 * 60 * 60), "1d"),
+];
+
+/// How long it takes for a recorded TTL to weigh half as much in the [`TtlHistogram`].
+const TTL_HALF_LIFE: Duration = Duration::from_secs(60);
+
+/// A histogram of the TTLs that keys were recently given, as reported by `INFO ttl`.
+///
+/// Recording a TTL is a single relaxed atomic increment. The server cron then folds
+/// the fresh counts into exponentially decayed ones, so that the histogram reflects
+/// the recent write pattern rather than everything since startup.
+#[derive(Debug)]
+pub struct TtlHistogram {
+    /// TTLs recorded since the last [`TtlHistogram::decay`], per bucket.
+    fresh: [AtomicU64; TTL_BUCKETS.len() + 1],
+    /// The decayed counts, per bucket, as of the last [`TtlHistogram::decay`].
+    decayed: std::sync::Mutex<[f64; TTL_BUCKETS.len() + 1]>,
+}
+
+impl Default for TtlHistogram {
+    fn default() -> Self {
+        Self {
+            fresh: array::from_fn(|_| AtomicU64::new(0)),
+            decayed: std::sync::Mutex::new([0.0; TTL_BUCKETS.len() + 1]),
+        }
+    }
+}
+
+impl TtlHistogram {
+    /// Record that a key was given the `ttl`. This never blocks.
+    pub fn record(&self, ttl: Duration) {
+        let bucket = TTL_BUCKETS
+            .iter()
+            .position(|(bound, _)| ttl < *bound)
+            .unwrap_or(TTL_BUCKETS.len());
+        let _ = self.fresh[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decay the counts by the `elapsed` time and fold in the freshly recorded TTLs.
+    pub fn decay(&self, elapsed: Duration) {
+        let factor = 0.5_f64.powf(elapsed.as_secs_f64() / TTL_HALF_LIFE.as_secs_f64());
+        let mut decayed = self.decayed();
+        for (count, fresh) in decayed.iter_mut().zip(&self.fresh) {
+            *count = (*count).mul_add(factor, fresh.swap(0, Ordering::Relaxed) as f64);
+        }
+    }
+
+    /// Render the `# TTL` section of `INFO`.
+    ///
+    /// The `forecast` holds the number of keys that expire within each of
+    /// the [`TTL_BUCKETS`] bounds, as computed by the caller.
+    pub fn info(&self, forecast: &[usize]) -> String {
+        let mut info = String::from("# TTL\r\n");
+        let decayed = *self.decayed();
+        for ((_, name), count) in TTL_BUCKETS.iter().zip(decayed) {
+            let _ = write!(info, "ttl_lt_{name}:{count:.2}\r\n");
+        }
+        let (_, longest) = TTL_BUCKETS[TTL_BUCKETS.len() - 1];
+        let _ = write!(
+            info,
+            "ttl_ge_{longest}:{:.2}\r\n",
+            decayed[TTL_BUCKETS.len()]
+        );
+        for ((_, name), count) in TTL_BUCKETS.iter().zip(forecast) {
+            let _ = write!(info, "expiring_within_{name}:{count}\r\n");
+        }
+        info
+    }
+
+    fn decayed(&self) -> std::sync::MutexGuard<'_, [f64; TTL_BUCKETS.len() + 1]> {
+        self.decayed
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counter, Stats, TtlHistogram, TTL_HALF_LIFE};
+    use std::time::Duration;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn concurrent_increments_add_up() {
+        let stats = Arc::new(Stats::default());
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let stats = Arc::clone(&stats);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        stats.incr(Counter::CommandsProcessed);
+                    }
+                    stats.incr(Counter::ConnectionsReceived);
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .for_each(|worker| worker.join().unwrap());
+
+        assert_eq!(stats.get(Counter::CommandsProcessed), 0);
+        stats.aggregate();
+        assert_eq!(stats.get(Counter::CommandsProcessed), 8000);
+        assert_eq!(stats.get(Counter::ConnectionsReceived), 8);
+    }
+
+    #[test]
+    fn info_section() {
+        let stats = Stats::default();
+        stats.incr(Counter::ConnectionsReceived);
+        stats.aggregate();
+        let info = stats.info();
+        assert!(info.starts_with("# Stats\r\n"));
+        assert!(info.contains("total_connections_received:1\r\n"));
+        assert!(info.contains("total_commands_processed:0\r\n"));
+        assert!(info.contains("instantaneous_ops_per_sec:"));
+    }
+
+    #[test]
+    fn ttl_histogram_buckets_and_decay() {
+        let histogram = TtlHistogram::default();
+        histogram.record(Duration::from_millis(500));
+        histogram.record(Duration::from_secs(5));
+        histogram.record(Duration::from_secs(5));
+        histogram.record(Duration::from_secs(7 * 24 * 60 * 60));
+        histogram.decay(Duration::ZERO);
+        assert_eq!(*histogram.decayed(), [1.0, 2.0, 0.0, 0.0, 0.0, 1.0]);
+
+        histogram.decay(TTL_HALF_LIFE);
+        assert_eq!(*histogram.decayed(), [0.5, 1.0, 0.0, 0.0, 0.0, 0.5]);
+
+        let info = histogram.info(&[0, 3, 3, 4, 4]);
+        assert!(info.starts_with("# TTL\r\n"));
+        assert!(info.contains("ttl_lt_10s:1.00\r\n"));
+        assert!(info.contains("ttl_ge_1d:0.50\r\n"));
+        assert!(info.contains("expiring_within_10s:3\r\n"));
+    }
+}
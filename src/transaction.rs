@@ -0,0 +1,150 @@
+//! # Transactions: the commands queued between `MULTI` and `EXEC`.
+//!
+//! Once a client sends `MULTI`, every following command is only checked and queued,
+//! with `+QUEUED` as the reply, until `EXEC` runs the whole queue at once, or `DISCARD`
+//! drops it. Like in Redis:
+//!
+//! - The queue runs under a single lock of the database, so that no other client can
+//!   see or change the keyspace halfway through, and its replies are sent back as one
+//!   array, in a single write.
+//! - A command that can not even be parsed aborts the transaction, and `EXEC` then
+//!   fails with `EXECABORT` without running anything. Commands that fail while running
+//!   do not, their errors just end up in the array of replies.
+//! - `QUIT` and `RESET` are never queued, and `RESET` drops the transaction too.
+//! - Keys watched with `WATCH` before `MULTI` make `EXEC` reply with a null array
+//!   instead of running anything if any other write touched them meanwhile, or they
+//!   expired. `EXEC`, `DISCARD`, `UNWATCH` and `RESET` all stop watching them.
+//! - Blocking commands like `BZPOPMIN` never block inside a transaction, they reply
+//!   as if their timeout ran out instead, since the database can't change meanwhile.
+
+use crate::command::{Command, Request};
+use crate::resp::Token;
+
+/// The reply to `MULTI` while a transaction is already open.
+pub const NESTED: &str = "ERR MULTI calls can not be nested";
+/// The reply to `EXEC` without an open transaction.
+pub const EXEC_WITHOUT_MULTI: &str = "ERR EXEC without MULTI";
+/// The reply to `DISCARD` without an open transaction.
+pub const DISCARD_WITHOUT_MULTI: &str = "ERR DISCARD without MULTI";
+/// The reply to `WATCH` while a transaction is open.
+pub const WATCH_INSIDE_MULTI: &str = "ERR WATCH inside MULTI is not allowed";
+/// The reply to `EXEC` once the transaction has been aborted.
+pub const EXEC_ABORTED: &str = "EXECABORT Transaction discarded because of previous errors.";
+
+/// An open transaction of a single connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    /// The queued commands, along with the requests they were parsed from.
+    queued: Vec<(Command, Request)>,
+    /// Whether a command could not be queued, which makes `EXEC` fail.
+    aborted: bool,
+}
+
+impl Transaction {
+    /// Queue `command`, parsed from `request`, to run on `EXEC`, returning the reply to send meanwhile.
+    pub fn queue(&mut self, command: Command, request: Request) -> Token {
+        self.queued.push((command, request));
+        Token::SimpleString {
+            data: "QUEUED".to_string(),
+        }
+    }
+
+    /// How many commands are queued so far.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Whether `CLIENT PAUSE WRITE` holds back `EXEC`, see [`Command::pauses_on_write`].
+    pub fn pauses_on_write(&self) -> bool {
+        self.queued
+            .iter()
+            .any(|(command, _)| command.pauses_on_write())
+    }
+
+    /// Abort the transaction because a command could not be queued.
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+
+    /// The commands to run on `EXEC` along with their requests, in order,
+    /// or the error to reply with instead.
+    pub fn into_commands(self) -> Result<Vec<(Command, Request)>, Token> {
+        if self.aborted {
+            return Err(error(EXEC_ABORTED));
+        }
+        Ok(self.queued)
+    }
+}
+
+/// Whether `command` runs right away, even while a transaction is open.
+pub const fn runs_immediately(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch { .. }
+            | Command::Quit
+            | Command::Reset
+    )
+}
+
+/// An error reply with the given `message`.
+pub fn error(message: &str) -> Token {
+    Token::SimpleError {
+        data: message.to_string(),
+    }
+}
+
+/// Merge the replies of a queued command into the single entry it gets in the reply of `EXEC`.
+pub fn merge(mut replies: Vec<Token>) -> Token {
+    if replies.len() == 1 {
+        replies.remove(0)
+    } else {
+        Token::Array { tokens: replies }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, runs_immediately, Transaction, EXEC_ABORTED};
+    use crate::command::Command;
+    use crate::resp::{Protocol, Token};
+
+    #[test]
+    fn queue_and_abort() {
+        let mut transaction = Transaction::default();
+        let ping = Command::Ping { message: None };
+        let request = vec![b"PING".to_vec()];
+        assert_eq!(
+            transaction
+                .queue(ping.clone(), request.clone())
+                .encode(Protocol::Resp2),
+            "+QUEUED\r\n"
+        );
+        assert_eq!(
+            transaction.clone().into_commands(),
+            Ok(vec![(ping, request)])
+        );
+
+        transaction.abort();
+        let Err(reply) = transaction.into_commands() else {
+            panic!("an aborted transaction should not run");
+        };
+        assert_eq!(
+            reply.encode(Protocol::Resp2),
+            format!("-{EXEC_ABORTED}\r\n")
+        );
+
+        assert!(runs_immediately(&Command::Discard));
+        assert!(!runs_immediately(&Command::Ping { message: None }));
+        let pong = Token::from("PONG".to_string());
+        assert_eq!(merge(vec![pong.clone()]), pong);
+        assert_eq!(
+            merge(vec![pong.clone(), pong.clone()]),
+            Token::Array {
+                tokens: vec![pong.clone(), pong]
+            }
+        );
+    }
+}
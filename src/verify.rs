@@ -0,0 +1,268 @@
+//! # `verify-replica`: keyspace diffing between a master and one of its replicas.
+//!
+//! Both keyspaces are walked with `SCAN`, and every key is described by its type,
+//! its value (normalized, so that e.g. set members can be compared regardless of
+//! their order) and its TTL. The two descriptions are then compared key by key.
+//!
+//! The snapshots are taken one after the other, not atomically, so keys that are
+//! being written to while the tool runs may show up as false positives. TTLs are
+//! compared with a tolerance, since they keep ticking between the two snapshots.
+
+use crate::client::Client;
+use crate::resp::Token;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+
+/// How many keys to ask for in each `SCAN` call.
+const SCAN_COUNT: &str = "1000";
+
+/// Everything that is compared about a single key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyState {
+    /// The type of the value, as reported by `TYPE`.
+    kind: String,
+    /// The value, normalized for comparison.
+    value: Vec<String>,
+    /// The remaining TTL in milliseconds, as reported by `PTTL`.
+    pttl: i64,
+}
+
+/// All the keys of a server, along with their [`KeyState`]s.
+pub type Snapshot = BTreeMap<String, KeyState>;
+
+/// A difference between the master and the replica.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    MissingOnReplica {
+        key: String,
+    },
+    MissingOnMaster {
+        key: String,
+    },
+    Type {
+        key: String,
+        master: String,
+        replica: String,
+    },
+    Value {
+        key: String,
+    },
+    Ttl {
+        key: String,
+        master: i64,
+        replica: i64,
+    },
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingOnReplica { key } => write!(f, "{key:?}: missing on the replica"),
+            Self::MissingOnMaster { key } => write!(f, "{key:?}: only present on the replica"),
+            Self::Type {
+                key,
+                master,
+                replica,
+            } => write!(
+                f,
+                "{key:?}: type is {master} on the master, {replica} on the replica"
+            ),
+            Self::Value { key } => write!(f, "{key:?}: values differ"),
+            Self::Ttl {
+                key,
+                master,
+                replica,
+            } => write!(
+                f,
+                "{key:?}: PTTL is {master} on the master, {replica} on the replica"
+            ),
+        }
+    }
+}
+
+/// Snapshot the keyspaces of the `master` and the `replica` and compare them.
+pub async fn verify_replica(
+    master: &str,
+    replica: &str,
+    ttl_tolerance: u64,
+) -> anyhow::Result<Vec<Divergence>> {
+    let master = snapshot(&mut Client::connect(master).await?).await?;
+    let replica = snapshot(&mut Client::connect(replica).await?).await?;
+    Ok(diff(&master, &replica, ttl_tolerance))
+}
+
+/// Walk the whole keyspace of the server behind `client` and describe every key.
+pub async fn snapshot(client: &mut Client) -> anyhow::Result<Snapshot> {
+    let mut keys = BTreeSet::new();
+    let mut cursor = String::from("0");
+    loop {
+        let reply = client.call(&["SCAN", &cursor, "COUNT", SCAN_COUNT]).await?;
+        let Token::Array { tokens } = reply else {
+            anyhow::bail!("Unexpected reply to SCAN: {reply:?}");
+        };
+        let [next, Token::Array { tokens: batch }] = tokens.as_slice() else {
+            anyhow::bail!("Unexpected reply to SCAN: {tokens:?}");
+        };
+        keys.extend(batch.iter().filter_map(Token::extract).map(String::from));
+        cursor = next.extract().unwrap_or("0").to_string();
+        if cursor == "0" {
+            break;
+        }
+    }
+
+    let mut snapshot = Snapshot::new();
+    for key in keys {
+        let kind = text(&client.call(&["TYPE", &key]).await?);
+        if kind == "none" {
+            // The key expired or got deleted while scanning.
+            continue;
+        }
+        let value = match kind.as_str() {
+            "string" => vec![text(&client.call(&["GET", &key]).await?)],
+            "set" => sorted(client.call(&["SMEMBERS", &key]).await?),
+            "list" => flat(client.call(&["LRANGE", &key, "0", "-1"]).await?),
+            "hash" => sorted(client.call(&["HGETALL", &key]).await?),
+            "zset" => flat(
+                client
+                    .call(&["ZRANGE", &key, "0", "-1", "WITHSCORES"])
+                    .await?,
+            ),
+            // Anything else is only compared by its type and TTL.
+            _ => vec![],
+        };
+        let pttl = match client.call(&["PTTL", &key]).await? {
+            Token::Integer { data } => data,
+            other => anyhow::bail!("Unexpected reply to PTTL: {other:?}"),
+        };
+        let _ = snapshot.insert(key, KeyState { kind, value, pttl });
+    }
+    Ok(snapshot)
+}
+
+/// Compare two [`Snapshot`]s, allowing TTLs to differ by up to `ttl_tolerance` milliseconds.
+pub fn diff(master: &Snapshot, replica: &Snapshot, ttl_tolerance: u64) -> Vec<Divergence> {
+    let mut divergences = vec![];
+    for (key, expected) in master {
+        let key = key.clone();
+        let Some(actual) = replica.get(&key) else {
+            divergences.push(Divergence::MissingOnReplica { key });
+            continue;
+        };
+        if expected.kind != actual.kind {
+            divergences.push(Divergence::Type {
+                key,
+                master: expected.kind.clone(),
+                replica: actual.kind.clone(),
+            });
+            continue;
+        }
+        if expected.value != actual.value {
+            divergences.push(Divergence::Value { key: key.clone() });
+        }
+        let ttls_match = match (expected.pttl, actual.pttl) {
+            (-1, -1) => true,
+            (master, replica) if master >= 0 && replica >= 0 => {
+                master.abs_diff(replica) <= ttl_tolerance
+            }
+            _ => false,
+        };
+        if !ttls_match {
+            divergences.push(Divergence::Ttl {
+                key,
+                master: expected.pttl,
+                replica: actual.pttl,
+            });
+        }
+    }
+    divergences.extend(
+        replica
+            .keys()
+            .filter(|key| !master.contains_key(*key))
+            .map(|key| Divergence::MissingOnMaster { key: key.clone() }),
+    );
+    divergences
+}
+
+fn text(token: &Token) -> String {
+    token.extract().unwrap_or_default().to_string()
+}
+
+fn flat(token: Token) -> Vec<String> {
+    match token {
+        Token::Array { tokens } => tokens.iter().map(text).collect(),
+        other => vec![text(&other)],
+    }
+}
+
+fn sorted(token: Token) -> Vec<String> {
+    let mut items = flat(token);
+    items.sort();
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, Divergence, KeyState, Snapshot};
+
+    fn state(kind: &str, value: &[&str], pttl: i64) -> KeyState {
+        KeyState {
+            kind: kind.to_string(),
+            value: value.iter().map(ToString::to_string).collect(),
+            pttl,
+        }
+    }
+
+    #[test]
+    fn identical_snapshots() {
+        let snapshot: Snapshot = [
+            ("a".to_string(), state("string", &["1"], -1)),
+            ("b".to_string(), state("set", &["x", "y"], 5000)),
+        ]
+        .into();
+        assert!(diff(&snapshot, &snapshot.clone(), 0).is_empty());
+    }
+
+    #[test]
+    fn every_kind_of_divergence() {
+        let master: Snapshot = [
+            ("missing".to_string(), state("string", &["1"], -1)),
+            ("type".to_string(), state("string", &["1"], -1)),
+            ("value".to_string(), state("string", &["1"], -1)),
+            ("ttl".to_string(), state("string", &["1"], 10_000)),
+            ("close-ttl".to_string(), state("string", &["1"], 10_000)),
+        ]
+        .into();
+        let replica: Snapshot = [
+            ("type".to_string(), state("set", &["1"], -1)),
+            ("value".to_string(), state("string", &["2"], -1)),
+            ("ttl".to_string(), state("string", &["1"], -1)),
+            ("close-ttl".to_string(), state("string", &["1"], 9_500)),
+            ("extra".to_string(), state("string", &["1"], -1)),
+        ]
+        .into();
+        assert_eq!(
+            diff(&master, &replica, 1000),
+            vec![
+                Divergence::MissingOnReplica {
+                    key: "missing".into()
+                },
+                Divergence::Ttl {
+                    key: "ttl".into(),
+                    master: 10_000,
+                    replica: -1
+                },
+                Divergence::Type {
+                    key: "type".into(),
+                    master: "string".into(),
+                    replica: "set".into()
+                },
+                Divergence::Value {
+                    key: "value".into()
+                },
+                Divergence::MissingOnMaster {
+                    key: "extra".into()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,1770 @@
+//! # End-to-end tests that mirror the stages of the CodeCrafters tester.
+//!
+//! Every test spawns the actual server binary on a port of its own, talks to it
+//! over TCP the way the tester does, and compares the raw RESP replies, so stage
+//! regressions show up with `cargo test` instead of after a push to the platform.
+//!
+//! Past the stages, the tests cover the features the server grew since, mostly one test
+//! per command family. Only the benchmark is `#[ignore]`d, as it takes a while to run.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a spawned server to start accepting connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A server process that lives for as long as this value does.
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    /// Spawn the server with the given extra command-line `args`.
+    fn spawn(args: &[&str]) -> Self {
+        let port = free_port();
+        let process = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"))
+            .arg("--port")
+            .arg(port.to_string())
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Could not spawn the server");
+        let server = Self { process, port };
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                Instant::now() < deadline,
+                "The server did not start in time"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        server
+    }
+
+    fn client(&self) -> Client {
+        Client::connect(self.port)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Find a port that nothing listens on, by letting the OS pick one.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Could not find a free port")
+        .port()
+}
+
+/// A bare-bones client that sends commands and returns the raw RESP replies.
+struct Client {
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).expect("Could not connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("Could not set a read timeout");
+        Self {
+            reader: BufReader::new(stream),
+        }
+    }
+
+    /// Send a command as an array of bulk strings and read back the whole reply.
+    fn call(&mut self, args: &[&str]) -> String {
+        self.send(args);
+        self.reply().expect("Could not read the reply")
+    }
+
+    /// Send a command as an array of bulk strings, without waiting for the reply.
+    fn send(&mut self, args: &[&str]) {
+        let mut command = format!("*{}\r\n", args.len());
+        for arg in args {
+            command.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+        }
+        self.reader
+            .get_mut()
+            .write_all(command.as_bytes())
+            .expect("Could not send the command");
+    }
+
+    /// Send raw bytes as they are, without waiting for any reply.
+    fn send_raw(&mut self, bytes: &[u8]) {
+        self.reader
+            .get_mut()
+            .write_all(bytes)
+            .expect("Could not send the bytes");
+    }
+
+    /// Read a single reply, including all the elements of an aggregate one.
+    fn reply(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let length = || line[1..].trim_end().parse::<i64>().unwrap_or(-1);
+        match line.chars().next() {
+            Some('$') if length() >= 0 => {
+                let mut data = vec![0; length() as usize + 2];
+                self.reader.read_exact(&mut data)?;
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            Some('*' | '>') => {
+                for _ in 0..length().max(0) {
+                    line.push_str(&self.reply()?);
+                }
+            }
+            Some('%') => {
+                for _ in 0..length().max(0) * 2 {
+                    line.push_str(&self.reply()?);
+                }
+            }
+            _ => {}
+        }
+        Ok(line)
+    }
+}
+
+/// Build the RESP encoding of a bulk string.
+fn bulk(data: &str) -> String {
+    format!("${}\r\n{data}\r\n", data.len())
+}
+
+/// The contents of a simple or bulk string reply, both of which the tester accepts as a string.
+fn string(reply: &str) -> Option<&str> {
+    match reply.split_once("\r\n")? {
+        (simple, "") if simple.starts_with('+') => Some(&simple[1..]),
+        (header, data) if header.starts_with('$') => data.strip_suffix("\r\n"),
+        _ => None,
+    }
+}
+
+#[test]
+fn bind() {
+    let server = Server::spawn(&[]);
+    assert!(TcpStream::connect(("127.0.0.1", server.port)).is_ok());
+}
+
+#[test]
+fn ping() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn ping_multiple_times() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    for _ in 0..3 {
+        assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+    }
+}
+
+#[test]
+fn pipelined_requests() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    client.send_raw(b"*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n*1\r\n$4\r\nPING\r\n");
+    assert_eq!(client.reply().unwrap(), "+PONG\r\n");
+    assert_eq!(string(&client.reply().unwrap()), Some("hey"));
+    assert_eq!(client.reply().unwrap(), "+PONG\r\n");
+
+    // A request split across writes waits for the rest of it.
+    client.send_raw(b"*2\r\n$4\r\nECHO\r\n$5\r\nap");
+    thread::sleep(Duration::from_millis(50));
+    client.send_raw(b"ple\r\n");
+    assert_eq!(string(&client.reply().unwrap()), Some("apple"));
+}
+
+#[test]
+fn inline_commands() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    // As typed into `telnet`, blank lines included.
+    client.send_raw(b"PING\r\n\r\nSET fruit  apple\r\nGET fruit\n");
+    assert_eq!(client.reply().unwrap(), "+PONG\r\n");
+    assert_eq!(client.reply().unwrap(), "+OK\r\n");
+    assert_eq!(string(&client.reply().unwrap()), Some("apple"));
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn large_requests() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    let value = "x".repeat(100_000);
+    assert_eq!(client.call(&["SET", "big", &value]), "+OK\r\n");
+    assert_eq!(string(&client.call(&["GET", "big"])), Some(value.as_str()));
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn concurrent_clients() {
+    let server = Server::spawn(&[]);
+    let mut clients: Vec<Client> = (0..3).map(|_| server.client()).collect();
+    for client in clients.iter_mut().rev() {
+        assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+    }
+    for client in &mut clients {
+        assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+    }
+}
+
+#[test]
+fn echo() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        string(&client.call(&["ECHO", "strawberry"])),
+        Some("strawberry")
+    );
+}
+
+#[test]
+fn parse_errors() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["NOPE", "a", "b"]),
+        "-ERR unknown command 'NOPE', with args beginning with: 'a' 'b' \r\n"
+    );
+    assert_eq!(
+        client.call(&["GET"]),
+        "-ERR wrong number of arguments for 'get' command\r\n"
+    );
+    // The connection is still open.
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn set_and_get() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SET", "grape", "mango"]), "+OK\r\n");
+    assert_eq!(string(&client.call(&["GET", "grape"])), Some("mango"));
+}
+
+#[test]
+fn binary_safe_get() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SET", "framed", "a\r\n:1"]), "+OK\r\n");
+    assert_eq!(client.call(&["GET", "framed"]), bulk("a\r\n:1"));
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+
+    // Setting the top bit makes a byte that isn't valid UTF-8 on its own.
+    assert_eq!(client.call(&["SETBIT", "bits", "0", "1"]), ":0\r\n");
+    client.send(&["GET", "bits"]);
+    let mut reply = [0; 7];
+    client.reader.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"$1\r\n\x80\r\n");
+
+    // Values that aren't valid UTF-8 make it in and back out byte for byte.
+    client.send_raw(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\n\xff\x00\xc3\x28\r\n");
+    assert_eq!(client.reply().unwrap(), "+OK\r\n");
+    client.send(&["GET", "k"]);
+    let mut reply = [0; 10];
+    client.reader.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"$4\r\n\xff\x00\xc3\x28\r\n");
+}
+
+#[test]
+fn expiry() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["SET", "pear", "apple", "px", "100"]),
+        "+OK\r\n"
+    );
+    assert_eq!(string(&client.call(&["GET", "pear"])), Some("apple"));
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(client.call(&["GET", "pear"]), "$-1\r\n");
+}
+
+#[test]
+fn rdb_config() {
+    let dir = std::env::temp_dir();
+    let dir = dir.to_string_lossy();
+    let server = Server::spawn(&["--dir", &dir, "--dbfilename", "stages.rdb"]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["CONFIG", "GET", "dir"]),
+        format!("*2\r\n{}{}", bulk("dir"), bulk(&dir))
+    );
+    assert_eq!(
+        client.call(&["CONFIG", "GET", "dbfilename"]),
+        format!("*2\r\n{}{}", bulk("dbfilename"), bulk("stages.rdb"))
+    );
+}
+
+/// An RDB file holding `foo` => `bar`, as the tester writes it.
+const RDB_WITH_ONE_KEY: &[u8] =
+    b"REDIS0011\xfe\x00\xfb\x01\x00\x00\x03foo\x03bar\xff\0\0\0\0\0\0\0\0";
+
+#[test]
+fn keys_from_rdb() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("one-key.rdb"), RDB_WITH_ONE_KEY).unwrap();
+    let server = Server::spawn(&[
+        "--dir",
+        &dir.to_string_lossy(),
+        "--dbfilename",
+        "one-key.rdb",
+    ]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["KEYS", "*"]),
+        format!("*1\r\n{}", bulk("foo"))
+    );
+    assert_eq!(string(&client.call(&["GET", "foo"])), Some("bar"));
+}
+
+#[test]
+fn dataset_survives_a_restart() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("restart.rdb"));
+    let args = [
+        "--dir",
+        &dir.to_string_lossy(),
+        "--dbfilename",
+        "restart.rdb",
+    ];
+    let mut server = Server::spawn(&args);
+    let mut client = server.client();
+    let library = "#!lua name=greeting\n\
+        redis.register_function('greet', function(keys) \
+          return redis.call('GET', keys[1]) end)";
+    assert_eq!(
+        client.call(&["FUNCTION", "LOAD", library]),
+        bulk("greeting")
+    );
+    assert_eq!(client.call(&["SET", "name", "world"]), "+OK\r\n");
+    assert_eq!(
+        client.call(&["XADD", "events", "1-1", "kind", "greeting"]),
+        bulk("1-1")
+    );
+    let group = ["XGROUP", "CREATE", "events", "readers", "0"];
+    assert_eq!(client.call(&group), "+OK\r\n");
+    let read = [
+        "XREADGROUP",
+        "GROUP",
+        "readers",
+        "alice",
+        "STREAMS",
+        "events",
+        ">",
+    ];
+    let entries = client.call(&read);
+    client.send(&["SHUTDOWN", "SAVE"]);
+    let _ = server.process.wait();
+
+    let server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(string(&client.call(&["GET", "name"])), Some("world"));
+    assert_eq!(client.call(&["TYPE", "events"]), "+stream\r\n");
+    // The entry is still pending for the consumer that read it.
+    let pending = [
+        "XREADGROUP",
+        "GROUP",
+        "readers",
+        "alice",
+        "STREAMS",
+        "events",
+        "0",
+    ];
+    assert_eq!(client.call(&pending), entries);
+    assert_eq!(
+        string(&client.call(&["FCALL", "greet", "1", "name"])),
+        Some("world")
+    );
+}
+
+#[test]
+fn append_only_file() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("stages.aof"));
+    let args = [
+        "--dir",
+        &dir.to_string_lossy(),
+        "--appendonly",
+        "yes",
+        "--appendfilename",
+        "stages.aof",
+        "--appendfsync",
+        "always",
+    ];
+    let server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["CONFIG", "GET", "appendfsync"]),
+        format!("*2\r\n{}{}", bulk("appendfsync"), bulk("always"))
+    );
+    assert_eq!(client.call(&["SET", "kept", "1", "EX", "100"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "gone", "2"]), "+OK\r\n");
+    assert_eq!(client.call(&["SADD", "set", "a", "b"]), ":2\r\n");
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["DEL", "gone"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["SREM", "set", "a"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["EXEC"]), "*2\r\n:1\r\n:1\r\n");
+    assert_eq!(
+        client.call(&["CONFIG", "SET", "appendfsync", "everysec"]),
+        "+OK\r\n"
+    );
+    assert!(client
+        .call(&["CONFIG", "SET", "appendfsync", "sometimes"])
+        .starts_with("-ERR"));
+    assert!(client
+        .call(&["INFO", "persistence"])
+        .contains("aof_enabled:1\r\n"));
+    // Killed without saving, which leaves only the AOF to load the dataset from.
+    drop(server);
+
+    let server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(string(&client.call(&["GET", "kept"])), Some("1"));
+    let pttl: i64 = client.call(&["PTTL", "kept"])[1..].trim().parse().unwrap();
+    assert!((1..=100_000).contains(&pttl));
+    assert_eq!(client.call(&["TYPE", "gone"]), "+none\r\n");
+    assert_eq!(
+        client.call(&["SMEMBERS", "set"]),
+        format!("*1\r\n{}", bulk("b"))
+    );
+}
+
+#[test]
+fn aof_rewrite() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    for preamble in ["yes", "no"] {
+        let file = format!("rewrite-{preamble}.aof");
+        let _ = std::fs::remove_file(dir.join(&file));
+        let args = [
+            "--dir",
+            &dir.to_string_lossy(),
+            "--appendonly",
+            "yes",
+            "--appendfilename",
+            &file,
+            "--aof-use-rdb-preamble",
+            preamble,
+        ];
+        let server = Server::spawn(&args);
+        let mut client = server.client();
+        for i in 0..10 {
+            assert_eq!(client.call(&["SET", "counter", &i.to_string()]), "+OK\r\n");
+        }
+        assert_eq!(
+            client.call(&["XADD", "stream", "1-1", "f", "v"]),
+            bulk("1-1")
+        );
+        assert_eq!(
+            client.call(&["XGROUP", "CREATE", "stream", "g", "0"]),
+            "+OK\r\n"
+        );
+        let read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "alice",
+            "STREAMS",
+            "stream",
+            ">",
+        ];
+        assert!(client.call(&read).contains("1-1"));
+        let before = std::fs::metadata(dir.join(&file)).unwrap().len();
+        assert_eq!(
+            client.call(&["BGREWRITEAOF"]),
+            "+Background append only file rewriting started\r\n"
+        );
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while client
+            .call(&["INFO", "persistence"])
+            .contains("aof_rewrite_in_progress:1")
+        {
+            assert!(
+                Instant::now() < deadline,
+                "The background rewrite took too long"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        let info = client.call(&["INFO", "persistence"]);
+        assert!(info.contains("aof_last_bgrewrite_status:ok"), "{info}");
+        let contents = std::fs::read(dir.join(&file)).unwrap();
+        assert!(
+            (contents.len() as u64) < before,
+            "{} >= {before}",
+            contents.len()
+        );
+        assert_eq!(contents.starts_with(b"REDIS"), preamble == "yes");
+        // Written after the rewrite, into the new file.
+        assert_eq!(client.call(&["SET", "late", "1"]), "+OK\r\n");
+        drop(server);
+
+        let server = Server::spawn(&args);
+        let mut client = server.client();
+        assert_eq!(string(&client.call(&["GET", "counter"])), Some("9"));
+        assert_eq!(string(&client.call(&["GET", "late"])), Some("1"));
+        let pending = client.call(&["XPENDING", "stream", "g", "-", "+", "10"]);
+        assert!(
+            pending.contains("1-1") && pending.contains("alice"),
+            "{pending}"
+        );
+        assert_eq!(client.call(&read), "*-1\r\n");
+    }
+}
+
+#[test]
+fn save_and_bgsave() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("bgsave.rdb"));
+    let args = [
+        "--dir",
+        &dir.to_string_lossy(),
+        "--dbfilename",
+        "bgsave.rdb",
+    ];
+    let server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SET", "saved", "1"]), "+OK\r\n");
+    assert_eq!(client.call(&["SAVE"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "bgsaved", "2"]), "+OK\r\n");
+    assert_eq!(client.call(&["BGSAVE"]), "+Background saving started\r\n");
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while client
+        .call(&["INFO", "persistence"])
+        .contains("rdb_bgsave_in_progress:1")
+    {
+        assert!(
+            Instant::now() < deadline,
+            "The background save took too long"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    let info = client.call(&["INFO", "persistence"]);
+    assert!(info.contains("rdb_last_bgsave_status:ok"), "{info}");
+    let last_save = client.call(&["LASTSAVE"]);
+    assert!(
+        info.contains(&format!(
+            "rdb_last_save_time:{}",
+            &last_save[1..last_save.len() - 2]
+        )),
+        "{info}"
+    );
+    drop(server);
+
+    let server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(string(&client.call(&["GET", "saved"])), Some("1"));
+    assert_eq!(string(&client.call(&["GET", "bgsaved"])), Some("2"));
+}
+
+#[test]
+fn save_points() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("save-points.rdb"));
+    let server = Server::spawn(&[
+        "--dir",
+        &dir.to_string_lossy(),
+        "--dbfilename",
+        "save-points.rdb",
+        "--save",
+        "3600 100 0 2",
+    ]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["CONFIG", "GET", "save"]),
+        format!("*2\r\n{}{}", bulk("save"), bulk("3600 100 0 2"))
+    );
+    assert_eq!(client.call(&["SET", "one", "1"]), "+OK\r\n");
+    thread::sleep(Duration::from_millis(300));
+    let info = client.call(&["INFO", "persistence"]);
+    assert!(info.contains("rdb_changes_since_last_save:1\r\n"), "{info}");
+
+    assert_eq!(client.call(&["SET", "two", "2"]), "+OK\r\n");
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "persistence"])
+        .contains("rdb_changes_since_last_save:0\r\n")
+    {
+        assert!(Instant::now() < deadline, "No save point was reached");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(dir.join("save-points.rdb").exists());
+}
+
+#[test]
+fn save_on_sigterm() {
+    let dir = std::env::temp_dir().join("redis-starter-rust-stages");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("sigterm.rdb"));
+    let args = [
+        "--dir",
+        &dir.to_string_lossy(),
+        "--dbfilename",
+        "sigterm.rdb",
+        "--save",
+        "3600 1",
+    ];
+    let mut server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SET", "survivor", "yes"]), "+OK\r\n");
+    let killed = Command::new("kill")
+        .args(["-TERM", &server.process.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert_eq!(server.process.wait().unwrap().code(), Some(0));
+
+    let server = Server::spawn(&args);
+    let mut client = server.client();
+    assert_eq!(string(&client.call(&["GET", "survivor"])), Some("yes"));
+}
+
+#[test]
+fn replication_handshake() {
+    let master = Server::spawn(&[]);
+    let address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut client = replica.client();
+    let info = client.call(&["INFO", "replication"]);
+    assert!(info.contains("role:slave"), "{info}");
+}
+
+#[test]
+fn master_handshake() {
+    let master = Server::spawn(&[]);
+    let mut client = master.client();
+    let info = client.call(&["INFO", "replication"]);
+    assert!(info.contains("role:master"), "{info}");
+    assert!(info.contains("master_repl_offset:0"), "{info}");
+
+    let mut replica = master.client();
+    assert_eq!(replica.call(&["PING"]), "+PONG\r\n");
+    assert_eq!(
+        replica.call(&["REPLCONF", "listening-port", "6380"]),
+        "+OK\r\n"
+    );
+    assert_eq!(replica.call(&["REPLCONF", "capa", "psync2"]), "+OK\r\n");
+    let reply = replica.call(&["PSYNC", "?", "-1"]);
+    let replid = reply
+        .strip_prefix("+FULLRESYNC ")
+        .and_then(|rest| rest.strip_suffix(" 0\r\n"))
+        .unwrap_or_else(|| panic!("{reply:?}"));
+    assert_eq!(replid.len(), 40);
+    // The RDB file is sent like a bulk string, but without the trailing CRLF.
+    let mut header = String::new();
+    replica.reader.read_line(&mut header).unwrap();
+    let len: usize = header[1..].trim_end().parse().unwrap();
+    let mut rdb = vec![0; len];
+    replica.reader.read_exact(&mut rdb).unwrap();
+    assert!(rdb.starts_with(b"REDIS0011"));
+    let info = client.call(&["INFO", "replication"]);
+    assert!(info.contains("connected_slaves:1"), "{info}");
+    assert!(info.contains("slave0:ip=127.0.0.1,port=6380"), "{info}");
+    assert!(info.contains(&format!("master_replid:{replid}")), "{info}");
+
+    // Acknowledgements get no reply, the next reply is to the `PING` after them.
+    replica.send(&["REPLCONF", "ACK", "42"]);
+    assert_eq!(replica.call(&["PING"]), "+PONG\r\n");
+    let info = client.call(&["INFO", "replication"]);
+    assert!(info.contains("state=online,offset=42,lag=0"), "{info}");
+}
+
+#[test]
+fn full_resync() {
+    let master = Server::spawn(&[]);
+    let mut client = master.client();
+    assert_eq!(client.call(&["SET", "foo", "bar"]), "+OK\r\n");
+    assert_eq!(client.call(&["SADD", "set", "a", "b"]), ":2\r\n");
+
+    let address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut client = replica.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "replication"])
+        .contains("master_link_status:up")
+    {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(string(&client.call(&["GET", "foo"])), Some("bar"));
+    assert_eq!(client.call(&["SCARD", "set"]), ":2\r\n");
+}
+
+#[test]
+fn min_replicas_to_write() {
+    let master = Server::spawn(&["--min-replicas-to-write", "1"]);
+    let mut client = master.client();
+    let no_replicas = "-NOREPLICAS Not enough good replicas to write.\r\n";
+    assert_eq!(client.call(&["SET", "foo", "1"]), no_replicas);
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "foo", "1"]), no_replicas);
+    assert!(client.call(&["EXEC"]).starts_with("-EXECABORT"));
+    assert_eq!(client.call(&["GET", "foo"]), "-Key not found\r\n");
+
+    let address = format!("127.0.0.1 {}", master.port);
+    let _replica = Server::spawn(&["--replicaof", &address]);
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while client.call(&["SET", "foo", "1"]) != "+OK\r\n" {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn expiry_propagation() {
+    let master = Server::spawn(&[]);
+    let mut replica = master.client();
+    let reply = replica.call(&["PSYNC", "?", "-1"]);
+    assert!(reply.starts_with("+FULLRESYNC "), "{reply:?}");
+    let mut header = String::new();
+    replica.reader.read_line(&mut header).unwrap();
+    let len: usize = header[1..].trim_end().parse().unwrap();
+    replica.reader.read_exact(&mut vec![0; len]).unwrap();
+
+    let mut client = master.client();
+    assert_eq!(client.call(&["SET", "gone", "1", "PX", "50"]), "+OK\r\n");
+    assert!(replica.reply().unwrap().contains("PX"));
+    thread::sleep(Duration::from_millis(100));
+    // The key is removed by the write that touches it, and the removal goes first.
+    assert_eq!(client.call(&["SADD", "gone", "a"]), ":1\r\n");
+    let del = |key| format!("*2\r\n{}{}", bulk("DEL"), bulk(key));
+    assert_eq!(replica.reply().unwrap(), del("gone"));
+    assert!(replica.reply().unwrap().contains("SADD"));
+
+    // Keys that nobody touches are removed by the expire cycle.
+    assert_eq!(client.call(&["SET", "idle", "1", "PX", "50"]), "+OK\r\n");
+    assert!(replica.reply().unwrap().contains("PX"));
+    assert_eq!(replica.reply().unwrap(), del("idle"));
+}
+
+#[test]
+fn write_propagation() {
+    let master = Server::spawn(&[]);
+    let address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut client = master.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "replication"])
+        .contains("connected_slaves:1")
+    {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(client.call(&["SET", "foo", "1"]), "+OK\r\n");
+    assert_eq!(client.call(&["SADD", "set", "a", "b"]), ":2\r\n");
+    assert!(client.call(&["SADD", "foo", "a"]).starts_with("-WRONGTYPE"));
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "bar", "2"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["SADD", "set", "c"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["EXEC"]), "*2\r\n+OK\r\n:1\r\n");
+    let script = "redis.call('SET', 'baz', '3'); return redis.call('SREM', 'set', 'a')";
+    assert_eq!(client.call(&["EVAL", script, "0"]), ":1\r\n");
+    let info = client.call(&["INFO", "replication"]);
+    assert!(!info.contains("master_repl_offset:0\r\n"), "{info}");
+
+    let mut client = replica.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while string(&client.call(&["GET", "baz"])) != Some("3") {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not apply the writes"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(string(&client.call(&["GET", "foo"])), Some("1"));
+    assert_eq!(string(&client.call(&["GET", "bar"])), Some("2"));
+    assert_eq!(client.call(&["SISMEMBER", "set", "a"]), ":0\r\n");
+    assert_eq!(client.call(&["SCARD", "set"]), ":2\r\n");
+
+    let read_only = "-READONLY You can't write against a read only replica.\r\n";
+    assert_eq!(client.call(&["SET", "foo", "2"]), read_only);
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SADD", "set", "d"]), read_only);
+    assert!(client.call(&["EXEC"]).starts_with("-EXECABORT"));
+    let script = "return redis.pcall('SET', 'foo', '3')['err']";
+    assert!(client.call(&["EVAL", script, "0"]).contains("READONLY"));
+    assert_eq!(string(&client.call(&["GET", "foo"])), Some("1"));
+}
+
+#[test]
+fn replica_promotion() {
+    let master = Server::spawn(&[]);
+    let address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut client = master.client();
+    assert_eq!(client.call(&["SET", "foo", "1"]), "+OK\r\n");
+    let mut promoted = replica.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while string(&promoted.call(&["GET", "foo"])) != Some("1") {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    let info = promoted.call(&["INFO", "replication"]);
+    let replid = info.lines().find(|line| line.starts_with("master_replid:"));
+
+    assert!(promoted
+        .call(&["ROLE"])
+        .starts_with("*5\r\n$5\r\nslave\r\n$9\r\n127.0.0.1\r\n"));
+    assert_eq!(promoted.call(&["REPLICAOF", "NO", "ONE"]), "+OK\r\n");
+    assert!(promoted
+        .call(&["ROLE"])
+        .starts_with("*3\r\n$6\r\nmaster\r\n"));
+    assert_eq!(promoted.call(&["SET", "foo", "2"]), "+OK\r\n");
+    let info = promoted.call(&["INFO", "replication"]);
+    assert!(info.contains("role:master\r\n"), "{info}");
+    assert!(!info.contains(replid.unwrap()), "{info}");
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "replication"])
+        .contains("connected_slaves:0")
+    {
+        assert!(
+            Instant::now() < deadline,
+            "The link to the replica did not go down"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(client.call(&["SET", "bar", "1"]), "+OK\r\n");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(string(&promoted.call(&["GET", "bar"])), None);
+
+    let port = master.port.to_string();
+    assert_eq!(promoted.call(&["REPLICAOF", "127.0.0.1", &port]), "+OK\r\n");
+    assert_eq!(
+        promoted.call(&["REPLICAOF", "127.0.0.1", &port]),
+        "+OK Already connected to specified master\r\n"
+    );
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while string(&promoted.call(&["GET", "bar"])) != Some("1") {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync again in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(string(&promoted.call(&["GET", "foo"])), Some("1"));
+}
+
+/// Play the `master` of the `replica` through the handshake, up to an empty full resync,
+/// returning the link to the replica.
+fn fake_sync(master: &TcpListener, replica: &Server) -> Client {
+    let (stream, _) = master.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut link = Client {
+        reader: BufReader::new(stream),
+    };
+    let mut expect = |request: &[&str], reply: &[u8]| {
+        let expected: String = request.iter().map(|arg| bulk(arg)).collect();
+        let actual = link.reply().unwrap();
+        assert!(actual.ends_with(&expected), "{actual:?}");
+        link.reader.get_mut().write_all(reply).unwrap();
+    };
+    expect(&["PING"], b"+PONG\r\n");
+    let port = replica.port.to_string();
+    expect(&["REPLCONF", "listening-port", &port], b"+OK\r\n");
+    expect(&["REPLCONF", "capa", "psync2"], b"+OK\r\n");
+    // An empty RDB file, without a checksum.
+    let mut sync =
+        b"+FULLRESYNC 8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb 0\r\n$18\r\nREDIS0011\xff".to_vec();
+    sync.extend_from_slice(&[0; 8]);
+    expect(&["PSYNC", "?", "-1"], &sync);
+    link
+}
+
+#[test]
+fn replica_applies_the_stream() {
+    let master = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("127.0.0.1 {}", master.local_addr().unwrap().port());
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut link = fake_sync(&master, &replica);
+    link.send(&["SET", "foo", "1"]);
+    link.send(&["SET", "bar", "2"]);
+
+    let mut client = replica.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while string(&client.call(&["GET", "bar"])) != Some("2") {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not apply the stream"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(string(&client.call(&["GET", "foo"])), Some("1"));
+    let info = client.call(&["INFO", "replication"]);
+    assert!(info.contains("master_link_status:up"), "{info}");
+
+    // The replica also acknowledges by itself every second, so skip those.
+    let get_ack = |link: &mut Client, expected: &str| {
+        link.send(&["REPLCONF", "GETACK", "*"]);
+        let expected = format!(
+            "*3\r\n{}{}{}",
+            bulk("REPLCONF"),
+            bulk("ACK"),
+            bulk(expected)
+        );
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while link.reply().unwrap() != expected {
+            assert!(Instant::now() < deadline, "The replica did not acknowledge");
+        }
+    };
+    // Two `SET`s of 29 bytes each.
+    get_ack(&mut link, "58");
+    // The 37 bytes of the `REPLCONF GETACK *` and the 14 of a `PING` on top.
+    link.send(&["PING"]);
+    get_ack(&mut link, "109");
+}
+
+#[test]
+fn replica_reconnects() {
+    let master = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("127.0.0.1 {}", master.local_addr().unwrap().port());
+    let args = ["--replicaof", &address, "--replica-serve-stale-data", "no"];
+    let replica = Server::spawn(&args);
+    let mut client = replica.client();
+    let master_down =
+        "-MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.\r\n";
+    assert_eq!(client.call(&["GET", "foo"]), master_down);
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+
+    let mut link = fake_sync(&master, &replica);
+    link.send(&["SET", "foo", "1"]);
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while string(&client.call(&["GET", "foo"])) != Some("1") {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not apply the stream"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // The replica keeps its data, but does not serve it until it is back in sync.
+    drop(link);
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "replication"])
+        .contains("master_link_status:down")
+    {
+        assert!(Instant::now() < deadline, "The link did not go down");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(client.call(&["GET", "foo"]), master_down);
+    let mut link = fake_sync(&master, &replica);
+    link.send(&["SET", "bar", "2"]);
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while string(&client.call(&["GET", "bar"])) != Some("2") {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not apply the stream again"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    // The second full resync replaced the dataset with the empty one of the master.
+    assert_eq!(string(&client.call(&["GET", "foo"])), None);
+}
+
+#[test]
+fn wait_without_replicas() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["WAIT", "0", "60000"]), ":0\r\n");
+    assert_eq!(client.call(&["WAIT", "1", "50"]), ":0\r\n");
+}
+
+#[test]
+fn wait_for_replicas() {
+    let master = Server::spawn(&[]);
+    let address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut client = master.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "replication"])
+        .contains("connected_slaves:1")
+    {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(client.call(&["SET", "foo", "1"]), "+OK\r\n");
+    assert_eq!(client.call(&["WAIT", "1", "5000"]), ":1\r\n");
+    assert_eq!(client.call(&["WAIT", "2", "100"]), ":1\r\n");
+    assert_eq!(string(&replica.client().call(&["GET", "foo"])), Some("1"));
+    assert!(replica
+        .client()
+        .call(&["WAIT", "0", "0"])
+        .starts_with("-ERR WAIT cannot be used with replica instances"));
+}
+
+#[test]
+fn replicas_get_the_effects() {
+    let master = Server::spawn(&[]);
+    let address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::spawn(&["--replicaof", &address]);
+    let mut client = master.client();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !client
+        .call(&["INFO", "replication"])
+        .contains("connected_slaves:1")
+    {
+        assert!(
+            Instant::now() < deadline,
+            "The replica did not sync in time"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let members: Vec<_> = (0..20).map(|member| member.to_string()).collect();
+    let sadd: Vec<_> = ["SADD", "set"]
+        .into_iter()
+        .chain(members.iter().map(String::as_str))
+        .collect();
+    assert_eq!(client.call(&sadd), ":20\r\n");
+    assert!(client.call(&["SPOP", "set", "5"]).starts_with("*5\r\n"));
+    assert!(client.call(&["SPOP", "set"]).starts_with('$'));
+    assert!(client
+        .call(&["XADD", "stream", "*", "foo", "bar"])
+        .starts_with('$'));
+    assert!(client
+        .call(&["XADD", "stream", "*", "foo", "baz"])
+        .starts_with('$'));
+    assert_eq!(client.call(&["ZADD", "zset", "1", "a", "2", "b"]), ":2\r\n");
+    assert!(client
+        .call(&["BZPOPMAX", "zset", "0"])
+        .starts_with("*3\r\n"));
+    // A transaction reaches the replica whole, even with a command that fails in it.
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "queued", "1"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["SADD", "queued", "x"]), "+QUEUED\r\n");
+    assert!(client
+        .call(&["EXEC"])
+        .starts_with("*2\r\n+OK\r\n-WRONGTYPE"));
+    assert_eq!(client.call(&["SET", "after", "2"]), "+OK\r\n");
+    assert_eq!(client.call(&["WAIT", "1", "5000"]), ":1\r\n");
+
+    let mut replica = replica.client();
+    let sorted = |reply: String| {
+        let mut lines: Vec<_> = reply.lines().map(ToString::to_string).collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(
+        sorted(replica.call(&["SMEMBERS", "set"])),
+        sorted(client.call(&["SMEMBERS", "set"]))
+    );
+    assert_eq!(
+        replica.call(&["XRANGE", "stream", "-", "+"]),
+        client.call(&["XRANGE", "stream", "-", "+"])
+    );
+    assert_eq!(
+        replica.call(&["ZRANGE", "zset", "0", "-1"]),
+        client.call(&["ZRANGE", "zset", "0", "-1"])
+    );
+    assert_eq!(string(&replica.call(&["GET", "queued"])), Some("1"));
+    assert_eq!(string(&replica.call(&["GET", "after"])), Some("2"));
+}
+
+#[test]
+fn streams() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["XADD", "berry", "0-1", "foo", "bar"]),
+        bulk("0-1")
+    );
+    assert_eq!(client.call(&["TYPE", "berry"]), "+stream\r\n");
+    assert_eq!(client.call(&["TYPE", "missing"]), "+none\r\n");
+    assert_eq!(
+        client.call(&["XADD", "berry", "0-1", "foo", "bar"]),
+        "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+    );
+    assert_eq!(
+        client.call(&["XADD", "berry", "0-0", "foo", "bar"]),
+        "-ERR The ID specified in XADD must be greater than 0-0\r\n"
+    );
+    assert_eq!(
+        client.call(&["XADD", "berry", "0-*", "foo", "bar"]),
+        bulk("0-2")
+    );
+    assert_eq!(
+        client.call(&["XADD", "berry", "5-*", "foo", "bar"]),
+        bulk("5-0")
+    );
+
+    let reply = client.call(&["XADD", "berry", "*", "foo", "bar"]);
+    let id = reply.lines().nth(1).unwrap_or_default();
+    assert!(id.ends_with("-0"), "{reply:?}");
+    let (ms, _) = id.split_once('-').unwrap();
+    assert!(ms.parse::<u64>().is_ok_and(|ms| ms > 5), "{reply:?}");
+}
+
+#[test]
+fn stream_ranges() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    for id in ["0-1", "0-2", "0-3"] {
+        assert_eq!(client.call(&["XADD", "fruit", id, "foo", id]), bulk(id));
+    }
+    let entry = |id: &str| format!("*2\r\n{}*2\r\n{}{}", bulk(id), bulk("foo"), bulk(id));
+    assert_eq!(
+        client.call(&["XRANGE", "fruit", "0-2", "0-3"]),
+        format!("*2\r\n{}{}", entry("0-2"), entry("0-3"))
+    );
+    assert_eq!(
+        client.call(&["XRANGE", "fruit", "-", "0-1"]),
+        format!("*1\r\n{}", entry("0-1"))
+    );
+    assert_eq!(
+        client.call(&["XRANGE", "fruit", "0-2", "+"]),
+        format!("*2\r\n{}{}", entry("0-2"), entry("0-3"))
+    );
+}
+
+#[test]
+fn stream_reads() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["XADD", "kiwi", "0-1", "temperature", "96"]),
+        bulk("0-1")
+    );
+    assert_eq!(
+        client.call(&["XADD", "plum", "0-2", "humidity", "42"]),
+        bulk("0-2")
+    );
+    let entry = |id: &str, field: &str, value: &str| {
+        format!("*2\r\n{}*2\r\n{}{}", bulk(id), bulk(field), bulk(value))
+    };
+    assert_eq!(
+        client.call(&["XREAD", "STREAMS", "kiwi", "plum", "0-0", "0-1"]),
+        format!(
+            "*2\r\n*2\r\n{}*1\r\n{}*2\r\n{}*1\r\n{}",
+            bulk("kiwi"),
+            entry("0-1", "temperature", "96"),
+            bulk("plum"),
+            entry("0-2", "humidity", "42"),
+        )
+    );
+    assert_eq!(client.call(&["XREAD", "STREAMS", "kiwi", "$"]), "*-1\r\n");
+}
+
+#[test]
+fn blocking_stream_reads() {
+    let server = Server::spawn(&[]);
+    let (mut reader, mut writer) = (server.client(), server.client());
+    assert_eq!(
+        writer.call(&["XADD", "apple", "0-1", "temperature", "96"]),
+        bulk("0-1")
+    );
+
+    reader.send(&["XREAD", "BLOCK", "1000", "STREAMS", "apple", "0-1"]);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        writer.call(&["XADD", "apple", "0-2", "temperature", "95"]),
+        bulk("0-2")
+    );
+    assert_eq!(
+        reader.reply().unwrap(),
+        format!(
+            "*1\r\n*2\r\n{}*1\r\n*2\r\n{}*2\r\n{}{}",
+            bulk("apple"),
+            bulk("0-2"),
+            bulk("temperature"),
+            bulk("95"),
+        )
+    );
+
+    reader.send(&["XREAD", "BLOCK", "0", "STREAMS", "apple", "$"]);
+    thread::sleep(Duration::from_millis(100));
+    writer.call(&["XADD", "apple", "0-3", "temperature", "94"]);
+    assert!(reader.reply().unwrap().contains(&bulk("0-3")));
+
+    assert_eq!(
+        reader.call(&["XREAD", "BLOCK", "100", "STREAMS", "apple", "0-3"]),
+        "*-1\r\n"
+    );
+}
+
+#[test]
+fn command_table() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    let count = client.call(&["COMMAND", "COUNT"]);
+    let count: usize = count[1..].trim_end().parse().expect("COUNT is an integer");
+    let all = client.call(&["COMMAND"]);
+    assert!(all.starts_with(&format!("*{count}\r\n*10\r\n")), "{all:?}");
+    assert_eq!(
+        client.call(&["COMMAND", "INFO", "SET", "nosuchcommand"]),
+        "*2\r\n*10\r\n$3\r\nset\r\n:-3\r\n*2\r\n+write\r\n+denyoom\r\n\
+         :1\r\n:1\r\n:1\r\n*0\r\n*0\r\n*0\r\n*0\r\n$-1\r\n"
+    );
+    let docs = client.call(&["COMMAND", "DOCS", "get"]);
+    assert!(
+        docs.starts_with(&format!("*2\r\n{}*4\r\n", bulk("get"))),
+        "{docs:?}"
+    );
+
+    assert_eq!(
+        client.call(&["COMMAND", "GETKEYS", "EVAL", "return 1", "2", "a", "b", "c"]),
+        format!("*2\r\n{}{}", bulk("a"), bulk("b"))
+    );
+    assert_eq!(
+        client.call(&["COMMAND", "GETKEYS", "PING"]),
+        "-ERR The command has no key arguments\r\n"
+    );
+}
+
+#[test]
+fn client_list() {
+    let server = Server::spawn(&[]);
+    let (mut client, mut other) = (server.client(), server.client());
+    assert_eq!(
+        other.call(&["SUBSCRIBE", "news"]),
+        format!("*3\r\n{}{}:1\r\n", bulk("subscribe"), bulk("news"))
+    );
+    let info = client.call(&["CLIENT", "INFO"]);
+    let line = string(&info).expect("CLIENT INFO is a bulk string");
+    assert!(line.starts_with("id="), "{line:?}");
+    assert!(line.contains(" flags=N db=0 sub=0 "), "{line:?}");
+    assert!(
+        line.ends_with(" cmd=client|info user=default resp=2\n"),
+        "{line:?}"
+    );
+
+    let list = client.call(&["CLIENT", "LIST"]);
+    let lines = string(&list).expect("CLIENT LIST is a bulk string");
+    assert_eq!(lines.lines().count(), 2, "{lines:?}");
+    assert!(lines.contains(" flags=P db=0 sub=1 psub=0 "), "{lines:?}");
+    let pubsub = client.call(&["CLIENT", "LIST", "TYPE", "pubsub"]);
+    assert_eq!(string(&pubsub).map(|lines| lines.lines().count()), Some(1));
+    assert_eq!(client.call(&["CLIENT", "LIST", "ID", "999999"]), bulk(""));
+}
+
+#[test]
+fn client_names() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["CLIENT", "GETNAME"]), "$-1\r\n");
+    assert_eq!(
+        client.call(&["CLIENT", "SETNAME", "bad name"]),
+        "-ERR Client names cannot contain spaces, newlines or special characters.\r\n"
+    );
+    assert_eq!(client.call(&["CLIENT", "SETNAME", "worker-1"]), "+OK\r\n");
+    assert_eq!(client.call(&["CLIENT", "GETNAME"]), bulk("worker-1"));
+
+    let id = client.call(&["CLIENT", "ID"]);
+    let id = id
+        .strip_prefix(':')
+        .expect("CLIENT ID is an integer")
+        .trim_end();
+    let list = client.call(&["CLIENT", "LIST", "ID", id]);
+    let line = string(&list).expect("CLIENT LIST is a bulk string");
+    assert!(line.starts_with(&format!("id={id} ")), "{line:?}");
+    assert!(line.contains(" name=worker-1 "), "{line:?}");
+
+    assert_eq!(client.call(&["CLIENT", "SETNAME", ""]), "+OK\r\n");
+    assert_eq!(client.call(&["CLIENT", "GETNAME"]), "$-1\r\n");
+}
+
+#[test]
+fn reads_count_as_accesses() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SADD", "set", "a"]), ":1\r\n");
+    assert_eq!(client.call(&["ZADD", "zset", "1", "a"]), ":1\r\n");
+    assert_eq!(
+        client.call(&["XADD", "stream", "1-1", "f", "v"]),
+        bulk("1-1")
+    );
+    let reads: [(&str, &[&str]); 3] = [
+        ("set", &["SMEMBERS", "set"]),
+        ("zset", &["ZRANGE", "zset", "0", "-1"]),
+        ("stream", &["XRANGE", "stream", "-", "+"]),
+    ];
+    // The first access always bumps the counter of a new key, from 5 to 6.
+    for (key, read) in reads {
+        assert!(client.call(&["TYPE", key]).starts_with('+'));
+        assert_eq!(client.call(&["OBJECT", "FREQ", key]), ":5\r\n");
+        assert!(client.call(read).starts_with('*'));
+        assert_eq!(client.call(&["OBJECT", "FREQ", key]), ":6\r\n");
+    }
+}
+
+#[test]
+fn client_flags() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["CLIENT", "NO-EVICT", "on"]), "+OK\r\n");
+    assert_eq!(client.call(&["CLIENT", "NO-TOUCH", "on"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "melon", "1"]), "+OK\r\n");
+    assert_eq!(string(&client.call(&["GET", "melon"])), Some("1"));
+    assert_eq!(client.call(&["SADD", "set", "a"]), ":1\r\n");
+    assert_eq!(
+        client.call(&["SMEMBERS", "set"]),
+        format!("*1\r\n{}", bulk("a"))
+    );
+    assert_eq!(client.call(&["ZADD", "zset", "1", "a"]), ":1\r\n");
+    assert!(client.call(&["ZRANGE", "zset", "0", "-1"]).contains('a'));
+    // Left at the counter of new keys, which the first access would bump.
+    for key in ["melon", "set", "zset"] {
+        assert_eq!(client.call(&["OBJECT", "FREQ", key]), ":5\r\n");
+    }
+    let info = client.call(&["CLIENT", "INFO"]);
+    assert!(info.contains(" flags=eT "), "{info:?}");
+    assert_eq!(client.call(&["CLIENT", "NO-EVICT", "off"]), "+OK\r\n");
+    let info = client.call(&["CLIENT", "INFO"]);
+    assert!(info.contains(" flags=T "), "{info:?}");
+    assert_eq!(client.call(&["CLIENT", "NO-TOUCH", "off"]), "+OK\r\n");
+    assert!(client.call(&["ZRANGE", "zset", "0", "-1"]).contains('a'));
+    assert_eq!(client.call(&["OBJECT", "FREQ", "zset"]), ":6\r\n");
+}
+
+#[test]
+fn client_kill() {
+    let server = Server::spawn(&[]);
+    let (mut client, mut other, mut third) = (server.client(), server.client(), server.client());
+    let id = other.call(&["CLIENT", "ID"]);
+    let id = id
+        .strip_prefix(':')
+        .expect("CLIENT ID is an integer")
+        .trim_end();
+    assert_eq!(client.call(&["CLIENT", "KILL", "ID", id]), ":1\r\n");
+    assert!(other.reply().is_err(), "The killed client stays connected");
+
+    // The client itself is spared, unless it asks not to be.
+    assert_eq!(client.call(&["CLIENT", "KILL", "TYPE", "normal"]), ":1\r\n");
+    assert!(third.reply().is_err(), "The killed client stays connected");
+    assert_eq!(
+        client.call(&["CLIENT", "KILL", "127.0.0.1:1"]),
+        "-ERR No such client\r\n"
+    );
+    assert_eq!(
+        client.call(&["CLIENT", "KILL", "TYPE", "normal", "SKIPME", "no"]),
+        ":1\r\n"
+    );
+    assert!(client.reply().is_err(), "The killed client stays connected");
+}
+
+#[test]
+fn client_pause() {
+    let server = Server::spawn(&[]);
+    let (mut client, mut writer, mut reader) = (server.client(), server.client(), server.client());
+    assert_eq!(
+        client.call(&["CLIENT", "PAUSE", "60000", "WRITE"]),
+        "+OK\r\n"
+    );
+    writer.send(&["SET", "melon", "1"]);
+    assert_eq!(reader.call(&["GET", "melon"]), "-Key not found\r\n");
+    assert_eq!(client.call(&["CLIENT", "UNPAUSE"]), "+OK\r\n");
+    assert_eq!(writer.reply().unwrap(), "+OK\r\n");
+    assert_eq!(string(&reader.call(&["GET", "melon"])), Some("1"));
+
+    // The pause lifts by itself too, and holds back reads as well by default.
+    let start = Instant::now();
+    assert_eq!(client.call(&["CLIENT", "PAUSE", "200"]), "+OK\r\n");
+    assert_eq!(string(&reader.call(&["GET", "melon"])), Some("1"));
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn memory() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["MEMORY", "USAGE", "melon"]), "$-1\r\n");
+    assert_eq!(client.call(&["SET", "melon", "1"]), "+OK\r\n");
+    let small = client.call(&["MEMORY", "USAGE", "melon"]);
+    let small: usize = small[1..]
+        .trim_end()
+        .parse()
+        .expect("MEMORY USAGE is an integer");
+    assert_eq!(client.call(&["SET", "melon", &"x".repeat(1000)]), "+OK\r\n");
+    let big = client.call(&["MEMORY", "USAGE", "melon", "SAMPLES", "0"]);
+    assert_eq!(big, format!(":{}\r\n", small + 999));
+
+    let stats = client.call(&["MEMORY", "STATS"]);
+    assert!(stats.starts_with("*14\r\n"), "{stats:?}");
+    assert!(stats.contains("$10\r\nkeys.count\r\n:1\r\n"), "{stats:?}");
+}
+
+#[test]
+fn debug() {
+    let server = Server::spawn(&[]);
+    let (mut client, mut other) = (server.client(), server.client());
+    assert_eq!(
+        client.call(&["DEBUG", "OBJECT", "melon"]),
+        "-ERR no such key\r\n"
+    );
+    assert_eq!(client.call(&["SET", "melon", "12"]), "+OK\r\n");
+    let object = client.call(&["DEBUG", "OBJECT", "melon"]);
+    assert!(object.starts_with("+Value at:0x"), "{object:?}");
+    assert!(object.contains(" refcount:1 encoding:int "), "{object:?}");
+
+    // The cron leaves expired keys alone while active expiry is off.
+    assert_eq!(client.call(&["DEBUG", "SET-ACTIVE-EXPIRE", "0"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "gone", "1", "PX", "10"]), "+OK\r\n");
+    thread::sleep(Duration::from_millis(300));
+    let report = client.call(&["EXT.WHENEXPIRES", "gone"]);
+    assert!(report.contains("overdue"), "{report:?}");
+    assert_eq!(client.call(&["DEBUG", "SET-ACTIVE-EXPIRE", "1"]), "+OK\r\n");
+    thread::sleep(Duration::from_millis(300));
+    let report = client.call(&["EXT.WHENEXPIRES", "gone"]);
+    assert!(report.contains("active"), "{report:?}");
+
+    // Other clients wait for the sleep to end.
+    client.send(&["DEBUG", "SLEEP", "0.3"]);
+    thread::sleep(Duration::from_millis(50));
+    let started = Instant::now();
+    assert_eq!(string(&other.call(&["GET", "melon"])), Some("12"));
+    assert!(started.elapsed() >= Duration::from_millis(150));
+    assert_eq!(client.reply().unwrap(), "+OK\r\n");
+    assert_eq!(client.call(&["DEBUG", "JMAP"]), "+OK\r\n");
+    assert!(client
+        .call(&["DEBUG", "CHAOS", "LIST"])
+        .starts_with("-ERR DEBUG CHAOS is disabled"));
+}
+
+#[test]
+fn debug_chaos() {
+    let server = Server::spawn(&["--enable-debug-chaos", "yes"]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&[
+            "DEBUG",
+            "CHAOS",
+            "SET",
+            "ping",
+            "delay-ms",
+            "100",
+            "error-rate",
+            "1"
+        ]),
+        "+OK\r\n"
+    );
+    let started = Instant::now();
+    assert_eq!(
+        client.call(&["PING"]),
+        "-ERR chaos: injected failure of \"PING\"\r\n"
+    );
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(
+        client.call(&["DEBUG", "CHAOS", "LIST"]),
+        format!(
+            "*2\r\n{}{}",
+            bulk("ping"),
+            bulk("delay-ms 100 jitter 0 error-rate 1")
+        )
+    );
+    assert_eq!(client.call(&["DEBUG", "CHAOS", "CLEAR"]), ":1\r\n");
+    assert_eq!(client.call(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn slowlog() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SLOWLOG", "LEN"]), ":0\r\n");
+    assert_eq!(
+        client.call(&["CONFIG", "SET", "slowlog-log-slower-than", "0"]),
+        "+OK\r\n"
+    );
+    assert_eq!(client.call(&["CLIENT", "SETNAME", "slow"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "melon", "1"]), "+OK\r\n");
+    let newest = client.call(&["SLOWLOG", "GET", "1"]);
+    assert!(newest.starts_with("*1\r\n*6\r\n"), "{newest:?}");
+    assert!(
+        newest.contains("*3\r\n$3\r\nSET\r\n$5\r\nmelon\r\n$1\r\n1\r\n"),
+        "{newest:?}"
+    );
+    assert!(newest.ends_with("$4\r\nslow\r\n"), "{newest:?}");
+    assert_eq!(client.call(&["SLOWLOG", "RESET"]), "+OK\r\n");
+    // `SLOWLOG RESET` itself is logged right after the log is cleared.
+    assert_eq!(client.call(&["SLOWLOG", "LEN"]), ":1\r\n");
+    assert_eq!(
+        client.call(&["CONFIG", "SET", "slowlog-max-len", "many"]),
+        "-ERR Invalid argument 'many' for CONFIG SET 'slowlog-max-len' - \
+        argument couldn't be parsed into an integer\r\n"
+    );
+    assert_eq!(
+        client.call(&["CONFIG", "GET", "slowlog-log-slower-than"]),
+        "*2\r\n$23\r\nslowlog-log-slower-than\r\n$1\r\n0\r\n"
+    );
+}
+
+#[test]
+fn transactions() {
+    let server = Server::spawn(&[]);
+    let (mut client, mut other) = (server.client(), server.client());
+    assert_eq!(client.call(&["EXEC"]), "-ERR EXEC without MULTI\r\n");
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "melon", "1"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["GET", "melon"]), "+QUEUED\r\n");
+    // Nothing runs before `EXEC`.
+    assert_eq!(other.call(&["TYPE", "melon"]), "+none\r\n");
+    let reply = client.call(&["EXEC"]);
+    let get = reply.strip_prefix("*2\r\n+OK\r\n");
+    assert_eq!(get.and_then(string), Some("1"), "{reply:?}");
+
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "melon", "2"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["DISCARD"]), "+OK\r\n");
+    assert_eq!(client.call(&["DISCARD"]), "-ERR DISCARD without MULTI\r\n");
+    assert_eq!(string(&client.call(&["GET", "melon"])), Some("1"));
+
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "melon", "3"]), "+QUEUED\r\n");
+    assert!(client.call(&["NOSUCHCOMMAND"]).starts_with("-ERR"));
+    assert_eq!(
+        client.call(&["EXEC"]),
+        "-EXECABORT Transaction discarded because of previous errors.\r\n"
+    );
+    assert_eq!(string(&client.call(&["GET", "melon"])), Some("1"));
+
+    // Commands that fail while running don't stop the rest, they just reply with an error.
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "melon", "4"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["CONFIG", "GET", "bogus"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["SADD", "melon", "seed"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["SET", "lemon", "5"]), "+QUEUED\r\n");
+    assert_eq!(
+        client.call(&["EXEC"]),
+        "*4\r\n+OK\r\n*0\r\n\
+         -WRONGTYPE Operation against a key holding the wrong kind of value\r\n+OK\r\n"
+    );
+    assert_eq!(string(&client.call(&["GET", "lemon"])), Some("5"));
+}
+
+#[test]
+fn optimistic_locking() {
+    let server = Server::spawn(&[]);
+    let (mut client, mut other) = (server.client(), server.client());
+    assert_eq!(client.call(&["WATCH", "lime"]), "+OK\r\n");
+    assert_eq!(other.call(&["SET", "lime", "1"]), "+OK\r\n");
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(
+        client.call(&["WATCH", "lime"]),
+        "-ERR WATCH inside MULTI is not allowed\r\n"
+    );
+    assert_eq!(client.call(&["SET", "lime", "2"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["EXEC"]), "*-1\r\n");
+    assert_eq!(string(&client.call(&["GET", "lime"])), Some("1"));
+
+    // `EXEC` stops watching, whether it ran or not, and so does `UNWATCH`.
+    assert_eq!(other.call(&["SET", "lime", "3"]), "+OK\r\n");
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "lime", "4"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["EXEC"]), "*1\r\n+OK\r\n");
+
+    assert_eq!(client.call(&["WATCH", "lime"]), "+OK\r\n");
+    assert_eq!(client.call(&["UNWATCH"]), "+OK\r\n");
+    assert_eq!(other.call(&["SET", "lime", "5"]), "+OK\r\n");
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["SET", "lime", "6"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["EXEC"]), "*1\r\n+OK\r\n");
+}
+
+#[test]
+fn scripting() {
+    let server = Server::spawn(&["--script-instruction-limit", "100000"]);
+    let mut client = server.client();
+    let set = "return redis.call('SET', KEYS[1], ARGV[1])";
+    assert_eq!(client.call(&["EVAL", set, "1", "kiwi", "1"]), "+OK\r\n");
+    let get = "redis.call('SET', KEYS[1], 2) return {redis.call('GET', KEYS[1]), 2.5, false}";
+    let reply = client.call(&["EVAL", get, "1", "kiwi"]);
+    let value = reply
+        .strip_prefix("*3\r\n")
+        .and_then(|rest| rest.strip_suffix(":2\r\n$-1\r\n"));
+    assert_eq!(value.and_then(string), Some("2"), "{reply:?}");
+    assert_eq!(
+        client.call(&["EVAL", "return redis.call('SADD', 'kiwi', 'x')", "0"]),
+        "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+    );
+    assert_eq!(
+        client.call(&["EVAL", "return redis.pcall('MULTI')", "0"]),
+        "-ERR This Redis command is not allowed from script\r\n"
+    );
+    assert_eq!(
+        client.call(&["EVAL", "while true do end", "0"]),
+        "-ERR Script ran more than 100000 instructions\r\n"
+    );
+    assert!(client
+        .call(&["EVAL", "return +", "0"])
+        .starts_with("-ERR Error compiling script"));
+    // Scripts can be queued like any other command.
+    assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.call(&["EVAL", set, "1", "kiwi", "3"]), "+QUEUED\r\n");
+    assert_eq!(client.call(&["EXEC"]), "*1\r\n+OK\r\n");
+    assert_eq!(string(&client.call(&["GET", "kiwi"])), Some("3"));
+}
+
+#[test]
+fn script_cache() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    let script = "return 'Immabe a cached script'";
+    let sha1 = "c664a3bf70bd1d45c4284ffebb65a6f2299bfc9f";
+    let no_script = "-NOSCRIPT No matching script. Please use EVAL.\r\n";
+    assert_eq!(client.call(&["EVALSHA", sha1, "0"]), no_script);
+    assert_eq!(client.call(&["SCRIPT", "LOAD", script]), bulk(sha1));
+    assert_eq!(
+        client.call(&["EVALSHA", sha1, "0"]),
+        bulk("Immabe a cached script")
+    );
+    assert_eq!(
+        client.call(&[
+            "SCRIPT",
+            "EXISTS",
+            sha1,
+            "ffffffffffffffffffffffffffffffffffffffff"
+        ]),
+        "*2\r\n:1\r\n:0\r\n"
+    );
+    assert_eq!(client.call(&["SCRIPT", "FLUSH"]), "+OK\r\n");
+    assert_eq!(client.call(&["EVALSHA", sha1, "0"]), no_script);
+    // Scripts run with `EVAL` get cached too.
+    assert_eq!(
+        client.call(&["EVAL", script, "0"]),
+        bulk("Immabe a cached script")
+    );
+    assert_eq!(client.call(&["SCRIPT", "EXISTS", sha1]), "*1\r\n:1\r\n");
+}
+
+#[test]
+fn functions() {
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+    let library = "#!lua name=fruit\n\
+        redis.register_function('plant', function(keys, args) \
+          return redis.call('SET', keys[1], args[1]) end)\n\
+        redis.register_function{function_name = 'pick', flags = {'no-writes'}, \
+          callback = function(keys) return redis.call('GET', keys[1]) end}\n\
+        redis.register_function{function_name = 'sneak', flags = {'no-writes'}, \
+          callback = function(keys) return redis.call('SET', keys[1], 'x') end}";
+    assert_eq!(client.call(&["FUNCTION", "LOAD", library]), bulk("fruit"));
+    assert_eq!(
+        client.call(&["FUNCTION", "LOAD", library]),
+        "-ERR Library 'fruit' already exists\r\n"
+    );
+    assert_eq!(
+        client.call(&["FUNCTION", "LOAD", "REPLACE", library]),
+        bulk("fruit")
+    );
+
+    assert_eq!(client.call(&["FCALL", "plant", "1", "fig", "1"]), "+OK\r\n");
+    assert_eq!(
+        string(&client.call(&["FCALL_RO", "pick", "1", "fig"])),
+        Some("1")
+    );
+    assert_eq!(
+        client.call(&["FCALL_RO", "plant", "1", "fig", "2"]),
+        "-ERR Can not execute a script with write flag using *_ro command.\r\n"
+    );
+    assert_eq!(
+        client.call(&["FCALL", "sneak", "1", "fig"]),
+        "-ERR Write commands are not allowed from read-only scripts.\r\n"
+    );
+    assert_eq!(
+        client.call(&["FCALL", "missing", "0"]),
+        "-ERR Function not found\r\n"
+    );
+
+    let list = client.call(&["FUNCTION", "LIST", "LIBRARYNAME", "fr*"]);
+    assert!(list.starts_with(&format!(
+        "*1\r\n*6\r\n{}{}",
+        bulk("library_name"),
+        bulk("fruit")
+    )));
+    assert!(list.contains(&bulk("no-writes")), "{list:?}");
+    assert_eq!(
+        client.call(&["FUNCTION", "LIST", "LIBRARYNAME", "veg*"]),
+        "*0\r\n"
+    );
+
+    let dump = client.call(&["FUNCTION", "DUMP"]);
+    let payload = string(&dump).unwrap();
+    assert_eq!(client.call(&["FUNCTION", "FLUSH"]), "+OK\r\n");
+    assert_eq!(
+        client.call(&["FCALL", "plant", "1", "fig", "1"]),
+        "-ERR Function not found\r\n"
+    );
+    assert_eq!(client.call(&["FUNCTION", "RESTORE", payload]), "+OK\r\n");
+    assert_eq!(
+        client.call(&["FUNCTION", "RESTORE", payload]),
+        "-ERR Library 'fruit' already exists\r\n"
+    );
+    assert_eq!(
+        client.call(&["FUNCTION", "RESTORE", payload, "REPLACE"]),
+        "+OK\r\n"
+    );
+    assert_eq!(client.call(&["FCALL", "plant", "1", "fig", "3"]), "+OK\r\n");
+}
+
+/// Compare `EXEC` of a batch of `SET`s against sending them one by one. Run it with
+/// `cargo test --release -- --ignored --nocapture exec_benchmark`.
+#[test]
+#[ignore = "benchmark"]
+fn exec_benchmark() {
+    const ROUNDS: u32 = 100;
+    const BATCH: usize = 100;
+    let server = Server::spawn(&[]);
+    let mut client = server.client();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for n in 0..BATCH {
+            assert_eq!(client.call(&["SET", &n.to_string(), "x"]), "+OK\r\n");
+        }
+    }
+    println!(
+        "{:>12}: {:?} per batch",
+        "standalone",
+        start.elapsed() / ROUNDS
+    );
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert_eq!(client.call(&["MULTI"]), "+OK\r\n");
+        for n in 0..BATCH {
+            assert_eq!(client.call(&["SET", &n.to_string(), "x"]), "+QUEUED\r\n");
+        }
+        assert!(client.call(&["EXEC"]).starts_with(&format!("*{BATCH}\r\n")));
+    }
+    println!("{:>12}: {:?} per batch", "EXEC", start.elapsed() / ROUNDS);
+}